@@ -0,0 +1,684 @@
+//! Export and import commands.
+//!
+//! Back up an agent filesystem to a tar archive and restore it again,
+//! without mounting it.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+
+use agentfs_sdk::filesystem::AgentFS;
+use agentfs_sdk::{
+    AgentFSOptions, BoxedFile, FileSystem, SnapshotChanges, Stats, TimeChange, S_IFREG,
+};
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+use crate::cmd::snapshot::find_mount;
+use crate::cmd::walk::Walker;
+
+const ROOT_INO: i64 = 1;
+
+/// Size of the reads used to stream file contents in and out of the archive.
+const COPY_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Prefix of the file names that mark deleted entries in an incremental
+/// archive, as in OCI image layers.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Handle the export command.
+///
+/// Writes every file, directory, symlink and hard link of the agent
+/// filesystem to a tar archive at `output` (`-` for stdout). Entries are
+/// written one at a time, so the archive is never held in memory.
+///
+/// With `since`, only entries created or modified after that snapshot are
+/// written, preceded by a whiteout for each deleted entry.
+pub async fn handle_export_command(
+    id_or_path: String,
+    output: String,
+    since: Option<String>,
+) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    eprintln!("Using agent: {}", id_or_path);
+    let agent = open_agentfs(options).await?;
+    let changes = match &since {
+        Some(label) => Some(
+            agent
+                .fs
+                .changes_since(label)
+                .await
+                .with_context(|| format!("Failed to list changes since '{}'", label))?,
+        ),
+        None => None,
+    };
+
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(std::io::stdout().lock())
+    } else {
+        let file = std::fs::File::create(&output)
+            .with_context(|| format!("Failed to create {}", output))?;
+        Box::new(std::io::BufWriter::new(file))
+    };
+    let mut builder = tar::Builder::new(writer);
+    let count = export_archive(&agent.fs, &mut builder, changes.as_ref()).await?;
+    builder.into_inner()?.flush()?;
+
+    match since {
+        Some(label) => eprintln!("Exported {} entries changed since '{}'", count, label),
+        None => eprintln!("Exported {} entries", count),
+    }
+    Ok(())
+}
+
+/// Handle the import command.
+///
+/// Refuses to import into a mounted filesystem, and into a non-empty one
+/// unless `overwrite` is set, in which case the archive is merged in and
+/// replaces existing entries with the same path.
+pub async fn handle_import_command(
+    id_or_path: String,
+    input: String,
+    overwrite: bool,
+) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    let db_path = options
+        .db_path()
+        .context("Failed to resolve database path")?;
+
+    if let Some(mountpoint) = find_mount(&id_or_path, &db_path) {
+        anyhow::bail!(
+            "Agent '{}' is mounted at {}; unmount it before importing",
+            id_or_path,
+            mountpoint.display()
+        );
+    }
+    eprintln!("Using agent: {}", id_or_path);
+    let agent = open_agentfs(options).await?;
+
+    let is_empty = agent
+        .fs
+        .readdir(ROOT_INO)
+        .await?
+        .is_none_or(|entries| entries.is_empty());
+    if !is_empty && !overwrite {
+        anyhow::bail!(
+            "Agent '{}' is not empty; use --overwrite to merge the archive into it",
+            id_or_path
+        );
+    }
+
+    let reader: Box<dyn Read> = if input == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        let file =
+            std::fs::File::open(&input).with_context(|| format!("Failed to open {}", input))?;
+        Box::new(std::io::BufReader::new(file))
+    };
+    let count = import_archive(&agent.fs, tar::Archive::new(reader)).await?;
+
+    eprintln!("Imported {} entries", count);
+    Ok(())
+}
+
+/// Write the filesystem to a tar builder, parents before children.
+///
+/// With `since`, deleted entries are written first as empty whiteout files
+/// named `.wh.<name>` next to where they were, followed by the changed
+/// entries only. Returns the number of entries written.
+async fn export_archive<W: Write>(
+    fs: &AgentFS,
+    builder: &mut tar::Builder<W>,
+    since: Option<&SnapshotChanges>,
+) -> AnyhowResult<usize> {
+    // First archive path of every inode with more than one link
+    let mut linked: HashMap<i64, String> = HashMap::new();
+    let mut walker = Walker::new(fs, ROOT_INO).await?;
+    let mut count = 0;
+
+    let changed: Option<HashSet<&str>> =
+        since.map(|changes| changes.changed.iter().map(String::as_str).collect());
+    for path in since.iter().flat_map(|changes| &changes.deleted) {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(0);
+        header.set_size(0);
+        let whiteout = format!("{}/{}{}", parent, WHITEOUT_PREFIX, name);
+        builder.append_data(
+            &mut header,
+            whiteout.trim_start_matches('/'),
+            std::io::empty(),
+        )?;
+        count += 1;
+    }
+
+    while let Some(entry) = walker.next().await? {
+        let name = entry.path;
+        let stats = entry.stats;
+        if let Some(changed) = &changed {
+            if !changed.contains(format!("/{}", name).as_str()) {
+                continue;
+            }
+        }
+        let mut header = header_for(&stats);
+
+        if stats.nlink > 1 && !stats.is_directory() {
+            if let Some(target) = linked.get(&stats.ino) {
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_size(0);
+                builder.append_link(&mut header, &name, target)?;
+                count += 1;
+                continue;
+            }
+            linked.insert(stats.ino, name.clone());
+        }
+
+        if stats.is_directory() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, format!("{}/", name), std::io::empty())?;
+        } else if stats.is_symlink() {
+            let target = FileSystem::readlink(fs, stats.ino)
+                .await?
+                .with_context(|| format!("Failed to read symlink /{}", name))?;
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, &name, target)?;
+        } else if stats.is_file() && stats.blocks * 512 < stats.size as u64 {
+            // Holes are left out of the archive instead of written as zeros
+            let path = format!("/{}", name);
+            let ranges = fs
+                .data_ranges(&path)
+                .await?
+                .with_context(|| format!("{} disappeared during export", path))?;
+            let mut data = Vec::new();
+            for &(offset, len) in &ranges {
+                data.extend(read_range(fs, &path, offset, len).await?);
+            }
+            let map = set_sparse(&mut header, &ranges, stats.size as u64, data.len() as u64);
+            builder.append_data(&mut header, &name, map.as_slice().chain(data.as_slice()))?;
+        } else if stats.is_file() {
+            let data = read_contents(fs, &format!("/{}", name)).await?;
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, &name, data.as_slice())?;
+        } else {
+            eprintln!("Warning: skipping special file /{}", name);
+            continue;
+        }
+        count += 1;
+    }
+
+    builder.finish()?;
+    Ok(count)
+}
+
+/// A tar header carrying an entry's mode, ownership and mtime.
+fn header_for(stats: &Stats) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(stats.mode & 0o7777);
+    header.set_uid(stats.uid as u64);
+    header.set_gid(stats.gid as u64);
+    header.set_mtime(stats.mtime.max(0) as u64);
+    header
+}
+
+/// Turn `header` into a GNU sparse file header for a file of `size` bytes
+/// whose data lies in `ranges`, `stored` bytes in all.
+///
+/// The header holds the first four ranges; the rest go into extension
+/// headers, which are returned to be written between the header and the
+/// data. The map always ends at `size`, so a trailing hole survives.
+fn set_sparse(header: &mut tar::Header, ranges: &[(u64, u64)], size: u64, stored: u64) -> Vec<u8> {
+    let mut map = ranges.to_vec();
+    if map.last().is_none_or(|&(offset, len)| offset + len < size) {
+        map.push((size, 0));
+    }
+
+    header.set_entry_type(tar::EntryType::GNUSparse);
+    header.set_size(stored);
+    let gnu = header.as_gnu_mut().expect("export writes GNU headers");
+    set_numeric(&mut gnu.realsize, size);
+    let (inline, rest) = map.split_at(map.len().min(gnu.sparse.len()));
+    for (entry, &(offset, len)) in gnu.sparse.iter_mut().zip(inline) {
+        set_numeric(&mut entry.offset, offset);
+        set_numeric(&mut entry.numbytes, len);
+    }
+    gnu.isextended[0] = u8::from(!rest.is_empty());
+
+    let mut extensions = Vec::new();
+    let mut blocks = rest.chunks(21).peekable();
+    while let Some(block) = blocks.next() {
+        let mut ext = tar::GnuExtSparseHeader::new();
+        for (entry, &(offset, len)) in ext.sparse.iter_mut().zip(block) {
+            set_numeric(&mut entry.offset, offset);
+            set_numeric(&mut entry.numbytes, len);
+        }
+        ext.isextended[0] = u8::from(blocks.peek().is_some());
+        extensions.extend_from_slice(ext.as_bytes());
+    }
+    extensions
+}
+
+/// Store a number in a 12-byte tar header field: octal when it fits,
+/// GNU base-256 otherwise.
+fn set_numeric(field: &mut [u8; 12], value: u64) {
+    if value < 8u64.pow(11) {
+        field.copy_from_slice(format!("{:011o}\0", value).as_bytes());
+    } else {
+        field.fill(0);
+        field[0] = 0x80;
+        field[4..].copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Read the contents of a regular file.
+async fn read_contents(fs: &AgentFS, path: &str) -> AnyhowResult<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        let chunk = fs
+            .pread(path, data.len() as u64, COPY_CHUNK_SIZE)
+            .await?
+            .with_context(|| format!("{} disappeared during export", path))?;
+        if chunk.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Read `len` bytes of a regular file starting at `offset`.
+async fn read_range(fs: &AgentFS, path: &str, offset: u64, len: u64) -> AnyhowResult<Vec<u8>> {
+    let mut data = Vec::new();
+    while (data.len() as u64) < len {
+        let want = COPY_CHUNK_SIZE.min(len - data.len() as u64);
+        let chunk = fs
+            .pread(path, offset + data.len() as u64, want)
+            .await?
+            .with_context(|| format!("{} disappeared during export", path))?;
+        if chunk.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Recreate the entries of a tar archive in the filesystem.
+///
+/// Returns the number of entries imported.
+async fn import_archive<R: Read>(
+    fs: &AgentFS,
+    mut archive: tar::Archive<R>,
+) -> AnyhowResult<usize> {
+    let mut dirs = Vec::new();
+    let mut count = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = archive_path(&entry.path()?)?;
+        if path == "/" {
+            continue;
+        }
+        if let Some(deleted) = whiteout_target(&path) {
+            if fs.lstat(&deleted).await?.is_some() {
+                FileSystem::remove_all(fs, &deleted).await?;
+            }
+            count += 1;
+            continue;
+        }
+        let header = entry.header();
+        let mode = header.mode()? & 0o7777;
+        let uid = header.uid()? as u32;
+        let gid = header.gid()? as u32;
+        let mtime = header.mtime()? as i64;
+        let entry_type = header.entry_type();
+
+        create_parents(fs, &path).await?;
+        let existing = fs.lstat(&path).await?;
+
+        match entry_type {
+            tar::EntryType::Directory => {
+                match existing {
+                    Some(stats) if stats.is_directory() => {}
+                    Some(_) => {
+                        fs.remove(&path).await?;
+                        fs.mkdir(&path, uid, gid).await?;
+                    }
+                    None => fs.mkdir(&path, uid, gid).await?,
+                }
+                dirs.push((path, mode, mtime));
+                count += 1;
+                continue;
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse => {
+                if existing.is_some() {
+                    fs.remove(&path).await?;
+                }
+                let (_, file) = fs.create_file(&path, S_IFREG | mode, uid, gid).await?;
+                let mut offset = 0;
+                let mut buf = vec![0u8; COPY_CHUNK_SIZE as usize];
+                loop {
+                    let n = entry.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    write_sparse(&file, offset, &buf[..n], fs.chunk_size() as u64).await?;
+                    offset += n as u64;
+                }
+                // A file ending in a hole gets its size from here
+                if file.fstat().await?.size as u64 != offset {
+                    file.truncate(offset).await?;
+                }
+            }
+            tar::EntryType::Symlink => {
+                let target = entry
+                    .link_name()?
+                    .with_context(|| format!("Symlink {} has no target", path))?;
+                if existing.is_some() {
+                    fs.remove(&path).await?;
+                }
+                fs.symlink(&target.to_string_lossy(), &path, uid, gid)
+                    .await?;
+                // Symlink mode and times are not meaningful
+                count += 1;
+                continue;
+            }
+            tar::EntryType::Link => {
+                let target = entry
+                    .link_name()?
+                    .with_context(|| format!("Hard link {} has no target", path))?;
+                let target = archive_path(&target)?;
+                if existing.is_some() {
+                    fs.remove(&path).await?;
+                }
+                fs.link(&target, &path).await?;
+                count += 1;
+                continue;
+            }
+            other => {
+                eprintln!("Warning: skipping {} ({:?} entry)", path, other);
+                continue;
+            }
+        }
+
+        set_metadata(fs, &path, mode, mtime).await?;
+        count += 1;
+    }
+
+    for (path, mode, mtime) in dirs.iter().rev() {
+        set_metadata(fs, path, *mode, *mtime).await?;
+    }
+    Ok(count)
+}
+
+/// Write `data` at `offset`, leaving chunks that hold only zeros as holes.
+///
+/// The holes of sparse entries reach the import as zeros, so this is what
+/// keeps them sparse.
+async fn write_sparse(
+    file: &BoxedFile,
+    offset: u64,
+    data: &[u8],
+    chunk_size: u64,
+) -> AnyhowResult<()> {
+    // Start of the run of non-zero chunks not yet written
+    let mut run = None;
+    let mut start = 0;
+    while start < data.len() {
+        let pos = offset + start as u64;
+        let end = (start + (chunk_size - pos % chunk_size) as usize).min(data.len());
+        let is_zero = data[start..end].iter().all(|&b| b == 0);
+        match (is_zero, run) {
+            (true, Some(run_start)) => {
+                file.pwrite(offset + run_start as u64, &data[run_start..start])
+                    .await?;
+                run = None;
+            }
+            (false, None) => run = Some(start),
+            _ => {}
+        }
+        start = end;
+    }
+    if let Some(run_start) = run {
+        file.pwrite(offset + run_start as u64, &data[run_start..])
+            .await?;
+    }
+    Ok(())
+}
+
+/// The path a whiteout in an incremental archive marks as deleted, if
+/// `path` is one.
+fn whiteout_target(path: &str) -> Option<String> {
+    let (parent, name) = path.rsplit_once('/')?;
+    let name = name.strip_prefix(WHITEOUT_PREFIX)?;
+    (!name.is_empty()).then(|| format!("{}/{}", parent, name))
+}
+
+/// Map a path from an archive to an absolute filesystem path.
+fn archive_path(path: &Path) -> AnyhowResult<String> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_string_lossy().to_string()),
+            Component::CurDir | Component::RootDir => {}
+            _ => anyhow::bail!("Refusing unsafe archive path: {}", path.display()),
+        }
+    }
+    Ok(format!("/{}", components.join("/")))
+}
+
+/// Create any missing parent directories of a path.
+async fn create_parents(fs: &AgentFS, path: &str) -> AnyhowResult<()> {
+    let mut current = String::new();
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    for component in &components[..components.len().saturating_sub(1)] {
+        current.push('/');
+        current.push_str(component);
+        if fs.lstat(&current).await?.is_none() {
+            fs.mkdir(&current, 0, 0).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Apply a mode and mtime from the archive to a path.
+async fn set_metadata(fs: &AgentFS, path: &str, mode: u32, mtime: i64) -> AnyhowResult<()> {
+    let stats = fs
+        .lstat(path)
+        .await?
+        .with_context(|| format!("{} disappeared during import", path))?;
+    FileSystem::chmod(fs, stats.ino, (stats.mode & !0o7777) | mode).await?;
+    FileSystem::utimens(fs, stats.ino, TimeChange::Omit, TimeChange::Set(mtime, 0)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use agentfs_sdk::{AgentFS, AgentFSOptions, FileSystem};
+    use tempfile::TempDir;
+
+    use super::{handle_export_command, handle_import_command};
+
+    async fn open(path: &str) -> AgentFS {
+        AgentFS::open(AgentFSOptions::with_path(path.to_string()))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_import_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source.db").to_str().unwrap().to_string();
+        let dest = dir.path().join("dest.db").to_str().unwrap().to_string();
+        let archive = dir.path().join("backup.tar").to_str().unwrap().to_string();
+
+        let agent = open(&source).await;
+        agent.fs.mkdir("/dir", 1000, 1000).await.unwrap();
+        agent.fs.pwrite("/dir/file.txt", 0, b"hello").await.unwrap();
+        agent
+            .fs
+            .symlink("dir/file.txt", "/link", 0, 0)
+            .await
+            .unwrap();
+        agent.fs.link("/dir/file.txt", "/hard.txt").await.unwrap();
+        let file = agent.fs.lstat("/dir/file.txt").await.unwrap().unwrap();
+        FileSystem::chmod(&agent.fs, file.ino, 0o100600)
+            .await
+            .unwrap();
+        drop(agent);
+
+        handle_export_command(source, archive.clone(), None)
+            .await
+            .unwrap();
+        // Import targets an existing agent, as created by `init`
+        drop(open(&dest).await);
+        handle_import_command(dest.clone(), archive.clone(), false)
+            .await
+            .unwrap();
+
+        let agent = open(&dest).await;
+        assert_eq!(
+            agent.fs.read_file("/dir/file.txt").await.unwrap().unwrap(),
+            b"hello"
+        );
+        let imported = agent.fs.lstat("/dir/file.txt").await.unwrap().unwrap();
+        assert_eq!(imported.mode & 0o7777, 0o600);
+        assert_eq!(imported.mtime, file.mtime);
+        assert_eq!(imported.nlink, 2);
+        let dir_stats = agent.fs.lstat("/dir").await.unwrap().unwrap();
+        assert_eq!((dir_stats.uid, dir_stats.gid), (1000, 1000));
+        assert_eq!(
+            agent.fs.readlink("/link").await.unwrap().unwrap(),
+            "dir/file.txt"
+        );
+        drop(agent);
+
+        // A second import needs --overwrite
+        assert!(handle_import_command(dest.clone(), archive.clone(), false)
+            .await
+            .is_err());
+        handle_import_command(dest, archive, true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn export_import_keeps_sparse_files_sparse() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source.db").to_str().unwrap().to_string();
+        let dest = dir.path().join("dest.db").to_str().unwrap().to_string();
+        let archive = dir.path().join("backup.tar").to_str().unwrap().to_string();
+
+        // A 1 GiB file with two small written regions
+        let agent = open(&source).await;
+        agent.fs.pwrite("/sparse.img", 0, b"").await.unwrap();
+        agent.fs.truncate("/sparse.img", 1 << 30).await.unwrap();
+        agent
+            .fs
+            .pwrite("/sparse.img", 4096, b"first")
+            .await
+            .unwrap();
+        let second = 700 << 20;
+        agent
+            .fs
+            .pwrite("/sparse.img", second, b"second")
+            .await
+            .unwrap();
+        let ranges = agent.fs.data_ranges("/sparse.img").await.unwrap();
+        drop(agent);
+
+        handle_export_command(source, archive.clone(), None)
+            .await
+            .unwrap();
+        // Only the written regions are in the archive
+        assert!(std::fs::metadata(&archive).unwrap().len() < 1 << 20);
+        drop(open(&dest).await);
+        handle_import_command(dest.clone(), archive, false)
+            .await
+            .unwrap();
+
+        let agent = open(&dest).await;
+        let imported = agent.fs.lstat("/sparse.img").await.unwrap().unwrap();
+        assert_eq!(imported.size, 1 << 30);
+        // Whole chunks are exported, so the copy stores at most its ranges
+        let range_blocks: u64 = ranges
+            .iter()
+            .flatten()
+            .map(|(_, len)| len.div_ceil(512))
+            .sum();
+        assert!(imported.blocks <= range_blocks);
+        assert_eq!(agent.fs.data_ranges("/sparse.img").await.unwrap(), ranges);
+        assert_eq!(
+            agent
+                .fs
+                .pread("/sparse.img", 4094, 9)
+                .await
+                .unwrap()
+                .unwrap(),
+            b"\0\0first\0\0"
+        );
+        assert_eq!(
+            agent
+                .fs
+                .pread("/sparse.img", second, 6)
+                .await
+                .unwrap()
+                .unwrap(),
+            b"second"
+        );
+    }
+
+    #[tokio::test]
+    async fn incremental_export_applies_over_full_export() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source.db").to_str().unwrap().to_string();
+        let dest = dir.path().join("dest.db").to_str().unwrap().to_string();
+        let full = dir.path().join("full.tar").to_str().unwrap().to_string();
+        let incremental = dir.path().join("incr.tar").to_str().unwrap().to_string();
+
+        let agent = open(&source).await;
+        agent.fs.mkdir("/dir", 0, 0).await.unwrap();
+        agent.fs.pwrite("/dir/gone.txt", 0, b"gone").await.unwrap();
+        agent.fs.pwrite("/kept.txt", 0, b"kept").await.unwrap();
+        agent.fs.pwrite("/edit.txt", 0, b"before").await.unwrap();
+        drop(agent);
+        handle_export_command(source.clone(), full.clone(), None)
+            .await
+            .unwrap();
+
+        let agent = open(&source).await;
+        agent.fs.snapshot("base").await.unwrap();
+        agent.fs.pwrite("/edit.txt", 0, b"after!").await.unwrap();
+        agent.fs.pwrite("/dir/new.txt", 0, b"new").await.unwrap();
+        agent.fs.remove("/dir/gone.txt").await.unwrap();
+        drop(agent);
+        handle_export_command(source, incremental.clone(), Some("base".to_string()))
+            .await
+            .unwrap();
+
+        let mut names = Vec::new();
+        let file = std::fs::File::open(&incremental).unwrap();
+        for entry in tar::Archive::new(file).entries().unwrap() {
+            names.push(entry.unwrap().path().unwrap().display().to_string());
+        }
+        assert_eq!(names[0], "dir/.wh.gone.txt");
+        assert!(names.contains(&"dir/new.txt".to_string()));
+
+        drop(open(&dest).await);
+        handle_import_command(dest.clone(), full, false)
+            .await
+            .unwrap();
+        handle_import_command(dest.clone(), incremental, true)
+            .await
+            .unwrap();
+
+        let agent = open(&dest).await;
+        let fs = &agent.fs;
+        assert_eq!(fs.read_file("/edit.txt").await.unwrap().unwrap(), b"after!");
+        assert_eq!(fs.read_file("/dir/new.txt").await.unwrap().unwrap(), b"new");
+        assert_eq!(fs.read_file("/kept.txt").await.unwrap().unwrap(), b"kept");
+        assert!(fs.lstat("/dir/gone.txt").await.unwrap().is_none());
+        assert!(fs.lstat("/dir/.wh.gone.txt").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,52 @@
+//! Overlay base command.
+//!
+//! Show the base directory an overlay is layered over, or point it at a new
+//! one after the base was moved.
+
+use std::path::PathBuf;
+
+use agentfs_sdk::AgentFSOptions;
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+use crate::cmd::snapshot::find_mount;
+
+/// Handle the base command.
+///
+/// Changing the base refuses to run while the filesystem is mounted, since
+/// the mount keeps the old base open.
+pub async fn handle_base_command(id_or_path: String, path: Option<PathBuf>) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    let db_path = options
+        .db_path()
+        .context("Failed to resolve database path")?;
+
+    if path.is_some() {
+        if let Some(mountpoint) = find_mount(&id_or_path, &db_path) {
+            anyhow::bail!(
+                "Agent '{}' is mounted at {}; unmount it before changing its base",
+                id_or_path,
+                mountpoint.display()
+            );
+        }
+    }
+
+    let agent = open_agentfs(options).await?;
+    let Some(base_path) = agent.is_overlay_enabled().await? else {
+        anyhow::bail!("Agent '{}' is not an overlay", id_or_path);
+    };
+    let Some(path) = path else {
+        println!("{}", base_path);
+        return Ok(());
+    };
+
+    let new_base = agent
+        .set_base_path(&path)
+        .await
+        .with_context(|| format!("Cannot use {} as the base", path.display()))?;
+    if agent.manifest().await?.is_some() {
+        agent.write_manifest().await?;
+    }
+    println!("Base: {} -> {}", base_path, new_base);
+    Ok(())
+}
@@ -0,0 +1,446 @@
+//! Commit command.
+//!
+//! Flush the delta layer of an overlay filesystem down into its base
+//! directory and clear the delta afterwards.
+
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use agentfs_sdk::error::Error as SdkError;
+use agentfs_sdk::{
+    AgentFSOptions, FileSystem, FsError, HostFS, Operation as FsOperation, OverlayFS, Stats,
+};
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+use crate::cmd::snapshot::find_mount;
+
+const ROOT_INO: i64 = 1;
+
+/// Size of the reads used to stream file contents out of the overlay.
+const COPY_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// A single change to apply to the base directory.
+#[derive(Debug)]
+enum Operation {
+    /// Create a directory (replacing a non-directory in the base)
+    CreateDir { path: String, mode: u32 },
+    /// Write a regular file with the overlay's contents
+    WriteFile { path: String, mode: u32 },
+    /// Create a symbolic link
+    Symlink { path: String, target: String },
+    /// Delete a whited-out path from the base
+    Remove { path: String },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::CreateDir { path, .. } => write!(f, "mkdir   {}", path),
+            Operation::WriteFile { path, .. } => write!(f, "write   {}", path),
+            Operation::Symlink { path, target } => write!(f, "symlink {} -> {}", path, target),
+            Operation::Remove { path } => write!(f, "remove  {}", path),
+        }
+    }
+}
+
+/// Handle the commit command.
+///
+/// Like restore, this refuses to run while the filesystem is mounted: the
+/// mount would keep serving the delta that the commit clears.
+pub async fn handle_commit_command(id_or_path: String, dry_run: bool) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    let db_path = options
+        .db_path()
+        .context("Failed to resolve database path")?;
+
+    if let Some(mountpoint) = find_mount(&id_or_path, &db_path) {
+        anyhow::bail!(
+            "Agent '{}' is mounted at {}; unmount it before committing",
+            id_or_path,
+            mountpoint.display()
+        );
+    }
+    eprintln!("Using agent: {}", id_or_path);
+
+    let agent = open_agentfs(options).await?;
+    let Some(base_path) = agent.is_overlay_enabled().await? else {
+        anyhow::bail!("Agent '{}' is not an overlay filesystem", id_or_path);
+    };
+    eprintln!("Base: {}", base_path);
+
+    // Committing writes to the user's real directory, so it takes the same
+    // opt-in as an overlay that writes through to its base
+    if !agent.is_base_writable().await? {
+        anyhow::bail!(
+            "The base of agent '{}' is read-only; mark it writable with \
+             AgentFS::set_base_writable (the base_writable key in fs_overlay_config) \
+             before committing",
+            id_or_path
+        );
+    }
+
+    // File contents are read through the overlay so that partially
+    // copied-up files are merged with their base blocks.
+    let base_fs = Arc::new(
+        agent
+            .open_overlay_base(&base_path)
+            .await
+            .context("Failed to open base directory")?,
+    );
+    let overlay = OverlayFS::new(base_fs.clone(), agent.fs.clone());
+    overlay.load().await?;
+
+    let base = PathBuf::from(&base_path);
+    let mut operations = Vec::new();
+
+    // The changeset is sorted by path, so every directory comes before the
+    // entries below it
+    let mut removals = Vec::new();
+    for change in overlay.changeset().await? {
+        let path = change.path;
+        let Some(stats) = change.delta else {
+            if change.base.is_some() {
+                removals.push(Operation::Remove { path });
+            }
+            continue;
+        };
+        let mode = stats.mode & 0o7777;
+        if stats.is_directory() {
+            if !base_join(&base, &path).is_dir() {
+                operations.push(Operation::CreateDir { path, mode });
+            }
+        } else if stats.is_file() {
+            operations.push(Operation::WriteFile { path, mode });
+        } else if stats.is_symlink() {
+            let target = FileSystem::readlink(&agent.fs, stats.ino)
+                .await?
+                .with_context(|| format!("Failed to read symlink {}", path))?;
+            operations.push(Operation::Symlink { path, target });
+        } else {
+            eprintln!("Warning: skipping special file {}", path);
+        }
+    }
+
+    // Whiteouts go last, children before their parents
+    removals.reverse();
+    operations.extend(removals);
+
+    if operations.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
+    for operation in &operations {
+        println!("{}", operation);
+    }
+
+    // Report everything that would fail before changing anything
+    let failures = preflight(&base_fs, &operations).await?;
+    for failure in &failures {
+        eprintln!("Error: {}", failure);
+    }
+    if !failures.is_empty() {
+        anyhow::bail!(
+            "{} change(s) would fail; nothing was committed",
+            failures.len()
+        );
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    for operation in &operations {
+        apply(&overlay, &base, operation)
+            .await
+            .with_context(|| format!("Failed to {}", operation))?;
+    }
+
+    overlay.clear_delta().await?;
+    eprintln!(
+        "Committed {} change(s) to {}",
+        operations.len(),
+        base.display()
+    );
+    Ok(())
+}
+
+/// Apply one operation to the base directory.
+async fn apply(overlay: &OverlayFS, base: &Path, operation: &Operation) -> AnyhowResult<()> {
+    match operation {
+        Operation::CreateDir { path, mode } => {
+            let dest = base_join(base, path);
+            remove_existing(&dest)?;
+            std::fs::create_dir(&dest)?;
+            std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(*mode))?;
+        }
+        Operation::WriteFile { path, mode } => {
+            let stats = lookup_path(overlay, path)
+                .await?
+                .with_context(|| format!("{} disappeared from the overlay", path))?;
+            let dest = base_join(base, path);
+
+            // Stream into a temporary sibling and rename it into place, so
+            // the base file stays readable until its contents are complete
+            let file_name = dest
+                .file_name()
+                .context("Invalid file name")?
+                .to_string_lossy()
+                .to_string();
+            let tmp = dest.with_file_name(format!(".{}.agentfs-commit", file_name));
+            let result = copy_out(overlay, stats.ino, &tmp).await;
+            if let Err(e) = result {
+                let _ = std::fs::remove_file(&tmp);
+                return Err(e);
+            }
+            std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(*mode))?;
+            if dest.is_dir() && !dest.is_symlink() {
+                std::fs::remove_dir_all(&dest)?;
+            }
+            std::fs::rename(&tmp, &dest)?;
+        }
+        Operation::Symlink { path, target } => {
+            let dest = base_join(base, path);
+            remove_existing(&dest)?;
+            std::os::unix::fs::symlink(target, &dest)?;
+        }
+        Operation::Remove { path } => {
+            remove_existing(&base_join(base, path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check each operation against the base directory as it is now, returning
+/// a description of every one that would fail.
+///
+/// An operation whose parent directory an earlier one creates can't be
+/// checked yet and is skipped.
+async fn preflight(base_fs: &HostFS, operations: &[Operation]) -> AnyhowResult<Vec<String>> {
+    let mut failures = Vec::new();
+    for operation in operations {
+        let Some(op) = base_op(base_fs, operation).await? else {
+            continue;
+        };
+        match (base_fs.check_op(&op).await, &op) {
+            (Ok(()), _) => {}
+            // Directories are emptied before they are removed, and a
+            // temporary file left by an interrupted commit is overwritten
+            (Err(SdkError::Fs(FsError::NotEmpty)), FsOperation::Rmdir { .. }) => {}
+            (Err(SdkError::Fs(FsError::AlreadyExists)), FsOperation::CreateFile { .. }) => {}
+            (Err(e), _) => failures.push(format!("{}: {}", operation, e)),
+        }
+    }
+    Ok(failures)
+}
+
+/// The first change `apply` makes to the base directory for an operation,
+/// or `None` if its parent isn't a directory in the base yet.
+async fn base_op(base_fs: &HostFS, operation: &Operation) -> AnyhowResult<Option<FsOperation>> {
+    let path = match operation {
+        Operation::CreateDir { path, .. }
+        | Operation::WriteFile { path, .. }
+        | Operation::Symlink { path, .. }
+        | Operation::Remove { path } => path,
+    };
+    let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let Some(parent) = lookup_path(base_fs, parent_path).await? else {
+        return Ok(None);
+    };
+    if !parent.is_directory() {
+        return Ok(None);
+    }
+    let parent_ino = parent.ino;
+    let existing = base_fs.lookup(parent_ino, name).await?;
+    let name = name.to_string();
+
+    Ok(Some(match (operation, existing) {
+        (Operation::WriteFile { .. }, _) => FsOperation::CreateFile {
+            parent_ino,
+            name: format!(".{}.agentfs-commit", name),
+        },
+        (_, Some(stats)) if stats.is_directory() => FsOperation::Rmdir { parent_ino, name },
+        (_, Some(_)) => FsOperation::Unlink { parent_ino, name },
+        (Operation::CreateDir { .. }, None) => FsOperation::Mkdir { parent_ino, name },
+        (Operation::Symlink { .. }, None) => FsOperation::Symlink { parent_ino, name },
+        (Operation::Remove { .. }, None) => return Ok(None),
+    }))
+}
+
+/// Copy the contents of an overlay file to a host path.
+async fn copy_out(overlay: &OverlayFS, ino: i64, dest: &Path) -> AnyhowResult<()> {
+    use std::io::Write;
+
+    let file = overlay.open(ino, libc::O_RDONLY).await?;
+    let mut out = std::fs::File::create(dest)?;
+    let mut offset = 0;
+    loop {
+        let data = file.pread(offset, COPY_CHUNK_SIZE).await?;
+        if data.is_empty() {
+            break;
+        }
+        out.write_all(&data)?;
+        offset += data.len() as u64;
+    }
+    out.sync_all()?;
+    Ok(())
+}
+
+/// Remove whatever exists at a host path, if anything.
+fn remove_existing(path: &Path) -> std::io::Result<()> {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Resolve an absolute overlay path by walking it from the root.
+async fn lookup_path(fs: &dyn FileSystem, path: &str) -> AnyhowResult<Option<Stats>> {
+    let mut stats = match fs.getattr(ROOT_INO).await? {
+        Some(stats) => stats,
+        None => return Ok(None),
+    };
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        stats = match fs.lookup(stats.ino, component).await? {
+            Some(stats) => stats,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(stats))
+}
+
+/// Map an absolute overlay path onto the base directory.
+fn base_join(base: &Path, path: &str) -> PathBuf {
+    base.join(path.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use agentfs_sdk::{AgentFS, AgentFSOptions, FileSystem, HostFS, OverlayFS};
+    use tempfile::TempDir;
+
+    use super::{handle_commit_command, lookup_path};
+
+    /// Create a base directory and an overlay database on top of it.
+    async fn overlay_agent() -> (TempDir, TempDir, String) {
+        let base = TempDir::new().unwrap();
+        std::fs::write(base.path().join("keep.txt"), b"keep").unwrap();
+        std::fs::write(base.path().join("edit.txt"), b"old").unwrap();
+        std::fs::create_dir(base.path().join("gone")).unwrap();
+        std::fs::write(base.path().join("gone/child.txt"), b"child").unwrap();
+
+        let db_dir = TempDir::new().unwrap();
+        let db_path = db_dir.path().join("agent.db").to_str().unwrap().to_string();
+        let agent = AgentFS::open(AgentFSOptions::with_path(db_path.clone()))
+            .await
+            .unwrap();
+        let overlay = OverlayFS::new(
+            std::sync::Arc::new(HostFS::new(base.path()).unwrap()),
+            agent.fs.clone(),
+        );
+        overlay.init(base.path().to_str().unwrap()).await.unwrap();
+        agent.set_base_writable(true).await.unwrap();
+
+        let edit = lookup_path(&overlay, "/edit.txt").await.unwrap().unwrap();
+        let file = overlay.open(edit.ino, libc::O_RDWR).await.unwrap();
+        file.truncate(0).await.unwrap();
+        file.pwrite(0, b"new").await.unwrap();
+
+        let dir = overlay.mkdir(1, "nested", 0o755, 0, 0).await.unwrap();
+        let (_, file) = overlay
+            .create_file(dir.ino, "added.txt", 0o100640, 0, 0)
+            .await
+            .unwrap();
+        file.pwrite(0, b"added").await.unwrap();
+
+        let gone = lookup_path(&overlay, "/gone").await.unwrap().unwrap();
+        overlay.unlink(gone.ino, "child.txt").await.unwrap();
+        overlay.rmdir(1, "gone").await.unwrap();
+
+        (base, db_dir, db_path)
+    }
+
+    fn read(base: &Path, path: &str) -> Option<Vec<u8>> {
+        std::fs::read(base.join(path)).ok()
+    }
+
+    #[tokio::test]
+    async fn commit_dry_run_leaves_base_untouched() {
+        let (base, _db_dir, db_path) = overlay_agent().await;
+
+        handle_commit_command(db_path.clone(), true).await.unwrap();
+
+        assert_eq!(read(base.path(), "edit.txt").unwrap(), b"old");
+        assert!(read(base.path(), "nested/added.txt").is_none());
+        assert!(base.path().join("gone/child.txt").exists());
+
+        // The delta is still there
+        let agent = AgentFS::open(AgentFSOptions::with_path(db_path))
+            .await
+            .unwrap();
+        assert!(!agent.get_delta_paths().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn commit_refuses_read_only_base() {
+        let (base, _db_dir, db_path) = overlay_agent().await;
+        let agent = AgentFS::open(AgentFSOptions::with_path(db_path.clone()))
+            .await
+            .unwrap();
+        agent.set_base_writable(false).await.unwrap();
+
+        let err = handle_commit_command(db_path, false).await.unwrap_err();
+        assert!(err.to_string().contains("set_base_writable"));
+        assert_eq!(read(base.path(), "edit.txt").unwrap(), b"old");
+        assert!(!agent.get_delta_paths().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn commit_checks_every_change_before_applying_any() {
+        // Root may write anywhere, so there is nothing to refuse
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let (base, _db_dir, db_path) = overlay_agent().await;
+        let gone = base.path().join("gone");
+        std::fs::set_permissions(&gone, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        let result = handle_commit_command(db_path.clone(), false).await;
+        std::fs::set_permissions(&gone, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(result.is_err());
+
+        // Changes that come before the one that would fail weren't applied
+        assert_eq!(read(base.path(), "edit.txt").unwrap(), b"old");
+        assert!(read(base.path(), "nested/added.txt").is_none());
+        assert!(gone.join("child.txt").exists());
+        let agent = AgentFS::open(AgentFSOptions::with_path(db_path))
+            .await
+            .unwrap();
+        assert!(!agent.get_delta_paths().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn commit_applies_delta_and_clears_it() {
+        let (base, _db_dir, db_path) = overlay_agent().await;
+
+        handle_commit_command(db_path.clone(), false).await.unwrap();
+
+        assert_eq!(read(base.path(), "keep.txt").unwrap(), b"keep");
+        assert_eq!(read(base.path(), "edit.txt").unwrap(), b"new");
+        assert_eq!(read(base.path(), "nested/added.txt").unwrap(), b"added");
+        assert!(!base.path().join("gone").exists());
+
+        let agent = AgentFS::open(AgentFSOptions::with_path(db_path))
+            .await
+            .unwrap();
+        assert!(agent.get_delta_paths().await.unwrap().is_empty());
+        assert!(agent.get_whiteouts().await.unwrap().is_empty());
+        assert!(FileSystem::getattr(&agent.fs, 1).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,315 @@
+//! Mount environment diagnostics.
+//!
+//! Check what mounting an agent filesystem depends on (the FUSE device and
+//! helper on Linux, the NFS client, permissions, the mountpoint) and report
+//! each check with a hint on how to fix it, instead of failing on the first
+//! missing piece at mount time.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::Result;
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    /// Only some mounts (another backend, or an option) are affected
+    Warn,
+    Fail,
+}
+
+/// A check with what was found and, unless it passed, how to fix it.
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Handle the doctor command.
+///
+/// Exits with an error if any check failed; warnings only affect the
+/// backend or option they name.
+pub fn handle_doctor_command(mountpoint: Option<&Path>) -> Result<()> {
+    let mut checks = platform_checks();
+    if let Some(mountpoint) = mountpoint {
+        checks.push(check_mountpoint(mountpoint));
+    }
+
+    for check in &checks {
+        let label = match check.status {
+            Status::Pass => " ok ",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        println!("[{}] {}: {}", label, check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            println!("       hint: {}", hint);
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn platform_checks() -> Vec<Check> {
+    vec![
+        check_fuse_device(),
+        check_fusermount(),
+        check_allow_other(),
+        check_nfs_client(
+            &["/sbin/mount.nfs", "/usr/sbin/mount.nfs"],
+            Status::Warn,
+            "Needed only for `--backend nfs`: install the NFS client tools (`nfs-common` or `nfs-utils`)",
+        ),
+        check_nfs_permission(),
+    ]
+}
+
+#[cfg(target_os = "macos")]
+fn platform_checks() -> Vec<Check> {
+    // macOS mounts are always served over NFS; mount_nfs needs no root for
+    // a mountpoint the user owns
+    vec![check_nfs_client(
+        &["/sbin/mount_nfs"],
+        Status::Fail,
+        "mount_nfs ships with macOS; check that /sbin is intact",
+    )]
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn platform_checks() -> Vec<Check> {
+    vec![Check::fail(
+        "platform",
+        std::env::consts::OS,
+        "Mounting is supported on Linux and macOS only",
+    )]
+}
+
+/// Whether the current user has `mode` access to `path`, as the kernel
+/// decides it.
+fn is_accessible(path: &Path, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(target_os = "linux")]
+fn check_fuse_device() -> Check {
+    const NAME: &str = "FUSE device";
+    let device = Path::new("/dev/fuse");
+    if !device.exists() {
+        return Check::fail(
+            NAME,
+            "/dev/fuse does not exist",
+            "Load the FUSE kernel module with `modprobe fuse`; in a container, pass the device in (e.g. `--device /dev/fuse`)",
+        );
+    }
+    if !is_accessible(device, libc::R_OK | libc::W_OK) {
+        return Check::fail(
+            NAME,
+            "/dev/fuse is not readable and writable by the current user",
+            "Add the user to the group that owns /dev/fuse, or make it world-accessible (mode 0666)",
+        );
+    }
+    Check::pass(NAME, "/dev/fuse is readable and writable")
+}
+
+#[cfg(target_os = "linux")]
+fn check_fusermount() -> Check {
+    const NAME: &str = "fusermount";
+    const CANDIDATES: &[&str] = &[
+        "fusermount3",
+        "fusermount",
+        "/sbin/fusermount3",
+        "/sbin/fusermount",
+        "/bin/fusermount3",
+        "/bin/fusermount",
+    ];
+    let Some(path) = CANDIDATES.iter().find_map(|name| find_program(name)) else {
+        return Check::fail(
+            NAME,
+            "neither fusermount3 nor fusermount was found",
+            "Install the FUSE userspace tools (the `fuse3` package on most distributions)",
+        );
+    };
+    let setuid = std::fs::metadata(&path)
+        .map(|meta| meta.mode() & libc::S_ISUID != 0)
+        .unwrap_or(false);
+    if !setuid && !is_root() {
+        return Check::fail(
+            NAME,
+            format!("{} is not setuid root", path.display()),
+            format!(
+                "Unprivileged FUSE mounts need it to be: `chown root {0} && chmod u+s {0}`",
+                path.display()
+            ),
+        );
+    }
+    Check::pass(NAME, path.display().to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn check_allow_other() -> Check {
+    const NAME: &str = "allow_other";
+    if is_root() {
+        return Check::pass(NAME, "running as root");
+    }
+    let conf = std::fs::read_to_string("/etc/fuse.conf").unwrap_or_default();
+    if allows_other(&conf) {
+        Check::pass(NAME, "user_allow_other is set in /etc/fuse.conf")
+    } else {
+        Check::warn(
+            NAME,
+            "user_allow_other is not set in /etc/fuse.conf",
+            "Needed only for `mount --allow-other`/`--allow-root`: add a `user_allow_other` line to /etc/fuse.conf",
+        )
+    }
+}
+
+/// Whether a fuse.conf enables `user_allow_other`.
+#[cfg(target_os = "linux")]
+fn allows_other(conf: &str) -> bool {
+    conf.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .any(|line| line == "user_allow_other")
+}
+
+fn check_nfs_client(helpers: &[&str], missing: Status, hint: &str) -> Check {
+    const NAME: &str = "NFS client";
+    match helpers.iter().find(|helper| Path::new(helper).exists()) {
+        Some(helper) => Check::pass(NAME, *helper),
+        None => Check {
+            status: missing,
+            ..Check::fail(NAME, format!("{} not found", helpers.join(" or ")), hint)
+        },
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_nfs_permission() -> Check {
+    const NAME: &str = "NFS mount permission";
+    if is_root() {
+        Check::pass(NAME, "running as root")
+    } else {
+        Check::warn(
+            NAME,
+            "not running as root",
+            "Needed only for `--backend nfs`: mounting NFS on Linux requires root, so run the mount with sudo or use the FUSE backend",
+        )
+    }
+}
+
+fn check_mountpoint(mountpoint: &Path) -> Check {
+    const NAME: &str = "mountpoint";
+    let shown = mountpoint.display();
+    let meta = match std::fs::metadata(mountpoint) {
+        Ok(meta) => meta,
+        Err(_) => {
+            return Check::fail(
+                NAME,
+                format!("{} does not exist", shown),
+                format!("Create it with `mkdir -p {}`", shown),
+            )
+        }
+    };
+    if !meta.is_dir() {
+        return Check::fail(
+            NAME,
+            format!("{} is not a directory", shown),
+            "Mount on an empty directory",
+        );
+    }
+    let canonical = std::fs::canonicalize(mountpoint).unwrap_or_else(|_| mountpoint.to_path_buf());
+    if agentfs_sdk::get_mounts()
+        .iter()
+        .any(|m| m.mountpoint == canonical)
+    {
+        return Check::fail(
+            NAME,
+            format!("an agent filesystem is already mounted on {}", shown),
+            format!("Unmount it first with `agentfs umount {}`", shown),
+        );
+    }
+    let owned = meta.uid() == unsafe { libc::geteuid() };
+    if !is_root() && !owned && !is_accessible(mountpoint, libc::W_OK) {
+        return Check::fail(
+            NAME,
+            format!(
+                "{} is neither owned nor writable by the current user",
+                shown
+            ),
+            "Mount on a directory you own, or change its owner with chown",
+        );
+    }
+    Check::pass(NAME, format!("{} can be mounted on", shown))
+}
+
+/// Find `name` in `PATH`, or as given if it is a path.
+#[cfg(target_os = "linux")]
+fn find_program(name: &str) -> Option<std::path::PathBuf> {
+    if name.contains('/') {
+        let path = Path::new(name);
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn fuse_conf_allow_other_ignores_comments() {
+        assert!(allows_other("# mount_max = 1000\nuser_allow_other\n"));
+        assert!(allows_other("  user_allow_other  # for agentfs\n"));
+        assert!(!allows_other("#user_allow_other\n"));
+        assert!(!allows_other(""));
+    }
+}
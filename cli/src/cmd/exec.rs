@@ -4,7 +4,7 @@
 //! filesystem to a temporary directory, runs a command with that as the
 //! working directory, and automatically unmounts when done.
 
-use agentfs_sdk::{AgentFSOptions, EncryptionConfig, FileSystem, HostFS, OverlayFS};
+use agentfs_sdk::{AgentFSOptions, EncryptionConfig, FileSystem, OverlayFS};
 use anyhow::{Context, Result};
 use std::path::PathBuf;
 use std::process::Command;
@@ -62,7 +62,7 @@ pub async fn handle_exec_command(
 
         if let Some(base_path) = base_path {
             eprintln!("Using overlay filesystem with base: {}", base_path);
-            let hostfs = HostFS::new(&base_path)?;
+            let hostfs = agentfs.open_overlay_base(&base_path).await?;
             let overlay = OverlayFS::new(Arc::new(hostfs), agentfs.fs);
             overlay.load().await?; // Load persisted whiteouts and origin mappings
             Arc::new(Mutex::new(overlay)) as Arc<Mutex<dyn FileSystem + Send>>
@@ -0,0 +1,293 @@
+//! Flatten command.
+//!
+//! Materialize the merged view of an overlay filesystem into a new,
+//! standalone agent filesystem that no longer needs the base directory.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use agentfs_sdk::filesystem::AgentFS;
+use agentfs_sdk::{agentfs_dir, AgentFSOptions, FileSystem, OverlayFS, Stats, TimeChange};
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+use crate::cmd::walk::Walker;
+
+const ROOT_INO: i64 = 1;
+
+/// Size of the reads used to stream file contents out of the overlay.
+const COPY_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// What a flatten copied into the new filesystem.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FlattenStats {
+    /// Entries created, counting each hard link
+    pub entries: u64,
+    /// Regular files copied, counting each hard-linked file once
+    pub files: u64,
+    /// Bytes of file contents copied
+    pub bytes: u64,
+}
+
+/// Handle the flatten command.
+///
+/// The source is only read, so it may stay mounted; writes made through
+/// the mount while flattening may or may not be included.
+pub async fn handle_flatten_command(id_or_path: String, new_id: String) -> AnyhowResult<()> {
+    if !AgentFSOptions::validate_agent_id(&new_id) {
+        anyhow::bail!(
+            "Invalid agent ID '{}'. Agent IDs must contain only alphanumeric characters, hyphens, and underscores.",
+            new_id
+        );
+    }
+    let db_path = agentfs_dir().join(format!("{}.db", new_id));
+    if db_path.exists() {
+        anyhow::bail!("Agent '{}' already exists at {}", new_id, db_path.display());
+    }
+
+    eprintln!("Using agent: {}", id_or_path);
+    let agent = open_agentfs(AgentFSOptions::resolve(&id_or_path)?).await?;
+    let Some(base_path) = agent.is_overlay_enabled().await? else {
+        anyhow::bail!("Agent '{}' is not an overlay filesystem", id_or_path);
+    };
+    eprintln!("Base: {}", base_path);
+    let hostfs = agent
+        .open_overlay_base(&base_path)
+        .await
+        .context("Failed to create HostFS")?;
+    let overlay = OverlayFS::new(Arc::new(hostfs), agent.fs.clone());
+    overlay.load().await?;
+
+    let dest = agentfs_sdk::AgentFS::open(AgentFSOptions::with_id(&new_id))
+        .await
+        .context("Failed to create database")?;
+    let stats = match flatten_into(&overlay, &dest.fs).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            // Leave no partial copy behind under the new ID
+            drop(dest);
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", db_path.display(), suffix));
+            }
+            return Err(e);
+        }
+    };
+
+    eprintln!(
+        "Flattened {} entries ({} files, {}) into {}",
+        stats.entries,
+        stats.files,
+        crate::cmd::ps::format_size(stats.bytes),
+        db_path.display()
+    );
+    eprintln!("Agent ID: {}", new_id);
+    Ok(())
+}
+
+/// Copy every entry visible in `source` into the empty filesystem `dest`.
+///
+/// Modes, ownership, timestamps, extended attributes and hard links are
+/// kept. Reading through an overlay means whited-out entries and the base
+/// contents of opaque directories are never seen.
+pub async fn flatten_into<F: FileSystem + ?Sized>(
+    source: &F,
+    dest: &AgentFS,
+) -> AnyhowResult<FlattenStats> {
+    let mut stats = FlattenStats::default();
+    // Path of every directory copied so far, to its new inode
+    let mut dir_inos: HashMap<String, i64> = HashMap::new();
+    // Source inode of every file with more than one link, to its new inode
+    let mut linked: HashMap<i64, i64> = HashMap::new();
+    let mut dirs = Vec::new();
+    let mut walker = Walker::new(source, ROOT_INO).await?;
+
+    while let Some(entry) = walker.next().await? {
+        let (parent, name) = entry.path.rsplit_once('/').unwrap_or(("", &entry.path));
+        let parent_ino = match parent {
+            "" => ROOT_INO,
+            parent => *dir_inos
+                .get(parent)
+                .with_context(|| format!("/{} was copied before its parent", entry.path))?,
+        };
+        let src = &entry.stats;
+
+        if src.nlink > 1 && !src.is_directory() {
+            if let Some(&ino) = linked.get(&src.ino) {
+                FileSystem::link(dest, ino, parent_ino, name).await?;
+                stats.entries += 1;
+                continue;
+            }
+        }
+
+        let created = if src.is_directory() {
+            let created =
+                FileSystem::mkdir(dest, parent_ino, name, src.mode, src.uid, src.gid).await?;
+            dir_inos.insert(entry.path.clone(), created.ino);
+            dirs.push((created.ino, src.clone()));
+            created
+        } else if src.is_symlink() {
+            let target = source
+                .readlink(src.ino)
+                .await?
+                .with_context(|| format!("Failed to read symlink /{}", entry.path))?;
+            FileSystem::symlink(dest, parent_ino, name, &target, src.uid, src.gid).await?
+        } else if src.is_file() {
+            let (created, file) =
+                FileSystem::create_file(dest, parent_ino, name, src.mode, src.uid, src.gid).await?;
+            let input = source.open(src.ino, libc::O_RDONLY).await?;
+            let mut offset = 0;
+            loop {
+                let chunk = input.pread(offset, COPY_CHUNK_SIZE).await?;
+                if chunk.is_empty() {
+                    break;
+                }
+                file.pwrite(offset, &chunk).await?;
+                offset += chunk.len() as u64;
+            }
+            stats.files += 1;
+            stats.bytes += offset;
+            created
+        } else {
+            FileSystem::mknod(dest, parent_ino, name, src.mode, src.rdev, src.uid, src.gid).await?
+        };
+
+        if src.nlink > 1 && !src.is_directory() {
+            linked.insert(src.ino, created.ino);
+        }
+        copy_xattrs(source, src.ino, dest, created.ino).await?;
+        if !src.is_directory() && !src.is_symlink() {
+            set_metadata(dest, created.ino, src).await?;
+        }
+        stats.entries += 1;
+    }
+
+    if let Some(root) = source.getattr(ROOT_INO).await? {
+        copy_xattrs(source, ROOT_INO, dest, ROOT_INO).await?;
+        FileSystem::chown(dest, ROOT_INO, Some(root.uid), Some(root.gid)).await?;
+        dirs.push((ROOT_INO, root));
+    }
+    for (ino, src) in dirs.iter().rev() {
+        set_metadata(dest, *ino, src).await?;
+    }
+    Ok(stats)
+}
+
+/// Copy the extended attributes of a source entry to its copy.
+async fn copy_xattrs<F: FileSystem + ?Sized>(
+    source: &F,
+    src_ino: i64,
+    dest: &AgentFS,
+    dest_ino: i64,
+) -> AnyhowResult<()> {
+    for name in source.listxattr(src_ino).await? {
+        if let Some(value) = source.getxattr(src_ino, &name).await? {
+            FileSystem::setxattr(dest, dest_ino, &name, &value, 0).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Apply the mode and timestamps of a source entry to its copy.
+///
+/// The mode is set explicitly so that a configured umask does not change
+/// what was copied.
+async fn set_metadata(dest: &AgentFS, ino: i64, src: &Stats) -> AnyhowResult<()> {
+    FileSystem::chmod(dest, ino, src.mode).await?;
+    FileSystem::utimens(
+        dest,
+        ino,
+        TimeChange::Set(src.atime, src.atime_nsec),
+        TimeChange::Set(src.mtime, src.mtime_nsec),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use agentfs_sdk::{AgentFS, AgentFSOptions, FileSystem, HostFS, OverlayFS, S_IFREG};
+    use tempfile::TempDir;
+
+    use super::{flatten_into, ROOT_INO};
+
+    #[tokio::test]
+    async fn test_flatten_overlay() {
+        let base = TempDir::new().unwrap();
+        std::fs::write(base.path().join("base_only.txt"), b"from base").unwrap();
+        std::fs::write(base.path().join("modified.txt"), b"original").unwrap();
+        std::fs::write(base.path().join("deleted.txt"), b"deleted").unwrap();
+        std::fs::create_dir(base.path().join("replaced")).unwrap();
+        std::fs::write(base.path().join("replaced/old.txt"), b"hidden").unwrap();
+        std::fs::create_dir(base.path().join("nested")).unwrap();
+        std::fs::write(base.path().join("nested/deep.txt"), b"deep").unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let delta_path = dir.path().join("delta.db");
+        let delta = AgentFS::open(
+            AgentFSOptions::with_path(delta_path.to_str().unwrap()).with_base(base.path()),
+        )
+        .await
+        .unwrap();
+        let hostfs = HostFS::new_readonly(base.path()).unwrap();
+        let overlay = OverlayFS::new(Arc::new(hostfs), delta.fs.clone());
+        overlay.load().await.unwrap();
+
+        let modified = overlay
+            .lookup(ROOT_INO, "modified.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        let file = overlay.open(modified.ino, libc::O_RDWR).await.unwrap();
+        file.pwrite(0, b"changed!").await.unwrap();
+        overlay.unlink(ROOT_INO, "deleted.txt").await.unwrap();
+        let (_, file) = overlay
+            .create_file(ROOT_INO, "new.txt", S_IFREG | 0o600, 0, 0)
+            .await
+            .unwrap();
+        file.pwrite(0, b"new").await.unwrap();
+        // An opaque directory hides everything the base had in it
+        overlay.remove_all("/replaced").await.unwrap();
+        let replaced = overlay
+            .mkdir(ROOT_INO, "replaced", 0o755, 0, 0)
+            .await
+            .unwrap();
+        overlay
+            .create_file(replaced.ino, "fresh.txt", S_IFREG | 0o644, 0, 0)
+            .await
+            .unwrap();
+
+        let flat_path = dir.path().join("flat.db");
+        let flat = AgentFS::open(AgentFSOptions::with_path(flat_path.to_str().unwrap()))
+            .await
+            .unwrap();
+        let stats = flatten_into(&overlay, &flat.fs).await.unwrap();
+        assert_eq!(stats.files, 5);
+        drop(overlay);
+        drop(base);
+
+        let fs = &flat.fs;
+        assert!(flat.is_overlay_enabled().await.unwrap().is_none());
+        assert_eq!(
+            fs.read_file("/base_only.txt").await.unwrap().unwrap(),
+            b"from base"
+        );
+        assert_eq!(
+            fs.read_file("/modified.txt").await.unwrap().unwrap(),
+            b"changed!"
+        );
+        assert_eq!(fs.read_file("/new.txt").await.unwrap().unwrap(), b"new");
+        assert_eq!(
+            fs.read_file("/nested/deep.txt").await.unwrap().unwrap(),
+            b"deep"
+        );
+        assert_eq!(
+            fs.lstat("/new.txt").await.unwrap().unwrap().mode & 0o7777,
+            0o600
+        );
+        assert!(fs.lstat("/deleted.txt").await.unwrap().is_none());
+        assert!(fs.lstat("/replaced/fresh.txt").await.unwrap().is_some());
+        assert!(fs.lstat("/replaced/old.txt").await.unwrap().is_none());
+    }
+}
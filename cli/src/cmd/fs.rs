@@ -1,100 +1,87 @@
 use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
-use agentfs_sdk::{AgentFSOptions, EncryptionConfig};
+use agentfs_sdk::filesystem::AgentFS;
+use agentfs_sdk::{
+    AgentFSOptions, ChangeEntry, ChangeKind, EncryptionConfig, FileSystem, Stats, TimeChange,
+};
 use anyhow::{Context, Result as AnyhowResult};
-use turso::Value;
 
 use crate::cmd::init::open_agentfs;
+use crate::cmd::walk::Walker;
 
-const ROOT_INO: i64 = 1;
 const S_IFMT: u32 = 0o170000;
 const S_IFDIR: u32 = 0o040000;
 const S_IFREG: u32 = 0o100000;
 const S_IFLNK: u32 = 0o120000;
 
-pub async fn ls_filesystem(
-    stdout: &mut impl std::io::Write,
-    id_or_path: String,
-    path: &str,
+/// Resolve an agent ID or database path, with the encryption settings
+/// given on the command line.
+fn resolve_options(
+    id_or_path: &str,
     encryption: Option<&(String, String)>,
-) -> AnyhowResult<()> {
-    let mut options = AgentFSOptions::resolve(&id_or_path)?;
+) -> AnyhowResult<AgentFSOptions> {
+    let mut options = AgentFSOptions::resolve(id_or_path)?;
     if let Some((key, cipher)) = encryption {
         options = options.with_encryption(EncryptionConfig {
             hex_key: key.clone(),
             cipher: cipher.clone(),
         });
     }
+    Ok(options)
+}
+
+/// List the entries below a directory, breadth first, one per line.
+///
+/// Each line holds a type character (`d`, `f`, `l`) and the path relative
+/// to `path`; with `long`, the type character is replaced by the mode,
+/// link count, owner, size and mtime, as in `ls -l`. A `path` that is not
+/// a directory lists only itself.
+pub async fn ls_filesystem(
+    stdout: &mut impl std::io::Write,
+    id_or_path: String,
+    path: &str,
+    long: bool,
+    encryption: Option<&(String, String)>,
+) -> AnyhowResult<()> {
+    let options = resolve_options(&id_or_path, encryption)?;
     eprintln!("Using agent: {}", id_or_path);
 
     let agentfs = open_agentfs(options).await?;
-    let conn = agentfs.get_connection().await?;
+    let fs = &agentfs.fs;
 
-    if path != "/" {
-        anyhow::bail!("Only root directory (/) is currently supported");
+    let stats = fs
+        .stat(path)
+        .await?
+        .with_context(|| format!("No such file or directory: {}", path))?;
+    if !stats.is_directory() {
+        let line = ls_line(fs, path, &stats, long).await?;
+        writeln!(stdout, "{}", line).context("Failed to write to stdout")?;
+        return Ok(());
     }
 
     let mut queue: VecDeque<(i64, String)> = VecDeque::new();
-    queue.push_back((ROOT_INO, String::new()));
+    queue.push_back((stats.ino, String::new()));
 
     while let Some((parent_ino, prefix)) = queue.pop_front() {
-        let query = format!(
-            "SELECT d.name, d.ino, i.mode FROM fs_dentry d
-             JOIN fs_inode i ON d.ino = i.ino
-             WHERE d.parent_ino = {}
-             ORDER BY d.name",
-            parent_ino
-        );
-
-        let mut rows = conn
-            .query(&query, ())
-            .await
-            .context("Failed to query directory entries")?;
-
-        let mut entries = Vec::new();
-        while let Some(row) = rows.next().await.context("Failed to fetch row")? {
-            let name: String = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| {
-                    if let Value::Text(s) = v {
-                        Some(s.clone())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default();
-
-            let ino: i64 = row
-                .get_value(1)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0);
-
-            let mode: u32 = row
-                .get_value(2)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
-
-            entries.push((name, ino, mode));
-        }
+        let mut entries = FileSystem::readdir_plus(fs, parent_ino)
+            .await
+            .context("Failed to read directory entries")?
+            .unwrap_or_default();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
 
-        for (name, ino, mode) in entries {
-            let is_dir = mode & S_IFMT == S_IFDIR;
-            let type_char = if is_dir { 'd' } else { 'f' };
+        for entry in entries {
             let full_path = if prefix.is_empty() {
-                name.clone()
+                entry.name
             } else {
-                format!("{}/{}", prefix, name)
+                format!("{}/{}", prefix, entry.name)
             };
+            let line = ls_line(fs, &full_path, &entry.stats, long).await?;
+            writeln!(stdout, "{}", line).context("Failed to write to stdout")?;
 
-            stdout
-                .write_fmt(format_args!("{} {}\n", type_char, full_path))
-                .context("Failed to write to stdout")?;
-
-            if is_dir {
-                queue.push_back((ino, full_path));
+            if entry.stats.is_directory() {
+                queue.push_back((entry.stats.ino, full_path));
             }
         }
     }
@@ -102,43 +89,59 @@ pub async fn ls_filesystem(
     Ok(())
 }
 
+/// Format one `ls` line for an entry.
+async fn ls_line(fs: &AgentFS, path: &str, stats: &Stats, long: bool) -> AnyhowResult<String> {
+    if !long {
+        return Ok(format!("{} {}", file_type_char(stats.mode), path));
+    }
+    let mut line = format!(
+        "{} {:>3} {:>5} {:>5} {:>10} {} {}",
+        mode_string(stats.mode),
+        stats.nlink,
+        stats.uid,
+        stats.gid,
+        stats.size,
+        format_timestamp(stats.mtime),
+        path
+    );
+    if stats.is_symlink() {
+        let target = FileSystem::readlink(fs, stats.ino)
+            .await?
+            .unwrap_or_default();
+        line.push_str(&format!(" -> {}", target));
+    }
+    Ok(line)
+}
+
+/// Write the contents of a file to `stdout`, byte for byte.
 pub async fn cat_filesystem(
     stdout: &mut impl std::io::Write,
     id_or_path: String,
     path: &str,
     encryption: Option<&(String, String)>,
 ) -> AnyhowResult<()> {
-    let mut options = AgentFSOptions::resolve(&id_or_path)?;
-    if let Some((key, cipher)) = encryption {
-        options = options.with_encryption(EncryptionConfig {
-            hex_key: key.clone(),
-            cipher: cipher.clone(),
-        });
-    }
+    let options = resolve_options(&id_or_path, encryption)?;
     let agentfs = open_agentfs(options).await?;
 
     match agentfs.fs.read_file(path).await? {
         Some(file) => {
             stdout.write_all(&file)?;
+            stdout.flush()?;
             Ok(())
         }
         None => anyhow::bail!("File not found: {}", path),
     }
 }
 
+/// Replace the contents of a file, creating it and any missing parent
+/// directories.
 pub async fn write_filesystem(
     id_or_path: String,
     path: &str,
-    content: &str,
+    content: &[u8],
     encryption: Option<&(String, String)>,
 ) -> AnyhowResult<()> {
-    let mut options = AgentFSOptions::resolve(&id_or_path)?;
-    if let Some((key, cipher)) = encryption {
-        options = options.with_encryption(EncryptionConfig {
-            hex_key: key.clone(),
-            cipher: cipher.clone(),
-        });
-    }
+    let options = resolve_options(&id_or_path, encryption)?;
     let agentfs = open_agentfs(options).await?;
 
     let mut components = path.split("/").collect::<Vec<_>>();
@@ -158,26 +161,405 @@ pub async fn write_filesystem(
         agentfs.fs.remove(path).await?;
     }
     let (_, file) = agentfs.fs.create_file(path, S_IFREG | 0o644, 0, 0).await?;
-    file.pwrite(0, content.as_bytes()).await?;
+    file.pwrite(0, content).await?;
     Ok(())
 }
 
-/// Represents a change type in the overlay filesystem
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum ChangeType {
-    Added,
-    Modified,
-    Deleted,
+/// Remove a file or empty directory, or with `recursive` a whole tree.
+pub async fn rm_filesystem(
+    id_or_path: String,
+    path: &str,
+    recursive: bool,
+    encryption: Option<&(String, String)>,
+) -> AnyhowResult<()> {
+    let options = resolve_options(&id_or_path, encryption)?;
+    let agentfs = open_agentfs(options).await?;
+
+    if agentfs.fs.lstat(path).await?.is_none() {
+        anyhow::bail!("No such file or directory: {}", path);
+    }
+    if recursive {
+        FileSystem::remove_all(&agentfs.fs, path).await?;
+    } else {
+        agentfs.fs.remove(path).await?;
+    }
+    Ok(())
 }
 
-impl std::fmt::Display for ChangeType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ChangeType::Added => write!(f, "A"),
-            ChangeType::Modified => write!(f, "M"),
-            ChangeType::Deleted => write!(f, "D"),
+/// Create a directory, or with `parents` a directory and any missing
+/// ancestors, succeeding if it already exists.
+pub async fn mkdir_filesystem(
+    id_or_path: String,
+    path: &str,
+    parents: bool,
+    encryption: Option<&(String, String)>,
+) -> AnyhowResult<()> {
+    let options = resolve_options(&id_or_path, encryption)?;
+    let agentfs = open_agentfs(options).await?;
+
+    if !parents {
+        agentfs.fs.mkdir(path, 0, 0).await?;
+        return Ok(());
+    }
+    let mut current = String::new();
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        current.push('/');
+        current.push_str(component);
+        match agentfs.fs.stat(&current).await? {
+            Some(stats) if stats.is_directory() => {}
+            Some(_) => anyhow::bail!("Not a directory: {}", current),
+            None => agentfs.fs.mkdir(&current, 0, 0).await?,
         }
     }
+    Ok(())
+}
+
+/// Print the metadata of an entry in the manner of `stat(1)`.
+///
+/// Symlinks are described themselves, not followed.
+pub async fn stat_filesystem(
+    stdout: &mut impl std::io::Write,
+    id_or_path: String,
+    path: &str,
+    encryption: Option<&(String, String)>,
+) -> AnyhowResult<()> {
+    let options = resolve_options(&id_or_path, encryption)?;
+    let agentfs = open_agentfs(options).await?;
+
+    let stats = agentfs
+        .fs
+        .lstat(path)
+        .await?
+        .with_context(|| format!("No such file or directory: {}", path))?;
+    let name = if stats.is_symlink() {
+        let target = agentfs.fs.readlink(path).await?.unwrap_or_default();
+        format!("{} -> {}", path, target)
+    } else {
+        path.to_string()
+    };
+    let kind = match stats.mode & S_IFMT {
+        S_IFDIR => "directory",
+        S_IFREG => "regular file",
+        S_IFLNK => "symbolic link",
+        _ => "special file",
+    };
+
+    writeln!(stdout, "  File: {}", name)?;
+    writeln!(
+        stdout,
+        "  Size: {:<12} Blocks: {:<8} {}",
+        stats.size, stats.blocks, kind
+    )?;
+    writeln!(stdout, " Inode: {:<12} Links: {}", stats.ino, stats.nlink)?;
+    writeln!(
+        stdout,
+        "Access: ({:04o}/{})  Uid: {}  Gid: {}",
+        stats.mode & 0o7777,
+        mode_string(stats.mode),
+        stats.uid,
+        stats.gid
+    )?;
+    writeln!(stdout, "Access: {}", format_timestamp(stats.atime))?;
+    writeln!(stdout, "Modify: {}", format_timestamp(stats.mtime))?;
+    writeln!(stdout, "Change: {}", format_timestamp(stats.ctime))?;
+    Ok(())
+}
+
+/// Format a mode as in `ls -l`, e.g. `drwxr-xr-x`.
+pub(crate) fn mode_string(mode: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match mode & S_IFMT {
+        S_IFDIR => 'd',
+        S_IFLNK => 'l',
+        S_IFREG => '-',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o010000 => 'p',
+        0o140000 => 's',
+        _ => '?',
+    });
+    // Special bits replace the execute bit of their class
+    let special = [(0o4000, 's'), (0o2000, 's'), (0o1000, 't')];
+    for (class, (bit, mark)) in special.into_iter().enumerate() {
+        let perms = (mode >> (6 - 3 * class)) & 0o7;
+        s.push(if perms & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if perms & 0o2 != 0 { 'w' } else { '-' });
+        s.push(match (perms & 0o1 != 0, mode & bit != 0) {
+            (true, true) => mark,
+            (false, true) => mark.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+/// Format a timestamp as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub(crate) fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// One side of a `cp` command.
+#[derive(Debug, PartialEq)]
+enum CpLocation {
+    /// A path inside an agent filesystem, written `<ID_OR_PATH>:<PATH>`
+    Agent { id_or_path: String, path: String },
+    /// A path on the host
+    Local(PathBuf),
+}
+
+/// Parse a `cp` argument.
+///
+/// Existing host paths are always local, so database paths and files whose
+/// names contain a colon can still be copied.
+fn parse_cp_location(spec: &str) -> CpLocation {
+    if !Path::new(spec).exists() {
+        if let Some((id_or_path, path)) = spec.split_once(':') {
+            if !id_or_path.is_empty() {
+                let path = if path.is_empty() { "/" } else { path };
+                return CpLocation::Agent {
+                    id_or_path: id_or_path.to_string(),
+                    path: format!("/{}", path.trim_start_matches('/')),
+                };
+            }
+        }
+    }
+    CpLocation::Local(PathBuf::from(spec))
+}
+
+/// Copy files between an agent filesystem and the host without mounting it.
+///
+/// Directories are copied recursively. File modes and modification times
+/// are preserved where the destination supports them.
+pub async fn cp_filesystem(
+    source: &str,
+    dest: &str,
+    encryption: Option<&(String, String)>,
+) -> AnyhowResult<()> {
+    let (id_or_path, export, agent_path, local) =
+        match (parse_cp_location(source), parse_cp_location(dest)) {
+            (CpLocation::Agent { id_or_path, path }, CpLocation::Local(local)) => {
+                (id_or_path, true, path, local)
+            }
+            (CpLocation::Local(local), CpLocation::Agent { id_or_path, path }) => {
+                (id_or_path, false, path, local)
+            }
+            _ => anyhow::bail!(
+                "Exactly one of SOURCE and DEST must be an agent path (<ID_OR_PATH>:<PATH>)"
+            ),
+        };
+
+    let options = resolve_options(&id_or_path, encryption)?;
+    eprintln!("Using agent: {}", id_or_path);
+    let agentfs = open_agentfs(options).await?;
+
+    let copied = if export {
+        export_tree(&agentfs.fs, &agent_path, &local).await?
+    } else {
+        import_tree(&agentfs.fs, &local, &agent_path).await?
+    };
+    eprintln!("Copied {} entries", copied);
+    Ok(())
+}
+
+/// Copy an agent path (recursively) to the host. Returns the number of entries copied.
+async fn export_tree(fs: &AgentFS, source: &str, dest: &Path) -> AnyhowResult<usize> {
+    let stats = fs
+        .lstat(source)
+        .await?
+        .with_context(|| format!("No such file or directory: {}", source))?;
+
+    // Like cp, copying into an existing directory keeps the source name
+    let dest = match Path::new(source).file_name() {
+        Some(name) if dest.is_dir() => dest.join(name),
+        _ => dest.to_path_buf(),
+    };
+
+    if !stats.is_directory() {
+        export_entry(fs, source, &dest, &stats).await?;
+        return Ok(1);
+    }
+    std::fs::create_dir_all(&dest)
+        .with_context(|| format!("Failed to create {}", dest.display()))?;
+    let mut dirs = vec![(dest.clone(), stats.clone())];
+    let mut copied = 1;
+    let mut walker = Walker::new(fs, stats.ino).await?;
+    while let Some(entry) = walker.next().await? {
+        let path = format!("{}/{}", source.trim_end_matches('/'), entry.path);
+        let dest = dest.join(&entry.path);
+        if entry.stats.is_directory() {
+            std::fs::create_dir_all(&dest)
+                .with_context(|| format!("Failed to create {}", dest.display()))?;
+            dirs.push((dest, entry.stats));
+        } else if !export_entry(fs, &path, &dest, &entry.stats).await? {
+            continue;
+        }
+        copied += 1;
+    }
+    for (dest, stats) in dirs.iter().rev() {
+        set_local_metadata(dest, stats);
+    }
+    Ok(copied)
+}
+
+/// Copy a file or symlink at an agent path to the host. Returns whether it
+/// was copied, as special files are skipped.
+async fn export_entry(fs: &AgentFS, path: &str, dest: &Path, stats: &Stats) -> AnyhowResult<bool> {
+    if stats.is_file() {
+        let data = fs.read_file(path).await?.unwrap_or_default();
+        std::fs::write(dest, data)
+            .with_context(|| format!("Failed to write {}", dest.display()))?;
+        set_local_metadata(dest, stats);
+    } else if stats.is_symlink() {
+        let target = fs.readlink(path).await?.unwrap_or_default();
+        export_symlink(&target, dest)?;
+    } else {
+        eprintln!("Warning: skipping special file {}", path);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Copy a host path (recursively) into an agent filesystem. Returns the number of entries copied.
+async fn import_tree(fs: &AgentFS, source: &Path, dest: &str) -> AnyhowResult<usize> {
+    let metadata = std::fs::symlink_metadata(source)
+        .with_context(|| format!("No such file or directory: {}", source.display()))?;
+
+    let dest = match (fs.stat(dest).await?, source.file_name()) {
+        (Some(stats), Some(name)) if stats.is_directory() => {
+            format!("{}/{}", dest.trim_end_matches('/'), name.to_string_lossy())
+        }
+        _ => dest.to_string(),
+    };
+
+    let mut pending = vec![(source.to_path_buf(), dest, metadata)];
+    let mut dirs = Vec::new();
+    let mut copied = 0;
+    while let Some((source, path, metadata)) = pending.pop() {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            match fs.lstat(&path).await? {
+                Some(stats) if stats.is_directory() => {}
+                Some(_) => anyhow::bail!("Not a directory: {}", path),
+                None => fs.mkdir(&path, 0, 0).await?,
+            }
+            for entry in std::fs::read_dir(&source)? {
+                let entry = entry?;
+                let child = format!(
+                    "{}/{}",
+                    path.trim_end_matches('/'),
+                    entry.file_name().to_string_lossy()
+                );
+                pending.push((entry.path(), child, entry.metadata()?));
+            }
+            dirs.push((path, metadata));
+        } else if file_type.is_file() {
+            let data = std::fs::read(&source)
+                .with_context(|| format!("Failed to read {}", source.display()))?;
+            if fs.lstat(&path).await?.is_some() {
+                fs.remove(&path).await?;
+            }
+            let (_, file) = fs
+                .create_file(&path, S_IFREG | (local_mode(&metadata) & 0o7777), 0, 0)
+                .await?;
+            file.pwrite(0, &data).await?;
+            set_agent_metadata(fs, &path, &metadata).await?;
+        } else if file_type.is_symlink() {
+            let target = std::fs::read_link(&source)?;
+            if fs.lstat(&path).await?.is_some() {
+                fs.remove(&path).await?;
+            }
+            fs.symlink(&target.to_string_lossy(), &path, 0, 0).await?;
+        } else {
+            eprintln!("Warning: skipping special file {}", source.display());
+            continue;
+        }
+        copied += 1;
+    }
+    for (path, metadata) in dirs.iter().rev() {
+        set_agent_metadata(fs, path, metadata).await?;
+    }
+    Ok(copied)
+}
+
+/// Apply an agent entry's mode and mtime to a host path, ignoring failures.
+fn set_local_metadata(dest: &Path, stats: &Stats) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ =
+            std::fs::set_permissions(dest, std::fs::Permissions::from_mode(stats.mode & 0o7777));
+    }
+    let mtime = UNIX_EPOCH + Duration::new(stats.mtime.max(0) as u64, stats.mtime_nsec);
+    if let Ok(file) = std::fs::File::open(dest) {
+        let _ = file.set_modified(mtime);
+    }
+}
+
+/// Apply a host entry's mode and mtime to an agent path.
+async fn set_agent_metadata(
+    fs: &AgentFS,
+    path: &str,
+    metadata: &std::fs::Metadata,
+) -> AnyhowResult<()> {
+    let Some(stats) = fs.lstat(path).await? else {
+        return Ok(());
+    };
+    FileSystem::chmod(
+        fs,
+        stats.ino,
+        (stats.mode & S_IFMT) | (local_mode(metadata) & 0o7777),
+    )
+    .await?;
+    if let Ok(mtime) = metadata.modified() {
+        if let Ok(since_epoch) = mtime.duration_since(UNIX_EPOCH) {
+            let mtime = TimeChange::Set(since_epoch.as_secs() as i64, since_epoch.subsec_nanos());
+            FileSystem::utimens(fs, stats.ino, TimeChange::Omit, mtime).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Permission bits of a host entry.
+#[cfg(unix)]
+fn local_mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn local_mode(metadata: &std::fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+#[cfg(unix)]
+fn export_symlink(target: &str, dest: &Path) -> AnyhowResult<()> {
+    if dest.symlink_metadata().is_ok() {
+        std::fs::remove_file(dest)?;
+    }
+    std::os::unix::fs::symlink(target, dest)
+        .with_context(|| format!("Failed to create symlink {}", dest.display()))
+}
+
+#[cfg(not(unix))]
+fn export_symlink(_target: &str, dest: &Path) -> AnyhowResult<()> {
+    eprintln!("Warning: skipping symlink {}", dest.display());
+    Ok(())
+}
+
+/// Single-letter code for a change kind
+fn change_code(kind: ChangeKind) -> char {
+    match kind {
+        ChangeKind::Added => 'A',
+        ChangeKind::Modified => 'M',
+        ChangeKind::Deleted => 'D',
+    }
 }
 
 /// Get file type character
@@ -190,13 +572,42 @@ fn file_type_char(mode: u32) -> char {
     }
 }
 
-/// Check if a path exists in the host filesystem (base layer)
-fn path_exists_in_base(base_path: &str, rel_path: &str) -> bool {
-    let full_path = format!("{}{}", base_path, rel_path);
-    std::path::Path::new(&full_path).exists()
+/// Format one change as `<code> <type> <path>`, followed by size and
+/// mtime deltas for modified non-directories.
+fn format_change(change: &ChangeEntry) -> String {
+    let mode = change
+        .delta
+        .as_ref()
+        .or(change.base.as_ref())
+        .map_or(0, |stats| stats.mode);
+    let mut line = format!(
+        "{} {} {}",
+        change_code(change.kind),
+        file_type_char(mode),
+        change.path
+    );
+    if let (Some(base), Some(delta)) = (&change.base, &change.delta) {
+        if !delta.is_directory() {
+            line.push_str(&format!(
+                " (size {} -> {}, mtime {:+}s)",
+                base.size,
+                delta.size,
+                delta.mtime - base.mtime
+            ));
+        }
+    }
+    line
 }
 
-pub async fn diff_filesystem(id_or_path: String) -> AnyhowResult<()> {
+#[cfg(unix)]
+pub async fn diff_filesystem(
+    stdout: &mut impl std::io::Write,
+    id_or_path: String,
+    name_only: bool,
+) -> AnyhowResult<()> {
+    use agentfs_sdk::OverlayFS;
+    use std::sync::Arc;
+
     let options = AgentFSOptions::resolve(&id_or_path)?;
     eprintln!("Using agent: {}", id_or_path);
 
@@ -206,75 +617,54 @@ pub async fn diff_filesystem(id_or_path: String) -> AnyhowResult<()> {
     let base_path = match agent.is_overlay_enabled().await? {
         Some(path) => path,
         None => {
-            println!("No diff (non-overlay filesystem)");
+            writeln!(stdout, "No diff (non-overlay filesystem)")?;
             return Ok(());
         }
     };
 
     eprintln!("Base: {}", base_path);
 
-    // Collect all changes
-    let mut changes: Vec<(ChangeType, char, String)> = Vec::new();
-
-    // Get all paths in delta layer
-    let delta_paths = agent.get_delta_paths().await?;
-
-    // Get all whiteouts (deleted paths)
-    let whiteouts = agent.get_whiteouts().await?;
-
-    // Process delta paths - determine if added or modified
-    for path in &delta_paths {
-        let mode = agent.get_file_mode(path).await?.unwrap_or(0);
-        let type_char = file_type_char(mode);
+    let hostfs = agent
+        .open_overlay_base(&base_path)
+        .await
+        .context("Failed to create HostFS")?;
+    let overlay = OverlayFS::new(Arc::new(hostfs), agent.fs);
+    overlay.load().await?;
+    let changes = overlay.changeset().await?;
 
-        if path_exists_in_base(&base_path, path) {
-            // File exists in both - it was modified (copy-on-write)
-            changes.push((ChangeType::Modified, type_char, path.clone()));
-        } else {
-            // File only exists in delta - it was added
-            changes.push((ChangeType::Added, type_char, path.clone()));
-        }
+    if changes.is_empty() && !name_only {
+        writeln!(stdout, "No changes")?;
     }
-
-    // Process whiteouts (deleted files)
-    for path in &whiteouts {
-        // Determine file type from base if possible, otherwise use '?'
-        let full_path = format!("{}{}", base_path, path);
-        let base_path_obj = std::path::Path::new(&full_path);
-        let type_char = if base_path_obj.is_dir() {
-            'd'
-        } else if base_path_obj.is_symlink() {
-            'l'
-        } else if base_path_obj.is_file() {
-            'f'
+    for change in &changes {
+        if name_only {
+            writeln!(stdout, "{}", change.path)?;
         } else {
-            '?'
-        };
-
-        changes.push((ChangeType::Deleted, type_char, path.clone()));
-    }
-
-    // Sort changes by path for consistent output
-    changes.sort_by(|a, b| a.2.cmp(&b.2));
-
-    // Print changes
-    if changes.is_empty() {
-        println!("No changes");
-    } else {
-        for (change_type, type_char, path) in changes {
-            println!("{} {} {}", change_type, type_char, path);
+            writeln!(stdout, "{}", format_change(change))?;
         }
     }
 
     Ok(())
 }
 
+#[cfg(not(unix))]
+pub async fn diff_filesystem(
+    _stdout: &mut impl std::io::Write,
+    _id_or_path: String,
+    _name_only: bool,
+) -> AnyhowResult<()> {
+    anyhow::bail!("diff is not supported on this platform")
+}
+
 #[cfg(test)]
 mod tests {
     use agentfs_sdk::{AgentFS, AgentFSOptions, EncryptionConfig};
     use tempfile::NamedTempFile;
 
-    use crate::cmd::fs::{cat_filesystem, ls_filesystem, write_filesystem};
+    use crate::cmd::fs::{
+        cat_filesystem, cp_filesystem, diff_filesystem, ls_filesystem, mkdir_filesystem,
+        mode_string, parse_cp_location, rm_filesystem, stat_filesystem, write_filesystem,
+        CpLocation,
+    };
 
     const TEST_KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
     const TEST_CIPHER: &str = "aes256gcm";
@@ -346,7 +736,9 @@ mod tests {
     pub async fn ls_empty() {
         let (_agentfs, path, _file) = agentfs().await;
         let mut buf = Vec::new();
-        ls_filesystem(&mut buf, path, "/", None).await.unwrap();
+        ls_filesystem(&mut buf, path, "/", false, None)
+            .await
+            .unwrap();
         assert_eq!(buf, b"");
     }
 
@@ -358,7 +750,9 @@ mod tests {
         let big = vec![100u8; 1024 * 1024];
         write_file(&agentfs.fs, "3.md", &big, 0, 0).await.unwrap();
         let mut buf = Vec::new();
-        ls_filesystem(&mut buf, path, "/", None).await.unwrap();
+        ls_filesystem(&mut buf, path, "/", false, None)
+            .await
+            .unwrap();
         assert_eq!(
             buf,
             b"f 1.md
@@ -387,7 +781,9 @@ f 3.md
             .await
             .unwrap();
         let mut buf = Vec::new();
-        ls_filesystem(&mut buf, path, "/", None).await.unwrap();
+        ls_filesystem(&mut buf, path, "/", false, None)
+            .await
+            .unwrap();
         assert_eq!(
             buf,
             b"d a
@@ -402,6 +798,109 @@ f d/e/3.md
         );
     }
 
+    #[tokio::test]
+    pub async fn ls_subdir_long() {
+        let (agentfs, path, _file) = agentfs().await;
+        agentfs.fs.mkdir("/a", 0, 0).await.unwrap();
+        agentfs.fs.mkdir("/a/b", 0, 0).await.unwrap();
+        write_file(&agentfs.fs, "/a/b/1.md", b"12345", 1000, 100)
+            .await
+            .unwrap();
+        agentfs.fs.symlink("b/1.md", "/a/link", 0, 0).await.unwrap();
+        write_file(&agentfs.fs, "/outside.md", b"", 0, 0)
+            .await
+            .unwrap();
+        let mtime = agentfs.fs.lstat("/a/b/1.md").await.unwrap().unwrap().mtime;
+        let mtime = chrono::DateTime::from_timestamp(mtime, 0)
+            .unwrap()
+            .format("%Y-%m-%d %H:%M:%S");
+
+        let mut buf = Vec::new();
+        ls_filesystem(&mut buf, path.clone(), "/a", false, None)
+            .await
+            .unwrap();
+        assert_eq!(buf, b"d b\nl link\nf b/1.md\n");
+
+        let mut buf = Vec::new();
+        ls_filesystem(&mut buf, path, "/a/b/1.md", true, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            format!(
+                "-rw-r--r--   1  1000   100          5 {} /a/b/1.md\n",
+                mtime
+            )
+        );
+    }
+
+    #[tokio::test]
+    pub async fn mkdir_and_rm() {
+        let (agentfs, path, _file) = agentfs().await;
+        mkdir_filesystem(path.clone(), "/x/y/z", true, None)
+            .await
+            .unwrap();
+        // -p succeeds on an existing directory, plain mkdir does not
+        mkdir_filesystem(path.clone(), "/x/y", true, None)
+            .await
+            .unwrap();
+        assert!(mkdir_filesystem(path.clone(), "/x/y", false, None)
+            .await
+            .is_err());
+        assert!(agentfs
+            .fs
+            .stat("/x/y/z")
+            .await
+            .unwrap()
+            .unwrap()
+            .is_directory());
+
+        assert!(rm_filesystem(path.clone(), "/x", false, None)
+            .await
+            .is_err());
+        rm_filesystem(path.clone(), "/x", true, None).await.unwrap();
+        assert!(agentfs.fs.lstat("/x").await.unwrap().is_none());
+        assert!(rm_filesystem(path, "/x", true, None).await.is_err());
+    }
+
+    #[tokio::test]
+    pub async fn stat_and_binary_cat() {
+        let (agentfs, path, _file) = agentfs().await;
+        // Not valid UTF-8, so any lossy conversion would show
+        let content = [0xff, 0xfe, 0x00, 0x80, b'\n'];
+        write_filesystem(path.clone(), "/bin/data", &content, None)
+            .await
+            .unwrap();
+
+        let mut buf = Vec::new();
+        cat_filesystem(&mut buf, path.clone(), "/bin/data", None)
+            .await
+            .unwrap();
+        assert_eq!(buf, content);
+
+        let ino = agentfs.fs.lstat("/bin/data").await.unwrap().unwrap().ino;
+        let mut buf = Vec::new();
+        stat_filesystem(&mut buf, path, "/bin/data", None)
+            .await
+            .unwrap();
+        let out = String::from_utf8(buf).unwrap();
+        assert!(out.starts_with("  File: /bin/data\n"));
+        assert!(out.contains("  Size: 5 "));
+        assert!(out.contains("regular file"));
+        assert!(out.contains(&format!(" Inode: {} ", ino)));
+        assert!(out.contains("Access: (0644/-rw-r--r--)  Uid: 0  Gid: 0"));
+    }
+
+    #[test]
+    fn mode_strings() {
+        assert_eq!(mode_string(0o040755), "drwxr-xr-x");
+        assert_eq!(mode_string(0o100644), "-rw-r--r--");
+        assert_eq!(mode_string(0o120777), "lrwxrwxrwx");
+        assert_eq!(mode_string(0o104755), "-rwsr-xr-x");
+        assert_eq!(mode_string(0o041777), "drwxrwxrwt");
+        assert_eq!(mode_string(0o102640), "-rw-r-S---");
+    }
+
     // Encryption tests
 
     #[tokio::test]
@@ -434,7 +933,7 @@ f d/e/3.md
 
         let encryption = Some((TEST_KEY.to_string(), TEST_CIPHER.to_string()));
         let mut buf = Vec::new();
-        ls_filesystem(&mut buf, path, "/", encryption.as_ref())
+        ls_filesystem(&mut buf, path, "/", false, encryption.as_ref())
             .await
             .unwrap();
         assert_eq!(buf, b"f file1.txt\nf file2.txt\n");
@@ -448,7 +947,7 @@ f d/e/3.md
         write_filesystem(
             path.clone(),
             "/new_file.txt",
-            "new content",
+            b"new content",
             encryption.as_ref(),
         )
         .await
@@ -461,6 +960,143 @@ f d/e/3.md
         assert_eq!(buf, b"new content");
     }
 
+    #[cfg(unix)]
+    async fn overlay_agentfs() -> (tempfile::TempDir, String, NamedTempFile) {
+        use agentfs_sdk::{FileSystem, HostFS, OverlayFS};
+
+        let base = tempfile::TempDir::new().unwrap();
+        std::fs::write(base.path().join("edit.txt"), b"old").unwrap();
+        std::fs::write(base.path().join("gone.txt"), b"gone").unwrap();
+
+        let (agentfs, path, file) = agentfs().await;
+        let overlay = OverlayFS::new(
+            std::sync::Arc::new(HostFS::new(base.path()).unwrap()),
+            agentfs.fs,
+        );
+        overlay.init(base.path().to_str().unwrap()).await.unwrap();
+
+        let edit = overlay.lookup(1, "edit.txt").await.unwrap().unwrap();
+        let handle = overlay.open(edit.ino, libc::O_RDWR).await.unwrap();
+        handle.pwrite(0, b"longer").await.unwrap();
+        let (_, handle) = overlay
+            .create_file(1, "new.txt", S_IFREG | 0o644, 0, 0)
+            .await
+            .unwrap();
+        handle.pwrite(0, b"new").await.unwrap();
+        overlay.unlink(1, "gone.txt").await.unwrap();
+
+        (base, path, file)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn diff_name_only() {
+        let (_base, path, _file) = overlay_agentfs().await;
+        let mut buf = Vec::new();
+        diff_filesystem(&mut buf, path, true).await.unwrap();
+        assert_eq!(buf, b"/edit.txt\n/gone.txt\n/new.txt\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn diff_shows_size_delta() {
+        let (_base, path, _file) = overlay_agentfs().await;
+        let mut buf = Vec::new();
+        diff_filesystem(&mut buf, path, false).await.unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("M f /edit.txt (size 3 -> 6, mtime "));
+        assert_eq!(lines[1], "D f /gone.txt");
+        assert_eq!(lines[2], "A f /new.txt");
+    }
+
+    #[test]
+    fn parse_cp_locations() {
+        assert_eq!(
+            parse_cp_location("agent:/dir/file.txt"),
+            CpLocation::Agent {
+                id_or_path: "agent".to_string(),
+                path: "/dir/file.txt".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_cp_location("agent:"),
+            CpLocation::Agent {
+                id_or_path: "agent".to_string(),
+                path: "/".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_cp_location("local/file.txt"),
+            CpLocation::Local("local/file.txt".into())
+        );
+    }
+
+    #[tokio::test]
+    pub async fn cp_exports_and_imports_directories() {
+        let (agentfs, path, _file) = agentfs().await;
+        agentfs.fs.mkdir("/dir", 0, 0).await.unwrap();
+        agentfs.fs.mkdir("/dir/sub", 0, 0).await.unwrap();
+        write_file(&agentfs.fs, "/dir/a.txt", b"alpha", 0, 0)
+            .await
+            .unwrap();
+        write_file(&agentfs.fs, "/dir/sub/b.txt", b"beta", 0, 0)
+            .await
+            .unwrap();
+        drop(agentfs);
+
+        // Export into an existing directory keeps the source name
+        let out = tempfile::TempDir::new().unwrap();
+        cp_filesystem(
+            &format!("{}:/dir", path),
+            out.path().to_str().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let exported = out.path().join("dir");
+        assert_eq!(std::fs::read(exported.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(std::fs::read(exported.join("sub/b.txt")).unwrap(), b"beta");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(exported.join("a.txt"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o644);
+        }
+
+        // Import it back under a new name
+        cp_filesystem(exported.to_str().unwrap(), &format!("{}:/copy", path), None)
+            .await
+            .unwrap();
+        let agentfs = AgentFS::open(AgentFSOptions::with_path(path.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            agentfs
+                .fs
+                .read_file("/copy/sub/b.txt")
+                .await
+                .unwrap()
+                .unwrap(),
+            b"beta"
+        );
+        let original = agentfs.fs.stat("/dir/a.txt").await.unwrap().unwrap();
+        let copy = agentfs.fs.stat("/copy/a.txt").await.unwrap().unwrap();
+        assert_eq!(copy.mode, original.mode);
+        assert_eq!(copy.mtime, original.mtime);
+    }
+
+    #[tokio::test]
+    pub async fn cp_requires_one_agent_path() {
+        let out = tempfile::TempDir::new().unwrap();
+        let local = out.path().to_str().unwrap();
+        assert!(cp_filesystem(local, local, None).await.is_err());
+    }
+
     async fn write_file(
         fs: &agentfs_sdk::filesystem::AgentFS,
         path: &str,
@@ -0,0 +1,86 @@
+//! Filesystem check command.
+//!
+//! Look for inconsistencies in an agent database and optionally repair them.
+
+use std::sync::Arc;
+
+use agentfs_sdk::{AgentFSOptions, OverlayFS};
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+use crate::cmd::snapshot::find_mount;
+
+/// Handle the fsck command.
+///
+/// Checking is read-only, but repairing refuses to run while the filesystem
+/// is mounted, since the mount caches the entries being rewritten. Exits
+/// with an error if any inconsistency is left unrepaired.
+pub async fn handle_fsck_command(id_or_path: String, repair: bool) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    let db_path = options
+        .db_path()
+        .context("Failed to resolve database path")?;
+
+    if repair {
+        if let Some(mountpoint) = find_mount(&id_or_path, &db_path) {
+            anyhow::bail!(
+                "Agent '{}' is mounted at {}; unmount it before repairing",
+                id_or_path,
+                mountpoint.display()
+            );
+        }
+    }
+    eprintln!("Using agent: {}", id_or_path);
+
+    let agent = open_agentfs(options).await?;
+
+    // Whiteouts can only be checked against the base of an overlay
+    let overlay = match agent.is_overlay_enabled().await? {
+        Some(base_path) => {
+            eprintln!("Base: {}", base_path);
+            let hostfs = agent
+                .open_overlay_base(&base_path)
+                .await
+                .context("Failed to create HostFS")?;
+            let overlay = OverlayFS::new(Arc::new(hostfs), agent.fs.clone());
+            overlay.load().await?;
+            Some(overlay)
+        }
+        None => None,
+    };
+
+    let found = match &overlay {
+        Some(overlay) => overlay.check().await?,
+        None => agent.fs.check().await?,
+    };
+    if found.is_empty() {
+        println!("No inconsistencies found");
+        return Ok(());
+    }
+    for finding in &found {
+        println!("{}", finding);
+    }
+
+    if !repair {
+        anyhow::bail!(
+            "{} inconsistency(ies) found; run with --repair to fix them",
+            found.len()
+        );
+    }
+
+    let repaired = match &overlay {
+        Some(overlay) => overlay.repair(&found).await?,
+        None => agent.fs.repair(&found).await?,
+    };
+    println!(
+        "Repaired {} of {} inconsistency(ies)",
+        repaired,
+        found.len()
+    );
+
+    let unrepairable = found.iter().filter(|f| !f.is_repairable()).count();
+    if unrepairable > 0 {
+        anyhow::bail!("{} inconsistency(ies) cannot be repaired", unrepairable);
+    }
+    Ok(())
+}
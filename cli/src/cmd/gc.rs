@@ -0,0 +1,45 @@
+//! Garbage collection command.
+//!
+//! Remove orphaned inodes and expired trash entries, and compact the agent
+//! database.
+
+use agentfs_sdk::AgentFSOptions;
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+use crate::cmd::snapshot::find_mount;
+
+/// Handle the gc command.
+///
+/// Refuses to run while the filesystem is mounted, since compaction
+/// rewrites the database underneath the mount.
+pub async fn handle_gc_command(id_or_path: String) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    let db_path = options
+        .db_path()
+        .context("Failed to resolve database path")?;
+
+    if let Some(mountpoint) = find_mount(&id_or_path, &db_path) {
+        anyhow::bail!(
+            "Agent '{}' is mounted at {}; unmount it before running gc",
+            id_or_path,
+            mountpoint.display()
+        );
+    }
+    eprintln!("Using agent: {}", id_or_path);
+
+    let agent = open_agentfs(options).await?;
+    let stats = agent.fs.compact().await?;
+
+    if stats.expired_trash > 0 {
+        println!("Freed {} expired trash entr(ies)", stats.expired_trash);
+    }
+    println!("Removed {} orphaned inode(s)", stats.orphaned_inodes);
+    println!(
+        "Stored data: {} -> {} bytes ({} bytes reclaimed)",
+        stats.bytes_before,
+        stats.bytes_after,
+        stats.bytes_reclaimed()
+    );
+    Ok(())
+}
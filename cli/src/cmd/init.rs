@@ -1,9 +1,9 @@
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use agentfs_sdk::{
-    agentfs_dir, AgentFS, AgentFSOptions, EncryptionConfig, OverlayFS, PartialBootstrapStrategy,
-    PartialSyncOpts, SyncOptions,
+    agentfs_dir, AgentFS, AgentFSOptions, AtimePolicy, CompressionKind, EncryptionConfig,
+    OverlayFS, PartialBootstrapStrategy, PartialSyncOpts, SyncOptions, TrashPolicy,
 };
 use anyhow::{Context, Result as AnyhowResult};
 
@@ -69,12 +69,25 @@ pub fn build_sync_options(sync_cmd_options: &SyncCommandOptions) -> SyncOptions
     sync
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn init_database(
     id: Option<String>,
     sync_options: SyncCommandOptions,
     force: bool,
     base: Option<PathBuf>,
+    seed: Option<PathBuf>,
     encryption: Option<EncryptionOptions>,
+    max_size: Option<u64>,
+    chunk_size: Option<u64>,
+    compression: Option<CompressionKind>,
+    dedup: bool,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    umask: Option<u32>,
+    atime: Option<AtimePolicy>,
+    case_insensitive: bool,
+    trash: bool,
+    trash_retention_days: Option<u64>,
     command: Option<String>,
     backend: MountBackend,
 ) -> AnyhowResult<()> {
@@ -105,6 +118,12 @@ pub async fn init_database(
         }
     }
 
+    if let Some(ref seed_path) = seed {
+        if !seed_path.is_dir() {
+            anyhow::bail!("Seed path is not a directory: {}", seed_path.display());
+        }
+    }
+
     // Check if agent already exists
     let db_path = agentfs_dir().join(format!("{}.db", &id));
     if db_path.exists() {
@@ -131,6 +150,39 @@ pub async fn init_database(
     if let Some(base_path) = base.as_ref() {
         open_options = open_options.with_base(base_path);
     }
+    if let Some(max_size) = max_size {
+        open_options = open_options.with_max_bytes(max_size);
+    }
+    if let Some(chunk_size) = chunk_size {
+        let chunk_size = usize::try_from(chunk_size).context("Chunk size is too large")?;
+        open_options = open_options.with_chunk_size(chunk_size);
+    }
+    if let Some(compression) = compression {
+        open_options = open_options.with_compression(compression);
+    }
+    if dedup {
+        open_options = open_options.with_dedup();
+    }
+    if let Some(uid) = uid {
+        open_options = open_options.with_uid(uid);
+    }
+    if let Some(gid) = gid {
+        open_options = open_options.with_gid(gid);
+    }
+    if let Some(umask) = umask {
+        open_options = open_options.with_umask(umask);
+    }
+    if let Some(atime) = atime {
+        open_options = open_options.with_atime_policy(atime);
+    }
+    if case_insensitive {
+        open_options = open_options.with_case_insensitive();
+    }
+    if trash {
+        open_options = open_options.with_trash(TrashPolicy {
+            retention: trash_retention_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        });
+    }
 
     let encrypted = if let Some(enc_opts) = encryption {
         if sync_options.sync_remote_url.is_some() {
@@ -179,17 +231,60 @@ pub async fn init_database(
             eprintln!("Encryption: enabled");
         }
     } else {
+        let seeded = match seed {
+            Some(ref seed_path) => Some(
+                seed_agentfs(&agent, seed_path)
+                    .await
+                    .with_context(|| format!("Failed to seed from {}", seed_path.display()))?,
+            ),
+            None => None,
+        };
         if agent.is_synced() {
             agent.push().await?;
         }
 
         eprintln!("Created agent filesystem: {}", db_path.display());
         eprintln!("Agent ID: {}", id);
+        if let (Some(seed_path), Some((files, bytes))) = (seed.as_ref(), seeded) {
+            eprintln!(
+                "Seeded {} file(s), {} from {}",
+                files,
+                crate::cmd::ps::format_size(bytes),
+                seed_path.display()
+            );
+        }
         if encrypted {
             eprintln!("Encryption: enabled");
         }
     }
 
+    // A cached view of the settings, for tooling that does not open the database
+    agent
+        .write_manifest()
+        .await
+        .context("Failed to write manifest")?;
+
+    if let Some(max_size) = max_size {
+        eprintln!("Size limit: {} bytes", max_size);
+    }
+    if let Some(chunk_size) = chunk_size {
+        eprintln!("Chunk size: {} bytes", chunk_size);
+    }
+    if let Some(umask) = umask {
+        eprintln!("Umask: {:03o}", umask);
+    }
+    if let Some(atime) = atime {
+        eprintln!("Access times: {}", atime);
+    }
+    if case_insensitive {
+        eprintln!("Names: case-insensitive");
+    }
+    match (trash, trash_retention_days) {
+        (true, Some(days)) => eprintln!("Trash: enabled, kept for {} day(s)", days),
+        (true, None) => eprintln!("Trash: enabled"),
+        _ => {}
+    }
+
     // If a command was provided, mount the filesystem and execute it
     if let Some(cmd_str) = command {
         run_init_cmd(&id, cmd_str, backend, base, agent).await?;
@@ -198,6 +293,19 @@ pub async fn init_database(
     Ok(())
 }
 
+/// Copy a host directory into a new filesystem, returning the number of
+/// files and bytes copied.
+#[cfg(unix)]
+async fn seed_agentfs(agent: &AgentFS, seed_path: &Path) -> AnyhowResult<(u64, u64)> {
+    let stats = crate::cmd::seed::seed_from_dir(&agent.fs, seed_path).await?;
+    Ok((stats.files, stats.bytes))
+}
+
+#[cfg(not(unix))]
+async fn seed_agentfs(_agent: &AgentFS, _seed_path: &Path) -> AnyhowResult<(u64, u64)> {
+    anyhow::bail!("The --seed option is not supported on Windows")
+}
+
 #[cfg(unix)]
 async fn run_init_cmd(
     id: &str,
@@ -216,7 +324,8 @@ async fn run_init_cmd(
         let canonical = base_path
             .canonicalize()
             .context("Failed to canonicalize base path")?;
-        let hostfs = HostFS::new(&canonical)?;
+        // A new filesystem can't have opted into a writable base yet
+        let hostfs = HostFS::new_readonly(&canonical)?;
         let overlay = OverlayFS::new(Arc::new(hostfs), agent.fs);
         Arc::new(Mutex::new(overlay)) as Arc<Mutex<dyn FileSystem + Send>>
     } else {
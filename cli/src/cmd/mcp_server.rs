@@ -1,4 +1,4 @@
-use agentfs_sdk::{AgentFS, AgentFSOptions, Stats};
+use agentfs_sdk::{AgentFS, AgentFSOptions, FileSystem, FileType, Stats};
 
 const S_IFREG: u32 = 0o100000;
 use anyhow::{Context, Result};
@@ -334,6 +334,10 @@ impl McpServer {
                         "path": {
                             "type": "string",
                             "description": "Path to the directory to list"
+                        },
+                        "types": {
+                            "type": "boolean",
+                            "description": "Return each entry as {name, type} instead of a bare name"
                         }
                     },
                     "required": ["path"]
@@ -591,6 +595,8 @@ struct WriteFileParams {
 #[derive(Debug, Serialize, Deserialize)]
 struct ReaddirParams {
     path: String,
+    #[serde(default)]
+    types: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -738,6 +744,18 @@ impl McpServer {
             .context("Failed to stat directory")?
             .ok_or_else(|| anyhow::anyhow!("Directory not found: {}", path))?;
 
+        if params.types {
+            let entries = FileSystem::readdir_types(&self.agentfs.fs, stats.ino)
+                .await
+                .context("Failed to read directory")?
+                .ok_or_else(|| anyhow::anyhow!("Directory not found: {}", path))?;
+            let entries: Vec<JsonValue> = entries
+                .into_iter()
+                .map(|(name, file_type)| json!({ "name": name, "type": file_type.as_str() }))
+                .collect();
+            return Ok(serde_json::to_string_pretty(&entries)?);
+        }
+
         let entries = self
             .agentfs
             .fs
@@ -866,31 +884,27 @@ impl McpServer {
                 None => return Ok(()),
             };
 
-            let entries = match self.agentfs.fs.readdir(dir_stats.ino).await? {
+            // Entry types come with the listing, so no stat is needed per entry
+            let entries = match FileSystem::readdir_types(&self.agentfs.fs, dir_stats.ino).await? {
                 Some(entries) => entries,
                 None => return Ok(()),
             };
 
-            for entry in entries {
+            for (entry, file_type) in entries {
                 let full_path = if path == "/" {
                     format!("/{}", entry)
                 } else {
                     format!("{}/{}", path, entry)
                 };
 
-                let stats = match self.agentfs.fs.stat(&full_path).await? {
-                    Some(s) => s,
-                    None => continue,
-                };
-
-                if stats.is_file() {
+                if file_type == FileType::Regular {
                     resources.push(json!({
                         "uri": full_path,
                         "name": entry,
                         "description": format!("File at {}", full_path),
                         "mimeType": guess_mime_type(&full_path)
                     }));
-                } else if stats.is_directory() {
+                } else if file_type == FileType::Directory {
                     // Recurse into subdirectory
                     self.collect_file_resources(&full_path, resources).await?;
                 }
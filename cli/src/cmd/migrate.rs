@@ -6,6 +6,7 @@ use agentfs_sdk::{AgentFSOptions, SchemaVersion, AGENTFS_SCHEMA_VERSION};
 use anyhow::{Context, Result as AnyhowResult};
 use std::io::Write;
 use std::path::Path;
+use turso::transaction::{Transaction, TransactionBehavior};
 use turso::Builder;
 
 /// Handle the migrate command.
@@ -40,7 +41,7 @@ pub async fn handle_migrate_command(
     writeln!(stdout, "Current schema version: {}", current_version)?;
     writeln!(stdout, "Target schema version: {}", AGENTFS_SCHEMA_VERSION)?;
 
-    if current_version == SchemaVersion::V0_4 {
+    if current_version == SchemaVersion::V0_5 {
         writeln!(stdout, "Database is already at the latest schema version.")?;
         return Ok(());
     }
@@ -79,11 +80,16 @@ fn print_pending_migrations(
         SchemaVersion::V0_0 => {
             writeln!(stdout, "  - v0.0 -> v0.2: Add nlink column to fs_inode")?;
             writeln!(stdout, "  - v0.2 -> v0.4: Add atime_nsec, mtime_nsec, ctime_nsec, rdev columns to fs_inode")?;
+            writeln!(stdout, "  - v0.4 -> v0.5: Add allocated column to fs_inode")?;
         }
         SchemaVersion::V0_2 => {
             writeln!(stdout, "  - v0.2 -> v0.4: Add atime_nsec, mtime_nsec, ctime_nsec, rdev columns to fs_inode")?;
+            writeln!(stdout, "  - v0.4 -> v0.5: Add allocated column to fs_inode")?;
         }
         SchemaVersion::V0_4 => {
+            writeln!(stdout, "  - v0.4 -> v0.5: Add allocated column to fs_inode")?;
+        }
+        SchemaVersion::V0_5 => {
             // Already at latest
         }
     }
@@ -102,12 +108,20 @@ async fn apply_migrations(
             migrate_v0_0_to_v0_2(conn, stdout).await?;
             // Then v0.2 -> v0.4
             migrate_v0_2_to_v0_4(conn, stdout).await?;
+            // Then v0.4 -> v0.5
+            migrate_v0_4_to_v0_5(conn, stdout).await?;
         }
         SchemaVersion::V0_2 => {
             // Migrate v0.2 -> v0.4
             migrate_v0_2_to_v0_4(conn, stdout).await?;
+            // Then v0.4 -> v0.5
+            migrate_v0_4_to_v0_5(conn, stdout).await?;
         }
         SchemaVersion::V0_4 => {
+            // Migrate v0.4 -> v0.5
+            migrate_v0_4_to_v0_5(conn, stdout).await?;
+        }
+        SchemaVersion::V0_5 => {
             // Already at latest version
         }
     }
@@ -193,6 +207,185 @@ async fn migrate_v0_2_to_v0_4(
     Ok(())
 }
 
+/// Migrate from v0.4 to v0.5: Add the allocated column and count the bytes
+/// already stored for each inode.
+async fn migrate_v0_4_to_v0_5(
+    conn: &turso::Connection,
+    stdout: &mut impl Write,
+) -> AnyhowResult<()> {
+    writeln!(stdout, "  Migrating v0.4 -> v0.5...")?;
+
+    // The column and its counts are added together: a database with the
+    // column already counts as v0.5, so a column left at zero would never
+    // be counted
+    let txn = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).await?;
+    let result = async {
+        rebuild_inode_table(conn, stdout).await?;
+        count_allocated(conn, stdout).await
+    }
+    .await;
+    match result {
+        Ok(()) => txn.commit().await?,
+        Err(e) => {
+            let _ = txn.rollback().await;
+            return Err(e);
+        }
+    }
+
+    writeln!(stdout, "  v0.4 -> v0.5 migration complete.")?;
+    Ok(())
+}
+
+/// Columns of fs_inode as of v0.4, which are copied when it is rebuilt.
+const V0_4_INODE_COLUMNS: &str =
+    "ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec";
+
+/// Recreate fs_inode with every v0.5 column.
+///
+/// Adding a column with ALTER TABLE drops the AUTOINCREMENT of the inode
+/// key, after which the number of a deleted newest inode is handed out
+/// again. The table is copied aside, created again the way
+/// `AgentFS::initialize_schema` creates it, and filled back, and the
+/// highest inode number ever used is carried over.
+async fn rebuild_inode_table(
+    conn: &turso::Connection,
+    stdout: &mut impl Write,
+) -> AnyhowResult<()> {
+    let mut next_ino = query_integer(conn, "SELECT COALESCE(MAX(ino), 0) FROM fs_inode").await?;
+    if table_exists(conn, "sqlite_sequence").await? {
+        let used = query_integer(
+            conn,
+            "SELECT COALESCE(MAX(seq), 0) FROM sqlite_sequence WHERE name = 'fs_inode'",
+        )
+        .await?;
+        next_ino = next_ino.max(used);
+    }
+
+    for sql in [
+        "CREATE TABLE fs_inode_v0_4 (
+            ino INTEGER PRIMARY KEY,
+            mode INTEGER NOT NULL,
+            nlink INTEGER NOT NULL DEFAULT 0,
+            uid INTEGER NOT NULL DEFAULT 0,
+            gid INTEGER NOT NULL DEFAULT 0,
+            size INTEGER NOT NULL DEFAULT 0,
+            atime INTEGER NOT NULL,
+            mtime INTEGER NOT NULL,
+            ctime INTEGER NOT NULL,
+            rdev INTEGER NOT NULL DEFAULT 0,
+            atime_nsec INTEGER NOT NULL DEFAULT 0,
+            mtime_nsec INTEGER NOT NULL DEFAULT 0,
+            ctime_nsec INTEGER NOT NULL DEFAULT 0
+        )",
+        &format!(
+            "INSERT INTO fs_inode_v0_4 ({V0_4_INODE_COLUMNS}) SELECT {V0_4_INODE_COLUMNS} FROM fs_inode"
+        ),
+        "DROP TABLE fs_inode",
+        "CREATE TABLE fs_inode (
+            ino INTEGER PRIMARY KEY AUTOINCREMENT,
+            mode INTEGER NOT NULL,
+            nlink INTEGER NOT NULL DEFAULT 0,
+            uid INTEGER NOT NULL DEFAULT 0,
+            gid INTEGER NOT NULL DEFAULT 0,
+            size INTEGER NOT NULL DEFAULT 0,
+            atime INTEGER NOT NULL,
+            mtime INTEGER NOT NULL,
+            ctime INTEGER NOT NULL,
+            rdev INTEGER NOT NULL DEFAULT 0,
+            atime_nsec INTEGER NOT NULL DEFAULT 0,
+            mtime_nsec INTEGER NOT NULL DEFAULT 0,
+            ctime_nsec INTEGER NOT NULL DEFAULT 0,
+            allocated INTEGER NOT NULL DEFAULT 0
+        )",
+        &format!(
+            "INSERT INTO fs_inode ({V0_4_INODE_COLUMNS}) SELECT {V0_4_INODE_COLUMNS} FROM fs_inode_v0_4"
+        ),
+        "DROP TABLE fs_inode_v0_4",
+    ] {
+        conn.execute(sql, ())
+            .await
+            .context("Failed to rebuild fs_inode")?;
+    }
+
+    // Inserting and deleting the highest number used moves the key's
+    // sequence past it, so that it is not handed out again
+    let max_ino = query_integer(conn, "SELECT COALESCE(MAX(ino), 0) FROM fs_inode").await?;
+    if next_ino > max_ino {
+        conn.execute(
+            "INSERT INTO fs_inode (ino, mode, atime, mtime, ctime) VALUES (?, 0, 0, 0, 0)",
+            [next_ino],
+        )
+        .await
+        .context("Failed to keep the inode sequence")?;
+        conn.execute("DELETE FROM fs_inode WHERE ino = ?", [next_ino])
+            .await
+            .context("Failed to keep the inode sequence")?;
+    }
+
+    writeln!(stdout, "    Rebuilt fs_inode with the allocated column")?;
+    Ok(())
+}
+
+/// Count the bytes stored for each inode into its allocated column.
+async fn count_allocated(conn: &turso::Connection, stdout: &mut impl Write) -> AnyhowResult<()> {
+    // Recount from the stored chunks, which is safe to repeat. Deduplicated
+    // chunks count the size of their blob. The sums are read first and set
+    // one inode at a time, since turso does not support subqueries in an
+    // UPDATE.
+    if table_exists(conn, "fs_data").await? {
+        let sql = if table_exists(conn, "fs_blob").await? {
+            "SELECT d.ino, SUM(LENGTH(COALESCE(b.data, d.data)))
+            FROM fs_data d LEFT JOIN fs_blob b ON b.hash = d.hash
+            GROUP BY d.ino"
+        } else {
+            "SELECT d.ino, SUM(LENGTH(d.data)) FROM fs_data d GROUP BY d.ino"
+        };
+        let mut rows = conn
+            .query(sql, ())
+            .await
+            .context("Failed to count allocated bytes")?;
+        let mut counts = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let ino: i64 = row.get(0)?;
+            let bytes: Option<i64> = row.get(1)?;
+            counts.push((ino, bytes.unwrap_or(0)));
+        }
+        drop(rows);
+
+        conn.execute("UPDATE fs_inode SET allocated = 0", ())
+            .await
+            .context("Failed to reset allocated bytes")?;
+        for (ino, bytes) in counts {
+            conn.execute(
+                "UPDATE fs_inode SET allocated = ? WHERE ino = ?",
+                (bytes, ino),
+            )
+            .await
+            .context("Failed to count allocated bytes")?;
+        }
+        writeln!(stdout, "    Counted allocated bytes of each inode")?;
+    }
+    Ok(())
+}
+
+/// Check whether `table_name` exists.
+async fn table_exists(conn: &turso::Connection, table_name: &str) -> AnyhowResult<bool> {
+    let mut rows = conn
+        .query(
+            "SELECT 1 FROM sqlite_master WHERE type='table' AND name = ?",
+            [table_name],
+        )
+        .await?;
+    Ok(rows.next().await?.is_some())
+}
+
+/// Run a query returning a single integer.
+async fn query_integer(conn: &turso::Connection, sql: &str) -> AnyhowResult<i64> {
+    let mut rows = conn.query(sql, ()).await?;
+    let row = rows.next().await?.context("Query returned no rows")?;
+    Ok(row.get(0)?)
+}
+
 /// Add a column idempotently (ignore duplicate column errors).
 async fn add_column_idempotent(
     conn: &turso::Connection,
@@ -224,6 +417,7 @@ async fn add_column_idempotent(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use agentfs_sdk::AgentFS;
     use tempfile::NamedTempFile;
 
     async fn create_test_db_v0_0() -> (turso::Database, NamedTempFile) {
@@ -376,7 +570,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_migrate_v0_0_to_v0_4() {
+    async fn test_migrate_v0_0_to_v0_5() {
         let (db, _file) = create_test_db_v0_0().await;
         let conn = db.connect().unwrap();
 
@@ -392,15 +586,15 @@ mod tests {
             .await
             .unwrap();
 
-        // Verify now at v0.4
+        // Verify now at v0.5
         assert_eq!(
             detect_schema_version_for_test(&conn).await.unwrap(),
-            SchemaVersion::V0_4
+            SchemaVersion::V0_5
         );
     }
 
     #[tokio::test]
-    async fn test_migrate_v0_2_to_v0_4() {
+    async fn test_migrate_v0_2_to_v0_5() {
         let (db, _file) = create_test_db_v0_2().await;
         let conn = db.connect().unwrap();
 
@@ -416,11 +610,120 @@ mod tests {
             .await
             .unwrap();
 
-        // Verify now at v0.4
+        // Verify now at v0.5
+        assert_eq!(
+            detect_schema_version_for_test(&conn).await.unwrap(),
+            SchemaVersion::V0_5
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_v0_4_to_v0_5() {
+        let (db, _file) = create_test_db_v0_4().await;
+        let conn = db.connect().unwrap();
+
+        // One inline chunk and one deduplicated chunk
+        conn.execute(
+            "INSERT INTO fs_inode (ino, mode, nlink, atime, mtime, ctime)
+            VALUES (2, 33188, 1, 0, 0, 0)",
+            (),
+        )
+        .await
+        .unwrap();
+        conn.execute(
+            "CREATE TABLE fs_data (
+                ino INTEGER NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                compression INTEGER NOT NULL DEFAULT 0,
+                hash BLOB,
+                PRIMARY KEY (ino, chunk_index)
+            )",
+            (),
+        )
+        .await
+        .unwrap();
+        conn.execute(
+            "CREATE TABLE fs_blob (
+                hash BLOB PRIMARY KEY,
+                data BLOB NOT NULL,
+                compression INTEGER NOT NULL DEFAULT 0,
+                refcount INTEGER NOT NULL DEFAULT 0
+            )",
+            (),
+        )
+        .await
+        .unwrap();
+        conn.execute(
+            "INSERT INTO fs_data (ino, chunk_index, data, hash)
+            VALUES (2, 0, X'00112233', NULL), (2, 1, X'', X'AA')",
+            (),
+        )
+        .await
+        .unwrap();
+        conn.execute(
+            "INSERT INTO fs_blob (hash, data, refcount) VALUES (X'AA', X'001122', 1)",
+            (),
+        )
+        .await
+        .unwrap();
+
+        let mut stdout = Vec::new();
+        apply_migrations(&conn, SchemaVersion::V0_4, &mut stdout)
+            .await
+            .unwrap();
+
         assert_eq!(
             detect_schema_version_for_test(&conn).await.unwrap(),
-            SchemaVersion::V0_4
+            SchemaVersion::V0_5
         );
+        let mut rows = conn
+            .query("SELECT allocated FROM fs_inode WHERE ino = 2", ())
+            .await
+            .unwrap();
+        let allocated: i64 = rows.next().await.unwrap().unwrap().get(0).unwrap();
+        assert_eq!(allocated, 7);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_v0_4_never_reuses_inode_numbers() {
+        let (db, file) = create_test_db_v0_4().await;
+        let conn = db.connect().unwrap();
+
+        // Inode 3 was the newest before it was deleted
+        conn.execute(
+            "INSERT INTO fs_inode (ino, mode, nlink, atime, mtime, ctime)
+            VALUES (1, 16877, 2, 0, 0, 0), (2, 33188, 1, 0, 0, 0), (3, 33188, 1, 0, 0, 0)",
+            (),
+        )
+        .await
+        .unwrap();
+        conn.execute("DELETE FROM fs_inode WHERE ino = 3", ())
+            .await
+            .unwrap();
+
+        let mut stdout = Vec::new();
+        apply_migrations(&conn, SchemaVersion::V0_4, &mut stdout)
+            .await
+            .unwrap();
+        drop(conn);
+        drop(db);
+
+        let path = file.path().to_str().unwrap().to_string();
+        let agent = AgentFS::open(AgentFSOptions::with_path(path.clone()))
+            .await
+            .unwrap();
+        let (stats, _) = agent.fs.create_file("/a", 0o100644, 0, 0).await.unwrap();
+        assert_eq!(stats.ino, 4);
+        agent.fs.remove("/a").await.unwrap();
+        drop(agent);
+
+        // The deleted newest inode stays used once the database is reopened
+        let agent = AgentFS::open(AgentFSOptions::with_path(path))
+            .await
+            .unwrap();
+        let (stats, _) = agent.fs.create_file("/b", 0o100644, 0, 0).await.unwrap();
+        assert_eq!(stats.ino, 5);
     }
 
     #[tokio::test]
@@ -437,10 +740,10 @@ mod tests {
             .await
             .unwrap();
 
-        // Should still be at v0.4
+        // Should still be at v0.5
         assert_eq!(
             detect_schema_version_for_test(&conn).await.unwrap(),
-            SchemaVersion::V0_4
+            SchemaVersion::V0_5
         );
     }
 }
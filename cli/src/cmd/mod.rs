@@ -1,11 +1,17 @@
+pub mod archive;
+pub mod base;
 pub mod completions;
 pub mod fs;
+pub mod gc;
 pub mod init;
 pub mod mcp_server;
 pub mod migrate;
 pub mod ps;
+pub mod snapshot;
 pub mod sync;
 pub mod timeline;
+pub mod trash;
+pub mod tree;
 
 #[cfg(unix)]
 pub mod mount;
@@ -14,14 +20,43 @@ pub mod mount;
 pub mod mount;
 
 mod run;
+mod walk;
 
 // Standalone NFS server command (Unix only)
 #[cfg(unix)]
 pub mod nfs;
 
+// Remote filesystem server command (Unix only)
+#[cfg(unix)]
+pub mod remote;
+
 // Exec command (Unix only)
 #[cfg(unix)]
 pub mod exec;
 
+// Commit command (Unix only)
+#[cfg(unix)]
+pub mod commit;
+
+// Flatten command (Unix only)
+#[cfg(unix)]
+pub mod flatten;
+
+// Seeding for `init --seed` (Unix only)
+#[cfg(unix)]
+pub mod seed;
+
+// Fsck command (Unix only)
+#[cfg(unix)]
+pub mod fsck;
+
+// Doctor command (Unix only)
+#[cfg(unix)]
+pub mod doctor;
+
+// Unmount command (Unix only)
+#[cfg(unix)]
+pub mod umount;
+
 pub use mount::{mount, MountArgs, MountBackend};
-pub use run::handle_run_command;
+pub use run::{handle_run_command, ResourceLimits};
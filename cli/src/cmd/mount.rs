@@ -1,14 +1,15 @@
-use agentfs_sdk::{error::Error as SdkError, AgentFSOptions, FileSystem, HostFS, OverlayFS};
+use agentfs_sdk::{error::Error as SdkError, AgentFSOptions, FileSystem, OverlayFS, ReadOnlyFS};
 use anyhow::{Context, Result};
 use std::{
     path::{Path, PathBuf},
     process::Command,
     sync::Arc,
+    time::Duration,
 };
 use tokio::sync::Mutex;
 use turso::value::Value;
 
-use crate::mount::{mount_fs, MountOpts};
+use crate::mount::{mount_fs, FuseCacheOptions, MountOpts};
 use crate::nfs::AgentNFS;
 use crate::nfsserve::tcp::NFSTcp;
 
@@ -51,6 +52,20 @@ pub struct MountArgs {
     pub gid: Option<u32>,
     /// The mount backend to use (fuse or nfs).
     pub backend: MountBackend,
+    /// Reject every modification with EROFS.
+    pub read_only: bool,
+    /// Have the kernel check stored modes and owners (FUSE only).
+    pub enforce_permissions: bool,
+    /// Name the mount is shown under (defaults to the agent ID or path).
+    pub volume_name: Option<String>,
+    /// Largest readahead the kernel may issue, in bytes (FUSE only).
+    pub max_readahead: Option<u32>,
+    /// How long the kernel may cache name lookups (FUSE only).
+    pub entry_timeout: Option<Duration>,
+    /// How long the kernel may cache file attributes (FUSE only).
+    pub attr_timeout: Option<Duration>,
+    /// Keep cached file contents when a file is opened again (FUSE only).
+    pub kernel_cache: bool,
 }
 
 /// Mount the agent filesystem (Linux).
@@ -82,6 +97,16 @@ pub fn mount(args: MountArgs) -> Result<()> {
     }
 }
 
+/// The kernel caching parameters requested for a mount.
+fn fuse_cache_options(args: &MountArgs) -> FuseCacheOptions {
+    FuseCacheOptions {
+        max_readahead: args.max_readahead,
+        entry_timeout: args.entry_timeout,
+        attr_timeout: args.attr_timeout,
+        kernel_cache: args.kernel_cache,
+    }
+}
+
 /// Mount the agent filesystem using FUSE (Linux only).
 #[cfg(target_os = "linux")]
 fn mount_fuse(args: MountArgs) -> Result<()> {
@@ -103,12 +128,7 @@ fn mount_fuse(args: MountArgs) -> Result<()> {
         }
     }
 
-    let fsname = format!(
-        "agentfs:{}",
-        std::fs::canonicalize(&args.id_or_path)
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|_| args.id_or_path.clone())
-    );
+    let fsname = mount_fsname(&args)?;
 
     if !args.mountpoint.exists() {
         anyhow::bail!("Mountpoint does not exist: {}", args.mountpoint.display());
@@ -130,9 +150,15 @@ fn mount_fuse(args: MountArgs) -> Result<()> {
         fsname,
         uid: args.uid,
         gid: args.gid,
+        read_only: args.read_only,
+        enforce_permissions: args.enforce_permissions,
+        cache: fuse_cache_options(&args),
     };
 
     let id_or_path = args.id_or_path.clone();
+    let read_only = args.read_only;
+    let foreground = args.foreground;
+    let signal_mountpoint = mountpoint.clone();
     let mount = move || {
         let rt = crate::get_runtime();
         let agentfs = match rt.block_on(open_agentfs(opts)) {
@@ -170,7 +196,7 @@ fn mount_fuse(args: MountArgs) -> Result<()> {
             if let Some(base_path) = base_path {
                 // Create OverlayFS with HostFS base, loading existing whiteouts
                 eprintln!("Using overlay filesystem with base: {}", base_path);
-                let hostfs = HostFS::new(&base_path)?;
+                let hostfs = agentfs.open_overlay_base(&base_path).await?;
                 let hostfs = hostfs.with_fuse_mountpoint(mountpoint_ino);
                 let overlay = OverlayFS::new(Arc::new(hostfs), agentfs.fs);
                 overlay.load().await?; // Load persisted whiteouts and origin mappings
@@ -180,6 +206,17 @@ fn mount_fuse(args: MountArgs) -> Result<()> {
                 Ok(Arc::new(agentfs.fs) as Arc<dyn FileSystem>)
             }
         })?;
+        let fs: Arc<dyn FileSystem> = if read_only {
+            Arc::new(ReadOnlyFS::new(fs))
+        } else {
+            fs
+        };
+
+        // A daemon is stopped with `agentfs umount`; in the foreground, Ctrl+C
+        // should not leave a stale mount behind
+        if foreground {
+            rt.spawn(unmount_on_signal(fs.clone(), signal_mountpoint));
+        }
 
         crate::fuse::mount(fs, fuse_opts, rt)
     };
@@ -195,10 +232,98 @@ fn mount_fuse(args: MountArgs) -> Result<()> {
     }
 }
 
+/// Wait for SIGINT or SIGTERM, then sync the filesystem and unmount it.
+///
+/// Unmounting ends the FUSE session, so `crate::fuse::mount` returns and the
+/// process exits normally. If the mount is busy, a lazy unmount detaches it
+/// instead; if even that fails, the error is shown and the next signal tries
+/// again.
+#[cfg(target_os = "linux")]
+async fn unmount_on_signal(fs: Arc<dyn FileSystem>, mountpoint: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Warning: failed to install signal handlers: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = interrupt.recv() => {}
+            _ = terminate.recv() => {}
+        }
+        eprintln!("Unmounting {}...", mountpoint.display());
+
+        if let Err(e) = fs.sync_all().await {
+            eprintln!("Warning: failed to sync filesystem: {}", e);
+        }
+
+        let path = mountpoint.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            crate::mount::unmount(&path, MountBackend::Fuse, false)
+                .or_else(|_| crate::mount::unmount(&path, MountBackend::Fuse, true))
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => eprintln!("Failed to unmount {}: {}", mountpoint.display(), e),
+            Err(e) => eprintln!("Failed to unmount {}: {}", mountpoint.display(), e),
+        }
+    }
+}
+
+/// Filesystem name for a mount, shown by `mount` and `df` and used as its ID
+/// by `agentfs mount` listings and `agentfs umount`.
+fn mount_fsname(args: &MountArgs) -> Result<String> {
+    let name = match &args.volume_name {
+        Some(name) => {
+            // The name ends up in comma-separated mount options and in the
+            // whitespace-separated /proc/mounts
+            if name.is_empty() || name.contains(|c: char| c == ',' || c.is_whitespace()) {
+                anyhow::bail!(
+                    "Invalid mount name '{}': it must be non-empty and contain no commas or whitespace",
+                    name
+                );
+            }
+            name.clone()
+        }
+        None => std::fs::canonicalize(&args.id_or_path)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| args.id_or_path.clone()),
+    };
+    Ok(format!("agentfs:{}", name))
+}
+
+/// Put a filesystem behind the mutex used by the NFS server, wrapping it in
+/// a `ReadOnlyFS` for read-only mounts.
+fn shared_fs<T: FileSystem + 'static>(fs: T, read_only: bool) -> Arc<Mutex<dyn FileSystem + Send>> {
+    if read_only {
+        Arc::new(Mutex::new(ReadOnlyFS::new(Arc::new(fs))))
+    } else {
+        Arc::new(Mutex::new(fs))
+    }
+}
+
 /// Mount the agent filesystem using NFS over localhost.
 async fn mount_nfs_backend(args: MountArgs) -> Result<()> {
     use crate::cmd::init::open_agentfs;
 
+    if fuse_cache_options(&args).is_set() {
+        eprintln!(
+            "Warning: --max-readahead, --entry-timeout, --attr-timeout and --kernel-cache \
+             only apply to the FUSE backend; ignoring them"
+        );
+    }
+    if args.enforce_permissions {
+        eprintln!("Warning: --enforce-permissions only applies to the FUSE backend; ignoring it");
+    }
+
     let opts = AgentFSOptions::resolve(&args.id_or_path)?;
 
     if !args.mountpoint.exists() {
@@ -207,12 +332,7 @@ async fn mount_nfs_backend(args: MountArgs) -> Result<()> {
 
     let mountpoint = std::fs::canonicalize(args.mountpoint.clone())?;
 
-    let fsname = format!(
-        "agentfs:{}",
-        std::fs::canonicalize(&args.id_or_path)
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|_| args.id_or_path.clone())
-    );
+    let fsname = mount_fsname(&args)?;
 
     // Open AgentFS
     let agentfs = match open_agentfs(opts).await {
@@ -246,16 +366,16 @@ async fn mount_nfs_backend(args: MountArgs) -> Result<()> {
         }
     }; // conn is dropped here
 
-    let fs: Arc<Mutex<dyn FileSystem + Send>> = if let Some(base_path) = base_path {
+    let fs = if let Some(base_path) = base_path {
         // Create OverlayFS with HostFS base, loading existing whiteouts
         eprintln!("Using overlay filesystem with base: {}", base_path);
-        let hostfs = HostFS::new(&base_path)?;
+        let hostfs = agentfs.open_overlay_base(&base_path).await?;
         let overlay = OverlayFS::new(Arc::new(hostfs), agentfs.fs);
         overlay.load().await?; // Load persisted whiteouts and origin mappings
-        Arc::new(Mutex::new(overlay)) as Arc<Mutex<dyn FileSystem + Send>>
+        shared_fs(overlay, args.read_only)
     } else {
         // Plain AgentFS
-        Arc::new(Mutex::new(agentfs.fs)) as Arc<Mutex<dyn FileSystem + Send>>
+        shared_fs(agentfs.fs, args.read_only)
     };
 
     if args.foreground {
@@ -273,13 +393,16 @@ async fn mount_nfs_backend(args: MountArgs) -> Result<()> {
             timeout: std::time::Duration::from_secs(10),
         };
 
-        let _mount_handle = mount_fs(fs, mount_opts).await?;
+        let _mount_handle = mount_fs(fs.clone(), mount_opts).await?;
 
         eprintln!("Mounted at {}", mountpoint.display());
         eprintln!("Press Ctrl+C to unmount and exit.");
         tokio::signal::ctrl_c().await?;
 
-        // Handle drops automatically when we exit this scope
+        // Checkpoint before the handle drops and unmounts
+        if let Err(e) = fs.lock().await.sync_all().await {
+            eprintln!("Warning: failed to sync filesystem: {}", e);
+        }
     } else {
         // Daemon mode: use manual NFS server setup for persistent background operation
         let nfs = AgentNFS::new(fs);
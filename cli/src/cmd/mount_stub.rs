@@ -1,5 +1,5 @@
 use anyhow::Result;
-use std::{io::Write, path::PathBuf};
+use std::{io::Write, path::PathBuf, time::Duration};
 
 pub use crate::opts::MountBackend;
 
@@ -25,6 +25,20 @@ pub struct MountArgs {
     pub gid: Option<u32>,
     /// The mount backend to use (fuse or nfs).
     pub backend: MountBackend,
+    /// Reject every modification with EROFS.
+    pub read_only: bool,
+    /// Have the kernel check stored modes and owners (FUSE only).
+    pub enforce_permissions: bool,
+    /// Name the mount is shown under (defaults to the agent ID or path).
+    pub volume_name: Option<String>,
+    /// Largest readahead the kernel may issue, in bytes (FUSE only).
+    pub max_readahead: Option<u32>,
+    /// How long the kernel may cache name lookups (FUSE only).
+    pub entry_timeout: Option<Duration>,
+    /// How long the kernel may cache file attributes (FUSE only).
+    pub attr_timeout: Option<Duration>,
+    /// Keep cached file contents when a file is opened again (FUSE only).
+    pub kernel_cache: bool,
 }
 
 /// List all currently mounted agentfs filesystems
@@ -4,7 +4,7 @@
 //! filesystem over the network, allowing remote systems (like VMs) to mount
 //! it as their root filesystem.
 
-use agentfs_sdk::{agentfs_dir, AgentFSOptions, FileSystem, HostFS, OverlayFS};
+use agentfs_sdk::{agentfs_dir, AgentFSOptions, FileSystem, OverlayFS};
 use anyhow::{Context, Result};
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -35,7 +35,10 @@ pub async fn handle_nfs_command(id_or_path: String, bind: String, port: u32) ->
 
     // Create filesystem - either direct AgentFS or overlay with base
     let fs: Arc<Mutex<dyn FileSystem>> = if let Some(base_str) = base_path {
-        let hostfs = HostFS::new(&base_str).context("Failed to create HostFS")?;
+        let hostfs = agentfs
+            .open_overlay_base(&base_str)
+            .await
+            .context("Failed to create HostFS")?;
         let overlay = OverlayFS::new(Arc::new(hostfs), agentfs.fs);
         overlay.load().await?; // Load persisted whiteouts and origin mappings
 
@@ -3,10 +3,12 @@
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::cmd::timeline::OutputFormat;
+
 /// Information about a process in a session.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProcInfo {
@@ -69,9 +71,29 @@ fn is_process_alive(pid: u32) -> bool {
     PathBuf::from(format!("/proc/{}", pid)).exists()
 }
 
+/// Whether a session's filesystem is currently mounted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SessionStatus {
+    Mounted,
+    Unmounted,
+}
+
 /// Information about a session with its processes.
+#[derive(Debug, Serialize)]
 struct SessionInfo {
+    #[serde(rename = "id")]
     session_id: String,
+    /// Where the session's filesystem is mounted.
+    mountpoint: PathBuf,
+    status: SessionStatus,
+    /// The session's delta database.
+    db_path: PathBuf,
+    /// On-disk size of the delta database and its WAL/SHM files in bytes,
+    /// if it exists.
+    db_size: Option<u64>,
+    /// PID of the session owner that holds the mount, while mounted.
+    mount_pid: Option<u32>,
     procs: Vec<ProcInfo>,
 }
 
@@ -151,7 +173,29 @@ fn list_sessions() -> Vec<SessionInfo> {
                 return None;
             }
 
-            Some(SessionInfo { session_id, procs })
+            let mountpoint = entry.path().join("mnt");
+            let status = if is_mountpoint(&mountpoint) {
+                SessionStatus::Mounted
+            } else {
+                SessionStatus::Unmounted
+            };
+            let db_path = entry.path().join("delta.db");
+            let db_size = database_disk_size(&db_path);
+            // The owner process created the mount and serves it
+            let mount_pid = match status {
+                SessionStatus::Mounted => procs.iter().find(|p| p.owner).map(|p| p.pid),
+                SessionStatus::Unmounted => None,
+            };
+
+            Some(SessionInfo {
+                session_id,
+                mountpoint,
+                status,
+                db_path,
+                db_size,
+                mount_pid,
+                procs,
+            })
         })
         .collect();
 
@@ -160,6 +204,118 @@ fn list_sessions() -> Vec<SessionInfo> {
     sessions
 }
 
+/// Total size of a database file and its WAL/SHM sidecars, which can hold
+/// most of a busy session's data before a checkpoint.
+fn database_disk_size(db_path: &Path) -> Option<u64> {
+    let db_size = std::fs::metadata(db_path).ok()?.len();
+    let sidecars: u64 = ["-wal", "-shm"]
+        .iter()
+        .filter_map(|suffix| {
+            let mut name = db_path.as_os_str().to_owned();
+            name.push(suffix);
+            std::fs::metadata(PathBuf::from(name)).ok()
+        })
+        .map(|m| m.len())
+        .sum();
+    Some(db_size + sidecars)
+}
+
+/// Check whether a path is a mountpoint by comparing its device with its parent's.
+#[cfg(unix)]
+fn is_mountpoint(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let parent = match path.parent() {
+        Some(p) => p,
+        None => return false,
+    };
+    match (std::fs::metadata(path), std::fs::metadata(parent)) {
+        (Ok(meta), Ok(parent_meta)) => meta.dev() != parent_meta.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_mountpoint(_path: &Path) -> bool {
+    false
+}
+
+/// Remove the run directories of sessions left behind by crashed agents.
+///
+/// A session is stale when none of its processes is alive and its
+/// mountpoint's device no longer differs from its parent's. A dead FUSE
+/// mount fails that check while the kernel still lists it in
+/// `/proc/mounts`; such mounts are lazily unmounted first, and a session
+/// whose mount stays listed is kept so its `delta.db` is never removed from
+/// under a mount.
+pub fn prune_sessions<W: Write>(out: &mut W) -> Result<()> {
+    let Some(home) = dirs::home_dir() else {
+        return Ok(());
+    };
+    let entries = match std::fs::read_dir(home.join(".agentfs").join("run")) {
+        Ok(e) => e,
+        Err(_) => return Ok(()),
+    };
+
+    let mut pruned = 0;
+    for entry in entries.flatten() {
+        let session_id = entry.file_name().to_string_lossy().to_string();
+        let run_dir = entry.path();
+        if !run_dir.is_dir() || !collect_session_procs(&run_dir.join("procs")).is_empty() {
+            continue;
+        }
+        let mountpoint = run_dir.join("mnt");
+        if is_mountpoint(&mountpoint) {
+            continue;
+        }
+
+        if let Some(mount) = kernel_mount(&mountpoint) {
+            #[cfg(unix)]
+            {
+                let backend = if mount.fstype.starts_with("nfs") {
+                    crate::opts::MountBackend::Nfs
+                } else {
+                    crate::opts::MountBackend::Fuse
+                };
+                if let Err(e) = crate::mount::unmount(&mountpoint, backend, true) {
+                    tracing::debug!("failed to unmount {}: {}", mountpoint.display(), e);
+                }
+            }
+            if kernel_mount(&mountpoint).is_some() {
+                writeln!(
+                    out,
+                    "Kept {}: still mounted at {} ({})",
+                    session_id,
+                    mountpoint.display(),
+                    mount.fstype
+                )?;
+                continue;
+            }
+            writeln!(out, "Unmounted {}", mountpoint.display())?;
+        }
+
+        let size = database_disk_size(&run_dir.join("delta.db"))
+            .map(format_size)
+            .unwrap_or_else(|| "-".to_string());
+        std::fs::remove_dir_all(&run_dir)
+            .with_context(|| format!("Failed to remove {}", run_dir.display()))?;
+        writeln!(out, "Removed stale session {} ({})", session_id, size)?;
+        pruned += 1;
+    }
+
+    if pruned == 0 {
+        writeln!(out, "No stale sessions to prune.")?;
+    }
+    Ok(())
+}
+
+/// The agentfs mount the kernel lists at `mountpoint`, if any.
+fn kernel_mount(mountpoint: &Path) -> Option<agentfs_sdk::Mount> {
+    agentfs_sdk::get_mounts()
+        .into_iter()
+        .find(|m| m.mountpoint == mountpoint)
+}
+
 /// Format a duration as a human-readable string.
 fn format_duration(duration: chrono::Duration) -> String {
     let secs = duration.num_seconds();
@@ -174,6 +330,21 @@ fn format_duration(duration: chrono::Duration) -> String {
     }
 }
 
+/// Format a byte count as a short human-readable size (e.g. "12.4 MiB").
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
 /// Truncate a string to a maximum length, adding ellipsis if needed.
 fn truncate(s: &str, max_len: usize) -> String {
     if s.len() <= max_len {
@@ -185,38 +356,66 @@ fn truncate(s: &str, max_len: usize) -> String {
 
 // Column widths for table output
 const COL_SESSION: usize = 36;
+const COL_SIZE: usize = 10;
 const COL_PID: usize = 8;
 const COL_OWNER: usize = 5;
 const COL_COMMAND: usize = 15;
 const COL_STARTED: usize = 10;
 
 /// List active agentfs run sessions.
-pub fn list_ps<W: Write>(out: &mut W) -> Result<()> {
+///
+/// `format` is either "table" (the default, for humans) or "json". Sizes in
+/// the table are human-readable unless `bytes` is set, and `total` adds a
+/// footer with the session count and their combined size.
+pub fn list_ps<W: Write>(out: &mut W, format: &str, bytes: bool, total: bool) -> Result<()> {
     let sessions = list_sessions();
 
+    if format.parse::<OutputFormat>()? == OutputFormat::Json {
+        let json = serde_json::to_string_pretty(&sessions)
+            .context("Failed to serialize sessions to JSON")?;
+        writeln!(out, "{}", json)?;
+        return Ok(());
+    }
+
     if sessions.is_empty() {
         writeln!(out, "No active agentfs run sessions.")?;
         return Ok(());
     }
 
+    let render_size = |size: Option<u64>| match size {
+        Some(size) if bytes => size.to_string(),
+        Some(size) => format_size(size),
+        None => "-".to_string(),
+    };
+    let sizes: Vec<String> = sessions.iter().map(|s| render_size(s.db_size)).collect();
+    let total_size = render_size(Some(sessions.iter().filter_map(|s| s.db_size).sum()));
+    // Raw byte counts can outgrow the default column
+    let size_width = sizes
+        .iter()
+        .chain(total.then_some(&total_size))
+        .map(String::len)
+        .fold(COL_SIZE, usize::max);
+
     // Print header
     writeln!(
         out,
-        "{:<COL_SESSION$} {:>COL_PID$} {:^COL_OWNER$} {:<COL_COMMAND$} {:>COL_STARTED$}",
-        "SESSION", "PID", "OWNER", "COMMAND", "STARTED",
+        "{:<COL_SESSION$} {:>size_width$} {:>COL_PID$} {:^COL_OWNER$} {:<COL_COMMAND$} {:>COL_STARTED$}",
+        "SESSION", "SIZE", "PID", "OWNER", "COMMAND", "STARTED",
     )?;
 
     let now = Utc::now();
 
-    for session in &sessions {
+    for (session, size) in sessions.iter().zip(&sizes) {
         for proc in &session.procs {
+            // The owner holds the mount; kill it to tear down a stuck sandbox
             let owner_marker = if proc.owner { "*" } else { "" };
             let duration = now.signed_duration_since(proc.started_at);
 
             writeln!(
                 out,
-                "{:<COL_SESSION$} {:>COL_PID$} {:^COL_OWNER$} {:<COL_COMMAND$} {:>COL_STARTED$}",
+                "{:<COL_SESSION$} {:>size_width$} {:>COL_PID$} {:^COL_OWNER$} {:<COL_COMMAND$} {:>COL_STARTED$}",
                 &session.session_id,
+                size,
                 proc.pid,
                 owner_marker,
                 truncate(&proc.command, COL_COMMAND),
@@ -225,5 +424,42 @@ pub fn list_ps<W: Write>(out: &mut W) -> Result<()> {
         }
     }
 
+    if total {
+        let label = format!(
+            "TOTAL ({} session{})",
+            sessions.len(),
+            if sessions.len() == 1 { "" } else { "s" }
+        );
+        writeln!(out, "{:<COL_SESSION$} {:>size_width$}", label, total_size)?;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(12 * 1024 * 1024 + 400 * 1024), "12.4 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_database_disk_size_counts_sidecars() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("delta.db");
+        assert_eq!(database_disk_size(&db_path), None);
+
+        std::fs::write(&db_path, vec![0u8; 4096]).unwrap();
+        assert_eq!(database_disk_size(&db_path), Some(4096));
+
+        std::fs::write(dir.path().join("delta.db-wal"), vec![0u8; 1000]).unwrap();
+        std::fs::write(dir.path().join("delta.db-shm"), vec![0u8; 32768]).unwrap();
+        assert_eq!(database_disk_size(&db_path), Some(4096 + 1000 + 32768));
+    }
+}
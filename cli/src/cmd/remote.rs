@@ -0,0 +1,55 @@
+//! Remote filesystem server command.
+//!
+//! Serve an agent filesystem over the AgentFS remote protocol, so that a
+//! `RemoteFS` on another host can use it as if it were local.
+
+use std::sync::Arc;
+
+use agentfs_sdk::filesystem::remote;
+use agentfs_sdk::{AgentFSOptions, FileSystem, OverlayFS};
+use anyhow::{Context, Result};
+use tokio::net::TcpListener;
+use tokio::signal;
+
+use crate::cmd::init::open_agentfs;
+
+/// Handle the `serve remote` command.
+pub async fn handle_remote_command(id_or_path: String, listen: String) -> Result<()> {
+    let agentfs = open_agentfs(AgentFSOptions::resolve(&id_or_path)?).await?;
+
+    let fs: Arc<dyn FileSystem> = match agentfs
+        .is_overlay_enabled()
+        .await
+        .context("Failed to check overlay config")?
+    {
+        Some(base_str) => {
+            let hostfs = agentfs
+                .open_overlay_base(&base_str)
+                .await
+                .context("Failed to create HostFS")?;
+            let overlay = OverlayFS::new(Arc::new(hostfs), agentfs.fs);
+            overlay.load().await?;
+            eprintln!("Mode: overlay (base: {})", base_str);
+            Arc::new(overlay)
+        }
+        None => {
+            eprintln!("Mode: direct AgentFS");
+            Arc::new(agentfs.fs)
+        }
+    };
+
+    let listener = TcpListener::bind(&listen)
+        .await
+        .with_context(|| format!("Failed to bind remote server to {}", listen))?;
+    eprintln!("Serving {} on {}", id_or_path, listener.local_addr()?);
+    eprintln!("Press Ctrl+C to stop.");
+
+    tokio::select! {
+        result = remote::serve(listener, fs) => result.context("Remote server failed")?,
+        result = signal::ctrl_c() => {
+            result.context("Failed to listen for ctrl+c")?;
+            eprintln!("Shutting down...");
+        }
+    }
+    Ok(())
+}
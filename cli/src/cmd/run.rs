@@ -16,6 +16,24 @@ use std::path::PathBuf;
 #[cfg_attr(not(feature = "sandbox"), path = "run_not_supported.rs")]
 mod sys;
 
+/// Limits on the resources the sandboxed command and its children may use.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ResourceLimits {
+    /// Maximum memory in bytes
+    pub memory: Option<u64>,
+    /// Maximum CPU time, in cores
+    pub cpu: Option<f64>,
+    /// Maximum number of processes and threads
+    pub pids: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Whether no limit was requested.
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu.is_none() && self.pids.is_none()
+    }
+}
+
 /// Handle the `run` command, dispatching to the platform-specific implementation.
 #[allow(clippy::too_many_arguments)]
 pub async fn handle_run_command(
@@ -24,8 +42,10 @@ pub async fn handle_run_command(
     experimental_sandbox: bool,
     strace: bool,
     session: Option<String>,
+    from: Option<String>,
     system: bool,
     encryption: Option<(String, String)>,
+    limits: ResourceLimits,
     command: PathBuf,
     args: Vec<String>,
 ) -> Result<()> {
@@ -35,8 +55,10 @@ pub async fn handle_run_command(
         experimental_sandbox,
         strace,
         session,
+        from,
         system,
         encryption,
+        limits,
         command,
         args,
     )
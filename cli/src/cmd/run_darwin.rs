@@ -9,7 +9,7 @@
 
 #![cfg(unix)]
 
-use agentfs_sdk::{AgentFS, AgentFSOptions, EncryptionConfig, FileSystem, HostFS, OverlayFS};
+use agentfs_sdk::{AgentFS, AgentFSOptions, EncryptionConfig, FileSystem, OverlayFS};
 use anyhow::{Context, Result};
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -33,11 +33,16 @@ pub async fn run(
     _experimental_sandbox: bool,
     _strace: bool,
     session_id: Option<String>,
+    from: Option<String>,
     _system: bool,
     encryption: Option<(String, String)>,
+    limits: super::ResourceLimits,
     command: PathBuf,
     args: Vec<String>,
 ) -> Result<()> {
+    if !limits.is_empty() {
+        eprintln!("Warning: --memory, --cpu and --pids are only supported on Linux, ignoring");
+    }
     let cwd = std::env::current_dir().context("Failed to get current directory")?;
     let home = dirs::home_dir().context("Failed to get home directory")?;
 
@@ -46,6 +51,12 @@ pub async fn run(
     // Check if we're joining an existing session
     if is_mountpoint(&session.mountpoint) {
         if is_mount_healthy(&session.mountpoint) {
+            if from.is_some() {
+                anyhow::bail!(
+                    "Session {} is already running; --from only applies to new sessions",
+                    session.session_id
+                );
+            }
             eprintln!("Joining existing session: {}", session.session_id);
             eprintln!();
             let exit_code = run_command_in_mount(&session, command, args)?;
@@ -58,6 +69,12 @@ pub async fn run(
         }
     }
 
+    let base_str = cwd.to_string_lossy().to_string();
+    if let Some(from) = &from {
+        crate::sandbox::seed::seed_delta(from, &session.db_path, &base_str, encryption.as_ref())
+            .await?;
+    }
+
     // Initialize the AgentFS database
     let db_path_str = session
         .db_path
@@ -77,8 +94,10 @@ pub async fn run(
         .context("Failed to create AgentFS")?;
 
     // Create overlay filesystem with CWD as base
-    let base_str = cwd.to_string_lossy().to_string();
-    let hostfs = HostFS::new(&base_str).context("Failed to create HostFS")?;
+    let hostfs = agentfs
+        .open_overlay_base(&base_str)
+        .await
+        .context("Failed to create HostFS")?;
     let overlay = OverlayFS::new(Arc::new(hostfs), agentfs.fs);
 
     // Initialize the overlay (copies directory structure)
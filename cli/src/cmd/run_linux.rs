@@ -14,8 +14,10 @@ pub async fn run(
     experimental_sandbox: bool,
     strace: bool,
     session: Option<String>,
+    from: Option<String>,
     system: bool,
     encryption: Option<(String, String)>,
+    limits: super::ResourceLimits,
     command: PathBuf,
     args: Vec<String>,
 ) -> Result<()> {
@@ -26,9 +28,15 @@ pub async fn run(
         if session.is_some() {
             eprintln!("Warning: --session is not supported with --experimental-sandbox, ignoring");
         }
+        if from.is_some() {
+            eprintln!("Warning: --from is not supported with --experimental-sandbox, ignoring");
+        }
         if encryption.is_some() {
             eprintln!("Warning: --key is not supported with --experimental-sandbox, ignoring");
         }
+        if !limits.is_empty() {
+            eprintln!("Warning: --memory, --cpu and --pids are not supported with --experimental-sandbox, ignoring");
+        }
         crate::sandbox::linux_ptrace::run_cmd(strace, command, args).await;
     } else {
         if strace {
@@ -38,8 +46,10 @@ pub async fn run(
             allow,
             no_default_allows,
             session,
+            from,
             system,
             encryption,
+            limits,
             command,
             args,
         )
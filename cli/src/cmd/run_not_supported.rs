@@ -6,14 +6,17 @@ use anyhow::{bail, Result};
 use std::path::PathBuf;
 
 /// Run the command in a Windows sandbox.
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     _allow: Vec<PathBuf>,
     _no_default_allows: bool,
     _experimental_sandbox: bool,
     _strace: bool,
     _session: Option<String>,
+    _from: Option<String>,
     _system: bool,
     _encryption: Option<(String, String)>,
+    _limits: super::ResourceLimits,
     _command: PathBuf,
     _args: Vec<String>,
 ) -> Result<()> {
@@ -13,8 +13,10 @@ pub async fn run(
     _experimental_sandbox: bool,
     _strace: bool,
     _session: Option<String>,
+    _from: Option<String>,
     _system: bool,
     _encryption: Option<(String, String)>,
+    _limits: super::ResourceLimits,
     _command: PathBuf,
     _args: Vec<String>,
 ) -> Result<()> {
@@ -0,0 +1,314 @@
+//! Seeding a new agent filesystem from a host directory.
+//!
+//! `agentfs init --seed DIR` copies the files, directories and symlinks of
+//! `DIR` into the new database through the `FileSystem` trait, keeping their
+//! modes and modification times. Unlike `--base`, the copy is complete once
+//! init returns and the directory is never consulted again.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+use agentfs_sdk::filesystem::AgentFS;
+use agentfs_sdk::{FileSystem, TimeChange, S_IFREG};
+use anyhow::{Context, Result as AnyhowResult};
+
+const ROOT_INO: i64 = 1;
+
+/// Size of the reads used to stream file contents into the filesystem.
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Name of the file in the seed directory listing patterns to leave out.
+pub const IGNORE_FILE: &str = ".agentfsignore";
+
+/// What a seed copied into the filesystem.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SeedStats {
+    /// Regular files copied, counting each hard-linked file once.
+    pub files: u64,
+    /// Bytes of file contents copied.
+    pub bytes: u64,
+}
+
+/// Copy the contents of `source` into the root of `fs`.
+///
+/// Entries are owned by the filesystem's root owner. Paths matching a
+/// pattern in `source/.agentfsignore` are skipped, along with everything
+/// below an ignored directory; the ignore file itself is not copied.
+/// Sockets, FIFOs and device nodes are skipped with a warning.
+pub async fn seed_from_dir(fs: &AgentFS, source: &Path) -> AnyhowResult<SeedStats> {
+    let ignore = match std::fs::read_to_string(source.join(IGNORE_FILE)) {
+        Ok(contents) => IgnorePatterns::parse(&contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => IgnorePatterns::default(),
+        Err(e) => return Err(e).context("Failed to read .agentfsignore"),
+    };
+    let root = FileSystem::getattr(fs, ROOT_INO)
+        .await?
+        .context("Filesystem has no root directory")?;
+    let (uid, gid) = (root.uid, root.gid);
+
+    let mut stats = SeedStats::default();
+    // Host (dev, ino) of every file with more than one link, to its new inode
+    let mut linked: HashMap<(u64, u64), i64> = HashMap::new();
+    let mut dirs = Vec::new();
+    let mut pending = vec![(source.to_path_buf(), String::new(), ROOT_INO)];
+
+    while let Some((dir, rel_dir, dir_ino)) = pending.pop() {
+        let mut entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let host_path = entry.path();
+            let name = entry.file_name();
+            let name = name
+                .to_str()
+                .with_context(|| format!("Non-UTF8 file name: {}", host_path.display()))?;
+            if rel_dir.is_empty() && name == IGNORE_FILE {
+                continue;
+            }
+            let rel_path = format!("{}/{}", rel_dir, name);
+            let meta = std::fs::symlink_metadata(&host_path)
+                .with_context(|| format!("Failed to stat {}", host_path.display()))?;
+            let file_type = meta.file_type();
+            if ignore.is_ignored(&rel_path, file_type.is_dir()) {
+                continue;
+            }
+            let mode = meta.permissions().mode() & 0o7777;
+
+            if file_type.is_dir() {
+                let created = FileSystem::mkdir(fs, dir_ino, name, mode, uid, gid).await?;
+                dirs.push((created.ino, mode, mtime_of(&meta)));
+                pending.push((host_path, rel_path, created.ino));
+            } else if file_type.is_symlink() {
+                let target = std::fs::read_link(&host_path)?;
+                let target = target
+                    .to_str()
+                    .with_context(|| format!("Non-UTF8 symlink target: {}", host_path.display()))?;
+                // Symlink mode and times are not meaningful
+                FileSystem::symlink(fs, dir_ino, name, target, uid, gid).await?;
+            } else if file_type.is_file() {
+                let key = (meta.dev(), meta.ino());
+                if meta.nlink() > 1 {
+                    if let Some(&ino) = linked.get(&key) {
+                        FileSystem::link(fs, ino, dir_ino, name).await?;
+                        continue;
+                    }
+                }
+                let (created, file) =
+                    FileSystem::create_file(fs, dir_ino, name, S_IFREG | mode, uid, gid).await?;
+                let mut host_file = std::fs::File::open(&host_path)
+                    .with_context(|| format!("Failed to open {}", host_path.display()))?;
+                let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+                let mut offset = 0;
+                loop {
+                    let n = host_file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    file.pwrite(offset, &buf[..n]).await?;
+                    offset += n as u64;
+                }
+                set_metadata(fs, created.ino, mode, mtime_of(&meta)).await?;
+                if meta.nlink() > 1 {
+                    linked.insert(key, created.ino);
+                }
+                stats.files += 1;
+                stats.bytes += offset;
+            } else {
+                eprintln!(
+                    "Warning: skipping {} (not a file, directory or symlink)",
+                    host_path.display()
+                );
+            }
+        }
+    }
+
+    for (ino, mode, mtime) in dirs.iter().rev() {
+        set_metadata(fs, *ino, *mode, *mtime).await?;
+    }
+    Ok(stats)
+}
+
+/// The modification time of a host entry as seconds and nanoseconds.
+fn mtime_of(meta: &std::fs::Metadata) -> (i64, u32) {
+    (meta.mtime(), meta.mtime_nsec() as u32)
+}
+
+/// Apply a host mode and mtime to a copied entry.
+///
+/// The mode is set explicitly so that a configured umask does not change
+/// what was copied.
+async fn set_metadata(fs: &AgentFS, ino: i64, mode: u32, mtime: (i64, u32)) -> AnyhowResult<()> {
+    FileSystem::chmod(fs, ino, mode).await?;
+    FileSystem::utimens(fs, ino, TimeChange::Omit, TimeChange::Set(mtime.0, mtime.1)).await?;
+    Ok(())
+}
+
+/// Patterns from an `.agentfsignore` file.
+///
+/// Each non-blank line not starting with `#` is a pattern in a subset of
+/// gitignore syntax: `*` and `?` match within a path component and `**`
+/// across components, a trailing `/` only matches directories, and a pattern
+/// containing any other `/` is anchored at the seed directory. Other
+/// patterns match an entry's name at any depth.
+#[derive(Debug, Default)]
+struct IgnorePatterns {
+    patterns: Vec<IgnorePattern>,
+}
+
+#[derive(Debug)]
+struct IgnorePattern {
+    glob: String,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl IgnorePatterns {
+    fn parse(contents: &str) -> Self {
+        let patterns = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (line, dir_only) = match line.strip_suffix('/') {
+                    Some(stripped) => (stripped, true),
+                    None => (line, false),
+                };
+                let anchored = line.contains('/');
+                let glob = line.trim_start_matches('/').to_string();
+                (!glob.is_empty()).then_some(IgnorePattern {
+                    glob,
+                    anchored,
+                    dir_only,
+                })
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    /// Whether the entry at `rel_path` (relative to the seed directory, with
+    /// a leading slash) should be skipped.
+    fn is_ignored(&self, rel_path: &str, is_dir: bool) -> bool {
+        let rel_path = rel_path.trim_start_matches('/');
+        let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        self.patterns.iter().any(|pattern| {
+            if pattern.dir_only && !is_dir {
+                return false;
+            }
+            let subject = if pattern.anchored { rel_path } else { name };
+            glob_match(pattern.glob.as_bytes(), subject.as_bytes())
+        })
+    }
+}
+
+/// Match `text` against a glob where `*` and `?` stop at `/` and `**` does not.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let limit = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=limit).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => match text {
+            [c, text @ ..] if *c != b'/' => glob_match(rest, text),
+            _ => false,
+        },
+        [c, rest @ ..] => match text {
+            [t, text @ ..] if t == c => glob_match(rest, text),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use agentfs_sdk::{AgentFS, AgentFSOptions};
+    use tempfile::TempDir;
+
+    use super::{glob_match, seed_from_dir, IgnorePatterns, SeedStats};
+
+    #[test]
+    fn test_ignore_patterns() {
+        let ignore =
+            IgnorePatterns::parse("# build output\n\ntarget/\n*.log\n/docs/*.tmp\nsrc/**/gen\n");
+        assert!(ignore.is_ignored("/target", true));
+        assert!(ignore.is_ignored("/crates/a/target", true));
+        assert!(!ignore.is_ignored("/target", false));
+        assert!(ignore.is_ignored("/debug.log", false));
+        assert!(ignore.is_ignored("/logs/today.log", false));
+        assert!(ignore.is_ignored("/docs/a.tmp", false));
+        assert!(!ignore.is_ignored("/other/docs/a.tmp", false));
+        assert!(ignore.is_ignored("/src/gen", true));
+        assert!(ignore.is_ignored("/src/a/b/gen", false));
+        assert!(!ignore.is_ignored("/src/main.rs", false));
+
+        assert!(glob_match(b"a?c", b"abc"));
+        assert!(!glob_match(b"a*c", b"a/c"));
+        assert!(glob_match(b"a/**/c", b"a/c"));
+    }
+
+    #[tokio::test]
+    async fn test_seed_from_dir() {
+        let source = TempDir::new().unwrap();
+        let root = source.path();
+        std::fs::create_dir_all(root.join("dir/nested")).unwrap();
+        std::fs::create_dir(root.join("target")).unwrap();
+        std::fs::write(root.join("dir/file.txt"), b"hello").unwrap();
+        std::fs::write(root.join("dir/nested/data.bin"), vec![7u8; 3000]).unwrap();
+        std::fs::write(root.join("target/out"), b"ignored").unwrap();
+        std::fs::write(root.join("debug.log"), b"ignored").unwrap();
+        std::fs::write(root.join(".agentfsignore"), b"target/\n*.log\n").unwrap();
+        std::fs::hard_link(root.join("dir/file.txt"), root.join("hard.txt")).unwrap();
+        std::os::unix::fs::symlink("dir/file.txt", root.join("link")).unwrap();
+        std::fs::set_permissions(
+            root.join("dir/file.txt"),
+            std::fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+        let mtime = std::fs::metadata(root.join("dir/nested/data.bin"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("seeded.db");
+        let agent = AgentFS::open(AgentFSOptions::with_path(db_path.to_str().unwrap()))
+            .await
+            .unwrap();
+        let stats = seed_from_dir(&agent.fs, root).await.unwrap();
+        assert_eq!(
+            stats,
+            SeedStats {
+                files: 2,
+                bytes: 3005
+            }
+        );
+
+        let fs = &agent.fs;
+        assert_eq!(
+            fs.read_file("/dir/file.txt").await.unwrap().unwrap(),
+            b"hello"
+        );
+        let file = fs.lstat("/dir/file.txt").await.unwrap().unwrap();
+        assert_eq!(file.mode & 0o7777, 0o640);
+        assert_eq!(file.nlink, 2);
+        assert_eq!(fs.lstat("/hard.txt").await.unwrap().unwrap().ino, file.ino);
+        let data = fs.lstat("/dir/nested/data.bin").await.unwrap().unwrap();
+        assert_eq!(data.mtime, mtime.as_secs() as i64);
+        assert_eq!(fs.readlink("/link").await.unwrap().unwrap(), "dir/file.txt");
+        for skipped in ["/target", "/debug.log", "/.agentfsignore"] {
+            assert!(fs.lstat(skipped).await.unwrap().is_none(), "{skipped}");
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Snapshot and restore commands.
+//!
+//! Record a point-in-time snapshot of an agent filesystem and roll back to it.
+
+use agentfs_sdk::{get_mounts, AgentFSOptions};
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+
+/// Handle the snapshot command.
+pub async fn handle_snapshot_command(id_or_path: String, label: String) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    eprintln!("Using agent: {}", id_or_path);
+
+    let agent = open_agentfs(options).await?;
+    agent.fs.snapshot(&label).await?;
+    eprintln!("Created snapshot '{}'", label);
+    Ok(())
+}
+
+/// Handle the restore command.
+///
+/// Refuses to run while the filesystem is mounted, since a live mount
+/// caches inodes that the restore would discard underneath it.
+pub async fn handle_restore_command(id_or_path: String, label: String) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    let db_path = options
+        .db_path()
+        .context("Failed to resolve database path")?;
+
+    if let Some(mountpoint) = find_mount(&id_or_path, &db_path) {
+        anyhow::bail!(
+            "Agent '{}' is mounted at {}; unmount it before restoring",
+            id_or_path,
+            mountpoint.display()
+        );
+    }
+    eprintln!("Using agent: {}", id_or_path);
+
+    let agent = open_agentfs(options).await?;
+    agent.fs.restore(&label).await?;
+    eprintln!("Restored snapshot '{}'", label);
+    Ok(())
+}
+
+/// Find the mountpoint of an agent filesystem, if it is mounted.
+///
+/// Mounts record either the agent ID or the canonical database path as
+/// their source, so both forms are checked.
+pub(crate) fn find_mount(id_or_path: &str, db_path: &str) -> Option<std::path::PathBuf> {
+    let canonical = |p: &str| {
+        std::fs::canonicalize(p)
+            .map(|p| p.to_string_lossy().to_string())
+            .ok()
+    };
+    let candidates = [
+        Some(id_or_path.to_string()),
+        canonical(id_or_path),
+        canonical(db_path),
+    ];
+
+    get_mounts()
+        .into_iter()
+        .find(|m| candidates.iter().flatten().any(|c| *c == m.id))
+        .map(|m| m.mountpoint)
+}
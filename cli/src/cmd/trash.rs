@@ -0,0 +1,123 @@
+//! Trash commands.
+//!
+//! List, restore and empty the entries an agent filesystem with trash mode
+//! kept instead of deleting them.
+
+use std::io::Write;
+
+use agentfs_sdk::filesystem::AgentFS;
+use agentfs_sdk::AgentFSOptions;
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::fs::{format_timestamp, mode_string};
+use crate::cmd::init::open_agentfs;
+use crate::cmd::snapshot::find_mount;
+
+/// Handle the trash list command.
+pub async fn handle_list_command(out: &mut impl Write, id_or_path: String) -> AnyhowResult<()> {
+    let agent = open_agentfs(AgentFSOptions::resolve(&id_or_path)?).await?;
+    if agent.fs.trash().is_none() {
+        eprintln!("Trash mode is off for this filesystem");
+    }
+    write_trash(out, &agent.fs).await
+}
+
+/// Write one line per trash entry: when it was removed, its mode and size,
+/// and the path it was removed from.
+async fn write_trash(out: &mut impl Write, fs: &AgentFS) -> AnyhowResult<()> {
+    for entry in fs.list_trash().await? {
+        writeln!(
+            out,
+            "{}  {} {:>10}  {}",
+            format_timestamp(entry.deleted_at),
+            mode_string(entry.mode),
+            entry.size,
+            entry.path
+        )?;
+    }
+    Ok(())
+}
+
+/// Handle the trash restore command.
+///
+/// Refuses to run while the filesystem is mounted, since the mount would
+/// not see the restored entries in its caches.
+pub async fn handle_restore_command(id_or_path: String, path: String) -> AnyhowResult<()> {
+    let options = AgentFSOptions::resolve(&id_or_path)?;
+    let db_path = options
+        .db_path()
+        .context("Failed to resolve database path")?;
+
+    if let Some(mountpoint) = find_mount(&id_or_path, &db_path) {
+        anyhow::bail!(
+            "Agent '{}' is mounted at {}; unmount it before restoring from the trash",
+            id_or_path,
+            mountpoint.display()
+        );
+    }
+
+    let agent = open_agentfs(options).await?;
+    let restored = agent
+        .fs
+        .restore_trash(&path)
+        .await
+        .with_context(|| format!("Failed to restore {}", path))?;
+    eprintln!("Restored {} entr{} at {}", restored, plural(restored), path);
+    Ok(())
+}
+
+/// Handle the trash empty command.
+pub async fn handle_empty_command(id_or_path: String) -> AnyhowResult<()> {
+    let agent = open_agentfs(AgentFSOptions::resolve(&id_or_path)?).await?;
+    let purged = agent.fs.empty_trash().await?;
+    eprintln!(
+        "Deleted {} entr{} from the trash",
+        purged,
+        plural(purged as usize)
+    );
+    Ok(())
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        "y"
+    } else {
+        "ies"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use agentfs_sdk::{AgentFS, AgentFSOptions, TrashPolicy};
+    use tempfile::TempDir;
+
+    use super::write_trash;
+
+    #[tokio::test]
+    async fn test_list_and_restore() {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("trash.db");
+        let agent = AgentFS::open(
+            AgentFSOptions::with_path(db_path.to_str().unwrap()).with_trash(TrashPolicy::default()),
+        )
+        .await
+        .unwrap();
+        let fs = &agent.fs;
+        fs.pwrite("/notes.txt", 0, b"hello").await.unwrap();
+        fs.remove("/notes.txt").await.unwrap();
+
+        let mut out = Vec::new();
+        write_trash(&mut out, fs).await.unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with(" -rw-r--r--          5  /notes.txt\n"),
+            "{}",
+            out
+        );
+
+        fs.restore_trash("/notes.txt").await.unwrap();
+        let mut out = Vec::new();
+        write_trash(&mut out, fs).await.unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+//! Tree command.
+//!
+//! Print the directory hierarchy of an agent filesystem in the manner of
+//! `tree(1)`, without mounting it.
+
+use std::io::Write;
+
+use agentfs_sdk::filesystem::AgentFS;
+use agentfs_sdk::{AgentFSOptions, FileSystem};
+use anyhow::{Context, Result as AnyhowResult};
+
+use crate::cmd::init::open_agentfs;
+use crate::cmd::walk::{WalkEntry, Walker};
+
+/// What to show for each entry of a tree.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TreeOptions {
+    /// Descend at most this many directories below the starting one
+    pub level: Option<usize>,
+    /// Show file sizes in bytes
+    pub size: bool,
+    /// Show inode numbers
+    pub inodes: bool,
+}
+
+/// Handle the tree command.
+///
+/// Symlinks are shown with their targets and never followed, except when
+/// `path` itself names a symlink to a directory.
+pub async fn handle_tree_command(
+    out: &mut impl Write,
+    id_or_path: String,
+    path: String,
+    options: TreeOptions,
+) -> AnyhowResult<()> {
+    let agent = open_agentfs(AgentFSOptions::resolve(&id_or_path)?).await?;
+    write_tree(out, &agent.fs, &path, options).await
+}
+
+/// Write the tree below `path`, followed by a count of what it contains.
+async fn write_tree(
+    out: &mut impl Write,
+    fs: &AgentFS,
+    path: &str,
+    options: TreeOptions,
+) -> AnyhowResult<()> {
+    let root = fs
+        .stat(path)
+        .await?
+        .with_context(|| format!("No such file or directory: {}", path))?;
+    if !root.is_directory() {
+        anyhow::bail!("Not a directory: {}", path);
+    }
+    writeln!(out, "{}", path)?;
+
+    let mut walker = Walker::new(fs, root.ino)
+        .await?
+        .max_depth(options.level.map(|level| level.saturating_sub(1)));
+    // Whether each open ancestor still has entries below the current one
+    let mut open: Vec<bool> = Vec::new();
+    let (mut dirs, mut files) = (0, 0);
+
+    while let Some(entry) = walker.next().await? {
+        open.truncate(entry.depth);
+        let mut line = String::new();
+        for &more in &open {
+            line.push_str(if more { "│   " } else { "    " });
+        }
+        line.push_str(if entry.is_last {
+            "└── "
+        } else {
+            "├── "
+        });
+        if let Some(details) = details(&entry, options) {
+            line.push_str(&format!("[{}]  ", details));
+        }
+        line.push_str(entry.path.rsplit('/').next().unwrap_or(&entry.path));
+
+        if entry.stats.is_symlink() {
+            let target = FileSystem::readlink(fs, entry.stats.ino)
+                .await?
+                .unwrap_or_default();
+            line.push_str(&format!(" -> {}", target));
+        }
+        writeln!(out, "{}", line)?;
+
+        if entry.stats.is_directory() {
+            dirs += 1;
+            open.push(!entry.is_last);
+        } else {
+            files += 1;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{} director{}, {} file{}",
+        dirs,
+        if dirs == 1 { "y" } else { "ies" },
+        files,
+        if files == 1 { "" } else { "s" }
+    )?;
+    Ok(())
+}
+
+/// The bracketed inode number and size shown before a name, if requested.
+fn details(entry: &WalkEntry, options: TreeOptions) -> Option<String> {
+    let mut fields = Vec::new();
+    if options.inodes {
+        fields.push(format!("{:>8}", entry.stats.ino));
+    }
+    if options.size {
+        fields.push(format!("{:>11}", entry.stats.size));
+    }
+    (!fields.is_empty()).then(|| fields.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use agentfs_sdk::{AgentFS, AgentFSOptions};
+    use tempfile::TempDir;
+
+    use super::{write_tree, TreeOptions};
+
+    async fn tree(
+        fs: &agentfs_sdk::filesystem::AgentFS,
+        path: &str,
+        options: TreeOptions,
+    ) -> String {
+        let mut out = Vec::new();
+        write_tree(&mut out, fs, path, options).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tree_output() {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("tree.db");
+        let agent = AgentFS::open(AgentFSOptions::with_path(db_path.to_str().unwrap()))
+            .await
+            .unwrap();
+        let fs = &agent.fs;
+        fs.mkdir("/src", 0, 0).await.unwrap();
+        fs.mkdir("/src/nested", 0, 0).await.unwrap();
+        fs.pwrite("/src/main.rs", 0, b"fn main() {}").await.unwrap();
+        fs.pwrite("/src/nested/mod.rs", 0, b"").await.unwrap();
+        fs.pwrite("/README", 0, b"hello").await.unwrap();
+        // A symlink loop is annotated, not followed
+        fs.symlink("/src", "/src/nested/up", 0, 0).await.unwrap();
+
+        assert_eq!(
+            tree(fs, "/", TreeOptions::default()).await,
+            "/\n\
+             ├── README\n\
+             └── src\n\
+             \x20   ├── main.rs\n\
+             \x20   └── nested\n\
+             \x20       ├── mod.rs\n\
+             \x20       └── up -> /src\n\
+             \n\
+             2 directories, 4 files\n"
+        );
+
+        let options = TreeOptions {
+            level: Some(1),
+            size: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            tree(fs, "/src", options).await,
+            "/src\n\
+             ├── [         12]  main.rs\n\
+             └── [          0]  nested\n\
+             \n\
+             1 directory, 1 file\n"
+        );
+    }
+}
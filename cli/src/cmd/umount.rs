@@ -0,0 +1,138 @@
+//! Unmount command.
+//!
+//! Unmount an agent filesystem by agent ID, database path or mountpoint,
+//! using the unmount helper that matches the mount's backend.
+
+use std::path::{Path, PathBuf};
+
+use agentfs_sdk::{get_mounts, AgentFSOptions, Mount};
+use anyhow::Result;
+
+use crate::mount::{unmount, MountBackend};
+
+/// Handle the umount command.
+///
+/// With `force`, the filesystem is detached lazily (`umount -l` /
+/// `fusermount -uz`) even if it is still busy.
+pub fn handle_umount_command(id_or_mountpoint: String, force: bool) -> Result<()> {
+    let mounts = get_mounts();
+    let (mountpoint, backend) = match find_target(&mounts, &id_or_mountpoint) {
+        Some(mount) => (mount.mountpoint.clone(), backend_of(mount)),
+        // Without a mount table (macOS) a mountpoint can still be unmounted
+        // directly; agentfs mounts there are served over NFS
+        None if cfg!(not(target_os = "linux")) && Path::new(&id_or_mountpoint).is_dir() => {
+            (PathBuf::from(&id_or_mountpoint), MountBackend::Nfs)
+        }
+        None => anyhow::bail!("'{}' is not currently mounted", id_or_mountpoint),
+    };
+
+    unmount(&mountpoint, backend, force)?;
+    cleanup_run_dir(&mountpoint);
+    println!("Unmounted {}", mountpoint.display());
+    Ok(())
+}
+
+/// Find the mount referred to by an agent ID, database path or mountpoint.
+fn find_target<'a>(mounts: &'a [Mount], id_or_mountpoint: &str) -> Option<&'a Mount> {
+    let canonical = |p: &str| {
+        std::fs::canonicalize(p)
+            .map(|p| p.to_string_lossy().to_string())
+            .ok()
+    };
+    let db_path = AgentFSOptions::resolve(id_or_mountpoint)
+        .ok()
+        .and_then(|options| options.db_path().ok());
+    let candidates = [
+        Some(id_or_mountpoint.to_string()),
+        canonical(id_or_mountpoint),
+        db_path.as_deref().and_then(canonical),
+    ];
+    let is_candidate = |s: &str| candidates.iter().flatten().any(|c| c == s);
+
+    mounts
+        .iter()
+        .find(|m| is_candidate(&m.id) || is_candidate(&m.mountpoint.to_string_lossy()))
+}
+
+/// Pick the unmount helper for a mount from its filesystem type.
+fn backend_of(mount: &Mount) -> MountBackend {
+    if mount.fstype.starts_with("fuse") {
+        MountBackend::Fuse
+    } else {
+        MountBackend::Nfs
+    }
+}
+
+/// Remove the mountpoint of an `agentfs run` session once it is unmounted.
+///
+/// Session mounts live at `~/.agentfs/run/<id>/mnt`. The session directory
+/// itself is only removed when nothing else (such as proc files of running
+/// processes) is left in it.
+fn cleanup_run_dir(mountpoint: &Path) {
+    let Some(home) = dirs::home_dir() else {
+        return;
+    };
+    let run_root = home.join(".agentfs").join("run");
+    let Some(session_dir) = mountpoint.parent() else {
+        return;
+    };
+    if mountpoint.file_name() != Some("mnt".as_ref())
+        || session_dir.parent() != Some(run_root.as_path())
+    {
+        return;
+    }
+    if std::fs::remove_dir(mountpoint).is_ok() {
+        let _ = std::fs::remove_dir(session_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use agentfs_sdk::Mount;
+
+    use super::{backend_of, find_target};
+    use crate::mount::MountBackend;
+
+    fn mounts() -> Vec<Mount> {
+        vec![
+            Mount {
+                id: "first".to_string(),
+                mountpoint: PathBuf::from("/mnt/first"),
+                fstype: "fuse".to_string(),
+            },
+            Mount {
+                id: "second".to_string(),
+                mountpoint: PathBuf::from("/mnt/second"),
+                fstype: "nfs".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn find_target_by_id_or_mountpoint() {
+        let mounts = mounts();
+
+        let mount = find_target(&mounts, "second").unwrap();
+        assert_eq!(mount.mountpoint, PathBuf::from("/mnt/second"));
+
+        let mount = find_target(&mounts, "/mnt/first").unwrap();
+        assert_eq!(mount.id, "first");
+
+        assert!(find_target(&mounts, "missing").is_none());
+    }
+
+    #[test]
+    fn backend_follows_fstype() {
+        let mounts = mounts();
+        assert!(matches!(backend_of(&mounts[0]), MountBackend::Fuse));
+        assert!(matches!(backend_of(&mounts[1]), MountBackend::Nfs));
+
+        let subtype = Mount {
+            fstype: "fuse.agentfs".to_string(),
+            ..mounts[1].clone()
+        };
+        assert!(matches!(backend_of(&subtype), MountBackend::Fuse));
+    }
+}
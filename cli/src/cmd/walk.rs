@@ -0,0 +1,91 @@
+//! Depth-first traversal of a filesystem through the `FileSystem` trait.
+//!
+//! Shared by the commands that visit a whole agent tree without mounting
+//! it, such as `cp`, `export`, `flatten` and `tree`.
+
+use agentfs_sdk::{DirEntry, FileSystem, Stats};
+use anyhow::Result as AnyhowResult;
+
+/// An entry visited by a [`Walker`].
+#[derive(Debug)]
+pub struct WalkEntry {
+    /// Path relative to the walk's root, without a leading slash
+    pub path: String,
+    /// Statistics of the entry itself; symlinks are not followed
+    pub stats: Stats,
+    /// Number of directories between the walk's root and the entry
+    pub depth: usize,
+    /// Whether this is the last entry of its directory
+    pub is_last: bool,
+}
+
+/// Visits every entry below a directory in name order, each directory
+/// followed by its contents.
+///
+/// Directories are read one at a time as the walk reaches them, and symlinks
+/// are reported rather than followed, so the walk always terminates.
+///
+/// Since a directory is visited before its contents, commands that copy a
+/// tree apply directory metadata in reverse order once the walk is done;
+/// creating the contents would otherwise reset each directory's mtime.
+pub struct Walker<'a, F: FileSystem + ?Sized> {
+    fs: &'a F,
+    max_depth: Option<usize>,
+    /// Remaining entries of each directory being walked, with its path
+    stack: Vec<(String, std::vec::IntoIter<DirEntry>)>,
+}
+
+impl<'a, F: FileSystem + ?Sized> Walker<'a, F> {
+    /// Start a walk of the directory `ino`.
+    pub async fn new(fs: &'a F, ino: i64) -> AnyhowResult<Self> {
+        let mut walker = Self {
+            fs,
+            max_depth: None,
+            stack: Vec::new(),
+        };
+        walker.push_dir(String::new(), ino).await?;
+        Ok(walker)
+    }
+
+    /// Do not descend more than `max_depth` directories below the root;
+    /// `Some(0)` visits only the root's own entries.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The next entry of the walk, or `None` once every entry was visited.
+    pub async fn next(&mut self) -> AnyhowResult<Option<WalkEntry>> {
+        while let Some((dir, entries)) = self.stack.last_mut() {
+            let Some(entry) = entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let is_last = entries.len() == 0;
+            let path = if dir.is_empty() {
+                entry.name
+            } else {
+                format!("{}/{}", dir, entry.name)
+            };
+            let depth = self.stack.len() - 1;
+            let stats = entry.stats;
+            if stats.is_directory() && self.max_depth.is_none_or(|max| depth < max) {
+                self.push_dir(path.clone(), stats.ino).await?;
+            }
+            return Ok(Some(WalkEntry {
+                path,
+                stats,
+                depth,
+                is_last,
+            }));
+        }
+        Ok(None)
+    }
+
+    async fn push_dir(&mut self, path: String, ino: i64) -> AnyhowResult<()> {
+        let mut entries = self.fs.readdir_plus(ino).await?.unwrap_or_default();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        self.stack.push((path, entries.into_iter()));
+        Ok(())
+    }
+}
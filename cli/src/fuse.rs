@@ -1,15 +1,18 @@
 use crate::fuser::{
     consts::{
-        FUSE_ASYNC_READ, FUSE_CACHE_SYMLINKS, FUSE_NO_OPENDIR_SUPPORT, FUSE_PARALLEL_DIROPS,
-        FUSE_WRITEBACK_CACHE,
+        FOPEN_KEEP_CACHE, FUSE_ASYNC_READ, FUSE_CACHE_SYMLINKS, FUSE_NO_OPENDIR_SUPPORT,
+        FUSE_PARALLEL_DIROPS, FUSE_WRITEBACK_CACHE,
     },
     fuse_forget_one, FileAttr, FileType, Filesystem, KernelConfig, MountOption, ReplyAttr,
     ReplyCreate, ReplyData, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyOpen,
-    ReplyStatfs, ReplyWrite, Request,
+    ReplyStatfs, ReplyWrite, ReplyXattr, Request,
 };
+use crate::mount::FuseCacheOptions;
 use agentfs_sdk::error::Error as SdkError;
-use agentfs_sdk::filesystem::{S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFSOCK};
-use agentfs_sdk::{BoxedFile, FileSystem, Stats, TimeChange};
+use agentfs_sdk::filesystem::{
+    encode_xattr_names, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFSOCK,
+};
+use agentfs_sdk::{BoxedFile, FileSystem, FilesystemStats, Stats, TimeChange};
 use parking_lot::Mutex;
 use std::{
     collections::HashMap,
@@ -26,18 +29,10 @@ use tracing;
 
 /// Convert an SDK error to an errno code for FUSE replies.
 ///
-/// If the error is a filesystem-specific FsError, returns the appropriate
-/// errno code (ENOENT, EEXIST, ENOTDIR, etc.). Database busy errors and
-/// connection pool timeouts return EAGAIN to signal the caller should retry.
-/// Otherwise falls back to EIO.
+/// See [`SdkError::to_errno`]: busy databases and connection pool timeouts
+/// return EAGAIN to signal the caller should retry.
 fn error_to_errno(e: &SdkError) -> i32 {
-    match e {
-        SdkError::Fs(fs_err) => fs_err.to_errno(),
-        SdkError::Io(io_err) => io_err.raw_os_error().unwrap_or(libc::EIO),
-        SdkError::Database(turso::Error::Busy(_)) => libc::EAGAIN,
-        SdkError::ConnectionPoolTimeout => libc::EAGAIN,
-        _ => libc::EIO,
-    }
+    e.to_errno()
 }
 
 /// Maximize the file descriptor limit by raising the soft limit to the hard limit.
@@ -68,9 +63,10 @@ fn maximize_fd_limit() {
     }
 }
 
-/// Cache entries never expire — we use deferred kernel cache invalidation
-/// (via Notifier::inval_entry) after mutations to keep the dcache consistent.
-/// This is safe because we are the only writer to the filesystem.
+/// Cache entries never expire by default — we use deferred kernel cache
+/// invalidation (via Notifier::inval_entry) after mutations to keep the
+/// dcache consistent. This is safe because we are the only writer to the
+/// filesystem.
 const TTL: Duration = Duration::MAX;
 
 /// Options for mounting an agent filesystem via FUSE.
@@ -91,6 +87,14 @@ pub struct FuseMountOptions {
     pub uid: Option<u32>,
     /// Group ID to report for all files (defaults to current group).
     pub gid: Option<u32>,
+    /// Ask the kernel to mount the filesystem read-only.
+    pub read_only: bool,
+    /// Have the kernel check the stored mode, uid and gid before every
+    /// access (`default_permissions`). Without it the filesystem does no
+    /// checks of its own, so anyone who can reach the mount can do anything.
+    pub enforce_permissions: bool,
+    /// Kernel caching parameters.
+    pub cache: FuseCacheOptions,
 }
 
 /// Tracks an open file handle
@@ -106,6 +110,14 @@ struct AgentFSFuse {
     open_files: Arc<Mutex<HashMap<u64, OpenFile>>>,
     /// Next file handle to allocate
     next_fh: AtomicU64,
+    /// How long the kernel may cache name lookups
+    entry_ttl: Duration,
+    /// How long the kernel may cache file attributes
+    attr_ttl: Duration,
+    /// Largest readahead to negotiate, if limited
+    max_readahead: Option<u32>,
+    /// Flags for open replies
+    open_flags: u32,
 }
 
 impl Filesystem for AgentFSFuse {
@@ -130,9 +142,32 @@ impl Filesystem for AgentFSFuse {
                 | FUSE_CACHE_SYMLINKS
                 | FUSE_NO_OPENDIR_SUPPORT,
         );
+        if let Some(max_readahead) = self.max_readahead {
+            if let Err(max) = config.set_max_readahead(max_readahead) {
+                tracing::warn!(
+                    "max_readahead {} exceeds the kernel limit, using {}",
+                    max_readahead,
+                    max
+                );
+                let _ = config.set_max_readahead(max);
+            }
+        }
         Ok(())
     }
 
+    /// Checkpoints the filesystem when it is unmounted.
+    ///
+    /// The kernel has flushed all dirty pages by the time this is called, so
+    /// after the checkpoint the database file alone holds every write made
+    /// through the mount.
+    fn destroy(&mut self) {
+        tracing::debug!("FUSE::destroy");
+        let fs = self.fs.clone();
+        if let Err(e) = self.runtime.block_on(async move { fs.sync_all().await }) {
+            tracing::error!("Failed to sync filesystem on unmount: {}", e);
+        }
+    }
+
     // ─────────────────────────────────────────────────────────────
     // Name Resolution & Attributes
     // ─────────────────────────────────────────────────────────────
@@ -157,7 +192,9 @@ impl Filesystem for AgentFSFuse {
         match result {
             Ok(Some(stats)) => {
                 let attr = fillattr(&stats);
-                reply.entry(&TTL, &attr, 0);
+                // Inode numbers are never reused while mounted, so every
+                // entry can have generation 0
+                reply.entry(&self.entry_ttl, &attr, 0);
             }
             Ok(None) => reply.error(libc::ENOENT),
             Err(e) => reply.error(error_to_errno(&e)),
@@ -177,7 +214,7 @@ impl Filesystem for AgentFSFuse {
             .block_on(async move { fs.getattr(ino as i64).await });
 
         match result {
-            Ok(Some(stats)) => reply.attr(&TTL, &fillattr(&stats)),
+            Ok(Some(stats)) => reply.attr(&self.attr_ttl, &fillattr(&stats)),
             Ok(None) => reply.error(libc::ENOENT),
             Err(e) => reply.error(error_to_errno(&e)),
         }
@@ -202,6 +239,26 @@ impl Filesystem for AgentFSFuse {
         }
     }
 
+    /// Checks whether the calling process may access an inode.
+    ///
+    /// Evaluated against the stored owner and mode bits for the request's
+    /// uid and gid; replies `EACCES` when the mask is not satisfied.
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        tracing::debug!("FUSE::access: ino={}, mask={}", ino, mask);
+
+        let fs = self.fs.clone();
+        let (uid, gid) = (req.uid(), req.gid());
+        let result = self
+            .runtime
+            .block_on(async move { fs.access(ino as i64, mask, uid, gid).await });
+
+        match result {
+            Ok(true) => reply.ok(),
+            Ok(false) => reply.error(libc::EACCES),
+            Err(e) => reply.error(error_to_errno(&e)),
+        }
+    }
+
     /// Sets file attributes, handling truncate and chmod operations.
     ///
     /// Currently `size` changes (truncate) and `mode` changes (chmod) are supported.
@@ -325,12 +382,115 @@ impl Filesystem for AgentFSFuse {
             .block_on(async move { fs.getattr(ino as i64).await });
 
         match result {
-            Ok(Some(stats)) => reply.attr(&TTL, &fillattr(&stats)),
+            Ok(Some(stats)) => reply.attr(&self.attr_ttl, &fillattr(&stats)),
             Ok(None) => reply.error(libc::ENOENT),
             Err(e) => reply.error(error_to_errno(&e)),
         }
     }
 
+    // ─────────────────────────────────────────────────────────────
+    // Extended Attributes
+    // ─────────────────────────────────────────────────────────────
+
+    /// Sets an extended attribute.
+    ///
+    /// `flags` carries `XATTR_CREATE`/`XATTR_REPLACE` from `setxattr(2)`.
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        tracing::debug!("FUSE::setxattr: ino={}, name={:?}", ino, name);
+
+        let Some(name_str) = name.to_str() else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+
+        let fs = self.fs.clone();
+        let name_owned = name_str.to_string();
+        let value = value.to_vec();
+        let result = self
+            .runtime
+            .block_on(async move { fs.setxattr(ino as i64, &name_owned, &value, flags).await });
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(error_to_errno(&e)),
+        }
+    }
+
+    /// Gets an extended attribute.
+    ///
+    /// A `size` of zero asks for the value length only; a non-zero `size`
+    /// smaller than the value yields `ERANGE`.
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        tracing::debug!(
+            "FUSE::getxattr: ino={}, name={:?}, size={}",
+            ino,
+            name,
+            size
+        );
+
+        let Some(name_str) = name.to_str() else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+
+        let fs = self.fs.clone();
+        let name_owned = name_str.to_string();
+        let result = self
+            .runtime
+            .block_on(async move { fs.getxattr(ino as i64, &name_owned).await });
+
+        match result {
+            Ok(Some(value)) => reply_xattr(reply, size, &value),
+            Ok(None) => reply.error(libc::ENODATA),
+            Err(e) => reply.error(error_to_errno(&e)),
+        }
+    }
+
+    /// Lists extended attribute names as a NUL-separated buffer.
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        tracing::debug!("FUSE::listxattr: ino={}, size={}", ino, size);
+
+        let fs = self.fs.clone();
+        let result = self
+            .runtime
+            .block_on(async move { fs.listxattr(ino as i64).await });
+
+        match result {
+            Ok(names) => reply_xattr(reply, size, &encode_xattr_names(&names)),
+            Err(e) => reply.error(error_to_errno(&e)),
+        }
+    }
+
+    /// Removes an extended attribute.
+    fn removexattr(&mut self, _req: &Request, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        tracing::debug!("FUSE::removexattr: ino={}, name={:?}", ino, name);
+
+        let Some(name_str) = name.to_str() else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+
+        let fs = self.fs.clone();
+        let name_owned = name_str.to_string();
+        let result = self
+            .runtime
+            .block_on(async move { fs.removexattr(ino as i64, &name_owned).await });
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(error_to_errno(&e)),
+        }
+    }
+
     // ─────────────────────────────────────────────────────────────
     // Directory Operations
     // ─────────────────────────────────────────────────────────────
@@ -458,14 +618,16 @@ impl Filesystem for AgentFSFuse {
             (1u64, parent_stats)
         };
 
-        // Build the entries list with full attributes
+        // Build the entries list with full attributes; each entry carries a
+        // single lifetime for both its name and its attributes
+        let ttl = self.entry_ttl.min(self.attr_ttl);
         let mut offset_counter = 0i64;
 
         // Add "." entry
         if offset <= offset_counter {
             if let Some(ref stats) = dir_stats {
                 let attr = fillattr(stats);
-                if reply.add(ino, offset_counter + 1, ".", &TTL, &attr, 0) {
+                if reply.add(ino, offset_counter + 1, ".", &ttl, &attr, 0) {
                     reply.ok();
                     return;
                 }
@@ -477,7 +639,7 @@ impl Filesystem for AgentFSFuse {
         if offset <= offset_counter {
             if let Some(ref stats) = parent_stats {
                 let attr = fillattr(stats);
-                if reply.add(parent_ino, offset_counter + 1, "..", &TTL, &attr, 0) {
+                if reply.add(parent_ino, offset_counter + 1, "..", &ttl, &attr, 0) {
                     reply.ok();
                     return;
                 }
@@ -494,7 +656,7 @@ impl Filesystem for AgentFSFuse {
                     entry.stats.ino as u64,
                     offset_counter + 1,
                     &entry.name,
-                    &TTL,
+                    &ttl,
                     &attr,
                     0,
                 ) {
@@ -508,6 +670,24 @@ impl Filesystem for AgentFSFuse {
         reply.ok();
     }
 
+    /// Makes the entries of a directory durable.
+    ///
+    /// Applications call `fsync` on a directory after creating, renaming or
+    /// removing entries in it to make those changes survive a crash.
+    fn fsyncdir(&mut self, _req: &Request, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        tracing::debug!("FUSE::fsyncdir: ino={}, fh={}", ino, fh);
+
+        let fs = self.fs.clone();
+        let result = self
+            .runtime
+            .block_on(async move { fs.fsync_dir(ino as i64).await });
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(error_to_errno(&e)),
+        }
+    }
+
     /// Creates a special file node (FIFO, device, socket, or regular file).
     ///
     /// Creates a file node at `name` under `parent` with the specified mode
@@ -547,7 +727,7 @@ impl Filesystem for AgentFSFuse {
         match result {
             Ok(stats) => {
                 let attr = fillattr(&stats);
-                reply.entry(&TTL, &attr, 0);
+                reply.entry(&self.entry_ttl, &attr, 0);
             }
             Err(e) => {
                 reply.error(error_to_errno(&e));
@@ -591,7 +771,7 @@ impl Filesystem for AgentFSFuse {
         match result {
             Ok(stats) => {
                 let attr = fillattr(&stats);
-                reply.entry(&TTL, &attr, 0);
+                reply.entry(&self.entry_ttl, &attr, 0);
             }
             Err(e) => {
                 reply.error(error_to_errno(&e));
@@ -673,7 +853,7 @@ impl Filesystem for AgentFSFuse {
                 let fh = self.alloc_fh();
                 self.open_files.lock().insert(fh, OpenFile { file });
 
-                reply.created(&TTL, &attr, 0, fh, 0);
+                reply.created(&self.entry_ttl, &attr, 0, fh, self.open_flags);
             }
             Err(e) => {
                 reply.error(error_to_errno(&e));
@@ -722,7 +902,7 @@ impl Filesystem for AgentFSFuse {
         match result {
             Ok(stats) => {
                 let attr = fillattr(&stats);
-                reply.entry(&TTL, &attr, 0);
+                reply.entry(&self.entry_ttl, &attr, 0);
             }
             Err(e) => {
                 reply.error(error_to_errno(&e));
@@ -763,7 +943,7 @@ impl Filesystem for AgentFSFuse {
         match result {
             Ok(stats) => {
                 let attr = fillattr(&stats);
-                reply.entry(&TTL, &attr, 0);
+                reply.entry(&self.entry_ttl, &attr, 0);
             }
             Err(e) => {
                 reply.error(error_to_errno(&e));
@@ -799,7 +979,8 @@ impl Filesystem for AgentFSFuse {
 
     /// Renames a file or directory.
     ///
-    /// Moves `name` from `parent` to `newname` under `newparent`.
+    /// Moves `name` from `parent` to `newname` under `newparent`, honoring
+    /// `RENAME_NOREPLACE` and `RENAME_EXCHANGE` from `renameat2(2)`.
     fn rename(
         &mut self,
         req: &Request,
@@ -807,15 +988,16 @@ impl Filesystem for AgentFSFuse {
         name: &OsStr,
         newparent: u64,
         newname: &OsStr,
-        _flags: u32,
+        flags: u32,
         reply: ReplyEmpty,
     ) {
         tracing::debug!(
-            "FUSE::rename: parent={}, name={:?}, newparent={}, newname={:?}",
+            "FUSE::rename: parent={}, name={:?}, newparent={}, newname={:?}, flags={:#x}",
             parent,
             name,
             newparent,
-            newname
+            newname,
+            flags
         );
 
         let Some(old_name_str) = name.to_str() else {
@@ -837,6 +1019,7 @@ impl Filesystem for AgentFSFuse {
                 &old_name_owned,
                 newparent as i64,
                 &new_name_owned,
+                flags,
             )
             .await
         });
@@ -859,9 +1042,13 @@ impl Filesystem for AgentFSFuse {
     /// Opens a file for reading or writing.
     ///
     /// Allocates a file handle and opens the file in the filesystem layer.
+    ///
+    /// `O_APPEND` is not passed down: with writeback caching the kernel
+    /// positions appends itself and flushes whole pages at their real offsets.
     fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
         tracing::debug!("FUSE::open: ino={}, flags={}", ino, flags);
 
+        let flags = flags & !libc::O_APPEND;
         let fs = self.fs.clone();
         let result = self
             .runtime
@@ -871,7 +1058,7 @@ impl Filesystem for AgentFSFuse {
             Ok(file) => {
                 let fh = self.alloc_fh();
                 self.open_files.lock().insert(fh, OpenFile { file });
-                reply.opened(fh, 0);
+                reply.opened(fh, self.open_flags);
             }
             Err(e) => reply.error(error_to_errno(&e)),
         }
@@ -951,14 +1138,26 @@ impl Filesystem for AgentFSFuse {
 
     /// Flushes data to the backend storage.
     ///
-    /// Since writes go directly to the database, this is a no-op.
+    /// Stores any writes the file handle buffered, so that they are not lost
+    /// once the file is closed.
     fn flush(&mut self, _req: &Request, _ino: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
         tracing::debug!("FUSE::flush: fh={}", fh);
-        let open_files = self.open_files.lock();
-        if open_files.contains_key(&fh) {
-            reply.ok();
-        } else {
-            reply.error(libc::EBADF);
+        let file = {
+            let open_files = self.open_files.lock();
+            match open_files.get(&fh) {
+                Some(open_file) => open_file.file.clone(),
+                None => {
+                    reply.error(libc::EBADF);
+                    return;
+                }
+            }
+        };
+
+        let result = self.runtime.block_on(async move { file.flush().await });
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(error_to_errno(&e)),
         }
     }
 
@@ -987,6 +1186,50 @@ impl Filesystem for AgentFSFuse {
         }
     }
 
+    /// Preallocates storage for a byte range of an open file.
+    ///
+    /// Only the default mode and `FALLOC_FL_KEEP_SIZE` are supported; other
+    /// modes (punch hole, collapse range, ...) reply `EOPNOTSUPP`.
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        tracing::debug!(
+            "FUSE::fallocate: ino={}, fh={}, offset={}, length={}, mode={:#x}",
+            ino,
+            fh,
+            offset,
+            length,
+            mode
+        );
+
+        if offset < 0 || length <= 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        if !self.open_files.lock().contains_key(&fh) {
+            reply.error(libc::EBADF);
+            return;
+        }
+
+        let fs = self.fs.clone();
+        let result = self.runtime.block_on(async move {
+            fs.fallocate(ino as i64, offset as u64, length as u64, mode)
+                .await
+        });
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(error_to_errno(&e)),
+        }
+    }
+
     /// Releases (closes) an open file handle.
     ///
     /// Removes the file handle from the open files table.
@@ -1008,38 +1251,42 @@ impl Filesystem for AgentFSFuse {
 
     /// Returns filesystem statistics.
     ///
-    /// Queries actual usage from the SDK and reports it to tools like `df`.
+    /// Queries usage and capacity from the SDK and reports them to tools
+    /// like `df`.
     fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
         tracing::debug!("FUSE::statfs");
-        const BLOCK_SIZE: u64 = 4096;
-        const TOTAL_INODES: u64 = 1_000_000; // Virtual limit
         const MAX_NAMELEN: u32 = 255;
 
         let fs = self.fs.clone();
         let result = self.runtime.block_on(async move { fs.statfs().await });
 
-        let (used_blocks, used_inodes) = match result {
-            Ok(stats) => {
-                let used_blocks = stats.bytes_used.div_ceil(BLOCK_SIZE);
-                (used_blocks, stats.inodes)
+        let stats = result.unwrap_or_else(|_| {
+            // Fallback: just the root inode on a large virtual volume
+            const BLOCK_SIZE: u64 = 4096;
+            FilesystemStats {
+                inodes: 1,
+                bytes_used: 0,
+                total_bytes: (1 << 30) * BLOCK_SIZE,
+                free_bytes: (1 << 30) * BLOCK_SIZE,
+                total_inodes: 1_000_000,
+                block_size: BLOCK_SIZE as u32,
             }
-            Err(_) => (0, 1), // Fallback: just root inode
-        };
+        });
 
-        // Report a large virtual capacity so tools don't think we're out of space
-        const TOTAL_BLOCKS: u64 = 1024 * 1024 * 1024; // ~4TB virtual size
-        let free_blocks = TOTAL_BLOCKS.saturating_sub(used_blocks);
-        let free_inodes = TOTAL_INODES.saturating_sub(used_inodes);
+        let block_size = u64::from(stats.block_size.max(1));
+        let total_blocks = stats.total_bytes / block_size;
+        let free_blocks = stats.free_bytes / block_size;
+        let free_inodes = stats.total_inodes.saturating_sub(stats.inodes);
 
         reply.statfs(
-            TOTAL_BLOCKS,
+            total_blocks,
             free_blocks,
             free_blocks,
-            TOTAL_INODES,
+            stats.total_inodes,
             free_inodes,
-            BLOCK_SIZE as u32,
-            MAX_NAMELEN,       // namelen: maximum filename length
-            BLOCK_SIZE as u32, // frsize: fragment size
+            stats.block_size,
+            MAX_NAMELEN,      // namelen: maximum filename length
+            stats.block_size, // frsize: fragment size
         );
     }
 
@@ -1081,12 +1328,20 @@ impl AgentFSFuse {
     ///
     /// The provided Tokio runtime is used to execute async FileSystem operations
     /// from within synchronous FUSE callbacks via `block_on`.
-    fn new(fs: Arc<dyn FileSystem>, runtime: Runtime) -> Self {
+    fn new(fs: Arc<dyn FileSystem>, runtime: Runtime, cache: FuseCacheOptions) -> Self {
         Self {
             fs,
             runtime,
             open_files: Arc::new(Mutex::new(HashMap::new())),
             next_fh: AtomicU64::new(1),
+            entry_ttl: cache.entry_timeout.unwrap_or(TTL),
+            attr_ttl: cache.attr_timeout.unwrap_or(TTL),
+            max_readahead: cache.max_readahead,
+            open_flags: if cache.kernel_cache {
+                FOPEN_KEEP_CACHE
+            } else {
+                0
+            },
         }
     }
 
@@ -1099,6 +1354,20 @@ impl AgentFSFuse {
     }
 }
 
+/// Reply to a getxattr/listxattr request following the size-probe protocol.
+///
+/// The kernel first asks with `size == 0` to learn the buffer length, then
+/// asks again with a buffer that must be large enough to hold `data`.
+fn reply_xattr(reply: ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(data);
+    }
+}
+
 // ─────────────────────────────────────────────────────────────
 // Attribute Conversion
 // ─────────────────────────────────────────────────────────────
@@ -1122,16 +1391,16 @@ fn fillattr(stats: &Stats) -> FileAttr {
         _ => FileType::RegularFile,
     };
 
-    let size = if file_type == S_IFDIR {
-        4096_u64 // Standard directory size
+    let (size, blocks) = if file_type == S_IFDIR {
+        (4096_u64, 8) // Standard directory size
     } else {
-        stats.size as u64
+        (stats.size as u64, stats.blocks)
     };
 
     FileAttr {
         ino: stats.ino as u64,
         size,
-        blocks: size.div_ceil(512),
+        blocks,
         atime: UNIX_EPOCH + Duration::new(stats.atime as u64, stats.atime_nsec),
         mtime: UNIX_EPOCH + Duration::new(stats.mtime as u64, stats.mtime_nsec),
         ctime: UNIX_EPOCH + Duration::new(stats.ctime as u64, stats.ctime_nsec),
@@ -1183,13 +1452,14 @@ pub fn mount(
     // when passthrough filesystems cache O_PATH file descriptors
     maximize_fd_limit();
 
-    let fs = AgentFSFuse::new(fs, runtime);
+    let fs = AgentFSFuse::new(fs, runtime, opts.cache);
 
-    let mut mount_opts = vec![
-        MountOption::FSName(opts.fsname),
-        // Enable kernel-level permission checking based on file mode/uid/gid
-        MountOption::DefaultPermissions,
-    ];
+    let mut mount_opts = vec![MountOption::FSName(opts.fsname)];
+
+    // Enable kernel-level permission checking based on file mode/uid/gid
+    if opts.enforce_permissions {
+        mount_opts.push(MountOption::DefaultPermissions);
+    }
 
     // Allow users other than the one who mounted the filesystem to access it.
     // This requires either running as root or having user_allow_other enabled
@@ -1210,6 +1480,9 @@ pub fn mount(
     if opts.allow_root {
         mount_opts.push(MountOption::AllowRoot);
     }
+    if opts.read_only {
+        mount_opts.push(MountOption::RO);
+    }
 
     crate::fuser::mount2(fs, &opts.mountpoint, &mount_opts)?;
 
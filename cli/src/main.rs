@@ -1,10 +1,11 @@
 use agentfs::{
     cmd::{self, completions::handle_completions},
     get_runtime,
-    opts::{Args, Command, FsCommand, PruneCommand, ServeCommand, SyncCommand},
+    opts::{Args, Command, FsCommand, PruneCommand, ServeCommand, SyncCommand, TrashCommand},
 };
 use clap::{CommandFactory, Parser};
 use clap_complete::CompleteEnv;
+use std::io::Read;
 use tracing_subscriber::prelude::*;
 
 /// Parse and validate encryption key and cipher options.
@@ -43,8 +44,20 @@ fn main() {
             id,
             force,
             base,
+            seed,
             key,
             cipher,
+            max_size,
+            chunk_size,
+            compress,
+            dedup,
+            uid,
+            gid,
+            umask,
+            atime,
+            case_insensitive,
+            trash,
+            trash_retention,
             command,
             backend,
             sync,
@@ -57,7 +70,19 @@ fn main() {
                 sync,
                 force,
                 base,
+                seed,
                 encryption_opts,
+                max_size,
+                chunk_size,
+                compress,
+                dedup,
+                uid,
+                gid,
+                umask,
+                atime,
+                case_insensitive,
+                trash,
+                trash_retention,
                 command,
                 backend,
             )) {
@@ -107,13 +132,18 @@ fn main() {
             experimental_sandbox,
             strace,
             session,
+            from,
             system,
             key,
             cipher,
+            memory,
+            cpu,
+            pids,
             command,
             args,
         } => {
             let encryption = parse_encryption(key, cipher);
+            let limits = cmd::ResourceLimits { memory, cpu, pids };
             let command = command.unwrap_or_else(default_shell);
             let rt = get_runtime();
             if let Err(e) = rt.block_on(cmd::handle_run_command(
@@ -122,8 +152,10 @@ fn main() {
                 experimental_sandbox,
                 strace,
                 session,
+                from,
                 system,
                 encryption,
+                limits,
                 command,
                 args,
             )) {
@@ -159,6 +191,13 @@ fn main() {
             uid,
             gid,
             backend,
+            read_only,
+            enforce_permissions,
+            volume_name,
+            max_readahead,
+            entry_timeout,
+            attr_timeout,
+            kernel_cache,
         } => match (id_or_path, mountpoint) {
             (Some(id_or_path), Some(mountpoint)) => {
                 if let Err(e) = cmd::mount(cmd::MountArgs {
@@ -171,6 +210,13 @@ fn main() {
                     uid,
                     gid,
                     backend,
+                    read_only,
+                    enforce_permissions,
+                    volume_name,
+                    max_readahead,
+                    entry_timeout,
+                    attr_timeout,
+                    kernel_cache,
                 }) {
                     eprintln!("Error: {}", e);
                     std::process::exit(1);
@@ -184,9 +230,56 @@ fn main() {
                 std::process::exit(1);
             }
         },
-        Command::Diff { id_or_path } => {
+        #[cfg(unix)]
+        Command::Umount {
+            id_or_mountpoint,
+            force,
+        } => {
+            if let Err(e) = cmd::umount::handle_umount_command(id_or_mountpoint, force) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(unix)]
+        Command::Doctor { mountpoint } => {
+            if let Err(e) = cmd::doctor::handle_doctor_command(mountpoint.as_deref()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Diff {
+            id_or_path,
+            name_only,
+        } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::fs::diff_filesystem(
+                &mut std::io::stdout(),
+                id_or_path,
+                name_only,
+            )) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Tree {
+            id_or_path,
+            path,
+            level,
+            size,
+            inodes,
+        } => {
             let rt = get_runtime();
-            if let Err(e) = rt.block_on(cmd::fs::diff_filesystem(id_or_path)) {
+            let options = cmd::tree::TreeOptions {
+                level,
+                size,
+                inodes,
+            };
+            if let Err(e) = rt.block_on(cmd::tree::handle_tree_command(
+                &mut std::io::stdout(),
+                id_or_path,
+                path,
+                options,
+            )) {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
@@ -223,11 +316,12 @@ fn main() {
             let encryption = parse_encryption(key, cipher);
             let rt = get_runtime();
             match command {
-                FsCommand::Ls { fs_path } => {
+                FsCommand::Ls { fs_path, long } => {
                     if let Err(e) = rt.block_on(cmd::fs::ls_filesystem(
                         &mut std::io::stdout(),
                         id_or_path,
                         &fs_path,
+                        long,
                         encryption.as_ref(),
                     )) {
                         eprintln!("Error: {}", e);
@@ -246,6 +340,17 @@ fn main() {
                     }
                 }
                 FsCommand::Write { file_path, content } => {
+                    let content = match content {
+                        Some(content) => content.into_bytes(),
+                        None => {
+                            let mut buf = Vec::new();
+                            if let Err(e) = std::io::stdin().read_to_end(&mut buf) {
+                                eprintln!("Error: Failed to read stdin: {}", e);
+                                std::process::exit(1);
+                            }
+                            buf
+                        }
+                    };
                     if let Err(e) = rt.block_on(cmd::fs::write_filesystem(
                         id_or_path,
                         &file_path,
@@ -256,6 +361,79 @@ fn main() {
                         std::process::exit(1);
                     }
                 }
+                FsCommand::Rm { fs_path, recursive } => {
+                    if let Err(e) = rt.block_on(cmd::fs::rm_filesystem(
+                        id_or_path,
+                        &fs_path,
+                        recursive,
+                        encryption.as_ref(),
+                    )) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                FsCommand::Mkdir { fs_path, parents } => {
+                    if let Err(e) = rt.block_on(cmd::fs::mkdir_filesystem(
+                        id_or_path,
+                        &fs_path,
+                        parents,
+                        encryption.as_ref(),
+                    )) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                FsCommand::Stat { fs_path } => {
+                    if let Err(e) = rt.block_on(cmd::fs::stat_filesystem(
+                        &mut std::io::stdout(),
+                        id_or_path,
+                        &fs_path,
+                        encryption.as_ref(),
+                    )) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        Command::Export {
+            id_or_path,
+            output,
+            since,
+        } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::archive::handle_export_command(
+                id_or_path, output, since,
+            )) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Import {
+            id_or_path,
+            input,
+            overwrite,
+        } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::archive::handle_import_command(
+                id_or_path, input, overwrite,
+            )) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Cp {
+            source,
+            dest,
+            key,
+            cipher,
+        } => {
+            let encryption = parse_encryption(key, cipher);
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::fs::cp_filesystem(&source, &dest, encryption.as_ref()))
+            {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
             }
         }
         Command::Completions { command } => handle_completions(command),
@@ -306,9 +484,35 @@ fn main() {
                     std::process::exit(1);
                 }
             }
+            #[cfg(unix)]
+            ServeCommand::Remote { id_or_path, listen } => {
+                let rt = get_runtime();
+                if let Err(e) = rt.block_on(cmd::remote::handle_remote_command(id_or_path, listen))
+                {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
         },
-        Command::Ps => {
-            if let Err(e) = cmd::ps::list_ps(&mut std::io::stdout()) {
+        Command::Ps {
+            format,
+            prune,
+            bytes,
+            total,
+        } => {
+            if prune {
+                // Keep JSON output parseable
+                let result = if format == "json" {
+                    cmd::ps::prune_sessions(&mut std::io::stderr())
+                } else {
+                    cmd::ps::prune_sessions(&mut std::io::stdout())
+                };
+                if let Err(e) = result {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            if let Err(e) = cmd::ps::list_ps(&mut std::io::stdout(), &format, bytes, total) {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
@@ -321,6 +525,80 @@ fn main() {
                 }
             }
         },
+        Command::Snapshot { id_or_path, label } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::snapshot::handle_snapshot_command(id_or_path, label)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Restore { id_or_path, label } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::snapshot::handle_restore_command(id_or_path, label)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Base { id_or_path, path } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::base::handle_base_command(id_or_path, path)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(unix)]
+        Command::Fsck { id_or_path, repair } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::fsck::handle_fsck_command(id_or_path, repair)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Gc { id_or_path } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::gc::handle_gc_command(id_or_path)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(unix)]
+        Command::Commit {
+            id_or_path,
+            dry_run,
+        } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::commit::handle_commit_command(id_or_path, dry_run)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Flatten { id_or_path, new_id } => {
+            let rt = get_runtime();
+            if let Err(e) = rt.block_on(cmd::flatten::handle_flatten_command(id_or_path, new_id)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Trash { command } => {
+            let rt = get_runtime();
+            let result = match command {
+                TrashCommand::List { id_or_path } => rt.block_on(cmd::trash::handle_list_command(
+                    &mut std::io::stdout(),
+                    id_or_path,
+                )),
+                TrashCommand::Restore {
+                    id_or_path,
+                    fs_path,
+                } => rt.block_on(cmd::trash::handle_restore_command(id_or_path, fs_path)),
+                TrashCommand::Empty { id_or_path } => {
+                    rt.block_on(cmd::trash::handle_empty_command(id_or_path))
+                }
+            };
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
         Command::Migrate {
             id_or_path,
             dry_run,
@@ -48,6 +48,10 @@ pub(super) fn mount_fuse(
         fsname: opts.fsname.clone(),
         uid: opts.uid,
         gid: opts.gid,
+        read_only: false,
+        // Mounts made through this API keep the kernel's permission checks
+        enforce_permissions: true,
+        cache: Default::default(),
     };
 
     let mountpoint = opts.mountpoint.clone();
@@ -119,6 +123,13 @@ impl agentfs_sdk::FileSystem for MutexFsAdapter {
         self.inner.lock().await.readdir_plus(ino).await
     }
 
+    async fn readdir_stream(
+        &self,
+        ino: i64,
+    ) -> std::result::Result<Option<agentfs_sdk::BoxedDirStream>, agentfs_sdk::error::Error> {
+        self.inner.lock().await.readdir_stream(ino).await
+    }
+
     async fn chmod(
         &self,
         ino: i64,
@@ -145,6 +156,53 @@ impl agentfs_sdk::FileSystem for MutexFsAdapter {
         self.inner.lock().await.utimens(ino, atime, mtime).await
     }
 
+    async fn access(
+        &self,
+        ino: i64,
+        mask: i32,
+        uid: u32,
+        gid: u32,
+    ) -> std::result::Result<bool, agentfs_sdk::error::Error> {
+        self.inner.lock().await.access(ino, mask, uid, gid).await
+    }
+
+    async fn getxattr(
+        &self,
+        ino: i64,
+        name: &str,
+    ) -> std::result::Result<Option<Vec<u8>>, agentfs_sdk::error::Error> {
+        self.inner.lock().await.getxattr(ino, name).await
+    }
+
+    async fn setxattr(
+        &self,
+        ino: i64,
+        name: &str,
+        value: &[u8],
+        flags: i32,
+    ) -> std::result::Result<(), agentfs_sdk::error::Error> {
+        self.inner
+            .lock()
+            .await
+            .setxattr(ino, name, value, flags)
+            .await
+    }
+
+    async fn listxattr(
+        &self,
+        ino: i64,
+    ) -> std::result::Result<Vec<String>, agentfs_sdk::error::Error> {
+        self.inner.lock().await.listxattr(ino).await
+    }
+
+    async fn removexattr(
+        &self,
+        ino: i64,
+        name: &str,
+    ) -> std::result::Result<(), agentfs_sdk::error::Error> {
+        self.inner.lock().await.removexattr(ino, name).await
+    }
+
     async fn open(
         &self,
         ino: i64,
@@ -250,17 +308,47 @@ impl agentfs_sdk::FileSystem for MutexFsAdapter {
         oldname: &str,
         newparent_ino: i64,
         newname: &str,
+        flags: u32,
+    ) -> std::result::Result<(), agentfs_sdk::error::Error> {
+        self.inner
+            .lock()
+            .await
+            .rename(oldparent_ino, oldname, newparent_ino, newname, flags)
+            .await
+    }
+
+    async fn fallocate(
+        &self,
+        ino: i64,
+        offset: u64,
+        len: u64,
+        mode: i32,
     ) -> std::result::Result<(), agentfs_sdk::error::Error> {
         self.inner
             .lock()
             .await
-            .rename(oldparent_ino, oldname, newparent_ino, newname)
+            .fallocate(ino, offset, len, mode)
             .await
     }
 
+    async fn sync_all(&self) -> std::result::Result<(), agentfs_sdk::error::Error> {
+        self.inner.lock().await.sync_all().await
+    }
+
+    async fn fsync_dir(&self, ino: i64) -> std::result::Result<(), agentfs_sdk::error::Error> {
+        self.inner.lock().await.fsync_dir(ino).await
+    }
+
     async fn statfs(
         &self,
     ) -> std::result::Result<agentfs_sdk::FilesystemStats, agentfs_sdk::error::Error> {
         self.inner.lock().await.statfs().await
     }
+
+    async fn check_op(
+        &self,
+        op: &agentfs_sdk::Operation,
+    ) -> std::result::Result<(), agentfs_sdk::error::Error> {
+        self.inner.lock().await.check_op(op).await
+    }
 }
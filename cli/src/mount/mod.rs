@@ -76,6 +76,32 @@ impl MountOpts {
     }
 }
 
+/// Kernel caching parameters for a FUSE mount.
+///
+/// Unset values keep the defaults, which cache entries and attributes for
+/// as long as the kernel likes and invalidate file contents on open.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FuseCacheOptions {
+    /// Largest readahead the kernel may issue, in bytes.
+    pub max_readahead: Option<u32>,
+    /// How long the kernel may cache name lookups.
+    pub entry_timeout: Option<Duration>,
+    /// How long the kernel may cache file attributes.
+    pub attr_timeout: Option<Duration>,
+    /// Keep cached file contents when a file is opened again.
+    pub kernel_cache: bool,
+}
+
+impl FuseCacheOptions {
+    /// Whether any parameter differs from the defaults.
+    pub fn is_set(&self) -> bool {
+        self.max_readahead.is_some()
+            || self.entry_timeout.is_some()
+            || self.attr_timeout.is_some()
+            || self.kernel_cache
+    }
+}
+
 impl Default for MountOpts {
     fn default() -> Self {
         Self::new(PathBuf::new(), MountBackend::default())
@@ -32,8 +32,9 @@ fn id_to_fs_ino(id: fileid3) -> i64 {
 
 /// Convert an SDK error to an NFS status code.
 ///
-/// Connection pool timeouts return NFS3ERR_JUKEBOX to signal the client
-/// should retry the operation later. Other errors map to NFS3ERR_IO.
+/// Connection pool timeouts and a database lock that stayed busy return
+/// NFS3ERR_JUKEBOX to signal the client should retry the operation later.
+/// Other errors map to NFS3ERR_IO.
 fn error_to_nfsstat(e: SdkError) -> nfsstat3 {
     match e {
         SdkError::Fs(ref fs_err) => match fs_err {
@@ -44,6 +45,13 @@ fn error_to_nfsstat(e: SdkError) -> nfsstat3 {
             FsError::IsADirectory => nfsstat3::NFS3ERR_ISDIR,
             FsError::NameTooLong => nfsstat3::NFS3ERR_NAMETOOLONG,
             FsError::RootOperation => nfsstat3::NFS3ERR_ACCES,
+            FsError::PermissionDenied => nfsstat3::NFS3ERR_ACCES,
+            FsError::NotSupported => nfsstat3::NFS3ERR_NOTSUPP,
+            FsError::NotImplemented => nfsstat3::NFS3ERR_NOTSUPP,
+            FsError::NoSpace => nfsstat3::NFS3ERR_NOSPC,
+            FsError::FileTooLarge => nfsstat3::NFS3ERR_FBIG,
+            FsError::ReadOnly => nfsstat3::NFS3ERR_ROFS,
+            FsError::Busy => nfsstat3::NFS3ERR_JUKEBOX,
             _ => nfsstat3::NFS3ERR_IO,
         },
         SdkError::ConnectionPoolTimeout => nfsstat3::NFS3ERR_JUKEBOX,
@@ -89,7 +97,7 @@ impl AgentNFS {
             uid: stats.uid,
             gid: stats.gid,
             size: stats.size as u64,
-            used: stats.size as u64,
+            used: stats.blocks * 512,
             rdev,
             fsid: 0,
             fileid: stats.ino as fileid3,
@@ -241,7 +249,8 @@ impl NFSFileSystem for AgentNFS {
         let file = fs
             .open(id_to_fs_ino(id), O_RDONLY)
             .await
-            .map_err(|_| nfsstat3::NFS3ERR_NOENT)?;
+            .map_err(error_to_nfsstat)?;
+        // A read at or past EOF returns no data rather than an error
         let data = file
             .pread(offset, count as u64)
             .await
@@ -250,7 +259,7 @@ impl NFSFileSystem for AgentNFS {
         // Check if we've reached EOF
         let stats = file.fstat().await.map_err(error_to_nfsstat)?;
 
-        let eof = offset + data.len() as u64 >= stats.size as u64;
+        let eof = offset.saturating_add(data.len() as u64) >= stats.size as u64;
         Ok((data, eof))
     }
 
@@ -444,7 +453,7 @@ impl NFSFileSystem for AgentNFS {
 
         let fs = self.fs.lock().await;
 
-        fs.rename(from_dir_fs_ino, from_name, to_dir_fs_ino, to_name)
+        fs.rename(from_dir_fs_ino, from_name, to_dir_fs_ino, to_name, 0)
             .await
             .map_err(error_to_nfsstat)?;
 
@@ -1,5 +1,5 @@
 use crate::cmd::completions::Shell;
-use agentfs_sdk::agentfs_dir;
+use agentfs_sdk::{agentfs_dir, AtimePolicy, CompressionKind};
 use clap::{Parser, Subcommand};
 use clap_complete::{
     engine::ValueCompleter, ArgValueCompleter, CompletionCandidate, PathCompleter,
@@ -82,6 +82,11 @@ pub enum Command {
         #[arg(long)]
         base: Option<PathBuf>,
 
+        /// Copy the contents of a host directory into the new filesystem,
+        /// skipping paths listed in its .agentfsignore
+        #[arg(long, value_name = "DIR", conflicts_with = "base")]
+        seed: Option<PathBuf>,
+
         /// Hex-encoded encryption key.
         /// Enables local encryption when provided.
         #[arg(long, env = "AGENTFS_KEY")]
@@ -92,6 +97,54 @@ pub enum Command {
         #[arg(long, env = "AGENTFS_CIPHER")]
         cipher: Option<String>,
 
+        /// Maximum total size of files (e.g. 500M, 2G).
+        /// Writes beyond the limit fail with ENOSPC.
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+
+        /// Size of the chunks file contents are stored in (e.g. 4K, 64K).
+        /// Smaller chunks make small writes into large files cheaper.
+        #[arg(long, alias = "block-size", value_parser = parse_size)]
+        chunk_size: Option<u64>,
+
+        /// Compress file contents as they are written.
+        /// Options: zstd
+        #[arg(long, value_name = "ALGORITHM")]
+        compress: Option<CompressionKind>,
+
+        /// Store identical file contents only once
+        #[arg(long)]
+        dedup: bool,
+
+        /// Owner of the root directory (default: the user opening the filesystem)
+        #[arg(long)]
+        uid: Option<u32>,
+
+        /// Group of the root directory (default: the group opening the filesystem)
+        #[arg(long)]
+        gid: Option<u32>,
+
+        /// Octal umask applied to newly created files and directories (e.g. 022)
+        #[arg(long, value_parser = parse_umask)]
+        umask: Option<u32>,
+
+        /// When reads update access times (default: noatime).
+        /// Options: always, relatime, noatime
+        #[arg(long, value_name = "POLICY")]
+        atime: Option<AtimePolicy>,
+
+        /// Look up names ignoring case, as macOS volumes do by default
+        #[arg(long)]
+        case_insensitive: bool,
+
+        /// Move removed entries to a trash they can be restored from
+        #[arg(long)]
+        trash: bool,
+
+        /// Free trashed entries older than this many days on gc (default: keep)
+        #[arg(long, value_name = "DAYS", requires = "trash")]
+        trash_retention: Option<u64>,
+
         /// Command to execute after initialization (mounts the filesystem, runs command, unmounts)
         #[arg(short = 'c', long = "command")]
         command: Option<String>,
@@ -130,6 +183,52 @@ pub enum Command {
         #[command(subcommand)]
         command: FsCommand,
     },
+    /// Back up an agent filesystem to a tar archive
+    Export {
+        /// Agent ID or database path
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Archive to write (`-` for stdout)
+        output: String,
+
+        /// Only include entries changed since this snapshot, with whiteouts
+        /// for deleted ones
+        #[arg(long, value_name = "LABEL")]
+        since: Option<String>,
+    },
+    /// Restore a tar archive into an agent filesystem (must not be mounted)
+    Import {
+        /// Agent ID or database path
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Archive to read (`-` for stdin)
+        input: String,
+
+        /// Merge into a non-empty filesystem, replacing entries with the same path
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Copy files between an agent filesystem and the host without mounting it
+    ///
+    /// Exactly one of SOURCE and DEST is an agent path, written
+    /// `<ID_OR_PATH>:<PATH>`. Directories are copied recursively.
+    Cp {
+        /// Source: a host path or `<ID_OR_PATH>:<PATH>`
+        source: String,
+
+        /// Destination: a host path or `<ID_OR_PATH>:<PATH>`
+        dest: String,
+
+        /// Hex-encoded encryption key for encrypted databases.
+        #[arg(long, env = "AGENTFS_KEY")]
+        key: Option<String>,
+
+        /// Cipher algorithm for encryption (required with --key).
+        #[arg(long, env = "AGENTFS_CIPHER")]
+        cipher: Option<String>,
+    },
     /// Run a command in the sandboxed environment.
     ///
     /// By default, uses FUSE+overlay with Linux user and mount namespaces for isolation.
@@ -159,6 +258,13 @@ pub enum Command {
         #[arg(long = "session", value_name = "ID")]
         session: Option<String>,
 
+        /// Start a new session from the contents of an existing filesystem
+        /// instead of an empty delta. Takes a session ID, agent ID or database
+        /// path, optionally followed by @LABEL to start from a snapshot.
+        /// The source is copied and never modified.
+        #[arg(long = "from", value_name = "SOURCE")]
+        from: Option<String>,
+
         /// Allow other system users to access this mount (requires /etc/fuse.conf
         /// user_allow_other; use cautiously)
         #[arg(long = "system")]
@@ -174,6 +280,21 @@ pub enum Command {
         #[arg(long, env = "AGENTFS_CIPHER")]
         cipher: Option<String>,
 
+        /// Limit the memory the command may use (e.g. 512M, 2G).
+        /// Linux only; requires a delegated cgroup v2 hierarchy.
+        #[arg(long, value_parser = parse_size)]
+        memory: Option<u64>,
+
+        /// Limit the CPU time the command may use, in cores (e.g. 0.5, 2).
+        /// Linux only; requires a delegated cgroup v2 hierarchy.
+        #[arg(long, value_name = "CORES", value_parser = parse_cpus)]
+        cpu: Option<f64>,
+
+        /// Limit the number of processes and threads the command may create.
+        /// Linux only; requires a delegated cgroup v2 hierarchy.
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        pids: Option<u64>,
+
         /// Command to execute (defaults to bash on Linux, zsh on macOS)
         command: Option<PathBuf>,
 
@@ -251,12 +372,85 @@ pub enum Command {
         /// Backend to use for mounting
         #[arg(long, default_value_t = MountBackend::default())]
         backend: MountBackend,
+
+        /// Mount read-only; every modification fails with EROFS
+        #[arg(long)]
+        read_only: bool,
+
+        /// Check stored modes and owners before every access (FUSE only;
+        /// default: the mounting user can do anything)
+        #[arg(long)]
+        enforce_permissions: bool,
+
+        /// Name to show for the mount (defaults to the agent ID or path)
+        #[arg(long = "name", value_name = "NAME")]
+        volume_name: Option<String>,
+
+        /// Largest readahead the kernel may issue, e.g. 1M (FUSE only)
+        #[arg(long, value_name = "SIZE", value_parser = parse_readahead)]
+        max_readahead: Option<u32>,
+
+        /// Seconds the kernel may cache name lookups (FUSE only; default: forever)
+        #[arg(long, value_name = "SECS", value_parser = parse_timeout)]
+        entry_timeout: Option<std::time::Duration>,
+
+        /// Seconds the kernel may cache file attributes (FUSE only; default: forever)
+        #[arg(long, value_name = "SECS", value_parser = parse_timeout)]
+        attr_timeout: Option<std::time::Duration>,
+
+        /// Keep cached file contents across opens (FUSE only)
+        #[arg(long)]
+        kernel_cache: bool,
+    },
+    /// Unmount a mounted agent filesystem
+    #[cfg(unix)]
+    #[command(alias = "unmount")]
+    Umount {
+        /// Agent ID, database path or mountpoint
+        #[arg(value_name = "ID_OR_MOUNTPOINT", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_mountpoint: String,
+
+        /// Detach the filesystem even if it is busy (lazy unmount)
+        #[arg(short = 'f', long)]
+        force: bool,
+    },
+    /// Check that this system can mount agent filesystems
+    #[cfg(unix)]
+    Doctor {
+        /// Also check that this directory can be mounted on
+        mountpoint: Option<PathBuf>,
     },
     /// Show differences between base filesystem and delta (overlay mode only)
     Diff {
         /// Agent ID or database path
         #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
         id_or_path: String,
+
+        /// Print only the paths of changed entries
+        #[arg(long)]
+        name_only: bool,
+    },
+    /// Print the directory hierarchy of an agent filesystem without mounting it
+    Tree {
+        /// Agent ID or database path
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Directory to start from
+        #[arg(default_value = "/")]
+        path: String,
+
+        /// Descend at most this many levels
+        #[arg(short = 'L', long)]
+        level: Option<usize>,
+
+        /// Show file sizes in bytes
+        #[arg(short = 's', long)]
+        size: bool,
+
+        /// Show inode numbers
+        #[arg(long)]
+        inodes: bool,
     },
     /// Display agent action timeline from tool call audit log
     Timeline {
@@ -317,12 +511,97 @@ pub enum Command {
         command: ServeCommand,
     },
     /// List active agentfs run sessions
-    Ps,
+    Ps {
+        /// Output format
+        #[arg(short = 'o', long, default_value = "table", value_parser = ["table", "json"])]
+        format: String,
+        /// Remove the run directories of crashed sessions before listing
+        #[arg(long)]
+        prune: bool,
+        /// Show sizes as raw byte counts instead of KiB/MiB/GiB
+        #[arg(long)]
+        bytes: bool,
+        /// Print a footer with the number of sessions and their total size
+        #[arg(long)]
+        total: bool,
+    },
     /// Prune unused resources
     Prune {
         #[command(subcommand)]
         command: PruneCommand,
     },
+    /// Record a point-in-time snapshot of an agent filesystem
+    Snapshot {
+        /// Agent ID or database path
+        #[arg(add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Snapshot label
+        label: String,
+    },
+    /// Roll an agent filesystem back to a snapshot (must not be mounted)
+    Restore {
+        /// Agent ID or database path
+        #[arg(add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Snapshot label
+        label: String,
+    },
+    /// Flush overlay changes into the base directory and clear the delta
+    /// (must not be mounted)
+    #[cfg(unix)]
+    Commit {
+        /// Agent ID or database path
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Print the planned operations without touching the base
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Copy the merged view of an overlay into a new standalone filesystem
+    #[cfg(unix)]
+    Flatten {
+        /// Agent ID or database path of the overlay
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Agent ID of the new filesystem
+        new_id: String,
+    },
+    /// Show or change the base directory of an overlay
+    Base {
+        /// Agent ID or database path of the overlay
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// New base directory, such as where the base was moved (must not be mounted)
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Check the database for inconsistencies
+    #[cfg(unix)]
+    Fsck {
+        /// Agent ID or database path
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Fix what can be fixed (must not be mounted)
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Remove orphaned data and compact the database (must not be mounted)
+    Gc {
+        /// Agent ID or database path
+        #[arg(add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+    },
+    /// List, restore or empty removed entries kept in trash mode
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
     /// Migrate database schema to the current version
     Migrate {
         /// Agent ID or database path
@@ -337,13 +616,17 @@ pub enum Command {
 
 #[derive(Subcommand, Debug)]
 pub enum FsCommand {
-    /// List files in the filesystem
+    /// List files below a directory, recursively
     Ls {
         /// Path to list (default: /)
         #[arg(default_value = "/")]
         fs_path: String,
+
+        /// Show mode, link count, owner, size and mtime
+        #[arg(short = 'l')]
+        long: bool,
     },
-    /// Display file contents
+    /// Write file contents to stdout
     Cat {
         /// Path to the file in the filesystem
         file_path: String,
@@ -353,8 +636,31 @@ pub enum FsCommand {
         /// Path to the file in the filesystem
         file_path: String,
 
-        /// Content of the file
-        content: String,
+        /// Content of the file (read from stdin if omitted)
+        content: Option<String>,
+    },
+    /// Remove a file or empty directory
+    Rm {
+        /// Path to remove
+        fs_path: String,
+
+        /// Remove directories and their contents
+        #[arg(short = 'r', long)]
+        recursive: bool,
+    },
+    /// Create a directory
+    Mkdir {
+        /// Path of the directory
+        fs_path: String,
+
+        /// Create missing parent directories; succeed if the directory exists
+        #[arg(short = 'p', long)]
+        parents: bool,
+    },
+    /// Show the metadata of a file or directory
+    Stat {
+        /// Path in the filesystem
+        fs_path: String,
     },
 }
 
@@ -400,6 +706,43 @@ pub enum ServeCommand {
         #[arg(long, value_delimiter = ',')]
         tools: Option<Vec<String>>,
     },
+
+    /// Serve an AgentFS filesystem to remote AgentFS clients
+    #[cfg(unix)]
+    Remote {
+        /// Agent ID or database path
+        #[arg(value_name = "ID_OR_PATH", add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:11112")]
+        listen: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TrashCommand {
+    /// List the entries in the trash
+    List {
+        /// Agent ID or database path
+        #[arg(add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+    },
+    /// Put a removed entry back where it was (must not be mounted)
+    Restore {
+        /// Agent ID or database path
+        #[arg(add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+
+        /// Path the entry was removed from
+        fs_path: String,
+    },
+    /// Delete everything in the trash for good
+    Empty {
+        /// Agent ID or database path
+        #[arg(add = ArgValueCompleter::new(id_or_path_completer))]
+        id_or_path: String,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -430,6 +773,67 @@ pub enum CompletionsCommand {
     Show,
 }
 
+/// Parse a byte size with an optional binary suffix (K, M, G, T).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, suffix) = s.split_at(split);
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let shift = match suffix.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("invalid size suffix in '{}'", s)),
+    };
+    let size = value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{}' is too large", s))?;
+    if size == 0 {
+        return Err("size must be greater than zero".to_string());
+    }
+    Ok(size)
+}
+
+/// Parse a positive number of CPU cores, possibly fractional.
+fn parse_cpus(s: &str) -> Result<f64, String> {
+    let cpus: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid number of CPUs '{}'", s))?;
+    if !cpus.is_finite() || cpus <= 0.0 {
+        return Err("number of CPUs must be greater than zero".to_string());
+    }
+    Ok(cpus)
+}
+
+/// Parse a readahead size, which the kernel takes as a 32-bit byte count.
+fn parse_readahead(s: &str) -> Result<u32, String> {
+    let size = parse_size(s)?;
+    u32::try_from(size).map_err(|_| format!("readahead '{}' is too large", s))
+}
+
+/// Parse a cache timeout in seconds, possibly fractional.
+fn parse_timeout(s: &str) -> Result<std::time::Duration, String> {
+    let secs: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid timeout '{}'", s))?;
+    std::time::Duration::try_from_secs_f64(secs)
+        .map_err(|_| "timeout must be a non-negative number of seconds".to_string())
+}
+
+fn parse_umask(s: &str) -> Result<u32, String> {
+    let umask = u32::from_str_radix(s.trim(), 8).map_err(|_| format!("invalid umask '{}'", s))?;
+    if umask > 0o777 {
+        return Err(format!("umask '{}' is out of range", s));
+    }
+    Ok(umask)
+}
+
 fn id_completer(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     let mut completions = vec![];
     let Some(current) = current.to_str() else {
@@ -477,3 +881,60 @@ fn id_or_path_completer(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
 
     completions
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500M"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_size("2g"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1KB"), Ok(1024));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_parse_cpus() {
+        assert_eq!(parse_cpus("2"), Ok(2.0));
+        assert_eq!(parse_cpus("0.5"), Ok(0.5));
+        assert!(parse_cpus("").is_err());
+        assert!(parse_cpus("0").is_err());
+        assert!(parse_cpus("-1").is_err());
+        assert!(parse_cpus("inf").is_err());
+    }
+
+    #[test]
+    fn test_parse_readahead() {
+        assert_eq!(parse_readahead("128K"), Ok(128 * 1024));
+        assert_eq!(parse_readahead("4096"), Ok(4096));
+        assert!(parse_readahead("0").is_err());
+        assert!(parse_readahead("8G").is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        use std::time::Duration;
+
+        assert_eq!(parse_timeout("1"), Ok(Duration::from_secs(1)));
+        assert_eq!(parse_timeout("0.5"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_timeout("0"), Ok(Duration::ZERO));
+        assert!(parse_timeout("-1").is_err());
+        assert!(parse_timeout("inf").is_err());
+        assert!(parse_timeout("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_umask() {
+        assert_eq!(parse_umask("022"), Ok(0o022));
+        assert_eq!(parse_umask("0027"), Ok(0o027));
+        assert_eq!(parse_umask("0"), Ok(0));
+        assert!(parse_umask("").is_err());
+        assert!(parse_umask("089").is_err());
+        assert!(parse_umask("1000").is_err());
+    }
+}
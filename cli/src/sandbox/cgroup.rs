@@ -0,0 +1,230 @@
+//! Resource limits for the overlay sandbox using cgroup v2.
+//!
+//! A limited run gets a cgroup of its own, created below the one `agentfs`
+//! runs in, and the sandboxed child is moved into it before it executes the
+//! command. Creating cgroups needs that part of the hierarchy to be
+//! delegated to the user, as systemd does for
+//! `systemd-run --user --scope -p Delegate=yes`, or root privileges.
+//!
+//! Controllers can only be enabled for a cgroup's children while the cgroup
+//! itself holds no processes, so `agentfs` first moves itself into a leaf
+//! cgroup next to the sandbox ones.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::cmd::ResourceLimits;
+
+/// Mount point of the cgroup v2 hierarchy.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Name of the leaf cgroup `agentfs` itself moves into.
+const SUPERVISOR_CGROUP: &str = "agentfs";
+
+/// Period over which the CPU limit is enforced, in microseconds.
+const CPU_PERIOD_USECS: u64 = 100_000;
+
+/// Smallest CPU quota the kernel accepts, in microseconds.
+const CPU_MIN_QUOTA_USECS: u64 = 1_000;
+
+/// Hint printed when the cgroup hierarchy is not delegated to the user.
+const DELEGATION_HINT: &str =
+    "run agentfs inside a delegated cgroup, e.g. `systemd-run --user --scope -p Delegate=yes agentfs run ...`";
+
+/// A cgroup holding a sandboxed command, removed when dropped.
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create a cgroup named `name` with `limits` applied.
+    ///
+    /// Fails if cgroup v2 is not mounted, a needed controller is not
+    /// delegated, or the cgroup `agentfs` runs in is shared with other
+    /// processes.
+    pub fn create(name: &str, limits: &ResourceLimits) -> Result<Self> {
+        let parent = Path::new(CGROUP_ROOT).join(current_cgroup()?);
+        let controllers = controllers(limits);
+        enable_controllers(&parent, &controllers)?;
+
+        let path = parent.join(format!("agentfs-{}", name));
+        std::fs::create_dir(&path)
+            .with_context(|| format!("Failed to create cgroup {}", path.display()))?;
+        let cgroup = Self { path };
+
+        if let Some(memory) = limits.memory {
+            cgroup.write("memory.max", &memory.to_string())?;
+        }
+        if let Some(cpu) = limits.cpu {
+            cgroup.write("cpu.max", &cpu_max(cpu))?;
+        }
+        if let Some(pids) = limits.pids {
+            cgroup.write("pids.max", &pids.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    /// Move process `pid` into the cgroup.
+    pub fn add_process(&self, pid: libc::pid_t) -> Result<()> {
+        self.write("cgroup.procs", &pid.to_string())
+    }
+
+    /// Kill whatever is left in the cgroup and remove it.
+    pub fn remove(&self) {
+        // cgroup.kill only exists on Linux 5.14 and later; before that,
+        // processes that outlive the command keep the cgroup around
+        let _ = std::fs::write(self.path.join("cgroup.kill"), "1");
+        for _ in 0..50 {
+            match std::fs::remove_dir(&self.path) {
+                Ok(()) => return,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+                // Killed processes leave the cgroup asynchronously
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+        eprintln!("Warning: Failed to remove cgroup {}", self.path.display());
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        std::fs::write(self.path.join(file), value).with_context(|| {
+            format!(
+                "Failed to write '{}' to {}",
+                value,
+                self.path.join(file).display()
+            )
+        })
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// The controllers needed to enforce `limits`.
+fn controllers(limits: &ResourceLimits) -> Vec<&'static str> {
+    let mut controllers = Vec::new();
+    if limits.memory.is_some() {
+        controllers.push("memory");
+    }
+    if limits.cpu.is_some() {
+        controllers.push("cpu");
+    }
+    if limits.pids.is_some() {
+        controllers.push("pids");
+    }
+    controllers
+}
+
+/// The `cpu.max` value allowing `cpus` cores.
+fn cpu_max(cpus: f64) -> String {
+    let quota = ((cpus * CPU_PERIOD_USECS as f64).round() as u64).max(CPU_MIN_QUOTA_USECS);
+    format!("{} {}", quota, CPU_PERIOD_USECS)
+}
+
+/// The cgroup this process runs in, relative to the hierarchy's root.
+fn current_cgroup() -> Result<PathBuf> {
+    let contents =
+        std::fs::read_to_string("/proc/self/cgroup").context("Failed to read /proc/self/cgroup")?;
+    match parse_cgroup_path(&contents) {
+        Some(path) if Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() => Ok(path),
+        _ => bail!(
+            "Resource limits need cgroup v2, which is not mounted at {}",
+            CGROUP_ROOT
+        ),
+    }
+}
+
+/// Extract the unified hierarchy path from the contents of `/proc/<pid>/cgroup`.
+fn parse_cgroup_path(contents: &str) -> Option<PathBuf> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| PathBuf::from(path.trim_start_matches('/')))
+}
+
+/// Make `controllers` available to new children of the cgroup `parent`.
+fn enable_controllers(parent: &Path, controllers: &[&str]) -> Result<()> {
+    let read = |file: &str| std::fs::read_to_string(parent.join(file)).unwrap_or_default();
+    let available = read("cgroup.controllers");
+    let enabled = read("cgroup.subtree_control");
+    let has = |list: &str, controller: &str| list.split_whitespace().any(|c| c == controller);
+
+    let missing: Vec<_> = controllers.iter().filter(|c| !has(&enabled, c)).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    if let Some(controller) = missing.iter().find(|c| !has(&available, c)) {
+        bail!(
+            "The {} controller is not available in cgroup {}; {}",
+            controller,
+            parent.display(),
+            DELEGATION_HINT
+        );
+    }
+
+    // Leave the cgroup, which must hold no processes before its children
+    // can get controllers
+    let supervisor = parent.join(SUPERVISOR_CGROUP);
+    match std::fs::create_dir(&supervisor) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => bail!(
+            "Cannot create cgroups in {}: {}; {}",
+            parent.display(),
+            e,
+            DELEGATION_HINT
+        ),
+    }
+    std::fs::write(
+        supervisor.join("cgroup.procs"),
+        std::process::id().to_string(),
+    )
+    .with_context(|| format!("Failed to move agentfs into {}", supervisor.display()))?;
+
+    let change: Vec<_> = missing.iter().map(|c| format!("+{}", c)).collect();
+    if let Err(e) = std::fs::write(parent.join("cgroup.subtree_control"), change.join(" ")) {
+        if e.raw_os_error() == Some(libc::EBUSY) {
+            bail!(
+                "Cannot enable controllers in cgroup {}: it is shared with other processes; {}",
+                parent.display(),
+                DELEGATION_HINT
+            );
+        }
+        bail!(
+            "Cannot enable controllers in cgroup {}: {}; {}",
+            parent.display(),
+            e,
+            DELEGATION_HINT
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_path() {
+        assert_eq!(
+            parse_cgroup_path("0::/user.slice/user-1000.slice/session-2.scope\n"),
+            Some(PathBuf::from("user.slice/user-1000.slice/session-2.scope"))
+        );
+        assert_eq!(parse_cgroup_path("0::/\n"), Some(PathBuf::new()));
+        // Hybrid hierarchies list the v1 controllers first
+        assert_eq!(
+            parse_cgroup_path("12:pids:/user.slice\n0::/user.slice\n"),
+            Some(PathBuf::from("user.slice"))
+        );
+        assert_eq!(parse_cgroup_path("4:memory:/user.slice\n"), None);
+    }
+
+    #[test]
+    fn test_cpu_max() {
+        assert_eq!(cpu_max(1.0), "100000 100000");
+        assert_eq!(cpu_max(2.5), "250000 100000");
+        assert_eq!(cpu_max(0.001), "1000 100000");
+    }
+}
@@ -15,8 +15,11 @@
 //! The HostFS base layer then accesses files through `/proc/self/fd/N`,
 //! bypassing the FUSE mount entirely.
 
+use super::cgroup::Cgroup;
 use super::group_paths_by_parent;
-use agentfs_sdk::{AgentFS, AgentFSOptions, EncryptionConfig, HostFS, OverlayFS};
+use super::seed::seed_delta;
+use crate::cmd::ResourceLimits;
+use agentfs_sdk::{AgentFS, AgentFSOptions, EncryptionConfig, OverlayFS};
 use anyhow::{bail, Context, Result};
 use std::{
     cmp::Reverse,
@@ -138,12 +141,15 @@ fn install_signal_handlers() {
 }
 
 /// Run a command in an overlay sandbox.
+#[allow(clippy::too_many_arguments)]
 pub async fn run_cmd(
     allow: Vec<PathBuf>,
     no_default_allows: bool,
     session_id: Option<String>,
+    from: Option<String>,
     system: bool,
     encryption: Option<(String, String)>,
+    limits: ResourceLimits,
     command: PathBuf,
     args: Vec<String>,
 ) -> Result<()> {
@@ -155,8 +161,24 @@ pub async fn run_cmd(
     // Check if we're joining an existing session
     let session = setup_run_directory(session_id)?;
 
+    // Set up resource limits before anything is started, so a missing
+    // delegation fails the run instead of running without limits.
+    // Each run gets its own cgroup, including runs joining a session.
+    let cgroup = if limits.is_empty() {
+        None
+    } else {
+        let name = format!("{}-{}", session.run_id, std::process::id());
+        Some(Cgroup::create(&name, &limits).context("Failed to set up resource limits")?)
+    };
+
     // If the FUSE mountpoint is already mounted, join the existing session
     if is_mountpoint(&session.fuse_mountpoint) {
+        if from.is_some() {
+            bail!(
+                "Session {} is already running; --from only applies to new sessions",
+                session.run_id
+            );
+        }
         // Get the original base path from the session's base_path file
         let overlay_base = std::fs::read_to_string(&session.base_path_file)
             .context("Failed to read session base path")?;
@@ -171,6 +193,7 @@ pub async fn run_cmd(
             command,
             args,
             &session.run_id,
+            cgroup,
         );
     }
 
@@ -183,6 +206,13 @@ pub async fn run_cmd(
     let fd_num = cwd_fd.as_raw_fd();
     let fd_path = format!("/proc/self/fd/{}", fd_num);
 
+    let cwd_str = cwd
+        .to_str()
+        .context("Current directory path contains non-UTF8 characters")?;
+    if let Some(from) = &from {
+        seed_delta(from, &session.db_path, cwd_str, encryption.as_ref()).await?;
+    }
+
     let db_path_str = session
         .db_path
         .to_str()
@@ -198,7 +228,10 @@ pub async fn run_cmd(
         .await
         .context("Failed to create delta AgentFS")?;
 
-    let hostfs = HostFS::new(&fd_path).context("Failed to create HostFS")?;
+    let hostfs = agentfs
+        .open_overlay_base(&fd_path)
+        .await
+        .context("Failed to create HostFS")?;
     #[cfg(target_family = "unix")]
     let hostfs = {
         let mountpoint_inode = fs::metadata(&session.fuse_mountpoint)
@@ -210,9 +243,6 @@ pub async fn run_cmd(
     let base = Arc::new(hostfs);
     let overlay = OverlayFS::new(base, agentfs.fs);
 
-    let cwd_str = cwd
-        .to_str()
-        .context("Current directory path contains non-UTF8 characters")?;
     overlay
         .init(cwd_str)
         .await
@@ -289,6 +319,7 @@ pub async fn run_cmd(
 
         // Configure user namespace mappings for the child
         write_namespace_mappings(child_pid, uid, gid, pipe_to_child[1]);
+        place_in_cgroup(cgroup.as_ref(), child_pid, pipe_to_child[1]);
 
         // Signal child that mappings are done
         // SAFETY: Writing to and closing valid pipe fds
@@ -306,7 +337,7 @@ pub async fn run_cmd(
         }
 
         // Keep cwd_fd alive - it's needed by HostFS in the FUSE thread
-        run_parent(child_pid, cwd_fd, mount_handle, &session.run_id);
+        run_parent(child_pid, cwd_fd, mount_handle, cgroup, &session.run_id);
     }
 }
 
@@ -321,6 +352,7 @@ fn run_in_existing_session(
     command: PathBuf,
     args: Vec<String>,
     session_id: &str,
+    cgroup: Option<Cgroup>,
 ) -> Result<()> {
     // SAFETY: getuid/getgid are always safe
     let uid = unsafe { libc::getuid() };
@@ -368,6 +400,7 @@ fn run_in_existing_session(
 
         // Configure user namespace mappings for the child
         write_namespace_mappings(child_pid, uid, gid, pipe_to_child[1]);
+        place_in_cgroup(cgroup.as_ref(), child_pid, pipe_to_child[1]);
 
         // Signal child that mappings are done
         unsafe {
@@ -391,6 +424,9 @@ fn run_in_existing_session(
         // Retry on EINTR (signal interruption)
         let exit_code = wait_for_child(child_pid);
 
+        // Kill anything the command left behind in its cgroup
+        drop(cgroup);
+
         // Clean up proc file
         crate::cmd::ps::remove_proc_file(session_id);
 
@@ -535,6 +571,21 @@ fn write_namespace_mappings(
     }
 }
 
+/// Move a child into the cgroup enforcing its resource limits, if any.
+///
+/// Called before the child is released to exec, so the limits apply from
+/// the command's first instruction. On failure, aborts the child and exits.
+fn place_in_cgroup(cgroup: Option<&Cgroup>, child_pid: libc::pid_t, pipe_write_fd: libc::c_int) {
+    let Some(cgroup) = cgroup else {
+        return;
+    };
+    if let Err(e) = cgroup.add_process(child_pid) {
+        eprintln!("Error: {:#}", e);
+        cgroup.remove();
+        abort_child(pipe_write_fd, child_pid);
+    }
+}
+
 /// Convert a path to a CString, exiting the child process on failure.
 ///
 /// Used in the child process context where we cannot return errors normally.
@@ -888,6 +939,7 @@ fn run_parent(
     child_pid: i32,
     cwd_fd: std::fs::File,
     mount_handle: MountHandle,
+    cgroup: Option<Cgroup>,
     session_id: &str,
 ) -> ! {
     // Store child PID and install signal handlers before waiting
@@ -897,6 +949,9 @@ fn run_parent(
     // Wait for child process to exit, retrying on EINTR (signal interruption)
     let exit_code = wait_for_child(child_pid);
 
+    // Kill anything the command left behind in its cgroup
+    drop(cgroup);
+
     // Clean up proc file
     crate::cmd::ps::remove_proc_file(session_id);
 
@@ -3,6 +3,7 @@
 //! This module provides platform-specific sandbox approaches:
 //! - `linux`: FUSE + namespace-based sandbox with copy-on-write filesystem
 //! - `linux_ptrace`: ptrace-based syscall interception sandbox (experimental)
+//! - `cgroup`: cgroup v2 resource limits for the `linux` sandbox
 //! - `darwin`: Kernel-enforced sandbox using sandbox-exec
 
 use std::collections::BTreeMap;
@@ -14,9 +15,15 @@ pub mod linux;
 #[cfg(all(target_os = "linux", feature = "sandbox"))]
 pub mod linux_ptrace;
 
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+pub mod cgroup;
+
 #[cfg(all(target_os = "macos", feature = "sandbox"))]
 pub mod darwin;
 
+#[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "sandbox"))]
+pub mod seed;
+
 /// Group paths by parent directory and format using brace expansion.
 ///
 /// For example, given paths:
@@ -0,0 +1,153 @@
+//! Seeding a new run session from an existing filesystem.
+//!
+//! `agentfs run --from` copies a source database into the session's
+//! `delta.db` before the overlay is set up, so the sandbox starts with the
+//! source's files layered over the working directory. Only the copy is ever
+//! opened, so nothing the sandbox does can reach the source.
+
+use std::path::{Path, PathBuf};
+
+use agentfs_sdk::{AgentFS, AgentFSOptions, EncryptionConfig};
+use anyhow::{Context, Result};
+
+use crate::cmd::snapshot::find_mount;
+
+/// Copy the filesystem named by `from` into the new delta at `db_path`.
+///
+/// `from` is a run session ID, an agent ID or a database path, optionally
+/// followed by `@LABEL` to roll the copy back to one of its snapshots. A
+/// source that is itself an overlay must have been layered over `base`,
+/// since its copied-up entries refer back to files there.
+pub async fn seed_delta(
+    from: &str,
+    db_path: &Path,
+    base: &str,
+    encryption: Option<&(String, String)>,
+) -> Result<()> {
+    if db_path.exists() {
+        anyhow::bail!(
+            "Session already has a delta at {}; --from only applies to new sessions",
+            db_path.display()
+        );
+    }
+
+    let (source, label) = match from.rsplit_once('@') {
+        Some((source, label)) if !label.is_empty() && !label.contains('/') => (source, Some(label)),
+        _ => (from, None),
+    };
+    let source_path = resolve_source(source)?;
+    let source_str = source_path
+        .to_str()
+        .context("Source path contains non-UTF8 characters")?;
+    if let Some(mountpoint) = find_mount(source, source_str) {
+        anyhow::bail!(
+            "'{}' is mounted at {}; unmount it before seeding a session from it",
+            source,
+            mountpoint.display()
+        );
+    }
+
+    copy_database(&source_path, db_path)
+        .with_context(|| format!("Failed to copy {}", source_path.display()))?;
+    let result = prepare_copy(db_path, base, label, encryption).await;
+    if result.is_err() {
+        // Leave no half-seeded delta behind for the next run to pick up
+        for path in database_files(db_path) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    result
+}
+
+/// Find the database file of a run session, agent or path.
+fn resolve_source(source: &str) -> Result<PathBuf> {
+    if AgentFSOptions::validate_agent_id(source) {
+        let home = dirs::home_dir().context("Failed to get home directory")?;
+        let delta = home
+            .join(".agentfs")
+            .join("run")
+            .join(source)
+            .join("delta.db");
+        if delta.is_file() {
+            return Ok(delta);
+        }
+    }
+
+    let options = AgentFSOptions::resolve(source)?;
+    if options.is_ephemeral() {
+        anyhow::bail!("Cannot seed a session from an in-memory filesystem");
+    }
+    Ok(PathBuf::from(options.db_path()?))
+}
+
+/// The database file followed by its write-ahead log and shared-memory index.
+fn database_files(db_path: &Path) -> [PathBuf; 3] {
+    let with_suffix = |suffix: &str| {
+        let mut name = db_path.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    [
+        db_path.to_path_buf(),
+        with_suffix("-wal"),
+        with_suffix("-shm"),
+    ]
+}
+
+/// Copy a database along with its write-ahead log, which holds committed
+/// changes that may not have reached the main file yet.
+fn copy_database(source: &Path, dest: &Path) -> std::io::Result<()> {
+    let [source_db, source_wal, _] = database_files(source);
+    let [dest_db, dest_wal, dest_shm] = database_files(dest);
+    for stale in [&dest_wal, &dest_shm] {
+        if stale.exists() {
+            std::fs::remove_file(stale)?;
+        }
+    }
+    std::fs::copy(source_db, dest_db)?;
+    if source_wal.exists() {
+        std::fs::copy(source_wal, dest_wal)?;
+    }
+    Ok(())
+}
+
+/// Check the copied database against the new base and roll it back to
+/// `label` if one was given.
+async fn prepare_copy(
+    db_path: &Path,
+    base: &str,
+    label: Option<&str>,
+    encryption: Option<&(String, String)>,
+) -> Result<()> {
+    let db_path_str = db_path
+        .to_str()
+        .context("Database path contains non-UTF8 characters")?;
+    let mut options = AgentFSOptions::with_path(db_path_str);
+    if let Some((key, cipher)) = encryption {
+        options = options.with_encryption(EncryptionConfig {
+            hex_key: key.clone(),
+            cipher: cipher.clone(),
+        });
+    }
+    let agentfs = AgentFS::open(options)
+        .await
+        .context("Failed to open seeded delta")?;
+
+    if let Some(source_base) = agentfs.is_overlay_enabled().await? {
+        if source_base != base {
+            anyhow::bail!(
+                "Source is an overlay of {}, not of the current directory {}",
+                source_base,
+                base
+            );
+        }
+    }
+    if let Some(label) = label {
+        agentfs
+            .fs
+            .restore(label)
+            .await
+            .with_context(|| format!("Failed to restore snapshot '{}'", label))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+#!/bin/sh
+set -e
+
+echo -n "TEST mount signal... "
+
+TEST_AGENT_ID="test-mount-signal-agent"
+MOUNTPOINT="/tmp/agentfs-test-mount-signal-$$"
+
+cleanup() {
+    # Unmount if mounted
+    fusermount -u "$MOUNTPOINT" 2>/dev/null || true
+    # Remove mountpoint
+    rmdir "$MOUNTPOINT" 2>/dev/null || true
+    # Remove test database
+    rm -f ".agentfs/${TEST_AGENT_ID}.db" ".agentfs/${TEST_AGENT_ID}.db-shm" ".agentfs/${TEST_AGENT_ID}.db-wal"
+}
+
+# Ensure cleanup on exit
+trap cleanup EXIT
+
+# Clean up any existing test artifacts
+cleanup
+
+# Initialize the database
+cargo run -- init "$TEST_AGENT_ID" > /dev/null 2>&1
+
+# Create mountpoint
+mkdir -p "$MOUNTPOINT"
+
+# Mount in foreground mode (background it ourselves so we can signal it)
+cargo run -- mount ".agentfs/${TEST_AGENT_ID}.db" "$MOUNTPOINT" --foreground 2>/dev/null &
+MOUNT_PID=$!
+
+# Wait for mount to be ready
+MAX_WAIT=10
+WAITED=0
+while [ $WAITED -lt $MAX_WAIT ]; do
+    if mountpoint -q "$MOUNTPOINT" 2>/dev/null; then
+        break
+    fi
+    sleep 0.5
+    WAITED=$((WAITED + 1))
+done
+
+if ! mountpoint -q "$MOUNTPOINT" 2>/dev/null; then
+    echo "FAILED: mount did not become ready in time"
+    kill $MOUNT_PID 2>/dev/null || true
+    exit 1
+fi
+
+echo "written before ctrl+c" > "$MOUNTPOINT/hello.txt"
+
+# Ctrl+C should unmount and exit
+kill -INT $MOUNT_PID
+if ! wait $MOUNT_PID; then
+    echo "FAILED: mount did not exit cleanly on SIGINT"
+    exit 1
+fi
+
+if mountpoint -q "$MOUNTPOINT" 2>/dev/null; then
+    echo "FAILED: mountpoint still mounted after SIGINT"
+    exit 1
+fi
+
+if cargo run -- mount 2>/dev/null | grep -q "$MOUNTPOINT"; then
+    echo "FAILED: 'agentfs mount' still lists the mountpoint after SIGINT"
+    exit 1
+fi
+
+# The file written through the mount is in the database
+CONTENT=$(cargo run -- fs ".agentfs/${TEST_AGENT_ID}.db" cat /hello.txt 2>/dev/null)
+if [ "$CONTENT" != "written before ctrl+c" ]; then
+    echo "FAILED: file content mismatch after unmount"
+    echo "Expected: written before ctrl+c"
+    echo "Got: $CONTENT"
+    exit 1
+fi
+
+echo "OK"
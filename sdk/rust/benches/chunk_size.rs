@@ -0,0 +1,66 @@
+//! Random-write throughput into a large file at different chunk sizes.
+//!
+//! Run with: cargo bench --bench chunk_size
+
+use agentfs_sdk::{AgentFS, AgentFSOptions};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tempfile::tempdir;
+
+/// Size of the file the writes land in
+const FILE_SIZE: u64 = 16 << 20;
+
+/// Size of each random write
+const WRITE_SIZE: usize = 512;
+
+/// Number of writes per iteration
+const WRITES: usize = 64;
+
+async fn create_fs(chunk_size: usize) -> (AgentFS, tempfile::TempDir) {
+    let dir = tempdir().expect("Failed to create temp dir");
+    let db_path = dir.path().join("bench.db");
+    let options = AgentFSOptions::with_path(db_path.to_str().unwrap()).with_chunk_size(chunk_size);
+    let agent = AgentFS::open(options)
+        .await
+        .expect("Failed to create AgentFS");
+    agent
+        .fs
+        .pwrite("/large.bin", 0, &vec![0xAB; FILE_SIZE as usize])
+        .await
+        .expect("Failed to write file");
+    (agent, dir)
+}
+
+fn bench_chunk_size(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let data = vec![0x5A; WRITE_SIZE];
+
+    let mut group = c.benchmark_group("chunk_size");
+    group.throughput(Throughput::Bytes((WRITE_SIZE * WRITES) as u64));
+
+    // Each write rewrites every chunk it touches, so smaller chunks do less work
+    for chunk_size in [4 << 10, 64 << 10, 1 << 20] {
+        let (agent, _dir) = rt.block_on(create_fs(chunk_size));
+        let mut rng = StdRng::seed_from_u64(42);
+
+        group.bench_with_input(
+            BenchmarkId::new("random_write", chunk_size),
+            &chunk_size,
+            |b, _| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        for _ in 0..WRITES {
+                            let offset = rng.gen_range(0..FILE_SIZE - WRITE_SIZE as u64);
+                            agent.fs.pwrite("/large.bin", offset, &data).await.unwrap();
+                        }
+                    });
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_chunk_size);
+criterion_main!(benches);
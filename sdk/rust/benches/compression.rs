@@ -0,0 +1,83 @@
+//! Read/write throughput of AgentFS with and without chunk compression.
+//!
+//! Run with: cargo bench --bench compression
+
+use agentfs_sdk::filesystem::{AgentFS, CompressionKind};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tempfile::tempdir;
+
+/// Size of the file written and read in each iteration
+const FILE_SIZE: usize = 1 << 20;
+
+/// Text resembling agent logs, which compresses well
+fn log_data(len: usize) -> Vec<u8> {
+    (0..)
+        .flat_map(|i| {
+            format!(
+                "2024-01-01T00:00:{:02}Z INFO tool_call id={} status=ok\n",
+                i % 60,
+                i
+            )
+            .into_bytes()
+        })
+        .take(len)
+        .collect()
+}
+
+async fn create_fs(compression: Option<CompressionKind>) -> (AgentFS, tempfile::TempDir) {
+    let dir = tempdir().expect("Failed to create temp dir");
+    let db_path = dir.path().join("bench.db");
+    let fs = AgentFS::new(db_path.to_str().unwrap())
+        .await
+        .expect("Failed to create AgentFS");
+    fs.set_compression(compression)
+        .await
+        .expect("Failed to set compression");
+    (fs, dir)
+}
+
+fn bench_compression(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let data = log_data(FILE_SIZE);
+
+    let mut group = c.benchmark_group("compression");
+
+    for (name, compression) in [("none", None), ("zstd", Some(CompressionKind::Zstd))] {
+        group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+        group.bench_with_input(BenchmarkId::new("write", name), &compression, |b, &c| {
+            b.iter_batched(
+                || rt.block_on(create_fs(c)),
+                |(fs, _dir)| {
+                    rt.block_on(async {
+                        fs.pwrite("/log.txt", 0, &data).await.unwrap();
+                    });
+                },
+                criterion::BatchSize::SmallInput,
+            );
+        });
+
+        let (fs, _dir) = rt.block_on(async {
+            let (fs, dir) = create_fs(compression).await;
+            fs.pwrite("/log.txt", 0, &data).await.unwrap();
+            (fs, dir)
+        });
+
+        group.bench_function(BenchmarkId::new("read_file", name), |b| {
+            b.iter(|| rt.block_on(fs.read_file("/log.txt")).unwrap());
+        });
+
+        // A small read in the middle only decodes the chunks it covers
+        group.throughput(Throughput::Bytes(4096));
+        group.bench_function(BenchmarkId::new("pread_4k", name), |b| {
+            b.iter(|| {
+                rt.block_on(fs.pread("/log.txt", FILE_SIZE as u64 / 2 + 100, 4096))
+                    .unwrap()
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_compression);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+//! Reading a large file front to back, streamed versus one pread per block.
+//!
+//! Run with: cargo bench --bench read_stream
+
+use agentfs_sdk::{AgentFS, AgentFSOptions, FileSystem};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tempfile::tempdir;
+
+/// Size of the file read per iteration (256 MiB)
+const FILE_SIZE: usize = 256 << 20;
+
+/// Size of each write while creating the file
+const WRITE_SIZE: usize = 1 << 20;
+
+async fn create_fs() -> (AgentFS, i64, tempfile::TempDir) {
+    let dir = tempdir().expect("Failed to create temp dir");
+    let db_path = dir.path().join("bench.db");
+    let agent = AgentFS::open(AgentFSOptions::with_path(db_path.to_str().unwrap()))
+        .await
+        .expect("Failed to create AgentFS");
+
+    // Distinct contents per write, so chunks are not deduplicated
+    let mut data = vec![0u8; WRITE_SIZE];
+    for i in 0..FILE_SIZE / WRITE_SIZE {
+        data[..8].copy_from_slice(&(i as u64).to_le_bytes());
+        agent
+            .fs
+            .pwrite("/model.bin", (i * WRITE_SIZE) as u64, &data)
+            .await
+            .expect("Failed to write file");
+    }
+    let ino = agent
+        .fs
+        .stat("/model.bin")
+        .await
+        .expect("Failed to stat file")
+        .expect("File is missing")
+        .ino;
+    (agent, ino, dir)
+}
+
+fn bench_read_stream(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let (agent, ino, _dir) = rt.block_on(create_fs());
+    let block_size = agent.fs.chunk_size() as u64;
+
+    let mut group = c.benchmark_group("read_stream");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+
+    // The caller tracks offsets and issues a query per block
+    group.bench_function(BenchmarkId::new("pread", block_size), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let file = FileSystem::open(&agent.fs, ino, libc::O_RDONLY)
+                    .await
+                    .unwrap();
+                let mut offset = 0;
+                loop {
+                    let data = file.pread(offset, block_size).await.unwrap();
+                    if data.is_empty() {
+                        break;
+                    }
+                    offset += data.len() as u64;
+                }
+                assert_eq!(offset, FILE_SIZE as u64);
+            });
+        });
+    });
+
+    // The stream fetches a page of blocks per query
+    group.bench_function(BenchmarkId::new("stream", block_size), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let mut stream = agent.fs.read_stream(ino).await.unwrap().unwrap();
+                let mut total = 0;
+                while let Some(chunk) = stream.next_chunk().await.unwrap() {
+                    total += chunk.len();
+                }
+                assert_eq!(total, FILE_SIZE);
+            });
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_read_stream);
+criterion_main!(benches);
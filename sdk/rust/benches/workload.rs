@@ -302,27 +302,53 @@ impl WorkloadGenerator {
     }
 }
 
+/// Resolve an absolute path by walking it from the root, returning its inode.
+async fn resolve(overlay: &OverlayFS, path: &str) -> Option<i64> {
+    let mut ino = 1;
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        ino = overlay.lookup(ino, component).await.ok()??.ino;
+    }
+    Some(ino)
+}
+
+/// Resolve the parent directory of an absolute path, returning its inode
+/// and the final component.
+async fn resolve_parent<'a>(overlay: &OverlayFS, path: &'a str) -> Option<(i64, &'a str)> {
+    let (parent, name) = path.rsplit_once('/')?;
+    Some((resolve(overlay, parent).await?, name))
+}
+
 /// Execute a single operation on the overlay filesystem.
+///
+/// Errors are ignored: paths may not exist, which is expected.
 async fn execute_operation(overlay: &OverlayFS, op: Operation, path: &str) {
     match op {
         Operation::CreateFile => {
-            // Ignore errors - path may not exist, which is expected
-            let _ = overlay.create_file(path, 0o100644, 0, 0).await;
+            if let Some((parent, name)) = resolve_parent(overlay, path).await {
+                let _ = overlay.create_file(parent, name, 0o100644, 0, 0).await;
+            }
         }
-        Operation::Lstat => {
-            let _ = overlay.lstat(path).await;
+        // There are no symlinks in the workload, so stat and lstat both
+        // resolve the path and read the attributes
+        Operation::Lstat | Operation::Stat => {
+            if let Some(ino) = resolve(overlay, path).await {
+                let _ = overlay.getattr(ino).await;
+            }
         }
         Operation::Mkdir => {
-            let _ = overlay.mkdir(path, 0, 0).await;
+            if let Some((parent, name)) = resolve_parent(overlay, path).await {
+                let _ = overlay.mkdir(parent, name, 0o755, 0, 0).await;
+            }
         }
         Operation::Open => {
-            let _ = overlay.open(path).await;
+            if let Some(ino) = resolve(overlay, path).await {
+                let _ = overlay.open(ino, libc::O_RDONLY).await;
+            }
         }
         Operation::ReaddirPlus => {
-            let _ = overlay.readdir_plus(path).await;
-        }
-        Operation::Stat => {
-            let _ = overlay.stat(path).await;
+            if let Some(ino) = resolve(overlay, path).await {
+                let _ = overlay.readdir_plus(ino).await;
+            }
         }
     }
 }
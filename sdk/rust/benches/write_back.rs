@@ -0,0 +1,62 @@
+//! Many tiny writes through an open file, with and without write-back.
+//!
+//! Run with: cargo bench --bench write_back
+
+use agentfs_sdk::{AgentFS, AgentFSOptions, WriteBackPolicy};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tempfile::tempdir;
+
+/// Number of one-byte writes per iteration
+const WRITES: usize = 10_000;
+
+async fn create_fs(write_back: bool) -> (AgentFS, tempfile::TempDir) {
+    let dir = tempdir().expect("Failed to create temp dir");
+    let db_path = dir.path().join("bench.db");
+    let mut options = AgentFSOptions::with_path(db_path.to_str().unwrap());
+    if write_back {
+        options = options.with_write_back(WriteBackPolicy::default());
+    }
+    let agent = AgentFS::open(options)
+        .await
+        .expect("Failed to create AgentFS");
+    agent
+        .fs
+        .pwrite("/log", 0, b"")
+        .await
+        .expect("Failed to create file");
+    (agent, dir)
+}
+
+fn bench_write_back(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("write_back");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(WRITES as u64));
+
+    // Without write-back every byte commits its own transaction
+    for write_back in [false, true] {
+        let (agent, _dir) = rt.block_on(create_fs(write_back));
+        let file = rt.block_on(agent.fs.open("/log")).unwrap();
+
+        group.bench_with_input(
+            BenchmarkId::new("one_byte_writes", write_back),
+            &write_back,
+            |b, _| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        for i in 0..WRITES {
+                            file.pwrite(i as u64, b"x").await.unwrap();
+                        }
+                        file.fsync().await.unwrap();
+                    });
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_write_back);
+criterion_main!(benches);
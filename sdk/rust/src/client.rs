@@ -0,0 +1,437 @@
+//! A path-based client for using AgentFS from Rust programs.
+//!
+//! [`AgentFSClient`] wraps any [`FileSystem`] with methods named after their
+//! `std::fs` counterparts, so embedding a filesystem does not mean walking
+//! inodes by hand. With the `blocking` feature, [`blocking::AgentFSClient`]
+//! offers the same methods to callers without an async runtime.
+//!
+//! Paths are absolute and resolved with [`FileSystem::lookup`]; symlinks are
+//! not followed.
+
+use crate::error::Result;
+use crate::filesystem::{
+    path_components, BoxedDirStream, BoxedFile, BoxedReadStream, FileSystem, FsError, Stats,
+    DEFAULT_DIR_MODE, DEFAULT_FILE_MODE,
+};
+use crate::{AgentFS, AgentFSOptions};
+use std::sync::Arc;
+
+/// Root inode number
+const ROOT_INO: i64 = 1;
+
+/// An async, path-based client for a filesystem.
+#[derive(Clone)]
+pub struct AgentFSClient {
+    fs: Arc<dyn FileSystem>,
+    uid: u32,
+    gid: u32,
+}
+
+impl AgentFSClient {
+    /// Wrap `fs`. Files and directories are created as the current user.
+    pub fn new(fs: Arc<dyn FileSystem>) -> Self {
+        // SAFETY: getuid/getgid are always safe
+        #[cfg(unix)]
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        #[cfg(not(unix))]
+        let (uid, gid) = (0u32, 0u32);
+        Self { fs, uid, gid }
+    }
+
+    /// Open the agent filesystem `options` selects and wrap it
+    pub async fn open(options: AgentFSOptions) -> Result<Self> {
+        let agent = AgentFS::open(options).await?;
+        Ok(Self::new(Arc::new(agent.fs)))
+    }
+
+    /// Create files and directories owned by `uid` and `gid`
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// The wrapped filesystem, for operations the client does not cover
+    pub fn filesystem(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+
+    /// Stats of the entry at `path`, failing with `FsError::NotFound` if
+    /// there is none
+    pub async fn metadata(&self, path: &str) -> Result<Stats> {
+        self.resolve(&path_components(path)).await
+    }
+
+    /// Whether an entry exists at `path`
+    pub async fn exists(&self, path: &str) -> Result<bool> {
+        match self.metadata(path).await {
+            Ok(_) => Ok(true),
+            Err(crate::error::Error::Fs(FsError::NotFound | FsError::NotADirectory)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the whole file at `path`
+    pub async fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let stats = self.metadata(path).await?;
+        if stats.is_directory() {
+            return Err(FsError::IsADirectory.into());
+        }
+        let file = self.fs.open(stats.ino, libc::O_RDONLY).await?;
+        let size = file.fstat().await?.size as u64;
+        file.pread(0, size).await
+    }
+
+    /// Read the whole file at `path` as UTF-8.
+    ///
+    /// Contents that are not valid UTF-8 fail with an I/O error of kind
+    /// `InvalidData`, as `std::fs::read_to_string` does.
+    pub async fn read_to_string(&self, path: &str) -> Result<String> {
+        let data = self.read(path).await?;
+        String::from_utf8(data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+    }
+
+    /// Stream the contents of the file at `path` in blocks, for files too
+    /// large to read into memory at once
+    pub async fn read_stream(&self, path: &str) -> Result<BoxedReadStream> {
+        let stats = self.metadata(path).await?;
+        self.fs
+            .read_stream(stats.ino)
+            .await?
+            .ok_or_else(|| FsError::NotFound.into())
+    }
+
+    /// Replace the contents of the file at `path` with `data`, creating the
+    /// file if it does not exist. Its parent directory must exist.
+    pub async fn write_all(&self, path: &str, data: impl AsRef<[u8]>) -> Result<()> {
+        let file = self.create(path).await?;
+        file.pwrite(0, data.as_ref()).await?;
+        file.flush().await
+    }
+
+    /// Create the directory at `path` and any missing ancestors
+    pub async fn create_dir_all(&self, path: &str) -> Result<()> {
+        self.fs
+            .mkdir_all(path, DEFAULT_DIR_MODE, self.uid, self.gid)
+            .await?;
+        Ok(())
+    }
+
+    /// Stream the entries of the directory at `path`
+    pub async fn read_dir(&self, path: &str) -> Result<BoxedDirStream> {
+        let stats = self.metadata(path).await?;
+        if !stats.is_directory() {
+            return Err(FsError::NotADirectory.into());
+        }
+        self.fs
+            .readdir_stream(stats.ino)
+            .await?
+            .ok_or_else(|| FsError::NotFound.into())
+    }
+
+    /// Remove the file at `path`
+    pub async fn remove_file(&self, path: &str) -> Result<()> {
+        self.fs.remove_file(path).await
+    }
+
+    /// Remove the file or directory tree at `path`
+    pub async fn remove_dir_all(&self, path: &str) -> Result<()> {
+        self.fs.remove_all(path).await
+    }
+
+    /// Move the entry at `from` to `to`, replacing what `to` names as
+    /// `rename(2)` would
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let (from_parent, from_name) = self.parent(from).await?;
+        let (to_parent, to_name) = self.parent(to).await?;
+        self.fs
+            .rename(from_parent.ino, from_name, to_parent.ino, to_name, 0)
+            .await
+    }
+
+    /// Open the file at `path` emptied, creating it if needed
+    async fn create(&self, path: &str) -> Result<BoxedFile> {
+        if path_components(path).is_empty() {
+            return Err(FsError::IsADirectory.into());
+        }
+        let (parent, name) = self.parent(path).await?;
+        match self.fs.lookup(parent.ino, name).await? {
+            Some(stats) if stats.is_directory() => Err(FsError::IsADirectory.into()),
+            Some(stats) => {
+                let file = self
+                    .fs
+                    .open(stats.ino, libc::O_WRONLY | libc::O_TRUNC)
+                    .await?;
+                file.truncate(0).await?;
+                Ok(file)
+            }
+            None => {
+                let (_, file) = self
+                    .fs
+                    .create_file(parent.ino, name, DEFAULT_FILE_MODE, self.uid, self.gid)
+                    .await?;
+                Ok(file)
+            }
+        }
+    }
+
+    /// Stats of the entry `components` lead to from the root
+    async fn resolve(&self, components: &[&str]) -> Result<Stats> {
+        let mut stats = self.fs.getattr(ROOT_INO).await?.ok_or(FsError::NotFound)?;
+        for name in components {
+            if !stats.is_directory() {
+                return Err(FsError::NotADirectory.into());
+            }
+            stats = self
+                .fs
+                .lookup(stats.ino, name)
+                .await?
+                .ok_or(FsError::NotFound)?;
+        }
+        Ok(stats)
+    }
+
+    /// The parent directory of `path` and the last component's name
+    async fn parent<'a>(&self, path: &'a str) -> Result<(Stats, &'a str)> {
+        let components = path_components(path);
+        let Some((name, ancestors)) = components.split_last() else {
+            return Err(FsError::RootOperation.into());
+        };
+        let parent = self.resolve(ancestors).await?;
+        if !parent.is_directory() {
+            return Err(FsError::NotADirectory.into());
+        }
+        Ok((parent, *name))
+    }
+}
+
+/// A blocking client, for programs without an async runtime.
+#[cfg(feature = "blocking")]
+pub mod blocking {
+    use crate::error::Result;
+    use crate::filesystem::{DirEntry, FileSystem, Stats};
+    use crate::AgentFSOptions;
+    use std::sync::Arc;
+    use tokio::runtime::Runtime;
+
+    /// A blocking, path-based client for a filesystem.
+    ///
+    /// Each client owns a Tokio runtime and blocks on it for every call, so
+    /// it must not be used from within an async context; use
+    /// [`super::AgentFSClient`] there instead.
+    pub struct AgentFSClient {
+        inner: super::AgentFSClient,
+        runtime: Runtime,
+    }
+
+    impl AgentFSClient {
+        /// Wrap `fs`. Files and directories are created as the current user.
+        pub fn new(fs: Arc<dyn FileSystem>) -> Result<Self> {
+            Ok(Self {
+                inner: super::AgentFSClient::new(fs),
+                runtime: runtime()?,
+            })
+        }
+
+        /// Open the agent filesystem `options` selects and wrap it
+        pub fn open(options: AgentFSOptions) -> Result<Self> {
+            let runtime = runtime()?;
+            let inner = runtime.block_on(super::AgentFSClient::open(options))?;
+            Ok(Self { inner, runtime })
+        }
+
+        /// Create files and directories owned by `uid` and `gid`
+        pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+            self.inner = self.inner.with_owner(uid, gid);
+            self
+        }
+
+        /// The wrapped filesystem, for operations the client does not cover
+        pub fn filesystem(&self) -> &Arc<dyn FileSystem> {
+            self.inner.filesystem()
+        }
+
+        /// See [`super::AgentFSClient::metadata`]
+        pub fn metadata(&self, path: &str) -> Result<Stats> {
+            self.runtime.block_on(self.inner.metadata(path))
+        }
+
+        /// See [`super::AgentFSClient::exists`]
+        pub fn exists(&self, path: &str) -> Result<bool> {
+            self.runtime.block_on(self.inner.exists(path))
+        }
+
+        /// See [`super::AgentFSClient::read`]
+        pub fn read(&self, path: &str) -> Result<Vec<u8>> {
+            self.runtime.block_on(self.inner.read(path))
+        }
+
+        /// See [`super::AgentFSClient::read_to_string`]
+        pub fn read_to_string(&self, path: &str) -> Result<String> {
+            self.runtime.block_on(self.inner.read_to_string(path))
+        }
+
+        /// See [`super::AgentFSClient::write_all`]
+        pub fn write_all(&self, path: &str, data: impl AsRef<[u8]>) -> Result<()> {
+            self.runtime.block_on(self.inner.write_all(path, data))
+        }
+
+        /// See [`super::AgentFSClient::create_dir_all`]
+        pub fn create_dir_all(&self, path: &str) -> Result<()> {
+            self.runtime.block_on(self.inner.create_dir_all(path))
+        }
+
+        /// The entries of the directory at `path`, collected rather than
+        /// streamed
+        pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+            self.runtime.block_on(async {
+                let mut stream = self.inner.read_dir(path).await?;
+                let mut entries = Vec::new();
+                while let Some(entry) = stream.next_entry().await? {
+                    entries.push(entry);
+                }
+                Ok(entries)
+            })
+        }
+
+        /// See [`super::AgentFSClient::remove_file`]
+        pub fn remove_file(&self, path: &str) -> Result<()> {
+            self.runtime.block_on(self.inner.remove_file(path))
+        }
+
+        /// See [`super::AgentFSClient::remove_dir_all`]
+        pub fn remove_dir_all(&self, path: &str) -> Result<()> {
+            self.runtime.block_on(self.inner.remove_dir_all(path))
+        }
+
+        /// See [`super::AgentFSClient::rename`]
+        pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+            self.runtime.block_on(self.inner.rename(from, to))
+        }
+    }
+
+    /// A runtime with one worker, so background tasks such as checkpoints
+    /// keep running between calls
+    fn runtime() -> Result<Runtime> {
+        Ok(tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[tokio::test]
+    async fn test_client_roundtrip() -> Result<()> {
+        let client = AgentFSClient::open(AgentFSOptions::ephemeral()).await?;
+        client.create_dir_all("/a/b").await?;
+        client.write_all("/a/b/note.txt", "first version").await?;
+        client.write_all("/a/b/note.txt", "second").await?;
+        assert_eq!(client.read_to_string("/a/b/note.txt").await?, "second");
+        let mut stream = client.read_stream("/a/b/note.txt").await?;
+        let mut streamed = Vec::new();
+        while let Some(chunk) = stream.next_chunk().await? {
+            streamed.extend(chunk);
+        }
+        assert_eq!(streamed, b"second");
+        assert_eq!(client.metadata("/a/b/note.txt").await?.size, 6);
+
+        client.rename("/a/b/note.txt", "/a/moved.txt").await?;
+        assert!(!client.exists("/a/b/note.txt").await?);
+        let mut names = Vec::new();
+        let mut entries = client.read_dir("/a").await?;
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.name);
+        }
+        names.sort();
+        assert_eq!(names, ["b", "moved.txt"]);
+
+        client.remove_dir_all("/a").await?;
+        assert!(!client.exists("/a").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_errors() -> Result<()> {
+        let client = AgentFSClient::open(AgentFSOptions::ephemeral()).await?;
+        client.create_dir_all("/dir").await?;
+        client.write_all("/bin", [0xffu8, 0xfe]).await?;
+
+        assert!(matches!(
+            client.read("/dir").await,
+            Err(Error::Fs(FsError::IsADirectory))
+        ));
+        assert!(matches!(
+            client.write_all("/missing/file", "x").await,
+            Err(Error::Fs(FsError::NotFound))
+        ));
+        assert!(matches!(
+            client.read_dir("/bin").await,
+            Err(Error::Fs(FsError::NotADirectory))
+        ));
+        assert!(matches!(
+            client.read_to_string("/bin").await,
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_root_path() -> Result<()> {
+        let client = AgentFSClient::open(AgentFSOptions::ephemeral()).await?;
+        client.create_dir_all("/dir").await?;
+        client.write_all("/file", "data").await?;
+
+        for root in ["/", ""] {
+            let stats = client.metadata(root).await?;
+            assert_eq!(stats.ino, ROOT_INO);
+            assert!(stats.is_directory());
+
+            let mut names = Vec::new();
+            let mut entries = client.read_dir(root).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                names.push(entry.name);
+            }
+            names.sort();
+            assert_eq!(names, ["dir", "file"]);
+
+            assert!(matches!(
+                client.write_all(root, "x").await,
+                Err(Error::Fs(FsError::IsADirectory))
+            ));
+            assert!(matches!(
+                client.remove_dir_all(root).await,
+                Err(Error::Fs(FsError::RootOperation))
+            ));
+            assert!(matches!(
+                client.rename(root, "/moved").await,
+                Err(Error::Fs(FsError::RootOperation))
+            ));
+            assert!(matches!(
+                client.rename("/dir", root).await,
+                Err(Error::Fs(FsError::RootOperation))
+            ));
+        }
+        assert!(client.exists("/dir").await?);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_blocking_client() -> Result<()> {
+        let client = blocking::AgentFSClient::open(AgentFSOptions::ephemeral())?;
+        client.create_dir_all("/dir")?;
+        client.write_all("/dir/file", "data")?;
+        assert_eq!(client.read_to_string("/dir/file")?, "data");
+        assert_eq!(client.read_dir("/dir")?.len(), 1);
+        Ok(())
+    }
+}
@@ -65,6 +65,26 @@ pub enum Error {
     #[error("invalid encryption key: {0}")]
     InvalidEncryptionKey(String),
 
+    /// Snapshot label not found
+    #[error("snapshot '{0}' not found")]
+    SnapshotNotFound(String),
+
+    /// Snapshot label already in use
+    #[error("snapshot '{0}' already exists")]
+    SnapshotExists(String),
+
+    /// Checkpoint policy that cannot be scheduled
+    #[error("invalid checkpoint policy: {0}")]
+    InvalidCheckpointPolicy(String),
+
+    /// Write-back policy that cannot be applied
+    #[error("invalid write-back policy: {0}")]
+    InvalidWriteBackPolicy(String),
+
+    /// Chunk size that is out of range or cannot be changed
+    #[error("invalid chunk size: {0}")]
+    InvalidChunkSize(String),
+
     /// Internal error (for unexpected conditions)
     #[error("{0}")]
     Internal(String),
@@ -74,5 +94,47 @@ pub enum Error {
     SchemaVersionMismatch { found: String, expected: String },
 }
 
+impl Error {
+    /// Convert to the libc errno code a filesystem caller should see.
+    ///
+    /// Filesystem errors keep their own code and I/O errors their OS code.
+    /// A busy database or an exhausted connection pool returns `EAGAIN` so
+    /// the caller can retry; anything else is `EIO`.
+    pub fn to_errno(&self) -> i32 {
+        match self {
+            Error::Fs(fs_err) => fs_err.to_errno(),
+            Error::Io(io_err) => io_err.raw_os_error().unwrap_or(libc::EIO),
+            Error::Database(turso::Error::Busy(_)) => libc::EAGAIN,
+            Error::ConnectionPoolTimeout => libc::EAGAIN,
+            Error::BaseDirectoryNotFound(_) => libc::ENOENT,
+            Error::NotADirectory(_) => libc::ENOTDIR,
+            Error::InvalidUtf8Path(_) => libc::EINVAL,
+            _ => libc::EIO,
+        }
+    }
+}
+
 /// Result type alias using the SDK Error type.
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::FsError;
+
+    #[test]
+    fn test_to_errno() {
+        assert_eq!(Error::Fs(FsError::NoSpace).to_errno(), libc::ENOSPC);
+        assert_eq!(Error::Fs(FsError::NotImplemented).to_errno(), libc::ENOSYS);
+        assert_eq!(
+            Error::Fs(FsError::PermissionDenied).to_errno(),
+            libc::EACCES
+        );
+        let io = std::io::Error::from_raw_os_error(libc::EXDEV);
+        assert_eq!(Error::Io(io).to_errno(), libc::EXDEV);
+        let io = std::io::Error::other("no os code");
+        assert_eq!(Error::Io(io).to_errno(), libc::EIO);
+        assert_eq!(Error::ConnectionPoolTimeout.to_errno(), libc::EAGAIN);
+        assert_eq!(Error::Internal("oops".to_string()).to_errno(), libc::EIO);
+    }
+}
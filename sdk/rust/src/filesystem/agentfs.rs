@@ -1,23 +1,668 @@
 use crate::error::{Error, Result};
 use async_trait::async_trait;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use lru::LruCache;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::num::NonZeroUsize;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 use turso::transaction::{Transaction, TransactionBehavior};
 use turso::{Builder, Connection, Value};
 
+use super::lock::{LockTable, LockType};
+use super::writeback::{Push, SpillPolicy, WriteBackPolicy, WriteBuffer};
 use super::{
-    BoxedFile, DirEntry, File, FileSystem, FilesystemStats, FsError, Stats, TimeChange,
-    DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, MAX_NAME_LEN, S_IFLNK, S_IFMT, S_IFREG,
+    check_copy_range, check_op_preconditions, checked_file_end, normalize_path, path_components,
+    BoxedDirStream, BoxedFile, BoxedReadStream, DirEntry, DirStream, File, FileSystem, FileType,
+    FilesystemStats, FsError, Operation, ReadStream, Stats, TimeChange, DEFAULT_DIR_MODE,
+    DEFAULT_FILE_MODE, FALLOC_FL_KEEP_SIZE, MAX_NAME_LEN, OWNER_UNCHANGED, RENAME_EXCHANGE,
+    RENAME_NOREPLACE, S_IFLNK, S_IFMT, S_IFREG, XATTR_CREATE, XATTR_REPLACE,
 };
 use crate::connection_pool::ConnectionPool;
 use crate::schema::AGENTFS_SCHEMA_VERSION;
 
 const ROOT_INO: i64 = 1;
 const DEFAULT_CHUNK_SIZE: usize = 4096;
+/// Smallest chunk size a filesystem can be created with.
+pub const MIN_CHUNK_SIZE: usize = 512;
+/// Largest chunk size a filesystem can be created with.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
 const DENTRY_CACHE_MAX_SIZE: usize = 10000;
+/// Number of entries fetched per query by `AgentFSDirStream`
+const READDIR_PAGE_SIZE: i64 = 256;
+/// Number of chunks fetched per query by `AgentFSReadStream`
+const READ_STREAM_PAGE_CHUNKS: u64 = 256;
+/// Capacity reported by statfs when no quota is set (4 TiB)
+const VIRTUAL_CAPACITY_BYTES: u64 = 4 << 40;
+/// Inode limit reported by statfs
+const VIRTUAL_INODES: u64 = 1_000_000;
+/// Maximum number of symlinks followed while resolving one path (Linux `MAXSYMLINKS`)
+const MAX_SYMLINKS: usize = 40;
+/// Chunks smaller than this are stored uncompressed even with compression enabled
+const COMPRESSION_THRESHOLD: usize = 512;
+/// `fs_data.compression` value of chunks stored as-is
+const COMPRESSION_NONE: u8 = 0;
+/// Flag set in `fs_data.compression` for chunks sealed with the blob key
+const CHUNK_ENCRYPTED: u8 = 0x80;
+/// Length of the nonce stored in front of each encrypted chunk
+const CHUNK_NONCE_LEN: usize = 12;
+/// Message hashed with the blob key into the `blob_key_check` config value
+const BLOB_KEY_CHECK_MESSAGE: &[u8] = b"agentfs blob key check";
+/// Change events buffered per subscriber before the slowest one starts lagging
+const CHANGE_EVENT_CAPACITY: usize = 1024;
+
+/// Compression applied to file contents before they are stored.
+///
+/// Each chunk in `fs_data` is compressed on its own and records how it was
+/// stored in its `compression` column, so reading at an offset only
+/// decompresses the chunks that cover the requested range. The flip side is
+/// that a partial write to a chunk decompresses and recompresses the whole
+/// chunk, and small chunks compress worse than whole files would.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionKind {
+    /// Zstandard at its default level
+    Zstd,
+}
+
+impl CompressionKind {
+    /// Value stored in the `fs_data.compression` column
+    fn code(self) -> u8 {
+        match self {
+            CompressionKind::Zstd => 1,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(CompressionKind::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Name used in `fs_config` and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionKind::Zstd => "zstd",
+        }
+    }
+}
+
+impl std::fmt::Display for CompressionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for CompressionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(CompressionKind::Zstd),
+            _ => Err(format!("unknown compression '{}' (expected: zstd)", s)),
+        }
+    }
+}
+
+/// Key encrypting file contents at rest with ChaCha20-Poly1305.
+///
+/// Only the bytes of file chunks are encrypted. Names, sizes, modes,
+/// timestamps, extended attributes and symlink targets stay in plaintext.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BlobKey([u8; BlobKey::LEN]);
+
+impl BlobKey {
+    /// Key length in bytes
+    pub const LEN: usize = 32;
+
+    /// Create a key from raw bytes, which must be exactly [`BlobKey::LEN`] long
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let key: [u8; Self::LEN] = bytes.try_into().map_err(|_| {
+            Error::InvalidEncryptionKey(format!(
+                "blob key must be {} bytes, got {}",
+                Self::LEN,
+                bytes.len()
+            ))
+        })?;
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+
+    /// Value stored in `fs_config` to recognize this key without storing it
+    fn check_value(&self) -> String {
+        blake3::keyed_hash(&self.0, BLOB_KEY_CHECK_MESSAGE)
+            .to_hex()
+            .to_string()
+    }
+}
+
+impl From<[u8; BlobKey::LEN]> for BlobKey {
+    fn from(key: [u8; BlobKey::LEN]) -> Self {
+        Self(key)
+    }
+}
+
+impl std::fmt::Debug for BlobKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BlobKey(..)")
+    }
+}
+
+/// How newly written chunks are stored.
+#[derive(Debug, Default)]
+struct ChunkEncoding {
+    /// Compression of new chunks, as a `fs_data.compression` value
+    compression: AtomicU8,
+    /// Whether new chunks are stored once per distinct content in `fs_blob`
+    dedup: AtomicBool,
+    /// Key sealing new chunks and opening encrypted ones
+    key: RwLock<Option<BlobKey>>,
+}
+
+/// Prepare a chunk for storage, returning the bytes to store and the value
+/// of its `compression` column.
+///
+/// The chunk is only kept compressed when that actually saves space. With a
+/// blob key set, the result is then encrypted under a fresh random nonce,
+/// which is stored in front of the ciphertext.
+fn encode_chunk(data: &[u8], encoding: &ChunkEncoding) -> Result<(Vec<u8>, u8)> {
+    let code = encoding.compression.load(Ordering::Relaxed);
+    let (stored, code) = match CompressionKind::from_code(code) {
+        Some(CompressionKind::Zstd) if data.len() >= COMPRESSION_THRESHOLD => {
+            let compressed = zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            if compressed.len() < data.len() {
+                (compressed, code)
+            } else {
+                (data.to_vec(), COMPRESSION_NONE)
+            }
+        }
+        _ => (data.to_vec(), COMPRESSION_NONE),
+    };
+
+    let key = encoding.key.read().unwrap();
+    let Some(key) = key.as_ref() else {
+        return Ok((stored, code));
+    };
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, stored.as_slice())
+        .map_err(|_| Error::Internal("failed to encrypt chunk".to_string()))?;
+    let mut sealed = Vec::with_capacity(CHUNK_NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok((sealed, code | CHUNK_ENCRYPTED))
+}
+
+/// Hash identifying `data` in `fs_blob`.
+///
+/// With a blob key set the hash is keyed, so it does not reveal a plaintext
+/// hash anyone could compute.
+fn blob_hash(data: &[u8], encoding: &ChunkEncoding) -> Vec<u8> {
+    let hash = match encoding.key.read().unwrap().as_ref() {
+        Some(key) => blake3::keyed_hash(&key.0, data),
+        None => blake3::hash(data),
+    };
+    hash.as_bytes().to_vec()
+}
+
+/// Read a stored chunk from `row`, whose `data` and `compression` columns are
+/// at `idx` and `idx + 1`, decrypting and decompressing it if needed.
+fn chunk_from_row(
+    row: &turso::Row,
+    idx: usize,
+    encoding: &ChunkEncoding,
+) -> Result<Option<Vec<u8>>> {
+    let Ok(Value::Blob(mut data)) = row.get_value(idx) else {
+        return Ok(None);
+    };
+    let mut code = row
+        .get_value(idx + 1)
+        .ok()
+        .and_then(|v| v.as_integer().copied())
+        .unwrap_or(0) as u8;
+
+    if code & CHUNK_ENCRYPTED != 0 {
+        let key = encoding.key.read().unwrap();
+        let Some(key) = key.as_ref() else {
+            return Err(Error::InvalidEncryptionKey(
+                "file contents are encrypted and no blob key is set".to_string(),
+            ));
+        };
+        if data.len() < CHUNK_NONCE_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "encrypted chunk is too short",
+            )
+            .into());
+        }
+        let (nonce, ciphertext) = data.split_at(CHUNK_NONCE_LEN);
+        data = key
+            .cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "encrypted chunk failed authentication",
+                )
+            })?;
+        code &= !CHUNK_ENCRYPTED;
+    }
+
+    match CompressionKind::from_code(code) {
+        Some(CompressionKind::Zstd) => Ok(Some(zstd::decode_all(data.as_slice())?)),
+        None if code == COMPRESSION_NONE => Ok(Some(data)),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unknown chunk compression {}", code),
+        )
+        .into()),
+    }
+}
+
+/// SQL expression for the 512-byte blocks stored for the `fs_inode` row
+/// named `$inode` in a stats query, rounded up from its `allocated` bytes.
+macro_rules! blocks_column {
+    ($inode:literal) => {
+        concat!("(", $inode, ".allocated + 511) / 512")
+    };
+}
+
+/// Read chunk `chunk_index` of `ino`, wherever its bytes are stored.
+async fn read_chunk(
+    conn: &Connection,
+    encoding: &ChunkEncoding,
+    ino: i64,
+    chunk_index: i64,
+) -> Result<Option<Vec<u8>>> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT COALESCE(b.data, d.data), COALESCE(b.compression, d.compression)
+            FROM fs_data d LEFT JOIN fs_blob b ON b.hash = d.hash
+            WHERE d.ino = ? AND d.chunk_index = ?",
+        )
+        .await?;
+    let mut rows = stmt.query((ino, chunk_index)).await?;
+    match rows.next().await? {
+        Some(row) => chunk_from_row(&row, 0, encoding),
+        None => Ok(None),
+    }
+}
+
+/// Read up to `size` bytes of `ino` starting at `offset`, stopping at the
+/// end of file.
+///
+/// Holes, missing chunks and chunks shorter than `chunk_size` read as zeros,
+/// so sparse regions need no stored data.
+async fn read_range(
+    conn: &Connection,
+    encoding: &ChunkEncoding,
+    chunk_size: u64,
+    ino: i64,
+    offset: u64,
+    size: u64,
+) -> Result<Vec<u8>> {
+    // Get the file size to avoid returning data beyond EOF
+    let mut size_stmt = conn
+        .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
+        .await?;
+    let mut size_rows = size_stmt.query((ino,)).await?;
+    let file_size = if let Some(row) = size_rows.next().await? {
+        row.get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .unwrap_or(0) as u64
+    } else {
+        0
+    };
+
+    // If offset is at or beyond EOF, return empty
+    if offset >= file_size {
+        return Ok(Vec::new());
+    }
+
+    // Limit size to not exceed EOF
+    let size = std::cmp::min(size, file_size - offset);
+
+    let start_chunk = offset / chunk_size;
+    let end_chunk = (offset + size).saturating_sub(1) / chunk_size;
+
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT d.chunk_index, COALESCE(b.data, d.data), COALESCE(b.compression, d.compression)
+            FROM fs_data d LEFT JOIN fs_blob b ON b.hash = d.hash
+            WHERE d.ino = ? AND d.chunk_index >= ? AND d.chunk_index <= ?
+            ORDER BY d.chunk_index",
+        )
+        .await?;
+    let mut rows = stmt
+        .query((ino, start_chunk as i64, end_chunk as i64))
+        .await?;
+
+    let mut result = Vec::with_capacity(size as usize);
+    let start_offset_in_chunk = (offset % chunk_size) as usize;
+    let mut next_expected_chunk = start_chunk;
+
+    while let Some(row) = rows.next().await? {
+        let chunk_index = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .unwrap_or(0) as u64;
+
+        // Fill gaps with zeros for sparse files
+        while next_expected_chunk < chunk_index && result.len() < size as usize {
+            let skip = if next_expected_chunk == start_chunk {
+                start_offset_in_chunk
+            } else {
+                0
+            };
+            let zeros_needed =
+                std::cmp::min(chunk_size as usize - skip, size as usize - result.len());
+            result.extend(std::iter::repeat_n(0u8, zeros_needed));
+            next_expected_chunk += 1;
+        }
+
+        if let Some(chunk_data) = chunk_from_row(&row, 1, encoding)? {
+            let skip = if chunk_index == start_chunk {
+                start_offset_in_chunk
+            } else {
+                0
+            };
+            if skip >= chunk_data.len() {
+                // Chunk is smaller than skip offset, fill with zeros
+                let zeros_needed =
+                    std::cmp::min(chunk_size as usize - skip, size as usize - result.len());
+                result.extend(std::iter::repeat_n(0u8, zeros_needed));
+            } else {
+                let remaining = size as usize - result.len();
+                let take = std::cmp::min(chunk_data.len() - skip, remaining);
+                result.extend_from_slice(&chunk_data[skip..skip + take]);
+
+                // If chunk is smaller than chunk_size, pad with zeros
+                let chunk_end = skip + take;
+                if chunk_end < chunk_size as usize && result.len() < size as usize {
+                    let zeros_needed = std::cmp::min(
+                        chunk_size as usize - chunk_end,
+                        size as usize - result.len(),
+                    );
+                    result.extend(std::iter::repeat_n(0u8, zeros_needed));
+                }
+            }
+        }
+        next_expected_chunk = chunk_index + 1;
+    }
+
+    // Fill any remaining space with zeros (for sparse file tail or missing chunks at end)
+    if result.len() < size as usize {
+        result.resize(size as usize, 0);
+    }
+
+    Ok(result)
+}
+
+/// Store `data` as chunk `chunk_index` of `ino`, replacing any previous
+/// version of the chunk.
+///
+/// With dedup enabled the bytes go to `fs_blob`, keyed by their BLAKE3 hash,
+/// and the chunk only refers to them. Content that is already stored just
+/// gains a reference instead of being written again.
+async fn store_chunk(
+    conn: &Connection,
+    encoding: &ChunkEncoding,
+    ino: i64,
+    chunk_index: i64,
+    data: &[u8],
+) -> Result<()> {
+    release_chunks(conn, ino, chunk_index, chunk_index).await?;
+
+    if !encoding.dedup.load(Ordering::Relaxed) {
+        let (stored, compression) = encode_chunk(data, encoding)?;
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO fs_data (ino, chunk_index, data, compression, hash)
+                VALUES (?, ?, ?, ?, NULL)",
+            )
+            .await?;
+        stmt.execute((ino, chunk_index, Value::Blob(stored), compression as i64))
+            .await?;
+        return account_chunks(conn, ino, chunk_index, chunk_index, 1).await;
+    }
+
+    let hash = blob_hash(data, encoding);
+    let mut stmt = conn
+        .prepare_cached("UPDATE fs_blob SET refcount = refcount + 1 WHERE hash = ?")
+        .await?;
+    if stmt.execute((Value::Blob(hash.clone()),)).await? == 0 {
+        let (stored, compression) = encode_chunk(data, encoding)?;
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_blob (hash, data, compression, refcount) VALUES (?, ?, ?, 1)",
+            )
+            .await?;
+        stmt.execute((
+            Value::Blob(hash.clone()),
+            Value::Blob(stored),
+            compression as i64,
+        ))
+        .await?;
+    }
+    let mut stmt = conn
+        .prepare_cached(
+            "INSERT OR REPLACE INTO fs_data (ino, chunk_index, data, compression, hash)
+            VALUES (?, ?, X'', 0, ?)",
+        )
+        .await?;
+    stmt.execute((ino, chunk_index, Value::Blob(hash))).await?;
+    account_chunks(conn, ino, chunk_index, chunk_index, 1).await
+}
+
+/// Make chunk `dst_index` of `dst_ino` a copy of chunk `src_index` of
+/// `src_ino` without decoding it.
+///
+/// The stored bytes are copied as they are, so compressed chunks stay
+/// compressed and deduplicated chunks take one more reference to their blob.
+/// A missing source chunk (a hole) leaves a hole in the destination.
+async fn share_chunk(
+    conn: &Connection,
+    src_ino: i64,
+    src_index: i64,
+    dst_ino: i64,
+    dst_index: i64,
+) -> Result<()> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT data, compression, hash FROM fs_data WHERE ino = ? AND chunk_index = ?",
+        )
+        .await?;
+    let mut rows = stmt.query((src_ino, src_index)).await?;
+    let source = match rows.next().await? {
+        Some(row) => Some((row.get_value(0)?, row.get_value(1)?, row.get_value(2)?)),
+        None => None,
+    };
+
+    // Take the new reference before dropping the old one, which may be the
+    // same blob
+    if let Some((_, _, Value::Blob(hash))) = &source {
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_blob SET refcount = refcount + 1 WHERE hash = ?")
+            .await?;
+        stmt.execute((Value::Blob(hash.clone()),)).await?;
+    }
+    release_chunks(conn, dst_ino, dst_index, dst_index).await?;
+
+    match source {
+        Some((data, compression, hash)) => {
+            let mut stmt = conn
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO fs_data (ino, chunk_index, data, compression, hash)
+                    VALUES (?, ?, ?, ?, ?)",
+                )
+                .await?;
+            stmt.execute((dst_ino, dst_index, data, compression, hash))
+                .await?;
+            account_chunks(conn, dst_ino, dst_index, dst_index, 1).await?;
+        }
+        None => {
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_data WHERE ino = ? AND chunk_index = ?")
+                .await?;
+            stmt.execute((dst_ino, dst_index)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Move chunk `chunk_index` of `ino` into `fs_blob` if it is stored inline,
+/// so that other chunks can refer to it.
+///
+/// The stored bytes move as they are, under the hash of their contents, so
+/// content already in `fs_blob` just gains a reference.
+async fn promote_chunk(
+    conn: &Connection,
+    encoding: &ChunkEncoding,
+    ino: i64,
+    chunk_index: i64,
+) -> Result<()> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT data, compression FROM fs_data
+            WHERE ino = ? AND chunk_index = ? AND hash IS NULL",
+        )
+        .await?;
+    let mut rows = stmt.query((ino, chunk_index)).await?;
+    let Some(row) = rows.next().await? else {
+        return Ok(());
+    };
+    let Some(data) = chunk_from_row(&row, 0, encoding)? else {
+        return Ok(());
+    };
+    let (stored, compression) = (row.get_value(0)?, row.get_value(1)?);
+
+    // Content already in `fs_blob` may be stored in a different size
+    account_chunks(conn, ino, chunk_index, chunk_index, -1).await?;
+    let hash = blob_hash(&data, encoding);
+    let mut stmt = conn
+        .prepare_cached("UPDATE fs_blob SET refcount = refcount + 1 WHERE hash = ?")
+        .await?;
+    if stmt.execute((Value::Blob(hash.clone()),)).await? == 0 {
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_blob (hash, data, compression, refcount) VALUES (?, ?, ?, 1)",
+            )
+            .await?;
+        stmt.execute((Value::Blob(hash.clone()), stored, compression))
+            .await?;
+    }
+    let mut stmt = conn
+        .prepare_cached(
+            "UPDATE fs_data SET data = X'', compression = 0, hash = ?
+            WHERE ino = ? AND chunk_index = ?",
+        )
+        .await?;
+    stmt.execute((Value::Blob(hash), ino, chunk_index)).await?;
+    account_chunks(conn, ino, chunk_index, chunk_index, 1).await
+}
+
+/// Delete the chunks of `ino` from `first_chunk` onwards.
+async fn delete_chunks(conn: &Connection, ino: i64, first_chunk: i64) -> Result<()> {
+    release_chunks(conn, ino, first_chunk, i64::MAX).await?;
+    let mut stmt = conn
+        .prepare_cached("DELETE FROM fs_data WHERE ino = ? AND chunk_index >= ?")
+        .await?;
+    stmt.execute((ino, first_chunk)).await?;
+    Ok(())
+}
+
+/// Drop the `fs_blob` references and the allocated bytes held by chunks
+/// `first..=last` of `ino`, ahead of the chunks being replaced or deleted.
+async fn release_chunks(conn: &Connection, ino: i64, first: i64, last: i64) -> Result<()> {
+    account_chunks(conn, ino, first, last, -1).await?;
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT hash FROM fs_data
+            WHERE ino = ? AND chunk_index >= ? AND chunk_index <= ? AND hash IS NOT NULL",
+        )
+        .await?;
+    let mut rows = stmt.query((ino, first, last)).await?;
+    let hashes = collect_hashes(&mut rows).await?;
+    release_blobs(conn, hashes).await
+}
+
+/// Add the stored size of chunks `first..=last` of `ino`, times `sign`, to
+/// its `fs_inode.allocated`.
+///
+/// Deduplicated chunks count the size of their blob, so this has to run
+/// while the blobs are still there.
+async fn account_chunks(
+    conn: &Connection,
+    ino: i64,
+    first: i64,
+    last: i64,
+    sign: i64,
+) -> Result<()> {
+    // Summed first and added separately, since subqueries are not supported
+    // in an UPDATE's SET clause
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT COALESCE(SUM(LENGTH(COALESCE(b.data, d.data))), 0)
+            FROM fs_data d LEFT JOIN fs_blob b ON b.hash = d.hash
+            WHERE d.ino = ? AND d.chunk_index >= ? AND d.chunk_index <= ?",
+        )
+        .await?;
+    let mut rows = stmt.query((ino, first, last)).await?;
+    let bytes = match rows.next().await? {
+        Some(row) => row_integer(&row, 0),
+        None => 0,
+    };
+    drop(rows);
+    if bytes == 0 {
+        return Ok(());
+    }
+    let mut stmt = conn
+        .prepare_cached("UPDATE fs_inode SET allocated = allocated + ? WHERE ino = ?")
+        .await?;
+    stmt.execute((sign * bytes, ino)).await?;
+    Ok(())
+}
+
+/// Collect the blob hashes returned in the first column of `rows`.
+async fn collect_hashes(rows: &mut turso::Rows) -> Result<Vec<Vec<u8>>> {
+    let mut hashes = Vec::new();
+    while let Some(row) = rows.next().await? {
+        if let Ok(Value::Blob(hash)) = row.get_value(0) {
+            hashes.push(hash);
+        }
+    }
+    Ok(hashes)
+}
+
+/// Drop one reference to each blob in `hashes` (once per occurrence),
+/// deleting blobs that are no longer referenced.
+async fn release_blobs(conn: &Connection, hashes: Vec<Vec<u8>>) -> Result<()> {
+    for hash in hashes {
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_blob SET refcount = refcount - 1 WHERE hash = ?")
+            .await?;
+        stmt.execute((Value::Blob(hash.clone()),)).await?;
+        let mut stmt = conn
+            .prepare_cached("DELETE FROM fs_blob WHERE hash = ? AND refcount <= 0")
+            .await?;
+        stmt.execute((Value::Blob(hash),)).await?;
+    }
+    Ok(())
+}
+
+/// Key under which a case-insensitive filesystem stores and looks up a name.
+fn fold_name(name: &str) -> String {
+    name.to_lowercase()
+}
 
 /// LRU cache for directory entry lookups.
 ///
@@ -27,14 +672,25 @@ const DENTRY_CACHE_MAX_SIZE: usize = 10000;
 struct DentryCache {
     // Mutex required because LruCache::get() mutates internal order
     entries: Mutex<LruCache<(i64, String), i64>>,
+    /// Names differing only in case share an entry
+    case_insensitive: bool,
 }
 
 impl DentryCache {
-    fn new(max_size: usize) -> Self {
+    fn new(max_size: usize, case_insensitive: bool) -> Self {
         Self {
             entries: Mutex::new(LruCache::new(
                 NonZeroUsize::new(max_size).expect("cache size must be > 0"),
             )),
+            case_insensitive,
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.case_insensitive {
+            fold_name(name)
+        } else {
+            name.to_string()
         }
     }
 
@@ -43,7 +699,7 @@ impl DentryCache {
         self.entries
             .lock()
             .unwrap()
-            .get(&(parent_ino, name.to_string()))
+            .get(&(parent_ino, self.key(name)))
             .copied()
     }
 
@@ -52,7 +708,7 @@ impl DentryCache {
         self.entries
             .lock()
             .unwrap()
-            .put((parent_ino, name.to_string()), child_ino);
+            .put((parent_ino, self.key(name)), child_ino);
     }
 
     /// Remove an entry from the cache
@@ -60,17 +716,51 @@ impl DentryCache {
         self.entries
             .lock()
             .unwrap()
-            .pop(&(parent_ino, name.to_string()));
+            .pop(&(parent_ino, self.key(name)));
+    }
+
+    /// Drop all cached entries
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
     }
 }
 
 /// A filesystem backed by SQLite
+///
+/// Inode numbers come from an `AUTOINCREMENT` key and are never reused, not
+/// even after the newest inode is deleted, a snapshot is restored or the
+/// database is reopened. A number cached by a mount can therefore never
+/// alias a different file.
 #[derive(Clone)]
 pub struct AgentFS {
     pool: ConnectionPool,
     chunk_size: usize,
     /// Cache for directory entry lookups (shared across clones)
     dentry_cache: Arc<DentryCache>,
+    /// Byte quota from `fs_config`, 0 when unlimited (shared across clones)
+    max_bytes: Arc<AtomicU64>,
+    /// Creation umask from `fs_config` (shared across clones)
+    umask: Arc<AtomicU32>,
+    /// How new chunks are stored (shared across clones)
+    encoding: Arc<ChunkEncoding>,
+    /// Change notifications (shared across clones)
+    events: broadcast::Sender<ChangeEvent>,
+    /// Advisory file locks (shared across clones)
+    locks: Arc<LockTable>,
+    /// Background checkpoint task, stopped with the last clone
+    checkpointer: Arc<Mutex<Option<Checkpointer>>>,
+    /// Retries of a busy write lock (shared across clones and open files)
+    busy_retry: Arc<Mutex<BusyRetry>>,
+    /// Access time policy from `fs_config` (shared across clones and open files)
+    atime_policy: Arc<AtomicU8>,
+    /// Whether names are looked up ignoring case, from `fs_config`
+    case_insensitive: bool,
+    /// Trash policy from `fs_config`, if removals go to the trash
+    trash: Arc<Mutex<Option<TrashPolicy>>>,
+    /// Writes buffered by open files (shared across clones and open files)
+    write_buffer: Arc<WriteBuffer>,
+    /// Background task storing buffered writes, stopped with the last clone
+    write_flusher: Arc<Mutex<Option<WriteFlusher>>>,
 }
 
 /// An open file handle for AgentFS.
@@ -81,205 +771,760 @@ pub struct AgentFSFile {
     pool: ConnectionPool,
     ino: i64,
     chunk_size: usize,
+    max_bytes: Arc<AtomicU64>,
+    encoding: Arc<ChunkEncoding>,
+    events: broadcast::Sender<ChangeEvent>,
+    busy_retry: Arc<Mutex<BusyRetry>>,
+    atime_policy: Arc<AtomicU8>,
+    write_buffer: Arc<WriteBuffer>,
+    /// Opened with `O_APPEND`: every write goes to the current end of file
+    append: bool,
 }
 
-#[async_trait]
-impl File for AgentFSFile {
-    async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
-        let conn = self.pool.get_connection().await?;
+/// When [`AgentFS`] copies its write-ahead log back into the database file.
+///
+/// Heavy write workloads can let the log grow large between checkpoints.
+/// A policy other than `Never` runs a background task that checkpoints on
+/// top of explicit [`FileSystem::sync_all`] calls; each checkpoint truncates
+/// the log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CheckpointPolicy {
+    /// No background checkpoints
+    #[default]
+    Never,
+    /// Checkpoint after every `n` changes made through the filesystem
+    EveryNWrites(u64),
+    /// Checkpoint on a fixed interval, skipping intervals without changes
+    Interval(Duration),
+}
 
-        // Get the file size to avoid returning data beyond EOF
-        let mut size_stmt = conn
-            .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut size_rows = size_stmt.query((self.ino,)).await?;
-        let file_size = if let Some(row) = size_rows.next().await? {
-            row.get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64
-        } else {
-            0
-        };
+/// How [`AgentFS`] retries taking the database write lock while another
+/// connection holds it.
+///
+/// Each retry waits twice as long as the one before, from `initial_backoff`
+/// up to `max_backoff`. Once `max_retries` retries have failed too, the
+/// operation fails with `FsError::Busy`. The lock is taken before a write
+/// changes anything, so a retried operation is never applied twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyRetry {
+    /// Retries after the first attempt; 0 fails on the first busy lock
+    pub max_retries: u32,
+    /// Wait before the first retry
+    pub initial_backoff: Duration,
+    /// Longest wait between two retries
+    pub max_backoff: Duration,
+}
 
-        // If offset is at or beyond EOF, return empty
-        if offset >= file_size {
-            return Ok(Vec::new());
+impl Default for BusyRetry {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_millis(500),
         }
+    }
+}
 
-        // Limit size to not exceed EOF
-        let size = std::cmp::min(size, file_size - offset);
-
-        let chunk_size = self.chunk_size as u64;
-        let start_chunk = offset / chunk_size;
-        let end_chunk = (offset + size).saturating_sub(1) / chunk_size;
-
-        let mut stmt = conn
-            .prepare_cached("SELECT chunk_index, data FROM fs_data WHERE ino = ? AND chunk_index >= ? AND chunk_index <= ? ORDER BY chunk_index")
-            .await?;
-        let mut rows = stmt
-            .query((self.ino, start_chunk as i64, end_chunk as i64))
-            .await?;
-
-        let mut result = Vec::with_capacity(size as usize);
-        let start_offset_in_chunk = (offset % chunk_size) as usize;
-        let mut next_expected_chunk = start_chunk;
-
-        while let Some(row) = rows.next().await? {
-            let chunk_index = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64;
-
-            // Fill gaps with zeros for sparse files
-            while next_expected_chunk < chunk_index && result.len() < size as usize {
-                let skip = if next_expected_chunk == start_chunk {
-                    start_offset_in_chunk
-                } else {
-                    0
-                };
-                let zeros_needed =
-                    std::cmp::min(chunk_size as usize - skip, size as usize - result.len());
-                result.extend(std::iter::repeat_n(0u8, zeros_needed));
-                next_expected_chunk += 1;
-            }
-
-            if let Ok(Value::Blob(chunk_data)) = row.get_value(1) {
-                let skip = if chunk_index == start_chunk {
-                    start_offset_in_chunk
-                } else {
-                    0
-                };
-                if skip >= chunk_data.len() {
-                    // Chunk is smaller than skip offset, fill with zeros
-                    let zeros_needed =
-                        std::cmp::min(chunk_size as usize - skip, size as usize - result.len());
-                    result.extend(std::iter::repeat_n(0u8, zeros_needed));
-                } else {
-                    let remaining = size as usize - result.len();
-                    let take = std::cmp::min(chunk_data.len() - skip, remaining);
-                    result.extend_from_slice(&chunk_data[skip..skip + take]);
-
-                    // If chunk is smaller than chunk_size, pad with zeros
-                    let chunk_end = skip + take;
-                    if chunk_end < chunk_size as usize && result.len() < size as usize {
-                        let zeros_needed = std::cmp::min(
-                            chunk_size as usize - chunk_end,
-                            size as usize - result.len(),
-                        );
-                        result.extend(std::iter::repeat_n(0u8, zeros_needed));
-                    }
+/// Begin an `IMMEDIATE` transaction, retrying while the write lock is busy.
+async fn begin_immediate<'a>(
+    conn: &'a Connection,
+    retry: &Mutex<BusyRetry>,
+) -> Result<Transaction<'a>> {
+    let policy = *retry.lock().unwrap();
+    let mut backoff = policy.initial_backoff;
+    for attempt in 0..=policy.max_retries {
+        match Transaction::new_unchecked(conn, TransactionBehavior::Immediate).await {
+            Ok(txn) => return Ok(txn),
+            Err(turso::Error::Busy(_) | turso::Error::BusySnapshot(_)) => {
+                if attempt < policy.max_retries {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(policy.max_backoff);
                 }
             }
-            next_expected_chunk = chunk_index + 1;
+            Err(e) => return Err(e.into()),
         }
+    }
+    Err(FsError::Busy.into())
+}
+
+/// When reads update the access time of a file or directory.
+///
+/// Every access time update is a write to the database, so reads that
+/// update it contend for the write lock like any other write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AtimePolicy {
+    /// Update the access time on every read
+    Always,
+    /// Update the access time only when it is not newer than the modification
+    /// or change time, or is more than a day old
+    Relatime,
+    /// Never update the access time on reads
+    #[default]
+    Noatime,
+}
 
-        // Fill any remaining space with zeros (for sparse file tail or missing chunks at end)
-        if result.len() < size as usize {
-            result.resize(size as usize, 0);
+impl AtimePolicy {
+    fn code(self) -> u8 {
+        match self {
+            AtimePolicy::Always => 0,
+            AtimePolicy::Relatime => 1,
+            AtimePolicy::Noatime => 2,
         }
+    }
 
-        Ok(result)
+    fn from_code(code: u8) -> Self {
+        match code {
+            0 => AtimePolicy::Always,
+            1 => AtimePolicy::Relatime,
+            _ => AtimePolicy::Noatime,
+        }
     }
 
-    async fn pwrite(&self, offset: u64, data: &[u8]) -> Result<()> {
-        if data.is_empty() {
-            return Ok(());
+    /// Name used in `fs_config` and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AtimePolicy::Always => "always",
+            AtimePolicy::Relatime => "relatime",
+            AtimePolicy::Noatime => "noatime",
         }
+    }
 
-        let conn = self.pool.get_connection().await?;
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
-        // Get current file size
-        let mut stmt = conn
-            .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((self.ino,)).await?;
-        let current_size = if let Some(row) = rows.next().await? {
-            row.get_value(0)
+    /// Whether a read at `now` updates an access time of `atime`, given the
+    /// modification and change times of the same inode.
+    fn needs_update(
+        self,
+        atime: (i64, i64),
+        mtime: (i64, i64),
+        ctime: (i64, i64),
+        now: i64,
+    ) -> bool {
+        match self {
+            AtimePolicy::Always => true,
+            AtimePolicy::Relatime => {
+                atime <= mtime || atime <= ctime || now - atime.0 >= RELATIME_INTERVAL_SECS
+            }
+            AtimePolicy::Noatime => false,
+        }
+    }
+}
+
+impl std::fmt::Display for AtimePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for AtimePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "always" | "strictatime" => Ok(AtimePolicy::Always),
+            "relatime" => Ok(AtimePolicy::Relatime),
+            "noatime" => Ok(AtimePolicy::Noatime),
+            _ => Err(format!(
+                "unknown atime policy '{}' (expected: always, relatime, noatime)",
+                s
+            )),
+        }
+    }
+}
+
+/// Age after which [`AtimePolicy::Relatime`] updates the access time anyway.
+const RELATIME_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+/// Record a read of `ino` in its access time, as the policy asks.
+///
+/// The update is best effort: a read must not fail because another
+/// connection holds the write lock, so errors are only logged.
+async fn touch_atime(conn: &Connection, ino: i64, policy: &AtomicU8) {
+    if let Err(e) = update_atime(conn, ino, policy).await {
+        tracing::debug!("failed to update the access time of inode {}: {}", ino, e);
+    }
+}
+
+async fn update_atime(conn: &Connection, ino: i64, policy: &AtomicU8) -> Result<()> {
+    let policy = AtimePolicy::from_code(policy.load(Ordering::Relaxed));
+    if policy == AtimePolicy::Noatime {
+        return Ok(());
+    }
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT atime, atime_nsec, mtime, mtime_nsec, ctime, ctime_nsec FROM fs_inode WHERE ino = ?",
+        )
+        .await?;
+    let mut rows = stmt.query((ino,)).await?;
+    let Some(row) = rows.next().await? else {
+        return Ok(());
+    };
+    let time = |idx: usize| -> (i64, i64) {
+        let get = |idx| {
+            row.get_value(idx)
                 .ok()
                 .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64
-        } else {
-            0
+                .unwrap_or(0)
         };
+        (get(idx), get(idx + 1))
+    };
+    let (atime, mtime, ctime) = (time(0), time(2), time(4));
+    drop(rows);
+
+    let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let now = dur.as_secs() as i64;
+    if !policy.needs_update(atime, mtime, ctime, now) {
+        return Ok(());
+    }
+    conn.execute(
+        "UPDATE fs_inode SET atime = ?, atime_nsec = ? WHERE ino = ?",
+        (now, dur.subsec_nanos() as i64, ino),
+    )
+    .await?;
+    Ok(())
+}
 
-        // Write the actual data (sparse gaps are handled by pread which fills
-        // missing chunks with zeros, so no need to zero-fill here)
-        self.write_data_at_offset_with_conn(&conn, offset, data)
-            .await?;
+/// Background task applying a [`CheckpointPolicy`], aborted when dropped.
+struct Checkpointer {
+    task: tokio::task::JoinHandle<()>,
+}
 
-        // Update file size and mtime
-        let new_size = std::cmp::max(current_size, offset + data.len() as u64);
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Background task storing buffered writes on an interval, aborted when
+/// dropped after a last flush.
+struct WriteFlusher {
+    task: tokio::task::JoinHandle<()>,
+    fs: AgentFS,
+}
+
+impl Drop for WriteFlusher {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let fs = self.fs.clone();
+            runtime.spawn(async move {
+                if let Err(e) = fs.flush_writes().await {
+                    tracing::warn!("failed to store buffered writes: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// What changed since a snapshot, as reported by [`AgentFS::changes_since`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotChanges {
+    /// Paths created or modified since the snapshot, parents before children
+    pub changed: Vec<String>,
+    /// Paths that existed at the snapshot and no longer do, without the
+    /// contents of deleted directories
+    pub deleted: Vec<String>,
+}
+
+/// A directory entry with its path, as listed by [`entry_paths`].
+struct PathEntry {
+    path: String,
+    dentry_id: i64,
+    ino: i64,
+    mtime: i64,
+    ctime: i64,
+}
+
+/// Every directory entry with its absolute path, parents before children
+/// and siblings in name order.
+async fn entry_paths(conn: &Connection) -> Result<Vec<PathEntry>> {
+    let mut rows = conn
+        .query(
+            "SELECT d.id, d.parent_ino, d.name, d.ino, i.mtime, i.ctime
+            FROM fs_dentry d JOIN fs_inode i ON d.ino = i.ino
+            ORDER BY d.name",
+            (),
+        )
+        .await?;
+    let mut children: HashMap<i64, Vec<(String, PathEntry)>> = HashMap::new();
+    while let Some(row) = rows.next().await? {
+        let int = |i: usize| {
+            row.get_value(i)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0)
+        };
+        let Ok(Value::Text(name)) = row.get_value(2) else {
+            continue;
+        };
+        let entry = PathEntry {
+            path: String::new(),
+            dentry_id: int(0),
+            ino: int(3),
+            mtime: int(4),
+            ctime: int(5),
+        };
+        children.entry(int(1)).or_default().push((name, entry));
+    }
+
+    let mut entries = Vec::new();
+    // Each directory is visited once, so hard-linked directories and
+    // cycles in a corrupt database cannot make the walk loop
+    let mut visited = HashSet::from([ROOT_INO]);
+    let mut stack = vec![(ROOT_INO, String::new())];
+    while let Some((dir_ino, dir_path)) = stack.pop() {
+        let Some(dir_children) = children.remove(&dir_ino) else {
+            continue;
+        };
+        let start = entries.len();
+        for (name, mut entry) in dir_children {
+            entry.path = format!("{}/{}", dir_path, name);
+            entries.push(entry);
+        }
+        // Pushed in reverse so the first subdirectory is walked first
+        for entry in entries[start..].iter().rev() {
+            if visited.insert(entry.ino) {
+                stack.push((entry.ino, entry.path.clone()));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Keeps removed entries recoverable; see [`AgentFS::set_trash`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrashPolicy {
+    /// How long removed entries are kept before [`AgentFS::compact`] frees
+    /// them; `None` keeps them until the trash is emptied
+    pub retention: Option<Duration>,
+}
+
+/// An entry in the trash, as listed by [`AgentFS::list_trash`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    /// Path the entry was removed from
+    pub path: String,
+    /// Inode the entry refers to
+    pub ino: i64,
+    /// When the entry was removed, in seconds since the Unix epoch
+    pub deleted_at: i64,
+    /// Mode of the inode, including the file type
+    pub mode: u32,
+    /// Size of the inode in bytes
+    pub size: i64,
+}
+
+/// Outcome of [`AgentFS::compact`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactStats {
+    /// Inodes removed because no directory entry referred to them
+    pub orphaned_inodes: u64,
+    /// Trash entries freed because their retention expired
+    pub expired_trash: u64,
+    /// Bytes of file data stored before compaction
+    pub bytes_before: u64,
+    /// Bytes of file data stored after compaction
+    pub bytes_after: u64,
+}
+
+impl CompactStats {
+    /// Bytes of file data freed by compaction
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+/// A problem found by [`AgentFS::check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// A directory entry whose inode or parent directory does not exist
+    DanglingEntry {
+        parent_ino: i64,
+        name: String,
+        ino: i64,
+    },
+    /// An inode, other than the root, that no directory entry refers to
+    OrphanedInode { ino: i64 },
+    /// Chunks, a symlink target or xattrs stored for an inode that does not exist
+    OrphanedData { ino: i64 },
+    /// An inode whose link count does not match its directory entries
+    WrongLinkCount { ino: i64, found: i64, expected: i64 },
+    /// A deduplicated chunk whose content is missing from `fs_blob`
+    MissingBlob { ino: i64, chunk_index: i64 },
+    /// Deduplicated content whose reference count does not match its chunks
+    WrongBlobRefcount {
+        hash: Vec<u8>,
+        found: i64,
+        expected: i64,
+    },
+    /// Deduplicated content that no chunk refers to
+    OrphanedBlob { hash: Vec<u8> },
+    /// An overlay whiteout for a path that does not exist in the base
+    StaleWhiteout { path: String },
+}
+
+impl Inconsistency {
+    /// Whether [`AgentFS::repair`] (or `OverlayFS::repair` for whiteouts)
+    /// can fix this without losing file contents
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, Inconsistency::MissingBlob { .. })
+    }
+}
+
+impl std::fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = |hash: &[u8]| -> String { hash.iter().map(|b| format!("{b:02x}")).collect() };
+        match self {
+            Inconsistency::DanglingEntry {
+                parent_ino,
+                name,
+                ino,
+            } => write!(
+                f,
+                "entry '{}' in directory {} refers to inode {}, but one of them does not exist",
+                name, parent_ino, ino
+            ),
+            Inconsistency::OrphanedInode { ino } => {
+                write!(f, "inode {} has no directory entry", ino)
+            }
+            Inconsistency::OrphanedData { ino } => {
+                write!(f, "data is stored for missing inode {}", ino)
+            }
+            Inconsistency::WrongLinkCount {
+                ino,
+                found,
+                expected,
+            } => write!(
+                f,
+                "inode {} has link count {}, expected {}",
+                ino, found, expected
+            ),
+            Inconsistency::MissingBlob { ino, chunk_index } => write!(
+                f,
+                "chunk {} of inode {} refers to missing content",
+                chunk_index, ino
+            ),
+            Inconsistency::WrongBlobRefcount {
+                hash,
+                found,
+                expected,
+            } => write!(
+                f,
+                "blob {} has reference count {}, expected {}",
+                hex(hash),
+                found,
+                expected
+            ),
+            Inconsistency::OrphanedBlob { hash } => {
+                write!(f, "blob {} is not referenced by any chunk", hex(hash))
+            }
+            Inconsistency::StaleWhiteout { path } => {
+                write!(f, "whiteout for {} hides nothing in the base", path)
+            }
+        }
+    }
+}
+
+/// The kind of change reported by a [`ChangeEvent`].
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeEventKind {
+    /// A file, symlink, special file or hard link was created
+    Create = 0,
+    /// File contents or size changed
+    Write = 1,
+    /// A directory entry was removed
+    Remove = 2,
+    /// An entry was renamed; sent once for the old path and once for the new
+    Rename = 3,
+    /// A directory was created
+    Mkdir = 4,
+}
+
+/// A change made through an [`AgentFS`] instance.
+///
+/// Events are delivered to receivers obtained from [`AgentFS::subscribe`].
+/// Delivery is best-effort: changes made by other processes sharing the
+/// database are not reported, and a receiver that falls too far behind gets
+/// [`broadcast::error::RecvError::Lagged`] instead of the dropped events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub kind: ChangeEventKind,
+    /// Absolute path of the affected entry
+    pub path: String,
+}
+
+/// Reconstruct the absolute path of an inode from its directory entries.
+///
+/// Inodes with several hard links resolve to one of them. Returns `None` for
+/// inodes no longer reachable from the root.
+async fn path_of(conn: &Connection, mut ino: i64) -> Result<Option<String>> {
+    let mut names = Vec::new();
+    let mut stmt = conn
+        .prepare_cached("SELECT parent_ino, name FROM fs_dentry WHERE ino = ? LIMIT 1")
+        .await?;
+    while ino != ROOT_INO {
+        // A cycle can only come from a corrupt tree; bail out rather than spin
+        if names.len() > 4096 {
+            return Ok(None);
+        }
+        let mut rows = stmt.query((ino,)).await?;
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        let parent = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("invalid parent_ino".to_string()))?;
+        let name = match row.get_value(1) {
+            Ok(Value::Text(name)) => name,
+            _ => return Err(Error::Internal("invalid dentry name".to_string())),
+        };
+        names.push(name);
+        ino = parent;
+    }
+    names.reverse();
+    Ok(Some(format!("/{}", names.join("/"))))
+}
+
+/// Send a change event for `ino` to any subscribers.
+///
+/// Failures to resolve the path are swallowed: notifications never fail the
+/// operation that triggered them.
+async fn notify_ino(
+    events: &broadcast::Sender<ChangeEvent>,
+    conn: &Connection,
+    kind: ChangeEventKind,
+    ino: i64,
+) {
+    if events.receiver_count() == 0 {
+        return;
+    }
+    if let Ok(Some(path)) = path_of(conn, ino).await {
+        let _ = events.send(ChangeEvent { kind, path });
+    }
+}
+
+/// Join a directory path and an entry name
+fn child_path(parent: &str, name: &str) -> String {
+    if parent == "/" {
+        format!("/{name}")
+    } else {
+        format!("{parent}/{name}")
+    }
+}
+
+/// A cursor over the entries of an AgentFS directory.
+///
+/// Entries are fetched in name order one page at a time, each page resuming
+/// after the last name returned. Memory use is bounded by the page size, and
+/// concurrent changes never cause an entry to be returned twice.
+pub struct AgentFSDirStream {
+    pool: ConnectionPool,
+    parent_ino: i64,
+    last_name: String,
+    buffer: VecDeque<DirEntry>,
+    exhausted: bool,
+}
+
+impl AgentFSDirStream {
+    /// Fetch the next page of entries into the buffer.
+    async fn fetch_page(&mut self) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
         let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET size = ?, mtime = ?, mtime_nsec = ? WHERE ino = ?")
+            .prepare_cached(
+                concat!(
+                    "SELECT i.ino, i.mode, i.nlink, i.uid, i.gid, i.size, i.atime, i.mtime, i.ctime, i.rdev, i.atime_nsec, i.mtime_nsec, i.ctime_nsec, ",
+                    blocks_column!("i"),
+                    ", d.name
+                    FROM fs_dentry d
+                    JOIN fs_inode i ON d.ino = i.ino
+                    WHERE d.parent_ino = ? AND d.name > ?
+                    ORDER BY d.name
+                    LIMIT ?"
+                ),
+            )
             .await?;
-        stmt.execute((new_size as i64, now_secs, now_nsec, self.ino))
+        let mut rows = stmt
+            .query((self.parent_ino, self.last_name.as_str(), READDIR_PAGE_SIZE))
             .await?;
-        txn.commit().await?;
 
+        let mut fetched = 0;
+        while let Some(row) = rows.next().await? {
+            fetched += 1;
+            let Ok(Value::Text(name)) = row.get_value(14) else {
+                continue;
+            };
+            let stats = AgentFS::build_stats_from_row(&row)?;
+            self.last_name = name.clone();
+            self.buffer.push_back(DirEntry { name, stats });
+        }
+
+        if fetched < READDIR_PAGE_SIZE {
+            self.exhausted = true;
+        }
         Ok(())
     }
+}
 
-    async fn truncate(&self, new_size: u64) -> Result<()> {
+#[async_trait]
+impl DirStream for AgentFSDirStream {
+    async fn next_entry(&mut self) -> Result<Option<DirEntry>> {
+        if self.buffer.is_empty() && !self.exhausted {
+            self.fetch_page().await?;
+        }
+        Ok(self.buffer.pop_front())
+    }
+}
+
+/// A cursor over the contents of an AgentFS file.
+///
+/// Chunks are fetched in order one page at a time, so a whole file is read
+/// with one query per page rather than one per chunk, and memory use is
+/// bounded by the page size. Writes still buffered by write-back are read
+/// through as `pread` would.
+pub struct AgentFSReadStream {
+    pool: ConnectionPool,
+    ino: i64,
+    chunk_size: u64,
+    encoding: Arc<ChunkEncoding>,
+    write_buffer: Arc<WriteBuffer>,
+    offset: u64,
+    buffer: VecDeque<Vec<u8>>,
+    exhausted: bool,
+}
+
+impl AgentFSReadStream {
+    /// Fetch the next page of chunks into the buffer.
+    async fn fetch_page(&mut self) -> Result<()> {
+        let page = self.chunk_size * READ_STREAM_PAGE_CHUNKS;
         let conn = self.pool.get_connection().await?;
+        let _gate = self.write_buffer.read_gate(self.ino).await;
+        let mut data = read_range(
+            &conn,
+            &self.encoding,
+            self.chunk_size,
+            self.ino,
+            self.offset,
+            page,
+        )
+        .await?;
+        self.write_buffer
+            .read_through(self.ino, self.offset, page, &mut data)?;
 
-        // Get current size
-        let mut stmt = conn
-            .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((self.ino,)).await?;
-        let current_size = if let Some(row) = rows.next().await? {
-            row.get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64
+        if (data.len() as u64) < page {
+            self.exhausted = true;
+        }
+        self.offset += data.len() as u64;
+        self.buffer
+            .extend(data.chunks(self.chunk_size as usize).map(<[u8]>::to_vec));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ReadStream for AgentFSReadStream {
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.buffer.is_empty() && !self.exhausted {
+            self.fetch_page().await?;
+        }
+        Ok(self.buffer.pop_front())
+    }
+}
+
+#[async_trait]
+impl File for AgentFSFile {
+    async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let conn = self.pool.get_connection().await?;
+        let gate = self.write_buffer.read_gate(self.ino).await;
+        let mut data = read_range(
+            &conn,
+            &self.encoding,
+            self.chunk_size as u64,
+            self.ino,
+            offset,
+            size,
+        )
+        .await?;
+        self.write_buffer
+            .read_through(self.ino, offset, size, &mut data)?;
+        drop(gate);
+        touch_atime(&conn, self.ino, &self.atime_policy).await;
+        Ok(data)
+    }
+
+    async fn pwrite(&self, offset: u64, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let offset = if self.append {
+            self.flush_buffered().await?;
+            None
         } else {
-            0
+            checked_file_end(offset, data.len() as u64)?;
+            if self.buffer_write(offset, data).await? {
+                return Ok(());
+            }
+            Some(offset)
         };
+        match (offset, self.write_buffer.batch_bytes()) {
+            (Some(offset), Some(batch)) if data.len() > batch => {
+                for (i, part) in data.chunks(batch).enumerate() {
+                    self.write_at(Some(offset + (i * batch) as u64), part)
+                        .await?;
+                }
+            }
+            _ => {
+                self.write_at(offset, data).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn truncate(&self, new_size: u64) -> Result<()> {
+        checked_file_end(new_size, 0)?;
+        self.flush_buffered().await?;
+        let conn = self.pool.get_connection().await?;
 
         let chunk_size = self.chunk_size as u64;
 
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
 
         let result: Result<()> = async {
+            // Get current size inside the transaction, so the quota check
+            // sees any concurrent extend
+            let mut stmt = conn
+                .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
+                .await?;
+            let mut rows = stmt.query((self.ino,)).await?;
+            let current_size = if let Some(row) = rows.next().await? {
+                row.get_value(0)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u64
+            } else {
+                0
+            };
+
+            check_quota(&conn, &self.max_bytes, new_size.saturating_sub(current_size)).await?;
+
             if new_size == 0 {
                 // Special case: truncate to zero - just delete all chunks
-                let mut stmt = conn
-                    .prepare_cached("DELETE FROM fs_data WHERE ino = ?")
-                    .await?;
-                stmt.execute((self.ino,)).await?;
+                delete_chunks(&conn, self.ino, 0).await?;
             } else if new_size < current_size {
                 // Shrinking: delete excess chunks and truncate last chunk if needed
                 let last_chunk_idx = (new_size - 1) / chunk_size;
 
                 // Delete all chunks beyond the last one we need
-                conn.execute(
-                    "DELETE FROM fs_data WHERE ino = ? AND chunk_index > ?",
-                    (self.ino, last_chunk_idx as i64),
-                )
-                .await?;
+                delete_chunks(&conn, self.ino, last_chunk_idx as i64 + 1).await?;
 
                 // Truncate the last chunk if needed
                 let offset_in_chunk = (new_size % chunk_size) as usize;
                 if offset_in_chunk > 0 {
-                    let mut stmt = conn
-                        .prepare_cached("SELECT data FROM fs_data WHERE ino = ? AND chunk_index = ?")
-                        .await?;
-                    let mut rows = stmt.query((self.ino, last_chunk_idx as i64)).await?;
-
-                    if let Some(row) = rows.next().await? {
-                        if let Ok(Value::Blob(mut chunk_data)) = row.get_value(0) {
-                            if chunk_data.len() > offset_in_chunk {
-                                chunk_data.truncate(offset_in_chunk);
-                                let mut stmt = conn
-                                    .prepare_cached("UPDATE fs_data SET data = ? WHERE ino = ? AND chunk_index = ?")
-                                    .await?;
-                                stmt.execute((Value::Blob(chunk_data), self.ino, last_chunk_idx as i64)).await?;
-                            }
+                    let chunk = read_chunk(&conn, &self.encoding, self.ino, last_chunk_idx as i64).await?;
+                    if let Some(mut chunk_data) = chunk {
+                        if chunk_data.len() > offset_in_chunk {
+                            chunk_data.truncate(offset_in_chunk);
+                            store_chunk(&conn, &self.encoding, self.ino, last_chunk_idx as i64, &chunk_data).await?;
                         }
                     }
                 }
@@ -305,64 +1550,363 @@ impl File for AgentFSFile {
             return result;
         }
         txn.commit().await?;
+        notify_ino(&self.events, &conn, ChangeEventKind::Write, self.ino).await;
         Ok(())
     }
 
     async fn fsync(&self) -> Result<()> {
+        self.flush_buffered().await?;
         let conn = self.pool.get_connection().await?;
-        conn.prepare_cached("PRAGMA synchronous = FULL")
-            .await?
-            .execute(())
-            .await?;
-        conn.prepare_cached("BEGIN").await?.execute(()).await?;
-        conn.prepare_cached("COMMIT").await?.execute(()).await?;
-        conn.prepare_cached("PRAGMA synchronous = OFF")
-            .await?
-            .execute(())
-            .await?;
-        Ok(())
+        sync_wal(&conn).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.flush_buffered().await
     }
 
     async fn fstat(&self) -> Result<Stats> {
         let conn = self.pool.get_connection().await?;
         let mut stmt = conn
-            .prepare_cached("SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec FROM fs_inode WHERE ino = ?")
+            .prepare_cached(concat!(
+                "SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec, ",
+                blocks_column!("fs_inode"),
+                " FROM fs_inode WHERE ino = ?"
+            ))
             .await?;
         let mut rows = stmt.query((self.ino,)).await?;
 
         if let Some(row) = rows.next().await? {
-            AgentFS::build_stats_from_row(&row)
+            let mut stats = AgentFS::build_stats_from_row(&row)?;
+            self.write_buffer.apply(&mut stats);
+            Ok(stats)
         } else {
             Err(FsError::NotFound.into())
         }
     }
 }
 
-impl AgentFSFile {
-    /// Write data at a specific offset, handling chunk boundaries.
-    /// Uses a provided connection to allow reuse within a transaction.
-    async fn write_data_at_offset_with_conn(
-        &self,
-        conn: &Connection,
-        offset: u64,
-        data: &[u8],
-    ) -> Result<()> {
-        let chunk_size = self.chunk_size as u64;
-        let mut written = 0usize;
-
-        if data.is_empty() {
-            return Ok(());
-        }
+/// Flush every committed transaction to stable storage.
+///
+/// Commits normally run with `synchronous = OFF`; committing an empty
+/// transaction in FULL mode syncs the write-ahead log, and with it every
+/// change committed before, file data and directory entries alike.
+async fn sync_wal(conn: &Connection) -> Result<()> {
+    conn.prepare_cached("PRAGMA synchronous = FULL")
+        .await?
+        .execute(())
+        .await?;
+    let result: Result<()> = async {
+        conn.prepare_cached("BEGIN").await?.execute(()).await?;
+        conn.prepare_cached("COMMIT").await?.execute(()).await?;
+        Ok(())
+    }
+    .await;
+    conn.prepare_cached("PRAGMA synchronous = OFF")
+        .await?
+        .execute(())
+        .await?;
+    result
+}
+
+/// Copy the write-ahead log into the database file and truncate it.
+///
+/// Fails with `FsError::WouldBlock` when a reader still using old log
+/// frames keeps the checkpoint from finishing.
+async fn checkpoint_wal(conn: &Connection) -> Result<()> {
+    conn.prepare_cached("PRAGMA synchronous = FULL")
+        .await?
+        .execute(())
+        .await?;
+    let busy: Result<bool> = async {
+        let mut rows = conn.query("PRAGMA wal_checkpoint(TRUNCATE)", ()).await?;
+        let busy = match rows.next().await? {
+            Some(row) => row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0),
+            None => 0,
+        };
+        Ok(busy != 0)
+    }
+    .await;
+    conn.prepare_cached("PRAGMA synchronous = OFF")
+        .await?
+        .execute(())
+        .await?;
+    if busy? {
+        return Err(FsError::WouldBlock.into());
+    }
+    Ok(())
+}
+
+/// Store the writes `fs` has buffered every `period`.
+async fn run_write_flushes(fs: AgentFS, period: Duration) {
+    let mut ticker = tokio::time::interval(period);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick completes immediately
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if let Err(e) = fs.flush_writes().await {
+            tracing::warn!("failed to store buffered writes: {}", e);
+        }
+    }
+}
+
+/// Checkpoint `pool` as `policy` asks, counting changes from `events`.
+///
+/// Returns once every sender of `events` is gone, though normally the task
+/// is aborted first when the filesystem is dropped.
+async fn run_checkpoints(
+    pool: ConnectionPool,
+    mut events: broadcast::Receiver<ChangeEvent>,
+    policy: CheckpointPolicy,
+) {
+    let mut ticker = match policy {
+        CheckpointPolicy::Interval(period) => {
+            let mut ticker = tokio::time::interval(period);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick completes immediately
+            ticker.tick().await;
+            Some(ticker)
+        }
+        _ => None,
+    };
+    let mut writes: u64 = 0;
+    loop {
+        let event = match ticker.as_mut() {
+            Some(ticker) => tokio::select! {
+                _ = ticker.tick() => None,
+                event = events.recv() => Some(event),
+            },
+            None => Some(events.recv().await),
+        };
+        let due = match event {
+            Some(Ok(_)) => {
+                writes += 1;
+                matches!(policy, CheckpointPolicy::EveryNWrites(n) if writes >= n)
+            }
+            Some(Err(broadcast::error::RecvError::Lagged(missed))) => {
+                writes += missed;
+                matches!(policy, CheckpointPolicy::EveryNWrites(n) if writes >= n)
+            }
+            Some(Err(broadcast::error::RecvError::Closed)) => return,
+            None => writes > 0,
+        };
+        if !due {
+            continue;
+        }
+        let result = match pool.get_connection().await {
+            Ok(conn) => checkpoint_wal(&conn).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => writes = 0,
+            // Retried on the next write or tick
+            Err(e) => tracing::debug!("background checkpoint failed: {}", e),
+        }
+    }
+}
+
+/// Set a directory's mtime and ctime after one of its entries was added,
+/// removed or renamed.
+async fn touch_dir(conn: &Connection, ino: i64, secs: i64, nsec: i64) -> Result<()> {
+    conn.prepare_cached(
+        "UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?",
+    )
+    .await?
+    .execute((secs, secs, nsec, nsec, ino))
+    .await?;
+    Ok(())
+}
+
+/// Fail with `FsError::NoSpace` if growing the filesystem by `growth` bytes
+/// would exceed the quota. Usage is the total file size, as in `statfs`.
+async fn check_quota(conn: &Connection, max_bytes: &AtomicU64, growth: u64) -> Result<()> {
+    let limit = max_bytes.load(Ordering::Relaxed);
+    if limit == 0 || growth == 0 {
+        return Ok(());
+    }
+
+    let mut rows = conn
+        .query("SELECT COALESCE(SUM(size), 0) FROM fs_inode", ())
+        .await?;
+    let used = if let Some(row) = rows.next().await? {
+        row.get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .unwrap_or(0) as u64
+    } else {
+        0
+    };
+
+    if used.saturating_add(growth) > limit {
+        return Err(FsError::NoSpace.into());
+    }
+    Ok(())
+}
+
+/// Read column `idx` of `row` as an integer, treating anything else as 0.
+fn row_integer(row: &turso::Row, idx: usize) -> i64 {
+    row.get_value(idx)
+        .ok()
+        .and_then(|v| v.as_integer().copied())
+        .unwrap_or(0)
+}
+
+/// Bytes of file data stored in the database, in chunks and deduplicated
+/// blobs.
+async fn stored_size(conn: &Connection) -> Result<u64> {
+    let mut rows = conn
+        .query(
+            "SELECT (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM fs_data)
+                + (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM fs_blob)",
+            (),
+        )
+        .await?;
+    let mut size = 0;
+    while let Some(row) = rows.next().await? {
+        size = row_integer(&row, 0) as u64;
+    }
+    Ok(size)
+}
+
+impl AgentFSFile {
+    /// Buffer a write at `offset` if the write-back policy allows it, and
+    /// return whether it was buffered.
+    async fn buffer_write(&self, offset: u64, data: &[u8]) -> Result<bool> {
+        let _gate = self.write_buffer.gate.lock().await;
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let mtime = (dur.as_secs() as i64, dur.subsec_nanos());
+        let mut push = self.write_buffer.push(self.ino, offset, data, mtime);
+        if push == Push::Rejected && self.write_buffer.contains(self.ino) {
+            // Store what is buffered first, so writes land in order
+            self.store_buffered().await?;
+            push = self.write_buffer.push(self.ino, offset, data, mtime);
+        }
+        match push {
+            Push::Buffered => Ok(true),
+            Push::Full => {
+                self.store_buffered().await?;
+                Ok(true)
+            }
+            Push::Rejected => Ok(false),
+        }
+    }
+
+    /// Store the bytes buffered for this file. The caller holds the write
+    /// buffer's gate.
+    ///
+    /// The bytes are discarded even if storing them fails, so that one
+    /// failed write does not fail every later operation too.
+    ///
+    /// Under a spill policy they are stored a batch at a time, so that only
+    /// one batch is in memory at once.
+    async fn store_buffered(&self) -> Result<()> {
+        let Some((offset, len)) = self.write_buffer.pending(self.ino) else {
+            return Ok(());
+        };
+        let batch = self.write_buffer.batch_bytes().unwrap_or(usize::MAX) as u64;
+        let result: Result<()> = async {
+            let mut at = 0;
+            while at < len {
+                let count = batch.min(len - at);
+                let data = self.write_buffer.read(self.ino, at, count as usize)?;
+                self.write_at(Some(offset + at), &data).await?;
+                at += count;
+            }
+            Ok(())
+        }
+        .await;
+        self.write_buffer.remove(self.ino);
+        result
+    }
+
+    /// Store the bytes buffered for this file, if there are any
+    async fn flush_buffered(&self) -> Result<()> {
+        if !self.write_buffer.contains(self.ino) {
+            return Ok(());
+        }
+        let _gate = self.write_buffer.gate.lock().await;
+        self.store_buffered().await
+    }
+
+    /// Write `data` at `offset`, or at the end of the file when `offset` is
+    /// `None`, and return the new file size.
+    ///
+    /// The end of the file is read inside the write transaction, so
+    /// concurrent appends never overwrite each other's bytes.
+    async fn write_at(&self, offset: Option<u64>, data: &[u8]) -> Result<u64> {
+        let conn = self.pool.get_connection().await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+        // Get current file size
+        let mut stmt = conn
+            .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((self.ino,)).await?;
+        let current_size = if let Some(row) = rows.next().await? {
+            row.get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u64
+        } else {
+            0
+        };
+        if data.is_empty() {
+            txn.commit().await?;
+            return Ok(current_size);
+        }
+        let offset = offset.unwrap_or(current_size);
+
+        // Reject the whole write rather than truncating it at the quota
+        let new_size = match checked_file_end(offset, data.len() as u64) {
+            Ok(end) => std::cmp::max(current_size, end),
+            Err(e) => {
+                let _ = txn.rollback().await;
+                return Err(e);
+            }
+        };
+        if let Err(e) = check_quota(&conn, &self.max_bytes, new_size - current_size).await {
+            let _ = txn.rollback().await;
+            return Err(e);
+        }
+
+        // Write the actual data (sparse gaps are handled by pread which fills
+        // missing chunks with zeros, so no need to zero-fill here)
+        self.write_data_at_offset_with_conn(&conn, offset, data)
+            .await?;
+
+        // Update file size and mtime
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET size = ?, mtime = ?, mtime_nsec = ? WHERE ino = ?")
+            .await?;
+        stmt.execute((new_size as i64, now_secs, now_nsec, self.ino))
+            .await?;
+        txn.commit().await?;
+
+        notify_ino(&self.events, &conn, ChangeEventKind::Write, self.ino).await;
+        Ok(new_size)
+    }
+
+    /// Write data at a specific offset, handling chunk boundaries.
+    /// Uses a provided connection to allow reuse within a transaction.
+    async fn write_data_at_offset_with_conn(
+        &self,
+        conn: &Connection,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let chunk_size = self.chunk_size as u64;
+        let mut written = 0usize;
+
+        if data.is_empty() {
+            return Ok(());
+        }
 
-        // get statements only once (in order to avoid heavy clone on every while iteration)
-        let mut select_stmt = conn
-            .prepare_cached("SELECT data FROM fs_data WHERE ino = ? AND chunk_index = ?")
-            .await?;
-        let mut insert_stmt = conn
-            .prepare_cached(
-                "INSERT OR REPLACE INTO fs_data (ino, chunk_index, data) VALUES (?, ?, ?)",
-            )
-            .await?;
         while written < data.len() {
             let current_offset = offset + written as u64;
             let chunk_index = (current_offset / chunk_size) as i64;
@@ -376,23 +1920,9 @@ impl AgentFSFile {
             let mut chunk_data;
             if to_write != chunk_size as usize {
                 // Get existing chunk data (if any)
-                let mut rows = select_stmt.query((self.ino, chunk_index)).await?;
-
-                chunk_data = if let Some(row) = rows.next().await? {
-                    row.get_value(0)
-                        .ok()
-                        .and_then(|v| {
-                            if let Value::Blob(b) = v {
-                                Some(b)
-                            } else {
-                                None
-                            }
-                        })
-                        .unwrap_or_default()
-                } else {
-                    Vec::new()
-                };
-                select_stmt.reset()?;
+                chunk_data = read_chunk(conn, &self.encoding, self.ino, chunk_index)
+                    .await?
+                    .unwrap_or_default();
 
                 // Extend chunk if needed
                 if chunk_data.len() < offset_in_chunk + to_write {
@@ -407,10 +1937,7 @@ impl AgentFSFile {
             }
 
             // Save chunk
-            insert_stmt
-                .execute((self.ino, chunk_index, Value::Blob(chunk_data)))
-                .await?;
-            insert_stmt.reset()?;
+            store_chunk(conn, &self.encoding, self.ino, chunk_index, &chunk_data).await?;
 
             written += to_write;
         }
@@ -442,4476 +1969,11067 @@ impl AgentFS {
 
         // Get chunk_size from config (or use default)
         let chunk_size = Self::read_chunk_size(&conn).await?;
+        let max_bytes = Self::read_max_bytes(&conn).await?;
+        let umask = Self::read_umask(&conn).await?;
+        let compression = Self::read_compression(&conn).await?;
+        let dedup = Self::read_dedup(&conn).await?;
+        let atime_policy = Self::read_atime_policy(&conn).await?;
+        let case_insensitive = Self::read_case_insensitive(&conn).await?;
+        let trash = Self::read_trash(&conn).await?;
 
         let fs = Self {
             pool,
             chunk_size,
-            dentry_cache: Arc::new(DentryCache::new(DENTRY_CACHE_MAX_SIZE)),
+            dentry_cache: Arc::new(DentryCache::new(DENTRY_CACHE_MAX_SIZE, case_insensitive)),
+            max_bytes: Arc::new(AtomicU64::new(max_bytes.unwrap_or(0))),
+            umask: Arc::new(AtomicU32::new(umask.unwrap_or(0))),
+            encoding: Arc::new(ChunkEncoding {
+                compression: AtomicU8::new(
+                    compression.map_or(COMPRESSION_NONE, CompressionKind::code),
+                ),
+                dedup: AtomicBool::new(dedup),
+                key: RwLock::new(None),
+            }),
+            events: broadcast::channel(CHANGE_EVENT_CAPACITY).0,
+            locks: Arc::new(LockTable::default()),
+            checkpointer: Arc::new(Mutex::new(None)),
+            busy_retry: Arc::new(Mutex::new(BusyRetry::default())),
+            atime_policy: Arc::new(AtomicU8::new(atime_policy.unwrap_or_default().code())),
+            case_insensitive,
+            trash: Arc::new(Mutex::new(trash)),
+            write_buffer: Arc::new(WriteBuffer::default()),
+            write_flusher: Arc::new(Mutex::new(None)),
         };
         Ok(fs)
     }
 
+    /// Get how writes retry a busy database lock
+    pub fn busy_retry(&self) -> BusyRetry {
+        *self.busy_retry.lock().unwrap()
+    }
+
+    /// Set how writes retry a busy database lock.
+    ///
+    /// This is not persisted; it applies to this instance, its clones and
+    /// the files they open.
+    pub fn set_busy_retry(&self, retry: BusyRetry) {
+        *self.busy_retry.lock().unwrap() = retry;
+    }
+
     /// Get the configured chunk size
     pub fn chunk_size(&self) -> usize {
         self.chunk_size
     }
 
-    /// Get a database connection from the pool
-    pub async fn get_connection(&self) -> Result<crate::connection_pool::PooledConnection> {
-        self.pool.get_connection().await
+    /// Get the configured byte quota, if any
+    pub fn max_bytes(&self) -> Option<u64> {
+        match self.max_bytes.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit),
+        }
     }
 
-    /// Get the connection pool
-    pub fn get_pool(&self) -> ConnectionPool {
-        self.pool.clone()
+    /// Set or clear the byte quota.
+    ///
+    /// The limit is stored in `fs_config` and applies to the total file size
+    /// reported by `statfs`. Writes that would exceed it fail with ENOSPC.
+    pub async fn set_max_bytes(&self, max_bytes: Option<u64>) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        match max_bytes {
+            Some(limit) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('max_bytes', ?)",
+                    (limit.to_string(),),
+                )
+                .await?;
+            }
+            None => {
+                conn.execute("DELETE FROM fs_config WHERE key = 'max_bytes'", ())
+                    .await?;
+            }
+        }
+        self.max_bytes
+            .store(max_bytes.unwrap_or(0), Ordering::Relaxed);
+        Ok(())
     }
 
-    /// Initialize the database schema
-    pub async fn initialize_schema(conn: &Connection) -> Result<()> {
-        // Create config table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS fs_config (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )",
-            (),
-        )
-        .await?;
+    /// Get the umask applied to newly created files and directories, if any
+    pub fn umask(&self) -> Option<u32> {
+        match self.umask.load(Ordering::Relaxed) {
+            0 => None,
+            umask => Some(umask),
+        }
+    }
 
-        // Create inode table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS fs_inode (
-                ino INTEGER PRIMARY KEY AUTOINCREMENT,
-                mode INTEGER NOT NULL,
-                nlink INTEGER NOT NULL DEFAULT 0,
-                uid INTEGER NOT NULL DEFAULT 0,
-                gid INTEGER NOT NULL DEFAULT 0,
-                size INTEGER NOT NULL DEFAULT 0,
-                atime INTEGER NOT NULL,
-                mtime INTEGER NOT NULL,
-                ctime INTEGER NOT NULL,
-                rdev INTEGER NOT NULL DEFAULT 0
-            )",
-            (),
-        )
-        .await?;
+    /// Set or clear the creation umask.
+    ///
+    /// The umask is stored in `fs_config` and clears permission bits of every
+    /// file, directory and node created from now on, on top of any umask the
+    /// caller already applied.
+    pub async fn set_umask(&self, umask: Option<u32>) -> Result<()> {
+        let umask = umask.map(|umask| umask & 0o777).filter(|&umask| umask != 0);
+        let conn = self.pool.get_connection().await?;
+        match umask {
+            Some(umask) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('umask', ?)",
+                    (format!("{:04o}", umask),),
+                )
+                .await?;
+            }
+            None => {
+                conn.execute("DELETE FROM fs_config WHERE key = 'umask'", ())
+                    .await?;
+            }
+        }
+        self.umask.store(umask.unwrap_or(0), Ordering::Relaxed);
+        Ok(())
+    }
 
-        // Add nanosecond timestamp columns (backward compatible migration)
-        conn.execute(
-            "ALTER TABLE fs_inode ADD COLUMN atime_nsec INTEGER NOT NULL DEFAULT 0",
-            (),
-        )
-        .await
-        .ok();
-        conn.execute(
-            "ALTER TABLE fs_inode ADD COLUMN mtime_nsec INTEGER NOT NULL DEFAULT 0",
-            (),
-        )
-        .await
-        .ok();
-        conn.execute(
-            "ALTER TABLE fs_inode ADD COLUMN ctime_nsec INTEGER NOT NULL DEFAULT 0",
-            (),
-        )
-        .await
-        .ok();
+    /// Clear the permission bits masked by the creation umask
+    fn apply_umask(&self, mode: u32) -> u32 {
+        mode & !self.umask.load(Ordering::Relaxed)
+    }
 
-        // Create directory entry table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS fs_dentry (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                parent_ino INTEGER NOT NULL,
-                ino INTEGER NOT NULL,
-                UNIQUE(parent_ino, name)
-            )",
-            (),
-        )
-        .await?;
+    /// Whether name lookups ignore case
+    pub fn case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
 
-        // Create index for efficient path lookups
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_fs_dentry_parent
-            ON fs_dentry(parent_ino, name)",
-            (),
-        )
-        .await?;
+    /// SQL condition selecting the directory entry of a name, binding the
+    /// parent inode and then the [`Self::dentry_key`] of the name
+    fn dentry_match(&self) -> &'static str {
+        if self.case_insensitive {
+            "parent_ino = ? AND name_key = ?"
+        } else {
+            "parent_ino = ? AND name = ?"
+        }
+    }
 
-        // Create data chunks table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS fs_data (
-                ino INTEGER NOT NULL,
-                chunk_index INTEGER NOT NULL,
-                data BLOB NOT NULL,
-                PRIMARY KEY (ino, chunk_index)
-            )",
-            (),
-        )
-        .await?;
+    /// Value matched against the name column of [`Self::dentry_match`]
+    fn dentry_key(&self, name: &str) -> String {
+        if self.case_insensitive {
+            fold_name(name)
+        } else {
+            name.to_string()
+        }
+    }
 
-        // Create symlink table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS fs_symlink (
-                ino INTEGER PRIMARY KEY,
-                target TEXT NOT NULL
-            )",
-            (),
-        )
-        .await?;
+    /// Value stored in `fs_dentry.name_key` for a new entry
+    fn name_key(&self, name: &str) -> Option<String> {
+        self.case_insensitive.then(|| fold_name(name))
+    }
 
-        // Ensure chunk_size config exists
-        let mut rows = conn
-            .query("SELECT value FROM fs_config WHERE key = 'chunk_size'", ())
-            .await?;
+    /// Get the owner configured for the root directory.
+    ///
+    /// Either ID is `None` when it follows the user opening the filesystem.
+    pub async fn owner(&self) -> Result<(Option<u32>, Option<u32>)> {
+        let conn = self.pool.get_connection().await?;
+        Self::read_owner(&conn).await
+    }
 
-        if rows.next().await?.is_none() {
+    /// Give the root directory a fixed owner.
+    ///
+    /// Without a configured owner, the root directory is handed to whoever
+    /// opens the filesystem. The IDs given here are stored in `fs_config`
+    /// and applied to the root on every open instead; a `None` ID keeps
+    /// following the opening user.
+    pub async fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        for (key, id) in [("uid", uid), ("gid", gid)] {
+            let Some(id) = id else { continue };
             conn.execute(
-                "INSERT INTO fs_config (key, value) VALUES ('chunk_size', ?)",
-                (DEFAULT_CHUNK_SIZE.to_string(),),
+                "INSERT OR REPLACE INTO fs_config (key, value) VALUES (?, ?)",
+                (key, id.to_string()),
             )
             .await?;
+            let sql = format!("UPDATE fs_inode SET {} = ? WHERE ino = ?", key);
+            conn.execute(&sql, (id, ROOT_INO)).await?;
         }
+        Ok(())
+    }
 
-        // Set schema version
+    /// Get the compression applied to newly written file data, if any
+    pub fn compression(&self) -> Option<CompressionKind> {
+        CompressionKind::from_code(self.encoding.compression.load(Ordering::Relaxed))
+    }
+
+    /// Set or clear compression of file data.
+    ///
+    /// The setting is stored in `fs_config` and applies to chunks written from
+    /// now on; chunks already stored keep their encoding and stay readable.
+    pub async fn set_compression(&self, compression: Option<CompressionKind>) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        match compression {
+            Some(kind) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('compression', ?)",
+                    (kind.as_str(),),
+                )
+                .await?;
+            }
+            None => {
+                conn.execute("DELETE FROM fs_config WHERE key = 'compression'", ())
+                    .await?;
+            }
+        }
+        self.encoding.compression.store(
+            compression.map_or(COMPRESSION_NONE, CompressionKind::code),
+            Ordering::Relaxed,
+        );
+        Ok(())
+    }
+
+    /// Get when reads update access times
+    pub fn atime_policy(&self) -> AtimePolicy {
+        AtimePolicy::from_code(self.atime_policy.load(Ordering::Relaxed))
+    }
+
+    /// Set when reads update access times.
+    ///
+    /// The policy is stored in `fs_config` and applies to reads from now on.
+    /// Explicit changes through [`FileSystem::utimens`] are always applied.
+    pub async fn set_atime_policy(&self, policy: AtimePolicy) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
         conn.execute(
-            "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('schema_version', ?)",
-            [AGENTFS_SCHEMA_VERSION],
+            "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('atime', ?)",
+            (policy.as_str(),),
         )
         .await?;
+        self.atime_policy.store(policy.code(), Ordering::Relaxed);
+        Ok(())
+    }
 
-        // Ensure root directory exists with correct ownership
-        let mut rows = conn
-            .query("SELECT ino FROM fs_inode WHERE ino = ?", (ROOT_INO,))
-            .await?;
+    /// Get the trash policy, if removals go to the trash
+    pub fn trash(&self) -> Option<TrashPolicy> {
+        *self.trash.lock().unwrap()
+    }
 
-        // SAFETY: getuid/getgid are always safe
-        #[cfg(unix)]
-        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
-        #[cfg(not(unix))]
-        let (uid, gid) = (0u32, 0u32);
+    /// Send removed entries to the trash instead of deleting them, or stop.
+    ///
+    /// With a policy set, `unlink`, `rmdir`, `remove` and `remove_all` only
+    /// detach the entry and record where it was in `fs_trash`; its inode and
+    /// data, and for a directory everything below it, stay until the trash is
+    /// emptied or, with a retention, until [`AgentFS::compact`] runs after it
+    /// expired. Entries replaced by a rename are still deleted right away.
+    /// The policy is stored in `fs_config`. Turning it off leaves what is
+    /// already in the trash there.
+    pub async fn set_trash(&self, policy: Option<TrashPolicy>) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        match policy {
+            Some(policy) => {
+                let secs = policy.retention.map_or(0, |r| r.as_secs().max(1));
+                conn.execute(
+                    "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('trash_retention', ?)",
+                    (secs.to_string(),),
+                )
+                .await?;
+            }
+            None => {
+                conn.execute("DELETE FROM fs_config WHERE key = 'trash_retention'", ())
+                    .await?;
+            }
+        }
+        *self.trash.lock().unwrap() = policy;
+        Ok(())
+    }
 
-        if rows.next().await?.is_none() {
-            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-            let now_secs = dur.as_secs() as i64;
-            let now_nsec = dur.subsec_nanos() as i64;
+    /// Whether newly written file data is deduplicated
+    pub fn dedup(&self) -> bool {
+        self.encoding.dedup.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable content deduplication of file data.
+    ///
+    /// With dedup on, each distinct chunk of file content is stored once in
+    /// `fs_blob` and reference counted, so copies of the same file share
+    /// their bytes. The setting is stored in `fs_config` and applies to
+    /// chunks written from now on; chunks stored either way stay readable.
+    pub async fn set_dedup(&self, dedup: bool) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        if dedup {
             conn.execute(
-                "INSERT INTO fs_inode (ino, mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
-                VALUES (?, ?, 2, ?, ?, 0, ?, ?, ?, ?, ?, ?)",
-                (ROOT_INO, DEFAULT_DIR_MODE as i64, uid, gid, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec),
+                "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('dedup', 'true')",
+                (),
             )
             .await?;
         } else {
-            // Update existing root inode ownership to current user
-            conn.execute(
-                "UPDATE fs_inode SET uid = ?, gid = ? WHERE ino = ?",
-                (uid, gid, ROOT_INO),
-            )
-            .await?;
+            conn.execute("DELETE FROM fs_config WHERE key = 'dedup'", ())
+                .await?;
         }
-
+        self.encoding.dedup.store(dedup, Ordering::Relaxed);
         Ok(())
     }
 
-    /// Read chunk size from config
-    async fn read_chunk_size(conn: &Connection) -> Result<usize> {
-        let mut rows = conn
-            .query("SELECT value FROM fs_config WHERE key = 'chunk_size'", ())
-            .await?;
+    /// Whether file contents have been encrypted with a blob key
+    pub async fn blob_encrypted(&self) -> Result<bool> {
+        let conn = self.pool.get_connection().await?;
+        Ok(Self::read_blob_key_check(&conn).await?.is_some())
+    }
 
-        if let Some(row) = rows.next().await? {
-            let value = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| match v {
-                    Value::Text(s) => s.parse::<usize>().ok(),
-                    Value::Integer(i) => Some(i as usize),
-                    _ => None,
-                })
-                .unwrap_or(DEFAULT_CHUNK_SIZE);
-            Ok(value)
-        } else {
-            Ok(DEFAULT_CHUNK_SIZE)
+    /// Encrypt file contents written from now on with `key`, and decrypt
+    /// encrypted contents on read.
+    ///
+    /// The first key set on a filesystem is recognized afterwards by a keyed
+    /// hash stored in `fs_config`; setting any other key later fails with
+    /// `Error::InvalidEncryptionKey` instead of returning garbage. Chunks
+    /// written before the first key was set stay unencrypted and readable.
+    ///
+    /// Only chunk contents are encrypted. Names, sizes, modes, timestamps,
+    /// extended attributes and symlink targets stay in plaintext, and chunks
+    /// with identical contents are still visible as such when dedup is on.
+    pub async fn set_blob_key(&self, key: BlobKey) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        let check = key.check_value();
+        match Self::read_blob_key_check(&conn).await? {
+            Some(stored) if stored != check => {
+                return Err(Error::InvalidEncryptionKey(
+                    "blob key does not match the key file contents were encrypted with".to_string(),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                conn.execute(
+                    "INSERT INTO fs_config (key, value) VALUES ('blob_key_check', ?)",
+                    (check,),
+                )
+                .await?;
+            }
         }
+        *self.encoding.key.write().unwrap() = Some(key);
+        Ok(())
     }
 
-    /// Normalize a path
-    fn normalize_path(&self, path: &str) -> String {
-        let normalized = path.trim_end_matches('/');
-        let normalized = if normalized.is_empty() {
-            "/"
-        } else if normalized.starts_with('/') {
-            normalized
-        } else {
-            return format!("/{}", normalized);
+    /// Start checkpointing the write-ahead log in the background as `policy`
+    /// asks, replacing any previous policy.
+    ///
+    /// The policy is not stored in the database and lasts until it is
+    /// replaced or the last clone of this filesystem is dropped, which stops
+    /// the task. Any policy other than `Never` must be set from within a
+    /// Tokio runtime.
+    pub fn set_checkpoint_policy(&self, policy: CheckpointPolicy) -> Result<()> {
+        let checkpointer = match policy {
+            CheckpointPolicy::Never => None,
+            CheckpointPolicy::EveryNWrites(0) => {
+                return Err(Error::InvalidCheckpointPolicy(
+                    "write count must be positive".to_string(),
+                ))
+            }
+            CheckpointPolicy::Interval(period) if period.is_zero() => {
+                return Err(Error::InvalidCheckpointPolicy(
+                    "interval must be positive".to_string(),
+                ))
+            }
+            _ => {
+                let runtime = tokio::runtime::Handle::try_current()
+                    .map_err(|e| Error::InvalidCheckpointPolicy(e.to_string()))?;
+                Some(Checkpointer {
+                    task: runtime.spawn(run_checkpoints(
+                        self.pool.clone(),
+                        self.events.subscribe(),
+                        policy,
+                    )),
+                })
+            }
         };
+        *self.checkpointer.lock().unwrap() = checkpointer;
+        Ok(())
+    }
 
-        // Handle . and .. components
-        let components: Vec<&str> = normalized.split('/').filter(|s| !s.is_empty()).collect();
-        let mut result = Vec::new();
+    /// The write-back policy open files buffer writes under, if any
+    pub fn write_back(&self) -> Option<WriteBackPolicy> {
+        self.write_buffer.policy()
+    }
 
-        for component in components {
-            match component {
-                "." => {
-                    // Current directory - skip it
-                    continue;
-                }
-                ".." => {
-                    // Parent directory - only pop if there is a component to pop (don't traverse above root)
-                    if !result.is_empty() {
-                        result.pop();
-                    }
-                }
-                _ => {
-                    result.push(component);
-                }
+    /// Buffer small writes made through open files as `policy` asks, or
+    /// store every write directly with `None`, replacing any previous policy.
+    ///
+    /// Writes buffered under the previous policy are stored first. Like a
+    /// checkpoint policy, the policy is not stored in the database and lasts
+    /// until it is replaced or the last clone of this filesystem is dropped;
+    /// a policy must be set from within a Tokio runtime.
+    pub async fn set_write_back(&self, policy: Option<WriteBackPolicy>) -> Result<()> {
+        let flusher = match policy {
+            None => None,
+            Some(policy) if policy.max_bytes == 0 => {
+                return Err(Error::InvalidWriteBackPolicy(
+                    "buffer size must be positive".to_string(),
+                ))
+            }
+            Some(policy) if policy.flush_interval.is_zero() => {
+                return Err(Error::InvalidWriteBackPolicy(
+                    "flush interval must be positive".to_string(),
+                ))
             }
+            Some(policy) => {
+                let runtime = tokio::runtime::Handle::try_current()
+                    .map_err(|e| Error::InvalidWriteBackPolicy(e.to_string()))?;
+                let fs = self.detached();
+                Some(WriteFlusher {
+                    task: runtime.spawn(run_write_flushes(fs.clone(), policy.flush_interval)),
+                    fs,
+                })
+            }
+        };
+        self.write_buffer.set_policy(policy);
+        *self.write_flusher.lock().unwrap() = flusher;
+        self.flush_writes().await
+    }
+
+    /// The spill policy large writes through open files follow, if any
+    pub fn spill(&self) -> Option<SpillPolicy> {
+        self.write_buffer.spill()
+    }
+
+    /// Keep large writes through open files out of memory as `policy` asks,
+    /// or hold them in memory with `None`, replacing any previous policy.
+    ///
+    /// Like a write-back policy, the policy is not stored in the database.
+    pub fn set_spill(&self, policy: Option<SpillPolicy>) -> Result<()> {
+        if policy.as_ref().is_some_and(|policy| policy.threshold == 0) {
+            return Err(Error::InvalidWriteBackPolicy(
+                "spill threshold must be positive".to_string(),
+            ));
         }
+        self.write_buffer.set_spill(policy);
+        Ok(())
+    }
 
-        if result.is_empty() {
-            "/".to_string()
-        } else {
-            format!("/{}", result.join("/"))
+    /// Store every write open files have buffered.
+    ///
+    /// Operations that would miss buffered writes call this first, so it
+    /// only needs calling directly to bound how much a crash can lose.
+    pub async fn flush_writes(&self) -> Result<()> {
+        if self.write_buffer.is_empty() {
+            return Ok(());
+        }
+        let _gate = self.write_buffer.gate.lock().await;
+        let mut result = Ok(());
+        for ino in self.write_buffer.inodes() {
+            if let Err(e) = self.file_handle(ino, false).store_buffered().await {
+                result = Err(e);
+            }
         }
+        result
     }
 
-    /// Split path into components
-    fn split_path(&self, path: &str) -> Vec<String> {
-        let normalized = self.normalize_path(path);
-        if normalized == "/" {
-            return vec![];
+    /// A clone that does not keep background tasks running, for the tasks
+    /// themselves to use
+    fn detached(&self) -> Self {
+        Self {
+            checkpointer: Arc::default(),
+            write_flusher: Arc::default(),
+            ..self.clone()
         }
-        normalized
-            .split('/')
-            .filter(|p| !p.is_empty())
-            .map(|s| s.to_string())
-            .collect()
     }
 
-    /// Look up a child entry by parent inode and name using a provided connection.
+    /// An open file handle for inode `ino`
+    fn file_handle(&self, ino: i64, append: bool) -> AgentFSFile {
+        AgentFSFile {
+            pool: self.pool.clone(),
+            ino,
+            chunk_size: self.chunk_size,
+            max_bytes: self.max_bytes.clone(),
+            encoding: self.encoding.clone(),
+            events: self.events.clone(),
+            busy_retry: self.busy_retry.clone(),
+            atime_policy: self.atime_policy.clone(),
+            write_buffer: self.write_buffer.clone(),
+            append,
+        }
+    }
+
+    /// Subscribe to changes made through this filesystem and its clones.
     ///
-    /// This is more efficient than `resolve_path` when you already have the parent inode,
-    /// as it avoids re-resolving all parent path components.
-    async fn lookup_child(
+    /// Only events sent after the call are received. Paths are resolved when
+    /// the change happens, so events are only produced while at least one
+    /// receiver is alive.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send a change event for the entry `name` in directory `parent_ino`
+    async fn notify_entry(
         &self,
         conn: &Connection,
+        kind: ChangeEventKind,
         parent_ino: i64,
         name: &str,
-    ) -> Result<Option<i64>> {
-        let mut stmt = conn
-            .prepare_cached("SELECT ino FROM fs_dentry WHERE parent_ino = ? AND name = ?")
-            .await?;
-        let mut rows = stmt.query((parent_ino, name)).await?;
-
-        let mut found_ino = None;
-        let mut row_count = 0;
-
-        while let Some(row) = rows.next().await? {
-            found_ino = row.get_value(0).ok().and_then(|v| v.as_integer().copied());
-            row_count += 1;
+    ) {
+        if self.events.receiver_count() == 0 {
+            return;
         }
-
-        if row_count > 1 {
-            return Err(FsError::InvalidPath.into());
+        if let Ok(Some(parent)) = path_of(conn, parent_ino).await {
+            let _ = self.events.send(ChangeEvent {
+                kind,
+                path: child_path(&parent, name),
+            });
         }
-
-        Ok(found_ino)
     }
 
-    /// Get link count for an inode
-    async fn get_link_count(&self, conn: &Connection, ino: i64) -> Result<u32> {
-        let mut stmt = conn
-            .prepare_cached("SELECT nlink FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
-
-        if let Some(row) = rows.next().await? {
-            let nlink = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0);
-            Ok(nlink as u32)
-        } else {
-            Ok(0)
+    /// Send a change event for an already normalized path
+    fn notify_path(&self, kind: ChangeEventKind, path: &str) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(ChangeEvent {
+                kind,
+                path: path.to_string(),
+            });
         }
     }
 
-    /// Get file attributes by inode using an existing connection
-    async fn getattr_with_conn(&self, conn: &Connection, ino: i64) -> Result<Option<Stats>> {
-        let mut stmt = conn
-            .prepare_cached("SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+    /// Get a database connection from the pool
+    pub async fn get_connection(&self) -> Result<crate::connection_pool::PooledConnection> {
+        self.pool.get_connection().await
+    }
 
-        if let Some(row) = rows.next().await? {
-            let stats = Self::build_stats_from_row(&row)?;
-            Ok(Some(stats))
-        } else {
-            Ok(None)
-        }
+    /// Get the connection pool
+    pub fn get_pool(&self) -> ConnectionPool {
+        self.pool.clone()
     }
 
-    /// Build a Stats object from a database row
-    ///
-    /// The row should contain columns in this order:
-    /// ino, mode, nlink, uid, gid, size, atime, mtime, ctime
-    fn build_stats_from_row(row: &turso::Row) -> Result<Stats> {
-        Ok(Stats {
-            ino: row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0),
-            mode: row
-                .get_value(1)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32,
-            nlink: row
-                .get_value(2)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(1) as u32,
-            uid: row
-                .get_value(3)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32,
-            gid: row
-                .get_value(4)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32,
-            size: row
-                .get_value(5)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0),
-            atime: row
-                .get_value(6)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0),
-            mtime: row
-                .get_value(7)
+    /// Initialize the database schema
+    pub async fn initialize_schema(conn: &Connection) -> Result<()> {
+        // Create config table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_config (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            (),
+        )
+        .await?;
+
+        // Create inode table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_inode (
+                ino INTEGER PRIMARY KEY AUTOINCREMENT,
+                mode INTEGER NOT NULL,
+                nlink INTEGER NOT NULL DEFAULT 0,
+                uid INTEGER NOT NULL DEFAULT 0,
+                gid INTEGER NOT NULL DEFAULT 0,
+                size INTEGER NOT NULL DEFAULT 0,
+                atime INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                ctime INTEGER NOT NULL,
+                rdev INTEGER NOT NULL DEFAULT 0,
+                atime_nsec INTEGER NOT NULL DEFAULT 0,
+                mtime_nsec INTEGER NOT NULL DEFAULT 0,
+                ctime_nsec INTEGER NOT NULL DEFAULT 0,
+                allocated INTEGER NOT NULL DEFAULT 0
+            )",
+            (),
+        )
+        .await?;
+
+        // Add nanosecond timestamp columns (backward compatible migration).
+        // New tables are created with every column, since turso drops
+        // AUTOINCREMENT from a table it adds a column to
+        conn.execute(
+            "ALTER TABLE fs_inode ADD COLUMN atime_nsec INTEGER NOT NULL DEFAULT 0",
+            (),
+        )
+        .await
+        .ok();
+        conn.execute(
+            "ALTER TABLE fs_inode ADD COLUMN mtime_nsec INTEGER NOT NULL DEFAULT 0",
+            (),
+        )
+        .await
+        .ok();
+        conn.execute(
+            "ALTER TABLE fs_inode ADD COLUMN ctime_nsec INTEGER NOT NULL DEFAULT 0",
+            (),
+        )
+        .await
+        .ok();
+
+        // Create directory entry table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_dentry (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                parent_ino INTEGER NOT NULL,
+                ino INTEGER NOT NULL,
+                name_key TEXT,
+                UNIQUE(parent_ino, name)
+            )",
+            (),
+        )
+        .await?;
+
+        // Add the case-folded name column (backward compatible migration)
+        conn.execute("ALTER TABLE fs_dentry ADD COLUMN name_key TEXT", ())
+            .await
+            .ok();
+
+        // Create index for efficient path lookups
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_fs_dentry_parent
+            ON fs_dentry(parent_ino, name)",
+            (),
+        )
+        .await?;
+
+        // Case-insensitive lookups, and at most one entry per folded name.
+        // Case-sensitive filesystems leave `name_key` NULL.
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_fs_dentry_key
+            ON fs_dentry(parent_ino, name_key)",
+            (),
+        )
+        .await?;
+
+        // Create data chunks table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_data (
+                ino INTEGER NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                compression INTEGER NOT NULL DEFAULT 0,
+                hash BLOB,
+                PRIMARY KEY (ino, chunk_index)
+            )",
+            (),
+        )
+        .await?;
+
+        // Add chunk compression and dedup columns (backward compatible migration)
+        conn.execute(
+            "ALTER TABLE fs_data ADD COLUMN compression INTEGER NOT NULL DEFAULT 0",
+            (),
+        )
+        .await
+        .ok();
+        conn.execute("ALTER TABLE fs_data ADD COLUMN hash BLOB", ())
+            .await
+            .ok();
+
+        // Create deduplicated content table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_blob (
+                hash BLOB PRIMARY KEY,
+                data BLOB NOT NULL,
+                compression INTEGER NOT NULL DEFAULT 0,
+                refcount INTEGER NOT NULL DEFAULT 0
+            )",
+            (),
+        )
+        .await?;
+
+        // Create symlink table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_symlink (
+                ino INTEGER PRIMARY KEY,
+                target TEXT NOT NULL
+            )",
+            (),
+        )
+        .await?;
+
+        // Create extended attribute table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_xattr (
+                ino INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                value BLOB NOT NULL,
+                PRIMARY KEY (ino, name)
+            )",
+            (),
+        )
+        .await?;
+
+        // Create snapshot table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_snapshots (
+                label TEXT PRIMARY KEY,
+                max_ino INTEGER NOT NULL,
+                max_dentry_id INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            (),
+        )
+        .await?;
+
+        // Paths that existed when each snapshot was taken, so that later
+        // deletions can be told apart from entries that never existed
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_snapshot_paths (
+                label TEXT NOT NULL,
+                path TEXT NOT NULL,
+                PRIMARY KEY (label, path)
+            )",
+            (),
+        )
+        .await?;
+
+        // Entries removed while the trash is on, each holding the link its
+        // directory entry held
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_trash (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                orig_path TEXT NOT NULL,
+                ino INTEGER NOT NULL,
+                deleted_at INTEGER NOT NULL
+            )",
+            (),
+        )
+        .await?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_fs_trash_ino ON fs_trash(ino)",
+            (),
+        )
+        .await?;
+
+        // Ensure chunk_size config exists
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'chunk_size'", ())
+            .await?;
+
+        if rows.next().await?.is_none() {
+            conn.execute(
+                "INSERT INTO fs_config (key, value) VALUES ('chunk_size', ?)",
+                (DEFAULT_CHUNK_SIZE.to_string(),),
+            )
+            .await?;
+        }
+
+        // Set schema version
+        conn.execute(
+            "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('schema_version', ?)",
+            [AGENTFS_SCHEMA_VERSION],
+        )
+        .await?;
+
+        // Ensure root directory exists with correct ownership
+        let mut rows = conn
+            .query("SELECT ino FROM fs_inode WHERE ino = ?", (ROOT_INO,))
+            .await?;
+
+        // SAFETY: getuid/getgid are always safe
+        #[cfg(unix)]
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        #[cfg(not(unix))]
+        let (uid, gid) = (0u32, 0u32);
+        // A configured owner takes precedence over the opening user
+        let (owner_uid, owner_gid) = Self::read_owner(conn).await?;
+        let (uid, gid) = (owner_uid.unwrap_or(uid), owner_gid.unwrap_or(gid));
+
+        if rows.next().await?.is_none() {
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            conn.execute(
+                "INSERT INTO fs_inode (ino, mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                VALUES (?, ?, 2, ?, ?, 0, ?, ?, ?, ?, ?, ?)",
+                (ROOT_INO, DEFAULT_DIR_MODE as i64, uid, gid, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec),
+            )
+            .await?;
+        } else {
+            // Update existing root inode ownership to the current user or configured owner
+            conn.execute(
+                "UPDATE fs_inode SET uid = ?, gid = ? WHERE ino = ?",
+                (uid, gid, ROOT_INO),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Read byte quota from config
+    async fn read_max_bytes(conn: &Connection) -> Result<Option<u64>> {
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'max_bytes'", ())
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(row.get_value(0).ok().and_then(|v| match v {
+                Value::Text(s) => s.parse::<u64>().ok(),
+                Value::Integer(i) => Some(i as u64),
+                _ => None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the creation umask from config
+    async fn read_umask(conn: &Connection) -> Result<Option<u32>> {
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'umask'", ())
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(row.get_value(0).ok().and_then(|v| match v {
+                Value::Text(s) => u32::from_str_radix(&s, 8).ok(),
+                _ => None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the configured root owner from config
+    async fn read_owner(conn: &Connection) -> Result<(Option<u32>, Option<u32>)> {
+        let mut rows = conn
+            .query(
+                "SELECT key, value FROM fs_config WHERE key IN ('uid', 'gid')",
+                (),
+            )
+            .await?;
+
+        let (mut uid, mut gid) = (None, None);
+        while let Some(row) = rows.next().await? {
+            let id = match row.get_value(1) {
+                Ok(Value::Text(s)) => s.parse::<u32>().ok(),
+                _ => None,
+            };
+            match row.get_value(0) {
+                Ok(Value::Text(key)) if key == "uid" => uid = id,
+                Ok(Value::Text(key)) if key == "gid" => gid = id,
+                _ => {}
+            }
+        }
+        Ok((uid, gid))
+    }
+
+    /// Read data compression from config
+    async fn read_compression(conn: &Connection) -> Result<Option<CompressionKind>> {
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'compression'", ())
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(row.get_value(0).ok().and_then(|v| match v {
+                Value::Text(s) => s.parse::<CompressionKind>().ok(),
+                _ => None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the access time policy from config
+    async fn read_atime_policy(conn: &Connection) -> Result<Option<AtimePolicy>> {
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'atime'", ())
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(row.get_value(0).ok().and_then(|v| match v {
+                Value::Text(s) => s.parse::<AtimePolicy>().ok(),
+                _ => None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read whether data deduplication is enabled from config
+    async fn read_dedup(conn: &Connection) -> Result<bool> {
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'dedup'", ())
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(matches!(row.get_value(0), Ok(Value::Text(s)) if s == "true"))
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Read the blob key check value from config
+    async fn read_blob_key_check(conn: &Connection) -> Result<Option<String>> {
+        let mut rows = conn
+            .query(
+                "SELECT value FROM fs_config WHERE key = 'blob_key_check'",
+                (),
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(row.get_value(0).ok().and_then(|v| match v {
+                Value::Text(s) => Some(s),
+                _ => None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Set the chunk size of a filesystem before it is opened.
+    ///
+    /// File contents are stored as rows of `chunk_size` bytes, so reads and
+    /// writes only touch the chunks their range covers. Smaller chunks make
+    /// small random writes into large files cheaper, since a partial write
+    /// rewrites a whole chunk; larger chunks mean fewer rows to read or write
+    /// for sequential I/O. `chunk_size` must be a power of two between
+    /// [`MIN_CHUNK_SIZE`] and [`MAX_CHUNK_SIZE`], and a filesystem that already
+    /// stores file data keeps its chunk size, failing with
+    /// `Error::InvalidChunkSize` if a different one is asked for.
+    pub async fn init_chunk_size(conn: &Connection, chunk_size: usize) -> Result<()> {
+        if !chunk_size.is_power_of_two() || !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size)
+        {
+            return Err(Error::InvalidChunkSize(format!(
+                "{} is not a power of two between {} and {}",
+                chunk_size, MIN_CHUNK_SIZE, MAX_CHUNK_SIZE
+            )));
+        }
+        Self::initialize_schema(conn).await?;
+        let current = Self::read_chunk_size(conn).await?;
+        if current == chunk_size {
+            return Ok(());
+        }
+        let mut rows = conn.query("SELECT 1 FROM fs_data LIMIT 1", ()).await?;
+        if rows.next().await?.is_some() {
+            return Err(Error::InvalidChunkSize(format!(
+                "filesystem already stores data in {}-byte chunks",
+                current
+            )));
+        }
+        conn.execute(
+            "UPDATE fs_config SET value = ? WHERE key = 'chunk_size'",
+            (chunk_size.to_string(),),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Make name lookups of a filesystem ignore case.
+    ///
+    /// Entries keep the name they were created with, which `readdir` shows,
+    /// but are found by any spelling that differs only in case, and a second
+    /// entry whose name differs from an existing one only in case fails with
+    /// `FsError::AlreadyExists`. Existing entries are indexed by their folded
+    /// names, which fails the same way if two of them collide. The setting is
+    /// stored in `fs_config` and read when the filesystem is opened; it
+    /// cannot be turned off again.
+    pub async fn init_case_insensitive(conn: &Connection) -> Result<()> {
+        Self::initialize_schema(conn).await?;
+        if Self::read_case_insensitive(conn).await? {
+            return Ok(());
+        }
+
+        let mut rows = conn
+            .query("SELECT id, parent_ino, name FROM fs_dentry", ())
+            .await?;
+        let mut keys = Vec::new();
+        let mut seen = HashSet::new();
+        while let Some(row) = rows.next().await? {
+            let id = row.get::<i64>(0)?;
+            let parent_ino = row.get::<i64>(1)?;
+            let key = fold_name(&row.get::<String>(2)?);
+            if !seen.insert((parent_ino, key.clone())) {
+                return Err(FsError::AlreadyExists.into());
+            }
+            keys.push((id, key));
+        }
+        drop(rows);
+
+        let txn = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).await?;
+        let result: Result<()> = async {
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_dentry SET name_key = ? WHERE id = ?")
+                .await?;
+            for (id, key) in keys {
+                stmt.execute((key, id)).await?;
+                stmt.reset()?;
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO fs_config (key, value) VALUES ('case_insensitive', 'true')",
+                (),
+            )
+            .await?;
+            Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => Ok(txn.commit().await?),
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Read the trash policy from config
+    async fn read_trash(conn: &Connection) -> Result<Option<TrashPolicy>> {
+        let mut rows = conn
+            .query(
+                "SELECT value FROM fs_config WHERE key = 'trash_retention'",
+                (),
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            let secs = row.get_value(0).ok().and_then(|v| match v {
+                Value::Text(s) => s.parse::<u64>().ok(),
+                Value::Integer(i) => Some(i as u64),
+                _ => None,
+            });
+            Ok(Some(TrashPolicy {
+                retention: secs.filter(|&s| s > 0).map(Duration::from_secs),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read whether name lookups ignore case from config
+    async fn read_case_insensitive(conn: &Connection) -> Result<bool> {
+        let mut rows = conn
+            .query(
+                "SELECT value FROM fs_config WHERE key = 'case_insensitive'",
+                (),
+            )
+            .await?;
+        Ok(rows.next().await?.is_some())
+    }
+
+    /// Read chunk size from config
+    async fn read_chunk_size(conn: &Connection) -> Result<usize> {
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'chunk_size'", ())
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            let value = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| match v {
+                    Value::Text(s) => s.parse::<usize>().ok(),
+                    Value::Integer(i) => Some(i as usize),
+                    _ => None,
+                })
+                .unwrap_or(DEFAULT_CHUNK_SIZE);
+            Ok(value)
+        } else {
+            Ok(DEFAULT_CHUNK_SIZE)
+        }
+    }
+
+    /// Split path into components
+    fn split_path(&self, path: &str) -> Vec<String> {
+        path_components(path)
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    /// Whether directory `ino` is `dir_ino` or lies somewhere below it.
+    async fn is_within(&self, conn: &Connection, ino: i64, dir_ino: i64) -> Result<bool> {
+        let mut stmt = conn
+            .prepare_cached("SELECT parent_ino FROM fs_dentry WHERE ino = ? LIMIT 1")
+            .await?;
+        let mut current = ino;
+        loop {
+            if current == dir_ino {
+                return Ok(true);
+            }
+            if current == ROOT_INO {
+                return Ok(false);
+            }
+            stmt.reset()?;
+            let mut rows = stmt.query((current,)).await?;
+            current = match rows.next().await? {
+                Some(row) => row
+                    .get_value(0)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(ROOT_INO),
+                None => return Ok(false),
+            };
+        }
+    }
+
+    /// Atomically swap two existing directory entries (`RENAME_EXCHANGE`).
+    async fn rename_exchange(
+        &self,
+        conn: &Connection,
+        oldparent_ino: i64,
+        oldname: &str,
+        newparent_ino: i64,
+        newname: &str,
+    ) -> Result<()> {
+        let src_ino = self
+            .lookup_child(conn, oldparent_ino, oldname)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        let dst_ino = self
+            .lookup_child(conn, newparent_ino, newname)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        if src_ino == ROOT_INO || dst_ino == ROOT_INO {
+            return Err(FsError::RootOperation.into());
+        }
+        if src_ino == dst_ino && oldparent_ino == newparent_ino {
+            return Ok(());
+        }
+
+        let src_stats = self
+            .getattr_with_conn(conn, src_ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        let dst_stats = self
+            .getattr_with_conn(conn, dst_ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Neither directory may end up inside itself
+        if (src_stats.is_directory() && self.is_within(conn, newparent_ino, src_ino).await?)
+            || (dst_stats.is_directory() && self.is_within(conn, oldparent_ino, dst_ino).await?)
+        {
+            return Err(FsError::InvalidRename.into());
+        }
+
+        let txn = begin_immediate(conn, &self.busy_retry).await?;
+
+        let result: Result<()> = async {
+            // Point each entry at the other inode
+            let mut stmt = conn
+                .prepare_cached(&format!(
+                    "UPDATE fs_dentry SET ino = ? WHERE {}",
+                    self.dentry_match()
+                ))
+                .await?;
+            stmt.execute((dst_ino, oldparent_ino, self.dentry_key(oldname)))
+                .await?;
+            stmt.reset()?;
+            stmt.execute((src_ino, newparent_ino, self.dentry_key(newname)))
+                .await?;
+
+            // A directory moving to another parent moves its ".." link with it
+            if oldparent_ino != newparent_ino {
+                let delta = src_stats.is_directory() as i64 - dst_stats.is_directory() as i64;
+                if delta != 0 {
+                    let mut stmt = conn
+                        .prepare_cached("UPDATE fs_inode SET nlink = nlink + ? WHERE ino = ?")
+                        .await?;
+                    stmt.execute((-delta, oldparent_ino)).await?;
+                    let mut stmt = conn
+                        .prepare_cached("UPDATE fs_inode SET nlink = nlink + ? WHERE ino = ?")
+                        .await?;
+                    stmt.execute((delta, newparent_ino)).await?;
+                }
+            }
+
+            let dur = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+
+            // Update ctime of both inodes
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_nsec, src_ino)).await?;
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_nsec, dst_ino)).await?;
+
+            // Update parent directory timestamps
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_secs, now_nsec, now_nsec, oldparent_ino)).await?;
+            if newparent_ino != oldparent_ino {
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+                    .await?;
+                stmt.execute((now_secs, now_secs, now_nsec, now_nsec, newparent_ino)).await?;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+
+                self.dentry_cache.insert(oldparent_ino, oldname, dst_ino);
+                self.dentry_cache.insert(newparent_ino, newname, src_ino);
+
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Insert a directory inode and its entry under `parent_ino`.
+    ///
+    /// Does not check for an existing entry, touch the dentry cache or send
+    /// change events; callers handle those.
+    async fn insert_dir(
+        &self,
+        conn: &Connection,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        // Create inode
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let dir_mode = super::S_IFDIR | (mode & 0o7777);
+        let row = stmt
+            .query_row((
+                dir_mode as i64,
+                uid,
+                gid,
+                now_secs,
+                now_secs,
+                now_secs,
+                now_nsec,
+                now_nsec,
+                now_nsec,
+            ))
+            .await?;
+
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+        // Create directory entry
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            )
+            .await?;
+        stmt.execute((name, parent_ino, ino, self.name_key(name)))
+            .await?;
+
+        // Set nlink to 2 for new directory (self "." + parent's dentry)
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET nlink = 2 WHERE ino = ?")
+            .await?;
+        stmt.execute((ino,)).await?;
+
+        // Increment parent nlink (new directory's ".." link) and update timestamps
+        let mut stmt = conn
+            .prepare_cached(
+                "UPDATE fs_inode SET nlink = nlink + 1, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
+            )
+            .await?;
+        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+            .await?;
+
+        Ok(Stats {
+            ino,
+            mode: dir_mode,
+            nlink: 2,
+            uid,
+            gid,
+            size: 0,
+            atime: now_secs,
+            mtime: now_secs,
+            ctime: now_secs,
+            atime_nsec: now_nsec as u32,
+            mtime_nsec: now_nsec as u32,
+            ctime_nsec: now_nsec as u32,
+            rdev: 0,
+            blocks: 0,
+        })
+    }
+
+    /// Look up a child entry by parent inode and name using a provided connection.
+    ///
+    /// This is more efficient than `resolve_path` when you already have the parent inode,
+    /// as it avoids re-resolving all parent path components.
+    async fn lookup_child(
+        &self,
+        conn: &Connection,
+        parent_ino: i64,
+        name: &str,
+    ) -> Result<Option<i64>> {
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "SELECT ino FROM fs_dentry WHERE {}",
+                self.dentry_match()
+            ))
+            .await?;
+        let mut rows = stmt.query((parent_ino, self.dentry_key(name))).await?;
+
+        let mut found_ino = None;
+        let mut row_count = 0;
+
+        while let Some(row) = rows.next().await? {
+            found_ino = row.get_value(0).ok().and_then(|v| v.as_integer().copied());
+            row_count += 1;
+        }
+
+        if row_count > 1 {
+            return Err(FsError::InvalidPath.into());
+        }
+
+        Ok(found_ino)
+    }
+
+    /// Get link count for an inode
+    async fn get_link_count(&self, conn: &Connection, ino: i64) -> Result<u32> {
+        let mut stmt = conn
+            .prepare_cached("SELECT nlink FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let nlink = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0);
+            Ok(nlink as u32)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Move the entry `name` of `parent_ino`, which refers to `ino`, to the
+    /// trash if the trash is on.
+    ///
+    /// The trash row takes over the link the entry held, so the inode keeps
+    /// its link count and, for a directory, its contents. Returns `false`
+    /// without changing anything if the trash is off or the parent is not
+    /// reachable from the root.
+    async fn trash_entry(
+        &self,
+        conn: &Connection,
+        parent_ino: i64,
+        name: &str,
+        ino: i64,
+        is_dir: bool,
+    ) -> Result<bool> {
+        if self.trash().is_none() {
+            return Ok(false);
+        }
+        let Some(parent_path) = path_of(conn, parent_ino).await? else {
+            return Ok(false);
+        };
+        let path = child_path(&parent_path, name);
+
+        let txn = begin_immediate(conn, &self.busy_retry).await?;
+        let result: Result<()> = async {
+            let mut stmt = conn
+                .prepare_cached(&format!(
+                    "DELETE FROM fs_dentry WHERE {}",
+                    self.dentry_match()
+                ))
+                .await?;
+            stmt.execute((parent_ino, self.dentry_key(name))).await?;
+
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            let mut stmt = conn
+                .prepare_cached(
+                    "INSERT INTO fs_trash (orig_path, ino, deleted_at) VALUES (?, ?, ?)",
+                )
+                .await?;
+            stmt.execute((path.as_str(), ino, now_secs)).await?;
+
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_nsec, ino)).await?;
+            touch_dir(conn, parent_ino, now_secs, now_nsec).await?;
+            // The parent loses the directory's ".." link
+            if is_dir {
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+                    .await?;
+                stmt.execute((parent_ino,)).await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+                self.dentry_cache.remove(parent_ino, name);
+                Ok(true)
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Drop one link to `ino`, whose directory entry or trash row is
+    /// already gone, and free what is no longer referenced.
+    ///
+    /// A directory goes with everything below it; files linked from outside
+    /// the subtree keep their data. Must run inside a transaction.
+    async fn release_tree(&self, conn: &Connection, ino: i64, is_dir: bool) -> Result<()> {
+        // Walk the subtree, collecting directories and the directory
+        // entries of everything else (a hard-linked file can appear twice)
+        let mut dirs = Vec::new();
+        let mut others = Vec::new();
+        if is_dir {
+            dirs.push(ino);
+        } else {
+            others.push(ino);
+        }
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT d.ino, i.mode FROM fs_dentry d JOIN fs_inode i ON i.ino = d.ino WHERE d.parent_ino = ?",
+            )
+            .await?;
+        let mut next = 0;
+        while next < dirs.len() {
+            let dir = dirs[next];
+            next += 1;
+            let mut rows = stmt.query((dir,)).await?;
+            while let Some(row) = rows.next().await? {
+                let child = row
+                    .get_value(0)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .ok_or_else(|| Error::Internal("invalid ino".to_string()))?;
+                let mode = row
+                    .get_value(1)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32;
+                if (mode & S_IFMT) == super::S_IFDIR {
+                    dirs.push(child);
+                } else {
+                    others.push(child);
+                }
+            }
+        }
+
+        let mut stmt = conn
+            .prepare_cached("DELETE FROM fs_dentry WHERE parent_ino = ?")
+            .await?;
+        for &dir in &dirs {
+            stmt.execute((dir,)).await?;
+        }
+
+        // Files linked from outside the subtree keep their data
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+            .await?;
+        for &file in &others {
+            stmt.execute((file,)).await?;
+        }
+        let mut doomed = dirs.clone();
+        for &file in &others {
+            if !doomed.contains(&file) && self.get_link_count(conn, file).await? == 0 {
+                delete_chunks(conn, file, 0).await?;
+                doomed.push(file);
+            }
+        }
+        for sql in [
+            "DELETE FROM fs_symlink WHERE ino = ?",
+            "DELETE FROM fs_xattr WHERE ino = ?",
+            "DELETE FROM fs_inode WHERE ino = ?",
+        ] {
+            let mut stmt = conn.prepare_cached(sql).await?;
+            for &ino in &doomed {
+                stmt.execute((ino,)).await?;
+            }
+        }
+        for &ino in &doomed {
+            self.locks.clear(ino);
+        }
+        Ok(())
+    }
+
+    /// Delete the trash rows removed at or before `cutoff`, or all of them,
+    /// freeing what they kept alive. Must run inside a transaction.
+    async fn purge_trash(&self, conn: &Connection, cutoff: Option<i64>) -> Result<u64> {
+        let mut rows = conn
+            .query(
+                "SELECT id, ino FROM fs_trash WHERE deleted_at <= ? ORDER BY id",
+                (cutoff.unwrap_or(i64::MAX),),
+            )
+            .await?;
+        let mut doomed = Vec::new();
+        while let Some(row) = rows.next().await? {
+            doomed.push((row_integer(&row, 0), row_integer(&row, 1)));
+        }
+        drop(rows);
+
+        let mut stmt = conn
+            .prepare_cached("DELETE FROM fs_trash WHERE id = ?")
+            .await?;
+        for &(id, ino) in &doomed {
+            stmt.execute((id,)).await?;
+            // Rows for inodes that no longer exist are just dropped
+            if let Some(stats) = self.getattr_with_conn(conn, ino).await? {
+                self.release_tree(conn, ino, stats.is_directory()).await?;
+            }
+        }
+        Ok(doomed.len() as u64)
+    }
+
+    /// Get file attributes by inode using an existing connection
+    async fn getattr_with_conn(&self, conn: &Connection, ino: i64) -> Result<Option<Stats>> {
+        let mut stmt = conn
+            .prepare_cached(concat!(
+                "SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec, ",
+                blocks_column!("fs_inode"),
+                " FROM fs_inode WHERE ino = ?"
+            ))
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mut stats = Self::build_stats_from_row(&row)?;
+            self.write_buffer.apply(&mut stats);
+            Ok(Some(stats))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Build a Stats object from a database row
+    ///
+    /// The row should contain columns in this order:
+    /// ino, mode, nlink, uid, gid, size, atime, mtime, ctime
+    fn build_stats_from_row(row: &turso::Row) -> Result<Stats> {
+        Ok(Stats {
+            ino: row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0),
+            mode: row
+                .get_value(1)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32,
+            nlink: row
+                .get_value(2)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(1) as u32,
+            uid: row
+                .get_value(3)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32,
+            gid: row
+                .get_value(4)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32,
+            size: row
+                .get_value(5)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0),
+            atime: row
+                .get_value(6)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0),
+            mtime: row
+                .get_value(7)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0),
+            ctime: row
+                .get_value(8)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0),
+            atime_nsec: row
+                .get_value(10)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32,
+            mtime_nsec: row
+                .get_value(11)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32,
+            ctime_nsec: row
+                .get_value(12)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32,
+            rdev: row
+                .get_value(9)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u64,
+            blocks: row
+                .get_value(13)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u64,
+        })
+    }
+
+    /// Resolve a path to an inode number, following symlinks
+    async fn resolve_path(&self, path: &str) -> Result<Option<i64>> {
+        let conn = self.pool.get_connection().await?;
+        self.resolve_path_follow_with_conn(&conn, path).await
+    }
+
+    /// Resolve a path to an inode number using a provided connection
+    async fn resolve_path_with_conn(&self, conn: &Connection, path: &str) -> Result<Option<i64>> {
+        let components = self.split_path(path);
+        if components.is_empty() {
+            return Ok(Some(ROOT_INO));
+        }
+
+        let mut statement: Option<turso::Statement> = None;
+        let mut current_ino = ROOT_INO;
+        for component in components {
+            // Check cache first
+            if let Some(cached_ino) = self.dentry_cache.get(current_ino, &component) {
+                current_ino = cached_ino;
+                continue;
+            }
+
+            // Cache miss - query database
+            if let Some(statement) = &mut statement {
+                statement.reset()?;
+            } else {
+                statement = Some(
+                    conn.prepare_cached(&format!(
+                        "SELECT ino FROM fs_dentry WHERE {}",
+                        self.dentry_match()
+                    ))
+                    .await?,
+                );
+            }
+            let statement = statement.as_mut().expect("statement was set above");
+            let mut rows = statement
+                .query((current_ino, self.dentry_key(&component)))
+                .await?;
+
+            let mut found_row = None;
+            let mut row_count = 0;
+
+            while let Some(row) = rows.next().await? {
+                found_row = Some(row);
+                row_count += 1;
+            }
+
+            if row_count > 1 {
+                return Err(FsError::InvalidPath.into());
+            }
+
+            if let Some(row) = found_row {
+                let child_ino = row
+                    .get_value(0)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0);
+
+                // Populate cache
+                self.dentry_cache.insert(current_ino, &component, child_ino);
+                current_ino = child_ino;
+            } else {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(current_ino))
+    }
+
+    /// Resolve every symlink along a path, returning the path they lead to
+    /// and its inode if it exists.
+    ///
+    /// Symlinks are followed in intermediate components as well as the final
+    /// one. Components that don't exist are kept as-is, so a dangling symlink
+    /// resolves to its (missing) target. Fails with `FsError::SymlinkLoop`
+    /// once more than `MAX_SYMLINKS` links have been followed.
+    async fn follow_path_with_conn(
+        &self,
+        conn: &Connection,
+        path: &str,
+    ) -> Result<(String, Option<i64>)> {
+        let mut pending: VecDeque<String> = self.split_path(path).into();
+        let mut resolved: Vec<String> = Vec::new();
+        let mut current_ino = Some(ROOT_INO);
+        let mut followed = 0;
+
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "SELECT d.ino, s.target FROM fs_dentry d LEFT JOIN fs_symlink s ON s.ino = d.ino WHERE {}",
+                self.dentry_match()
+            ))
+            .await?;
+        while let Some(component) = pending.pop_front() {
+            // Below a missing component nothing can be a symlink
+            let Some(parent_ino) = current_ino else {
+                resolved.push(component);
+                continue;
+            };
+
+            stmt.reset()?;
+            let mut rows = stmt
+                .query((parent_ino, self.dentry_key(&component)))
+                .await?;
+            let Some(row) = rows.next().await? else {
+                current_ino = None;
+                resolved.push(component);
+                continue;
+            };
+            // Run the statement to completion before it is reset, otherwise
+            // turso rolls back the surrounding transaction
+            while rows.next().await?.is_some() {}
+            let child_ino = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0);
+            let target = match row.get_value(1) {
+                Ok(Value::Text(target)) => target,
+                _ => {
+                    current_ino = Some(child_ino);
+                    resolved.push(component);
+                    continue;
+                }
+            };
+
+            followed += 1;
+            if followed > MAX_SYMLINKS {
+                return Err(FsError::SymlinkLoop.into());
+            }
+
+            // Restart from the link target, relative to the link's directory
+            let mut next = if target.starts_with('/') {
+                target
+            } else {
+                format!("/{}/{}", resolved.join("/"), target)
+            };
+            for rest in &pending {
+                next.push('/');
+                next.push_str(rest);
+            }
+            pending = self.split_path(&next).into();
+            resolved.clear();
+            current_ino = Some(ROOT_INO);
+        }
+
+        Ok((format!("/{}", resolved.join("/")), current_ino))
+    }
+
+    /// Resolve a path to an inode number, following symlinks
+    async fn resolve_path_follow_with_conn(
+        &self,
+        conn: &Connection,
+        path: &str,
+    ) -> Result<Option<i64>> {
+        let (_, ino) = self.follow_path_with_conn(conn, path).await?;
+        Ok(ino)
+    }
+
+    /// Get file statistics without following symlinks
+    pub async fn lstat(&self, path: &str) -> Result<Option<Stats>> {
+        let conn = self.pool.get_connection().await?;
+        let path = normalize_path(path);
+        let ino = match self.resolve_path_with_conn(&conn, &path).await? {
+            Some(ino) => ino,
+            None => return Ok(None),
+        };
+
+        let mut stmt = conn
+            .prepare_cached(concat!(
+                "SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec, ",
+                blocks_column!("fs_inode"),
+                " FROM fs_inode WHERE ino = ?"
+            ))
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mut stats = Self::build_stats_from_row(&row)?;
+            self.write_buffer.apply(&mut stats);
+            Ok(Some(stats))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get file statistics, following symlinks
+    pub async fn stat(&self, path: &str) -> Result<Option<Stats>> {
+        let conn = self.pool.get_connection().await?;
+        self.stat_with_conn(&conn, path).await
+    }
+
+    /// Get file statistics, following symlinks (using provided connection)
+    async fn stat_with_conn(&self, conn: &Connection, path: &str) -> Result<Option<Stats>> {
+        match self.resolve_path_follow_with_conn(conn, path).await? {
+            Some(ino) => self.getattr_with_conn(conn, ino).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Create a directory
+    pub async fn mkdir(&self, path: &str, uid: u32, gid: u32) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        let path = normalize_path(path);
+        let components = self.split_path(&path);
+
+        if components.is_empty() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        let parent_path = if components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!("/{}", components[..components.len() - 1].join("/"))
+        };
+
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let name = components.last().unwrap();
+
+        // Check if already exists (single query using parent_ino we already have)
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Create inode with default directory mode (path-based API doesn't accept mode)
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let row = stmt
+            .query_row((
+                self.apply_umask(DEFAULT_DIR_MODE) as i64,
+                uid,
+                gid,
+                now_secs,
+                now_secs,
+                now_secs,
+                now_nsec,
+                now_nsec,
+                now_nsec,
+            ))
+            .await?;
+
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+        // Create directory entry
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            )
+            .await?;
+        stmt.execute((name.as_str(), parent_ino, ino, self.name_key(name.as_str())))
+            .await?;
+
+        // Set nlink to 2 for new directory (self "." + parent's dentry)
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET nlink = 2 WHERE ino = ?")
+            .await?;
+        stmt.execute((ino,)).await?;
+
+        // Increment parent nlink (new directory's ".." link) and update timestamps
+        let mut stmt = conn
+            .prepare_cached(
+                "UPDATE fs_inode SET nlink = nlink + 1, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
+            )
+            .await?;
+        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+            .await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(parent_ino, name, ino);
+
+        self.notify_path(ChangeEventKind::Mkdir, &path);
+        Ok(())
+    }
+
+    /// Create a special file node (FIFO, device, socket, or regular file)
+    pub async fn mknod(&self, path: &str, mode: u32, rdev: u64, uid: u32, gid: u32) -> Result<()> {
+        let mode = self.apply_umask(mode);
+        let conn = self.pool.get_connection().await?;
+        let path = normalize_path(path);
+        let components = self.split_path(&path);
+
+        if components.is_empty() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        let parent_path = if components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!("/{}", components[..components.len() - 1].join("/"))
+        };
+
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let name = components.last().unwrap();
+
+        // Check if already exists
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Create inode with mode and rdev
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec)
+                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let row = stmt
+            .query_row((
+                mode as i64,
+                uid,
+                gid,
+                now_secs,
+                now_secs,
+                now_secs,
+                rdev as i64,
+                now_nsec,
+                now_nsec,
+                now_nsec,
+            ))
+            .await?;
+
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+        // Create directory entry
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            )
+            .await?;
+        stmt.execute((name.as_str(), parent_ino, ino, self.name_key(name.as_str())))
+            .await?;
+
+        // Increment link count
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
+            .await?;
+        stmt.execute((ino,)).await?;
+        touch_dir(&conn, parent_ino, now_secs, now_nsec).await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(parent_ino, name, ino);
+
+        self.notify_path(ChangeEventKind::Create, &path);
+        Ok(())
+    }
+
+    /// Create a new empty file with the specified mode and ownership.
+    ///
+    /// This is an optimized path for FUSE create() that combines inode creation,
+    /// dentry creation, and file handle opening in a single operation.
+    /// Returns both Stats and an open file handle.
+    pub async fn create_file(
+        &self,
+        path: &str,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<(Stats, BoxedFile)> {
+        let mode = self.apply_umask(mode);
+        let conn = self.pool.get_connection().await?;
+        let path = normalize_path(path);
+        let components = self.split_path(&path);
+
+        if components.is_empty() {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        let parent_path = match components.len() {
+            1 => "/".to_string(),
+            _ => format!("/{}", components[..components.len() - 1].join("/")),
+        };
+
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let name = components.last().unwrap();
+
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Prepare statements before starting the transaction
+        let mut inode_stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                 VALUES (?, 1, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let mut dentry_stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            )
+            .await?;
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let file_mode = S_IFREG | (mode & 0o7777);
+
+        let row = inode_stmt
+            .query_row((
+                file_mode as i64,
+                uid,
+                gid,
+                now_secs,
+                now_secs,
+                now_secs,
+                now_nsec,
+                now_nsec,
+                now_nsec,
+            ))
+            .await?;
+
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+        dentry_stmt
+            .execute((name.as_str(), parent_ino, ino, self.name_key(name.as_str())))
+            .await?;
+        touch_dir(&conn, parent_ino, now_secs, now_nsec).await?;
+
+        txn.commit().await?;
+
+        self.dentry_cache.insert(parent_ino, name, ino);
+
+        let stats = Stats {
+            ino,
+            mode: file_mode,
+            nlink: 1,
+            uid,
+            gid,
+            size: 0,
+            atime: now_secs,
+            mtime: now_secs,
+            ctime: now_secs,
+            atime_nsec: now_nsec as u32,
+            mtime_nsec: now_nsec as u32,
+            ctime_nsec: now_nsec as u32,
+            rdev: 0,
+            blocks: 0,
+        };
+
+        let file: BoxedFile = Arc::new(self.file_handle(ino, false));
+
+        self.notify_path(ChangeEventKind::Create, &path);
+        Ok((stats, file))
+    }
+
+    /// Read data from a file
+    ///
+    /// Holes in sparse files read as zeros.
+    pub async fn read_file(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+
+        // Resolve and read from one snapshot, so that a file replaced by
+        // `atomic_write` meanwhile is seen whole
+        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Deferred).await?;
+        let mut resolved = self.resolve_path_follow_with_conn(&conn, path).await?;
+        if let Some(ino) = resolved {
+            if self.get_link_count(&conn, ino).await? == 0 {
+                // A cached entry still named the file that was replaced
+                self.dentry_cache.clear();
+                resolved = self.resolve_path_follow_with_conn(&conn, path).await?;
+            }
+        }
+        let Some(ino) = resolved else {
+            txn.commit().await?;
+            return Ok(None);
+        };
+        let data = read_range(
+            &conn,
+            &self.encoding,
+            self.chunk_size as u64,
+            ino,
+            0,
+            u64::MAX,
+        )
+        .await?;
+        txn.commit().await?;
+
+        touch_atime(&conn, ino, &self.atime_policy).await;
+        Ok(Some(data))
+    }
+
+    /// Reads from a file at a given offset.
+    ///
+    /// Similar to POSIX `pread`, this reads up to `size` bytes from the file
+    /// starting at `offset`, without modifying any file cursor. A read that
+    /// straddles the end of the file returns only the bytes before it, and
+    /// one starting at or past the end returns `Ok(Some(vec![]))`.
+    ///
+    /// Returns `Ok(None)` only if the file does not exist.
+    pub async fn pread(&self, path: &str, offset: u64, size: u64) -> Result<Option<Vec<u8>>> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let ino = match self.resolve_path_follow_with_conn(&conn, path).await? {
+            Some(ino) => ino,
+            None => return Ok(None),
+        };
+
+        let data = read_range(
+            &conn,
+            &self.encoding,
+            self.chunk_size as u64,
+            ino,
+            offset,
+            size,
+        )
+        .await?;
+        touch_atime(&conn, ino, &self.atime_policy).await;
+        Ok(Some(data))
+    }
+
+    /// Byte ranges of a file that hold stored data, as `(offset, len)`
+    /// pairs in ascending order.
+    ///
+    /// Everything outside them is a hole that reads as zeros, like the
+    /// ranges `lseek(2)` finds with `SEEK_DATA` and `SEEK_HOLE`. Ranges
+    /// cover whole chunks, clipped to the file size, so they may include
+    /// zeros that were written explicitly.
+    ///
+    /// Returns `Ok(None)` if the file does not exist.
+    pub async fn data_ranges(&self, path: &str) -> Result<Option<Vec<(u64, u64)>>> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let ino = match self.resolve_path_follow_with_conn(&conn, path).await? {
+            Some(ino) => ino,
+            None => return Ok(None),
+        };
+        let size = match self.getattr_with_conn(&conn, ino).await? {
+            Some(stats) => stats.size.max(0) as u64,
+            None => return Ok(None),
+        };
+
+        let chunk_size = self.chunk_size as u64;
+        let mut stmt = conn
+            .prepare_cached("SELECT chunk_index FROM fs_data WHERE ino = ? ORDER BY chunk_index")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let start = row_integer(&row, 0) as u64 * chunk_size;
+            if start >= size {
+                break;
+            }
+            let len = chunk_size.min(size - start);
+            match ranges.last_mut() {
+                Some((offset, prev_len)) if *offset + *prev_len == start => *prev_len += len,
+                _ => ranges.push((start, len)),
+            }
+        }
+        Ok(Some(ranges))
+    }
+
+    /// Writes to a file at a given offset.
+    ///
+    /// Similar to POSIX `pwrite`, this writes `data` to the file starting at
+    /// `offset`, without modifying any file cursor.
+    ///
+    /// If the offset is beyond the current file size, the file is extended with zeros.
+    /// If the file does not exist, it will be created.
+    pub async fn pwrite(&self, path: &str, offset: u64, data: &[u8]) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let (path, _) = self.follow_path_with_conn(&conn, path).await?;
+        let components = self.split_path(&path);
+
+        if components.is_empty() {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        let parent_path = if components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!("/{}", components[..components.len() - 1].join("/"))
+        };
+
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let name = components.last().unwrap();
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let result: Result<bool> = async {
+            // Calculate the final size upfront
+            let write_end = checked_file_end(offset, data.len() as u64)?;
+
+            // Get or create the inode
+            let (ino, current_size, is_new) =
+                if let Some(ino) = self.resolve_path_with_conn(&conn, &path).await? {
+                    // Get current file size
+                    let mut stmt = conn
+                        .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
+                        .await?;
+                    let mut rows = stmt.query((ino,)).await?;
+                    let size = if let Some(row) = rows.next().await? {
+                        row.get_value(0)
+                            .ok()
+                            .and_then(|v| v.as_integer().copied())
+                            .unwrap_or(0) as u64
+                    } else {
+                        0
+                    };
+                    check_quota(&conn, &self.max_bytes, write_end.saturating_sub(size)).await?;
+                    (ino, size, false)
+                } else {
+                    check_quota(&conn, &self.max_bytes, write_end).await?;
+
+                    // Create new inode with correct size upfront
+                    let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                    let now_secs = dur.as_secs() as i64;
+                    let now_nsec = dur.subsec_nanos() as i64;
+                    let new_size = write_end as i64;
+                    let mut stmt = conn
+                        .prepare_cached(
+                            "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, nlink, atime_nsec, mtime_nsec, ctime_nsec)
+                        VALUES (?, 0, 0, ?, ?, ?, ?, 1, ?, ?, ?) RETURNING ino",
+                        )
+                        .await?;
+                    let row = stmt
+                        .query_row((DEFAULT_FILE_MODE as i64, new_size, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec))
+                        .await?;
+
+                    let ino = row
+                        .get_value(0)
+                        .ok()
+                        .and_then(|v| v.as_integer().copied())
+                        .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+                    // Create directory entry
+                    let mut stmt = conn
+                        .prepare_cached(
+                            "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+                        )
+                        .await?;
+                    stmt.execute((name.as_str(), parent_ino, ino, self.name_key(name.as_str()))).await?;
+                    touch_dir(&conn, parent_ino, now_secs, now_nsec).await?;
+
+                    (ino, 0, true)
+                };
+
+            // Handle empty writes - just update mtime
+            if data.is_empty() {
+                let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                let now_secs = dur.as_secs() as i64;
+                let now_nsec = dur.subsec_nanos() as i64;
+                conn.prepare_cached("UPDATE fs_inode SET mtime = ?, mtime_nsec = ? WHERE ino = ?")
+                    .await?
+                    .execute((now_secs, now_nsec, ino))
+                    .await?;
+                return Ok(is_new);
+            }
+
+            let chunk_size = self.chunk_size as u64;
+
+            // Calculate affected chunk range
+            let start_chunk = offset / chunk_size;
+            let end_chunk = (write_end - 1) / chunk_size;
+
+            // Process each affected chunk
+            for chunk_idx in start_chunk..=end_chunk {
+                let chunk_start = chunk_idx * chunk_size;
+
+                // Calculate what part of data goes into this chunk
+                let data_start = if offset > chunk_start {
+                    (offset - chunk_start) as usize
+                } else {
+                    0
+                };
+                let data_end =
+                    std::cmp::min(chunk_size as usize, (write_end - chunk_start) as usize);
+
+                // Calculate what part of data to copy
+                let src_start = if chunk_start > offset {
+                    (chunk_start - offset) as usize
+                } else {
+                    0
+                };
+                let src_end = std::cmp::min(data.len(), src_start + (data_end - data_start));
+
+                // Read existing chunk if we need to preserve some data
+                let needs_read = data_start > 0 || data_end < chunk_size as usize;
+                let mut chunk_data = if needs_read {
+                    if let Some(mut v) = read_chunk(&conn, &self.encoding, ino, chunk_idx as i64).await? {
+                        v.resize(chunk_size as usize, 0);
+                        v
+                    } else {
+                        vec![0u8; chunk_size as usize]
+                    }
+                } else {
+                    vec![0u8; chunk_size as usize]
+                };
+
+                // Copy the new data into the chunk
+                chunk_data[data_start..data_end].copy_from_slice(&data[src_start..src_end]);
+
+                // Trim trailing zeros for the last chunk
+                let actual_len = if chunk_idx == end_chunk {
+                    let file_end_in_chunk = (write_end - chunk_start) as usize;
+                    let old_end_in_chunk = if current_size > chunk_start {
+                        std::cmp::min((current_size - chunk_start) as usize, chunk_size as usize)
+                    } else {
+                        0
+                    };
+                    std::cmp::max(file_end_in_chunk, old_end_in_chunk)
+                } else {
+                    chunk_size as usize
+                };
+
+                // Write the chunk, replacing the existing one
+                store_chunk(
+                    &conn,
+                    &self.encoding,
+                    ino,
+                    chunk_idx as i64,
+                    &chunk_data[..actual_len],
+                )
+                .await?;
+            }
+
+            // Update size and mtime (only if not new, since new inodes already have correct values)
+            if !is_new {
+                let new_size = std::cmp::max(current_size, write_end);
+                let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                let now_secs = dur.as_secs() as i64;
+                let now_nsec = dur.subsec_nanos() as i64;
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET size = ?, mtime = ?, mtime_nsec = ? WHERE ino = ?")
+                    .await?;
+                stmt.execute((new_size as i64, now_secs, now_nsec, ino)).await?;
+            }
+
+            Ok(is_new)
+        }
+        .await;
+
+        match result {
+            Ok(is_new) => {
+                txn.commit().await?;
+                let kind = if is_new {
+                    ChangeEventKind::Create
+                } else {
+                    ChangeEventKind::Write
+                };
+                self.notify_path(kind, &path);
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Truncate a file to a specific size.
+    ///
+    /// This operates directly on chunks without loading the entire file into memory:
+    /// - Shrinking: deletes chunks beyond new size, truncates the last chunk if needed
+    /// - Extending: leaves a sparse hole that reads as zeros
+    pub async fn truncate(&self, path: &str, new_size: u64) -> Result<()> {
+        self.flush_writes().await?;
+        checked_file_end(new_size, 0)?;
+        let conn = self.pool.get_connection().await?;
+        let ino = self
+            .resolve_path_follow_with_conn(&conn, path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let chunk_size = self.chunk_size as u64;
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let result: Result<()> = async {
+            // Directories, including `/`, have no contents to truncate. The
+            // size is read inside the transaction, so the quota check sees
+            // any concurrent extend.
+            let stats = self
+                .getattr_with_conn(&conn, ino)
+                .await?
+                .ok_or(FsError::NotFound)?;
+            if stats.is_directory() {
+                return Err(FsError::IsADirectory.into());
+            }
+            let current_size = stats.size as u64;
+
+            check_quota(
+                &conn,
+                &self.max_bytes,
+                new_size.saturating_sub(current_size),
+            )
+            .await?;
+
+            if new_size == 0 {
+                // Special case: truncate to zero - just delete all chunks
+                delete_chunks(&conn, ino, 0).await?;
+            } else if new_size < current_size {
+                // Shrinking: delete excess chunks and truncate last chunk if needed
+                let last_chunk_idx = (new_size - 1) / chunk_size;
+
+                // Delete all chunks beyond the last one we need
+                delete_chunks(&conn, ino, last_chunk_idx as i64 + 1).await?;
+
+                // Calculate where in the last chunk the file should end
+                let end_in_last_chunk = ((new_size - 1) % chunk_size) + 1;
+
+                // If the last chunk needs to be truncated (not a full chunk),
+                // read it, truncate, and rewrite
+                if end_in_last_chunk < chunk_size {
+                    if let Some(chunk_data) =
+                        read_chunk(&conn, &self.encoding, ino, last_chunk_idx as i64).await?
+                    {
+                        if chunk_data.len() > end_in_last_chunk as usize {
+                            let truncated = &chunk_data[..end_in_last_chunk as usize];
+                            store_chunk(
+                                &conn,
+                                &self.encoding,
+                                ino,
+                                last_chunk_idx as i64,
+                                truncated,
+                            )
+                            .await?;
+                        }
+                    }
+                }
+            }
+            // Extending only updates the size: the new range is a hole that
+            // reads as zeros without storing any chunks
+
+            // Update size and mtime
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            let mut stmt = conn
+                .prepare_cached(
+                    "UPDATE fs_inode SET size = ?, mtime = ?, mtime_nsec = ? WHERE ino = ?",
+                )
+                .await?;
+            stmt.execute((new_size as i64, now_secs, now_nsec, ino))
+                .await?;
+
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+                notify_ino(&self.events, &conn, ChangeEventKind::Write, ino).await;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// List directory contents
+    pub async fn readdir(&self, ino: i64) -> Result<Option<Vec<String>>> {
+        let conn = self.pool.get_connection().await?;
+        let mut rows = conn
+            .query(
+                "SELECT name FROM fs_dentry WHERE parent_ino = ? ORDER BY name",
+                (ino,),
+            )
+            .await?;
+
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let name = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| {
+                    if let Value::Text(s) = v {
+                        Some(s.clone())
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default();
+            if !name.is_empty() {
+                entries.push(name);
+            }
+        }
+
+        touch_atime(&conn, ino, &self.atime_policy).await;
+        Ok(Some(entries))
+    }
+
+    /// List directory contents with full statistics (optimized batch query)
+    ///
+    /// Returns entries with their stats in a single JOIN query, avoiding N+1 queries.
+    pub async fn readdir_plus(&self, ino: i64) -> Result<Option<Vec<DirEntry>>> {
+        let conn = self.pool.get_connection().await?;
+        let mut stmt = conn.prepare_cached(concat!(
+            "SELECT d.name, i.ino, i.mode, i.nlink, i.uid, i.gid, i.size, i.atime, i.mtime, i.ctime, i.rdev, i.atime_nsec, i.mtime_nsec, i.ctime_nsec, ",
+            blocks_column!("i"),
+            "
+            FROM fs_dentry d
+            JOIN fs_inode i ON d.ino = i.ino
+            WHERE d.parent_ino = ?
+            ORDER BY d.name"
+        )).await?;
+        // Single JOIN query to get all entry names and their stats (including link count)
+        let mut rows = stmt.query((ino,)).await?;
+
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let name = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| {
+                    if let Value::Text(s) = v {
+                        Some(s.clone())
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default();
+
+            if name.is_empty() {
+                continue;
+            }
+
+            let entry_ino = row
+                .get_value(1)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0);
+
+            let nlink = row
+                .get_value(3)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(1) as u32;
+
+            let mut stats = Stats {
+                ino: entry_ino,
+                mode: row
+                    .get_value(2)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                nlink,
+                uid: row
+                    .get_value(4)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                gid: row
+                    .get_value(5)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                size: row
+                    .get_value(6)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                atime: row
+                    .get_value(7)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                mtime: row
+                    .get_value(8)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                ctime: row
+                    .get_value(9)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                atime_nsec: row
+                    .get_value(11)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                mtime_nsec: row
+                    .get_value(12)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                ctime_nsec: row
+                    .get_value(13)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                rdev: row
+                    .get_value(10)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u64,
+                blocks: row
+                    .get_value(14)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u64,
+            };
+
+            self.write_buffer.apply(&mut stats);
+            entries.push(DirEntry { name, stats });
+        }
+
+        touch_atime(&conn, ino, &self.atime_policy).await;
+        Ok(Some(entries))
+    }
+
+    /// Create a symbolic link with the specified ownership
+    pub async fn symlink(&self, target: &str, linkpath: &str, uid: u32, gid: u32) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        let linkpath = normalize_path(linkpath);
+        let components = self.split_path(&linkpath);
+
+        if components.is_empty() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Get parent directory
+        let parent_path = if components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!("/{}", components[..components.len() - 1].join("/"))
+        };
+
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let name = components.last().unwrap();
+
+        // Check if entry already exists (single query using parent_ino we already have)
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Create inode for symlink
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+
+        let mode = S_IFLNK | 0o777; // Symlinks typically have 777 permissions
+        let size = target.len() as i64;
+
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let row = stmt
+            .query_row((
+                mode, uid, gid, size, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec,
+            ))
+            .await?;
+
+        // Get the newly created inode
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .unwrap_or(0);
+
+        // Store symlink target
+        conn.execute(
+            "INSERT INTO fs_symlink (ino, target) VALUES (?, ?)",
+            (ino, target),
+        )
+        .await?;
+
+        // Create directory entry
+        conn.execute(
+            "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            (name.as_str(), parent_ino, ino, self.name_key(name.as_str())),
+        )
+        .await?;
+
+        // Increment link count
+        conn.execute(
+            "UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?",
+            (ino,),
+        )
+        .await?;
+        touch_dir(&conn, parent_ino, now_secs, now_nsec).await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(parent_ino, name, ino);
+
+        self.notify_path(ChangeEventKind::Create, &linkpath);
+        Ok(())
+    }
+
+    /// Create a hard link
+    ///
+    /// Creates a new directory entry `newpath` that refers to the same inode as `oldpath`.
+    /// Both paths will share the same file data and metadata (except for the name).
+    /// The link count (nlink) of the inode is incremented.
+    pub async fn link(&self, oldpath: &str, newpath: &str) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        let oldpath = normalize_path(oldpath);
+        let newpath = normalize_path(newpath);
+        let components = self.split_path(&newpath);
+
+        if components.is_empty() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Resolve old path to get its inode
+        let ino = self
+            .resolve_path_with_conn(&conn, &oldpath)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Check if source is a directory (hard links to directories are not allowed)
+        let mut rows = conn
+            .query("SELECT mode FROM fs_inode WHERE ino = ?", (ino,))
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32;
+
+            if (mode & S_IFMT) == super::S_IFDIR {
+                return Err(FsError::IsADirectory.into());
+            }
+        } else {
+            return Err(FsError::NotFound.into());
+        }
+
+        // Get parent directory of new path
+        let parent_path = if components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!("/{}", components[..components.len() - 1].join("/"))
+        };
+
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let name = components.last().unwrap();
+
+        // Check if new path already exists (single query using parent_ino we already have)
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Create directory entry pointing to the same inode
+        conn.execute(
+            "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            (name.as_str(), parent_ino, ino, self.name_key(name.as_str())),
+        )
+        .await?;
+
+        // Increment link count
+        conn.execute(
+            "UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?",
+            (ino,),
+        )
+        .await?;
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        touch_dir(
+            &conn,
+            parent_ino,
+            dur.as_secs() as i64,
+            dur.subsec_nanos() as i64,
+        )
+        .await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(parent_ino, name, ino);
+
+        self.notify_path(ChangeEventKind::Create, &newpath);
+        Ok(())
+    }
+
+    /// Read the target of a symbolic link
+    pub async fn readlink(&self, path: &str) -> Result<Option<String>> {
+        let conn = self.pool.get_connection().await?;
+        self.readlink_with_conn(&conn, path).await
+    }
+
+    /// Read the target of a symbolic link using a provided connection
+    async fn readlink_with_conn(&self, conn: &Connection, path: &str) -> Result<Option<String>> {
+        let path = normalize_path(path);
+
+        let ino = match self.resolve_path_with_conn(conn, &path).await? {
+            Some(ino) => ino,
+            None => return Ok(None),
+        };
+
+        // Check if it's a symlink by querying the inode
+        let mut rows = conn
+            .query("SELECT mode FROM fs_inode WHERE ino = ?", (ino,))
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32;
+
+            // Check if it's a symlink
+            if (mode & S_IFMT) != S_IFLNK {
+                return Err(FsError::NotASymlink.into());
+            }
+        } else {
+            return Ok(None);
+        }
+
+        // Read target from fs_symlink table
+        let mut rows = conn
+            .query("SELECT target FROM fs_symlink WHERE ino = ?", (ino,))
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            let target = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| match v {
+                    Value::Text(s) => Some(s.to_string()),
+                    _ => None,
+                })
+                .ok_or(FsError::InvalidPath)?;
+            Ok(Some(target))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Remove a file or empty directory
+    pub async fn remove(&self, path: &str) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let path = normalize_path(path);
+        let components = self.split_path(&path);
+
+        if components.is_empty() {
+            return Err(FsError::RootOperation.into());
+        }
+
+        let ino = self
+            .resolve_path_with_conn(&conn, &path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        if ino == ROOT_INO {
+            return Err(FsError::RootOperation.into());
+        }
+
+        // Get stats to check if it's a directory
+        let stats = self
+            .stat_with_conn(&conn, &path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Check if directory is empty
+        let mut stmt = conn
+            .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let count = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0);
+            if count > 0 {
+                return Err(FsError::NotEmpty.into());
+            }
+        }
+
+        // Get parent directory and name
+        let parent_path = if components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!("/{}", components[..components.len() - 1].join("/"))
+        };
+
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        let name = components.last().unwrap();
+
+        if self
+            .trash_entry(&conn, parent_ino, name, ino, stats.is_directory())
+            .await?
+        {
+            self.notify_path(ChangeEventKind::Remove, &path);
+            return Ok(());
+        }
+
+        // Delete the specific directory entry (not all entries pointing to this inode)
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "DELETE FROM fs_dentry WHERE {}",
+                self.dentry_match()
+            ))
+            .await?;
+        stmt.execute((parent_ino, self.dentry_key(name))).await?;
+
+        // Invalidate cache for this entry
+        self.dentry_cache.remove(parent_ino, name);
+
+        // Decrement link count
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+            .await?;
+        stmt.execute((ino,)).await?;
+
+        // If removing a directory, decrement parent nlink (removed dir's ".." link)
+        if stats.is_directory() {
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+                .await?;
+            stmt.execute((parent_ino,)).await?;
+        }
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        touch_dir(
+            &conn,
+            parent_ino,
+            dur.as_secs() as i64,
+            dur.subsec_nanos() as i64,
+        )
+        .await?;
+
+        // Check if this was the last link to the inode
+        let link_count = self.get_link_count(&conn, ino).await?;
+        if link_count == 0 {
+            // Manually handle cascading deletes since we don't use foreign keys
+            // Delete data blocks
+            delete_chunks(&conn, ino, 0).await?;
+
+            // Delete symlink if exists
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+
+            // Delete extended attributes
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_xattr WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+
+            // Delete inode
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+            self.locks.clear(ino);
+        }
+
+        self.notify_path(ChangeEventKind::Remove, &path);
+        Ok(())
+    }
+
+    /// Change file ownership
+    ///
+    /// Changes the user and/or group ownership of a file.
+    /// Pass None for uid or gid to leave that value unchanged.
+    pub async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        let uid = uid.filter(|&id| id != OWNER_UNCHANGED);
+        let gid = gid.filter(|&id| id != OWNER_UNCHANGED);
+        if uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
+
+        let conn = self.pool.get_connection().await?;
+
+        // Build the update query dynamically based on which values are provided
+        let mut updates = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(uid) = uid {
+            updates.push("uid = ?");
+            values.push(Value::Integer(uid as i64));
+        }
+        if let Some(gid) = gid {
+            updates.push("gid = ?");
+            values.push(Value::Integer(gid as i64));
+        }
+
+        values.push(Value::Integer(ino));
+        let sql = format!("UPDATE fs_inode SET {} WHERE ino = ?", updates.join(", "));
+        conn.execute(&sql, values).await?;
+
+        Ok(())
+    }
+
+    /// Rename/move a file or directory.
+    ///
+    /// This operation is atomic - either all changes succeed or none do.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let from_path = normalize_path(from);
+        let to_path = normalize_path(to);
+
+        // Cannot rename root
+        if from_path == "/" {
+            return Err(FsError::RootOperation.into());
+        }
+
+        // Get source inode
+        let src_ino = self
+            .resolve_path_with_conn(&conn, &from_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Get source stats to check if it's a directory, without following
+        // a symlink: the link itself is what moves
+        let src_stats = self
+            .getattr_with_conn(&conn, src_ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Parse source path to get parent and name
+        let from_components = self.split_path(&from_path);
+        let src_name = from_components.last().ok_or(FsError::InvalidPath)?;
+        let src_parent_path = if from_components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!(
+                "/{}",
+                from_components[..from_components.len() - 1].join("/")
+            )
+        };
+        let src_parent_ino = self
+            .resolve_path_with_conn(&conn, &src_parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Parse destination path to get parent and name
+        let to_components = self.split_path(&to_path);
+        if to_components.is_empty() {
+            return Err(FsError::RootOperation.into());
+        }
+        let dst_name = to_components.last().unwrap();
+        let dst_parent_path = if to_components.len() == 1 {
+            "/".to_string()
+        } else {
+            format!("/{}", to_components[..to_components.len() - 1].join("/"))
+        };
+        let dst_parent_ino = self
+            .resolve_path_with_conn(&conn, &dst_parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Prevent renaming a directory into its own subtree (would create a cycle)
+        if src_stats.is_directory() && self.is_within(&conn, dst_parent_ino, src_ino).await? {
+            return Err(FsError::InvalidRename.into());
+        }
+
+        // Clone strings for use inside the transaction closure
+        let src_name = src_name.clone();
+        let dst_name = dst_name.clone();
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        // Renaming an entry onto itself only changes the case of its name
+        let same_entry = src_parent_ino == dst_parent_ino
+            && self.dentry_key(&src_name) == self.dentry_key(&dst_name);
+
+        let result: Result<()> = async {
+            // Check if destination exists (inside transaction for atomicity)
+            let dst_ino = if same_entry {
+                None
+            } else {
+                self.resolve_path_with_conn(&conn, &to_path).await?
+            };
+            if let Some(dst_ino) = dst_ino {
+                let dst_stats = self.getattr_with_conn(&conn, dst_ino).await?.ok_or(FsError::NotFound)?;
+
+                // Can't replace directory with non-directory
+                if dst_stats.is_directory() && !src_stats.is_directory() {
+                    return Err(FsError::IsADirectory.into());
+                }
+
+                // Can't replace non-directory with directory
+                if !dst_stats.is_directory() && src_stats.is_directory() {
+                    return Err(FsError::NotADirectory.into());
+                }
+
+                // If destination is directory, it must be empty
+                if dst_stats.is_directory() {
+                    let mut stmt = conn
+                        .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
+                        .await?;
+                    let mut rows = stmt.query((dst_ino,)).await?;
+
+                    if let Some(row) = rows.next().await? {
+                        let count = row
+                            .get_value(0)
+                            .ok()
+                            .and_then(|v| v.as_integer().copied())
+                            .unwrap_or(0);
+                        if count > 0 {
+                            return Err(FsError::NotEmpty.into());
+                        }
+                    }
+                }
+
+                // Remove destination entry
+                let mut stmt = conn
+                    .prepare_cached(&format!(
+                        "DELETE FROM fs_dentry WHERE {}",
+                        self.dentry_match()
+                    ))
+                    .await?;
+                stmt.execute((dst_parent_ino, self.dentry_key(&dst_name))).await?;
+
+                // Decrement link count
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+                    .await?;
+                stmt.execute((dst_ino,)).await?;
+
+                // Clean up destination inode if no more links
+                let link_count = self.get_link_count(&conn, dst_ino).await?;
+                if link_count == 0 {
+                    delete_chunks(&conn, dst_ino, 0).await?;
+                    let mut stmt = conn
+                        .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
+                        .await?;
+                    stmt.execute((dst_ino,)).await?;
+                    let mut stmt = conn
+                        .prepare_cached("DELETE FROM fs_xattr WHERE ino = ?")
+                        .await?;
+                    stmt.execute((dst_ino,)).await?;
+                    let mut stmt = conn
+                        .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
+                        .await?;
+                    stmt.execute((dst_ino,)).await?;
+                    self.locks.clear(dst_ino);
+                }
+            }
+
+            // Update the dentry: change parent and/or name
+            let mut stmt = conn
+                .prepare_cached(&format!(
+                    "UPDATE fs_dentry SET parent_ino = ?, name = ?, name_key = ? WHERE {}",
+                    self.dentry_match()
+                ))
+                .await?;
+            stmt.execute((
+                dst_parent_ino,
+                dst_name.as_str(),
+                self.name_key(&dst_name),
+                src_parent_ino,
+                self.dentry_key(&src_name),
+            ))
+            .await?;
+
+            // If renaming a directory across parents, adjust parent nlink counts
+            if src_stats.is_directory() && src_parent_ino != dst_parent_ino {
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+                    .await?;
+                stmt.execute((src_parent_ino,)).await?;
+
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
+                    .await?;
+                stmt.execute((dst_parent_ino,)).await?;
+            }
+
+            // Update ctime of the inode
+            let dur = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_nsec, src_ino)).await?;
+
+            // Update source parent directory timestamps
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_secs, now_nsec, now_nsec, src_parent_ino)).await?;
+
+            // Update destination parent directory timestamps
+            if dst_parent_ino != src_parent_ino {
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+                    .await?;
+                stmt.execute((now_secs, now_secs, now_nsec, now_nsec, dst_parent_ino)).await?;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+
+                // Invalidate cache for source and destination
+                self.dentry_cache.remove(src_parent_ino, &src_name);
+                self.dentry_cache.remove(dst_parent_ino, &dst_name);
+
+                // Add new entry to cache (source inode is now at destination)
+                self.dentry_cache.insert(dst_parent_ino, &dst_name, src_ino);
+
+                self.notify_path(ChangeEventKind::Rename, &from_path);
+                self.notify_path(ChangeEventKind::Rename, &to_path);
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Get filesystem statistics
+    ///
+    /// Returns the number of inodes and bytes used by file contents. The
+    /// capacity is the byte quota when one is set, and a large virtual size
+    /// otherwise, so that tools don't think the filesystem is full.
+    pub async fn statfs(&self) -> Result<FilesystemStats> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        // Count total inodes
+        let mut stmt = conn.prepare_cached("SELECT COUNT(*) FROM fs_inode").await?;
+        let mut rows = stmt.query(()).await?;
+
+        let inodes = if let Some(row) = rows.next().await? {
+            row.get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u64
+        } else {
+            0
+        };
+
+        // Sum total bytes used (from file sizes in inodes)
+        let mut stmt = conn
+            .prepare_cached("SELECT COALESCE(SUM(size), 0) FROM fs_inode")
+            .await?;
+        let mut rows = stmt.query(()).await?;
+
+        let bytes_used = if let Some(row) = rows.next().await? {
+            row.get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u64
+        } else {
+            0
+        };
+
+        let total_bytes = self.max_bytes().unwrap_or(VIRTUAL_CAPACITY_BYTES);
+        Ok(FilesystemStats {
+            inodes,
+            bytes_used,
+            total_bytes,
+            free_bytes: total_bytes.saturating_sub(bytes_used),
+            total_inodes: VIRTUAL_INODES.max(inodes),
+            block_size: self.chunk_size as u32,
+        })
+    }
+
+    /// Synchronize file data to persistent storage
+    ///
+    /// Temporarily enables FULL synchronous mode, runs a transaction to force
+    /// a checkpoint, then restores OFF mode. This ensures durability while
+    /// maintaining high performance for normal operations.
+    ///
+    /// Note: The path parameter is ignored since all data is in a single database.
+    pub async fn fsync(&self, _path: &str) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        sync_wal(&conn).await
+    }
+
+    /// Open a file and return a file handle.
+    ///
+    /// The returned handle can be used for efficient read/write/fsync operations
+    /// without requiring path lookups on each operation.
+    pub async fn open(&self, path: &str) -> Result<BoxedFile> {
+        let ino = self.resolve_path(path).await?.ok_or(FsError::NotFound)?;
+
+        Ok(Arc::new(self.file_handle(ino, false)))
+    }
+
+    /// Record a point-in-time snapshot of the filesystem under `label`.
+    ///
+    /// The snapshot stores the current high-water marks of the inode and
+    /// directory entry tables, which [`AgentFS::restore`] uses to discard
+    /// everything created afterwards, and the path of every entry, which
+    /// [`AgentFS::changes_since`] uses to find deletions.
+    pub async fn snapshot(&self, label: &str) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+
+        let mut stmt = conn
+            .prepare_cached("SELECT 1 FROM fs_snapshots WHERE label = ?")
+            .await?;
+        let mut rows = stmt.query((label,)).await?;
+        if rows.next().await?.is_some() {
+            return Err(Error::SnapshotExists(label.to_string()));
+        }
+        drop(rows);
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+        let result: Result<()> = async {
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let mut stmt = conn
+                .prepare_cached(
+                    "INSERT INTO fs_snapshots (label, max_ino, max_dentry_id, created_at)
+                    SELECT ?, (SELECT COALESCE(MAX(ino), 0) FROM fs_inode),
+                           (SELECT COALESCE(MAX(id), 0) FROM fs_dentry), ?",
+                )
+                .await?;
+            stmt.execute((label, dur.as_secs() as i64)).await?;
+
+            let mut stmt = conn
+                .prepare_cached("INSERT INTO fs_snapshot_paths (label, path) VALUES (?, ?)")
+                .await?;
+            for entry in entry_paths(&conn).await? {
+                stmt.execute((label, entry.path.as_str())).await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// List what was created, modified and deleted since the snapshot
+    /// recorded under `label`.
+    ///
+    /// An entry counts as changed if it was created after the snapshot, or
+    /// if its inode was modified or renamed in or after the second the
+    /// snapshot was taken, so the result may include a few entries that
+    /// were not changed but never misses one that was. Deletions are only
+    /// known for snapshots that recorded their paths; for older snapshots
+    /// of a non-empty filesystem this fails with `Error::Internal`.
+    pub async fn changes_since(&self, label: &str) -> Result<SnapshotChanges> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT max_ino, max_dentry_id, created_at FROM fs_snapshots WHERE label = ?",
+            )
+            .await?;
+        let mut rows = stmt.query((label,)).await?;
+        let (max_ino, max_dentry_id, created_at) = match rows.next().await? {
+            Some(row) => {
+                let get = |i: usize| {
+                    row.get_value(i)
+                        .ok()
+                        .and_then(|v| v.as_integer().copied())
+                        .unwrap_or(0)
+                };
+                (get(0), get(1), get(2))
+            }
+            None => return Err(Error::SnapshotNotFound(label.to_string())),
+        };
+        drop(rows);
+
+        let mut stmt = conn
+            .prepare_cached("SELECT path FROM fs_snapshot_paths WHERE label = ?")
+            .await?;
+        let mut rows = stmt.query((label,)).await?;
+        let mut before = HashSet::new();
+        while let Some(row) = rows.next().await? {
+            if let Ok(Value::Text(path)) = row.get_value(0) {
+                before.insert(path);
+            }
+        }
+        drop(rows);
+        if before.is_empty() && max_dentry_id > 0 {
+            return Err(Error::Internal(format!(
+                "snapshot '{}' does not record its paths; take a new snapshot",
+                label
+            )));
+        }
+
+        let mut changes = SnapshotChanges::default();
+        let mut now = HashSet::new();
+        for entry in entry_paths(&conn).await? {
+            if entry.ino > max_ino
+                || entry.dentry_id > max_dentry_id
+                || entry.mtime >= created_at
+                || entry.ctime >= created_at
+            {
+                changes.changed.push(entry.path.clone());
+            }
+            now.insert(entry.path);
+        }
+
+        let mut deleted: Vec<String> = before.into_iter().filter(|p| !now.contains(p)).collect();
+        deleted.sort();
+        // A deleted directory takes its contents with it
+        for path in deleted {
+            let covered = changes
+                .deleted
+                .last()
+                .is_some_and(|dir: &String| path.starts_with(&format!("{}/", dir)));
+            if !covered {
+                changes.deleted.push(path);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Roll the filesystem back to the snapshot recorded under `label`.
+    ///
+    /// Removes every inode and directory entry created after the snapshot,
+    /// along with their data, and recomputes link counts. Changes made to
+    /// entries that already existed at snapshot time (writes, renames,
+    /// deletions) are not reverted; an older entry renamed into a directory
+    /// created after the snapshot is removed with that directory. Later
+    /// snapshots are dropped, since they would refer to discarded entries.
+    pub async fn restore(&self, label: &str) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT max_ino, max_dentry_id, rowid FROM fs_snapshots WHERE label = ?",
+            )
+            .await?;
+        let mut rows = stmt.query((label,)).await?;
+        let (max_ino, max_dentry_id, snapshot_rowid) = match rows.next().await? {
+            Some(row) => {
+                let get = |i: usize| {
+                    row.get_value(i)
+                        .ok()
+                        .and_then(|v| v.as_integer().copied())
+                        .unwrap_or(0)
+                };
+                (get(0), get(1), get(2))
+            }
+            None => return Err(Error::SnapshotNotFound(label.to_string())),
+        };
+        drop(rows);
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let result: Result<()> = async {
+            // Entries created after the snapshot, or living under a directory
+            // created after it, go away with it
+            conn.execute(
+                "DELETE FROM fs_dentry WHERE id > ? OR ino > ? OR parent_ino > ?",
+                (max_dentry_id, max_ino, max_ino),
+            )
+            .await?;
+            let mut rows = conn
+                .query(
+                    "SELECT hash FROM fs_data WHERE ino > ? AND hash IS NOT NULL",
+                    (max_ino,),
+                )
+                .await?;
+            let hashes = collect_hashes(&mut rows).await?;
+            drop(rows);
+            release_blobs(&conn, hashes).await?;
+            for table in ["fs_data", "fs_symlink", "fs_xattr", "fs_inode", "fs_trash"] {
+                conn.execute(&format!("DELETE FROM {table} WHERE ino > ?"), (max_ino,))
+                    .await?;
+            }
+
+            // An inode from before the snapshot that was since renamed into
+            // a newer directory lost its only entry with that directory.
+            // Free it, and the entries of an orphaned directory, until no
+            // orphan is left, so its data and blobs are not kept
+            loop {
+                let mut rows = conn
+                    .query(
+                        "SELECT ino FROM fs_inode WHERE ino != ?
+                            AND NOT EXISTS (SELECT 1 FROM fs_dentry d WHERE d.ino = fs_inode.ino)
+                            AND NOT EXISTS (SELECT 1 FROM fs_trash t WHERE t.ino = fs_inode.ino)",
+                        (ROOT_INO,),
+                    )
+                    .await?;
+                let mut orphans = Vec::new();
+                while let Some(row) = rows.next().await? {
+                    orphans.push(row_integer(&row, 0));
+                }
+                drop(rows);
+                if orphans.is_empty() {
+                    break;
+                }
+                for ino in orphans {
+                    delete_chunks(&conn, ino, 0).await?;
+                    for sql in [
+                        "DELETE FROM fs_dentry WHERE parent_ino = ?",
+                        "DELETE FROM fs_symlink WHERE ino = ?",
+                        "DELETE FROM fs_xattr WHERE ino = ?",
+                        "DELETE FROM fs_inode WHERE ino = ?",
+                    ] {
+                        conn.execute(sql, (ino,)).await?;
+                    }
+                }
+            }
+
+            // Recompute link counts: files count their dentries and trash
+            // entries, directories count "." and ".." plus one per
+            // subdirectory. They are counted first and set one inode at a
+            // time, since subqueries are not supported in an UPDATE
+            let mut rows = conn
+                .query(
+                    "SELECT i.ino, CASE WHEN (i.mode & ?) = ?
+                        THEN 2 + (SELECT COUNT(*) FROM fs_dentry d JOIN fs_inode c ON d.ino = c.ino
+                            WHERE d.parent_ino = i.ino AND (c.mode & ?) = ?)
+                        ELSE (SELECT COUNT(*) FROM fs_dentry d WHERE d.ino = i.ino)
+                            + (SELECT COUNT(*) FROM fs_trash t WHERE t.ino = i.ino)
+                    END
+                    FROM fs_inode i",
+                    (
+                        S_IFMT as i64,
+                        super::S_IFDIR as i64,
+                        S_IFMT as i64,
+                        super::S_IFDIR as i64,
+                    ),
+                )
+                .await?;
+            let mut nlinks = Vec::new();
+            while let Some(row) = rows.next().await? {
+                nlinks.push((row_integer(&row, 0), row_integer(&row, 1)));
+            }
+            drop(rows);
+            for (ino, nlink) in nlinks {
+                conn.execute("UPDATE fs_inode SET nlink = ? WHERE ino = ?", (nlink, ino))
+                    .await?;
+            }
+
+            // Snapshots taken after this one are dropped with their paths
+            let mut rows = conn
+                .query(
+                    "SELECT label FROM fs_snapshots WHERE rowid > ?",
+                    (snapshot_rowid,),
+                )
+                .await?;
+            let mut newer = Vec::new();
+            while let Some(row) = rows.next().await? {
+                if let Ok(Value::Text(label)) = row.get_value(0) {
+                    newer.push(label);
+                }
+            }
+            drop(rows);
+            for newer_label in newer {
+                conn.execute(
+                    "DELETE FROM fs_snapshot_paths WHERE label = ?",
+                    (newer_label.as_str(),),
+                )
+                .await?;
+            }
+            conn.execute(
+                "DELETE FROM fs_snapshots WHERE rowid > ?",
+                (snapshot_rowid,),
+            )
+            .await?;
+
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+                self.dentry_cache.clear();
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove every file, directory, snapshot and trash entry, leaving an
+    /// empty root.
+    ///
+    /// Used once the contents have been persisted elsewhere, e.g. after
+    /// committing an overlay delta into its base directory.
+    pub async fn clear(&self) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let result: Result<()> = async {
+            conn.execute("DELETE FROM fs_dentry", ()).await?;
+            conn.execute("DELETE FROM fs_blob", ()).await?;
+            for table in ["fs_data", "fs_symlink", "fs_xattr", "fs_inode"] {
+                conn.execute(&format!("DELETE FROM {table} WHERE ino != ?"), (ROOT_INO,))
+                    .await?;
+            }
+            conn.execute(
+                "UPDATE fs_inode SET nlink = 2, size = 0 WHERE ino = ?",
+                (ROOT_INO,),
+            )
+            .await?;
+            // Snapshots refer to inodes that no longer exist
+            conn.execute("DELETE FROM fs_snapshots", ()).await?;
+            conn.execute("DELETE FROM fs_snapshot_paths", ()).await?;
+            conn.execute("DELETE FROM fs_trash", ()).await?;
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+                self.dentry_cache.clear();
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// List the entries in the trash, oldest first.
+    pub async fn list_trash(&self) -> Result<Vec<TrashEntry>> {
+        let conn = self.pool.get_connection().await?;
+        let mut rows = conn
+            .query(
+                "SELECT t.orig_path, t.ino, t.deleted_at, i.mode, i.size
+                FROM fs_trash t JOIN fs_inode i ON i.ino = t.ino
+                ORDER BY t.deleted_at, t.id",
+                (),
+            )
+            .await?;
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let path = match row.get_value(0) {
+                Ok(Value::Text(path)) => path,
+                _ => return Err(Error::Internal("invalid trash path".to_string())),
+            };
+            entries.push(TrashEntry {
+                path,
+                ino: row_integer(&row, 1),
+                deleted_at: row_integer(&row, 2),
+                mode: row_integer(&row, 3) as u32,
+                size: row_integer(&row, 4),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Put the entry last removed from `path` back, along with anything
+    /// removed from below it, and return how many entries were restored.
+    ///
+    /// Fails with `NotFound` if nothing removed from `path` is in the trash
+    /// or its parent directory no longer exists, and with `AlreadyExists` if
+    /// `path` was recreated since. Entries below `path` that would replace
+    /// an existing one stay in the trash.
+    pub async fn restore_trash(&self, path: &str) -> Result<usize> {
+        let path = normalize_path(path);
+        if path == "/" {
+            return Err(FsError::RootOperation.into());
+        }
+        let prefix = format!("{path}/");
+        let conn = self.pool.get_connection().await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let result: Result<usize> = async {
+            // Parents sort before their children, newest removal first
+            let mut rows = conn
+                .query(
+                    "SELECT id, orig_path, ino FROM fs_trash
+                    WHERE orig_path = ? OR substr(orig_path, 1, ?) = ?
+                    ORDER BY length(orig_path), deleted_at DESC, id DESC",
+                    (path.as_str(), prefix.len() as i64, prefix.as_str()),
+                )
+                .await?;
+            let mut candidates = Vec::new();
+            let mut seen = HashSet::new();
+            while let Some(row) = rows.next().await? {
+                let orig_path = match row.get_value(1) {
+                    Ok(Value::Text(path)) => path,
+                    _ => continue,
+                };
+                if seen.insert(orig_path.clone()) {
+                    candidates.push((row_integer(&row, 0), orig_path, row_integer(&row, 2)));
+                }
+            }
+            drop(rows);
+            if candidates.first().is_none_or(|(_, p, _)| *p != path) {
+                return Err(FsError::NotFound.into());
+            }
+
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            let mut restored = 0;
+            for (i, (id, orig_path, ino)) in candidates.iter().enumerate() {
+                let top = i == 0;
+                let (parent_path, name) = match orig_path.rsplit_once('/') {
+                    Some(("", name)) => ("/", name),
+                    Some((parent, name)) => (parent, name),
+                    None => continue,
+                };
+                let parent_ino = self.resolve_path_with_conn(&conn, parent_path).await?;
+                let stats = self.getattr_with_conn(&conn, *ino).await?;
+                let (Some(parent_ino), Some(stats)) = (parent_ino, stats) else {
+                    if top {
+                        return Err(FsError::NotFound.into());
+                    }
+                    continue;
+                };
+                if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+                    if top {
+                        return Err(FsError::AlreadyExists.into());
+                    }
+                    continue;
+                }
+
+                let mut stmt = conn
+                    .prepare_cached(
+                        "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+                    )
+                    .await?;
+                stmt.execute((name, parent_ino, *ino, self.name_key(name)))
+                    .await?;
+                if stats.is_directory() {
+                    let mut stmt = conn
+                        .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
+                        .await?;
+                    stmt.execute((parent_ino,)).await?;
+                }
+                let mut stmt = conn
+                    .prepare_cached("DELETE FROM fs_trash WHERE id = ?")
+                    .await?;
+                stmt.execute((*id,)).await?;
+                touch_dir(&conn, parent_ino, now_secs, now_nsec).await?;
+                restored += 1;
+            }
+            Ok(restored)
+        }
+        .await;
+
+        match result {
+            Ok(restored) => {
+                txn.commit().await?;
+                self.dentry_cache.clear();
+                self.notify_path(ChangeEventKind::Create, &path);
+                Ok(restored)
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Delete everything in the trash and return how many entries it held.
+    pub async fn empty_trash(&self) -> Result<u64> {
+        let conn = self.pool.get_connection().await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+        let result = self.purge_trash(&conn, None).await;
+        match result {
+            Ok(purged) => {
+                txn.commit().await?;
+                self.dentry_cache.clear();
+                Ok(purged)
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove orphaned inodes to reclaim space.
+    ///
+    /// An inode is orphaned when no directory entry or trash entry refers to
+    /// it any more; its data, symlink and xattr rows go with it, as do rows
+    /// left behind for inodes that no longer exist. Trash entries older than
+    /// the trash retention are freed first. The cleanup runs in one
+    /// transaction. The freed pages stay in the database file for reuse, as
+    /// turso does not support `VACUUM` yet.
+    ///
+    /// Callers must make sure no other process has the database mounted.
+    pub async fn compact(&self) -> Result<CompactStats> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let bytes_before = stored_size(&conn).await?;
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+        let result: Result<(u64, u64)> = async {
+            let expired = match self.trash().and_then(|policy| policy.retention) {
+                Some(retention) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+                    let cutoff = now - retention.as_secs() as i64;
+                    self.purge_trash(&conn, Some(cutoff)).await?
+                }
+                None => 0,
+            };
+            // Rows are found first and deleted by inode, since subqueries
+            // are not supported in a DELETE's WHERE clause
+            let mut rows = conn
+                .query(
+                    "SELECT ino FROM fs_inode WHERE ino != ?
+                        AND NOT EXISTS (SELECT 1 FROM fs_dentry d WHERE d.ino = fs_inode.ino)
+                        AND NOT EXISTS (SELECT 1 FROM fs_trash t WHERE t.ino = fs_inode.ino)",
+                    (ROOT_INO,),
+                )
+                .await?;
+            let mut orphans = Vec::new();
+            while let Some(row) = rows.next().await? {
+                orphans.push(row_integer(&row, 0));
+            }
+            drop(rows);
+            for ino in &orphans {
+                conn.execute("DELETE FROM fs_inode WHERE ino = ?", (*ino,))
+                    .await?;
+            }
+            let mut rows = conn
+                .query(
+                    "SELECT hash FROM fs_data
+                    WHERE ino NOT IN (SELECT ino FROM fs_inode) AND hash IS NOT NULL",
+                    (),
+                )
+                .await?;
+            let hashes = collect_hashes(&mut rows).await?;
+            drop(rows);
+            release_blobs(&conn, hashes).await?;
+            for table in ["fs_data", "fs_symlink", "fs_xattr"] {
+                let mut rows = conn
+                    .query(
+                        &format!(
+                            "SELECT DISTINCT ino FROM {table}
+                            WHERE ino NOT IN (SELECT ino FROM fs_inode)"
+                        ),
+                        (),
+                    )
+                    .await?;
+                let mut stray = Vec::new();
+                while let Some(row) = rows.next().await? {
+                    stray.push(row_integer(&row, 0));
+                }
+                drop(rows);
+                for ino in stray {
+                    conn.execute(&format!("DELETE FROM {table} WHERE ino = ?"), (ino,))
+                        .await?;
+                }
+            }
+            Ok((orphans.len() as u64, expired))
+        }
+        .await;
+
+        let (orphaned_inodes, expired_trash) = match result {
+            Ok(counts) => {
+                txn.commit().await?;
+                self.dentry_cache.clear();
+                counts
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                return Err(e);
+            }
+        };
+
+        let bytes_after = stored_size(&conn).await?;
+
+        Ok(CompactStats {
+            orphaned_inodes,
+            expired_trash,
+            bytes_before,
+            bytes_after,
+        })
+    }
+
+    /// Look for inconsistencies between the filesystem tables.
+    ///
+    /// Checks that directory entries refer to existing inodes, that every
+    /// inode but the root is reachable from an entry or the trash, that link
+    /// counts match the entries, and that chunks and deduplicated blobs agree with each
+    /// other. Nothing is modified; pass the findings to [`AgentFS::repair`].
+    pub async fn check(&self) -> Result<Vec<Inconsistency>> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let mut found = Vec::new();
+
+        let mut rows = conn
+            .query(
+                "SELECT parent_ino, name, ino FROM fs_dentry d
+                WHERE NOT EXISTS (SELECT 1 FROM fs_inode i WHERE i.ino = d.ino)
+                    OR NOT EXISTS (SELECT 1 FROM fs_inode i WHERE i.ino = d.parent_ino)
+                ORDER BY parent_ino, name",
+                (),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            let name = match row.get_value(1) {
+                Ok(Value::Text(name)) => name,
+                _ => String::new(),
+            };
+            found.push(Inconsistency::DanglingEntry {
+                parent_ino: row_integer(&row, 0),
+                name,
+                ino: row_integer(&row, 2),
+            });
+        }
+        drop(rows);
+
+        // An entry inside a missing directory does not make its inode reachable
+        let mut orphans = std::collections::HashSet::new();
+        let mut rows = conn
+            .query(
+                "SELECT ino FROM fs_inode i WHERE ino != ? AND NOT EXISTS (
+                    SELECT 1 FROM fs_dentry d JOIN fs_inode p ON p.ino = d.parent_ino
+                    WHERE d.ino = i.ino
+                ) AND NOT EXISTS (SELECT 1 FROM fs_trash t WHERE t.ino = i.ino)
+                ORDER BY ino",
+                (ROOT_INO,),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            let ino = row_integer(&row, 0);
+            orphans.insert(ino);
+            found.push(Inconsistency::OrphanedInode { ino });
+        }
+        drop(rows);
+
+        let mut orphaned_data = std::collections::BTreeSet::new();
+        for table in ["fs_data", "fs_symlink", "fs_xattr"] {
+            let mut rows = conn
+                .query(
+                    &format!(
+                        "SELECT DISTINCT ino FROM {table}
+                        WHERE ino NOT IN (SELECT ino FROM fs_inode)"
+                    ),
+                    (),
+                )
+                .await?;
+            while let Some(row) = rows.next().await? {
+                orphaned_data.insert(row_integer(&row, 0));
+            }
+        }
+        found.extend(
+            orphaned_data
+                .into_iter()
+                .map(|ino| Inconsistency::OrphanedData { ino }),
+        );
+
+        // Files count their entries and trash entries; directories count "."
+        // and the entry in their parent, plus the ".." of each subdirectory
+        let mut rows = conn
+            .query(
+                "SELECT i.ino, i.nlink, CASE WHEN (i.mode & ?) = ?
+                    THEN 2 + (SELECT COUNT(*) FROM fs_dentry d JOIN fs_inode c ON c.ino = d.ino
+                        WHERE d.parent_ino = i.ino AND (c.mode & ?) = ?)
+                    ELSE (SELECT COUNT(*) FROM fs_dentry d JOIN fs_inode p ON p.ino = d.parent_ino
+                        WHERE d.ino = i.ino)
+                        + (SELECT COUNT(*) FROM fs_trash t WHERE t.ino = i.ino)
+                END
+                FROM fs_inode i ORDER BY i.ino",
+                (
+                    S_IFMT as i64,
+                    super::S_IFDIR as i64,
+                    S_IFMT as i64,
+                    super::S_IFDIR as i64,
+                ),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            let ino = row_integer(&row, 0);
+            let found_nlink = row_integer(&row, 1);
+            let expected = row_integer(&row, 2);
+            if found_nlink != expected && !orphans.contains(&ino) {
+                found.push(Inconsistency::WrongLinkCount {
+                    ino,
+                    found: found_nlink,
+                    expected,
+                });
+            }
+        }
+        drop(rows);
+
+        let mut rows = conn
+            .query(
+                "SELECT ino, chunk_index FROM fs_data d
+                WHERE hash IS NOT NULL
+                    AND NOT EXISTS (SELECT 1 FROM fs_blob b WHERE b.hash = d.hash)
+                ORDER BY ino, chunk_index",
+                (),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            found.push(Inconsistency::MissingBlob {
+                ino: row_integer(&row, 0),
+                chunk_index: row_integer(&row, 1),
+            });
+        }
+        drop(rows);
+
+        let mut rows = conn
+            .query(
+                "SELECT b.hash, b.refcount, (
+                    SELECT COUNT(*) FROM fs_data d
+                    WHERE d.hash = b.hash AND d.ino IN (SELECT ino FROM fs_inode)
+                ) FROM fs_blob b ORDER BY b.hash",
+                (),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            let Ok(Value::Blob(hash)) = row.get_value(0) else {
+                continue;
+            };
+            let refcount = row_integer(&row, 1);
+            let expected = row_integer(&row, 2);
+            if expected == 0 {
+                found.push(Inconsistency::OrphanedBlob { hash });
+            } else if refcount != expected {
+                found.push(Inconsistency::WrongBlobRefcount {
+                    hash,
+                    found: refcount,
+                    expected,
+                });
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Fix the repairable findings of [`AgentFS::check`] and return how many
+    /// were fixed.
+    ///
+    /// Dangling entries and orphaned inodes or data are deleted, link counts
+    /// are corrected, and blob reference counts are recomputed from the
+    /// chunks that remain. Missing blobs and whiteouts are left alone. An
+    /// orphaned directory can leave its entries dangling once it is deleted,
+    /// so another check may find more to repair.
+    pub async fn repair(&self, found: &[Inconsistency]) -> Result<usize> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let result: Result<usize> = async {
+            let mut repaired = 0;
+            for finding in found {
+                match finding {
+                    Inconsistency::DanglingEntry {
+                        parent_ino, name, ..
+                    } => {
+                        conn.execute(
+                            "DELETE FROM fs_dentry WHERE parent_ino = ? AND name = ?",
+                            (*parent_ino, name.as_str()),
+                        )
+                        .await?;
+                    }
+                    Inconsistency::OrphanedInode { ino } | Inconsistency::OrphanedData { ino } => {
+                        for table in ["fs_data", "fs_symlink", "fs_xattr", "fs_inode"] {
+                            conn.execute(&format!("DELETE FROM {table} WHERE ino = ?"), (*ino,))
+                                .await?;
+                        }
+                    }
+                    Inconsistency::WrongLinkCount { ino, expected, .. } => {
+                        conn.execute(
+                            "UPDATE fs_inode SET nlink = ? WHERE ino = ?",
+                            (*expected, *ino),
+                        )
+                        .await?;
+                    }
+                    // Fixed by the recount below
+                    Inconsistency::WrongBlobRefcount { .. }
+                    | Inconsistency::OrphanedBlob { .. } => {}
+                    Inconsistency::MissingBlob { .. } | Inconsistency::StaleWhiteout { .. } => {
+                        continue
+                    }
+                }
+                repaired += 1;
+            }
+
+            if repaired > 0 {
+                // Deleted chunks no longer hold their blobs. The references
+                // are counted first, since subqueries are not supported in
+                // an UPDATE
+                let mut rows = conn
+                    .query(
+                        "SELECT b.hash, (SELECT COUNT(*) FROM fs_data d WHERE d.hash = b.hash)
+                        FROM fs_blob b",
+                        (),
+                    )
+                    .await?;
+                let mut refcounts = Vec::new();
+                while let Some(row) = rows.next().await? {
+                    if let Ok(Value::Blob(hash)) = row.get_value(0) {
+                        refcounts.push((hash, row_integer(&row, 1)));
+                    }
+                }
+                drop(rows);
+                for (hash, refcount) in refcounts {
+                    conn.execute(
+                        "UPDATE fs_blob SET refcount = ? WHERE hash = ?",
+                        (refcount, Value::Blob(hash)),
+                    )
+                    .await?;
+                }
+                conn.execute("DELETE FROM fs_blob WHERE refcount = 0", ())
+                    .await?;
+            }
+            Ok(repaired)
+        }
+        .await;
+
+        match result {
+            Ok(repaired) => {
+                txn.commit().await?;
+                self.dentry_cache.clear();
+                Ok(repaired)
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Get the number of chunks for a given inode (for testing)
+    #[cfg(test)]
+    async fn get_chunk_count(&self, ino: i64) -> Result<i64> {
+        let conn = self.pool.get_connection().await?;
+        let mut rows = conn
+            .query("SELECT COUNT(*) FROM fs_data WHERE ino = ?", (ino,))
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0))
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+#[async_trait]
+impl FileSystem for AgentFS {
+    async fn lookup(&self, parent_ino: i64, name: &str) -> Result<Option<Stats>> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let conn = self.pool.get_connection().await?;
+
+        // Handle ".." by finding the parent of parent_ino
+        if name == ".." {
+            if parent_ino == ROOT_INO {
+                // Root's parent is itself
+                return self.getattr_with_conn(&conn, ROOT_INO).await;
+            }
+            let mut stmt = conn
+                .prepare_cached("SELECT parent_ino FROM fs_dentry WHERE ino = ? LIMIT 1")
+                .await?;
+            let mut rows = stmt.query((parent_ino,)).await?;
+            let parent = if let Some(row) = rows.next().await? {
+                row.get_value(0)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(ROOT_INO)
+            } else {
+                ROOT_INO
+            };
+            return self.getattr_with_conn(&conn, parent).await;
+        }
+
+        // Look up the child inode
+        let child_ino = match self.lookup_child(&conn, parent_ino, name).await? {
+            Some(ino) => ino,
+            None => return Ok(None),
+        };
+
+        // Get stats for the child inode
+        let mut stmt = conn
+            .prepare_cached(concat!(
+                "SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec, ",
+                blocks_column!("fs_inode"),
+                " FROM fs_inode WHERE ino = ?"
+            ))
+            .await?;
+        let mut rows = stmt.query((child_ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mut stats = Self::build_stats_from_row(&row)?;
+            self.write_buffer.apply(&mut stats);
+            // Cache the lookup result
+            self.dentry_cache.insert(parent_ino, name, child_ino);
+            Ok(Some(stats))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn getattr(&self, ino: i64) -> Result<Option<Stats>> {
+        let conn = self.pool.get_connection().await?;
+        self.getattr_with_conn(&conn, ino).await
+    }
+
+    async fn readlink(&self, ino: i64) -> Result<Option<String>> {
+        let conn = self.pool.get_connection().await?;
+
+        // Check if the inode exists and is a symlink
+        let mut stmt = conn
+            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
                 .ok()
                 .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0),
-            ctime: row
-                .get_value(8)
+                .unwrap_or(0) as u32;
+
+            if (mode & S_IFMT) != S_IFLNK {
+                return Err(FsError::NotASymlink.into());
+            }
+        } else {
+            return Ok(None);
+        }
+
+        // Read target from fs_symlink table
+        let mut stmt = conn
+            .prepare_cached("SELECT target FROM fs_symlink WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let target = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| match v {
+                    Value::Text(s) => Some(s.to_string()),
+                    _ => None,
+                })
+                .ok_or(FsError::InvalidPath)?;
+            Ok(Some(target))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn readdir(&self, ino: i64) -> Result<Option<Vec<String>>> {
+        let conn = self.pool.get_connection().await?;
+
+        // Check if inode exists and is a directory
+        let mut stmt = conn
+            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32;
+
+            if (mode & S_IFMT) != super::S_IFDIR {
+                return Err(FsError::NotADirectory.into());
+            }
+        } else {
+            return Ok(None);
+        }
+
+        let mut stmt = conn
+            .prepare_cached("SELECT name FROM fs_dentry WHERE parent_ino = ? ORDER BY name")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let name = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| {
+                    if let Value::Text(s) = v {
+                        Some(s.clone())
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default();
+            if !name.is_empty() {
+                entries.push(name);
+            }
+        }
+
+        touch_atime(&conn, ino, &self.atime_policy).await;
+        Ok(Some(entries))
+    }
+
+    async fn readdir_plus(&self, ino: i64) -> Result<Option<Vec<DirEntry>>> {
+        let conn = self.pool.get_connection().await?;
+
+        // Check if inode exists and is a directory
+        let mut stmt = conn
+            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32;
+
+            if (mode & S_IFMT) != super::S_IFDIR {
+                return Err(FsError::NotADirectory.into());
+            }
+        } else {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare_cached(concat!(
+            "SELECT d.name, i.ino, i.mode, i.nlink, i.uid, i.gid, i.size, i.atime, i.mtime, i.ctime, i.rdev, i.atime_nsec, i.mtime_nsec, i.ctime_nsec, ",
+            blocks_column!("i"),
+            "
+            FROM fs_dentry d
+            JOIN fs_inode i ON d.ino = i.ino
+            WHERE d.parent_ino = ?
+            ORDER BY d.name"
+        )).await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let name = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| {
+                    if let Value::Text(s) = v {
+                        Some(s.clone())
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default();
+
+            if name.is_empty() {
+                continue;
+            }
+
+            let entry_ino = row
+                .get_value(1)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0);
+
+            let mut stats = Stats {
+                ino: entry_ino,
+                mode: row
+                    .get_value(2)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                nlink: row
+                    .get_value(3)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(1) as u32,
+                uid: row
+                    .get_value(4)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                gid: row
+                    .get_value(5)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                size: row
+                    .get_value(6)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                atime: row
+                    .get_value(7)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                mtime: row
+                    .get_value(8)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                ctime: row
+                    .get_value(9)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0),
+                atime_nsec: row
+                    .get_value(11)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                mtime_nsec: row
+                    .get_value(12)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                ctime_nsec: row
+                    .get_value(13)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u32,
+                rdev: row
+                    .get_value(10)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u64,
+                blocks: row
+                    .get_value(14)
+                    .ok()
+                    .and_then(|v| v.as_integer().copied())
+                    .unwrap_or(0) as u64,
+            };
+
+            self.write_buffer.apply(&mut stats);
+            entries.push(DirEntry { name, stats });
+        }
+
+        touch_atime(&conn, ino, &self.atime_policy).await;
+        Ok(Some(entries))
+    }
+
+    async fn readdir_types(&self, ino: i64) -> Result<Option<Vec<(String, FileType)>>> {
+        let conn = self.pool.get_connection().await?;
+        match self.getattr_with_conn(&conn, ino).await? {
+            None => return Ok(None),
+            Some(stats) if !stats.is_directory() => {
+                return Err(FsError::NotADirectory.into());
+            }
+            Some(_) => {}
+        }
+
+        // The mode alone is enough for the type, without the rest of the stats
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT d.name, i.mode FROM fs_dentry d
+                JOIN fs_inode i ON d.ino = i.ino
+                WHERE d.parent_ino = ?
+                ORDER BY d.name",
+            )
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let name = match row.get_value(0) {
+                Ok(Value::Text(name)) => name,
+                _ => continue,
+            };
+            let mode = row
+                .get_value(1)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0) as u32;
+            entries.push((name, FileType::from_mode(mode)));
+        }
+
+        touch_atime(&conn, ino, &self.atime_policy).await;
+        Ok(Some(entries))
+    }
+
+    async fn readdir_stream(&self, ino: i64) -> Result<Option<BoxedDirStream>> {
+        let conn = self.pool.get_connection().await?;
+        match self.getattr_with_conn(&conn, ino).await? {
+            None => return Ok(None),
+            Some(stats) if !stats.is_directory() => {
+                return Err(FsError::NotADirectory.into());
+            }
+            Some(_) => {}
+        }
+        touch_atime(&conn, ino, &self.atime_policy).await;
+
+        Ok(Some(Box::new(AgentFSDirStream {
+            pool: self.pool.clone(),
+            parent_ino: ino,
+            last_name: String::new(),
+            buffer: VecDeque::new(),
+            exhausted: false,
+        }) as BoxedDirStream))
+    }
+
+    async fn read_stream(&self, ino: i64) -> Result<Option<BoxedReadStream>> {
+        let conn = self.pool.get_connection().await?;
+        match self.getattr_with_conn(&conn, ino).await? {
+            None => return Ok(None),
+            Some(stats) if stats.is_directory() => {
+                return Err(FsError::IsADirectory.into());
+            }
+            Some(_) => {}
+        }
+        touch_atime(&conn, ino, &self.atime_policy).await;
+
+        Ok(Some(Box::new(AgentFSReadStream {
+            pool: self.pool.clone(),
+            ino,
+            chunk_size: self.chunk_size as u64,
+            encoding: self.encoding.clone(),
+            write_buffer: self.write_buffer.clone(),
+            offset: 0,
+            buffer: VecDeque::new(),
+            exhausted: false,
+        }) as BoxedReadStream))
+    }
+
+    async fn chmod(&self, ino: i64, mode: u32) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+
+        // Get current mode to preserve file type bits
+        let mut stmt = conn
+            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        let current_mode = if let Some(row) = rows.next().await? {
+            row.get_value(0)
                 .ok()
                 .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0),
-            atime_nsec: row
-                .get_value(10)
+                .unwrap_or(0) as u32
+        } else {
+            return Err(FsError::NotFound.into());
+        };
+
+        // Preserve file type bits (upper bits), replace permission bits (lower 12 bits)
+        let new_mode = (current_mode & S_IFMT) | (mode & 0o7777);
+
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET mode = ?, ctime = ?, ctime_nsec = ? WHERE ino = ?")
+            .await?;
+        stmt.execute((new_mode as i64, now_secs, now_nsec, ino))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        let uid = uid.filter(|&id| id != OWNER_UNCHANGED);
+        let gid = gid.filter(|&id| id != OWNER_UNCHANGED);
+        if uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
+
+        let conn = self.pool.get_connection().await?;
+
+        // Verify inode exists
+        let mut stmt = conn
+            .prepare_cached("SELECT ino FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if rows.next().await?.is_none() {
+            return Err(FsError::NotFound.into());
+        }
+
+        // Build the update query dynamically based on which values are provided
+        let mut updates = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(uid) = uid {
+            updates.push("uid = ?");
+            values.push(Value::Integer(uid as i64));
+        }
+        if let Some(gid) = gid {
+            updates.push("gid = ?");
+            values.push(Value::Integer(gid as i64));
+        }
+
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        updates.push("ctime = ?");
+        values.push(Value::Integer(now_secs));
+        updates.push("ctime_nsec = ?");
+        values.push(Value::Integer(now_nsec));
+
+        values.push(Value::Integer(ino));
+        let sql = format!("UPDATE fs_inode SET {} WHERE ino = ?", updates.join(", "));
+        conn.execute(&sql, values).await?;
+
+        Ok(())
+    }
+
+    async fn utimens(&self, ino: i64, atime: TimeChange, mtime: TimeChange) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+
+        // Verify inode exists
+        let mut stmt = conn
+            .prepare_cached("SELECT ino FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+        if rows.next().await?.is_none() {
+            return Err(FsError::NotFound.into());
+        }
+
+        let mut updates = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        let resolve = |tc: TimeChange| -> (i64, i64) {
+            match tc {
+                TimeChange::Set(secs, nsec) => (secs, nsec as i64),
+                TimeChange::Now => {
+                    let dur = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                    (dur.as_secs() as i64, dur.subsec_nanos() as i64)
+                }
+                TimeChange::Omit => unreachable!(),
+            }
+        };
+
+        if !matches!(atime, TimeChange::Omit) {
+            let (secs, nsec) = resolve(atime);
+            updates.push("atime = ?");
+            values.push(Value::Integer(secs));
+            updates.push("atime_nsec = ?");
+            values.push(Value::Integer(nsec));
+        }
+
+        if !matches!(mtime, TimeChange::Omit) {
+            let (secs, nsec) = resolve(mtime);
+            updates.push("mtime = ?");
+            values.push(Value::Integer(secs));
+            updates.push("mtime_nsec = ?");
+            values.push(Value::Integer(nsec));
+        }
+
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        // Also update ctime
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        updates.push("ctime = ?");
+        values.push(Value::Integer(dur.as_secs() as i64));
+        updates.push("ctime_nsec = ?");
+        values.push(Value::Integer(dur.subsec_nanos() as i64));
+
+        values.push(Value::Integer(ino));
+        let sql = format!("UPDATE fs_inode SET {} WHERE ino = ?", updates.join(", "));
+        conn.execute(&sql, values).await?;
+
+        Ok(())
+    }
+
+    async fn getxattr(&self, ino: i64, name: &str) -> Result<Option<Vec<u8>>> {
+        let conn = self.pool.get_connection().await?;
+
+        let mut stmt = conn
+            .prepare_cached("SELECT value FROM fs_xattr WHERE ino = ? AND name = ?")
+            .await?;
+        let mut rows = stmt.query((ino, name)).await?;
+
+        match rows.next().await? {
+            Some(row) => match row.get_value(0) {
+                Ok(Value::Blob(value)) => Ok(Some(value)),
+                _ => Ok(Some(Vec::new())),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn setxattr(&self, ino: i64, name: &str, value: &[u8], flags: i32) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+
+        // Verify inode exists
+        let mut stmt = conn
+            .prepare_cached("SELECT ino FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+        if rows.next().await?.is_none() {
+            return Err(FsError::NotFound.into());
+        }
+
+        if flags & (XATTR_CREATE | XATTR_REPLACE) != 0 {
+            let mut stmt = conn
+                .prepare_cached("SELECT 1 FROM fs_xattr WHERE ino = ? AND name = ?")
+                .await?;
+            let mut rows = stmt.query((ino, name)).await?;
+            let exists = rows.next().await?.is_some();
+            if exists && flags & XATTR_CREATE != 0 {
+                return Err(FsError::AlreadyExists.into());
+            }
+            if !exists && flags & XATTR_REPLACE != 0 {
+                return Err(FsError::NoAttribute.into());
+            }
+        }
+
+        let mut stmt = conn
+            .prepare_cached("INSERT OR REPLACE INTO fs_xattr (ino, name, value) VALUES (?, ?, ?)")
+            .await?;
+        stmt.execute((ino, name, Value::Blob(value.to_vec())))
+            .await?;
+
+        // Attribute changes update ctime
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
+            .await?;
+        stmt.execute((dur.as_secs() as i64, dur.subsec_nanos() as i64, ino))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn listxattr(&self, ino: i64) -> Result<Vec<String>> {
+        let conn = self.pool.get_connection().await?;
+
+        let mut stmt = conn
+            .prepare_cached("SELECT name FROM fs_xattr WHERE ino = ? ORDER BY name")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        let mut names = Vec::new();
+        while let Some(row) = rows.next().await? {
+            if let Ok(Value::Text(name)) = row.get_value(0) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    async fn removexattr(&self, ino: i64, name: &str) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+
+        let mut stmt = conn
+            .prepare_cached("DELETE FROM fs_xattr WHERE ino = ? AND name = ?")
+            .await?;
+        let removed = stmt.execute((ino, name)).await?;
+        if removed == 0 {
+            return Err(FsError::NoAttribute.into());
+        }
+
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
+            .await?;
+        stmt.execute((dur.as_secs() as i64, dur.subsec_nanos() as i64, ino))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn open(&self, ino: i64, flags: i32) -> Result<BoxedFile> {
+        let conn = self.pool.get_connection().await?;
+
+        // Verify inode exists
+        let mut stmt = conn
+            .prepare_cached("SELECT ino FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if rows.next().await?.is_none() {
+            return Err(FsError::NotFound.into());
+        }
+
+        Ok(Arc::new(self.file_handle(ino, flags & libc::O_APPEND != 0)))
+    }
+
+    async fn mkdir(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let mode = self.apply_umask(mode);
+        let conn = self.pool.get_connection().await?;
+
+        // Check if already exists
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        let stats = self
+            .insert_dir(&conn, parent_ino, name, mode, uid, gid)
+            .await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(parent_ino, name, stats.ino);
+        self.notify_entry(&conn, ChangeEventKind::Mkdir, parent_ino, name)
+            .await;
+
+        Ok(stats)
+    }
+
+    async fn mkdir_all(&self, path: &str, mode: u32, uid: u32, gid: u32) -> Result<Stats> {
+        let components = self.split_path(path);
+        if components.iter().any(|c| c.len() > MAX_NAME_LEN) {
+            return Err(FsError::NameTooLong.into());
+        }
+        let mode = self.apply_umask(mode);
+        let conn = self.pool.get_connection().await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        // Created (parent_ino, name, ino) entries, published to the dentry
+        // cache and subscribers only once the whole chain is committed
+        let mut created = Vec::new();
+        let result: Result<Stats> = async {
+            let mut stats = self
+                .getattr_with_conn(&conn, ROOT_INO)
+                .await?
+                .ok_or(FsError::NotFound)?;
+            for name in &components {
+                let parent_ino = stats.ino;
+                stats = match self.lookup_child(&conn, parent_ino, name).await? {
+                    Some(ino) => {
+                        let existing = self
+                            .getattr_with_conn(&conn, ino)
+                            .await?
+                            .ok_or(FsError::NotFound)?;
+                        if !existing.is_directory() {
+                            return Err(FsError::NotADirectory.into());
+                        }
+                        existing
+                    }
+                    None => {
+                        let new = self
+                            .insert_dir(&conn, parent_ino, name, mode, uid, gid)
+                            .await?;
+                        created.push((parent_ino, name.as_str(), new.ino));
+                        new
+                    }
+                };
+            }
+            Ok(stats)
+        }
+        .await;
+
+        let stats = match result {
+            Ok(stats) => stats,
+            Err(e) => {
+                let _ = txn.rollback().await;
+                return Err(e);
+            }
+        };
+        txn.commit().await?;
+
+        for (parent_ino, name, ino) in created {
+            self.dentry_cache.insert(parent_ino, name, ino);
+            self.notify_entry(&conn, ChangeEventKind::Mkdir, parent_ino, name)
+                .await;
+        }
+        Ok(stats)
+    }
+
+    async fn create_file(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<(Stats, BoxedFile)> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let mode = self.apply_umask(mode);
+        let conn = self.pool.get_connection().await?;
+
+        // Check if already exists
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Prepare statements before starting the transaction
+        let mut inode_stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                 VALUES (?, 1, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let mut dentry_stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            )
+            .await?;
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let file_mode = S_IFREG | (mode & 0o7777);
+
+        let row = inode_stmt
+            .query_row((
+                file_mode as i64,
+                uid,
+                gid,
+                now_secs,
+                now_secs,
+                now_secs,
+                now_nsec,
+                now_nsec,
+                now_nsec,
+            ))
+            .await?;
+
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+        dentry_stmt
+            .execute((name, parent_ino, ino, self.name_key(name)))
+            .await?;
+
+        // Update parent directory ctime and mtime
+        conn.execute(
+            "UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
+            (now_secs, now_secs, now_nsec, now_nsec, parent_ino),
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        self.dentry_cache.insert(parent_ino, name, ino);
+        self.notify_entry(&conn, ChangeEventKind::Create, parent_ino, name)
+            .await;
+
+        let stats = Stats {
+            ino,
+            mode: file_mode,
+            nlink: 1,
+            uid,
+            gid,
+            size: 0,
+            atime: now_secs,
+            mtime: now_secs,
+            ctime: now_secs,
+            atime_nsec: now_nsec as u32,
+            mtime_nsec: now_nsec as u32,
+            ctime_nsec: now_nsec as u32,
+            rdev: 0,
+            blocks: 0,
+        };
+
+        let file: BoxedFile = Arc::new(self.file_handle(ino, false));
+
+        Ok((stats, file))
+    }
+
+    async fn mknod(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        rdev: u64,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let mode = self.apply_umask(mode);
+        let conn = self.pool.get_connection().await?;
+
+        // Check if already exists
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Create inode with mode and rdev
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec)
+                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let row = stmt
+            .query_row((
+                mode as i64,
+                uid,
+                gid,
+                now_secs,
+                now_secs,
+                now_secs,
+                rdev as i64,
+                now_nsec,
+                now_nsec,
+                now_nsec,
+            ))
+            .await?;
+
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+        // Create directory entry
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            )
+            .await?;
+        stmt.execute((name, parent_ino, ino, self.name_key(name)))
+            .await?;
+
+        // Increment link count
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
+            .await?;
+        stmt.execute((ino,)).await?;
+
+        // Update parent directory ctime and mtime
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?")
+            .await?;
+        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+            .await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(parent_ino, name, ino);
+        self.notify_entry(&conn, ChangeEventKind::Create, parent_ino, name)
+            .await;
+
+        Ok(Stats {
+            ino,
+            mode,
+            nlink: 1,
+            uid,
+            gid,
+            size: 0,
+            atime: now_secs,
+            mtime: now_secs,
+            ctime: now_secs,
+            atime_nsec: now_nsec as u32,
+            mtime_nsec: now_nsec as u32,
+            ctime_nsec: now_nsec as u32,
+            rdev,
+            blocks: 0,
+        })
+    }
+
+    async fn symlink(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        target: &str,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let conn = self.pool.get_connection().await?;
+
+        // Check if entry already exists
+        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Create inode for symlink
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mode = S_IFLNK | 0o777; // Symlinks typically have 777 permissions
+        let size = target.len() as i64;
+
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
+            )
+            .await?;
+        let row = stmt
+            .query_row((
+                mode, uid, gid, size, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec,
+            ))
+            .await?;
+
+        let ino = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+
+        // Store symlink target
+        conn.execute(
+            "INSERT INTO fs_symlink (ino, target) VALUES (?, ?)",
+            (ino, target),
+        )
+        .await?;
+
+        // Create directory entry
+        conn.execute(
+            "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            (name, parent_ino, ino, self.name_key(name)),
+        )
+        .await?;
+
+        // Increment link count
+        conn.execute(
+            "UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?",
+            (ino,),
+        )
+        .await?;
+
+        // Update parent directory ctime and mtime
+        conn.execute(
+            "UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
+            (now_secs, now_secs, now_nsec, now_nsec, parent_ino),
+        )
+        .await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(parent_ino, name, ino);
+        self.notify_entry(&conn, ChangeEventKind::Create, parent_ino, name)
+            .await;
+
+        Ok(Stats {
+            ino,
+            mode,
+            nlink: 1,
+            uid,
+            gid,
+            size,
+            atime: now_secs,
+            mtime: now_secs,
+            ctime: now_secs,
+            atime_nsec: now_nsec as u32,
+            mtime_nsec: now_nsec as u32,
+            ctime_nsec: now_nsec as u32,
+            rdev: 0,
+            blocks: 0,
+        })
+    }
+
+    async fn unlink(&self, parent_ino: i64, name: &str) -> Result<()> {
+        self.flush_writes().await?;
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let conn = self.pool.get_connection().await?;
+
+        // Look up the child inode
+        let ino = self
+            .lookup_child(&conn, parent_ino, name)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Check if it's a directory (use rmdir for directories)
+        let mut stmt = conn
+            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
                 .ok()
                 .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32,
-            mtime_nsec: row
-                .get_value(11)
+                .unwrap_or(0) as u32;
+
+            if (mode & S_IFMT) == super::S_IFDIR {
+                return Err(FsError::IsADirectory.into());
+            }
+        }
+
+        if self
+            .trash_entry(&conn, parent_ino, name, ino, false)
+            .await?
+        {
+            self.notify_entry(&conn, ChangeEventKind::Remove, parent_ino, name)
+                .await;
+            return Ok(());
+        }
+
+        // Delete the directory entry
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "DELETE FROM fs_dentry WHERE {}",
+                self.dentry_match()
+            ))
+            .await?;
+        stmt.execute((parent_ino, self.dentry_key(name))).await?;
+
+        // Invalidate cache
+        self.dentry_cache.remove(parent_ino, name);
+
+        // Update parent directory mtime and ctime
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+            .await?;
+        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+            .await?;
+
+        // Decrement link count and update ctime
+        let mut stmt = conn
+            .prepare_cached(
+                "UPDATE fs_inode SET nlink = nlink - 1, ctime = ?, ctime_nsec = ? WHERE ino = ?",
+            )
+            .await?;
+        stmt.execute((now_secs, now_nsec, ino)).await?;
+
+        // Check if this was the last link to the inode
+        let link_count = self.get_link_count(&conn, ino).await?;
+        if link_count == 0 {
+            // Delete data blocks
+            delete_chunks(&conn, ino, 0).await?;
+
+            // Delete symlink if exists
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+
+            // Delete extended attributes
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_xattr WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+
+            // Delete inode
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+            self.locks.clear(ino);
+        }
+
+        self.notify_entry(&conn, ChangeEventKind::Remove, parent_ino, name)
+            .await;
+        Ok(())
+    }
+
+    async fn rmdir(&self, parent_ino: i64, name: &str) -> Result<()> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let conn = self.pool.get_connection().await?;
+
+        // Look up the child inode
+        let ino = self
+            .lookup_child(&conn, parent_ino, name)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        if ino == ROOT_INO {
+            return Err(FsError::RootOperation.into());
+        }
+
+        // Check if it's a directory
+        let mut stmt = conn
+            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
                 .ok()
                 .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32,
-            ctime_nsec: row
-                .get_value(12)
+                .unwrap_or(0) as u32;
+
+            if (mode & S_IFMT) != super::S_IFDIR {
+                return Err(FsError::NotADirectory.into());
+            }
+        } else {
+            return Err(FsError::NotFound.into());
+        }
+
+        // Check if directory is empty
+        let mut stmt = conn
+            .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let count = row
+                .get_value(0)
                 .ok()
                 .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32,
-            rdev: row
-                .get_value(9)
+                .unwrap_or(0);
+            if count > 0 {
+                return Err(FsError::NotEmpty.into());
+            }
+        }
+
+        if self.trash_entry(&conn, parent_ino, name, ino, true).await? {
+            self.notify_entry(&conn, ChangeEventKind::Remove, parent_ino, name)
+                .await;
+            return Ok(());
+        }
+
+        // Delete the directory entry
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "DELETE FROM fs_dentry WHERE {}",
+                self.dentry_match()
+            ))
+            .await?;
+        stmt.execute((parent_ino, self.dentry_key(name))).await?;
+
+        // Invalidate cache
+        self.dentry_cache.remove(parent_ino, name);
+
+        // Decrement link count on removed directory
+        let mut stmt = conn
+            .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+            .await?;
+        stmt.execute((ino,)).await?;
+
+        // Decrement parent nlink (removed directory's ".." link) and update timestamps
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        let mut stmt = conn
+            .prepare_cached(
+                "UPDATE fs_inode SET nlink = nlink - 1, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
+            )
+            .await?;
+        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+            .await?;
+
+        // Delete inode if no more links
+        let link_count = self.get_link_count(&conn, ino).await?;
+        if link_count == 0 {
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_xattr WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
+                .await?;
+            stmt.execute((ino,)).await?;
+            self.locks.clear(ino);
+        }
+
+        self.notify_entry(&conn, ChangeEventKind::Remove, parent_ino, name)
+            .await;
+        Ok(())
+    }
+
+    async fn remove_all(&self, path: &str) -> Result<()> {
+        self.flush_writes().await?;
+        let path = normalize_path(path);
+        let components = self.split_path(&path);
+        let Some((name, ancestors)) = components.split_last() else {
+            return Err(FsError::RootOperation.into());
+        };
+        let conn = self.pool.get_connection().await?;
+        let parent_path = format!("/{}", ancestors.join("/"));
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &parent_path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        let ino = self
+            .lookup_child(&conn, parent_ino, name)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        let stats = self
+            .getattr_with_conn(&conn, ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // The subtree stays intact below the trashed entry
+        if self
+            .trash_entry(&conn, parent_ino, name, ino, stats.is_directory())
+            .await?
+        {
+            self.notify_path(ChangeEventKind::Remove, &path);
+            return Ok(());
+        }
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        let result: Result<()> = async {
+            let mut stmt = conn
+                .prepare_cached(&format!(
+                    "DELETE FROM fs_dentry WHERE {}",
+                    self.dentry_match()
+                ))
+                .await?;
+            stmt.execute((parent_ino, self.dentry_key(name))).await?;
+            self.release_tree(&conn, ino, stats.is_directory()).await?;
+
+            // Update the parent, dropping the removed directory's ".." link
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            let nlink_delta = if stats.is_directory() { 1 } else { 0 };
+            let mut stmt = conn
+                .prepare_cached(
+                    "UPDATE fs_inode SET nlink = nlink - ?, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
+                )
+                .await?;
+            stmt.execute((nlink_delta, now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+                .await?;
+
+            Ok(())
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = txn.rollback().await;
+            return result;
+        }
+        txn.commit().await?;
+
+        // Entries anywhere below the removed directory may be cached
+        self.dentry_cache.clear();
+        self.notify_path(ChangeEventKind::Remove, &path);
+        Ok(())
+    }
+
+    async fn link(&self, ino: i64, newparent_ino: i64, newname: &str) -> Result<Stats> {
+        if newname.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let conn = self.pool.get_connection().await?;
+
+        // Check if source inode exists and is not a directory
+        let mut stmt = conn
+            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
+            .await?;
+        let mut rows = stmt.query((ino,)).await?;
+
+        if let Some(row) = rows.next().await? {
+            let mode = row
+                .get_value(0)
                 .ok()
                 .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64,
-        })
+                .unwrap_or(0) as u32;
+
+            if (mode & S_IFMT) == super::S_IFDIR {
+                return Err(FsError::IsADirectory.into());
+            }
+        } else {
+            return Err(FsError::NotFound.into());
+        }
+
+        // Check if destination already exists
+        if self
+            .lookup_child(&conn, newparent_ino, newname)
+            .await?
+            .is_some()
+        {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // Create directory entry pointing to the same inode
+        conn.execute(
+            "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            (newname, newparent_ino, ino, self.name_key(newname)),
+        )
+        .await?;
+
+        // Increment link count and update ctime
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let now_secs = dur.as_secs() as i64;
+        let now_nsec = dur.subsec_nanos() as i64;
+        conn.execute(
+            "UPDATE fs_inode SET nlink = nlink + 1, ctime = ?, ctime_nsec = ? WHERE ino = ?",
+            (now_secs, now_nsec, ino),
+        )
+        .await?;
+
+        // Update parent directory ctime and mtime
+        conn.execute(
+            "UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
+            (now_secs, now_secs, now_nsec, now_nsec, newparent_ino),
+        )
+        .await?;
+
+        // Populate dentry cache
+        self.dentry_cache.insert(newparent_ino, newname, ino);
+
+        self.notify_entry(&conn, ChangeEventKind::Create, newparent_ino, newname)
+            .await;
+
+        // Return updated stats
+        self.getattr_with_conn(&conn, ino)
+            .await?
+            .ok_or(FsError::NotFound.into())
     }
 
-    /// Resolve a path to an inode number
-    async fn resolve_path(&self, path: &str) -> Result<Option<i64>> {
+    async fn rename(
+        &self,
+        oldparent_ino: i64,
+        oldname: &str,
+        newparent_ino: i64,
+        newname: &str,
+        flags: u32,
+    ) -> Result<()> {
+        self.flush_writes().await?;
+        if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0
+            || flags == RENAME_NOREPLACE | RENAME_EXCHANGE
+        {
+            return Err(FsError::InvalidPath.into());
+        }
+        if newname.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
         let conn = self.pool.get_connection().await?;
-        self.resolve_path_with_conn(&conn, path).await
+
+        if flags & RENAME_EXCHANGE != 0 {
+            self.rename_exchange(&conn, oldparent_ino, oldname, newparent_ino, newname)
+                .await?;
+            self.notify_entry(&conn, ChangeEventKind::Rename, oldparent_ino, oldname)
+                .await;
+            self.notify_entry(&conn, ChangeEventKind::Rename, newparent_ino, newname)
+                .await;
+            return Ok(());
+        }
+
+        // Get source inode
+        let src_ino = self
+            .lookup_child(&conn, oldparent_ino, oldname)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        if src_ino == ROOT_INO {
+            return Err(FsError::RootOperation.into());
+        }
+
+        // Get source stats to check if it's a directory
+        let src_stats = self
+            .getattr_with_conn(&conn, src_ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        // Prevent moving a directory into its own subtree (would create a cycle)
+        if src_stats.is_directory() && self.is_within(&conn, newparent_ino, src_ino).await? {
+            return Err(FsError::InvalidRename.into());
+        }
+
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
+
+        // Renaming an entry onto itself only changes the case of its name
+        let same_entry =
+            oldparent_ino == newparent_ino && self.dentry_key(oldname) == self.dentry_key(newname);
+
+        let result: Result<()> = async {
+            // Check if destination exists
+            let dst_ino = if same_entry {
+                None
+            } else {
+                self.lookup_child(&conn, newparent_ino, newname).await?
+            };
+            if let Some(dst_ino) = dst_ino {
+                if flags & RENAME_NOREPLACE != 0 {
+                    return Err(FsError::AlreadyExists.into());
+                }
+                let dst_stats = self.getattr_with_conn(&conn, dst_ino).await?.ok_or(FsError::NotFound)?;
+
+                // Can't replace directory with non-directory
+                if dst_stats.is_directory() && !src_stats.is_directory() {
+                    return Err(FsError::IsADirectory.into());
+                }
+
+                // Can't replace non-directory with directory
+                if !dst_stats.is_directory() && src_stats.is_directory() {
+                    return Err(FsError::NotADirectory.into());
+                }
+
+                // If destination is directory, it must be empty
+                if dst_stats.is_directory() {
+                    let mut stmt = conn
+                        .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
+                        .await?;
+                    let mut rows = stmt.query((dst_ino,)).await?;
+
+                    if let Some(row) = rows.next().await? {
+                        let count = row
+                            .get_value(0)
+                            .ok()
+                            .and_then(|v| v.as_integer().copied())
+                            .unwrap_or(0);
+                        if count > 0 {
+                            return Err(FsError::NotEmpty.into());
+                        }
+                    }
+                }
+
+                // Remove destination entry
+                let mut stmt = conn
+                    .prepare_cached(&format!(
+                        "DELETE FROM fs_dentry WHERE {}",
+                        self.dentry_match()
+                    ))
+                    .await?;
+                stmt.execute((newparent_ino, self.dentry_key(newname))).await?;
+
+                // Decrement link count and update ctime on destination inode
+                let dur_dec = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let now_dec = dur_dec.as_secs() as i64;
+                let now_dec_nsec = dur_dec.subsec_nanos() as i64;
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1, ctime = ?, ctime_nsec = ? WHERE ino = ?")
+                    .await?;
+                stmt.execute((now_dec, now_dec_nsec, dst_ino)).await?;
+
+                // Clean up destination inode if no more links
+                let link_count = self.get_link_count(&conn, dst_ino).await?;
+                if link_count == 0 {
+                    delete_chunks(&conn, dst_ino, 0).await?;
+                    let mut stmt = conn
+                        .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
+                        .await?;
+                    stmt.execute((dst_ino,)).await?;
+                    let mut stmt = conn
+                        .prepare_cached("DELETE FROM fs_xattr WHERE ino = ?")
+                        .await?;
+                    stmt.execute((dst_ino,)).await?;
+                    let mut stmt = conn
+                        .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
+                        .await?;
+                    stmt.execute((dst_ino,)).await?;
+                    self.locks.clear(dst_ino);
+                }
+            }
+
+            // Update the dentry: change parent and/or name
+            let mut stmt = conn
+                .prepare_cached(&format!(
+                    "UPDATE fs_dentry SET parent_ino = ?, name = ?, name_key = ? WHERE {}",
+                    self.dentry_match()
+                ))
+                .await?;
+            stmt.execute((
+                newparent_ino,
+                newname,
+                self.name_key(newname),
+                oldparent_ino,
+                self.dentry_key(oldname),
+            ))
+            .await?;
+
+            // If renaming a directory across parents, adjust parent nlink counts
+            // (the ".." link moves from old parent to new parent)
+            if src_stats.is_directory() && oldparent_ino != newparent_ino {
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
+                    .await?;
+                stmt.execute((oldparent_ino,)).await?;
+
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
+                    .await?;
+                stmt.execute((newparent_ino,)).await?;
+            }
+
+            // Update ctime of the inode
+            let dur = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_nsec, src_ino)).await?;
+
+            // Update source parent directory timestamps
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+                .await?;
+            stmt.execute((now_secs, now_secs, now_nsec, now_nsec, oldparent_ino)).await?;
+
+            // Update destination parent directory timestamps
+            if newparent_ino != oldparent_ino {
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+                    .await?;
+                stmt.execute((now_secs, now_secs, now_nsec, now_nsec, newparent_ino)).await?;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                txn.commit().await?;
+
+                // Invalidate cache for source and destination
+                self.dentry_cache.remove(oldparent_ino, oldname);
+                self.dentry_cache.remove(newparent_ino, newname);
+
+                // Add new entry to cache (source inode is now at destination)
+                self.dentry_cache.insert(newparent_ino, newname, src_ino);
+
+                self.notify_entry(&conn, ChangeEventKind::Rename, oldparent_ino, oldname)
+                    .await;
+                self.notify_entry(&conn, ChangeEventKind::Rename, newparent_ino, newname)
+                    .await;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
     }
 
-    /// Resolve a path to an inode number using a provided connection
-    async fn resolve_path_with_conn(&self, conn: &Connection, path: &str) -> Result<Option<i64>> {
-        let components = self.split_path(path);
-        if components.is_empty() {
-            return Ok(Some(ROOT_INO));
+    async fn fallocate(&self, ino: i64, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.flush_writes().await?;
+        if mode & !FALLOC_FL_KEEP_SIZE != 0 {
+            return Err(FsError::NotSupported.into());
+        }
+        if len == 0 {
+            return Err(FsError::InvalidPath.into());
+        }
+        let end = checked_file_end(offset, len)?;
+
+        let conn = self.pool.get_connection().await?;
+        let stats = self
+            .getattr_with_conn(&conn, ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        if stats.is_directory() {
+            return Err(FsError::IsADirectory.into());
         }
 
-        let mut statement: Option<turso::Statement> = None;
-        let mut current_ino = ROOT_INO;
-        for component in components {
-            // Check cache first
-            if let Some(cached_ino) = self.dentry_cache.get(current_ino, &component) {
-                current_ino = cached_ino;
-                continue;
-            }
+        let chunk_size = self.chunk_size as u64;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
 
-            // Cache miss - query database
-            if let Some(statement) = &mut statement {
-                statement.reset()?;
-            } else {
-                statement = Some(
-                    conn.prepare_cached(
-                        "SELECT ino FROM fs_dentry WHERE parent_ino = ? AND name = ?",
-                    )
-                    .await?,
-                );
+        let grows = mode & FALLOC_FL_KEEP_SIZE == 0 && end > stats.size as u64;
+        let result: Result<()> = async {
+            // The quota counts file sizes, so only growing the file is
+            // charged, and nothing is reserved for the allocated range
+            if grows {
+                check_quota(&conn, &self.max_bytes, end - stats.size as u64).await?;
             }
-            let statement = statement.as_mut().expect("statement was set above");
-            let mut rows = statement.query((current_ino, component.as_str())).await?;
 
-            let mut found_row = None;
-            let mut row_count = 0;
+            // Materialize every chunk in the range, zero-padding short ones
+            for chunk_index in offset / chunk_size..=(end - 1) / chunk_size {
+                let wanted = chunk_size.min(end - chunk_index * chunk_size) as usize;
 
-            while let Some(row) = rows.next().await? {
-                found_row = Some(row);
-                row_count += 1;
+                let mut chunk_data = read_chunk(&conn, &self.encoding, ino, chunk_index as i64)
+                    .await?
+                    .unwrap_or_default();
+                if chunk_data.len() >= wanted {
+                    continue;
+                }
+                chunk_data.resize(wanted, 0);
+                store_chunk(&conn, &self.encoding, ino, chunk_index as i64, &chunk_data).await?;
             }
 
-            if row_count > 1 {
-                return Err(FsError::InvalidPath.into());
+            if grows {
+                let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                let now_secs = dur.as_secs() as i64;
+                let now_nsec = dur.subsec_nanos() as i64;
+                let mut stmt = conn
+                    .prepare_cached("UPDATE fs_inode SET size = ?, mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
+                    .await?;
+                stmt.execute((end as i64, now_secs, now_secs, now_nsec, now_nsec, ino))
+                    .await?;
             }
 
-            if let Some(row) = found_row {
-                let child_ino = row
-                    .get_value(0)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0);
+            Ok(())
+        }
+        .await;
 
-                // Populate cache
-                self.dentry_cache.insert(current_ino, &component, child_ino);
-                current_ino = child_ino;
-            } else {
-                return Ok(None);
-            }
+        if result.is_err() {
+            let _ = txn.rollback().await;
+            return result;
         }
+        txn.commit().await?;
+        notify_ino(&self.events, &conn, ChangeEventKind::Write, ino).await;
+        Ok(())
+    }
 
-        Ok(Some(current_ino))
+    async fn append(&self, path: &str, data: &[u8]) -> Result<u64> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        let ino = self
+            .resolve_path_follow_with_conn(&conn, path)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        let stats = self
+            .getattr_with_conn(&conn, ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        if stats.is_directory() {
+            return Err(FsError::IsADirectory.into());
+        }
+        drop(conn);
+
+        let file = self.file_handle(ino, true);
+        file.write_at(None, data).await
     }
 
-    /// Get file statistics without following symlinks
-    pub async fn lstat(&self, path: &str) -> Result<Option<Stats>> {
+    /// Store `data` in a new inode and point the directory entry at it in
+    /// the same transaction as the old file is released, so the new
+    /// contents are never visible under `path` until they are complete.
+    async fn atomic_write(&self, path: &str, data: &[u8]) -> Result<()> {
+        self.flush_writes().await?;
+        let size = checked_file_end(0, data.len() as u64)?;
         let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-        let ino = match self.resolve_path_with_conn(&conn, &path).await? {
-            Some(ino) => ino,
-            None => return Ok(None),
+        let path = normalize_path(path);
+        let components = self.split_path(&path);
+        let Some((name, ancestors)) = components.split_last() else {
+            return Err(FsError::IsADirectory.into());
         };
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &format!("/{}", ancestors.join("/")))
+            .await?
+            .ok_or(FsError::NotFound)?;
 
-        let mut stmt = conn
-            .prepare_cached("SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
 
-        if let Some(row) = rows.next().await? {
-            let stats = Self::build_stats_from_row(&row)?;
-            Ok(Some(stats))
-        } else {
-            Ok(None)
+        let result: Result<(i64, bool)> = async {
+            let parent = self
+                .getattr_with_conn(&conn, parent_ino)
+                .await?
+                .ok_or(FsError::NotFound)?;
+            if !parent.is_directory() {
+                return Err(FsError::NotADirectory.into());
+            }
+            let old = match self.lookup_child(&conn, parent_ino, name).await? {
+                Some(ino) => self.getattr_with_conn(&conn, ino).await?,
+                None => None,
+            };
+            if old.as_ref().is_some_and(|s| s.is_directory()) {
+                return Err(FsError::IsADirectory.into());
+            }
+            let (mode, uid, gid) = match old.as_ref().filter(|s| s.is_file()) {
+                Some(stats) => (stats.mode & 0o7777, stats.uid, stats.gid),
+                None => (self.apply_umask(DEFAULT_FILE_MODE) & 0o7777, 0, 0),
+            };
+            // The old contents are freed unless another link keeps them
+            let freed = match &old {
+                Some(stats) if stats.nlink == 1 => stats.size as u64,
+                _ => 0,
+            };
+            check_quota(&conn, &self.max_bytes, size.saturating_sub(freed)).await?;
+
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            let row = conn
+                .prepare_cached(
+                    "INSERT INTO fs_inode (mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                     VALUES (?, 1, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
+                )
+                .await?
+                .query_row((
+                    (S_IFREG | mode) as i64,
+                    uid,
+                    gid,
+                    size as i64,
+                    now_secs,
+                    now_secs,
+                    now_secs,
+                    now_nsec,
+                    now_nsec,
+                    now_nsec,
+                ))
+                .await?;
+            let ino = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+            for (index, chunk) in data.chunks(self.chunk_size).enumerate() {
+                store_chunk(&conn, &self.encoding, ino, index as i64, chunk).await?;
+            }
+
+            match &old {
+                Some(old) => {
+                    conn.prepare_cached(&format!(
+                        "UPDATE fs_dentry SET ino = ? WHERE {}",
+                        self.dentry_match()
+                    ))
+                    .await?
+                    .execute((ino, parent_ino, self.dentry_key(name)))
+                    .await?;
+                    conn.prepare_cached(
+                        "UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?",
+                    )
+                    .await?
+                    .execute((now_secs, now_nsec, old.ino))
+                    .await?;
+                    self.release_tree(&conn, old.ino, false).await?;
+                }
+                None => {
+                    conn.prepare_cached(
+                        "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+                    )
+                    .await?
+                    .execute((name.as_str(), parent_ino, ino, self.name_key(name)))
+                    .await?;
+                }
+            }
+            touch_dir(&conn, parent_ino, now_secs, now_nsec).await?;
+            Ok((ino, old.is_some()))
+        }
+        .await;
+
+        match result {
+            Ok((ino, replaced)) => {
+                txn.commit().await?;
+                self.dentry_cache.remove(parent_ino, name);
+                self.dentry_cache.insert(parent_ino, name, ino);
+                let kind = if replaced {
+                    ChangeEventKind::Write
+                } else {
+                    ChangeEventKind::Create
+                };
+                self.notify_path(kind, &path);
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
         }
     }
 
-    /// Get file statistics, following symlinks
-    pub async fn stat(&self, path: &str) -> Result<Option<Stats>> {
+    /// Move every chunk of `src` into `fs_blob` and give the clone a
+    /// reference to each, in one transaction. Writing either file replaces
+    /// the references of the chunks written, as it does for deduplicated
+    /// chunks, so only those diverge.
+    async fn reflink(&self, src: &str, dst: &str) -> Result<()> {
+        self.flush_writes().await?;
         let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-
-        // Follow symlinks with a maximum depth to prevent infinite loops
-        let mut current_path = path;
-        let max_symlink_depth = 40; // Standard limit for symlink following
-
-        let mut stmt = conn.prepare_cached(
-            "SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec FROM fs_inode WHERE ino = ?",
-        ).await?;
-        for _ in 0..max_symlink_depth {
-            let ino = match self.resolve_path_with_conn(&conn, &current_path).await? {
-                Some(ino) => ino,
-                None => return Ok(None),
-            };
-
-            stmt.reset()?;
-            let mut rows = stmt.query((ino,)).await?;
+        let dst = normalize_path(dst);
+        let components = self.split_path(&dst);
+        let Some((name, ancestors)) = components.split_last() else {
+            return Err(FsError::AlreadyExists.into());
+        };
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let src_ino = self
+            .resolve_path_follow_with_conn(&conn, &normalize_path(src))
+            .await?
+            .ok_or(FsError::NotFound)?;
+        let parent_ino = self
+            .resolve_path_with_conn(&conn, &format!("/{}", ancestors.join("/")))
+            .await?
+            .ok_or(FsError::NotFound)?;
 
-            if let Some(row) = rows.next().await? {
-                let mode = row
-                    .get_value(1)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32;
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
 
-                // Check if this is a symlink
-                if (mode & S_IFMT) == S_IFLNK {
-                    // Read the symlink target
-                    let target = self
-                        .readlink_with_conn(&conn, &current_path)
-                        .await?
-                        .ok_or(FsError::NotFound)?;
+        let result: Result<i64> = async {
+            let src = self
+                .getattr_with_conn(&conn, src_ino)
+                .await?
+                .ok_or(FsError::NotFound)?;
+            if src.is_directory() {
+                return Err(FsError::IsADirectory.into());
+            }
+            if !src.is_file() {
+                return Err(FsError::InvalidPath.into());
+            }
+            let parent = self
+                .getattr_with_conn(&conn, parent_ino)
+                .await?
+                .ok_or(FsError::NotFound)?;
+            if !parent.is_directory() {
+                return Err(FsError::NotADirectory.into());
+            }
+            if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
+                return Err(FsError::AlreadyExists.into());
+            }
+            check_quota(&conn, &self.max_bytes, src.size as u64).await?;
 
-                    // Resolve target path (handle both absolute and relative paths)
-                    current_path = if target.starts_with('/') {
-                        target
-                    } else {
-                        // Relative path - resolve relative to the symlink's directory
-                        let base_path = Path::new(&current_path);
-                        let parent = base_path.parent().unwrap_or(Path::new("/"));
-                        let joined = parent.join(&target);
-                        joined.to_string_lossy().into_owned()
-                    };
-                    current_path = self.normalize_path(&current_path);
-                    continue; // Follow the symlink
-                }
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            let row = conn
+                .prepare_cached(
+                    "INSERT INTO fs_inode (mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
+                     VALUES (?, 1, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
+                )
+                .await?
+                .query_row((
+                    src.mode as i64,
+                    src.uid,
+                    src.gid,
+                    src.size,
+                    now_secs,
+                    now_secs,
+                    now_secs,
+                    now_nsec,
+                    now_nsec,
+                    now_nsec,
+                ))
+                .await?;
+            let ino = row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
 
-                // Not a symlink, return the stats
-                let stats = Self::build_stats_from_row(&row)?;
-                return Ok(Some(stats));
-            } else {
-                return Ok(None);
+            let mut stmt = conn
+                .prepare_cached("SELECT chunk_index FROM fs_data WHERE ino = ? ORDER BY chunk_index")
+                .await?;
+            let mut rows = stmt.query((src_ino,)).await?;
+            let mut chunks = Vec::new();
+            while let Some(row) = rows.next().await? {
+                chunks.push(row_integer(&row, 0));
             }
+            for chunk_index in chunks {
+                promote_chunk(&conn, &self.encoding, src_ino, chunk_index).await?;
+                share_chunk(&conn, src_ino, chunk_index, ino, chunk_index).await?;
+            }
+
+            conn.prepare_cached(
+                "INSERT INTO fs_dentry (name, parent_ino, ino, name_key) VALUES (?, ?, ?, ?)",
+            )
+            .await?
+            .execute((name.as_str(), parent_ino, ino, self.name_key(name)))
+            .await?;
+            touch_dir(&conn, parent_ino, now_secs, now_nsec).await?;
+            Ok(ino)
         }
+        .await;
 
-        // Too many symlinks
-        Err(FsError::SymlinkLoop.into())
+        match result {
+            Ok(ino) => {
+                txn.commit().await?;
+                self.dentry_cache.insert(parent_ino, name, ino);
+                self.notify_path(ChangeEventKind::Create, &dst);
+                Ok(())
+            }
+            Err(e) => {
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
     }
 
-    /// Get file statistics, following symlinks (using provided connection)
-    async fn stat_with_conn(&self, conn: &Connection, path: &str) -> Result<Option<Stats>> {
-        let path = self.normalize_path(path);
+    async fn copy_range(
+        &self,
+        src_ino: i64,
+        src_offset: u64,
+        dst_ino: i64,
+        dst_offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.flush_writes().await?;
+        check_copy_range(src_ino, src_offset, dst_ino, dst_offset, len)?;
+        let conn = self.pool.get_connection().await?;
+        let src = self
+            .getattr_with_conn(&conn, src_ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        let dst = self
+            .getattr_with_conn(&conn, dst_ino)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        if src.is_directory() || dst.is_directory() {
+            return Err(FsError::IsADirectory.into());
+        }
 
-        // Follow symlinks with a maximum depth to prevent infinite loops
-        let mut current_path = path;
-        let max_symlink_depth = 40; // Standard limit for symlink following
+        let src_size = src.size as u64;
+        let dst_size = dst.size as u64;
+        let len = len.min(src_size.saturating_sub(src_offset));
+        if len == 0 {
+            return Ok(0);
+        }
+        let dst_end = checked_file_end(dst_offset, len)?;
+        let chunk_size = self.chunk_size as u64;
 
-        for _ in 0..max_symlink_depth {
-            let ino = match self.resolve_path_with_conn(conn, &current_path).await? {
-                Some(ino) => ino,
-                None => return Ok(None),
-            };
+        let txn = begin_immediate(&conn, &self.busy_retry).await?;
 
-            let mut rows = conn
-                .query(
-                    "SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec FROM fs_inode WHERE ino = ?",
-                    (ino,),
-                )
+        let result: Result<()> = async {
+            check_quota(&conn, &self.max_bytes, dst_end.saturating_sub(dst_size)).await?;
+
+            for chunk_index in dst_offset / chunk_size..=(dst_end - 1) / chunk_size {
+                let chunk_start = chunk_index * chunk_size;
+                let lo = dst_offset.max(chunk_start);
+                let hi = dst_end.min(chunk_start + chunk_size);
+                let src_lo = src_offset + (lo - dst_offset);
+                let src_hi = src_lo + (hi - lo);
+
+                // A destination chunk replaced as a whole by a source chunk
+                // at the same alignment takes the stored chunk directly. A
+                // short last chunk qualifies when both files end there.
+                let whole = lo == chunk_start
+                    && src_lo.is_multiple_of(chunk_size)
+                    && (hi == chunk_start + chunk_size || (hi >= dst_size && src_hi == src_size));
+                if whole {
+                    share_chunk(
+                        &conn,
+                        src_ino,
+                        (src_lo / chunk_size) as i64,
+                        dst_ino,
+                        chunk_index as i64,
+                    )
+                    .await?;
+                    continue;
+                }
+
+                // Otherwise gather the source bytes, which may straddle two
+                // chunks, and merge them into the destination chunk
+                let mut data = Vec::with_capacity((hi - lo) as usize);
+                let mut pos = src_lo;
+                while pos < src_hi {
+                    let offset_in_chunk = (pos % chunk_size) as usize;
+                    let take = (chunk_size - pos % chunk_size).min(src_hi - pos) as usize;
+                    let chunk = read_chunk(&conn, &self.encoding, src_ino, (pos / chunk_size) as i64)
+                        .await?
+                        .unwrap_or_default();
+                    let mut piece = vec![0u8; take];
+                    if let Some(available) = chunk.get(offset_in_chunk..) {
+                        let n = available.len().min(take);
+                        piece[..n].copy_from_slice(&available[..n]);
+                    }
+                    data.extend_from_slice(&piece);
+                    pos += take as u64;
+                }
+
+                let mut chunk_data = read_chunk(&conn, &self.encoding, dst_ino, chunk_index as i64)
+                    .await?
+                    .unwrap_or_default();
+                let start = (lo - chunk_start) as usize;
+                let end = (hi - chunk_start) as usize;
+                if chunk_data.len() < end {
+                    chunk_data.resize(end, 0);
+                }
+                chunk_data[start..end].copy_from_slice(&data);
+                store_chunk(&conn, &self.encoding, dst_ino, chunk_index as i64, &chunk_data)
+                    .await?;
+            }
+
+            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let now_secs = dur.as_secs() as i64;
+            let now_nsec = dur.subsec_nanos() as i64;
+            let mut stmt = conn
+                .prepare_cached("UPDATE fs_inode SET size = ?, mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
                 .await?;
+            stmt.execute((
+                dst_size.max(dst_end) as i64,
+                now_secs,
+                now_secs,
+                now_nsec,
+                now_nsec,
+                dst_ino,
+            ))
+            .await?;
 
-            if let Some(row) = rows.next().await? {
-                let mode = row
-                    .get_value(1)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            let _ = txn.rollback().await;
+            return Err(e);
+        }
+        txn.commit().await?;
+        notify_ino(&self.events, &conn, ChangeEventKind::Write, dst_ino).await;
+        Ok(len)
+    }
 
-                // Check if this is a symlink
-                if (mode & S_IFMT) == S_IFLNK {
-                    // Read the symlink target
-                    let target = self
-                        .readlink_with_conn(conn, &current_path)
-                        .await?
-                        .ok_or(FsError::InvalidPath)?;
+    async fn lock(&self, ino: i64, lock_type: LockType, owner: u64) -> Result<()> {
+        let conn = self.pool.get_connection().await?;
+        if self.getattr_with_conn(&conn, ino).await?.is_none() {
+            return Err(FsError::NotFound.into());
+        }
+        self.locks.lock(ino, lock_type, owner)
+    }
 
-                    // Resolve target path (handle both absolute and relative paths)
-                    current_path = if target.starts_with('/') {
-                        target
-                    } else {
-                        // Relative path - resolve relative to the symlink's directory
-                        let base_path = Path::new(&current_path);
-                        let parent = base_path.parent().unwrap_or(Path::new("/"));
-                        let joined = parent.join(&target);
-                        joined.to_string_lossy().into_owned()
-                    };
-                    current_path = self.normalize_path(&current_path);
-                    continue; // Follow the symlink
-                }
+    async fn unlock(&self, ino: i64, owner: u64) -> Result<()> {
+        self.locks.unlock(ino, owner);
+        Ok(())
+    }
 
-                // Not a symlink, return the stats
-                let stats = Self::build_stats_from_row(&row)?;
-                return Ok(Some(stats));
-            } else {
-                return Ok(None);
-            }
+    async fn sync_all(&self) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        checkpoint_wal(&conn).await
+    }
+
+    async fn fsync_dir(&self, ino: i64) -> Result<()> {
+        self.flush_writes().await?;
+        let conn = self.pool.get_connection().await?;
+        match self.getattr_with_conn(&conn, ino).await? {
+            None => Err(FsError::NotFound.into()),
+            Some(stats) if !stats.is_directory() => Err(FsError::NotADirectory.into()),
+            // Directory entries share the log with file data, so syncing it
+            // covers both
+            Some(_) => sync_wal(&conn).await,
         }
+    }
 
-        // Too many symlinks
-        Err(FsError::SymlinkLoop.into())
+    async fn statfs(&self) -> Result<FilesystemStats> {
+        AgentFS::statfs(self).await
     }
 
-    /// Create a directory
-    pub async fn mkdir(&self, path: &str, uid: u32, gid: u32) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-        let components = self.split_path(&path);
+    async fn check_op(&self, op: &Operation) -> Result<()> {
+        // Buffered bytes count towards file sizes and the quota
+        self.flush_writes().await?;
+        check_op_preconditions(self, op, None).await?;
 
-        if components.is_empty() {
-            return Err(FsError::RootOperation.into());
+        let conn = self.pool.get_connection().await?;
+        match op {
+            Operation::Rename {
+                oldparent_ino,
+                oldname,
+                newparent_ino,
+                ..
+            } => {
+                let src_ino = self
+                    .lookup_child(&conn, *oldparent_ino, oldname)
+                    .await?
+                    .ok_or(FsError::NotFound)?;
+                let src = self
+                    .getattr_with_conn(&conn, src_ino)
+                    .await?
+                    .ok_or(FsError::NotFound)?;
+                if src.is_directory() && self.is_within(&conn, *newparent_ino, src_ino).await? {
+                    return Err(FsError::InvalidRename.into());
+                }
+            }
+            Operation::Write { ino, offset, len } => {
+                let stats = self
+                    .getattr_with_conn(&conn, *ino)
+                    .await?
+                    .ok_or(FsError::NotFound)?;
+                let end = checked_file_end(*offset, *len)?;
+                let growth = end.saturating_sub(stats.size as u64);
+                check_quota(&conn, &self.max_bytes, growth).await?;
+            }
+            Operation::Truncate { ino, size } => {
+                let stats = self
+                    .getattr_with_conn(&conn, *ino)
+                    .await?
+                    .ok_or(FsError::NotFound)?;
+                let growth = size.saturating_sub(stats.size as u64);
+                check_quota(&conn, &self.max_bytes, growth).await?;
+            }
+            _ => {}
         }
+        Ok(())
+    }
+}
 
-        let parent_path = if components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!("/{}", components[..components.len() - 1].join("/"))
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
 
-        let parent_ino = self
-            .resolve_path_with_conn(&conn, &parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+    async fn create_test_fs() -> Result<(AgentFS, tempfile::TempDir)> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let fs = AgentFS::new(db_path.to_str().unwrap()).await?;
+        Ok((fs, dir))
+    }
 
-        let name = components.last().unwrap();
+    // ==================== Chunk Size Boundary Tests ====================
 
-        // Check if already exists (single query using parent_ino we already have)
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+    #[tokio::test]
+    async fn test_file_smaller_than_chunk_size() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Create inode with default directory mode (path-based API doesn't accept mode)
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mut stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
-                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
-            .await?;
-        let row = stmt
-            .query_row((
-                DEFAULT_DIR_MODE as i64,
-                uid,
-                gid,
-                now_secs,
-                now_secs,
-                now_secs,
-                now_nsec,
-                now_nsec,
-                now_nsec,
-            ))
+        // Write a file smaller than chunk_size (100 bytes)
+        let data = vec![0u8; 100];
+        let (_, file) = fs
+            .create_file("/small.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
+        file.pwrite(0, &data).await?;
 
-        let ino = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| v.as_integer().copied())
-            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+        // Read it back
+        let read_data = fs.read_file("/small.txt").await?.unwrap();
+        assert_eq!(read_data.len(), 100);
+        assert_eq!(read_data, data);
 
-        // Create directory entry
-        let mut stmt = conn
-            .prepare_cached("INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)")
-            .await?;
-        stmt.execute((name.as_str(), parent_ino, ino)).await?;
+        // Verify only 1 chunk was created
+        let ino = fs.resolve_path("/small.txt").await?.unwrap();
+        let chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(chunk_count, 1);
 
-        // Set nlink to 2 for new directory (self "." + parent's dentry)
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET nlink = 2 WHERE ino = ?")
-            .await?;
-        stmt.execute((ino,)).await?;
+        Ok(())
+    }
 
-        // Increment parent nlink (new directory's ".." link) and update timestamps
-        let mut stmt = conn
-            .prepare_cached(
-                "UPDATE fs_inode SET nlink = nlink + 1, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
-            )
-            .await?;
-        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+    #[tokio::test]
+    async fn test_file_exactly_chunk_size() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+
+        // Write exactly chunk_size bytes
+        let chunk_size = fs.chunk_size();
+        let data: Vec<u8> = (0..chunk_size).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/exact.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
+        file.pwrite(0, &data).await?;
 
-        // Populate dentry cache
-        self.dentry_cache.insert(parent_ino, name, ino);
+        // Read it back
+        let read_data = fs.read_file("/exact.txt").await?.unwrap();
+        assert_eq!(read_data.len(), chunk_size);
+        assert_eq!(read_data, data);
+
+        // Verify only 1 chunk was created
+        let ino = fs.resolve_path("/exact.txt").await?.unwrap();
+        let chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(chunk_count, 1);
 
         Ok(())
     }
 
-    /// Create a special file node (FIFO, device, socket, or regular file)
-    pub async fn mknod(&self, path: &str, mode: u32, rdev: u64, uid: u32, gid: u32) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-        let components = self.split_path(&path);
+    #[tokio::test]
+    async fn test_file_one_byte_over_chunk_size() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if components.is_empty() {
-            return Err(FsError::RootOperation.into());
-        }
+        // Write chunk_size + 1 bytes
+        let chunk_size = fs.chunk_size();
+        let data: Vec<u8> = (0..=chunk_size).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/overflow.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &data).await?;
 
-        let parent_path = if components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!("/{}", components[..components.len() - 1].join("/"))
-        };
+        // Read it back
+        let read_data = fs.read_file("/overflow.txt").await?.unwrap();
+        assert_eq!(read_data.len(), chunk_size + 1);
+        assert_eq!(read_data, data);
 
-        let parent_ino = self
-            .resolve_path_with_conn(&conn, &parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        // Verify 2 chunks were created
+        let ino = fs.resolve_path("/overflow.txt").await?.unwrap();
+        let chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(chunk_count, 2);
 
-        let name = components.last().unwrap();
+        Ok(())
+    }
 
-        // Check if already exists
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+    #[tokio::test]
+    async fn test_file_spanning_multiple_chunks() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Create inode with mode and rdev
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mut stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec)
-                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
-            .await?;
-        let row = stmt
-            .query_row((
-                mode as i64,
-                uid,
-                gid,
-                now_secs,
-                now_secs,
-                now_secs,
-                rdev as i64,
-                now_nsec,
-                now_nsec,
-                now_nsec,
-            ))
+        // Write ~2.5 chunks worth of data
+        let chunk_size = fs.chunk_size();
+        let data_size = chunk_size * 2 + chunk_size / 2;
+        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/multi.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
+        file.pwrite(0, &data).await?;
 
-        let ino = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| v.as_integer().copied())
-            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+        // Read it back
+        let read_data = fs.read_file("/multi.txt").await?.unwrap();
+        assert_eq!(read_data.len(), data_size);
+        assert_eq!(read_data, data);
 
-        // Create directory entry
-        let mut stmt = conn
-            .prepare_cached("INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)")
-            .await?;
-        stmt.execute((name.as_str(), parent_ino, ino)).await?;
+        // Verify 3 chunks were created
+        let ino = fs.resolve_path("/multi.txt").await?.unwrap();
+        let chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(chunk_count, 3);
 
-        // Increment link count
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
+        Ok(())
+    }
+
+    // ==================== Data Integrity Tests ====================
+
+    #[tokio::test]
+    async fn test_roundtrip_byte_for_byte() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+
+        // Create data that spans chunk boundaries with identifiable patterns
+        let chunk_size = fs.chunk_size();
+        let data_size = chunk_size * 3 + 123; // Odd size spanning 4 chunks
+
+        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/roundtrip.bin", DEFAULT_FILE_MODE, 0, 0)
             .await?;
-        stmt.execute((ino,)).await?;
+        file.pwrite(0, &data).await?;
 
-        // Populate dentry cache
-        self.dentry_cache.insert(parent_ino, name, ino);
+        let read_data = fs.read_file("/roundtrip.bin").await?.unwrap();
+        assert_eq!(read_data.len(), data_size);
+        assert_eq!(read_data, data, "Data mismatch after roundtrip");
 
         Ok(())
     }
 
-    /// Create a new empty file with the specified mode and ownership.
-    ///
-    /// This is an optimized path for FUSE create() that combines inode creation,
-    /// dentry creation, and file handle opening in a single operation.
-    /// Returns both Stats and an open file handle.
-    pub async fn create_file(
-        &self,
-        path: &str,
-        mode: u32,
-        uid: u32,
-        gid: u32,
-    ) -> Result<(Stats, BoxedFile)> {
-        let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-        let components = self.split_path(&path);
+    #[tokio::test]
+    async fn test_binary_data_with_null_bytes() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if components.is_empty() {
-            return Err(FsError::RootOperation.into());
-        }
+        let chunk_size = fs.chunk_size();
+        // Create data with null bytes at chunk boundaries
+        let mut data = vec![0u8; chunk_size * 2 + 100];
+        // Put nulls at the chunk boundary
+        data[chunk_size - 1] = 0;
+        data[chunk_size] = 0;
+        data[chunk_size + 1] = 0;
+        // Put some non-null bytes around
+        data[chunk_size - 2] = 0xFF;
+        data[chunk_size + 2] = 0xFF;
 
-        let parent_path = match components.len() {
-            1 => "/".to_string(),
-            _ => format!("/{}", components[..components.len() - 1].join("/")),
-        };
+        let (_, file) = fs
+            .create_file("/nulls.bin", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &data).await?;
+        let read_data = fs.read_file("/nulls.bin").await?.unwrap();
 
-        let parent_ino = self
-            .resolve_path_with_conn(&conn, &parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        assert_eq!(read_data, data, "Null bytes at chunk boundary corrupted");
 
-        let name = components.last().unwrap();
+        Ok(())
+    }
 
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+    #[tokio::test]
+    async fn test_chunk_ordering() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Prepare statements before starting the transaction
-        let mut inode_stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
-                 VALUES (?, 1, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
-            .await?;
-        let mut dentry_stmt = conn
-            .prepare_cached("INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)")
+        let chunk_size = fs.chunk_size();
+        // Create sequential bytes spanning multiple chunks
+        let data_size = chunk_size * 5;
+        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/sequential.bin", DEFAULT_FILE_MODE, 0, 0)
             .await?;
+        file.pwrite(0, &data).await?;
 
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
+        let read_data = fs.read_file("/sequential.bin").await?.unwrap();
 
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let file_mode = S_IFREG | (mode & 0o7777);
+        // Verify every byte is in the correct position
+        for (i, (&expected, &actual)) in data.iter().zip(read_data.iter()).enumerate() {
+            assert_eq!(
+                expected, actual,
+                "Byte mismatch at position {}: expected {}, got {}",
+                i, expected, actual
+            );
+        }
 
-        let row = inode_stmt
-            .query_row((
-                file_mode as i64,
-                uid,
-                gid,
-                now_secs,
-                now_secs,
-                now_secs,
-                now_nsec,
-                now_nsec,
-                now_nsec,
-            ))
-            .await?;
+        Ok(())
+    }
 
-        let ino = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| v.as_integer().copied())
-            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+    // ==================== Edge Case Tests ====================
 
-        dentry_stmt
-            .execute((name.as_str(), parent_ino, ino))
-            .await?;
+    #[tokio::test]
+    async fn test_empty_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        txn.commit().await?;
+        // Write empty file
+        let (_, file) = fs
+            .create_file("/empty.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &[]).await?;
 
-        self.dentry_cache.insert(parent_ino, name, ino);
+        // Read it back
+        let read_data = fs.read_file("/empty.txt").await?.unwrap();
+        assert!(read_data.is_empty());
 
-        let stats = Stats {
-            ino,
-            mode: file_mode,
-            nlink: 1,
-            uid,
-            gid,
-            size: 0,
-            atime: now_secs,
-            mtime: now_secs,
-            ctime: now_secs,
-            atime_nsec: now_nsec as u32,
-            mtime_nsec: now_nsec as u32,
-            ctime_nsec: now_nsec as u32,
-            rdev: 0,
-        };
+        // Verify 0 chunks were created
+        let ino = fs.resolve_path("/empty.txt").await?.unwrap();
+        let chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(chunk_count, 0);
 
-        let file: BoxedFile = Arc::new(AgentFSFile {
-            pool: self.pool.clone(),
-            ino,
-            chunk_size: self.chunk_size,
-        });
+        // Verify size is 0
+        let stats = fs.stat("/empty.txt").await?.unwrap();
+        assert_eq!(stats.size, 0);
 
-        Ok((stats, file))
+        Ok(())
     }
 
-    /// Read data from a file
-    pub async fn read_file(&self, path: &str) -> Result<Option<Vec<u8>>> {
-        let conn = self.pool.get_connection().await?;
-        let ino = match self.resolve_path_with_conn(&conn, path).await? {
-            Some(ino) => ino,
-            None => return Ok(None),
-        };
-
-        let mut rows = conn
-            .query(
-                "SELECT data FROM fs_data WHERE ino = ? ORDER BY chunk_index",
-                (ino,),
-            )
-            .await?;
+    #[tokio::test]
+    async fn test_overwrite_existing_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        let mut data = Vec::new();
-        while let Some(row) = rows.next().await? {
-            if let Ok(Value::Blob(chunk)) = row.get_value(0) {
-                data.extend_from_slice(&chunk);
-            }
-        }
+        let chunk_size = fs.chunk_size();
 
-        Ok(Some(data))
-    }
+        // Write initial large file (3 chunks)
+        let initial_data: Vec<u8> = (0..chunk_size * 3).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/overwrite.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &initial_data).await?;
 
-    /// Reads from a file at a given offset.
-    ///
-    /// Similar to POSIX `pread`, this reads up to `size` bytes from the file
-    /// starting at `offset`, without modifying any file cursor.
-    ///
-    /// Returns `Ok(None)` if the file does not exist.
-    pub async fn pread(&self, path: &str, offset: u64, size: u64) -> Result<Option<Vec<u8>>> {
-        let conn = self.pool.get_connection().await?;
-        let ino = match self.resolve_path_with_conn(&conn, path).await? {
-            Some(ino) => ino,
-            None => return Ok(None),
-        };
+        let ino = fs.resolve_path("/overwrite.txt").await?.unwrap();
+        let initial_chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(initial_chunk_count, 3);
 
-        // Calculate which chunks we need
-        let chunk_size = self.chunk_size as u64;
-        let start_chunk = offset / chunk_size;
-        let end_chunk = (offset + size).saturating_sub(1) / chunk_size;
+        // Overwrite with smaller file (1 chunk)
+        let new_data = vec![42u8; 100];
+        fs.truncate("/overwrite.txt", 0).await?;
+        let file = fs.open("/overwrite.txt").await?;
+        file.pwrite(0, &new_data).await?;
 
-        let mut rows = conn
-            .query(
-                "SELECT chunk_index, data FROM fs_data WHERE ino = ? AND chunk_index >= ? AND chunk_index <= ? ORDER BY chunk_index",
-                (ino, start_chunk as i64, end_chunk as i64),
-            )
-            .await?;
+        // Verify old chunks are gone and new data is correct
+        let read_data = fs.read_file("/overwrite.txt").await?.unwrap();
+        assert_eq!(read_data, new_data);
 
-        let mut result = Vec::with_capacity(size as usize);
-        let start_offset_in_chunk = (offset % chunk_size) as usize;
+        let new_chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(new_chunk_count, 1);
 
-        while let Some(row) = rows.next().await? {
-            if let Ok(Value::Blob(chunk_data)) = row.get_value(1) {
-                let skip = if result.is_empty() {
-                    start_offset_in_chunk
-                } else {
-                    0
-                };
-                if skip >= chunk_data.len() {
-                    continue;
-                }
-                let remaining = size as usize - result.len();
-                let take = std::cmp::min(chunk_data.len() - skip, remaining);
-                result.extend_from_slice(&chunk_data[skip..skip + take]);
-            }
-        }
+        // Verify size is updated
+        let stats = fs.stat("/overwrite.txt").await?.unwrap();
+        assert_eq!(stats.size, 100);
 
-        Ok(Some(result))
+        Ok(())
     }
 
-    /// Writes to a file at a given offset.
-    ///
-    /// Similar to POSIX `pwrite`, this writes `data` to the file starting at
-    /// `offset`, without modifying any file cursor.
-    ///
-    /// If the offset is beyond the current file size, the file is extended with zeros.
-    /// If the file does not exist, it will be created.
-    pub async fn pwrite(&self, path: &str, offset: u64, data: &[u8]) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-        let components = self.split_path(&path);
+    #[tokio::test]
+    async fn test_overwrite_with_larger_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if components.is_empty() {
-            return Err(FsError::RootOperation.into());
-        }
+        let chunk_size = fs.chunk_size();
 
-        let parent_path = if components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!("/{}", components[..components.len() - 1].join("/"))
-        };
+        // Write initial small file (1 chunk)
+        let initial_data = vec![1u8; 100];
+        let (_, file) = fs.create_file("/grow.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &initial_data).await?;
 
-        let parent_ino = self
-            .resolve_path_with_conn(&conn, &parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        let ino = fs.resolve_path("/grow.txt").await?.unwrap();
+        assert_eq!(fs.get_chunk_count(ino).await?, 1);
+
+        // Overwrite with larger file (3 chunks)
+        let new_data: Vec<u8> = (0..chunk_size * 3).map(|i| (i % 256) as u8).collect();
+        fs.truncate("/grow.txt", 0).await?;
+        let file = fs.open("/grow.txt").await?;
+        file.pwrite(0, &new_data).await?;
 
-        let name = components.last().unwrap();
+        // Verify data is correct
+        let read_data = fs.read_file("/grow.txt").await?.unwrap();
+        assert_eq!(read_data, new_data);
+        assert_eq!(fs.get_chunk_count(ino).await?, 3);
 
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
+        Ok(())
+    }
 
-        let result: Result<()> = async {
-            // Calculate the final size upfront
-            let write_end = offset + data.len() as u64;
+    #[tokio::test]
+    async fn test_very_large_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-            // Get or create the inode
-            let (ino, current_size, is_new) =
-                if let Some(ino) = self.resolve_path_with_conn(&conn, &path).await? {
-                    // Get current file size
-                    let mut stmt = conn
-                        .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
-                        .await?;
-                    let mut rows = stmt.query((ino,)).await?;
-                    let size = if let Some(row) = rows.next().await? {
-                        row.get_value(0)
-                            .ok()
-                            .and_then(|v| v.as_integer().copied())
-                            .unwrap_or(0) as u64
-                    } else {
-                        0
-                    };
-                    (ino, size, false)
-                } else {
-                    // Create new inode with correct size upfront
-                    let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-                    let now_secs = dur.as_secs() as i64;
-                    let now_nsec = dur.subsec_nanos() as i64;
-                    let new_size = write_end as i64;
-                    let mut stmt = conn
-                        .prepare_cached(
-                            "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, nlink, atime_nsec, mtime_nsec, ctime_nsec)
-                        VALUES (?, 0, 0, ?, ?, ?, ?, 1, ?, ?, ?) RETURNING ino",
-                        )
-                        .await?;
-                    let row = stmt
-                        .query_row((DEFAULT_FILE_MODE as i64, new_size, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec))
-                        .await?;
+        // Write 1MB file
+        let data_size = 1024 * 1024;
+        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/large.bin", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &data).await?;
 
-                    let ino = row
-                        .get_value(0)
-                        .ok()
-                        .and_then(|v| v.as_integer().copied())
-                        .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+        let read_data = fs.read_file("/large.bin").await?.unwrap();
+        assert_eq!(read_data.len(), data_size);
+        assert_eq!(read_data, data);
 
-                    // Create directory entry
-                    let mut stmt = conn
-                        .prepare_cached(
-                            "INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)",
-                        )
-                        .await?;
-                    stmt.execute((name.as_str(), parent_ino, ino)).await?;
+        // Verify correct number of chunks
+        let chunk_size = fs.chunk_size();
+        let expected_chunks = data_size.div_ceil(chunk_size);
+        let ino = fs.resolve_path("/large.bin").await?.unwrap();
+        let actual_chunks = fs.get_chunk_count(ino).await? as usize;
+        assert_eq!(actual_chunks, expected_chunks);
 
-                    (ino, 0, true)
-                };
+        Ok(())
+    }
 
-            // Handle empty writes - just update mtime
-            if data.is_empty() {
-                let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-                let now_secs = dur.as_secs() as i64;
-                let now_nsec = dur.subsec_nanos() as i64;
-                conn.prepare_cached("UPDATE fs_inode SET mtime = ?, mtime_nsec = ? WHERE ino = ?")
-                    .await?
-                    .execute((now_secs, now_nsec, ino))
-                    .await?;
-                return Ok(());
-            }
+    // ==================== Configuration Tests ====================
 
-            let chunk_size = self.chunk_size as u64;
+    #[tokio::test]
+    async fn test_default_chunk_size() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-            // Calculate affected chunk range
-            let start_chunk = offset / chunk_size;
-            let end_chunk = (write_end - 1) / chunk_size;
+        assert_eq!(fs.chunk_size(), DEFAULT_CHUNK_SIZE);
+        assert_eq!(fs.chunk_size(), 4096);
 
-            // Process each affected chunk
-            for chunk_idx in start_chunk..=end_chunk {
-                let chunk_start = chunk_idx * chunk_size;
+        Ok(())
+    }
 
-                // Calculate what part of data goes into this chunk
-                let data_start = if offset > chunk_start {
-                    (offset - chunk_start) as usize
-                } else {
-                    0
-                };
-                let data_end =
-                    std::cmp::min(chunk_size as usize, (write_end - chunk_start) as usize);
+    #[tokio::test]
+    async fn test_chunk_size_accessor() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-                // Calculate what part of data to copy
-                let src_start = if chunk_start > offset {
-                    (chunk_start - offset) as usize
-                } else {
-                    0
-                };
-                let src_end = std::cmp::min(data.len(), src_start + (data_end - data_start));
+        let chunk_size = fs.chunk_size();
+        assert!(chunk_size > 0);
 
-                // Read existing chunk if we need to preserve some data
-                let needs_read = data_start > 0 || data_end < chunk_size as usize;
-                let mut chunk_data = if needs_read {
-                    let mut rows = conn
-                        .query(
-                            "SELECT data FROM fs_data WHERE ino = ? AND chunk_index = ?",
-                            (ino, chunk_idx as i64),
-                        )
-                        .await?;
-                    if let Some(row) = rows.next().await? {
-                        if let Ok(Value::Blob(data)) = row.get_value(0) {
-                            let mut v = data.clone();
-                            v.resize(chunk_size as usize, 0);
-                            v
-                        } else {
-                            vec![0u8; chunk_size as usize]
-                        }
-                    } else {
-                        vec![0u8; chunk_size as usize]
-                    }
-                } else {
-                    vec![0u8; chunk_size as usize]
-                };
+        // Write data and verify chunks match expected based on chunk_size
+        let data = vec![0u8; chunk_size * 2 + 1];
+        let (_, file) = fs.create_file("/test.bin", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-                // Copy the new data into the chunk
-                chunk_data[data_start..data_end].copy_from_slice(&data[src_start..src_end]);
+        let ino = fs.resolve_path("/test.bin").await?.unwrap();
+        let chunk_count = fs.get_chunk_count(ino).await?;
+        assert_eq!(chunk_count, 3);
 
-                // Trim trailing zeros for the last chunk
-                let actual_len = if chunk_idx == end_chunk {
-                    let file_end_in_chunk = (write_end - chunk_start) as usize;
-                    let old_end_in_chunk = if current_size > chunk_start {
-                        std::cmp::min((current_size - chunk_start) as usize, chunk_size as usize)
-                    } else {
-                        0
-                    };
-                    std::cmp::max(file_end_in_chunk, old_end_in_chunk)
-                } else {
-                    chunk_size as usize
-                };
+        Ok(())
+    }
 
-                // Write the chunk - delete existing then insert
-                conn.execute(
-                    "DELETE FROM fs_data WHERE ino = ? AND chunk_index = ?",
-                    (ino, chunk_idx as i64),
-                )
-                .await?;
-                conn.execute(
-                    "INSERT INTO fs_data (ino, chunk_index, data) VALUES (?, ?, ?)",
-                    (ino, chunk_idx as i64, &chunk_data[..actual_len]),
-                )
-                .await?;
-            }
+    #[tokio::test]
+    async fn test_config_persistence() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-            // Update size and mtime (only if not new, since new inodes already have correct values)
-            if !is_new {
-                let new_size = std::cmp::max(current_size, write_end);
-                let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-                let now_secs = dur.as_secs() as i64;
-                let now_nsec = dur.subsec_nanos() as i64;
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET size = ?, mtime = ?, mtime_nsec = ? WHERE ino = ?")
-                    .await?;
-                stmt.execute((new_size as i64, now_secs, now_nsec, ino)).await?;
-            }
+        // Query fs_config table directly
+        let conn = fs.pool.get_connection().await?;
+        let mut rows = conn
+            .query("SELECT value FROM fs_config WHERE key = 'chunk_size'", ())
+            .await?;
 
-            Ok(())
-        }
-        .await;
+        let row = rows.next().await?.expect("chunk_size config should exist");
+        let value = row
+            .get_value(0)
+            .ok()
+            .and_then(|v| match v {
+                Value::Text(s) => Some(s.clone()),
+                _ => None,
+            })
+            .expect("chunk_size should be a text value");
 
-        match result {
-            Ok(()) => {
-                txn.commit().await?;
-                Ok(())
-            }
-            Err(e) => {
-                let _ = txn.rollback().await;
-                Err(e)
-            }
-        }
-    }
+        assert_eq!(value, "4096");
 
-    /// Truncate a file to a specific size.
-    ///
-    /// This operates directly on chunks without loading the entire file into memory:
-    /// - Shrinking: deletes chunks beyond new size, truncates the last chunk if needed
-    /// - Extending: pads with zeros up to the new size
-    pub async fn truncate(&self, path: &str, new_size: u64) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-        let ino = self
-            .resolve_path_with_conn(&conn, &path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        Ok(())
+    }
 
-        // Get current size
-        let mut stmt = conn
-            .prepare_cached("SELECT size FROM fs_inode WHERE ino = ?")
+    #[tokio::test]
+    async fn test_init_chunk_size() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let db = Builder::new_local(db_path.to_str().unwrap())
+            .build()
             .await?;
-        let mut rows = stmt.query((ino,)).await?;
-        let current_size = if let Some(row) = rows.next().await? {
-            row.get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64
-        } else {
-            0
-        };
+        let pool = ConnectionPool::new(db);
+        let conn = pool.get_connection().await?;
 
-        let chunk_size = self.chunk_size as u64;
+        for invalid in [0, 1000, MIN_CHUNK_SIZE / 2, MAX_CHUNK_SIZE * 2] {
+            assert!(matches!(
+                AgentFS::init_chunk_size(&conn, invalid).await,
+                Err(Error::InvalidChunkSize(_))
+            ));
+        }
+        AgentFS::init_chunk_size(&conn, 1024).await?;
+        drop(conn);
+        let fs = AgentFS::from_pool(pool.clone()).await?;
+        assert_eq!(fs.chunk_size(), 1024);
+
+        // A partial write only rewrites the chunks it covers
+        fs.pwrite("/test.bin", 0, &vec![1u8; 4096]).await?;
+        fs.pwrite("/test.bin", 1500, b"xy").await?;
+        let ino = fs.resolve_path("/test.bin").await?.unwrap();
+        assert_eq!(fs.get_chunk_count(ino).await?, 4);
+        let data = fs.read_file("/test.bin").await?.unwrap();
+        assert_eq!(&data[1499..1503], b"\x01xy\x01");
 
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
+        // Once data is stored, the chunk size can no longer change
+        let conn = pool.get_connection().await?;
+        AgentFS::init_chunk_size(&conn, 1024).await?;
+        assert!(matches!(
+            AgentFS::init_chunk_size(&conn, 2048).await,
+            Err(Error::InvalidChunkSize(_))
+        ));
 
-        let result: Result<()> = async {
-            if new_size == 0 {
-                // Special case: truncate to zero - just delete all chunks
-                let mut stmt = conn
-                    .prepare_cached("DELETE FROM fs_data WHERE ino = ?")
-                    .await?;
-                stmt.execute((ino,)).await?;
-            } else if new_size < current_size {
-                // Shrinking: delete excess chunks and truncate last chunk if needed
-                let last_chunk_idx = (new_size - 1) / chunk_size;
+        Ok(())
+    }
 
-                // Delete all chunks beyond the last one we need
-                conn.execute(
-                    "DELETE FROM fs_data WHERE ino = ? AND chunk_index > ?",
-                    (ino, last_chunk_idx as i64),
-                )
-                .await?;
+    // ==================== Schema Tests ====================
 
-                // Calculate where in the last chunk the file should end
-                let end_in_last_chunk = ((new_size - 1) % chunk_size) + 1;
+    #[tokio::test]
+    async fn test_chunk_index_uniqueness() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-                // If the last chunk needs to be truncated (not a full chunk),
-                // read it, truncate, and rewrite
-                if end_in_last_chunk < chunk_size {
-                    let mut stmt = conn
-                        .prepare_cached("SELECT data FROM fs_data WHERE ino = ? AND chunk_index = ?")
-                        .await?;
-                    let mut rows = stmt.query((ino, last_chunk_idx as i64)).await?;
+        // Write a file to create chunks
+        let chunk_size = fs.chunk_size();
+        let data = vec![0u8; chunk_size * 2];
+        let (_, file) = fs
+            .create_file("/unique.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &data).await?;
 
-                    if let Some(row) = rows.next().await? {
-                        if let Ok(Value::Blob(chunk_data)) = row.get_value(0) {
-                            if chunk_data.len() > end_in_last_chunk as usize {
-                                let truncated = &chunk_data[..end_in_last_chunk as usize];
-                                let mut stmt = conn
-                                    .prepare_cached("UPDATE fs_data SET data = ? WHERE ino = ? AND chunk_index = ?")
-                                    .await?;
-                                stmt.execute((truncated, ino, last_chunk_idx as i64)).await?;
-                            }
-                        }
-                    }
-                }
-            } else if new_size > current_size {
-                // Extending: pad last existing chunk and add zero chunks as needed
-                let last_existing_chunk = if current_size == 0 {
-                    None
-                } else {
-                    Some((current_size - 1) / chunk_size)
-                };
-                let last_new_chunk = (new_size - 1) / chunk_size;
+        let ino = fs.resolve_path("/unique.txt").await?.unwrap();
 
-                // Pad the last existing chunk with zeros if it's not full
-                if let Some(last_idx) = last_existing_chunk {
-                    let mut stmt = conn
-                        .prepare_cached("SELECT data FROM fs_data WHERE ino = ? AND chunk_index = ?")
-                        .await?;
-                    let mut rows = stmt.query((ino, last_idx as i64)).await?;
+        // Try to insert a duplicate chunk - should fail due to PRIMARY KEY constraint
+        let conn = fs.pool.get_connection().await?;
+        let result = conn
+            .execute(
+                "INSERT INTO fs_data (ino, chunk_index, data) VALUES (?, 0, ?)",
+                (ino, vec![1u8; 10]),
+            )
+            .await;
 
-                    if let Some(row) = rows.next().await? {
-                        if let Ok(Value::Blob(chunk_data)) = row.get_value(0) {
-                            let current_chunk_len = chunk_data.len();
-                            let needed_len = if last_idx == last_new_chunk {
-                                // Last existing chunk is also the last new chunk
-                                ((new_size - 1) % chunk_size + 1) as usize
-                            } else {
-                                // Need to fill this chunk completely
-                                chunk_size as usize
-                            };
-
-                            if needed_len > current_chunk_len {
-                                let mut padded = chunk_data.clone();
-                                padded.resize(needed_len, 0);
-                                let mut stmt = conn
-                                    .prepare_cached("UPDATE fs_data SET data = ? WHERE ino = ? AND chunk_index = ?")
-                                    .await?;
-                                stmt.execute((&padded[..], ino, last_idx as i64)).await?;
-                            }
-                        }
-                    }
-                }
+        assert!(result.is_err(), "Duplicate chunk_index should be rejected");
 
-                // Add new zero-filled chunks if needed
-                let start_new_chunk = last_existing_chunk.map(|i| i + 1).unwrap_or(0);
-                for chunk_idx in start_new_chunk..=last_new_chunk {
-                    let chunk_len = if chunk_idx == last_new_chunk {
-                        ((new_size - 1) % chunk_size + 1) as usize
-                    } else {
-                        chunk_size as usize
-                    };
-                    let zeros = vec![0u8; chunk_len];
-                    conn.execute(
-                        "INSERT INTO fs_data (ino, chunk_index, data) VALUES (?, ?, ?)",
-                        (ino, chunk_idx as i64, &zeros[..]),
-                    )
-                    .await?;
-                }
-            }
-            // else: new_size == current_size, nothing to do for data
+        Ok(())
+    }
 
-            // Update size and mtime
-            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-            let now_secs = dur.as_secs() as i64;
-            let now_nsec = dur.subsec_nanos() as i64;
-            let mut stmt = conn
-                .prepare_cached("UPDATE fs_inode SET size = ?, mtime = ?, mtime_nsec = ? WHERE ino = ?")
-                .await?;
-            stmt.execute((new_size as i64, now_secs, now_nsec, ino)).await?;
+    #[tokio::test]
+    async fn test_chunk_ordering_in_database() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-            Ok(())
-        }
-        .await;
+        let chunk_size = fs.chunk_size();
+        // Create 5 chunks with identifiable data
+        let data_size = chunk_size * 5;
+        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs
+            .create_file("/ordered.bin", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &data).await?;
 
-        match result {
-            Ok(()) => {
-                txn.commit().await?;
-                Ok(())
-            }
-            Err(e) => {
-                let _ = txn.rollback().await;
-                Err(e)
-            }
-        }
-    }
+        let ino = fs.resolve_path("/ordered.bin").await?.unwrap();
 
-    /// List directory contents
-    pub async fn readdir(&self, ino: i64) -> Result<Option<Vec<String>>> {
-        let conn = self.pool.get_connection().await?;
+        // Query chunks in order
+        let conn = fs.pool.get_connection().await?;
         let mut rows = conn
             .query(
-                "SELECT name FROM fs_dentry WHERE parent_ino = ? ORDER BY name",
+                "SELECT chunk_index FROM fs_data WHERE ino = ? ORDER BY chunk_index",
                 (ino,),
             )
             .await?;
 
-        let mut entries = Vec::new();
+        let mut indices = Vec::new();
         while let Some(row) = rows.next().await? {
-            let name = row
+            let idx = row
                 .get_value(0)
                 .ok()
-                .and_then(|v| {
-                    if let Value::Text(s) = v {
-                        Some(s.clone())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default();
-            if !name.is_empty() {
-                entries.push(name);
-            }
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(-1);
+            indices.push(idx);
         }
 
-        Ok(Some(entries))
+        assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+
+        Ok(())
     }
 
-    /// List directory contents with full statistics (optimized batch query)
-    ///
-    /// Returns entries with their stats in a single JOIN query, avoiding N+1 queries.
-    pub async fn readdir_plus(&self, ino: i64) -> Result<Option<Vec<DirEntry>>> {
-        let conn = self.pool.get_connection().await?;
-        let mut stmt = conn.prepare_cached("SELECT d.name, i.ino, i.mode, i.nlink, i.uid, i.gid, i.size, i.atime, i.mtime, i.ctime, i.rdev, i.atime_nsec, i.mtime_nsec, i.ctime_nsec
-            FROM fs_dentry d
-            JOIN fs_inode i ON d.ino = i.ino
-            WHERE d.parent_ino = ?
-            ORDER BY d.name"
-        ).await?;
-        // Single JOIN query to get all entry names and their stats (including link count)
-        let mut rows = stmt.query((ino,)).await?;
+    // ==================== Cleanup Tests ====================
 
-        let mut entries = Vec::new();
-        while let Some(row) = rows.next().await? {
-            let name = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| {
-                    if let Value::Text(s) = v {
-                        Some(s.clone())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default();
+    #[tokio::test]
+    async fn test_delete_file_removes_all_chunks() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-            if name.is_empty() {
-                continue;
-            }
+        let chunk_size = fs.chunk_size();
+        // Create multi-chunk file
+        let data = vec![0u8; chunk_size * 4];
+        let (_, file) = fs
+            .create_file("/deleteme.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, &data).await?;
 
-            let entry_ino = row
-                .get_value(1)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0);
+        let ino = fs.resolve_path("/deleteme.txt").await?.unwrap();
+        assert_eq!(fs.get_chunk_count(ino).await?, 4);
 
-            let nlink = row
-                .get_value(3)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(1) as u32;
+        // Delete the file
+        fs.remove("/deleteme.txt").await?;
 
-            let stats = Stats {
-                ino: entry_ino,
-                mode: row
-                    .get_value(2)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                nlink,
-                uid: row
-                    .get_value(4)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                gid: row
-                    .get_value(5)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                size: row
-                    .get_value(6)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                atime: row
-                    .get_value(7)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                mtime: row
-                    .get_value(8)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                ctime: row
-                    .get_value(9)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                atime_nsec: row
-                    .get_value(11)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                mtime_nsec: row
-                    .get_value(12)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                ctime_nsec: row
-                    .get_value(13)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                rdev: row
-                    .get_value(10)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u64,
-            };
+        // Verify all chunks are gone
+        let conn = fs.pool.get_connection().await?;
+        let mut rows = conn
+            .query("SELECT COUNT(*) FROM fs_data WHERE ino = ?", (ino,))
+            .await?;
+
+        let count = rows
+            .next()
+            .await?
+            .and_then(|r| r.get_value(0).ok().and_then(|v| v.as_integer().copied()))
+            .unwrap_or(-1);
+
+        assert_eq!(count, 0, "All chunks should be deleted");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multiple_files_different_sizes() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+
+        let chunk_size = fs.chunk_size();
+
+        // Create files of various sizes
+        let files = vec![
+            ("/tiny.txt", 10),
+            ("/small.txt", chunk_size / 2),
+            ("/exact.txt", chunk_size),
+            ("/medium.txt", chunk_size * 2 + 100),
+            ("/large.txt", chunk_size * 5),
+        ];
+
+        for (path, size) in &files {
+            let data: Vec<u8> = (0..*size).map(|i| (i % 256) as u8).collect();
+            let (_, file) = fs.create_file(path, DEFAULT_FILE_MODE, 0, 0).await?;
+            file.pwrite(0, &data).await?;
+        }
 
-            entries.push(DirEntry { name, stats });
+        // Verify each file has correct data and chunk count
+        for (path, size) in &files {
+            let read_data = fs.read_file(path).await?.unwrap();
+            assert_eq!(read_data.len(), *size, "Size mismatch for {}", path);
+
+            let expected_data: Vec<u8> = (0..*size).map(|i| (i % 256) as u8).collect();
+            assert_eq!(read_data, expected_data, "Data mismatch for {}", path);
+
+            let expected_chunks = size.div_ceil(chunk_size);
+            let ino = fs.resolve_path(path).await?.unwrap();
+            let actual_chunks = fs.get_chunk_count(ino).await? as usize;
+            assert_eq!(
+                actual_chunks, expected_chunks,
+                "Chunk count mismatch for {}",
+                path
+            );
         }
 
-        Ok(Some(entries))
+        Ok(())
     }
 
-    /// Create a symbolic link with the specified ownership
-    pub async fn symlink(&self, target: &str, linkpath: &str, uid: u32, gid: u32) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let linkpath = self.normalize_path(linkpath);
-        let components = self.split_path(&linkpath);
+    #[tokio::test]
+    async fn test_pread_basic() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if components.is_empty() {
-            return Err(FsError::RootOperation.into());
-        }
+        // Write a file with known content
+        let data: Vec<u8> = (0..100).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        // Get parent directory
-        let parent_path = if components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!("/{}", components[..components.len() - 1].join("/"))
-        };
+        // Read from the beginning
+        let result = fs.pread("/test.txt", 0, 10).await?.unwrap();
+        assert_eq!(result, &data[0..10]);
 
-        let parent_ino = self
-            .resolve_path_with_conn(&conn, &parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        // Read from the middle
+        let result = fs.pread("/test.txt", 50, 20).await?.unwrap();
+        assert_eq!(result, &data[50..70]);
 
-        let name = components.last().unwrap();
+        // Read from near the end
+        let result = fs.pread("/test.txt", 90, 10).await?.unwrap();
+        assert_eq!(result, &data[90..100]);
 
-        // Check if entry already exists (single query using parent_ino we already have)
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+        Ok(())
+    }
 
-        // Create inode for symlink
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
+    #[tokio::test]
+    async fn test_pread_past_eof() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        let mode = S_IFLNK | 0o777; // Symlinks typically have 777 permissions
-        let size = target.len() as i64;
+        let data: Vec<u8> = (0..50).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        let mut stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
-            .await?;
-        let row = stmt
-            .query_row((
-                mode, uid, gid, size, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec,
-            ))
-            .await?;
+        // Read starting at or past EOF should return empty
+        let result = fs.pread("/test.txt", 50, 10).await?.unwrap();
+        assert!(result.is_empty());
+        let result = fs.pread("/test.txt", 100, 10).await?.unwrap();
+        assert!(result.is_empty());
+        let result = fs.pread("/test.txt", u64::MAX, u64::MAX).await?.unwrap();
+        assert!(result.is_empty());
 
-        // Get the newly created inode
-        let ino = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| v.as_integer().copied())
-            .unwrap_or(0);
+        // Read that extends past EOF should return only available data
+        let result = fs.pread("/test.txt", 40, 20).await?.unwrap();
+        assert_eq!(result, &data[40..50]);
 
-        // Store symlink target
-        conn.execute(
-            "INSERT INTO fs_symlink (ino, target) VALUES (?, ?)",
-            (ino, target),
-        )
-        .await?;
+        // The same holds for open files, including bytes not yet stored
+        assert_eq!(file.pread(50, 10).await?, b"");
+        assert_eq!(file.pread(100, 10).await?, b"");
+        assert_eq!(file.pread(40, u64::MAX).await?, &data[40..50]);
+        fs.set_write_back(Some(WriteBackPolicy::default())).await?;
+        file.pwrite(50, b"tail").await?;
+        assert_eq!(
+            file.pread(48, 10).await?,
+            [&data[48..], b"tail".as_slice()].concat()
+        );
+        assert_eq!(file.pread(54, 10).await?, b"");
 
-        // Create directory entry
-        conn.execute(
-            "INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)",
-            (name.as_str(), parent_ino, ino),
-        )
-        .await?;
+        Ok(())
+    }
 
-        // Increment link count
-        conn.execute(
-            "UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?",
-            (ino,),
-        )
-        .await?;
+    #[tokio::test]
+    async fn test_pread_nonexistent_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Populate dentry cache
-        self.dentry_cache.insert(parent_ino, name, ino);
+        let result = fs.pread("/nonexistent.txt", 0, 10).await?;
+        assert!(result.is_none());
 
         Ok(())
     }
 
-    /// Create a hard link
-    ///
-    /// Creates a new directory entry `newpath` that refers to the same inode as `oldpath`.
-    /// Both paths will share the same file data and metadata (except for the name).
-    /// The link count (nlink) of the inode is incremented.
-    pub async fn link(&self, oldpath: &str, newpath: &str) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let oldpath = self.normalize_path(oldpath);
-        let newpath = self.normalize_path(newpath);
-        let components = self.split_path(&newpath);
+    #[tokio::test]
+    async fn test_pread_across_chunks() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size();
 
-        if components.is_empty() {
-            return Err(FsError::RootOperation.into());
-        }
+        // Create data spanning multiple chunks
+        let data: Vec<u8> = (0..(chunk_size * 3)).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        // Resolve old path to get its inode
-        let ino = self
-            .resolve_path_with_conn(&conn, &oldpath)
+        // Read across chunk boundary
+        let start = chunk_size - 10;
+        let result = fs.pread("/test.txt", start as u64, 20).await?.unwrap();
+        assert_eq!(result, &data[start..start + 20]);
+
+        // Read spanning multiple chunks
+        let start = chunk_size / 2;
+        let size = chunk_size * 2;
+        let result = fs
+            .pread("/test.txt", start as u64, size as u64)
             .await?
-            .ok_or(FsError::NotFound)?;
+            .unwrap();
+        assert_eq!(result, &data[start..start + size]);
 
-        // Check if source is a directory (hard links to directories are not allowed)
-        let mut rows = conn
-            .query("SELECT mode FROM fs_inode WHERE ino = ?", (ino,))
-            .await?;
+        Ok(())
+    }
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+    #[tokio::test]
+    async fn test_pwrite_basic() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-            if (mode & S_IFMT) == super::S_IFDIR {
-                return Err(FsError::IsADirectory.into());
-            }
-        } else {
-            return Err(FsError::NotFound.into());
-        }
+        // Write initial data
+        let data: Vec<u8> = vec![0; 100];
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        // Get parent directory of new path
-        let parent_path = if components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!("/{}", components[..components.len() - 1].join("/"))
-        };
+        // Overwrite in the middle
+        fs.pwrite("/test.txt", 50, &[1, 2, 3, 4, 5]).await?;
 
-        let parent_ino = self
-            .resolve_path_with_conn(&conn, &parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert_eq!(result.len(), 100);
+        assert_eq!(&result[50..55], &[1, 2, 3, 4, 5]);
+        assert_eq!(&result[0..50], &vec![0u8; 50][..]);
+        assert_eq!(&result[55..100], &vec![0u8; 45][..]);
 
-        let name = components.last().unwrap();
+        Ok(())
+    }
 
-        // Check if new path already exists (single query using parent_ino we already have)
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+    #[tokio::test]
+    async fn test_pwrite_extend_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Create directory entry pointing to the same inode
-        conn.execute(
-            "INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)",
-            (name.as_str(), parent_ino, ino),
-        )
-        .await?;
+        // Write initial data
+        let data: Vec<u8> = vec![1; 50];
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
+
+        // Write past EOF - should extend with zeros
+        fs.pwrite("/test.txt", 100, &[2, 2, 2, 2, 2]).await?;
+
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert_eq!(result.len(), 105);
+        assert_eq!(&result[0..50], &vec![1u8; 50][..]);
+        assert_eq!(&result[50..100], &vec![0u8; 50][..]);
+        assert_eq!(&result[100..105], &[2, 2, 2, 2, 2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pwrite_creates_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+
+        // pwrite to a non-existent file should create it
+        fs.pwrite("/new.txt", 0, &[1, 2, 3]).await?;
+
+        let result = fs.read_file("/new.txt").await?.unwrap();
+        assert_eq!(result, &[1, 2, 3]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pwrite_across_chunks() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size();
+
+        // Create initial data spanning multiple chunks
+        let data: Vec<u8> = vec![0; chunk_size * 3];
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
+
+        // Write across chunk boundary
+        let write_data: Vec<u8> = (0..20).collect();
+        let start = chunk_size - 10;
+        fs.pwrite("/test.txt", start as u64, &write_data).await?;
 
-        // Increment link count
-        conn.execute(
-            "UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?",
-            (ino,),
-        )
-        .await?;
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert_eq!(&result[start..start + 20], &write_data[..]);
 
-        // Populate dentry cache
-        self.dentry_cache.insert(parent_ino, name, ino);
+        // Verify surrounding data is unchanged
+        assert_eq!(&result[0..start], &vec![0u8; start][..]);
+        assert_eq!(
+            &result[start + 20..],
+            &vec![0u8; chunk_size * 3 - start - 20][..]
+        );
 
         Ok(())
     }
 
-    /// Read the target of a symbolic link
-    pub async fn readlink(&self, path: &str) -> Result<Option<String>> {
-        let conn = self.pool.get_connection().await?;
-        self.readlink_with_conn(&conn, path).await
-    }
-
-    /// Read the target of a symbolic link using a provided connection
-    async fn readlink_with_conn(&self, conn: &Connection, path: &str) -> Result<Option<String>> {
-        let path = self.normalize_path(path);
-
-        let ino = match self.resolve_path_with_conn(conn, &path).await? {
-            Some(ino) => ino,
-            None => return Ok(None),
-        };
+    #[tokio::test]
+    async fn test_pread_pwrite_roundtrip() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size();
 
-        // Check if it's a symlink by querying the inode
-        let mut rows = conn
-            .query("SELECT mode FROM fs_inode WHERE ino = ?", (ino,))
-            .await?;
+        // Create a file
+        let initial: Vec<u8> = (0..(chunk_size * 2)).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &initial).await?;
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+        // Write some data at various offsets
+        let patches = vec![
+            (0u64, vec![0xAAu8; 10]),
+            (chunk_size as u64 - 5, vec![0xBB; 10]),
+            (chunk_size as u64 * 2 - 1, vec![0xCC; 1]),
+        ];
 
-            // Check if it's a symlink
-            if (mode & S_IFMT) != S_IFLNK {
-                return Err(FsError::NotASymlink.into());
-            }
-        } else {
-            return Ok(None);
+        for (offset, data) in &patches {
+            fs.pwrite("/test.txt", *offset, data).await?;
         }
 
-        // Read target from fs_symlink table
-        let mut rows = conn
-            .query("SELECT target FROM fs_symlink WHERE ino = ?", (ino,))
-            .await?;
-
-        if let Some(row) = rows.next().await? {
-            let target = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| match v {
-                    Value::Text(s) => Some(s.to_string()),
-                    _ => None,
-                })
-                .ok_or(FsError::InvalidPath)?;
-            Ok(Some(target))
-        } else {
-            Ok(None)
+        // Verify with pread
+        for (offset, expected) in &patches {
+            let result = fs
+                .pread("/test.txt", *offset, expected.len() as u64)
+                .await?
+                .unwrap();
+            assert_eq!(&result, expected);
         }
-    }
 
-    /// Remove a file or empty directory
-    pub async fn remove(&self, path: &str) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let path = self.normalize_path(path);
-        let components = self.split_path(&path);
+        Ok(())
+    }
 
-        if components.is_empty() {
-            return Err(FsError::RootOperation.into());
-        }
+    // ─────────────────────────────────────────────────────────────
+    // Truncate Tests
+    // ─────────────────────────────────────────────────────────────
 
-        let ino = self
-            .resolve_path_with_conn(&conn, &path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+    #[tokio::test]
+    async fn test_truncate_to_zero() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if ino == ROOT_INO {
-            return Err(FsError::RootOperation.into());
-        }
+        // Create a file with some data
+        let data: Vec<u8> = (0..100).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        // Get stats to check if it's a directory
-        let stats = self
-            .stat_with_conn(&conn, &path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        // Truncate to zero
+        fs.truncate("/test.txt", 0).await?;
 
-        // Check if directory is empty
-        let mut stmt = conn
-            .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        // Verify file is empty
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert!(result.is_empty());
 
-        if let Some(row) = rows.next().await? {
-            let count = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0);
-            if count > 0 {
-                return Err(FsError::NotEmpty.into());
-            }
-        }
+        // Verify stat shows size 0
+        let stats = fs.stat("/test.txt").await?.unwrap();
+        assert_eq!(stats.size, 0);
 
-        // Get parent directory and name
-        let parent_path = if components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!("/{}", components[..components.len() - 1].join("/"))
-        };
+        Ok(())
+    }
 
-        let parent_ino = self
-            .resolve_path_with_conn(&conn, &parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+    #[tokio::test]
+    async fn test_truncate_smaller_within_chunk() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        let name = components.last().unwrap();
+        // Create a file smaller than chunk size
+        let data: Vec<u8> = (0..100).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        // Delete the specific directory entry (not all entries pointing to this inode)
-        let mut stmt = conn
-            .prepare_cached("DELETE FROM fs_dentry WHERE parent_ino = ? AND name = ?")
-            .await?;
-        stmt.execute((parent_ino, name.as_str())).await?;
+        // Truncate to 50 bytes
+        fs.truncate("/test.txt", 50).await?;
 
-        // Invalidate cache for this entry
-        self.dentry_cache.remove(parent_ino, name);
+        // Verify data is truncated correctly
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert_eq!(result.len(), 50);
+        assert_eq!(result, &data[..50]);
 
-        // Decrement link count
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
-            .await?;
-        stmt.execute((ino,)).await?;
+        Ok(())
+    }
 
-        // If removing a directory, decrement parent nlink (removed dir's ".." link)
-        if stats.is_directory() {
-            let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-            let now_secs = dur.as_secs() as i64;
-            let now_nsec = dur.subsec_nanos() as i64;
-            let mut stmt = conn
-                .prepare_cached(
-                    "UPDATE fs_inode SET nlink = nlink - 1, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
-                )
-                .await?;
-            stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
-                .await?;
-        }
+    #[tokio::test]
+    async fn test_truncate_across_chunk_boundary() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size();
 
-        // Check if this was the last link to the inode
-        let link_count = self.get_link_count(&conn, ino).await?;
-        if link_count == 0 {
-            // Manually handle cascading deletes since we don't use foreign keys
-            // Delete data blocks
-            let mut stmt = conn
-                .prepare_cached("DELETE FROM fs_data WHERE ino = ?")
-                .await?;
-            stmt.execute((ino,)).await?;
+        // Create a file spanning multiple chunks
+        let data: Vec<u8> = (0..(chunk_size * 3)).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-            // Delete symlink if exists
-            let mut stmt = conn
-                .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
-                .await?;
-            stmt.execute((ino,)).await?;
+        // Truncate to middle of second chunk
+        let new_size = chunk_size + chunk_size / 2;
+        fs.truncate("/test.txt", new_size as u64).await?;
 
-            // Delete inode
-            let mut stmt = conn
-                .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
-                .await?;
-            stmt.execute((ino,)).await?;
-        }
+        // Verify data
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert_eq!(result.len(), new_size);
+        assert_eq!(result, &data[..new_size]);
 
         Ok(())
     }
 
-    /// Change file ownership
-    ///
-    /// Changes the user and/or group ownership of a file.
-    /// Pass None for uid or gid to leave that value unchanged.
-    pub async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
-        if uid.is_none() && gid.is_none() {
-            return Ok(());
-        }
+    #[tokio::test]
+    async fn test_truncate_extend_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        let conn = self.pool.get_connection().await?;
+        // Create a small file
+        let data: Vec<u8> = (0..50).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        // Build the update query dynamically based on which values are provided
-        let mut updates = Vec::new();
-        let mut values: Vec<Value> = Vec::new();
+        // Extend to 100 bytes
+        fs.truncate("/test.txt", 100).await?;
+
+        // Verify size increased
+        let stats = fs.stat("/test.txt").await?.unwrap();
+        assert_eq!(stats.size, 100);
+
+        // Original data should be preserved, rest should be zeros (sparse)
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert_eq!(result.len(), 100);
+        assert_eq!(&result[..50], &data[..]);
+
+        Ok(())
+    }
 
-        if let Some(uid) = uid {
-            updates.push("uid = ?");
-            values.push(Value::Integer(uid as i64));
-        }
-        if let Some(gid) = gid {
-            updates.push("gid = ?");
-            values.push(Value::Integer(gid as i64));
-        }
+    #[tokio::test]
+    async fn test_truncate_nonexistent_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        values.push(Value::Integer(ino));
-        let sql = format!("UPDATE fs_inode SET {} WHERE ino = ?", updates.join(", "));
-        conn.execute(&sql, values).await?;
+        // Truncate non-existent file should fail
+        let result = fs.truncate("/nonexistent.txt", 100).await;
+        assert!(result.is_err());
 
         Ok(())
     }
 
-    /// Rename/move a file or directory.
-    ///
-    /// This operation is atomic - either all changes succeed or none do.
-    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        let from_path = self.normalize_path(from);
-        let to_path = self.normalize_path(to);
+    #[tokio::test]
+    async fn test_truncate_at_chunk_boundary() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size();
 
-        // Cannot rename root
-        if from_path == "/" {
-            return Err(FsError::RootOperation.into());
-        }
+        // Create a file spanning multiple chunks
+        let data: Vec<u8> = (0..(chunk_size * 3)).map(|i| (i % 256) as u8).collect();
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &data).await?;
 
-        // Get source inode
-        let src_ino = self
-            .resolve_path_with_conn(&conn, &from_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        // Truncate exactly at chunk boundary
+        fs.truncate("/test.txt", chunk_size as u64).await?;
 
-        // Get source stats to check if it's a directory
-        let src_stats = self
-            .stat_with_conn(&conn, &from_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        // Verify
+        let result = fs.read_file("/test.txt").await?.unwrap();
+        assert_eq!(result.len(), chunk_size);
+        assert_eq!(result, &data[..chunk_size]);
 
-        // Prevent renaming a directory into its own subtree (would create a cycle)
-        if src_stats.is_directory() {
-            let from_prefix = format!("{}/", from_path);
-            if to_path.starts_with(&from_prefix) || to_path == from_path {
-                return Err(FsError::InvalidRename.into());
-            }
-        }
+        Ok(())
+    }
 
-        // Parse source path to get parent and name
-        let from_components = self.split_path(&from_path);
-        let src_name = from_components.last().ok_or(FsError::InvalidPath)?;
-        let src_parent_path = if from_components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!(
-                "/{}",
-                from_components[..from_components.len() - 1].join("/")
-            )
-        };
-        let src_parent_ino = self
-            .resolve_path_with_conn(&conn, &src_parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+    // ─────────────────────────────────────────────────────────────
+    // Rename Tests
+    // ─────────────────────────────────────────────────────────────
 
-        // Parse destination path to get parent and name
-        let to_components = self.split_path(&to_path);
-        if to_components.is_empty() {
-            return Err(FsError::RootOperation.into());
-        }
-        let dst_name = to_components.last().unwrap();
-        let dst_parent_path = if to_components.len() == 1 {
-            "/".to_string()
-        } else {
-            format!("/{}", to_components[..to_components.len() - 1].join("/"))
-        };
-        let dst_parent_ino = self
-            .resolve_path_with_conn(&conn, &dst_parent_path)
-            .await?
-            .ok_or(FsError::NotFound)?;
+    #[tokio::test]
+    async fn test_rename_file_same_directory() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Clone strings for use inside the transaction closure
-        let src_name = src_name.clone();
-        let dst_name = dst_name.clone();
+        // Create a file
+        let data = b"hello world";
+        let (_, file) = fs.create_file("/old.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, data).await?;
 
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
+        // Rename it
+        fs.rename("/old.txt", "/new.txt").await?;
 
-        let result: Result<()> = async {
-            // Check if destination exists (inside transaction for atomicity)
-            if let Some(dst_ino) = self.resolve_path_with_conn(&conn, &to_path).await? {
-                let dst_stats = self.stat_with_conn(&conn, &to_path).await?.ok_or(FsError::NotFound)?;
+        // Old path should not exist
+        assert!(fs.stat("/old.txt").await?.is_none());
 
-                // Can't replace directory with non-directory
-                if dst_stats.is_directory() && !src_stats.is_directory() {
-                    return Err(FsError::IsADirectory.into());
-                }
+        // New path should exist with same data
+        let result = fs.read_file("/new.txt").await?.unwrap();
+        assert_eq!(result, data);
 
-                // Can't replace non-directory with directory
-                if !dst_stats.is_directory() && src_stats.is_directory() {
-                    return Err(FsError::NotADirectory.into());
-                }
+        Ok(())
+    }
 
-                // If destination is directory, it must be empty
-                if dst_stats.is_directory() {
-                    let mut stmt = conn
-                        .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
-                        .await?;
-                    let mut rows = stmt.query((dst_ino,)).await?;
+    #[tokio::test]
+    async fn test_rename_file_to_different_directory() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-                    if let Some(row) = rows.next().await? {
-                        let count = row
-                            .get_value(0)
-                            .ok()
-                            .and_then(|v| v.as_integer().copied())
-                            .unwrap_or(0);
-                        if count > 0 {
-                            return Err(FsError::NotEmpty.into());
-                        }
-                    }
-                }
+        // Create directory and file
+        fs.mkdir("/subdir", 0, 0).await?;
+        let data = b"test data";
+        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, data).await?;
 
-                // Remove destination entry
-                let mut stmt = conn
-                    .prepare_cached("DELETE FROM fs_dentry WHERE parent_ino = ? AND name = ?")
-                    .await?;
-                stmt.execute((dst_parent_ino, dst_name.as_str())).await?;
+        // Move file to subdirectory
+        fs.rename("/file.txt", "/subdir/file.txt").await?;
 
-                // Decrement link count
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
-                    .await?;
-                stmt.execute((dst_ino,)).await?;
+        // Old path should not exist
+        assert!(fs.stat("/file.txt").await?.is_none());
 
-                // Clean up destination inode if no more links
-                let link_count = self.get_link_count(&conn, dst_ino).await?;
-                if link_count == 0 {
-                    let mut stmt = conn
-                        .prepare_cached("DELETE FROM fs_data WHERE ino = ?")
-                        .await?;
-                    stmt.execute((dst_ino,)).await?;
-                    let mut stmt = conn
-                        .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
-                        .await?;
-                    stmt.execute((dst_ino,)).await?;
-                    let mut stmt = conn
-                        .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
-                        .await?;
-                    stmt.execute((dst_ino,)).await?;
-                }
-            }
+        // New path should exist
+        let result = fs.read_file("/subdir/file.txt").await?.unwrap();
+        assert_eq!(result, data);
 
-            // Update the dentry: change parent and/or name
-            let mut stmt = conn
-                .prepare_cached(
-                    "UPDATE fs_dentry SET parent_ino = ?, name = ? WHERE parent_ino = ? AND name = ?",
-                )
-                .await?;
-            stmt.execute((
-                dst_parent_ino,
-                dst_name.as_str(),
-                src_parent_ino,
-                src_name.as_str(),
-            ))
-            .await?;
+        Ok(())
+    }
 
-            // If renaming a directory across parents, adjust parent nlink counts
-            if src_stats.is_directory() && src_parent_ino != dst_parent_ino {
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
-                    .await?;
-                stmt.execute((src_parent_ino,)).await?;
+    #[tokio::test]
+    async fn test_rename_overwrite_existing_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
-                    .await?;
-                stmt.execute((dst_parent_ino,)).await?;
-            }
+        // Create two files
+        let (_, file) = fs.create_file("/src.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"source").await?;
+        let (_, file) = fs.create_file("/dst.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"destination").await?;
 
-            // Update ctime of the inode
-            let dur = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default();
-            let now_secs = dur.as_secs() as i64;
-            let now_nsec = dur.subsec_nanos() as i64;
+        // Rename src to dst (overwrites dst)
+        fs.rename("/src.txt", "/dst.txt").await?;
 
-            let mut stmt = conn
-                .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
-                .await?;
-            stmt.execute((now_secs, now_nsec, src_ino)).await?;
+        // Only dst should exist with src's content
+        assert!(fs.stat("/src.txt").await?.is_none());
+        let result = fs.read_file("/dst.txt").await?.unwrap();
+        assert_eq!(result, b"source");
 
-            // Update source parent directory timestamps
-            let mut stmt = conn
-                .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
-                .await?;
-            stmt.execute((now_secs, now_secs, now_nsec, now_nsec, src_parent_ino)).await?;
+        Ok(())
+    }
 
-            // Update destination parent directory timestamps
-            if dst_parent_ino != src_parent_ino {
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
-                    .await?;
-                stmt.execute((now_secs, now_secs, now_nsec, now_nsec, dst_parent_ino)).await?;
-            }
+    #[tokio::test]
+    async fn test_rename_directory() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-            Ok(())
-        }
-        .await;
+        // Create directory with a file inside
+        fs.mkdir("/olddir", 0, 0).await?;
+        let (_, file) = fs
+            .create_file("/olddir/file.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"content").await?;
 
-        match result {
-            Ok(()) => {
-                txn.commit().await?;
+        // Rename directory
+        fs.rename("/olddir", "/newdir").await?;
 
-                // Invalidate cache for source and destination
-                self.dentry_cache.remove(src_parent_ino, &src_name);
-                self.dentry_cache.remove(dst_parent_ino, &dst_name);
+        // Old path should not exist
+        assert!(fs.stat("/olddir").await?.is_none());
 
-                // Add new entry to cache (source inode is now at destination)
-                self.dentry_cache.insert(dst_parent_ino, &dst_name, src_ino);
+        // New path should exist and contain the file
+        assert!(fs.stat("/newdir").await?.is_some());
+        let result = fs.read_file("/newdir/file.txt").await?.unwrap();
+        assert_eq!(result, b"content");
 
-                Ok(())
-            }
-            Err(e) => {
-                let _ = txn.rollback().await;
-                Err(e)
-            }
-        }
+        Ok(())
     }
 
-    /// Get filesystem statistics
-    ///
-    /// Returns the total number of inodes and bytes used by file contents.
-    pub async fn statfs(&self) -> Result<FilesystemStats> {
-        let conn = self.pool.get_connection().await?;
-        // Count total inodes
-        let mut stmt = conn.prepare_cached("SELECT COUNT(*) FROM fs_inode").await?;
-        let mut rows = stmt.query(()).await?;
+    #[tokio::test]
+    async fn test_rename_directory_into_own_subtree_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        let inodes = if let Some(row) = rows.next().await? {
-            row.get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64
-        } else {
-            0
-        };
+        // Create nested directories
+        fs.mkdir("/parent", 0, 0).await?;
+        fs.mkdir("/parent/child", 0, 0).await?;
 
-        // Sum total bytes used (from file sizes in inodes)
-        let mut stmt = conn
-            .prepare_cached("SELECT COALESCE(SUM(size), 0) FROM fs_inode")
-            .await?;
-        let mut rows = stmt.query(()).await?;
+        // Try to rename parent into its child - should fail
+        let result = fs.rename("/parent", "/parent/child/parent").await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::InvalidRename))
+        ));
 
-        let bytes_used = if let Some(row) = rows.next().await? {
-            row.get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u64
-        } else {
-            0
-        };
+        // Original structure should be intact
+        assert!(fs.stat("/parent").await?.is_some());
+        assert!(fs.stat("/parent/child").await?.is_some());
 
-        Ok(FilesystemStats { inodes, bytes_used })
+        Ok(())
     }
 
-    /// Synchronize file data to persistent storage
-    ///
-    /// Temporarily enables FULL synchronous mode, runs a transaction to force
-    /// a checkpoint, then restores OFF mode. This ensures durability while
-    /// maintaining high performance for normal operations.
-    ///
-    /// Note: The path parameter is ignored since all data is in a single database.
-    pub async fn fsync(&self, _path: &str) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
-        conn.prepare_cached("PRAGMA synchronous = FULL")
-            .await?
-            .execute(())
-            .await?;
-        conn.prepare_cached("BEGIN").await?.execute(()).await?;
-        conn.prepare_cached("COMMIT").await?.execute(()).await?;
-        conn.prepare_cached("PRAGMA synchronous = OFF")
-            .await?
-            .execute(())
-            .await?;
+    #[tokio::test]
+    async fn test_rename_root_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+
+        // Try to rename root - should fail
+        let result = fs.rename("/", "/newroot").await;
+        assert!(result.is_err());
+
         Ok(())
     }
 
-    /// Open a file and return a file handle.
-    ///
-    /// The returned handle can be used for efficient read/write/fsync operations
-    /// without requiring path lookups on each operation.
-    pub async fn open(&self, path: &str) -> Result<BoxedFile> {
-        let path = self.normalize_path(path);
-        let ino = self.resolve_path(&path).await?.ok_or(FsError::NotFound)?;
+    #[tokio::test]
+    async fn test_rename_to_root_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        Ok(Arc::new(AgentFSFile {
-            pool: self.pool.clone(),
-            ino,
-            chunk_size: self.chunk_size,
-        }))
-    }
+        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"data").await?;
 
-    /// Get the number of chunks for a given inode (for testing)
-    #[cfg(test)]
-    async fn get_chunk_count(&self, ino: i64) -> Result<i64> {
-        let conn = self.pool.get_connection().await?;
-        let mut rows = conn
-            .query("SELECT COUNT(*) FROM fs_data WHERE ino = ?", (ino,))
-            .await?;
+        // Try to rename to root - should fail
+        let result = fs.rename("/file.txt", "/").await;
+        assert!(result.is_err());
 
-        if let Some(row) = rows.next().await? {
-            Ok(row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0))
-        } else {
-            Ok(0)
-        }
+        Ok(())
     }
-}
 
-#[async_trait]
-impl FileSystem for AgentFS {
-    async fn lookup(&self, parent_ino: i64, name: &str) -> Result<Option<Stats>> {
-        if name.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_rename_nonexistent_source_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Handle ".." by finding the parent of parent_ino
-        if name == ".." {
-            if parent_ino == ROOT_INO {
-                // Root's parent is itself
-                return self.getattr_with_conn(&conn, ROOT_INO).await;
-            }
-            let mut stmt = conn
-                .prepare_cached("SELECT parent_ino FROM fs_dentry WHERE ino = ? LIMIT 1")
-                .await?;
-            let mut rows = stmt.query((parent_ino,)).await?;
-            let parent = if let Some(row) = rows.next().await? {
-                row.get_value(0)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(ROOT_INO)
-            } else {
-                ROOT_INO
-            };
-            return self.getattr_with_conn(&conn, parent).await;
-        }
+        // Try to rename non-existent file
+        let result = fs.rename("/nonexistent.txt", "/new.txt").await;
+        assert!(result.is_err());
 
-        // Look up the child inode
-        let child_ino = match self.lookup_child(&conn, parent_ino, name).await? {
-            Some(ino) => ino,
-            None => return Ok(None),
-        };
+        Ok(())
+    }
 
-        // Get stats for the child inode
-        let mut stmt = conn
-            .prepare_cached("SELECT ino, mode, nlink, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec FROM fs_inode WHERE ino = ?")
+    #[tokio::test]
+    async fn test_rename_overwrite_nonempty_directory_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+
+        // Create source directory and target directory with content
+        fs.mkdir("/src", 0, 0).await?;
+        fs.mkdir("/dst", 0, 0).await?;
+        let (_, file) = fs
+            .create_file("/dst/file.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
-        let mut rows = stmt.query((child_ino,)).await?;
+        file.pwrite(0, b"content").await?;
 
-        if let Some(row) = rows.next().await? {
-            let stats = Self::build_stats_from_row(&row)?;
-            // Cache the lookup result
-            self.dentry_cache.insert(parent_ino, name, child_ino);
-            Ok(Some(stats))
-        } else {
-            Ok(None)
-        }
-    }
+        // Try to rename src to dst (dst is not empty) - should fail
+        let result = fs.rename("/src", "/dst").await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotEmpty))
+        ));
 
-    async fn getattr(&self, ino: i64) -> Result<Option<Stats>> {
-        let conn = self.pool.get_connection().await?;
-        self.getattr_with_conn(&conn, ino).await
+        // Both directories should still exist
+        assert!(fs.stat("/src").await?.is_some());
+        assert!(fs.stat("/dst").await?.is_some());
+        assert!(fs.stat("/dst/file.txt").await?.is_some());
+
+        Ok(())
     }
 
-    async fn readlink(&self, ino: i64) -> Result<Option<String>> {
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_rename_file_to_directory_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Check if the inode exists and is a symlink
-        let mut stmt = conn
-            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        // Create a file and an empty directory
+        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"data").await?;
+        fs.mkdir("/dir", 0, 0).await?;
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+        // Try to rename file over directory - should fail
+        let result = fs.rename("/file.txt", "/dir").await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
 
-            if (mode & S_IFMT) != S_IFLNK {
-                return Err(FsError::NotASymlink.into());
-            }
-        } else {
-            return Ok(None);
-        }
+        Ok(())
+    }
 
-        // Read target from fs_symlink table
-        let mut stmt = conn
-            .prepare_cached("SELECT target FROM fs_symlink WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+    #[tokio::test]
+    async fn test_rename_directory_to_file_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if let Some(row) = rows.next().await? {
-            let target = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| match v {
-                    Value::Text(s) => Some(s.to_string()),
-                    _ => None,
-                })
-                .ok_or(FsError::InvalidPath)?;
-            Ok(Some(target))
-        } else {
-            Ok(None)
-        }
-    }
+        // Create a directory and a file
+        fs.mkdir("/dir", 0, 0).await?;
+        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"data").await?;
 
-    async fn readdir(&self, ino: i64) -> Result<Option<Vec<String>>> {
-        let conn = self.pool.get_connection().await?;
+        // Try to rename directory over file - should fail
+        let result = fs.rename("/dir", "/file.txt").await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotADirectory))
+        ));
 
-        // Check if inode exists and is a directory
-        let mut stmt = conn
-            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rename_directory_onto_empty_directory() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/new", 0, 0).await?;
+        fs.pwrite("/new/file.txt", 0, b"new").await?;
+        fs.mkdir("/current", 0, 0).await?;
+        let new_ino = fs.lstat("/new").await?.unwrap().ino;
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+        // An empty directory is replaced, as in an atomic directory swap
+        fs.rename("/new", "/current").await?;
+        assert!(fs.lstat("/new").await?.is_none());
+        assert_eq!(fs.lstat("/current").await?.unwrap().ino, new_ino);
+        assert_eq!(fs.read_file("/current/file.txt").await?.unwrap(), b"new");
 
-            if (mode & S_IFMT) != super::S_IFDIR {
-                return Err(FsError::NotADirectory.into());
-            }
-        } else {
-            return Ok(None);
-        }
+        // Renaming a directory onto itself does nothing
+        fs.rename("/current", "/current").await?;
+        assert_eq!(fs.lstat("/current").await?.unwrap().ino, new_ino);
 
-        let mut stmt = conn
-            .prepare_cached("SELECT name FROM fs_dentry WHERE parent_ino = ? ORDER BY name")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        Ok(())
+    }
 
-        let mut entries = Vec::new();
-        while let Some(row) = rows.next().await? {
-            let name = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| {
-                    if let Value::Text(s) = v {
-                        Some(s.clone())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default();
-            if !name.is_empty() {
-                entries.push(name);
-            }
+    #[tokio::test]
+    async fn test_rename_by_inode_checks_destination() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/a", 0, 0).await?;
+        fs.mkdir("/a/b", 0, 0).await?;
+        fs.mkdir("/full", 0, 0).await?;
+        fs.pwrite("/full/file.txt", 0, b"data").await?;
+        fs.pwrite("/file.txt", 0, b"data").await?;
+        let a_ino = fs.lstat("/a").await?.unwrap().ino;
+        let b_ino = fs.lstat("/a/b").await?.unwrap().ino;
+
+        let cases = [
+            (ROOT_INO, "file.txt", ROOT_INO, "a", libc::EISDIR),
+            (ROOT_INO, "a", ROOT_INO, "file.txt", libc::ENOTDIR),
+            (a_ino, "b", ROOT_INO, "full", libc::ENOTEMPTY),
+            (ROOT_INO, "a", b_ino, "a", libc::EINVAL),
+            (ROOT_INO, "a", a_ino, "a", libc::EINVAL),
+        ];
+        for (oldparent, oldname, newparent, newname, errno) in cases {
+            let result = FileSystem::rename(&fs, oldparent, oldname, newparent, newname, 0).await;
+            assert_eq!(
+                result.unwrap_err().to_errno(),
+                errno,
+                "{oldname} -> {newname}"
+            );
         }
 
-        Ok(Some(entries))
+        // Nothing moved
+        assert!(fs.lstat("/a/b").await?.unwrap().is_directory());
+        assert!(fs.lstat("/file.txt").await?.unwrap().is_file());
+        assert!(fs.lstat("/full/file.txt").await?.is_some());
+
+        Ok(())
     }
 
-    async fn readdir_plus(&self, ino: i64) -> Result<Option<Vec<DirEntry>>> {
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_rename_symlink_to_directory_moves_link() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.symlink("/dir", "/link", 0, 0).await?;
+        fs.pwrite("/file.txt", 0, b"data").await?;
 
-        // Check if inode exists and is a directory
-        let mut stmt = conn
-            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        // The link is not a directory, so it may replace a file and may
+        // point into its own target
+        fs.rename("/link", "/file.txt").await?;
+        assert!(fs.lstat("/file.txt").await?.unwrap().is_symlink());
+        fs.rename("/file.txt", "/dir/link").await?;
+        assert_eq!(fs.readlink("/dir/link").await?.unwrap(), "/dir");
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+        Ok(())
+    }
 
-            if (mode & S_IFMT) != super::S_IFDIR {
-                return Err(FsError::NotADirectory.into());
-            }
-        } else {
-            return Ok(None);
-        }
+    #[tokio::test]
+    async fn test_rename_updates_ctime() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        let mut stmt = conn.prepare_cached("SELECT d.name, i.ino, i.mode, i.nlink, i.uid, i.gid, i.size, i.atime, i.mtime, i.ctime, i.rdev, i.atime_nsec, i.mtime_nsec, i.ctime_nsec
-            FROM fs_dentry d
-            JOIN fs_inode i ON d.ino = i.ino
-            WHERE d.parent_ino = ?
-            ORDER BY d.name"
-        ).await?;
-        let mut rows = stmt.query((ino,)).await?;
+        // Create a file
+        let (_, file) = fs.create_file("/old.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"data").await?;
+        let stats_before = fs.stat("/old.txt").await?.unwrap();
 
-        let mut entries = Vec::new();
-        while let Some(row) = rows.next().await? {
-            let name = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| {
-                    if let Value::Text(s) = v {
-                        Some(s.clone())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default();
+        // Small delay to ensure time changes
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
 
-            if name.is_empty() {
-                continue;
-            }
+        // Rename it
+        fs.rename("/old.txt", "/new.txt").await?;
 
-            let entry_ino = row
-                .get_value(1)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0);
+        // ctime should be updated
+        let stats_after = fs.stat("/new.txt").await?.unwrap();
+        assert!(stats_after.ctime >= stats_before.ctime);
 
-            let stats = Stats {
-                ino: entry_ino,
-                mode: row
-                    .get_value(2)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                nlink: row
-                    .get_value(3)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(1) as u32,
-                uid: row
-                    .get_value(4)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                gid: row
-                    .get_value(5)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                size: row
-                    .get_value(6)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                atime: row
-                    .get_value(7)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                mtime: row
-                    .get_value(8)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                ctime: row
-                    .get_value(9)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0),
-                atime_nsec: row
-                    .get_value(11)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                mtime_nsec: row
-                    .get_value(12)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                ctime_nsec: row
-                    .get_value(13)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u32,
-                rdev: row
-                    .get_value(10)
-                    .ok()
-                    .and_then(|v| v.as_integer().copied())
-                    .unwrap_or(0) as u64,
-            };
+        Ok(())
+    }
 
-            entries.push(DirEntry { name, stats });
-        }
+    #[tokio::test]
+    async fn test_chmod_regular_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        Ok(Some(entries))
-    }
+        // Create a file with default permissions
+        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"content").await?;
 
-    async fn chmod(&self, ino: i64, mode: u32) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
+        let stats = fs.stat("/test.txt").await?.unwrap();
+        let ino = stats.ino;
+        assert_eq!(
+            stats.mode & 0o7777,
+            0o644,
+            "Default file mode should be 0o644"
+        );
 
-        // Get current mode to preserve file type bits
-        let mut stmt = conn
-            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        // Change to executable
+        fs.chmod(ino, 0o755).await?;
 
-        let current_mode = if let Some(row) = rows.next().await? {
-            row.get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32
-        } else {
-            return Err(FsError::NotFound.into());
-        };
+        let stats = fs.stat("/test.txt").await?.unwrap();
+        assert_eq!(
+            stats.mode & 0o7777,
+            0o755,
+            "Mode should be 0o755 after chmod"
+        );
+        assert!(stats.is_file(), "Should still be a regular file");
 
-        // Preserve file type bits (upper bits), replace permission bits (lower 12 bits)
-        let new_mode = (current_mode & S_IFMT) | (mode & 0o7777);
+        // Change to read-only
+        fs.chmod(ino, 0o444).await?;
 
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET mode = ?, ctime = ?, ctime_nsec = ? WHERE ino = ?")
-            .await?;
-        stmt.execute((new_mode as i64, now_secs, now_nsec, ino))
-            .await?;
+        let stats = fs.stat("/test.txt").await?.unwrap();
+        assert_eq!(
+            stats.mode & 0o7777,
+            0o444,
+            "Mode should be 0o444 after chmod"
+        );
 
         Ok(())
     }
 
-    async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
-        if uid.is_none() && gid.is_none() {
-            return Ok(());
-        }
-
-        let conn = self.pool.get_connection().await?;
-
-        // Verify inode exists
-        let mut stmt = conn
-            .prepare_cached("SELECT ino FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+    #[tokio::test]
+    async fn test_chmod_preserves_file_type() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if rows.next().await?.is_none() {
-            return Err(FsError::NotFound.into());
-        }
+        // Create a regular file
+        let (file_stats, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"content").await?;
+        fs.chmod(file_stats.ino, 0o755).await?;
+        let stats = fs.stat("/file.txt").await?.unwrap();
+        assert!(stats.is_file(), "Should remain a regular file after chmod");
 
-        // Build the update query dynamically based on which values are provided
-        let mut updates = Vec::new();
-        let mut values: Vec<Value> = Vec::new();
+        // Create a directory
+        fs.mkdir("/dir", 0, 0).await?;
+        let dir_stats = fs.stat("/dir").await?.unwrap();
+        fs.chmod(dir_stats.ino, 0o700).await?;
+        let stats = fs.stat("/dir").await?.unwrap();
+        assert!(
+            stats.is_directory(),
+            "Should remain a directory after chmod"
+        );
+        assert_eq!(stats.mode & 0o7777, 0o700, "Directory mode should be 0o700");
 
-        if let Some(uid) = uid {
-            updates.push("uid = ?");
-            values.push(Value::Integer(uid as i64));
-        }
-        if let Some(gid) = gid {
-            updates.push("gid = ?");
-            values.push(Value::Integer(gid as i64));
-        }
+        Ok(())
+    }
 
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        updates.push("ctime = ?");
-        values.push(Value::Integer(now_secs));
-        updates.push("ctime_nsec = ?");
-        values.push(Value::Integer(now_nsec));
+    #[tokio::test]
+    async fn test_chmod_nonexistent_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        values.push(Value::Integer(ino));
-        let sql = format!("UPDATE fs_inode SET {} WHERE ino = ?", updates.join(", "));
-        conn.execute(&sql, values).await?;
+        // Use a non-existent inode
+        let result = fs.chmod(999999, 0o755).await;
+        assert!(result.is_err(), "chmod on nonexistent inode should fail");
 
         Ok(())
     }
 
-    async fn utimens(&self, ino: i64, atime: TimeChange, mtime: TimeChange) -> Result<()> {
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_chmod_symlink() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Verify inode exists
-        let mut stmt = conn
-            .prepare_cached("SELECT ino FROM fs_inode WHERE ino = ?")
+        // Create target and symlink
+        let (_, file) = fs
+            .create_file("/target.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
-        let mut rows = stmt.query((ino,)).await?;
-        if rows.next().await?.is_none() {
-            return Err(FsError::NotFound.into());
-        }
+        file.pwrite(0, b"content").await?;
+        fs.symlink("/target.txt", "/link.txt", 0, 0).await?;
+        let link_stats = fs.lstat("/link.txt").await?.unwrap();
 
-        let mut updates = Vec::new();
-        let mut values: Vec<Value> = Vec::new();
+        // chmod the symlink (should work on the symlink inode)
+        fs.chmod(link_stats.ino, 0o755).await?;
 
-        let resolve = |tc: TimeChange| -> (i64, i64) {
-            match tc {
-                TimeChange::Set(secs, nsec) => (secs, nsec as i64),
-                TimeChange::Now => {
-                    let dur = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-                    (dur.as_secs() as i64, dur.subsec_nanos() as i64)
-                }
-                TimeChange::Omit => unreachable!(),
-            }
-        };
+        let stats = fs.lstat("/link.txt").await?.unwrap();
+        assert!(stats.is_symlink(), "Should still be a symlink");
 
-        if !matches!(atime, TimeChange::Omit) {
-            let (secs, nsec) = resolve(atime);
-            updates.push("atime = ?");
-            values.push(Value::Integer(secs));
-            updates.push("atime_nsec = ?");
-            values.push(Value::Integer(nsec));
-        }
+        Ok(())
+    }
 
-        if !matches!(mtime, TimeChange::Omit) {
-            let (secs, nsec) = resolve(mtime);
-            updates.push("mtime = ?");
-            values.push(Value::Integer(secs));
-            updates.push("mtime_nsec = ?");
-            values.push(Value::Integer(nsec));
-        }
+    #[tokio::test]
+    async fn test_chown_unchanged_sentinel() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        if updates.is_empty() {
-            return Ok(());
-        }
+        let (stats, _) = fs
+            .create_file("/owned.txt", DEFAULT_FILE_MODE, 1000, 1000)
+            .await?;
 
-        // Also update ctime
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        updates.push("ctime = ?");
-        values.push(Value::Integer(dur.as_secs() as i64));
-        updates.push("ctime_nsec = ?");
-        values.push(Value::Integer(dur.subsec_nanos() as i64));
+        // u32::MAX means "don't change" for chown(2)
+        FileSystem::chown(&fs, stats.ino, Some(2000), Some(OWNER_UNCHANGED)).await?;
+        let stats = fs.lstat("/owned.txt").await?.unwrap();
+        assert_eq!(stats.uid, 2000);
+        assert_eq!(stats.gid, 1000, "gid should be untouched");
 
-        values.push(Value::Integer(ino));
-        let sql = format!("UPDATE fs_inode SET {} WHERE ino = ?", updates.join(", "));
-        conn.execute(&sql, values).await?;
+        FileSystem::chown(&fs, stats.ino, Some(OWNER_UNCHANGED), Some(3000)).await?;
+        let stats = fs.lstat("/owned.txt").await?.unwrap();
+        assert_eq!(stats.uid, 2000, "uid should be untouched");
+        assert_eq!(stats.gid, 3000);
 
         Ok(())
     }
 
-    async fn open(&self, ino: i64, _flags: i32) -> Result<BoxedFile> {
-        let conn = self.pool.get_connection().await?;
+    // ==================== Timestamp Tests ====================
 
-        // Verify inode exists
-        let mut stmt = conn
-            .prepare_cached("SELECT ino FROM fs_inode WHERE ino = ?")
+    #[tokio::test]
+    async fn test_utimens_sets_exact_times() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs
+            .create_file("/times.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
-        let mut rows = stmt.query((ino,)).await?;
-
-        if rows.next().await?.is_none() {
-            return Err(FsError::NotFound.into());
-        }
 
-        Ok(Arc::new(AgentFSFile {
-            pool: self.pool.clone(),
-            ino,
-            chunk_size: self.chunk_size,
-        }))
-    }
+        fs.utimens(
+            stats.ino,
+            TimeChange::Set(1_000_000_000, 123_456_789),
+            TimeChange::Set(1_500_000_000, 987_654_321),
+        )
+        .await?;
 
-    async fn mkdir(
-        &self,
-        parent_ino: i64,
-        name: &str,
-        mode: u32,
-        uid: u32,
-        gid: u32,
-    ) -> Result<Stats> {
-        if name.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+        let stats = fs.lstat("/times.txt").await?.unwrap();
+        assert_eq!(
+            (stats.atime, stats.atime_nsec),
+            (1_000_000_000, 123_456_789)
+        );
+        assert_eq!(
+            (stats.mtime, stats.mtime_nsec),
+            (1_500_000_000, 987_654_321)
+        );
 
-        // Check if already exists
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+        Ok(())
+    }
 
-        // Create inode
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mut stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
-                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
+    #[tokio::test]
+    async fn test_utimens_omit_leaves_time_unchanged() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs
+            .create_file("/times.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
-        let dir_mode = super::S_IFDIR | (mode & 0o7777);
-        let row = stmt
-            .query_row((
-                dir_mode as i64,
-                uid,
-                gid,
-                now_secs,
-                now_secs,
-                now_secs,
-                now_nsec,
-                now_nsec,
-                now_nsec,
-            ))
+        fs.utimens(stats.ino, TimeChange::Set(100, 1), TimeChange::Set(200, 2))
             .await?;
 
-        let ino = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| v.as_integer().copied())
-            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
-
-        // Create directory entry
-        let mut stmt = conn
-            .prepare_cached("INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)")
+        // Only touch mtime
+        fs.utimens(stats.ino, TimeChange::Omit, TimeChange::Set(300, 3))
             .await?;
-        stmt.execute((name, parent_ino, ino)).await?;
+        let after = fs.lstat("/times.txt").await?.unwrap();
+        assert_eq!((after.atime, after.atime_nsec), (100, 1));
+        assert_eq!((after.mtime, after.mtime_nsec), (300, 3));
 
-        // Set nlink to 2 for new directory (self "." + parent's dentry)
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET nlink = 2 WHERE ino = ?")
+        // "Now" moves atime forward
+        fs.utimens(stats.ino, TimeChange::Now, TimeChange::Omit)
             .await?;
-        stmt.execute((ino,)).await?;
+        let after = fs.lstat("/times.txt").await?.unwrap();
+        assert!(after.atime > 100);
+        assert_eq!((after.mtime, after.mtime_nsec), (300, 3));
 
-        // Increment parent nlink (new directory's ".." link) and update timestamps
-        let mut stmt = conn
-            .prepare_cached(
-                "UPDATE fs_inode SET nlink = nlink + 1, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
-            )
-            .await?;
-        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
-            .await?;
+        Ok(())
+    }
 
-        // Populate dentry cache
-        self.dentry_cache.insert(parent_ino, name, ino);
+    // ==================== Hard Link Tests ====================
 
-        Ok(Stats {
-            ino,
-            mode: dir_mode,
-            nlink: 2,
-            uid,
-            gid,
-            size: 0,
-            atime: now_secs,
-            mtime: now_secs,
-            ctime: now_secs,
-            atime_nsec: now_nsec as u32,
-            mtime_nsec: now_nsec as u32,
-            ctime_nsec: now_nsec as u32,
-            rdev: 0,
-        })
-    }
+    #[tokio::test]
+    async fn test_link_increments_nlink() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, file) = fs.create_file("/orig.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"hello").await?;
 
-    async fn create_file(
-        &self,
-        parent_ino: i64,
-        name: &str,
-        mode: u32,
-        uid: u32,
-        gid: u32,
-    ) -> Result<(Stats, BoxedFile)> {
-        if name.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+        fs.link("/orig.txt", "/alias.txt").await?;
 
-        // Check if already exists
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+        let orig = fs.lstat("/orig.txt").await?.unwrap();
+        let alias = fs.lstat("/alias.txt").await?.unwrap();
+        assert_eq!(orig.ino, stats.ino);
+        assert_eq!(alias.ino, stats.ino, "link should share the inode");
+        assert_eq!(alias.nlink, 2);
 
-        // Prepare statements before starting the transaction
-        let mut inode_stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, nlink, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
-                 VALUES (?, 1, ?, ?, 0, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
-            .await?;
-        let mut dentry_stmt = conn
-            .prepare_cached("INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)")
-            .await?;
+        Ok(())
+    }
 
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
+    #[tokio::test]
+    async fn test_link_data_survives_until_last_unlink() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, file) = fs.create_file("/orig.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"hello").await?;
+        fs.link("/orig.txt", "/alias.txt").await?;
 
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let file_mode = S_IFREG | (mode & 0o7777);
+        fs.remove("/orig.txt").await?;
+        let alias = fs.lstat("/alias.txt").await?.unwrap();
+        assert_eq!(alias.nlink, 1);
+        assert_eq!(fs.read_file("/alias.txt").await?.unwrap(), b"hello");
 
-        let row = inode_stmt
-            .query_row((
-                file_mode as i64,
-                uid,
-                gid,
-                now_secs,
-                now_secs,
-                now_secs,
-                now_nsec,
-                now_nsec,
-                now_nsec,
-            ))
+        fs.remove("/alias.txt").await?;
+        assert!(
+            fs.getattr(stats.ino).await?.is_none(),
+            "inode should be freed"
+        );
+
+        Ok(())
+    }
+
+    // ==================== Inode Numbering Tests ====================
+
+    #[tokio::test]
+    async fn test_inode_numbers_are_not_reused() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        // Deleting the newest inode is what would free its number for reuse
+        fs.pwrite("/a", 0, b"a").await?;
+        let a = fs.lstat("/a").await?.unwrap().ino;
+        fs.remove("/a").await?;
+        fs.pwrite("/b", 0, b"b").await?;
+        let b = fs.lstat("/b").await?.unwrap().ino;
+        assert!(b > a, "inode {a} was reused as {b}");
+
+        fs.mkdir("/d", 0, 0).await?;
+        let d = fs.lstat("/d").await?.unwrap().ino;
+        fs.remove("/d").await?;
+        fs.mkdir("/e", 0, 0).await?;
+        let e = fs.lstat("/e").await?.unwrap().ino;
+        assert!(e > d, "inode {d} was reused as {e}");
+
+        // Nor after a restore discards newer inodes
+        fs.snapshot("before").await?;
+        fs.pwrite("/c", 0, b"c").await?;
+        let c = fs.lstat("/c").await?.unwrap().ino;
+        fs.restore("before").await?;
+        fs.pwrite("/f", 0, b"f").await?;
+        let f = fs.lstat("/f").await?.unwrap().ino;
+        assert!(f > c, "inode {c} was reused as {f}");
+
+        // Nor once the database is reopened
+        fs.remove("/f").await?;
+        drop(fs);
+        let fs = AgentFS::new(dir.path().join("test.db").to_str().unwrap()).await?;
+        fs.pwrite("/g", 0, b"g").await?;
+        let g = fs.lstat("/g").await?.unwrap().ino;
+        assert!(g > f, "inode {f} was reused as {g}");
+
+        Ok(())
+    }
+
+    // ==================== Extended Attribute Tests ====================
+
+    #[tokio::test]
+    async fn test_xattr_set_get_list_remove() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs
+            .create_file("/attrs.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
+        let ino = stats.ino;
 
-        let ino = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| v.as_integer().copied())
-            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+        assert!(fs.getxattr(ino, "user.missing").await?.is_none());
 
-        dentry_stmt.execute((name, parent_ino, ino)).await?;
+        fs.setxattr(ino, "user.b", b"second", 0).await?;
+        fs.setxattr(ino, "user.a", b"first", 0).await?;
+        assert_eq!(fs.getxattr(ino, "user.a").await?, Some(b"first".to_vec()));
+        assert_eq!(fs.listxattr(ino).await?, vec!["user.a", "user.b"]);
 
-        // Update parent directory ctime and mtime
-        conn.execute(
-            "UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
-            (now_secs, now_secs, now_nsec, now_nsec, parent_ino),
-        )
-        .await?;
+        fs.removexattr(ino, "user.a").await?;
+        assert!(fs.getxattr(ino, "user.a").await?.is_none());
+        assert_eq!(fs.listxattr(ino).await?, vec!["user.b"]);
 
-        txn.commit().await?;
+        let err = fs.removexattr(ino, "user.a").await.unwrap_err();
+        assert!(matches!(err, Error::Fs(FsError::NoAttribute)));
 
-        self.dentry_cache.insert(parent_ino, name, ino);
+        Ok(())
+    }
 
-        let stats = Stats {
-            ino,
-            mode: file_mode,
-            nlink: 1,
-            uid,
-            gid,
-            size: 0,
-            atime: now_secs,
-            mtime: now_secs,
-            ctime: now_secs,
-            atime_nsec: now_nsec as u32,
-            mtime_nsec: now_nsec as u32,
-            ctime_nsec: now_nsec as u32,
-            rdev: 0,
-        };
+    #[tokio::test]
+    async fn test_xattr_create_replace_flags() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs
+            .create_file("/flags.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        let ino = stats.ino;
 
-        let file: BoxedFile = Arc::new(AgentFSFile {
-            pool: self.pool.clone(),
-            ino,
-            chunk_size: self.chunk_size,
-        });
+        let err = fs
+            .setxattr(ino, "user.k", b"v", XATTR_REPLACE)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Fs(FsError::NoAttribute)));
 
-        Ok((stats, file))
+        fs.setxattr(ino, "user.k", b"v1", XATTR_CREATE).await?;
+        let err = fs
+            .setxattr(ino, "user.k", b"v2", XATTR_CREATE)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Fs(FsError::AlreadyExists)));
+
+        fs.setxattr(ino, "user.k", b"v3", XATTR_REPLACE).await?;
+        assert_eq!(fs.getxattr(ino, "user.k").await?, Some(b"v3".to_vec()));
+
+        Ok(())
     }
 
-    async fn mknod(
-        &self,
-        parent_ino: i64,
-        name: &str,
-        mode: u32,
-        rdev: u64,
-        uid: u32,
-        gid: u32,
-    ) -> Result<Stats> {
-        if name.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_xattr_removed_with_inode() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs.create_file("/gone.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        fs.setxattr(stats.ino, "user.k", b"v", 0).await?;
 
-        // Check if already exists
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+        fs.remove("/gone.txt").await?;
 
-        // Create inode with mode and rdev
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mut stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, rdev, atime_nsec, mtime_nsec, ctime_nsec)
-                VALUES (?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
-            .await?;
-        let row = stmt
-            .query_row((
-                mode as i64,
-                uid,
-                gid,
-                now_secs,
-                now_secs,
-                now_secs,
-                rdev as i64,
-                now_nsec,
-                now_nsec,
-                now_nsec,
-            ))
+        let conn = fs.get_connection().await?;
+        let mut rows = conn
+            .query("SELECT COUNT(*) FROM fs_xattr WHERE ino = ?", (stats.ino,))
             .await?;
-
-        let ino = row
+        let row = rows.next().await?.unwrap();
+        let count = row
             .get_value(0)
             .ok()
             .and_then(|v| v.as_integer().copied())
-            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+            .unwrap_or(-1);
+        assert_eq!(count, 0, "xattrs should be deleted with the inode");
 
-        // Create directory entry
-        let mut stmt = conn
-            .prepare_cached("INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)")
-            .await?;
-        stmt.execute((name, parent_ino, ino)).await?;
+        Ok(())
+    }
 
-        // Increment link count
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
-            .await?;
-        stmt.execute((ino,)).await?;
+    // ==================== Snapshot Tests ====================
 
-        // Update parent directory ctime and mtime
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?")
+    #[tokio::test]
+    async fn test_restore_discards_entries_created_after_snapshot() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/keep", 0, 0).await?;
+        let (_, file) = fs
+            .create_file("/keep/a.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
-        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+        file.pwrite(0, b"before").await?;
+
+        fs.snapshot("s1").await?;
+
+        fs.mkdir("/new", 0, 0).await?;
+        fs.mkdir("/keep/sub", 0, 0).await?;
+        let (_, file) = fs
+            .create_file("/new/b.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
+        file.pwrite(0, b"after").await?;
+        fs.link("/keep/a.txt", "/keep/alias.txt").await?;
 
-        // Populate dentry cache
-        self.dentry_cache.insert(parent_ino, name, ino);
+        fs.restore("s1").await?;
 
-        Ok(Stats {
-            ino,
-            mode,
-            nlink: 1,
-            uid,
-            gid,
-            size: 0,
-            atime: now_secs,
-            mtime: now_secs,
-            ctime: now_secs,
-            atime_nsec: now_nsec as u32,
-            mtime_nsec: now_nsec as u32,
-            ctime_nsec: now_nsec as u32,
-            rdev,
-        })
+        assert!(fs.lstat("/new").await?.is_none());
+        assert!(fs.lstat("/new/b.txt").await?.is_none());
+        assert!(fs.lstat("/keep/sub").await?.is_none());
+        assert!(fs.lstat("/keep/alias.txt").await?.is_none());
+        assert_eq!(fs.read_file("/keep/a.txt").await?.unwrap(), b"before");
+
+        let a = fs.lstat("/keep/a.txt").await?.unwrap();
+        assert_eq!(a.nlink, 1, "link count should be recomputed");
+        let keep = fs.lstat("/keep").await?.unwrap();
+        assert_eq!(keep.nlink, 2, "removed subdirectory should not be counted");
+
+        Ok(())
     }
 
-    async fn symlink(
-        &self,
-        parent_ino: i64,
-        name: &str,
-        target: &str,
-        uid: u32,
-        gid: u32,
-    ) -> Result<Stats> {
-        if name.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_restore_frees_entries_moved_into_newer_directories() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let before = blob_usage(&fs).await?;
+        fs.pwrite("/moved.bin", 0, &pseudo_random_data(3 * fs.chunk_size()))
+            .await?;
+        fs.mkdir("/old", 0, 0).await?;
+        fs.pwrite("/old/inner.txt", 0, b"inner").await?;
+        let moved = fs.lstat("/moved.bin").await?.unwrap();
+        let old = fs.lstat("/old").await?.unwrap();
 
-        // Check if entry already exists
-        if self.lookup_child(&conn, parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
-        }
+        fs.snapshot("s1").await?;
 
-        // Create inode for symlink
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mode = S_IFLNK | 0o777; // Symlinks typically have 777 permissions
-        let size = target.len() as i64;
+        fs.mkdir("/new", 0, 0).await?;
+        fs.rename("/moved.bin", "/new/moved.bin").await?;
+        fs.rename("/old", "/new/old").await?;
 
-        let mut stmt = conn
-            .prepare_cached(
-                "INSERT INTO fs_inode (mode, uid, gid, size, atime, mtime, ctime, atime_nsec, mtime_nsec, ctime_nsec)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING ino",
-            )
-            .await?;
-        let row = stmt
-            .query_row((
-                mode, uid, gid, size, now_secs, now_secs, now_secs, now_nsec, now_nsec, now_nsec,
-            ))
-            .await?;
+        fs.restore("s1").await?;
 
-        let ino = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| v.as_integer().copied())
-            .ok_or_else(|| Error::Internal("failed to get inode".to_string()))?;
+        // Their entries went with /new, so nothing is left referring to them
+        assert!(fs.lstat("/moved.bin").await?.is_none());
+        assert!(fs.lstat("/old").await?.is_none());
+        assert!(FileSystem::getattr(&fs, moved.ino).await?.is_none());
+        assert!(FileSystem::getattr(&fs, old.ino).await?.is_none());
+        assert_eq!(blob_usage(&fs).await?, before);
+        assert_eq!(fs.check().await?, vec![]);
 
-        // Store symlink target
-        conn.execute(
-            "INSERT INTO fs_symlink (ino, target) VALUES (?, ?)",
-            (ino, target),
-        )
-        .await?;
+        Ok(())
+    }
 
-        // Create directory entry
-        conn.execute(
-            "INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)",
-            (name, parent_ino, ino),
-        )
-        .await?;
+    #[tokio::test]
+    async fn test_snapshot_labels() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
 
-        // Increment link count
-        conn.execute(
-            "UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?",
-            (ino,),
-        )
-        .await?;
+        fs.snapshot("s1").await?;
+        assert!(matches!(
+            fs.snapshot("s1").await,
+            Err(Error::SnapshotExists(_))
+        ));
+        assert!(matches!(
+            fs.restore("missing").await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+
+        // Restoring drops snapshots taken afterwards
+        fs.snapshot("s2").await?;
+        fs.restore("s1").await?;
+        assert!(matches!(
+            fs.restore("s2").await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+        fs.restore("s1").await?;
 
-        // Update parent directory ctime and mtime
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changes_since_snapshot() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.mkdir("/dir/sub", 0, 0).await?;
+        fs.pwrite("/dir/sub/deep.txt", 0, b"deep").await?;
+        fs.pwrite("/same.txt", 0, b"same").await?;
+        fs.pwrite("/edit.txt", 0, b"before").await?;
+        fs.pwrite("/old_name.txt", 0, b"moved").await?;
+        // Changes are tracked by the second, so age what exists so far
+        let conn = fs.get_connection().await?;
         conn.execute(
-            "UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
-            (now_secs, now_secs, now_nsec, now_nsec, parent_ino),
+            "UPDATE fs_inode SET mtime = mtime - 60, ctime = ctime - 60",
+            (),
         )
         .await?;
+        drop(conn);
 
-        // Populate dentry cache
-        self.dentry_cache.insert(parent_ino, name, ino);
+        fs.snapshot("s1").await?;
+        assert_eq!(fs.changes_since("s1").await?, SnapshotChanges::default());
 
-        Ok(Stats {
-            ino,
-            mode,
-            nlink: 1,
-            uid,
-            gid,
-            size,
-            atime: now_secs,
-            mtime: now_secs,
-            ctime: now_secs,
-            atime_nsec: now_nsec as u32,
-            mtime_nsec: now_nsec as u32,
-            ctime_nsec: now_nsec as u32,
-            rdev: 0,
-        })
+        fs.pwrite("/edit.txt", 0, b"after").await?;
+        fs.pwrite("/new.txt", 0, b"new").await?;
+        fs.rename("/old_name.txt", "/new_name.txt").await?;
+        fs.remove("/dir/sub/deep.txt").await?;
+        fs.remove("/dir/sub").await?;
+
+        let changes = fs.changes_since("s1").await?;
+        assert_eq!(
+            changes.changed,
+            vec!["/dir", "/edit.txt", "/new.txt", "/new_name.txt"]
+        );
+        // The deleted directory's contents are implied
+        assert_eq!(changes.deleted, vec!["/dir/sub", "/old_name.txt"]);
+
+        assert!(matches!(
+            fs.changes_since("missing").await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+
+        // Restoring forgets the paths of dropped snapshots
+        fs.snapshot("s2").await?;
+        fs.restore("s1").await?;
+        let conn = fs.get_connection().await?;
+        let mut rows = conn
+            .query(
+                "SELECT COUNT(*) FROM fs_snapshot_paths WHERE label = 's2'",
+                (),
+            )
+            .await?;
+        let row = rows.next().await?.unwrap();
+        assert_eq!(
+            row.get_value(0).ok().and_then(|v| v.as_integer().copied()),
+            Some(0)
+        );
+
+        Ok(())
     }
 
-    async fn unlink(&self, parent_ino: i64, name: &str) -> Result<()> {
-        if name.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+    // ==================== Access Tests ====================
+
+    #[tokio::test]
+    async fn test_access_checks_mode_bits() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs.create_file("/perm.txt", 0o100640, 1000, 100).await?;
+        let ino = stats.ino;
+
+        // Owner: rw-
+        assert!(FileSystem::access(&fs, ino, libc::R_OK | libc::W_OK, 1000, 100).await?);
+        assert!(!FileSystem::access(&fs, ino, libc::X_OK, 1000, 100).await?);
+
+        // Group: r--
+        assert!(FileSystem::access(&fs, ino, libc::R_OK, 2000, 100).await?);
+        assert!(!FileSystem::access(&fs, ino, libc::W_OK, 2000, 100).await?);
+
+        // Other: ---
+        assert!(!FileSystem::access(&fs, ino, libc::R_OK, 2000, 200).await?);
+        assert!(FileSystem::access(&fs, ino, libc::F_OK, 2000, 200).await?);
+
+        // Root bypasses read/write but needs an execute bit
+        assert!(FileSystem::access(&fs, ino, libc::R_OK | libc::W_OK, 0, 0).await?);
+        assert!(!FileSystem::access(&fs, ino, libc::X_OK, 0, 0).await?);
+
+        // Missing inode
+        assert!(FileSystem::access(&fs, 99999, libc::F_OK, 0, 0)
+            .await
+            .is_err());
+
+        Ok(())
+    }
 
-        // Look up the child inode
-        let ino = self
-            .lookup_child(&conn, parent_ino, name)
-            .await?
-            .ok_or(FsError::NotFound)?;
+    // ==================== Rename Flag Tests ====================
 
-        // Check if it's a directory (use rmdir for directories)
-        let mut stmt = conn
-            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+    #[tokio::test]
+    async fn test_rename_noreplace() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (_, file) = fs.create_file("/src.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"source").await?;
+        let (_, file) = fs.create_file("/dst.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"dest").await?;
+
+        let result = FileSystem::rename(
+            &fs,
+            ROOT_INO,
+            "src.txt",
+            ROOT_INO,
+            "dst.txt",
+            RENAME_NOREPLACE,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::AlreadyExists))
+        ));
+        assert_eq!(fs.read_file("/dst.txt").await?.unwrap(), b"dest");
+
+        // A free destination behaves like a plain rename
+        FileSystem::rename(
+            &fs,
+            ROOT_INO,
+            "src.txt",
+            ROOT_INO,
+            "new.txt",
+            RENAME_NOREPLACE,
+        )
+        .await?;
+        assert!(fs.lstat("/src.txt").await?.is_none());
+        assert_eq!(fs.read_file("/new.txt").await?.unwrap(), b"source");
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+        Ok(())
+    }
 
-            if (mode & S_IFMT) == super::S_IFDIR {
-                return Err(FsError::IsADirectory.into());
-            }
-        }
+    #[tokio::test]
+    async fn test_rename_exchange_into_own_subtree_fails() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/dir/inner.txt", 0, b"inner").await?;
+        let dir_ino = fs.lstat("/dir").await?.unwrap().ino;
 
-        // Delete the directory entry
-        let mut stmt = conn
-            .prepare_cached("DELETE FROM fs_dentry WHERE parent_ino = ? AND name = ?")
-            .await?;
-        stmt.execute((parent_ino, name)).await?;
+        let result =
+            FileSystem::rename(&fs, ROOT_INO, "dir", dir_ino, "inner.txt", RENAME_EXCHANGE).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::InvalidRename))
+        ));
+        assert_eq!(fs.read_file("/dir/inner.txt").await?.unwrap(), b"inner");
 
-        // Invalidate cache
-        self.dentry_cache.remove(parent_ino, name);
+        Ok(())
+    }
 
-        // Update parent directory mtime and ctime
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
-            .await?;
-        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
+    #[tokio::test]
+    async fn test_rename_exchange() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"file").await?;
+        let (_, file) = fs
+            .create_file("/dir/inner.txt", DEFAULT_FILE_MODE, 0, 0)
             .await?;
+        file.pwrite(0, b"inner").await?;
+        let dir_ino = fs.lstat("/dir").await?.unwrap().ino;
+        let file_ino = fs.lstat("/file.txt").await?.unwrap().ino;
+
+        FileSystem::rename(
+            &fs,
+            ROOT_INO,
+            "file.txt",
+            dir_ino,
+            "inner.txt",
+            RENAME_EXCHANGE,
+        )
+        .await?;
+        assert_eq!(fs.read_file("/file.txt").await?.unwrap(), b"inner");
+        assert_eq!(fs.read_file("/dir/inner.txt").await?.unwrap(), b"file");
+        assert_eq!(fs.lstat("/dir/inner.txt").await?.unwrap().ino, file_ino);
+
+        // Swapping a directory with a file moves its ".." link
+        fs.mkdir("/other", 0, 0).await?;
+        let root_nlink = fs.lstat("/").await?.unwrap().nlink;
+        FileSystem::rename(
+            &fs,
+            ROOT_INO,
+            "other",
+            dir_ino,
+            "inner.txt",
+            RENAME_EXCHANGE,
+        )
+        .await?;
+        assert!(fs.lstat("/other").await?.unwrap().is_file());
+        assert!(fs.lstat("/dir/inner.txt").await?.unwrap().is_directory());
+        assert_eq!(fs.lstat("/").await?.unwrap().nlink, root_nlink - 1);
+        assert_eq!(fs.lstat("/dir").await?.unwrap().nlink, 3);
+
+        // Both entries must exist
+        let result =
+            FileSystem::rename(&fs, ROOT_INO, "other", ROOT_INO, "missing", RENAME_EXCHANGE).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotFound))
+        ));
 
-        // Decrement link count and update ctime
-        let mut stmt = conn
-            .prepare_cached(
-                "UPDATE fs_inode SET nlink = nlink - 1, ctime = ?, ctime_nsec = ? WHERE ino = ?",
+        Ok(())
+    }
+
+    // ==================== Fallocate Tests ====================
+
+    async fn allocated_bytes(fs: &AgentFS, ino: i64) -> Result<i64> {
+        let conn = fs.get_connection().await?;
+        let mut rows = conn
+            .query(
+                "SELECT COALESCE(SUM(LENGTH(data)), 0) FROM fs_data WHERE ino = ?",
+                (ino,),
             )
             .await?;
-        stmt.execute((now_secs, now_nsec, ino)).await?;
+        let row = rows.next().await?.unwrap();
+        Ok(row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .unwrap_or(0))
+    }
 
-        // Check if this was the last link to the inode
-        let link_count = self.get_link_count(&conn, ino).await?;
-        if link_count == 0 {
-            // Delete data blocks
-            let mut stmt = conn
-                .prepare_cached("DELETE FROM fs_data WHERE ino = ?")
-                .await?;
-            stmt.execute((ino,)).await?;
+    #[tokio::test]
+    async fn test_fallocate_extends_with_zeros() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, file) = fs
+            .create_file("/alloc.bin", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"keep").await?;
 
-            // Delete symlink if exists
-            let mut stmt = conn
-                .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
-                .await?;
-            stmt.execute((ino,)).await?;
+        let len = fs.chunk_size() as u64 * 2 + 100;
+        FileSystem::fallocate(&fs, stats.ino, 0, len, 0).await?;
 
-            // Delete inode
-            let mut stmt = conn
-                .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
-                .await?;
-            stmt.execute((ino,)).await?;
-        }
+        assert_eq!(file.fstat().await?.size as u64, len);
+        assert_eq!(allocated_bytes(&fs, stats.ino).await? as u64, len);
+
+        let data = fs.read_file("/alloc.bin").await?.unwrap();
+        assert_eq!(&data[..4], b"keep");
+        assert!(data[4..].iter().all(|&b| b == 0));
 
         Ok(())
     }
 
-    async fn rmdir(&self, parent_ino: i64, name: &str) -> Result<()> {
-        if name.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_fallocate_keep_size() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, file) = fs.create_file("/keep.bin", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, b"hello").await?;
 
-        // Look up the child inode
-        let ino = self
-            .lookup_child(&conn, parent_ino, name)
-            .await?
-            .ok_or(FsError::NotFound)?;
+        FileSystem::fallocate(&fs, stats.ino, 0, 8192, FALLOC_FL_KEEP_SIZE).await?;
 
-        if ino == ROOT_INO {
-            return Err(FsError::RootOperation.into());
-        }
+        assert_eq!(file.fstat().await?.size, 5);
+        assert_eq!(allocated_bytes(&fs, stats.ino).await?, 8192);
+        assert_eq!(fs.read_file("/keep.bin").await?.unwrap(), b"hello");
 
-        // Check if it's a directory
-        let mut stmt = conn
-            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        Ok(())
+    }
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+    #[tokio::test]
+    async fn test_fallocate_rejects_unsupported_modes() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs.create_file("/mode.bin", DEFAULT_FILE_MODE, 0, 0).await?;
+
+        // FALLOC_FL_PUNCH_HOLE
+        let result = FileSystem::fallocate(&fs, stats.ino, 0, 4096, 0x2).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotSupported))
+        ));
+
+        let root = fs.lstat("/").await?.unwrap();
+        let result = FileSystem::fallocate(&fs, root.ino, 0, 4096, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
 
-            if (mode & S_IFMT) != super::S_IFDIR {
-                return Err(FsError::NotADirectory.into());
-            }
-        } else {
-            return Err(FsError::NotFound.into());
-        }
+        Ok(())
+    }
 
-        // Check if directory is empty
-        let mut stmt = conn
-            .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+    // ==================== Quota Tests ====================
 
-        if let Some(row) = rows.next().await? {
-            let count = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0);
-            if count > 0 {
-                return Err(FsError::NotEmpty.into());
-            }
-        }
+    #[tokio::test]
+    async fn test_quota_rejects_whole_write() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.set_max_bytes(Some(10_000)).await?;
 
-        // Delete the directory entry
-        let mut stmt = conn
-            .prepare_cached("DELETE FROM fs_dentry WHERE parent_ino = ? AND name = ?")
+        let (stats, file) = fs
+            .create_file("/quota.bin", DEFAULT_FILE_MODE, 0, 0)
             .await?;
-        stmt.execute((parent_ino, name)).await?;
+        file.pwrite(0, &[1u8; 8000]).await?;
+
+        // Crossing the limit writes nothing at all
+        let result = file.pwrite(8000, &[2u8; 4000]).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
+        assert_eq!(file.fstat().await?.size, 8000);
+        assert_eq!(fs.read_file("/quota.bin").await?.unwrap(), vec![1u8; 8000]);
+
+        // Overwrites that don't grow the file are still allowed
+        file.pwrite(0, &[3u8; 1000]).await?;
+
+        let result = fs.pwrite("/other.bin", 0, &[4u8; 4000]).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
+        assert!(fs.lstat("/other.bin").await?.is_none());
+
+        let result = file.truncate(20_000).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
+
+        let result = FileSystem::fallocate(&fs, stats.ino, 0, 20_000, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
+        assert_eq!(file.fstat().await?.size, 8000);
 
-        // Invalidate cache
-        self.dentry_cache.remove(parent_ino, name);
+        Ok(())
+    }
 
-        // Decrement link count on removed directory
-        let mut stmt = conn
-            .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
-            .await?;
-        stmt.execute((ino,)).await?;
+    #[tokio::test]
+    async fn test_quota_not_reserved_by_fallocate_keep_size() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.set_max_bytes(Some(10_000)).await?;
+        let (stats, file) = fs.create_file("/keep.bin", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.pwrite(0, &[1u8; 8000]).await?;
+
+        // The size does not change, so nothing is charged or reserved
+        FileSystem::fallocate(&fs, stats.ino, 0, 20_000, FALLOC_FL_KEEP_SIZE).await?;
+        assert_eq!(file.fstat().await?.size, 8000);
+        let result = file.pwrite(8000, &[2u8; 4000]).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
 
-        // Decrement parent nlink (removed directory's ".." link) and update timestamps
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        let mut stmt = conn
-            .prepare_cached(
-                "UPDATE fs_inode SET nlink = nlink - 1, ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
-            )
-            .await?;
-        stmt.execute((now_secs, now_secs, now_nsec, now_nsec, parent_ino))
-            .await?;
+        Ok(())
+    }
 
-        // Delete inode if no more links
-        let link_count = self.get_link_count(&conn, ino).await?;
-        if link_count == 0 {
-            let mut stmt = conn
-                .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
-                .await?;
-            stmt.execute((ino,)).await?;
-        }
+    #[tokio::test]
+    async fn test_quota_persists_across_open() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("quota.db");
+        let db_path = db_path.to_str().unwrap();
+
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.max_bytes(), None);
+        fs.set_max_bytes(Some(1 << 20)).await?;
+        drop(fs);
+
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.max_bytes(), Some(1 << 20));
+        fs.set_max_bytes(None).await?;
+        drop(fs);
+
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.max_bytes(), None);
 
         Ok(())
     }
 
-    async fn link(&self, ino: i64, newparent_ino: i64, newname: &str) -> Result<Stats> {
-        if newname.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+    #[tokio::test]
+    async fn test_statfs_reports_quota_capacity() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/data.bin", 0, &[1u8; 3000]).await?;
 
-        // Check if source inode exists and is not a directory
-        let mut stmt = conn
-            .prepare_cached("SELECT mode FROM fs_inode WHERE ino = ?")
-            .await?;
-        let mut rows = stmt.query((ino,)).await?;
+        // Without a quota the capacity is virtual
+        let stats = fs.statfs().await?;
+        assert_eq!(stats.total_bytes, VIRTUAL_CAPACITY_BYTES);
+        assert_eq!(stats.free_bytes, VIRTUAL_CAPACITY_BYTES - 3000);
+        assert_eq!(stats.block_size, DEFAULT_CHUNK_SIZE as u32);
+        assert!(stats.total_inodes >= stats.inodes);
 
-        if let Some(row) = rows.next().await? {
-            let mode = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(0) as u32;
+        fs.set_max_bytes(Some(10_000)).await?;
+        let stats = fs.statfs().await?;
+        assert_eq!(stats.bytes_used, 3000);
+        assert_eq!(stats.total_bytes, 10_000);
+        assert_eq!(stats.free_bytes, 7000);
 
-            if (mode & S_IFMT) == super::S_IFDIR {
-                return Err(FsError::IsADirectory.into());
-            }
-        } else {
-            return Err(FsError::NotFound.into());
-        }
+        Ok(())
+    }
 
-        // Check if destination already exists
-        if self
-            .lookup_child(&conn, newparent_ino, newname)
-            .await?
-            .is_some()
-        {
-            return Err(FsError::AlreadyExists.into());
-        }
+    // ==================== Ownership Tests ====================
 
-        // Create directory entry pointing to the same inode
-        conn.execute(
-            "INSERT INTO fs_dentry (name, parent_ino, ino) VALUES (?, ?, ?)",
-            (newname, newparent_ino, ino),
-        )
-        .await?;
+    #[tokio::test]
+    async fn test_umask_applies_to_new_entries() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("umask.db");
+        let db_path = db_path.to_str().unwrap();
+
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.umask(), None);
+        fs.set_umask(Some(0o027)).await?;
+        drop(fs);
+
+        // The umask is read back from fs_config
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.umask(), Some(0o027));
+
+        let (stats, _) = FileSystem::create_file(&fs, ROOT_INO, "a.txt", 0o666, 0, 0).await?;
+        assert_eq!(stats.mode, S_IFREG | 0o640);
+        let subdir = FileSystem::mkdir(&fs, ROOT_INO, "sub", 0o777, 0, 0).await?;
+        assert_eq!(subdir.mode, crate::filesystem::S_IFDIR | 0o750);
+        fs.mkdir("/path", 0, 0).await?;
+        assert_eq!(
+            fs.stat("/path").await?.unwrap().mode,
+            crate::filesystem::S_IFDIR | 0o750
+        );
+        let nested = fs.mkdir_all("/x/y", 0o775, 0, 0).await?;
+        assert_eq!(nested.mode, crate::filesystem::S_IFDIR | 0o750);
 
-        // Increment link count and update ctime
-        let dur = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let now_secs = dur.as_secs() as i64;
-        let now_nsec = dur.subsec_nanos() as i64;
-        conn.execute(
-            "UPDATE fs_inode SET nlink = nlink + 1, ctime = ?, ctime_nsec = ? WHERE ino = ?",
-            (now_secs, now_nsec, ino),
-        )
-        .await?;
+        // Existing entries keep their mode
+        fs.set_umask(None).await?;
+        let (stats, _) = FileSystem::create_file(&fs, ROOT_INO, "b.txt", 0o666, 0, 0).await?;
+        assert_eq!(stats.mode, S_IFREG | 0o666);
+        assert_eq!(fs.stat("/a.txt").await?.unwrap().mode, S_IFREG | 0o640);
 
-        // Update parent directory ctime and mtime
-        conn.execute(
-            "UPDATE fs_inode SET ctime = ?, mtime = ?, ctime_nsec = ?, mtime_nsec = ? WHERE ino = ?",
-            (now_secs, now_secs, now_nsec, now_nsec, newparent_ino),
-        )
-        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_owner_persists_across_open() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("owner.db");
+        let db_path = db_path.to_str().unwrap();
+
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.owner().await?, (None, None));
+        fs.set_owner(Some(1234), Some(5678)).await?;
+        let root = fs.getattr(ROOT_INO).await?.unwrap();
+        assert_eq!((root.uid, root.gid), (1234, 5678));
+        drop(fs);
+
+        // Reopening no longer hands the root to the current user
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.owner().await?, (Some(1234), Some(5678)));
+        let root = fs.getattr(ROOT_INO).await?.unwrap();
+        assert_eq!((root.uid, root.gid), (1234, 5678));
+
+        Ok(())
+    }
 
-        // Populate dentry cache
-        self.dentry_cache.insert(newparent_ino, newname, ino);
+    // ==================== Path Normalization Tests ====================
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/a/b/../c"), "/a/c");
+        assert_eq!(normalize_path("//a///b"), "/a/b");
+        assert_eq!(normalize_path("/a/./"), "/a");
+        assert_eq!(normalize_path("a/./b/"), "/a/b");
+        assert_eq!(normalize_path("a/../../b"), "/b");
+        assert_eq!(normalize_path("/.."), "/");
+        assert_eq!(normalize_path(""), "/");
+    }
 
-        // Return updated stats
-        self.getattr_with_conn(&conn, ino)
-            .await?
-            .ok_or(FsError::NotFound.into())
+    #[tokio::test]
+    async fn test_paths_resolve_after_normalization() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/a", 0, 0).await?;
+        fs.mkdir("/a/b", 0, 0).await?;
+        fs.pwrite("/a/c", 0, b"data").await?;
+        let a = fs.stat("/a").await?.unwrap();
+        let b = fs.stat("/a/b").await?.unwrap();
+        let c = fs.stat("/a/c").await?.unwrap();
+
+        assert_eq!(fs.stat("/a/b/../c").await?.unwrap().ino, c.ino);
+        assert_eq!(fs.stat("//a///b").await?.unwrap().ino, b.ino);
+        assert_eq!(fs.stat("/a/./").await?.unwrap().ino, a.ino);
+        assert_eq!(fs.stat("a/b/../c").await?.unwrap().ino, c.ino);
+        // ".." never climbs above the root
+        assert_eq!(fs.stat("/../../a/c").await?.unwrap().ino, c.ino);
+
+        // Path-based trait methods resolve the same way
+        assert_eq!(FileSystem::append(&fs, "/a/b/../c", b"!").await?, 5);
+        let stats = FileSystem::mkdir_all(&fs, "//a///b/./", 0o755, 0, 0).await?;
+        assert_eq!(stats.ino, b.ino);
+
+        Ok(())
     }
 
-    async fn rename(
-        &self,
-        oldparent_ino: i64,
-        oldname: &str,
-        newparent_ino: i64,
-        newname: &str,
-    ) -> Result<()> {
-        if newname.len() > MAX_NAME_LEN {
-            return Err(FsError::NameTooLong.into());
-        }
-        let conn = self.pool.get_connection().await?;
+    // ==================== Directory Stream Tests ====================
 
-        // Get source inode
-        let src_ino = self
-            .lookup_child(&conn, oldparent_ino, oldname)
-            .await?
-            .ok_or(FsError::NotFound)?;
+    #[tokio::test]
+    async fn test_readdir_stream_pages_through_large_directory() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/big", 0, 0).await?;
+        let count = READDIR_PAGE_SIZE as usize * 2 + 10;
+        for i in 0..count {
+            fs.create_file(&format!("/big/file-{:04}", i), DEFAULT_FILE_MODE, 0, 0)
+                .await?;
+        }
+        // Names that would need escaping in JSON
+        fs.create_file("/big/quote\"back\\slash", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
 
-        if src_ino == ROOT_INO {
-            return Err(FsError::RootOperation.into());
+        let dir_ino = fs.lstat("/big").await?.unwrap().ino;
+        let mut stream = FileSystem::readdir_stream(&fs, dir_ino).await?.unwrap();
+        let mut streamed = Vec::new();
+        while let Some(entry) = stream.next_entry().await? {
+            assert!(entry.stats.is_file());
+            streamed.push(entry.name);
         }
 
-        // Get source stats to check if it's a directory
-        let src_stats = self
-            .getattr_with_conn(&conn, src_ino)
+        let expected: Vec<String> = FileSystem::readdir_plus(&fs, dir_ino)
             .await?
-            .ok_or(FsError::NotFound)?;
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(streamed.len(), count + 1);
+        assert_eq!(streamed, expected);
+        assert!(streamed.contains(&"quote\"back\\slash".to_string()));
 
-        let txn = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate).await?;
+        // Exhausted streams stay exhausted
+        assert!(stream.next_entry().await?.is_none());
 
-        let result: Result<()> = async {
-            // Check if destination exists
-            if let Some(dst_ino) = self.lookup_child(&conn, newparent_ino, newname).await? {
-                let dst_stats = self.getattr_with_conn(&conn, dst_ino).await?.ok_or(FsError::NotFound)?;
+        Ok(())
+    }
 
-                // Can't replace directory with non-directory
-                if dst_stats.is_directory() && !src_stats.is_directory() {
-                    return Err(FsError::IsADirectory.into());
-                }
+    #[tokio::test]
+    async fn test_readdir_stream_errors() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs
+            .create_file("/plain.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
 
-                // Can't replace non-directory with directory
-                if !dst_stats.is_directory() && src_stats.is_directory() {
-                    return Err(FsError::NotADirectory.into());
-                }
+        assert!(FileSystem::readdir_stream(&fs, 99999).await?.is_none());
+        let result = FileSystem::readdir_stream(&fs, stats.ino).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotADirectory))
+        ));
 
-                // If destination is directory, it must be empty
-                if dst_stats.is_directory() {
-                    let mut stmt = conn
-                        .prepare_cached("SELECT COUNT(*) FROM fs_dentry WHERE parent_ino = ?")
-                        .await?;
-                    let mut rows = stmt.query((dst_ino,)).await?;
+        Ok(())
+    }
 
-                    if let Some(row) = rows.next().await? {
-                        let count = row
-                            .get_value(0)
-                            .ok()
-                            .and_then(|v| v.as_integer().copied())
-                            .unwrap_or(0);
-                        if count > 0 {
-                            return Err(FsError::NotEmpty.into());
-                        }
-                    }
-                }
+    #[tokio::test]
+    async fn test_read_stream_pages_through_large_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size();
+        let data = pseudo_random_data(chunk_size * READ_STREAM_PAGE_CHUNKS as usize * 2 + 100);
+        fs.pwrite("/big", 0, &data).await?;
+        // A hole before the only stored chunk reads as zeros
+        fs.pwrite("/sparse", chunk_size as u64 * 3, b"end").await?;
+
+        let ino = fs.stat("/big").await?.unwrap().ino;
+        let mut stream = FileSystem::read_stream(&fs, ino).await?.unwrap();
+        let mut streamed = Vec::new();
+        while let Some(chunk) = stream.next_chunk().await? {
+            assert!(chunk.len() == chunk_size || streamed.len() + chunk.len() == data.len());
+            streamed.extend(chunk);
+        }
+        assert_eq!(streamed, data);
+        // Exhausted streams stay exhausted
+        assert!(stream.next_chunk().await?.is_none());
+
+        let ino = fs.stat("/sparse").await?.unwrap().ino;
+        let mut stream = FileSystem::read_stream(&fs, ino).await?.unwrap();
+        let mut streamed = Vec::new();
+        while let Some(chunk) = stream.next_chunk().await? {
+            streamed.extend(chunk);
+        }
+        let mut expected = vec![0u8; chunk_size * 3];
+        expected.extend_from_slice(b"end");
+        assert_eq!(streamed, expected);
 
-                // Remove destination entry
-                let mut stmt = conn
-                    .prepare_cached("DELETE FROM fs_dentry WHERE parent_ino = ? AND name = ?")
-                    .await?;
-                stmt.execute((newparent_ino, newname)).await?;
+        Ok(())
+    }
 
-                // Decrement link count and update ctime on destination inode
-                let dur_dec = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap_or_default();
-                let now_dec = dur_dec.as_secs() as i64;
-                let now_dec_nsec = dur_dec.subsec_nanos() as i64;
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1, ctime = ?, ctime_nsec = ? WHERE ino = ?")
-                    .await?;
-                stmt.execute((now_dec, now_dec_nsec, dst_ino)).await?;
+    #[tokio::test]
+    async fn test_read_stream_errors() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/empty", 0, b"").await?;
+
+        assert!(FileSystem::read_stream(&fs, 99999).await?.is_none());
+        let dir_ino = fs.stat("/dir").await?.unwrap().ino;
+        assert!(matches!(
+            FileSystem::read_stream(&fs, dir_ino).await,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
+        let ino = fs.stat("/empty").await?.unwrap().ino;
+        let mut stream = FileSystem::read_stream(&fs, ino).await?.unwrap();
+        assert!(stream.next_chunk().await?.is_none());
 
-                // Clean up destination inode if no more links
-                let link_count = self.get_link_count(&conn, dst_ino).await?;
-                if link_count == 0 {
-                    let mut stmt = conn
-                        .prepare_cached("DELETE FROM fs_data WHERE ino = ?")
-                        .await?;
-                    stmt.execute((dst_ino,)).await?;
-                    let mut stmt = conn
-                        .prepare_cached("DELETE FROM fs_symlink WHERE ino = ?")
-                        .await?;
-                    stmt.execute((dst_ino,)).await?;
-                    let mut stmt = conn
-                        .prepare_cached("DELETE FROM fs_inode WHERE ino = ?")
-                        .await?;
-                    stmt.execute((dst_ino,)).await?;
-                }
-            }
+        Ok(())
+    }
 
-            // Update the dentry: change parent and/or name
-            let mut stmt = conn
-                .prepare_cached(
-                    "UPDATE fs_dentry SET parent_ino = ?, name = ? WHERE parent_ino = ? AND name = ?",
-                )
-                .await?;
-            stmt.execute((newparent_ino, newname, oldparent_ino, oldname))
-                .await?;
+    #[tokio::test]
+    async fn test_readdir_types() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/file", 0, b"data").await?;
+        fs.symlink("/file", "/link", 0, 0).await?;
+        fs.mknod("/fifo", crate::filesystem::S_IFIFO | 0o644, 0, 0, 0)
+            .await?;
 
-            // If renaming a directory across parents, adjust parent nlink counts
-            // (the ".." link moves from old parent to new parent)
-            if src_stats.is_directory() && oldparent_ino != newparent_ino {
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET nlink = nlink - 1 WHERE ino = ?")
-                    .await?;
-                stmt.execute((oldparent_ino,)).await?;
+        let entries = FileSystem::readdir_types(&fs, ROOT_INO).await?.unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("dir".to_string(), FileType::Directory),
+                ("fifo".to_string(), FileType::Fifo),
+                ("file".to_string(), FileType::Regular),
+                ("link".to_string(), FileType::Symlink),
+            ]
+        );
+        assert_eq!(FileType::Directory.dirent_type(), libc::DT_DIR);
+        assert_eq!(FileType::Symlink.as_str(), "symlink");
 
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET nlink = nlink + 1 WHERE ino = ?")
-                    .await?;
-                stmt.execute((newparent_ino,)).await?;
-            }
+        let file_ino = fs.lstat("/file").await?.unwrap().ino;
+        assert!(FileSystem::readdir_types(&fs, 99999).await?.is_none());
+        assert!(matches!(
+            FileSystem::readdir_types(&fs, file_ino).await,
+            Err(crate::error::Error::Fs(FsError::NotADirectory))
+        ));
 
-            // Update ctime of the inode
-            let dur = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default();
-            let now_secs = dur.as_secs() as i64;
-            let now_nsec = dur.subsec_nanos() as i64;
+        Ok(())
+    }
 
-            let mut stmt = conn
-                .prepare_cached("UPDATE fs_inode SET ctime = ?, ctime_nsec = ? WHERE ino = ?")
-                .await?;
-            stmt.execute((now_secs, now_nsec, src_ino)).await?;
+    // ==================== Special File Tests ====================
 
-            // Update source parent directory timestamps
-            let mut stmt = conn
-                .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
-                .await?;
-            stmt.execute((now_secs, now_secs, now_nsec, now_nsec, oldparent_ino)).await?;
+    #[tokio::test]
+    async fn test_mknod_special_files() -> Result<()> {
+        use crate::filesystem::{S_IFCHR, S_IFIFO, S_IFSOCK};
 
-            // Update destination parent directory timestamps
-            if newparent_ino != oldparent_ino {
-                let mut stmt = conn
-                    .prepare_cached("UPDATE fs_inode SET mtime = ?, ctime = ?, mtime_nsec = ?, ctime_nsec = ? WHERE ino = ?")
-                    .await?;
-                stmt.execute((now_secs, now_secs, now_nsec, now_nsec, newparent_ino)).await?;
-            }
+        let (fs, _dir) = create_test_fs().await?;
 
-            Ok(())
-        }
-        .await;
+        let fifo = FileSystem::mknod(&fs, ROOT_INO, "fifo", S_IFIFO | 0o644, 0, 0, 0).await?;
+        let sock = FileSystem::mknod(&fs, ROOT_INO, "sock", S_IFSOCK | 0o755, 0, 0, 0).await?;
+        let rdev = libc::makedev(1, 3) as u64;
+        FileSystem::mknod(&fs, ROOT_INO, "null", S_IFCHR | 0o666, rdev, 0, 0).await?;
+
+        // Type bits and device numbers survive a round trip through the database
+        let fifo = fs.getattr(fifo.ino).await?.unwrap();
+        assert_eq!(fifo.mode, S_IFIFO | 0o644);
+        assert_eq!(fifo.size, 0);
+        let sock = fs.getattr(sock.ino).await?.unwrap();
+        assert_eq!(sock.mode & S_IFMT, S_IFSOCK);
+        let chr = FileSystem::lookup(&fs, ROOT_INO, "null").await?.unwrap();
+        assert_eq!(chr.mode, S_IFCHR | 0o666);
+        assert_eq!(chr.rdev, rdev);
+
+        let result = FileSystem::mknod(&fs, ROOT_INO, "fifo", S_IFIFO | 0o644, 0, 0, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::AlreadyExists))
+        ));
 
-        match result {
-            Ok(()) => {
-                txn.commit().await?;
+        Ok(())
+    }
 
-                // Invalidate cache for source and destination
-                self.dentry_cache.remove(oldparent_ino, oldname);
-                self.dentry_cache.remove(newparent_ino, newname);
+    // ==================== Compaction Tests ====================
 
-                // Add new entry to cache (source inode is now at destination)
-                self.dentry_cache.insert(newparent_ino, newname, src_ino);
+    #[tokio::test]
+    async fn test_compact_removes_orphans_and_shrinks() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let data = vec![7u8; 512 * 1024];
+        fs.pwrite("/keep.bin", 0, b"keep").await?;
+        fs.pwrite("/big.bin", 0, &data).await?;
+        fs.pwrite("/orphan.bin", 0, &data).await?;
+        let orphan = fs.lstat("/orphan.bin").await?.unwrap();
+
+        // Simulate an inode left behind without a directory entry
+        let conn = fs.get_connection().await?;
+        conn.execute("DELETE FROM fs_dentry WHERE ino = ?", (orphan.ino,))
+            .await?;
+        drop(conn);
+        fs.remove("/big.bin").await?;
 
-                Ok(())
-            }
-            Err(e) => {
-                let _ = txn.rollback().await;
-                Err(e)
-            }
-        }
+        let stats = fs.compact().await?;
+        assert_eq!(stats.orphaned_inodes, 1);
+        assert!(stats.bytes_after < stats.bytes_before);
+        assert_eq!(
+            stats.bytes_reclaimed(),
+            stats.bytes_before - stats.bytes_after
+        );
+        assert_eq!(fs.get_chunk_count(orphan.ino).await?, 0);
+        assert!(FileSystem::getattr(&fs, orphan.ino).await?.is_none());
+        assert_eq!(fs.read_file("/keep.bin").await?.unwrap(), b"keep");
+
+        // Nothing left to clean up
+        assert_eq!(fs.compact().await?.orphaned_inodes, 0);
+
+        Ok(())
     }
 
-    async fn statfs(&self) -> Result<FilesystemStats> {
-        AgentFS::statfs(self).await
+    // ==================== Symlink Resolution Tests ====================
+
+    fn is_symlink_loop<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Fs(FsError::SymlinkLoop)))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+    #[tokio::test]
+    async fn test_symlink_self_loop() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.symlink("/self", "/self", 0, 0).await?;
 
-    async fn create_test_fs() -> Result<(AgentFS, tempfile::TempDir)> {
-        let dir = tempdir()?;
-        let db_path = dir.path().join("test.db");
-        let fs = AgentFS::new(db_path.to_str().unwrap()).await?;
-        Ok((fs, dir))
+        assert!(fs.lstat("/self").await?.unwrap().is_symlink());
+        assert!(is_symlink_loop(fs.stat("/self").await));
+        assert!(is_symlink_loop(fs.read_file("/self").await));
+        assert!(is_symlink_loop(fs.pread("/self", 0, 10).await));
+        assert!(is_symlink_loop(fs.pwrite("/self", 0, b"data").await));
+        assert!(is_symlink_loop(fs.stat("/self/child").await));
+
+        Ok(())
     }
 
-    // ==================== Chunk Size Boundary Tests ====================
+    #[tokio::test]
+    async fn test_symlink_two_node_cycle() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.symlink("/b", "/a", 0, 0).await?;
+        fs.symlink("a", "/b", 0, 0).await?;
+
+        assert!(is_symlink_loop(fs.stat("/a").await));
+        assert!(is_symlink_loop(fs.stat("/b").await));
+        assert!(is_symlink_loop(fs.truncate("/a", 0).await));
+        assert!(is_symlink_loop(fs.open("/b").await));
+
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn test_file_smaller_than_chunk_size() -> Result<()> {
+    async fn test_symlink_deep_chain_resolves() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/dir/target.txt", 0, b"end of chain").await?;
+
+        // link0 -> dir/target.txt, linkN -> link(N-1), alternating
+        // absolute and relative targets
+        fs.symlink("dir/target.txt", "/link0", 0, 0).await?;
+        for i in 1..30 {
+            let target = if i % 2 == 0 {
+                format!("/link{}", i - 1)
+            } else {
+                format!("link{}", i - 1)
+            };
+            fs.symlink(&target, &format!("/link{}", i), 0, 0).await?;
+        }
 
-        // Write a file smaller than chunk_size (100 bytes)
-        let data = vec![0u8; 100];
-        let (_, file) = fs
-            .create_file("/small.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
+        let target = fs.lstat("/dir/target.txt").await?.unwrap();
+        let stats = fs.stat("/link29").await?.unwrap();
+        assert_eq!(stats.ino, target.ino);
+        assert_eq!(fs.read_file("/link29").await?.unwrap(), b"end of chain");
+        assert_eq!(fs.pread("/link29", 7, 5).await?.unwrap(), b"chain");
 
-        // Read it back
-        let read_data = fs.read_file("/small.txt").await?.unwrap();
-        assert_eq!(read_data.len(), 100);
-        assert_eq!(read_data, data);
+        // Writes go to the target, not the link
+        fs.pwrite("/link29", 0, b"END").await?;
+        assert_eq!(
+            fs.read_file("/dir/target.txt").await?.unwrap(),
+            b"END of chain"
+        );
+        assert!(fs.lstat("/link29").await?.unwrap().is_symlink());
 
-        // Verify only 1 chunk was created
-        let ino = fs.resolve_path("/small.txt").await?.unwrap();
-        let chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(chunk_count, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_symlink_in_intermediate_component() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/real", 0, 0).await?;
+        fs.pwrite("/real/file.txt", 0, b"inside").await?;
+        fs.symlink("real", "/alias", 0, 0).await?;
+
+        let stats = fs.stat("/alias/file.txt").await?.unwrap();
+        assert_eq!(stats.ino, fs.lstat("/real/file.txt").await?.unwrap().ino);
+        assert_eq!(fs.read_file("/alias/file.txt").await?.unwrap(), b"inside");
+
+        // Creating through the link lands in the real directory
+        fs.pwrite("/alias/new.txt", 0, b"new").await?;
+        assert_eq!(fs.read_file("/real/new.txt").await?.unwrap(), b"new");
+
+        // A dangling link resolves to nothing rather than an error
+        fs.symlink("/missing", "/dangling", 0, 0).await?;
+        assert!(fs.stat("/dangling").await?.is_none());
+
+        Ok(())
+    }
+
+    // ==================== Compression Tests ====================
+
+    /// Count the chunks of a file stored with and without compression
+    async fn count_compressed_chunks(fs: &AgentFS, path: &str) -> Result<(i64, i64)> {
+        let ino = fs.lstat(path).await?.unwrap().ino;
+        let conn = fs.get_connection().await?;
+        let mut rows = conn
+            .query(
+                "SELECT SUM(compression != 0), SUM(compression = 0) FROM fs_data WHERE ino = ?",
+                (ino,),
+            )
+            .await?;
+        let row = rows.next().await?.unwrap();
+        let get = |i: usize| {
+            row.get_value(i)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0)
+        };
+        Ok((get(0), get(1)))
+    }
 
-        Ok(())
+    fn compressible_data(len: usize) -> Vec<u8> {
+        (0..)
+            .flat_map(|i| format!("log line {} of the agent run\n", i % 100).into_bytes())
+            .take(len)
+            .collect()
     }
 
     #[tokio::test]
-    async fn test_file_exactly_chunk_size() -> Result<()> {
+    async fn test_compression_roundtrip() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_compression(Some(CompressionKind::Zstd)).await?;
+        assert_eq!(fs.compression(), Some(CompressionKind::Zstd));
+
+        let data = compressible_data(5 * fs.chunk_size() + 123);
+        fs.pwrite("/log.txt", 0, &data).await?;
+
+        // Every chunk but the short tail is large enough to be compressed
+        let (compressed, plain) = count_compressed_chunks(&fs, "/log.txt").await?;
+        assert_eq!((compressed, plain), (5, 1));
+        assert_eq!(fs.read_file("/log.txt").await?.unwrap(), data);
+
+        // Reads at an offset only see the requested range
+        let offset = fs.chunk_size() as u64 + 17;
+        let range = fs.pread("/log.txt", offset, 3000).await?.unwrap();
+        assert_eq!(range, &data[offset as usize..offset as usize + 3000]);
+
+        // Partial writes and truncation rewrite compressed chunks in place
+        let mut expected = data.clone();
+        expected[100..110].copy_from_slice(b"0123456789");
+        fs.pwrite("/log.txt", 100, b"0123456789").await?;
+        expected.truncate(2 * fs.chunk_size() + 700);
+        fs.truncate("/log.txt", expected.len() as u64).await?;
+        assert_eq!(fs.read_file("/log.txt").await?.unwrap(), expected);
+
+        // File handles decode the same chunks
+        let ino = fs.lstat("/log.txt").await?.unwrap().ino;
+        let file = FileSystem::open(&fs, ino, libc::O_RDWR).await?;
+        file.pwrite(5000, b"handle").await?;
+        expected[5000..5006].copy_from_slice(b"handle");
+        assert_eq!(file.pread(0, expected.len() as u64).await?, expected);
 
-        // Write exactly chunk_size bytes
-        let chunk_size = fs.chunk_size();
-        let data: Vec<u8> = (0..chunk_size).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/exact.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
+        Ok(())
+    }
 
-        // Read it back
-        let read_data = fs.read_file("/exact.txt").await?.unwrap();
-        assert_eq!(read_data.len(), chunk_size);
-        assert_eq!(read_data, data);
+    #[tokio::test]
+    async fn test_compression_skips_incompressible_data() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.set_compression(Some(CompressionKind::Zstd)).await?;
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let data: Vec<u8> = (0..3 * fs.chunk_size())
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        fs.pwrite("/random.bin", 0, &data).await?;
 
-        // Verify only 1 chunk was created
-        let ino = fs.resolve_path("/exact.txt").await?.unwrap();
-        let chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(chunk_count, 1);
+        let (compressed, plain) = count_compressed_chunks(&fs, "/random.bin").await?;
+        assert_eq!((compressed, plain), (0, 3));
+        assert_eq!(fs.read_file("/random.bin").await?.unwrap(), data);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_file_one_byte_over_chunk_size() -> Result<()> {
-        let (fs, _dir) = create_test_fs().await?;
+    async fn test_compression_setting_persists_and_mixes() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let data = compressible_data(2 * DEFAULT_CHUNK_SIZE);
 
-        // Write chunk_size + 1 bytes
-        let chunk_size = fs.chunk_size();
-        let data: Vec<u8> = (0..=chunk_size).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/overflow.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
+        {
+            let fs = AgentFS::new(db_path).await?;
+            fs.pwrite("/plain.txt", 0, &data).await?;
+            fs.set_compression(Some(CompressionKind::Zstd)).await?;
+        }
 
-        // Read it back
-        let read_data = fs.read_file("/overflow.txt").await?.unwrap();
-        assert_eq!(read_data.len(), chunk_size + 1);
-        assert_eq!(read_data, data);
+        let fs = AgentFS::new(db_path).await?;
+        assert_eq!(fs.compression(), Some(CompressionKind::Zstd));
+        fs.pwrite("/packed.txt", 0, &data).await?;
 
-        // Verify 2 chunks were created
-        let ino = fs.resolve_path("/overflow.txt").await?.unwrap();
-        let chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(chunk_count, 2);
+        // Data written before compression was enabled stays readable as-is
+        assert_eq!(count_compressed_chunks(&fs, "/plain.txt").await?, (0, 2));
+        assert_eq!(count_compressed_chunks(&fs, "/packed.txt").await?, (2, 0));
+        assert_eq!(fs.read_file("/plain.txt").await?.unwrap(), data);
+        assert_eq!(fs.read_file("/packed.txt").await?.unwrap(), data);
+
+        // Turning it off again only affects new writes
+        fs.set_compression(None).await?;
+        assert_eq!(fs.compression(), None);
+        fs.pwrite("/packed.txt", 0, b"x").await?;
+        assert_eq!(count_compressed_chunks(&fs, "/packed.txt").await?, (1, 1));
+        assert_eq!(fs.read_file("/packed.txt").await?.unwrap()[1..], data[1..]);
 
         Ok(())
     }
 
-    #[tokio::test]
-    async fn test_file_spanning_multiple_chunks() -> Result<()> {
-        let (fs, _dir) = create_test_fs().await?;
+    // ==================== Dedup Tests ====================
 
-        // Write ~2.5 chunks worth of data
-        let chunk_size = fs.chunk_size();
-        let data_size = chunk_size * 2 + chunk_size / 2;
-        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/multi.txt", DEFAULT_FILE_MODE, 0, 0)
+    /// Number of stored blobs and their total size in bytes
+    async fn blob_usage(fs: &AgentFS) -> Result<(i64, i64)> {
+        let conn = fs.get_connection().await?;
+        let mut rows = conn
+            .query(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(data)), 0) FROM fs_blob",
+                (),
+            )
             .await?;
-        file.pwrite(0, &data).await?;
+        let row = rows.next().await?.unwrap();
+        let get = |i: usize| {
+            row.get_value(i)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0)
+        };
+        Ok((get(0), get(1)))
+    }
 
-        // Read it back
-        let read_data = fs.read_file("/multi.txt").await?.unwrap();
-        assert_eq!(read_data.len(), data_size);
-        assert_eq!(read_data, data);
+    fn pseudo_random_data(len: usize) -> Vec<u8> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
 
-        // Verify 3 chunks were created
-        let ino = fs.resolve_path("/multi.txt").await?.unwrap();
-        let chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(chunk_count, 3);
+    #[tokio::test]
+    async fn test_dedup_identical_writes_share_storage() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.set_dedup(true).await?;
+        assert!(fs.dedup());
+
+        let data = pseudo_random_data(1 << 20);
+        fs.pwrite("/a.bin", 0, &data).await?;
+        fs.pwrite("/b.bin", 0, &data).await?;
+
+        // Both files together take up the size of one
+        let (blobs, bytes) = blob_usage(&fs).await?;
+        assert_eq!(blobs, (data.len() / fs.chunk_size()) as i64);
+        assert_eq!(bytes, data.len() as i64);
+        assert_eq!(fs.read_file("/a.bin").await?.unwrap(), data);
+        assert_eq!(
+            fs.pread("/b.bin", 5000, 100).await?.unwrap(),
+            &data[5000..5100]
+        );
+
+        // Overwriting one copy leaves the other intact
+        fs.pwrite("/a.bin", 0, b"changed").await?;
+        assert_eq!(blob_usage(&fs).await?.0, blobs + 1);
+        assert_eq!(fs.read_file("/b.bin").await?.unwrap(), data);
+
+        // Blobs go away with their last reference
+        fs.remove("/a.bin").await?;
+        assert_eq!(blob_usage(&fs).await?, (blobs, bytes));
+        fs.truncate("/b.bin", 0).await?;
+        assert_eq!(blob_usage(&fs).await?, (0, 0));
 
         Ok(())
     }
 
-    // ==================== Data Integrity Tests ====================
+    #[tokio::test]
+    async fn test_dedup_repeated_chunks_within_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.set_dedup(true).await?;
+        fs.set_compression(Some(CompressionKind::Zstd)).await?;
+
+        // Four identical chunks share a single compressed blob
+        let data = vec![b'a'; 4 * fs.chunk_size()];
+        fs.pwrite("/same.txt", 0, &data).await?;
+        let (blobs, bytes) = blob_usage(&fs).await?;
+        assert_eq!(blobs, 1);
+        assert!(bytes < fs.chunk_size() as i64);
+        assert_eq!(fs.read_file("/same.txt").await?.unwrap(), data);
+
+        // Shrinking drops one reference per removed chunk
+        fs.truncate("/same.txt", fs.chunk_size() as u64).await?;
+        assert_eq!(blob_usage(&fs).await?.0, 1);
+        fs.remove("/same.txt").await?;
+        assert_eq!(blob_usage(&fs).await?, (0, 0));
+
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn test_roundtrip_byte_for_byte() -> Result<()> {
+    async fn test_dedup_restore_releases_blobs() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_dedup(true).await?;
+        let data = pseudo_random_data(3 * fs.chunk_size());
+        fs.pwrite("/kept.bin", 0, &data).await?;
 
-        // Create data that spans chunk boundaries with identifiable patterns
-        let chunk_size = fs.chunk_size();
-        let data_size = chunk_size * 3 + 123; // Odd size spanning 4 chunks
+        fs.snapshot("before").await?;
+        fs.pwrite("/copy.bin", 0, &data).await?;
+        fs.pwrite("/new.bin", 0, b"only after the snapshot").await?;
+        assert_eq!(blob_usage(&fs).await?.0, 4);
 
-        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/roundtrip.bin", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
+        fs.restore("before").await?;
+        assert_eq!(blob_usage(&fs).await?.0, 3);
+        assert_eq!(fs.read_file("/kept.bin").await?.unwrap(), data);
 
-        let read_data = fs.read_file("/roundtrip.bin").await?.unwrap();
-        assert_eq!(read_data.len(), data_size);
-        assert_eq!(read_data, data, "Data mismatch after roundtrip");
+        // Turning dedup off stores new data inline again
+        fs.set_dedup(false).await?;
+        fs.pwrite("/inline.bin", 0, &data).await?;
+        assert_eq!(blob_usage(&fs).await?.0, 3);
+        assert_eq!(fs.read_file("/inline.bin").await?.unwrap(), data);
 
         Ok(())
     }
 
+    // ==================== Change Event Tests ====================
+
+    fn drain_events(rx: &mut broadcast::Receiver<ChangeEvent>) -> Vec<(ChangeEventKind, String)> {
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push((event.kind, event.path));
+        }
+        events
+    }
+
     #[tokio::test]
-    async fn test_binary_data_with_null_bytes() -> Result<()> {
+    async fn test_change_events_path_api() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        let mut rx = fs.subscribe();
 
-        let chunk_size = fs.chunk_size();
-        // Create data with null bytes at chunk boundaries
-        let mut data = vec![0u8; chunk_size * 2 + 100];
-        // Put nulls at the chunk boundary
-        data[chunk_size - 1] = 0;
-        data[chunk_size] = 0;
-        data[chunk_size + 1] = 0;
-        // Put some non-null bytes around
-        data[chunk_size - 2] = 0xFF;
-        data[chunk_size + 2] = 0xFF;
-
-        let (_, file) = fs
-            .create_file("/nulls.bin", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
-        let read_data = fs.read_file("/nulls.bin").await?.unwrap();
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/dir/a.txt", 0, b"hello").await?;
+        fs.pwrite("/dir/a.txt", 5, b" world").await?;
+        fs.truncate("/dir/a.txt", 5).await?;
+        fs.rename("/dir/a.txt", "/b.txt").await?;
+        fs.remove("/b.txt").await?;
 
-        assert_eq!(read_data, data, "Null bytes at chunk boundary corrupted");
+        use ChangeEventKind::*;
+        assert_eq!(
+            drain_events(&mut rx),
+            vec![
+                (Mkdir, "/dir".to_string()),
+                (Create, "/dir/a.txt".to_string()),
+                (Write, "/dir/a.txt".to_string()),
+                (Write, "/dir/a.txt".to_string()),
+                (Rename, "/dir/a.txt".to_string()),
+                (Rename, "/b.txt".to_string()),
+                (Remove, "/b.txt".to_string()),
+            ]
+        );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_chunk_ordering() -> Result<()> {
+    async fn test_change_events_inode_api() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        let dir = FileSystem::mkdir(&fs, ROOT_INO, "dir", DEFAULT_DIR_MODE, 0, 0).await?;
+        let mut rx = fs.subscribe();
 
-        let chunk_size = fs.chunk_size();
-        // Create sequential bytes spanning multiple chunks
-        let data_size = chunk_size * 5;
-        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/sequential.bin", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
+        let (stats, file) = FileSystem::create_file(&fs, dir.ino, "f", 0o644, 0, 0).await?;
+        file.pwrite(0, b"data").await?;
+        file.truncate(0).await?;
+        FileSystem::link(&fs, stats.ino, ROOT_INO, "hard").await?;
+        FileSystem::unlink(&fs, dir.ino, "f").await?;
+        FileSystem::rmdir(&fs, ROOT_INO, "dir").await?;
 
-        let read_data = fs.read_file("/sequential.bin").await?.unwrap();
+        use ChangeEventKind::*;
+        assert_eq!(
+            drain_events(&mut rx),
+            vec![
+                (Create, "/dir/f".to_string()),
+                (Write, "/dir/f".to_string()),
+                (Write, "/dir/f".to_string()),
+                (Create, "/hard".to_string()),
+                (Remove, "/dir/f".to_string()),
+                (Remove, "/dir".to_string()),
+            ]
+        );
 
-        // Verify every byte is in the correct position
-        for (i, (&expected, &actual)) in data.iter().zip(read_data.iter()).enumerate() {
-            assert_eq!(
-                expected, actual,
-                "Byte mismatch at position {}: expected {}, got {}",
-                i, expected, actual
-            );
-        }
+        // Events are shared across clones
+        let clone = fs.clone();
+        clone.mkdir("/other", 0, 0).await?;
+        assert_eq!(drain_events(&mut rx), vec![(Mkdir, "/other".to_string())]);
 
         Ok(())
     }
 
-    // ==================== Edge Case Tests ====================
+    // ==================== Mkdir All Tests ====================
 
     #[tokio::test]
-    async fn test_empty_file() -> Result<()> {
+    async fn test_mkdir_all_creates_missing_ancestors() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Write empty file
-        let (_, file) = fs
-            .create_file("/empty.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &[]).await?;
-
-        // Read it back
-        let read_data = fs.read_file("/empty.txt").await?.unwrap();
-        assert!(read_data.is_empty());
-
-        // Verify 0 chunks were created
-        let ino = fs.resolve_path("/empty.txt").await?.unwrap();
-        let chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(chunk_count, 0);
-
-        // Verify size is 0
-        let stats = fs.stat("/empty.txt").await?.unwrap();
-        assert_eq!(stats.size, 0);
+        fs.mkdir("/a", 0, 0).await?;
+        let a = fs.stat("/a").await?.unwrap();
+
+        let stats = FileSystem::mkdir_all(&fs, "/a/b/c/", 0o700, 1000, 1000).await?;
+        assert!(stats.is_directory());
+        assert_eq!(stats.mode & 0o7777, 0o700);
+        assert_eq!(fs.stat("/a/b/c").await?.unwrap().ino, stats.ino);
+        // Existing ancestors are kept, and nlink counts the new subdirectory
+        let a_after = fs.stat("/a").await?.unwrap();
+        assert_eq!(a_after.ino, a.ino);
+        assert_eq!(a_after.nlink, 3);
+
+        // Repeating it is a no-op that returns the same directory
+        let again = FileSystem::mkdir_all(&fs, "/a/b/c", 0o755, 0, 0).await?;
+        assert_eq!(again.ino, stats.ino);
+        assert_eq!(again.mode & 0o7777, 0o700);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_overwrite_existing_file() -> Result<()> {
+    async fn test_mkdir_all_non_directory_ancestor() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/a", 0, 0).await?;
+        fs.pwrite("/a/file", 0, b"x").await?;
+
+        let result = FileSystem::mkdir_all(&fs, "/a/file/sub", 0o755, 0, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotADirectory))
+        ));
+        let result = FileSystem::mkdir_all(&fs, "/a/file", 0o755, 0, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotADirectory))
+        ));
+
+        // Symlinks are not followed, even to directories
+        fs.symlink("/a", "/link", 0, 0).await?;
+        let result = FileSystem::mkdir_all(&fs, "/link/sub", 0o755, 0, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotADirectory))
+        ));
+        assert!(fs.lstat("/a/sub").await?.is_none());
 
-        let chunk_size = fs.chunk_size();
+        Ok(())
+    }
 
-        // Write initial large file (3 chunks)
-        let initial_data: Vec<u8> = (0..chunk_size * 3).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/overwrite.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &initial_data).await?;
+    // ==================== Remove All Tests ====================
 
-        let ino = fs.resolve_path("/overwrite.txt").await?.unwrap();
-        let initial_chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(initial_chunk_count, 3);
+    async fn count_rows(fs: &AgentFS, table: &str) -> Result<i64> {
+        let conn = fs.get_connection().await?;
+        let mut rows = conn
+            .query(&format!("SELECT COUNT(*) FROM {table}"), ())
+            .await?;
+        let row = rows.next().await?.unwrap();
+        Ok(row
+            .get_value(0)
+            .ok()
+            .and_then(|v| v.as_integer().copied())
+            .unwrap_or(0))
+    }
 
-        // Overwrite with smaller file (1 chunk)
-        let new_data = vec![42u8; 100];
-        fs.truncate("/overwrite.txt", 0).await?;
-        let file = fs.open("/overwrite.txt").await?;
-        file.pwrite(0, &new_data).await?;
+    #[tokio::test]
+    async fn test_remove_all_deep_tree() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/keep", 0, 0).await?;
+        fs.pwrite("/keep/outside.txt", 0, b"outside").await?;
+
+        // Five levels of directories, each with a file and a symlink
+        let mut dir = String::new();
+        for level in 1..=5 {
+            dir = format!("{dir}/d{level}");
+            fs.mkdir(&dir, 0, 0).await?;
+            let data = vec![level as u8; 3 * fs.chunk_size()];
+            fs.pwrite(&format!("{dir}/file"), 0, &data).await?;
+            fs.symlink("/keep", &format!("{dir}/link"), 0, 0).await?;
+        }
+        // A hard link from outside the tree keeps its data alive
+        fs.link("/d1/d2/d3/file", "/keep/hard").await?;
+        let root_nlink = fs.stat("/").await?.unwrap().nlink;
 
-        // Verify old chunks are gone and new data is correct
-        let read_data = fs.read_file("/overwrite.txt").await?.unwrap();
-        assert_eq!(read_data, new_data);
+        FileSystem::remove_all(&fs, "/d1").await?;
 
-        let new_chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(new_chunk_count, 1);
+        assert!(fs.lstat("/d1").await?.is_none());
+        assert!(fs.lstat("/d1/d2/d3/d4/d5/file").await?.is_none());
+        assert_eq!(fs.stat("/").await?.unwrap().nlink, root_nlink - 1);
+        assert_eq!(
+            fs.read_file("/keep/hard").await?.unwrap(),
+            vec![3u8; 3 * fs.chunk_size()]
+        );
+        assert_eq!(fs.stat("/keep/hard").await?.unwrap().nlink, 1);
+        assert_eq!(
+            fs.read_file("/keep/outside.txt").await?.unwrap(),
+            b"outside"
+        );
 
-        // Verify size is updated
-        let stats = fs.stat("/overwrite.txt").await?.unwrap();
-        assert_eq!(stats.size, 100);
+        // Only the surviving inodes and their chunks are left
+        assert_eq!(count_rows(&fs, "fs_inode").await?, 4);
+        assert_eq!(count_rows(&fs, "fs_data").await?, 4);
+        assert_eq!(count_rows(&fs, "fs_symlink").await?, 0);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_overwrite_with_larger_file() -> Result<()> {
+    async fn test_remove_all_guards() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.symlink("/dir", "/link", 0, 0).await?;
+
+        for root in ["/", "", "/dir/.."] {
+            let result = FileSystem::remove_all(&fs, root).await;
+            assert!(matches!(
+                result,
+                Err(crate::error::Error::Fs(FsError::RootOperation))
+            ));
+        }
+        let result = FileSystem::remove_all(&fs, "/missing").await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotFound))
+        ));
 
-        let chunk_size = fs.chunk_size();
-
-        // Write initial small file (1 chunk)
-        let initial_data = vec![1u8; 100];
-        let (_, file) = fs.create_file("/grow.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &initial_data).await?;
-
-        let ino = fs.resolve_path("/grow.txt").await?.unwrap();
-        assert_eq!(fs.get_chunk_count(ino).await?, 1);
+        // A symlink is removed itself, not the directory it points to
+        FileSystem::remove_all(&fs, "/link").await?;
+        assert!(fs.lstat("/link").await?.is_none());
+        assert!(fs.stat("/dir").await?.is_some());
 
-        // Overwrite with larger file (3 chunks)
-        let new_data: Vec<u8> = (0..chunk_size * 3).map(|i| (i % 256) as u8).collect();
-        fs.truncate("/grow.txt", 0).await?;
-        let file = fs.open("/grow.txt").await?;
-        file.pwrite(0, &new_data).await?;
+        Ok(())
+    }
 
-        // Verify data is correct
-        let read_data = fs.read_file("/grow.txt").await?.unwrap();
-        assert_eq!(read_data, new_data);
-        assert_eq!(fs.get_chunk_count(ino).await?, 3);
+    #[tokio::test]
+    async fn test_remove_file_and_remove_dir() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.mkdir("/full", 0, 0).await?;
+        fs.pwrite("/full/file", 0, b"data").await?;
+        fs.pwrite("/file", 0, b"data").await?;
+
+        assert!(matches!(
+            FileSystem::remove_file(&fs, "/dir").await,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
+        assert!(matches!(
+            FileSystem::remove_dir(&fs, "/file").await,
+            Err(crate::error::Error::Fs(FsError::NotADirectory))
+        ));
+        assert!(matches!(
+            FileSystem::remove_dir(&fs, "/full").await,
+            Err(crate::error::Error::Fs(FsError::NotEmpty))
+        ));
+        assert!(matches!(
+            FileSystem::remove_dir(&fs, "/").await,
+            Err(crate::error::Error::Fs(FsError::RootOperation))
+        ));
+        assert!(matches!(
+            FileSystem::remove_file(&fs, "/missing").await,
+            Err(crate::error::Error::Fs(FsError::NotFound))
+        ));
+
+        FileSystem::remove_file(&fs, "/file").await?;
+        FileSystem::remove_dir(&fs, "/dir").await?;
+        assert!(fs.stat("/file").await?.is_none());
+        assert!(fs.stat("/dir").await?.is_none());
+
+        // remove dispatches on what the path names
+        FileSystem::remove(&fs, "/full/file").await?;
+        FileSystem::remove(&fs, "/full").await?;
+        assert!(fs.stat("/full").await?.is_none());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_very_large_file() -> Result<()> {
+    async fn test_root_path_edge_cases() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        let root_size = fs.stat("/").await?.unwrap().size;
 
-        // Write 1MB file
-        let data_size = 1024 * 1024;
-        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/large.bin", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
-
-        let read_data = fs.read_file("/large.bin").await?.unwrap();
-        assert_eq!(read_data.len(), data_size);
-        assert_eq!(read_data, data);
+        for root in ["/", ""] {
+            for stats in [fs.stat(root).await?, fs.lstat(root).await?] {
+                let stats = stats.expect("root should exist");
+                assert_eq!(stats.ino, ROOT_INO);
+                assert!(stats.is_directory());
+            }
 
-        // Verify correct number of chunks
-        let chunk_size = fs.chunk_size();
-        let expected_chunks = data_size.div_ceil(chunk_size);
-        let ino = fs.resolve_path("/large.bin").await?.unwrap();
-        let actual_chunks = fs.get_chunk_count(ino).await? as usize;
-        assert_eq!(actual_chunks, expected_chunks);
+            for result in [
+                fs.remove(root).await,
+                fs.rename(root, "/moved").await,
+                fs.rename("/dir", root).await,
+            ] {
+                assert!(matches!(
+                    result,
+                    Err(crate::error::Error::Fs(FsError::RootOperation))
+                ));
+            }
+            for result in [fs.truncate(root, 0).await, fs.pwrite(root, 0, b"x").await] {
+                assert!(matches!(
+                    result,
+                    Err(crate::error::Error::Fs(FsError::IsADirectory))
+                ));
+            }
+            assert!(matches!(
+                fs.mkdir(root, 0, 0).await,
+                Err(crate::error::Error::Fs(FsError::AlreadyExists))
+            ));
+        }
+        assert!(matches!(
+            fs.truncate("/dir", 0).await,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
+        assert_eq!(FsError::RootOperation.to_errno(), libc::EBUSY);
+
+        assert_eq!(fs.stat("/").await?.unwrap().size, root_size);
+        assert!(fs.stat("/dir").await?.is_some());
 
         Ok(())
     }
 
-    // ==================== Configuration Tests ====================
+    // ==================== Copy Range Tests ====================
 
     #[tokio::test]
-    async fn test_default_chunk_size() -> Result<()> {
+    async fn test_copy_range_whole_file_shares_blobs() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        assert_eq!(fs.chunk_size(), DEFAULT_CHUNK_SIZE);
-        assert_eq!(fs.chunk_size(), 4096);
+        fs.set_dedup(true).await?;
+        let data = pseudo_random_data(3 * fs.chunk_size() + 100);
+        fs.pwrite("/src.bin", 0, &data).await?;
+        let (dst, _) = fs.create_file("/dst.bin", DEFAULT_FILE_MODE, 0, 0).await?;
+        let src = fs.stat("/src.bin").await?.unwrap();
+        let before = blob_usage(&fs).await?;
+
+        let copied = FileSystem::copy_range(&fs, src.ino, 0, dst.ino, 0, u64::MAX).await?;
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(fs.read_file("/dst.bin").await?.unwrap(), data);
+        // No new blobs: every chunk of the copy references the source's
+        assert_eq!(blob_usage(&fs).await?, before);
+
+        fs.remove("/src.bin").await?;
+        assert_eq!(blob_usage(&fs).await?, before);
+        assert_eq!(fs.read_file("/dst.bin").await?.unwrap(), data);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_chunk_size_accessor() -> Result<()> {
+    async fn test_copy_range_unaligned() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
+        fs.set_compression(Some(CompressionKind::Zstd)).await?;
         let chunk_size = fs.chunk_size();
-        assert!(chunk_size > 0);
-
-        // Write data and verify chunks match expected based on chunk_size
-        let data = vec![0u8; chunk_size * 2 + 1];
-        let (_, file) = fs.create_file("/test.bin", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
-
-        let ino = fs.resolve_path("/test.bin").await?.unwrap();
-        let chunk_count = fs.get_chunk_count(ino).await?;
-        assert_eq!(chunk_count, 3);
+        let src_data: Vec<u8> = (0..4 * chunk_size).map(|i| (i % 251) as u8).collect();
+        fs.pwrite("/src.bin", 0, &src_data).await?;
+        let mut expected = vec![0xAAu8; 3 * chunk_size];
+        fs.pwrite("/dst.bin", 0, &expected).await?;
+        let src = fs.stat("/src.bin").await?.unwrap();
+        let dst = fs.stat("/dst.bin").await?.unwrap();
+
+        // Misaligned on both sides, straddling chunk boundaries and growing
+        // the destination past its end
+        let (src_offset, dst_offset, len) = (100, chunk_size + 7, 2 * chunk_size + 50);
+        let copied = FileSystem::copy_range(
+            &fs,
+            src.ino,
+            src_offset as u64,
+            dst.ino,
+            dst_offset as u64,
+            len as u64,
+        )
+        .await?;
+        assert_eq!(copied, len as u64);
+        expected.resize(dst_offset + len, 0);
+        expected[dst_offset..].copy_from_slice(&src_data[src_offset..src_offset + len]);
+        assert_eq!(fs.read_file("/dst.bin").await?.unwrap(), expected);
+
+        // The copy stops at the end of the source
+        let copied =
+            FileSystem::copy_range(&fs, src.ino, (src_data.len() - 10) as u64, dst.ino, 0, 100)
+                .await?;
+        assert_eq!(copied, 10);
+        let copied =
+            FileSystem::copy_range(&fs, src.ino, src_data.len() as u64, dst.ino, 0, 100).await?;
+        assert_eq!(copied, 0);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_config_persistence() -> Result<()> {
+    async fn test_copy_range_errors() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/file", 0, &[1u8; 100]).await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        let file = fs.stat("/file").await?.unwrap();
+        let dir = fs.stat("/dir").await?.unwrap();
+
+        let result = FileSystem::copy_range(&fs, file.ino, 0, file.ino, 50, 60).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::InvalidPath))
+        ));
+        let result = FileSystem::copy_range(&fs, file.ino, 0, dir.ino, 0, 10).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
+
+        // Disjoint ranges within one file are fine
+        assert_eq!(
+            FileSystem::copy_range(&fs, file.ino, 0, file.ino, 100, 100).await?,
+            100
+        );
+        assert_eq!(fs.stat("/file").await?.unwrap().size, 200);
 
-        // Query fs_config table directly
-        let conn = fs.pool.get_connection().await?;
+        Ok(())
+    }
+
+    // ==================== Reflink Tests ====================
+
+    /// Total bytes stored for file contents, inline and in blobs
+    async fn stored_bytes(fs: &AgentFS) -> Result<i64> {
+        let conn = fs.get_connection().await?;
         let mut rows = conn
-            .query("SELECT value FROM fs_config WHERE key = 'chunk_size'", ())
+            .query(
+                "SELECT (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM fs_data)
+                    + (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM fs_blob)",
+                (),
+            )
             .await?;
+        Ok(row_integer(&rows.next().await?.unwrap(), 0))
+    }
 
-        let row = rows.next().await?.expect("chunk_size config should exist");
-        let value = row
-            .get_value(0)
-            .ok()
-            .and_then(|v| match v {
-                Value::Text(s) => Some(s.clone()),
-                _ => None,
-            })
-            .expect("chunk_size should be a text value");
-
-        assert_eq!(value, "4096");
+    #[tokio::test]
+    async fn test_reflink_shares_storage_until_written() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let data = pseudo_random_data(1024 * 1024);
+        fs.pwrite("/fixture.bin", 0, &data).await?;
+        let fixture = fs.stat("/fixture.bin").await?.unwrap();
+        FileSystem::chmod(&fs, fixture.ino, 0o600).await?;
+        let before = stored_bytes(&fs).await?;
+
+        FileSystem::reflink(&fs, "/fixture.bin", "/clone.bin").await?;
+        assert_eq!(stored_bytes(&fs).await?, before);
+        let clone = fs.stat("/clone.bin").await?.unwrap();
+        assert_eq!(clone.mode & 0o7777, 0o600);
+        assert_eq!(fs.read_file("/clone.bin").await?.unwrap(), data);
+
+        // Writing one byte of the clone stores one new chunk, not a copy of
+        // the file
+        fs.pwrite("/clone.bin", 12345, b"!").await?;
+        assert_eq!(fs.read_file("/fixture.bin").await?.unwrap(), data);
+        let mut expected = data.clone();
+        expected[12345] = b'!';
+        assert_eq!(fs.read_file("/clone.bin").await?.unwrap(), expected);
+        assert_eq!(stored_bytes(&fs).await?, before + fs.chunk_size() as i64);
+
+        // Each file keeps its data once the other is gone
+        fs.remove("/fixture.bin").await?;
+        assert_eq!(fs.read_file("/clone.bin").await?.unwrap(), expected);
+        assert_eq!(stored_bytes(&fs).await?, before);
 
         Ok(())
     }
 
-    // ==================== Schema Tests ====================
-
     #[tokio::test]
-    async fn test_chunk_index_uniqueness() -> Result<()> {
+    async fn test_reflink_errors() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/file", 0, b"data").await?;
+        fs.mkdir("/dir", 0, 0).await?;
 
-        // Write a file to create chunks
-        let chunk_size = fs.chunk_size();
-        let data = vec![0u8; chunk_size * 2];
-        let (_, file) = fs
-            .create_file("/unique.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
-
-        let ino = fs.resolve_path("/unique.txt").await?.unwrap();
-
-        // Try to insert a duplicate chunk - should fail due to PRIMARY KEY constraint
-        let conn = fs.pool.get_connection().await?;
-        let result = conn
-            .execute(
-                "INSERT INTO fs_data (ino, chunk_index, data) VALUES (?, 0, ?)",
-                (ino, vec![1u8; 10]),
-            )
-            .await;
-
-        assert!(result.is_err(), "Duplicate chunk_index should be rejected");
+        assert!(matches!(
+            FileSystem::reflink(&fs, "/file", "/dir").await,
+            Err(Error::Fs(FsError::AlreadyExists))
+        ));
+        assert!(matches!(
+            FileSystem::reflink(&fs, "/dir", "/copy").await,
+            Err(Error::Fs(FsError::IsADirectory))
+        ));
+        assert!(matches!(
+            FileSystem::reflink(&fs, "/missing", "/copy").await,
+            Err(Error::Fs(FsError::NotFound))
+        ));
+        assert!(matches!(
+            FileSystem::reflink(&fs, "/file", "/missing/copy").await,
+            Err(Error::Fs(FsError::NotFound))
+        ));
+        assert!(fs.stat("/copy").await?.is_none());
 
         Ok(())
     }
 
+    // ==================== Lock Tests ====================
+
     #[tokio::test]
-    async fn test_chunk_ordering_in_database() -> Result<()> {
+    async fn test_locks_shared_across_clones() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        let (stats, _) = fs.create_file("/locked", DEFAULT_FILE_MODE, 0, 0).await?;
+        let clone = fs.clone();
+
+        FileSystem::lock(&fs, stats.ino, LockType::Exclusive, 1).await?;
+        let result = FileSystem::lock(&clone, stats.ino, LockType::Shared, 2).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::WouldBlock))
+        ));
+        FileSystem::unlock(&fs, stats.ino, 1).await?;
+        FileSystem::lock(&clone, stats.ino, LockType::Shared, 2).await?;
+
+        let result = FileSystem::lock(&fs, 99999, LockType::Shared, 1).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotFound))
+        ));
+
+        // Removing one of several links keeps the inode and its locks
+        fs.link("/locked", "/other").await?;
+        FileSystem::unlink(&fs, ROOT_INO, "locked").await?;
+        let result = FileSystem::lock(&fs, stats.ino, LockType::Exclusive, 1).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::WouldBlock))
+        ));
 
-        let chunk_size = fs.chunk_size();
-        // Create 5 chunks with identifiable data
-        let data_size = chunk_size * 5;
-        let data: Vec<u8> = (0..data_size).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs
-            .create_file("/ordered.bin", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
-
-        let ino = fs.resolve_path("/ordered.bin").await?.unwrap();
+        Ok(())
+    }
 
-        // Query chunks in order
-        let conn = fs.pool.get_connection().await?;
-        let mut rows = conn
-            .query(
-                "SELECT chunk_index FROM fs_data WHERE ino = ? ORDER BY chunk_index",
-                (ino,),
-            )
-            .await?;
+    // ==================== Append Tests ====================
 
-        let mut indices = Vec::new();
-        while let Some(row) = rows.next().await? {
-            let idx = row
-                .get_value(0)
-                .ok()
-                .and_then(|v| v.as_integer().copied())
-                .unwrap_or(-1);
-            indices.push(idx);
+    #[tokio::test]
+    async fn test_concurrent_appends_do_not_overwrite() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/log", 0, b"").await?;
+
+        // Records straddle chunk boundaries so appends share chunks
+        let record_len = 1000;
+        let records = 20;
+        let mut handles = vec![];
+        for byte in [b'a', b'b'] {
+            let fs = fs.clone();
+            handles.push(tokio::spawn(async move {
+                let record = vec![byte; record_len];
+                for _ in 0..records {
+                    FileSystem::append(&fs, "/log", &record).await?;
+                }
+                Ok::<_, crate::error::Error>(())
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap()?;
         }
 
-        assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+        let data = fs.read_file("/log").await?.unwrap();
+        assert_eq!(data.len(), 2 * records * record_len);
+        for record in data.chunks(record_len) {
+            assert!(record.iter().all(|&b| b == record[0]));
+        }
+        assert_eq!(
+            data.iter().filter(|&&b| b == b'a').count(),
+            records * record_len
+        );
 
         Ok(())
     }
 
-    // ==================== Cleanup Tests ====================
-
     #[tokio::test]
-    async fn test_delete_file_removes_all_chunks() -> Result<()> {
+    async fn test_append_returns_new_size() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/log", 0, b"hello").await?;
+        fs.symlink("/log", "/link", 0, 0).await?;
+
+        assert_eq!(FileSystem::append(&fs, "/log", b" world").await?, 11);
+        assert_eq!(FileSystem::append(&fs, "/link", b"!").await?, 12);
+        assert_eq!(FileSystem::append(&fs, "/log", b"").await?, 12);
+        assert_eq!(fs.read_file("/log").await?.unwrap(), b"hello world!");
+
+        let result = FileSystem::append(&fs, "/missing", b"x").await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotFound))
+        ));
+        fs.mkdir("/dir", 0, 0).await?;
+        let result = FileSystem::append(&fs, "/dir", b"x").await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
 
-        let chunk_size = fs.chunk_size();
-        // Create multi-chunk file
-        let data = vec![0u8; chunk_size * 4];
-        let (_, file) = fs
-            .create_file("/deleteme.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, &data).await?;
-
-        let ino = fs.resolve_path("/deleteme.txt").await?.unwrap();
-        assert_eq!(fs.get_chunk_count(ino).await?, 4);
-
-        // Delete the file
-        fs.remove("/deleteme.txt").await?;
+        Ok(())
+    }
 
-        // Verify all chunks are gone
-        let conn = fs.pool.get_connection().await?;
-        let mut rows = conn
-            .query("SELECT COUNT(*) FROM fs_data WHERE ino = ?", (ino,))
-            .await?;
+    #[tokio::test]
+    async fn test_open_append_ignores_offset() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/log", 0, b"one").await?;
+        let ino = fs.resolve_path("/log").await?.unwrap();
 
-        let count = rows
-            .next()
-            .await?
-            .and_then(|r| r.get_value(0).ok().and_then(|v| v.as_integer().copied()))
-            .unwrap_or(-1);
+        let first = FileSystem::open(&fs, ino, libc::O_WRONLY | libc::O_APPEND).await?;
+        let second = FileSystem::open(&fs, ino, libc::O_WRONLY | libc::O_APPEND).await?;
+        // Both handles start from the same stale offset
+        first.pwrite(3, b"two").await?;
+        second.pwrite(3, b"six").await?;
+        assert_eq!(fs.read_file("/log").await?.unwrap(), b"onetwosix");
 
-        assert_eq!(count, 0, "All chunks should be deleted");
+        // Without O_APPEND the offset is honoured
+        let plain = FileSystem::open(&fs, ino, libc::O_WRONLY).await?;
+        plain.pwrite(0, b"ONE").await?;
+        assert_eq!(fs.read_file("/log").await?.unwrap(), b"ONEtwosix");
 
         Ok(())
     }
 
+    // ==================== Atomic Write Tests ====================
+
     #[tokio::test]
-    async fn test_multiple_files_different_sizes() -> Result<()> {
+    async fn test_atomic_write_replaces_file_keeping_mode() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/file", 0, b"old contents").await?;
+        let old = fs.stat("/file").await?.unwrap();
+        FileSystem::chmod(&fs, old.ino, 0o600).await?;
+        FileSystem::chown(&fs, old.ino, Some(1000), Some(1000)).await?;
+
+        FileSystem::atomic_write(&fs, "/file", b"new").await?;
+        let new = fs.stat("/file").await?.unwrap();
+        assert_ne!(new.ino, old.ino);
+        assert_eq!(new.mode, S_IFREG | 0o600);
+        assert_eq!((new.uid, new.gid, new.size), (1000, 1000, 3));
+        assert_eq!(fs.read_file("/file").await?.unwrap(), b"new");
+        // The old inode and its data are gone
+        assert!(FileSystem::getattr(&fs, old.ino).await?.is_none());
+        assert_eq!(fs.get_chunk_count(old.ino).await?, 0);
+
+        // A new file gets the default mode, and nothing else is left behind
+        FileSystem::atomic_write(&fs, "/created", b"").await?;
+        assert_eq!(fs.stat("/created").await?.unwrap().mode, DEFAULT_FILE_MODE);
+        let mut names = FileSystem::readdir(&fs, 1).await?.unwrap();
+        names.sort();
+        assert_eq!(names, vec!["created", "file"]);
 
-        let chunk_size = fs.chunk_size();
+        fs.mkdir("/dir", 0, 0).await?;
+        assert!(matches!(
+            FileSystem::atomic_write(&fs, "/dir", b"x").await,
+            Err(Error::Fs(FsError::IsADirectory))
+        ));
+        assert!(matches!(
+            FileSystem::atomic_write(&fs, "/missing/file", b"x").await,
+            Err(Error::Fs(FsError::NotFound))
+        ));
 
-        // Create files of various sizes
-        let files = vec![
-            ("/tiny.txt", 10),
-            ("/small.txt", chunk_size / 2),
-            ("/exact.txt", chunk_size),
-            ("/medium.txt", chunk_size * 2 + 100),
-            ("/large.txt", chunk_size * 5),
-        ];
+        Ok(())
+    }
 
-        for (path, size) in &files {
-            let data: Vec<u8> = (0..*size).map(|i| (i % 256) as u8).collect();
-            let (_, file) = fs.create_file(path, DEFAULT_FILE_MODE, 0, 0).await?;
-            file.pwrite(0, &data).await?;
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_atomic_write_readers_never_see_partial_contents() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        // Each version spans several chunks, is filled with one byte and has
+        // a length of its own, so a mix of two versions shows up
+        let version = |i: usize| vec![b'a' + (i % 26) as u8; 3 * 4096 + 100 * i];
+        FileSystem::atomic_write(&fs, "/file", &version(0)).await?;
+
+        let writer = {
+            let fs = fs.clone();
+            tokio::spawn(async move {
+                for i in 1..50 {
+                    FileSystem::atomic_write(&fs, "/file", &version(i)).await?;
+                }
+                Ok::<_, Error>(())
+            })
+        };
+        let mut reads = 0;
+        while !writer.is_finished() {
+            let data = fs.read_file("/file").await?.unwrap();
+            let i = (data.len() - 3 * 4096) / 100;
+            assert_eq!(data, version(i), "torn read of version {}", i);
+            reads += 1;
         }
+        writer.await.unwrap()?;
+        assert!(reads > 0);
+        assert_eq!(fs.read_file("/file").await?.unwrap(), version(49));
 
-        // Verify each file has correct data and chunk count
-        for (path, size) in &files {
-            let read_data = fs.read_file(path).await?.unwrap();
-            assert_eq!(read_data.len(), *size, "Size mismatch for {}", path);
+        Ok(())
+    }
 
-            let expected_data: Vec<u8> = (0..*size).map(|i| (i % 256) as u8).collect();
-            assert_eq!(read_data, expected_data, "Data mismatch for {}", path);
+    // ==================== Sparse File Tests ====================
 
-            let expected_chunks = size.div_ceil(chunk_size);
-            let ino = fs.resolve_path(path).await?.unwrap();
-            let actual_chunks = fs.get_chunk_count(ino).await? as usize;
-            assert_eq!(
-                actual_chunks, expected_chunks,
-                "Chunk count mismatch for {}",
-                path
-            );
-        }
+    #[tokio::test]
+    async fn test_sparse_file_blocks() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size() as u64;
+        fs.pwrite("/dense", 0, &pseudo_random_data(10_000)).await?;
+        assert_eq!(fs.stat("/dense").await?.unwrap().blocks, 20);
+
+        // Growing to 1 GiB stores nothing
+        fs.pwrite("/sparse", 0, b"").await?;
+        fs.truncate("/sparse", 1 << 30).await?;
+        let stats = fs.stat("/sparse").await?.unwrap();
+        assert_eq!(stats.size, 1 << 30);
+        assert_eq!(stats.blocks, 0);
+
+        // A small write in the middle only stores its own chunk
+        let offset = 512 * 1024 * 1024 + 10;
+        fs.pwrite("/sparse", offset, b"island").await?;
+        let stats = fs.stat("/sparse").await?.unwrap();
+        assert_eq!(stats.size, 1 << 30);
+        assert!(stats.blocks <= chunk_size.div_ceil(512));
+        assert_eq!(fs.get_chunk_count(stats.ino).await?, 1);
+
+        assert_eq!(
+            fs.pread("/sparse", offset - 4, 12).await?.unwrap(),
+            b"\0\0\0\0island\0\0"
+        );
+        assert_eq!(fs.pread("/sparse", 0, 16).await?.unwrap(), vec![0u8; 16]);
+        let entries = fs.readdir_plus(ROOT_INO).await?.unwrap();
+        let entry = entries.iter().find(|e| e.name == "sparse").unwrap();
+        assert_eq!(entry.stats.blocks, stats.blocks);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_pread_basic() -> Result<()> {
+    async fn test_blocks_follow_chunk_changes() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size() as u64;
 
-        // Write a file with known content
-        let data: Vec<u8> = (0..100).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
-
-        // Read from the beginning
-        let result = fs.pread("/test.txt", 0, 10).await?.unwrap();
-        assert_eq!(result, &data[0..10]);
-
-        // Read from the middle
-        let result = fs.pread("/test.txt", 50, 20).await?.unwrap();
-        assert_eq!(result, &data[50..70]);
+        // The blocks kept on the inode match a recount of its chunks after
+        // every kind of chunk change
+        async fn assert_blocks(fs: &AgentFS, path: &str) -> Result<()> {
+            let stats = fs.stat(path).await?.unwrap();
+            let conn = fs.get_connection().await?;
+            let mut rows = conn
+                .query(
+                    "SELECT (COALESCE(SUM(LENGTH(COALESCE(b.data, d.data))), 0) + 511) / 512
+                    FROM fs_data d LEFT JOIN fs_blob b ON b.hash = d.hash WHERE d.ino = ?",
+                    (stats.ino,),
+                )
+                .await?;
+            let expected = row_integer(&rows.next().await?.unwrap(), 0);
+            assert_eq!(stats.blocks, expected as u64, "{path}");
+            Ok(())
+        }
 
-        // Read from near the end
-        let result = fs.pread("/test.txt", 90, 10).await?.unwrap();
-        assert_eq!(result, &data[90..100]);
+        let data = pseudo_random_data(3 * chunk_size as usize);
+        fs.pwrite("/file", 0, &data).await?;
+        assert_blocks(&fs, "/file").await?;
+        fs.pwrite("/file", chunk_size + 10, b"patch").await?;
+        assert_blocks(&fs, "/file").await?;
+        fs.truncate("/file", chunk_size + 1).await?;
+        assert_blocks(&fs, "/file").await?;
+
+        // Shared chunks count for each file, at the size of their blob
+        fs.set_dedup(true).await?;
+        fs.set_compression(Some(CompressionKind::Zstd)).await?;
+        fs.pwrite("/copy", 0, &vec![7u8; 2 * chunk_size as usize])
+            .await?;
+        FileSystem::reflink(&fs, "/file", "/clone").await?;
+        fs.pwrite("/clone", 0, &vec![7u8; chunk_size as usize])
+            .await?;
+        for path in ["/file", "/copy", "/clone"] {
+            assert_blocks(&fs, path).await?;
+        }
+        fs.remove("/copy").await?;
+        fs.truncate("/clone", 0).await?;
+        assert_eq!(fs.stat("/clone").await?.unwrap().blocks, 0);
+        assert_blocks(&fs, "/file").await?;
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_pread_past_eof() -> Result<()> {
+    async fn test_data_ranges_skip_holes() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        let chunk_size = fs.chunk_size() as u64;
+        fs.pwrite("/sparse", 0, b"head").await?;
+        fs.pwrite(
+            "/sparse",
+            10 * chunk_size,
+            &vec![1u8; chunk_size as usize + 1],
+        )
+        .await?;
+        fs.truncate("/sparse", 100 * chunk_size + 7).await?;
 
-        let data: Vec<u8> = (0..50).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
-
-        // Read starting past EOF should return empty
-        let result = fs.pread("/test.txt", 100, 10).await?.unwrap();
-        assert!(result.is_empty());
+        // Adjacent chunks merge, and the hole at the end has no range
+        assert_eq!(
+            fs.data_ranges("/sparse").await?.unwrap(),
+            vec![(0, chunk_size), (10 * chunk_size, 2 * chunk_size)]
+        );
 
-        // Read that extends past EOF should return only available data
-        let result = fs.pread("/test.txt", 40, 20).await?.unwrap();
-        assert_eq!(result, &data[40..50]);
+        // The last chunk is clipped to the file size
+        fs.truncate("/sparse", 10 * chunk_size + 3).await?;
+        assert_eq!(
+            fs.data_ranges("/sparse").await?.unwrap(),
+            vec![(0, chunk_size), (10 * chunk_size, 3)]
+        );
+        assert!(fs.data_ranges("/missing").await?.is_none());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_pread_nonexistent_file() -> Result<()> {
+    async fn test_sparse_file_reads_holes_as_zeros() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        let result = fs.pread("/nonexistent.txt", 0, 10).await?;
-        assert!(result.is_none());
+        let chunk_size = fs.chunk_size();
+        let (_, file) = fs.create_file("/holes", DEFAULT_FILE_MODE, 0, 0).await?;
+        file.truncate(4 * chunk_size as u64).await?;
+        file.pwrite(chunk_size as u64 + 1, b"ab").await?;
+        // Only the written chunk is stored, up to the end of the write
+        assert_eq!(file.fstat().await?.blocks, 1);
+
+        let mut expected = vec![0u8; 4 * chunk_size];
+        expected[chunk_size + 1..chunk_size + 3].copy_from_slice(b"ab");
+        assert_eq!(fs.read_file("/holes").await?.unwrap(), expected);
+        assert_eq!(
+            fs.pread("/holes", chunk_size as u64 - 1, 4).await?.unwrap(),
+            b"\0\0ab"
+        );
+        assert_eq!(file.pread(0, u64::MAX).await?, expected);
 
         Ok(())
     }
 
+    // ==================== Large File Tests ====================
+
     #[tokio::test]
-    async fn test_pread_across_chunks() -> Result<()> {
+    async fn test_large_file_offsets() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-        let chunk_size = fs.chunk_size();
+        let size = 5 * 1024 * 1024 * 1024;
+        fs.pwrite("/big", 0, b"").await?;
+        fs.truncate("/big", size).await?;
+        assert_eq!(fs.stat("/big").await?.unwrap().size as u64, size);
+
+        // Offsets past 4 GiB must not wrap to the start of the file
+        let offset = 5_000_000_000;
+        fs.pwrite("/big", offset, b"far away").await?;
+        assert_eq!(fs.pread("/big", offset, 8).await?.unwrap(), b"far away");
+        assert_eq!(fs.pread("/big", offset - 2, 4).await?.unwrap(), b"\0\0fa");
+        assert_eq!(fs.pread("/big", 0, 8).await?.unwrap(), vec![0u8; 8]);
+        assert_eq!(fs.pread("/big", size, 8).await?.unwrap(), b"");
+
+        let file = fs.open("/big").await?;
+        file.pwrite(size, b"tail").await?;
+        let stats = file.fstat().await?;
+        assert_eq!(stats.size as u64, size + 4);
+        assert_eq!(fs.get_chunk_count(stats.ino).await?, 2);
 
-        // Create data spanning multiple chunks
-        let data: Vec<u8> = (0..(chunk_size * 3)).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        Ok(())
+    }
 
-        // Read across chunk boundary
-        let start = chunk_size - 10;
-        let result = fs.pread("/test.txt", start as u64, 20).await?.unwrap();
-        assert_eq!(result, &data[start..start + 20]);
+    #[tokio::test]
+    async fn test_write_past_max_file_size() -> Result<()> {
+        use super::super::MAX_FILE_SIZE;
 
-        // Read spanning multiple chunks
-        let start = chunk_size / 2;
-        let size = chunk_size * 2;
-        let result = fs
-            .pread("/test.txt", start as u64, size as u64)
-            .await?
-            .unwrap();
-        assert_eq!(result, &data[start..start + size]);
+        let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/big", 0, b"data").await?;
+        assert!(matches!(
+            fs.pwrite("/big", MAX_FILE_SIZE, b"x").await,
+            Err(Error::Fs(FsError::FileTooLarge))
+        ));
+        assert!(matches!(
+            fs.truncate("/big", u64::MAX).await,
+            Err(Error::Fs(FsError::FileTooLarge))
+        ));
+        let file = fs.open("/big").await?;
+        assert!(matches!(
+            file.pwrite(u64::MAX - 1, b"xyz").await,
+            Err(Error::Fs(FsError::FileTooLarge))
+        ));
+        assert_eq!(FsError::FileTooLarge.to_errno(), libc::EFBIG);
+
+        // Failed writes leave the file as it was
+        assert_eq!(fs.read_file("/big").await?.unwrap(), b"data");
 
         Ok(())
     }
 
+    // ==================== Create Tests ====================
+
     #[tokio::test]
-    async fn test_pwrite_basic() -> Result<()> {
+    async fn test_create_applies_mode() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
 
-        // Write initial data
-        let data: Vec<u8> = vec![0; 100];
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        FileSystem::create(&fs, "/dir/new", 0o600, libc::O_EXCL).await?;
+        let stats = fs.lstat("/dir/new").await?.unwrap();
+        assert!(stats.is_file());
+        assert_eq!(stats.mode & 0o7777, 0o600);
+        assert_eq!(stats.size, 0);
 
-        // Overwrite in the middle
-        fs.pwrite("/test.txt", 50, &[1, 2, 3, 4, 5]).await?;
+        let result = FileSystem::create(&fs, "/dir/new", 0o644, libc::O_EXCL).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::AlreadyExists))
+        ));
+        let result = FileSystem::create(&fs, "/missing/new", 0o644, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotFound))
+        ));
+        let result = FileSystem::create(&fs, "/dir", 0o644, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
 
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert_eq!(result.len(), 100);
-        assert_eq!(&result[50..55], &[1, 2, 3, 4, 5]);
-        assert_eq!(&result[0..50], &vec![0u8; 50][..]);
-        assert_eq!(&result[55..100], &vec![0u8; 45][..]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_existing_file() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/file", 0, b"contents").await?;
+        let ino = fs.lstat("/file").await?.unwrap().ino;
+
+        // Without O_TRUNC the file and its mode are left alone
+        FileSystem::create(&fs, "/file", 0o600, 0).await?;
+        let stats = fs.lstat("/file").await?.unwrap();
+        assert_eq!(stats.ino, ino);
+        assert_eq!(stats.mode & 0o7777, 0o644);
+        assert_eq!(fs.read_file("/file").await?.unwrap(), b"contents");
+
+        FileSystem::create(&fs, "/file", 0o600, libc::O_TRUNC).await?;
+        let stats = fs.lstat("/file").await?.unwrap();
+        assert_eq!(stats.ino, ino);
+        assert_eq!(stats.size, 0);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_pwrite_extend_file() -> Result<()> {
+    async fn test_create_follows_symlink() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/dir/file", 0, b"contents").await?;
+        let ino = fs.lstat("/dir/file").await?.unwrap().ino;
+
+        // A link to an existing file opens the file
+        fs.symlink("dir/file", "/link", 0, 0).await?;
+        FileSystem::create(&fs, "/link", 0o600, libc::O_TRUNC).await?;
+        let stats = fs.lstat("/dir/file").await?.unwrap();
+        assert_eq!(stats.ino, ino);
+        assert_eq!(stats.size, 0);
+        assert!(fs.lstat("/link").await?.unwrap().is_symlink());
+
+        // A link to a missing file creates its target
+        fs.symlink("/dir/new", "/dangling", 0, 0).await?;
+        FileSystem::create(&fs, "/dangling", 0o600, 0).await?;
+        let stats = fs.lstat("/dir/new").await?.unwrap();
+        assert!(stats.is_file());
+        assert_eq!(stats.mode & 0o7777, 0o600);
+
+        // O_NOFOLLOW and O_EXCL don't follow the link
+        let result = FileSystem::create(&fs, "/link", 0o644, libc::O_NOFOLLOW).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::SymlinkLoop))
+        ));
+        let result = FileSystem::create(&fs, "/link", 0o644, libc::O_EXCL).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::AlreadyExists))
+        ));
+
+        // A cycle fails instead of looping forever
+        fs.symlink("/loop", "/loop", 0, 0).await?;
+        let result = FileSystem::create(&fs, "/loop", 0o644, 0).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::SymlinkLoop))
+        ));
 
-        // Write initial data
-        let data: Vec<u8> = vec![1; 50];
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        Ok(())
+    }
 
-        // Write past EOF - should extend with zeros
-        fs.pwrite("/test.txt", 100, &[2, 2, 2, 2, 2]).await?;
+    // ==================== Directory Timestamp Tests ====================
 
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert_eq!(result.len(), 105);
-        assert_eq!(&result[0..50], &vec![1u8; 50][..]);
-        assert_eq!(&result[50..100], &vec![0u8; 50][..]);
-        assert_eq!(&result[100..105], &[2, 2, 2, 2, 2]);
+    /// Reset a directory's mtime to the epoch so any later change is visible.
+    async fn age_dir(fs: &AgentFS, path: &str) -> Result<i64> {
+        let ino = fs.resolve_path(path).await?.unwrap();
+        FileSystem::utimens(fs, ino, TimeChange::Set(0, 0), TimeChange::Set(0, 0)).await?;
+        Ok(ino)
+    }
 
-        Ok(())
+    async fn dir_touched(fs: &AgentFS, ino: i64) -> Result<bool> {
+        let stats = FileSystem::getattr(fs, ino).await?.unwrap();
+        Ok(stats.mtime > 0 && stats.ctime >= stats.mtime)
     }
 
     #[tokio::test]
-    async fn test_pwrite_creates_file() -> Result<()> {
+    async fn test_path_ops_update_parent_mtime() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/target", 0, b"data").await?;
 
-        // pwrite to a non-existent file should create it
-        fs.pwrite("/new.txt", 0, &[1, 2, 3]).await?;
+        let dir = age_dir(&fs, "/dir").await?;
+        fs.mkdir("/dir/sub", 0, 0).await?;
+        assert!(dir_touched(&fs, dir).await?);
 
-        let result = fs.read_file("/new.txt").await?.unwrap();
-        assert_eq!(result, &[1, 2, 3]);
+        age_dir(&fs, "/dir").await?;
+        fs.create_file("/dir/file", DEFAULT_FILE_MODE, 0, 0).await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        age_dir(&fs, "/dir").await?;
+        fs.pwrite("/dir/written", 0, b"x").await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        age_dir(&fs, "/dir").await?;
+        fs.mknod("/dir/fifo", libc::S_IFIFO | 0o644, 0, 0, 0)
+            .await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        age_dir(&fs, "/dir").await?;
+        fs.symlink("/target", "/dir/link", 0, 0).await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        age_dir(&fs, "/dir").await?;
+        fs.link("/target", "/dir/hard").await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        age_dir(&fs, "/dir").await?;
+        fs.remove("/dir/file").await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        // Renaming across directories touches both parents
+        fs.mkdir("/other", 0, 0).await?;
+        age_dir(&fs, "/dir").await?;
+        let other = age_dir(&fs, "/other").await?;
+        fs.rename("/dir/hard", "/other/hard").await?;
+        assert!(dir_touched(&fs, dir).await?);
+        assert!(dir_touched(&fs, other).await?);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_pwrite_across_chunks() -> Result<()> {
+    async fn test_inode_ops_update_parent_mtime() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-        let chunk_size = fs.chunk_size();
+        let dir = FileSystem::mkdir(&fs, ROOT_INO, "dir", 0o755, 0, 0)
+            .await?
+            .ino;
+        let other = FileSystem::mkdir(&fs, ROOT_INO, "other", 0o755, 0, 0)
+            .await?
+            .ino;
 
-        // Create initial data spanning multiple chunks
-        let data: Vec<u8> = vec![0; chunk_size * 3];
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        age_dir(&fs, "/dir").await?;
+        let (file, _) = FileSystem::create_file(&fs, dir, "file", DEFAULT_FILE_MODE, 0, 0).await?;
+        assert!(dir_touched(&fs, dir).await?);
 
-        // Write across chunk boundary
-        let write_data: Vec<u8> = (0..20).collect();
-        let start = chunk_size - 10;
-        fs.pwrite("/test.txt", start as u64, &write_data).await?;
+        age_dir(&fs, "/dir").await?;
+        FileSystem::symlink(&fs, dir, "link", "file", 0, 0).await?;
+        assert!(dir_touched(&fs, dir).await?);
 
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert_eq!(&result[start..start + 20], &write_data[..]);
+        age_dir(&fs, "/other").await?;
+        FileSystem::link(&fs, file.ino, other, "hard").await?;
+        assert!(dir_touched(&fs, other).await?);
 
-        // Verify surrounding data is unchanged
-        assert_eq!(&result[0..start], &vec![0u8; start][..]);
+        age_dir(&fs, "/dir").await?;
+        let other = age_dir(&fs, "/other").await?;
+        FileSystem::rename(&fs, dir, "file", other, "moved", 0).await?;
+        assert!(dir_touched(&fs, dir).await?);
+        assert!(dir_touched(&fs, other).await?);
+
+        age_dir(&fs, "/other").await?;
+        FileSystem::unlink(&fs, other, "moved").await?;
+        assert!(dir_touched(&fs, other).await?);
+
+        age_dir(&fs, "/dir").await?;
+        FileSystem::mkdir(&fs, dir, "sub", 0o755, 0, 0).await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        age_dir(&fs, "/dir").await?;
+        FileSystem::rmdir(&fs, dir, "sub").await?;
+        assert!(dir_touched(&fs, dir).await?);
+
+        Ok(())
+    }
+
+    // ==================== Sync All Tests ====================
+
+    #[tokio::test]
+    async fn test_sync_all_checkpoints_into_database_file() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/dir/file", 0, &pseudo_random_data(10_000))
+            .await?;
+
+        FileSystem::sync_all(&fs).await?;
+        let wal = dir.path().join("test.db-wal");
+        assert!(!wal.exists() || std::fs::metadata(&wal)?.len() == 0);
+
+        // The database file on its own now holds everything
+        let copy = dir.path().join("copy.db");
+        std::fs::copy(dir.path().join("test.db"), &copy)?;
+        let reopened = AgentFS::new(copy.to_str().unwrap()).await?;
         assert_eq!(
-            &result[start + 20..],
-            &vec![0u8; chunk_size * 3 - start - 20][..]
+            reopened.read_file("/dir/file").await?.unwrap(),
+            pseudo_random_data(10_000)
         );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_pread_pwrite_roundtrip() -> Result<()> {
-        let (fs, _dir) = create_test_fs().await?;
-        let chunk_size = fs.chunk_size();
-
-        // Create a file
-        let initial: Vec<u8> = (0..(chunk_size * 2)).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &initial).await?;
+    async fn test_fsync_dir_survives_reopen() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.rename("/dir", "/renamed").await?;
+        FileSystem::fsync_dir(&fs, ROOT_INO).await?;
+        drop(fs);
+
+        let reopened = AgentFS::new(dir.path().join("test.db").to_str().unwrap()).await?;
+        assert!(reopened.stat("/renamed").await?.unwrap().is_directory());
+        assert!(reopened.stat("/dir").await?.is_none());
+
+        reopened.pwrite("/file", 0, b"data").await?;
+        let file_ino = reopened.stat("/file").await?.unwrap().ino;
+        assert!(matches!(
+            FileSystem::fsync_dir(&reopened, file_ino).await,
+            Err(Error::Fs(FsError::NotADirectory))
+        ));
+        assert!(matches!(
+            FileSystem::fsync_dir(&reopened, 99999).await,
+            Err(Error::Fs(FsError::NotFound))
+        ));
 
-        // Write some data at various offsets
-        let patches = vec![
-            (0u64, vec![0xAAu8; 10]),
-            (chunk_size as u64 - 5, vec![0xBB; 10]),
-            (chunk_size as u64 * 2 - 1, vec![0xCC; 1]),
-        ];
+        Ok(())
+    }
 
-        for (offset, data) in &patches {
-            fs.pwrite("/test.txt", *offset, data).await?;
+    #[tokio::test]
+    async fn test_checkpoint_policy_every_n_writes() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        let wal = dir.path().join("test.db-wal");
+        let wal_len = || std::fs::metadata(&wal).map(|m| m.len()).unwrap_or(0);
+
+        fs.set_checkpoint_policy(CheckpointPolicy::EveryNWrites(3))?;
+        for i in 0..3 {
+            fs.pwrite(&format!("/file{i}"), 0, &pseudo_random_data(8192))
+                .await?;
         }
 
-        // Verify with pread
-        for (offset, expected) in &patches {
-            let result = fs
-                .pread("/test.txt", *offset, expected.len() as u64)
-                .await?
-                .unwrap();
-            assert_eq!(&result, expected);
+        // The background task truncates the log shortly after the third write
+        let mut waited = 0;
+        while wal_len() > 0 && waited < 5000 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            waited += 10;
         }
+        assert_eq!(wal_len(), 0);
 
         Ok(())
     }
 
-    // ─────────────────────────────────────────────────────────────
-    // Truncate Tests
-    // ─────────────────────────────────────────────────────────────
-
     #[tokio::test]
-    async fn test_truncate_to_zero() -> Result<()> {
+    async fn test_checkpoint_task_stops_with_last_clone() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Create a file with some data
-        let data: Vec<u8> = (0..100).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
-
-        // Truncate to zero
-        fs.truncate("/test.txt", 0).await?;
-
-        // Verify file is empty
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert!(result.is_empty());
-
-        // Verify stat shows size 0
-        let stats = fs.stat("/test.txt").await?.unwrap();
-        assert_eq!(stats.size, 0);
+        fs.set_checkpoint_policy(CheckpointPolicy::Interval(Duration::from_millis(10)))?;
+        let task = fs
+            .checkpointer
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .task
+            .abort_handle();
+
+        let clone = fs.clone();
+        drop(fs);
+        tokio::task::yield_now().await;
+        assert!(!task.is_finished());
+
+        drop(clone);
+        let mut waited = 0;
+        while !task.is_finished() && waited < 1000 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            waited += 10;
+        }
+        assert!(task.is_finished());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_truncate_smaller_within_chunk() -> Result<()> {
+    async fn test_checkpoint_policy_validation() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        assert!(matches!(
+            fs.set_checkpoint_policy(CheckpointPolicy::EveryNWrites(0)),
+            Err(Error::InvalidCheckpointPolicy(_))
+        ));
+        assert!(matches!(
+            fs.set_checkpoint_policy(CheckpointPolicy::Interval(Duration::ZERO)),
+            Err(Error::InvalidCheckpointPolicy(_))
+        ));
+
+        // Replacing a policy with Never stops the task
+        fs.set_checkpoint_policy(CheckpointPolicy::EveryNWrites(1))?;
+        fs.set_checkpoint_policy(CheckpointPolicy::Never)?;
+        assert!(fs.checkpointer.lock().unwrap().is_none());
 
-        // Create a file smaller than chunk size
-        let data: Vec<u8> = (0..100).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        Ok(())
+    }
 
-        // Truncate to 50 bytes
-        fs.truncate("/test.txt", 50).await?;
+    // ==================== Busy Retry Tests ====================
 
-        // Verify data is truncated correctly
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert_eq!(result.len(), 50);
-        assert_eq!(result, &data[..50]);
+    /// Make `fs` fail fast on a busy lock, so only its retries wait, and
+    /// take the write lock from another connection.
+    async fn hold_write_lock(fs: &AgentFS, retry: BusyRetry) -> Result<turso::Connection> {
+        let conn = fs.pool.get_connection().await?;
+        conn.execute("PRAGMA busy_timeout = 0", ()).await?;
+        drop(conn);
+        fs.set_busy_retry(retry);
 
-        Ok(())
+        let holder = fs.pool.database().unwrap().connect()?;
+        holder.execute("BEGIN IMMEDIATE", ()).await?;
+        Ok(holder)
     }
 
     #[tokio::test]
-    async fn test_truncate_across_chunk_boundary() -> Result<()> {
+    async fn test_concurrent_writes_retry_busy_lock() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-        let chunk_size = fs.chunk_size();
-
-        // Create a file spanning multiple chunks
-        let data: Vec<u8> = (0..(chunk_size * 3)).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        let holder = hold_write_lock(
+            &fs,
+            BusyRetry {
+                max_retries: 50,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(20),
+            },
+        )
+        .await?;
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            holder.execute("COMMIT", ()).await.unwrap();
+        });
 
-        // Truncate to middle of second chunk
-        let new_size = chunk_size + chunk_size / 2;
-        fs.truncate("/test.txt", new_size as u64).await?;
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let fs = fs.clone();
+                tokio::spawn(async move { fs.pwrite(&format!("/file{i}"), 0, b"data").await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap()?;
+        }
+        release.await.unwrap();
 
-        // Verify data
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert_eq!(result.len(), new_size);
-        assert_eq!(result, &data[..new_size]);
+        for i in 0..8 {
+            assert_eq!(fs.read_file(&format!("/file{i}")).await?.unwrap(), b"data");
+        }
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_truncate_extend_file() -> Result<()> {
+    async fn test_busy_lock_gives_up_after_retries() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        let holder = hold_write_lock(
+            &fs,
+            BusyRetry {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            },
+        )
+        .await?;
 
-        // Create a small file
-        let data: Vec<u8> = (0..50).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        let result = fs.pwrite("/file", 0, b"data").await;
+        assert!(matches!(result, Err(Error::Fs(FsError::Busy))));
+        assert_eq!(FsError::Busy.to_errno(), libc::EBUSY);
 
-        // Extend to 100 bytes
-        fs.truncate("/test.txt", 100).await?;
+        // Nothing was applied, so the write succeeds once the lock is free
+        holder.execute("COMMIT", ()).await?;
+        fs.pwrite("/file", 0, b"data").await?;
 
-        // Verify size increased
-        let stats = fs.stat("/test.txt").await?.unwrap();
-        assert_eq!(stats.size, 100);
+        Ok(())
+    }
 
-        // Original data should be preserved, rest should be zeros (sparse)
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert_eq!(result.len(), 100);
-        assert_eq!(&result[..50], &data[..]);
+    // ==================== Access Time Tests ====================
 
-        Ok(())
+    async fn atime(fs: &AgentFS, path: &str) -> Result<(i64, u32)> {
+        let stats = fs.lstat(path).await?.unwrap();
+        Ok((stats.atime, stats.atime_nsec))
     }
 
     #[tokio::test]
-    async fn test_truncate_nonexistent_file() -> Result<()> {
+    async fn test_noatime_reads_do_not_write() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        assert_eq!(fs.atime_policy(), AtimePolicy::Noatime);
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/dir/file", 0, b"data").await?;
+        let ino = fs.lstat("/dir/file").await?.unwrap().ino;
+        let dir_ino = fs.lstat("/dir").await?.unwrap().ino;
+        let before = (atime(&fs, "/dir").await?, atime(&fs, "/dir/file").await?);
+
+        // Any write would fail while another connection holds the lock
+        let holder = hold_write_lock(
+            &fs,
+            BusyRetry {
+                max_retries: 0,
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(fs.read_file("/dir/file").await?.unwrap(), b"data");
+        assert_eq!(fs.pread("/dir/file", 1, 2).await?.unwrap(), b"at");
+        let file = FileSystem::open(&fs, ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 4).await?, b"data");
+        assert_eq!(fs.readdir(dir_ino).await?.unwrap(), vec!["file"]);
+        assert_eq!(fs.readdir_plus(dir_ino).await?.unwrap().len(), 1);
+        holder.execute("COMMIT", ()).await?;
 
-        // Truncate non-existent file should fail
-        let result = fs.truncate("/nonexistent.txt", 100).await;
-        assert!(result.is_err());
+        let after = (atime(&fs, "/dir").await?, atime(&fs, "/dir/file").await?);
+        assert_eq!(before, after);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_truncate_at_chunk_boundary() -> Result<()> {
+    async fn test_atime_policy() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-        let chunk_size = fs.chunk_size();
-
-        // Create a file spanning multiple chunks
-        let data: Vec<u8> = (0..(chunk_size * 3)).map(|i| (i % 256) as u8).collect();
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, &data).await?;
+        fs.pwrite("/file", 0, b"data").await?;
+        let ino = fs.lstat("/file").await?.unwrap().ino;
+        let old = TimeChange::Set(100, 0);
+
+        fs.set_atime_policy(AtimePolicy::Always).await?;
+        assert_eq!(fs.atime_policy(), AtimePolicy::Always);
+        fs.utimens(ino, old, TimeChange::Omit).await?;
+        fs.read_file("/file").await?;
+        assert!(atime(&fs, "/file").await?.0 > 100);
+
+        // An access time older than the change time is updated ...
+        fs.set_atime_policy(AtimePolicy::Relatime).await?;
+        fs.utimens(ino, old, TimeChange::Omit).await?;
+        fs.read_file("/file").await?;
+        assert!(atime(&fs, "/file").await?.0 > 100);
+
+        // ... one newer than the modification and change times is not
+        let future = atime(&fs, "/file").await?.0 + 3600;
+        fs.utimens(ino, TimeChange::Set(future, 0), TimeChange::Omit)
+            .await?;
+        fs.read_file("/file").await?;
+        assert_eq!(atime(&fs, "/file").await?, (future, 0));
 
-        // Truncate exactly at chunk boundary
-        fs.truncate("/test.txt", chunk_size as u64).await?;
+        fs.set_atime_policy(AtimePolicy::Noatime).await?;
+        fs.utimens(ino, old, TimeChange::Omit).await?;
+        fs.read_file("/file").await?;
+        assert_eq!(atime(&fs, "/file").await?, (100, 0));
 
-        // Verify
-        let result = fs.read_file("/test.txt").await?.unwrap();
-        assert_eq!(result.len(), chunk_size);
-        assert_eq!(result, &data[..chunk_size]);
+        // The policy is kept across opens
+        fs.set_atime_policy(AtimePolicy::Relatime).await?;
+        let reopened = AgentFS::from_pool(fs.pool.clone()).await?;
+        assert_eq!(reopened.atime_policy(), AtimePolicy::Relatime);
 
         Ok(())
     }
 
-    // ─────────────────────────────────────────────────────────────
-    // Rename Tests
-    // ─────────────────────────────────────────────────────────────
-
     #[tokio::test]
-    async fn test_rename_file_same_directory() -> Result<()> {
+    async fn test_reads_succeed_while_atime_update_is_busy() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_atime_policy(AtimePolicy::Always).await?;
+        fs.pwrite("/file", 0, b"data").await?;
+        let ino = fs.lstat("/file").await?.unwrap().ino;
+        let before = atime(&fs, "/file").await?;
+
+        // The access time can't be written while another connection holds
+        // the lock, but the reads still succeed
+        let holder = hold_write_lock(
+            &fs,
+            BusyRetry {
+                max_retries: 0,
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(fs.read_file("/file").await?.unwrap(), b"data");
+        let file = FileSystem::open(&fs, ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 4).await?, b"data");
+        assert_eq!(fs.readdir(ROOT_INO).await?.unwrap(), vec!["file"]);
+        holder.execute("COMMIT", ()).await?;
 
-        // Create a file
-        let data = b"hello world";
-        let (_, file) = fs.create_file("/old.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, data).await?;
+        assert_eq!(atime(&fs, "/file").await?, before);
 
-        // Rename it
-        fs.rename("/old.txt", "/new.txt").await?;
+        Ok(())
+    }
 
-        // Old path should not exist
-        assert!(fs.stat("/old.txt").await?.is_none());
+    #[test]
+    fn test_relatime_updates_stale_atime() {
+        let day = RELATIME_INTERVAL_SECS;
+        let relatime = AtimePolicy::Relatime;
+        assert!(!relatime.needs_update((1000, 0), (900, 0), (900, 0), 1000 + day - 1));
+        assert!(relatime.needs_update((1000, 0), (900, 0), (900, 0), 1000 + day));
+        assert!(relatime.needs_update((1000, 0), (1000, 0), (900, 0), 1001));
+        assert!(relatime.needs_update((1000, 0), (900, 0), (1000, 5), 1001));
+        assert!("relatime".parse::<AtimePolicy>().is_ok());
+        assert!("sometimes".parse::<AtimePolicy>().is_err());
+    }
 
-        // New path should exist with same data
-        let result = fs.read_file("/new.txt").await?.unwrap();
-        assert_eq!(result, data);
+    // ==================== Case Insensitivity Tests ====================
+
+    #[tokio::test]
+    async fn test_case_insensitive_lookup() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/Existing.txt", 0, b"before").await?;
+        let conn = fs.get_connection().await?;
+        AgentFS::init_case_insensitive(&conn).await?;
+        drop(conn);
+        let fs = AgentFS::from_pool(fs.pool.clone()).await?;
+        assert!(fs.case_insensitive());
+
+        fs.mkdir("/Dir", 0, 0).await?;
+        fs.pwrite("/Dir/Foo.txt", 0, b"data").await?;
+        assert_eq!(fs.read_file("/dir/foo.TXT").await?.unwrap(), b"data");
+        assert_eq!(fs.read_file("/existing.txt").await?.unwrap(), b"before");
+        let dir_ino = fs.lstat("/DIR").await?.unwrap().ino;
+        assert!(fs.lookup(dir_ino, "FOO.txt").await?.is_some());
+
+        // The name keeps the casing it was created with
+        assert_eq!(fs.readdir(dir_ino).await?.unwrap(), vec!["Foo.txt"]);
+
+        // A name differing only in case is taken
+        let err = fs
+            .create_file("/dir/foo.txt", S_IFREG | 0o644, 0, 0)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Fs(FsError::AlreadyExists)));
+        let err = FileSystem::create_file(&fs, dir_ino, "FOO.txt", S_IFREG | 0o644, 0, 0)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Fs(FsError::AlreadyExists)));
+        assert!(matches!(
+            fs.mkdir("/dir/FOO.TXT", 0, 0).await,
+            Err(Error::Fs(FsError::AlreadyExists))
+        ));
+
+        // Renaming onto the same entry changes only its case
+        fs.rename("/dir/foo.txt", "/dir/FOO.txt").await?;
+        assert_eq!(fs.readdir(dir_ino).await?.unwrap(), vec!["FOO.txt"]);
+        FileSystem::rename(&fs, dir_ino, "FOO.TXT", dir_ino, "foo.txt", 0).await?;
+        assert_eq!(fs.readdir(dir_ino).await?.unwrap(), vec!["foo.txt"]);
+        assert_eq!(fs.read_file("/Dir/Foo.txt").await?.unwrap(), b"data");
+
+        fs.unlink(dir_ino, "FOO.TXT").await?;
+        assert!(fs.readdir(dir_ino).await?.unwrap().is_empty());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_file_to_different_directory() -> Result<()> {
+    async fn test_case_insensitive_rejects_colliding_names() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        assert!(!fs.case_insensitive());
+        fs.pwrite("/foo", 0, b"lower").await?;
+        fs.pwrite("/FOO", 0, b"upper").await?;
+        assert_eq!(fs.read_file("/FOO").await?.unwrap(), b"upper");
+
+        let conn = fs.get_connection().await?;
+        assert!(matches!(
+            AgentFS::init_case_insensitive(&conn).await,
+            Err(Error::Fs(FsError::AlreadyExists))
+        ));
+        drop(conn);
+        let fs = AgentFS::from_pool(fs.pool.clone()).await?;
+        assert!(!fs.case_insensitive());
+        assert_eq!(fs.read_file("/foo").await?.unwrap(), b"lower");
 
-        // Create directory and file
-        fs.mkdir("/subdir", 0, 0).await?;
-        let data = b"test data";
-        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, data).await?;
-
-        // Move file to subdirectory
-        fs.rename("/file.txt", "/subdir/file.txt").await?;
+        Ok(())
+    }
 
-        // Old path should not exist
-        assert!(fs.stat("/file.txt").await?.is_none());
+    // ==================== Check Tests ====================
 
-        // New path should exist
-        let result = fs.read_file("/subdir/file.txt").await?.unwrap();
-        assert_eq!(result, data);
+    #[tokio::test]
+    async fn test_check_clean_filesystem() -> Result<()> {
+        let (fs, _dir) = create_test_fs().await?;
+        fs.set_dedup(true).await?;
+        fs.mkdir("/a", 0, 0).await?;
+        fs.mkdir("/a/b", 0, 0).await?;
+        fs.pwrite("/a/f", 0, b"same").await?;
+        fs.pwrite("/a/b/g", 0, b"same").await?;
+        fs.link("/a/f", "/h").await?;
+        fs.symlink("/a/f", "/s", 0, 0).await?;
+        fs.rename("/a/b", "/c").await?;
+        fs.remove("/s").await?;
+
+        assert_eq!(fs.check().await?, vec![]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_overwrite_existing_file() -> Result<()> {
+    async fn test_check_and_repair() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.pwrite("/f", 0, b"data").await?;
+        fs.link("/f", "/g").await?;
+        fs.pwrite("/orphan", 0, b"lost").await?;
+        let f_ino = fs.lstat("/f").await?.unwrap().ino;
+        let orphan_ino = fs.lstat("/orphan").await?.unwrap().ino;
 
-        // Create two files
-        let (_, file) = fs.create_file("/src.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"source").await?;
-        let (_, file) = fs.create_file("/dst.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"destination").await?;
+        let conn = fs.get_connection().await?;
+        conn.execute(
+            "INSERT INTO fs_dentry (name, parent_ino, ino) VALUES ('ghost', 1, 9999)",
+            (),
+        )
+        .await?;
+        conn.execute("DELETE FROM fs_dentry WHERE name IN ('g', 'orphan')", ())
+            .await?;
+        conn.execute(
+            "INSERT INTO fs_xattr (ino, name, value) VALUES (8888, 'user.x', X'00')",
+            (),
+        )
+        .await?;
+        drop(conn);
 
-        // Rename src to dst (overwrites dst)
-        fs.rename("/src.txt", "/dst.txt").await?;
+        let found = fs.check().await?;
+        assert_eq!(
+            found,
+            vec![
+                Inconsistency::DanglingEntry {
+                    parent_ino: ROOT_INO,
+                    name: "ghost".to_string(),
+                    ino: 9999,
+                },
+                Inconsistency::OrphanedInode { ino: orphan_ino },
+                Inconsistency::OrphanedData { ino: 8888 },
+                Inconsistency::WrongLinkCount {
+                    ino: f_ino,
+                    found: 2,
+                    expected: 1,
+                },
+            ]
+        );
 
-        // Only dst should exist with src's content
-        assert!(fs.stat("/src.txt").await?.is_none());
-        let result = fs.read_file("/dst.txt").await?.unwrap();
-        assert_eq!(result, b"source");
+        assert_eq!(fs.repair(&found).await?, 4);
+        assert_eq!(fs.check().await?, vec![]);
+        assert!(fs.stat("/ghost").await?.is_none());
+        assert_eq!(fs.stat("/f").await?.unwrap().nlink, 1);
+        assert_eq!(fs.read_file("/f").await?.unwrap(), b"data");
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_directory() -> Result<()> {
+    async fn test_check_blob_references() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Create directory with a file inside
-        fs.mkdir("/olddir", 0, 0).await?;
-        let (_, file) = fs
-            .create_file("/olddir/file.txt", DEFAULT_FILE_MODE, 0, 0)
+        fs.set_dedup(true).await?;
+        fs.pwrite("/a", 0, b"shared").await?;
+        fs.pwrite("/b", 0, b"shared").await?;
+        fs.pwrite("/c", 0, b"single").await?;
+        let c_ino = fs.lstat("/c").await?.unwrap().ino;
+
+        let conn = fs.get_connection().await?;
+        let shared = blake3::hash(b"shared").as_bytes().to_vec();
+        let single = blake3::hash(b"single").as_bytes().to_vec();
+        conn.execute(
+            "UPDATE fs_blob SET refcount = 5 WHERE hash = ?",
+            (Value::Blob(shared.clone()),),
+        )
+        .await?;
+        conn.execute(
+            "INSERT INTO fs_blob (hash, data, compression, refcount) VALUES (X'00', X'00', 0, 1)",
+            (),
+        )
+        .await?;
+        conn.execute("DELETE FROM fs_blob WHERE hash = ?", (Value::Blob(single),))
             .await?;
-        file.pwrite(0, b"content").await?;
-
-        // Rename directory
-        fs.rename("/olddir", "/newdir").await?;
+        drop(conn);
 
-        // Old path should not exist
-        assert!(fs.stat("/olddir").await?.is_none());
+        let found = fs.check().await?;
+        assert_eq!(
+            found,
+            vec![
+                Inconsistency::MissingBlob {
+                    ino: c_ino,
+                    chunk_index: 0,
+                },
+                Inconsistency::OrphanedBlob { hash: vec![0] },
+                Inconsistency::WrongBlobRefcount {
+                    hash: shared,
+                    found: 5,
+                    expected: 2,
+                },
+            ]
+        );
 
-        // New path should exist and contain the file
-        assert!(fs.stat("/newdir").await?.is_some());
-        let result = fs.read_file("/newdir/file.txt").await?.unwrap();
-        assert_eq!(result, b"content");
+        // Lost contents cannot be repaired
+        assert_eq!(fs.repair(&found).await?, 2);
+        assert_eq!(
+            fs.check().await?,
+            vec![Inconsistency::MissingBlob {
+                ino: c_ino,
+                chunk_index: 0,
+            }]
+        );
+        assert_eq!(fs.read_file("/b").await?.unwrap(), b"shared");
 
         Ok(())
     }
 
+    // ==================== Trash Tests ====================
+
     #[tokio::test]
-    async fn test_rename_directory_into_own_subtree_fails() -> Result<()> {
+    async fn test_trash_unlink_and_restore() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Create nested directories
-        fs.mkdir("/parent", 0, 0).await?;
-        fs.mkdir("/parent/child", 0, 0).await?;
-
-        // Try to rename parent into its child - should fail
-        let result = fs.rename("/parent", "/parent/child/parent").await;
-        assert!(result.is_err());
-
-        // Original structure should be intact
-        assert!(fs.stat("/parent").await?.is_some());
-        assert!(fs.stat("/parent/child").await?.is_some());
+        fs.set_trash(Some(TrashPolicy::default())).await?;
+        fs.mkdir("/docs", 0, 0).await?;
+        fs.pwrite("/docs/a.txt", 0, b"keep me").await?;
+        let ino = fs.lstat("/docs/a.txt").await?.unwrap().ino;
+
+        fs.remove("/docs/a.txt").await?;
+        assert!(fs.lstat("/docs/a.txt").await?.is_none());
+        let trash = fs.list_trash().await?;
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].path, "/docs/a.txt");
+        assert_eq!(trash[0].ino, ino);
+        assert_eq!(trash[0].size, 7);
+        assert_eq!(fs.check().await?, vec![]);
+
+        // Compacting without a retention keeps trashed inodes
+        fs.compact().await?;
+        assert_eq!(fs.restore_trash("/docs/a.txt").await?, 1);
+        assert_eq!(fs.read_file("/docs/a.txt").await?.unwrap(), b"keep me");
+        assert!(fs.list_trash().await?.is_empty());
+        assert_eq!(fs.check().await?, vec![]);
+
+        assert!(matches!(
+            fs.restore_trash("/docs/a.txt").await,
+            Err(Error::Fs(FsError::NotFound))
+        ));
+        fs.remove("/docs/a.txt").await?;
+        fs.pwrite("/docs/a.txt", 0, b"new").await?;
+        assert!(matches!(
+            fs.restore_trash("/docs/a.txt").await,
+            Err(Error::Fs(FsError::AlreadyExists))
+        ));
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_root_fails() -> Result<()> {
+    async fn test_trash_remove_all_and_restore() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Try to rename root - should fail
-        let result = fs.rename("/", "/newroot").await;
-        assert!(result.is_err());
+        fs.set_trash(Some(TrashPolicy::default())).await?;
+        fs.mkdir("/proj", 0, 0).await?;
+        fs.mkdir("/proj/src", 0, 0).await?;
+        fs.pwrite("/proj/src/main.rs", 0, b"fn main() {}").await?;
+        fs.pwrite("/proj/README", 0, b"readme").await?;
+        fs.remove("/proj/README").await?;
+
+        FileSystem::remove_all(&fs, "/proj").await?;
+        assert!(fs.lstat("/proj").await?.is_none());
+        assert_eq!(fs.lstat("/").await?.unwrap().nlink, 2);
+        assert_eq!(fs.check().await?, vec![]);
+
+        // The directory comes back with its contents and what was removed
+        // from it before
+        assert_eq!(fs.restore_trash("/proj").await?, 2);
+        assert_eq!(
+            fs.read_file("/proj/src/main.rs").await?.unwrap(),
+            b"fn main() {}"
+        );
+        assert_eq!(fs.read_file("/proj/README").await?.unwrap(), b"readme");
+        assert_eq!(fs.lstat("/").await?.unwrap().nlink, 3);
+        assert_eq!(fs.check().await?, vec![]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_to_root_fails() -> Result<()> {
+    async fn test_trash_keeps_hard_links() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_trash(Some(TrashPolicy::default())).await?;
+        fs.pwrite("/f", 0, b"data").await?;
+        fs.link("/f", "/g").await?;
 
-        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"data").await?;
+        fs.remove("/f").await?;
+        assert_eq!(fs.lstat("/g").await?.unwrap().nlink, 2);
+        assert_eq!(fs.check().await?, vec![]);
 
-        // Try to rename to root - should fail
-        let result = fs.rename("/file.txt", "/").await;
-        assert!(result.is_err());
+        assert_eq!(fs.empty_trash().await?, 1);
+        assert_eq!(fs.lstat("/g").await?.unwrap().nlink, 1);
+        assert_eq!(fs.read_file("/g").await?.unwrap(), b"data");
+        assert_eq!(fs.check().await?, vec![]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_nonexistent_source_fails() -> Result<()> {
+    async fn test_empty_trash_frees_data() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Try to rename non-existent file
-        let result = fs.rename("/nonexistent.txt", "/new.txt").await;
-        assert!(result.is_err());
+        fs.set_trash(Some(TrashPolicy::default())).await?;
+        fs.mkdir("/d", 0, 0).await?;
+        fs.pwrite("/d/f", 0, b"data").await?;
+        let ino = fs.lstat("/d/f").await?.unwrap().ino;
+        FileSystem::remove_all(&fs, "/d").await?;
+
+        assert_eq!(fs.empty_trash().await?, 1);
+        assert!(fs.list_trash().await?.is_empty());
+        assert!(fs.getattr(ino).await?.is_none());
+        let conn = fs.get_connection().await?;
+        let mut rows = conn.query("SELECT COUNT(*) FROM fs_data", ()).await?;
+        let row = rows.next().await?.unwrap();
+        assert_eq!(row.get_value(0)?.as_integer().copied(), Some(0));
+        drop(rows);
+        drop(conn);
+        assert_eq!(fs.check().await?, vec![]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_overwrite_nonempty_directory_fails() -> Result<()> {
-        let (fs, _dir) = create_test_fs().await?;
-
-        // Create source directory and target directory with content
-        fs.mkdir("/src", 0, 0).await?;
-        fs.mkdir("/dst", 0, 0).await?;
-        let (_, file) = fs
-            .create_file("/dst/file.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, b"content").await?;
+    async fn test_trash_retention_expires_on_compact() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        fs.set_trash(Some(TrashPolicy {
+            retention: Some(Duration::from_secs(3600)),
+        }))
+        .await?;
+        fs.pwrite("/old", 0, b"old").await?;
+        fs.pwrite("/recent", 0, b"recent").await?;
+        fs.remove("/old").await?;
+        fs.remove("/recent").await?;
+        let conn = fs.get_connection().await?;
+        conn.execute(
+            "UPDATE fs_trash SET deleted_at = deleted_at - 7200 WHERE orig_path = '/old'",
+            (),
+        )
+        .await?;
+        drop(conn);
 
-        // Try to rename src to dst (dst is not empty) - should fail
-        let result = fs.rename("/src", "/dst").await;
-        assert!(result.is_err());
+        let stats = fs.compact().await?;
+        assert_eq!(stats.expired_trash, 1);
+        assert_eq!(stats.orphaned_inodes, 0);
+        let trash = fs.list_trash().await?;
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].path, "/recent");
 
-        // Both directories should still exist
-        assert!(fs.stat("/src").await?.is_some());
-        assert!(fs.stat("/dst").await?.is_some());
-        assert!(fs.stat("/dst/file.txt").await?.is_some());
+        // The policy is persisted; turning it off deletes right away again
+        let reopened = AgentFS::new(dir.path().join("test.db").to_str().unwrap()).await?;
+        assert_eq!(
+            reopened.trash().unwrap().retention,
+            Some(Duration::from_secs(3600))
+        );
+        fs.set_trash(None).await?;
+        fs.pwrite("/gone", 0, b"gone").await?;
+        fs.remove("/gone").await?;
+        assert_eq!(fs.list_trash().await?.len(), 1);
 
         Ok(())
     }
 
+    // ==================== Blob Encryption Tests ====================
+
     #[tokio::test]
-    async fn test_rename_file_to_directory_fails() -> Result<()> {
+    async fn test_blob_encryption_roundtrip() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Create a file and an empty directory
-        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"data").await?;
-        fs.mkdir("/dir", 0, 0).await?;
-
-        // Try to rename file over directory - should fail
-        let result = fs.rename("/file.txt", "/dir").await;
-        assert!(result.is_err());
+        fs.set_compression(Some(CompressionKind::Zstd)).await?;
+        fs.set_blob_key(BlobKey::from([7u8; BlobKey::LEN])).await?;
+        assert!(fs.blob_encrypted().await?);
+
+        let data = compressible_data(3 * fs.chunk_size() + 100);
+        fs.pwrite("/secret.txt", 0, &data).await?;
+        fs.pwrite("/secret.txt", 10, b"partial").await?;
+        let mut expected = data.clone();
+        expected[10..17].copy_from_slice(b"partial");
+        assert_eq!(fs.read_file("/secret.txt").await?.unwrap(), expected);
+
+        // Every stored chunk is sealed and none holds the plaintext
+        let ino = fs.lstat("/secret.txt").await?.unwrap().ino;
+        let conn = fs.get_connection().await?;
+        let mut rows = conn
+            .query(
+                "SELECT data, compression FROM fs_data WHERE ino = ?",
+                (ino,),
+            )
+            .await?;
+        let mut chunks = 0;
+        while let Some(row) = rows.next().await? {
+            let Ok(Value::Blob(stored)) = row.get_value(0) else {
+                panic!("chunk data is not a blob");
+            };
+            let code = row.get_value(1)?.as_integer().copied().unwrap() as u8;
+            assert_ne!(code & CHUNK_ENCRYPTED, 0);
+            assert!(!stored.windows(8).any(|w| w == b"log line"));
+            chunks += 1;
+        }
+        assert_eq!(chunks, 4);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_directory_to_file_fails() -> Result<()> {
-        let (fs, _dir) = create_test_fs().await?;
+    async fn test_blob_key_mismatch_fails_cleanly() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let key = BlobKey::from([1u8; BlobKey::LEN]);
+        let data = pseudo_random_data(2 * DEFAULT_CHUNK_SIZE);
 
-        // Create a directory and a file
-        fs.mkdir("/dir", 0, 0).await?;
-        let (_, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"data").await?;
+        {
+            let fs = AgentFS::new(db_path).await?;
+            fs.pwrite("/plain.bin", 0, b"before").await?;
+            fs.set_blob_key(key.clone()).await?;
+            fs.pwrite("/sealed.bin", 0, &data).await?;
+        }
 
-        // Try to rename directory over file - should fail
-        let result = fs.rename("/dir", "/file.txt").await;
-        assert!(result.is_err());
+        let fs = AgentFS::new(db_path).await?;
+        assert!(matches!(
+            fs.set_blob_key(BlobKey::from([2u8; BlobKey::LEN])).await,
+            Err(Error::InvalidEncryptionKey(_))
+        ));
+
+        // Without the key, encrypted contents are refused rather than garbled
+        assert!(matches!(
+            fs.read_file("/sealed.bin").await,
+            Err(Error::InvalidEncryptionKey(_))
+        ));
+        assert_eq!(fs.read_file("/plain.bin").await?.unwrap(), b"before");
+
+        fs.set_blob_key(key).await?;
+        assert_eq!(fs.read_file("/sealed.bin").await?.unwrap(), data);
+        assert_eq!(fs.read_file("/plain.bin").await?.unwrap(), b"before");
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_rename_updates_ctime() -> Result<()> {
+    async fn test_blob_encryption_with_dedup() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_dedup(true).await?;
+        fs.set_blob_key(BlobKey::from([3u8; BlobKey::LEN])).await?;
+
+        let data = pseudo_random_data(DEFAULT_CHUNK_SIZE);
+        fs.pwrite("/a", 0, &data).await?;
+        fs.pwrite("/b", 0, &data).await?;
+        assert_eq!(fs.read_file("/b").await?.unwrap(), data);
+
+        // Blobs are keyed by a keyed hash, not the plain content hash
+        let conn = fs.get_connection().await?;
+        let mut rows = conn.query("SELECT hash, refcount FROM fs_blob", ()).await?;
+        let row = rows.next().await?.unwrap();
+        let Ok(Value::Blob(hash)) = row.get_value(0) else {
+            panic!("blob hash is not a blob");
+        };
+        assert_ne!(hash, blake3::hash(&data).as_bytes().to_vec());
+        assert_eq!(row.get_value(1)?.as_integer().copied(), Some(2));
+        assert!(rows.next().await?.is_none());
 
-        // Create a file
-        let (_, file) = fs.create_file("/old.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"data").await?;
-        let stats_before = fs.stat("/old.txt").await?.unwrap();
-
-        // Small delay to ensure time changes
-        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        Ok(())
+    }
 
-        // Rename it
-        fs.rename("/old.txt", "/new.txt").await?;
+    #[test]
+    fn test_blob_key_length_is_checked() {
+        assert!(BlobKey::from_bytes(&[0u8; BlobKey::LEN]).is_ok());
+        assert!(matches!(
+            BlobKey::from_bytes(&[0u8; 16]),
+            Err(Error::InvalidEncryptionKey(_))
+        ));
+    }
 
-        // ctime should be updated
-        let stats_after = fs.stat("/new.txt").await?.unwrap();
-        assert!(stats_after.ctime >= stats_before.ctime);
+    // ==================== Check Op Tests ====================
 
-        Ok(())
+    /// The errno `op` would fail with, or `None` if it would succeed
+    async fn check_errno(fs: &AgentFS, op: Operation) -> Option<i32> {
+        match fs.check_op(&op).await {
+            Ok(()) => None,
+            Err(crate::error::Error::Fs(e)) => Some(e.to_errno()),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
     }
 
     #[tokio::test]
-    async fn test_chmod_regular_file() -> Result<()> {
+    async fn test_check_op_reports_would_be_errors() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
-
-        // Create a file with default permissions
-        let (_, file) = fs.create_file("/test.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"content").await?;
-
-        let stats = fs.stat("/test.txt").await?.unwrap();
-        let ino = stats.ino;
+        fs.mkdir("/dir", 0, 0).await?;
+        fs.pwrite("/dir/inner.txt", 0, b"inner").await?;
+        let dir_ino = fs.lstat("/dir").await?.unwrap().ino;
+        let file_ino = fs.lstat("/dir/inner.txt").await?.unwrap().ino;
+        let name = |name: &str| name.to_string();
+
+        let mkdir = |parent_ino, n| Operation::Mkdir {
+            parent_ino,
+            name: name(n),
+        };
+        assert_eq!(check_errno(&fs, mkdir(ROOT_INO, "new")).await, None);
         assert_eq!(
-            stats.mode & 0o7777,
-            0o644,
-            "Default file mode should be 0o644"
+            check_errno(&fs, mkdir(ROOT_INO, "dir")).await,
+            Some(libc::EEXIST)
         );
-
-        // Change to executable
-        fs.chmod(ino, 0o755).await?;
-
-        let stats = fs.stat("/test.txt").await?.unwrap();
         assert_eq!(
-            stats.mode & 0o7777,
-            0o755,
-            "Mode should be 0o755 after chmod"
+            check_errno(&fs, mkdir(file_ino, "x")).await,
+            Some(libc::ENOTDIR)
         );
-        assert!(stats.is_file(), "Should still be a regular file");
+        assert_eq!(check_errno(&fs, mkdir(9999, "x")).await, Some(libc::ENOENT));
 
-        // Change to read-only
-        fs.chmod(ino, 0o444).await?;
+        let unlink = Operation::Unlink {
+            parent_ino: ROOT_INO,
+            name: name("dir"),
+        };
+        assert_eq!(check_errno(&fs, unlink).await, Some(libc::EISDIR));
+        let rmdir = Operation::Rmdir {
+            parent_ino: ROOT_INO,
+            name: name("dir"),
+        };
+        assert_eq!(check_errno(&fs, rmdir).await, Some(libc::ENOTEMPTY));
+        let rename = Operation::Rename {
+            oldparent_ino: ROOT_INO,
+            oldname: name("dir"),
+            newparent_ino: dir_ino,
+            newname: name("moved"),
+            flags: 0,
+        };
+        assert_eq!(check_errno(&fs, rename).await, Some(libc::EINVAL));
+        let link = Operation::Link {
+            ino: file_ino,
+            newparent_ino: ROOT_INO,
+            newname: name("linked.txt"),
+        };
+        assert_eq!(check_errno(&fs, link).await, None);
 
-        let stats = fs.stat("/test.txt").await?.unwrap();
-        assert_eq!(
-            stats.mode & 0o7777,
-            0o444,
-            "Mode should be 0o444 after chmod"
-        );
+        // Nothing was changed
+        assert!(fs.lstat("/new").await?.is_none());
+        assert!(fs.lstat("/linked.txt").await?.is_none());
+        assert!(fs.lstat("/dir/moved").await?.is_none());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_chmod_preserves_file_type() -> Result<()> {
+    async fn test_check_op_respects_quota() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_max_bytes(Some(10_000)).await?;
+        fs.pwrite("/data.bin", 0, &[1u8; 8000]).await?;
+        let ino = fs.lstat("/data.bin").await?.unwrap().ino;
+
+        let write = |offset: u64, len: u64| Operation::Write { ino, offset, len };
+        fs.check_op(&write(0, 8000)).await?;
+        fs.check_op(&write(8000, 2000)).await?;
+        assert!(matches!(
+            fs.check_op(&write(8000, 4000)).await,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
+        assert!(matches!(
+            fs.check_op(&Operation::Truncate { ino, size: 20_000 })
+                .await,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
+        assert_eq!(fs.statfs().await?.bytes_used, 8000);
 
-        // Create a regular file
-        let (file_stats, file) = fs.create_file("/file.txt", DEFAULT_FILE_MODE, 0, 0).await?;
-        file.pwrite(0, b"content").await?;
-        fs.chmod(file_stats.ino, 0o755).await?;
-        let stats = fs.stat("/file.txt").await?.unwrap();
-        assert!(stats.is_file(), "Should remain a regular file after chmod");
+        Ok(())
+    }
 
-        // Create a directory
-        fs.mkdir("/dir", 0, 0).await?;
-        let dir_stats = fs.stat("/dir").await?.unwrap();
-        fs.chmod(dir_stats.ino, 0o700).await?;
-        let stats = fs.stat("/dir").await?.unwrap();
-        assert!(
-            stats.is_directory(),
-            "Should remain a directory after chmod"
-        );
-        assert_eq!(stats.mode & 0o7777, 0o700, "Directory mode should be 0o700");
+    // ==================== Write-Back Tests ====================
+
+    #[tokio::test]
+    async fn test_write_back_coalesces_small_writes() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        fs.set_write_back(Some(WriteBackPolicy::default())).await?;
+        fs.pwrite("/log", 0, b"").await?;
+        let file = fs.open("/log").await?;
+        let data = pseudo_random_data(1000);
+        for (i, byte) in data.iter().enumerate() {
+            file.pwrite(i as u64, std::slice::from_ref(byte)).await?;
+        }
+
+        // Buffered bytes are visible through the filesystem...
+        assert_eq!(file.pread(0, 2000).await?, data);
+        assert_eq!(file.fstat().await?.size, 1000);
+        assert_eq!(fs.stat("/log").await?.unwrap().size, 1000);
+
+        // ...but not yet stored
+        let other = AgentFS::new(dir.path().join("test.db").to_str().unwrap()).await?;
+        assert_eq!(other.stat("/log").await?.unwrap().size, 0);
+
+        file.fsync().await?;
+        assert_eq!(other.read_file("/log").await?.unwrap(), data);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_chmod_nonexistent_fails() -> Result<()> {
+    async fn test_write_back_flushes_before_other_operations() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_write_back(Some(WriteBackPolicy::default())).await?;
+        fs.pwrite("/file", 0, b"0123456789").await?;
+        let file = fs.open("/file").await?;
 
-        // Use a non-existent inode
-        let result = fs.chmod(999999, 0o755).await;
-        assert!(result.is_err(), "chmod on nonexistent inode should fail");
+        // A write outside the buffered extent stores it first, in order
+        file.pwrite(2, b"ab").await?;
+        file.pwrite(1, b"X").await?;
+        file.pwrite(3, b"Y").await?;
+        assert_eq!(fs.read_file("/file").await?.unwrap(), b"0XaY456789");
+
+        file.pwrite(10, b"tail").await?;
+        file.truncate(12).await?;
+        assert_eq!(fs.read_file("/file").await?.unwrap(), b"0XaY456789ta");
+
+        file.pwrite(12, b"gone").await?;
+        fs.remove("/file").await?;
+        assert!(fs.write_buffer.is_empty());
+        assert!(fs.stat("/file").await?.is_none());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_chmod_symlink() -> Result<()> {
+    async fn test_write_back_read_during_flush() -> Result<()> {
         let (fs, _dir) = create_test_fs().await?;
+        fs.set_write_back(Some(WriteBackPolicy::default())).await?;
+        fs.pwrite("/file", 0, b"").await?;
+        let file = fs.open("/file").await?;
+
+        // A read racing a flush sees the buffered bytes whether it runs
+        // before, during or after the flush
+        for round in 0..20u8 {
+            let data = vec![round; 3000];
+            file.pwrite(0, &data).await?;
+            let (flushed, reads) = tokio::join!(fs.flush_writes(), async {
+                let mut reads = Vec::new();
+                for _ in 0..4 {
+                    reads.push(file.pread(0, 3000).await);
+                    tokio::task::yield_now().await;
+                }
+                reads
+            });
+            flushed?;
+            for read in reads {
+                assert_eq!(read?, data);
+            }
+        }
 
-        // Create target and symlink
-        let (_, file) = fs
-            .create_file("/target.txt", DEFAULT_FILE_MODE, 0, 0)
-            .await?;
-        file.pwrite(0, b"content").await?;
-        fs.symlink("/target.txt", "/link.txt", 0, 0).await?;
-        let link_stats = fs.lstat("/link.txt").await?.unwrap();
+        Ok(())
+    }
 
-        // chmod the symlink (should work on the symlink inode)
-        fs.chmod(link_stats.ino, 0o755).await?;
+    #[tokio::test]
+    async fn test_write_back_flushes_on_interval() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        fs.set_write_back(Some(WriteBackPolicy {
+            max_bytes: 1024,
+            flush_interval: Duration::from_millis(50),
+        }))
+        .await?;
+        fs.pwrite("/file", 0, b"").await?;
+        let file = fs.open("/file").await?;
+        file.pwrite(0, b"hello").await?;
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let other = AgentFS::new(dir.path().join("test.db").to_str().unwrap()).await?;
+        assert_eq!(other.read_file("/file").await?.unwrap(), b"hello");
+
+        // Writes at least as large as the buffer are never buffered
+        file.pwrite(5, &[b'!'; 1024]).await?;
+        assert!(fs.write_buffer.is_empty());
+
+        assert!(matches!(
+            fs.set_write_back(Some(WriteBackPolicy {
+                max_bytes: 0,
+                ..Default::default()
+            }))
+            .await,
+            Err(Error::InvalidWriteBackPolicy(_))
+        ));
+        fs.set_write_back(None).await?;
+        assert_eq!(fs.write_back(), None);
 
-        let stats = fs.lstat("/link.txt").await?.unwrap();
-        assert!(stats.is_symlink(), "Should still be a symlink");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spill_stores_large_writes_in_batches() -> Result<()> {
+        let (fs, dir) = create_test_fs().await?;
+        let spill_dir = tempfile::tempdir()?;
+        fs.set_write_back(Some(WriteBackPolicy {
+            max_bytes: 1024 * 1024,
+            flush_interval: Duration::from_secs(60),
+        }))
+        .await?;
+        fs.set_spill(Some(SpillPolicy {
+            dir: Some(spill_dir.path().to_path_buf()),
+            threshold: 64 * 1024,
+        }))?;
+        fs.pwrite("/big", 0, b"").await?;
+        let file = fs.open("/big").await?;
+        let mut rx = fs.subscribe();
+
+        // Past the threshold, buffered bytes live in a temporary file and are
+        // still read through
+        let data = pseudo_random_data(600_000);
+        for (i, part) in data.chunks(4000).enumerate() {
+            file.pwrite((i * 4000) as u64, part).await?;
+        }
+        assert_eq!(file.pread(0, 1_000_000).await?, data);
+        let other = AgentFS::new(dir.path().join("test.db").to_str().unwrap()).await?;
+        assert_eq!(other.stat("/big").await?.unwrap().size, 0);
+
+        // Each transaction stores at most one threshold's worth
+        file.fsync().await?;
+        assert_eq!(
+            drain_events(&mut rx).len(),
+            600_000_usize.div_ceil(64 * 1024)
+        );
+        assert_eq!(other.read_file("/big").await?.unwrap(), data);
+        assert_eq!(std::fs::read_dir(spill_dir.path())?.count(), 0);
+
+        // So does a write too large to buffer at all
+        let large = pseudo_random_data(1024 * 1024);
+        file.pwrite(0, &large).await?;
+        assert_eq!(drain_events(&mut rx).len(), 16);
+        assert_eq!(other.read_file("/big").await?.unwrap(), large);
+
+        assert!(matches!(
+            fs.set_spill(Some(SpillPolicy {
+                threshold: 0,
+                ..Default::default()
+            })),
+            Err(Error::InvalidWriteBackPolicy(_))
+        ));
 
         Ok(())
     }
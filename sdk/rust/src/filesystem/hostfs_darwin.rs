@@ -4,14 +4,18 @@
 //! O_PATH file descriptors. macOS doesn't support O_PATH or AT_EMPTY_PATH,
 //! so we use a path-based approach similar to libfuse's passthrough.c example.
 
-use super::{BoxedFile, DirEntry, File, FileSystem, FilesystemStats, FsError, Stats, TimeChange};
+use super::readonly::WRITE_FLAGS;
+use super::{
+    check_access, check_op_preconditions, host_off_t, BoxedFile, DirEntry, File, FileSystem,
+    FilesystemStats, FsError, Operation, Stats, TimeChange, RENAME_EXCHANGE, RENAME_NOREPLACE,
+};
 use crate::error::{Error, Result};
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::RwLock;
@@ -55,6 +59,8 @@ pub struct HostFS {
     next_ino: AtomicU64,
     /// FUSE mountpoint inode to avoid deadlock when overlaying
     fuse_mountpoint_inode: Option<u64>,
+    /// Fail every mutating call with `EROFS`
+    readonly: bool,
 }
 
 /// An open file handle for HostFS (real fd for I/O)
@@ -67,6 +73,7 @@ pub struct HostFSFile {
 impl File for HostFSFile {
     async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
         let fd = self.fd.as_raw_fd();
+        let offset = host_off_t(offset)?;
         tokio::task::spawn_blocking(move || {
             let mut buf = vec![0u8; size as usize];
             let n = unsafe {
@@ -74,7 +81,7 @@ impl File for HostFSFile {
                     fd,
                     buf.as_mut_ptr() as *mut libc::c_void,
                     size as usize,
-                    offset as libc::off_t,
+                    offset,
                 )
             };
             if n < 0 {
@@ -89,15 +96,11 @@ impl File for HostFSFile {
 
     async fn pwrite(&self, offset: u64, data: &[u8]) -> Result<()> {
         let fd = self.fd.as_raw_fd();
+        let offset = host_off_t(offset)?;
         let data = data.to_vec();
         tokio::task::spawn_blocking(move || {
             let n = unsafe {
-                libc::pwrite(
-                    fd,
-                    data.as_ptr() as *const libc::c_void,
-                    data.len(),
-                    offset as libc::off_t,
-                )
+                libc::pwrite(fd, data.as_ptr() as *const libc::c_void, data.len(), offset)
             };
             if n < 0 {
                 return Err(std::io::Error::last_os_error().into());
@@ -110,8 +113,9 @@ impl File for HostFSFile {
 
     async fn truncate(&self, size: u64) -> Result<()> {
         let fd = self.fd.as_raw_fd();
+        let size = host_off_t(size)?;
         tokio::task::spawn_blocking(move || {
-            let result = unsafe { libc::ftruncate(fd, size as libc::off_t) };
+            let result = unsafe { libc::ftruncate(fd, size) };
             if result < 0 {
                 return Err(std::io::Error::last_os_error().into());
             }
@@ -165,7 +169,21 @@ fn stat_to_stats(stat: &libc::stat) -> Stats {
         mtime_nsec: stat.st_mtime_nsec as u32,
         ctime_nsec: stat.st_ctime_nsec as u32,
         rdev: stat.st_rdev as u64,
+        blocks: stat.st_blocks as u64,
+    }
+}
+
+/// Join a directory entry name onto its parent's path, refusing names that leave the parent.
+///
+/// `Path::join` replaces the path outright for an absolute name, and the
+/// kernel resolves `..` and symlinks in intermediate components, so names
+/// like `..` or `link/etc` would reach files outside the base directory.
+/// Such names are rejected with `PermissionDenied`.
+fn child_path(parent: &Path, name: &str) -> Result<PathBuf> {
+    if name == ".." || name.contains('/') {
+        return Err(FsError::PermissionDenied.into());
     }
+    Ok(parent.join(name))
 }
 
 /// Clear errno (platform-specific)
@@ -225,9 +243,36 @@ impl HostFS {
             src_to_ino: RwLock::new(src_to_ino),
             next_ino: AtomicU64::new(2), // 1 is root
             fuse_mountpoint_inode: None,
+            readonly: false,
+        })
+    }
+
+    /// Create a HostFS that refuses to modify the directory
+    ///
+    /// Every mutating call, including opening a file for writing, fails with
+    /// `FsError::ReadOnly` (`EROFS`) before touching the host. Overlays use
+    /// this for their base so that a copy-up bug can't write to the user's
+    /// real directory.
+    pub fn new_readonly(root: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            readonly: true,
+            ..Self::new(root)?
         })
     }
 
+    /// Whether this HostFS was created with [`HostFS::new_readonly`]
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Fail with `EROFS` if the directory must not be modified
+    fn check_writable(&self) -> Result<()> {
+        if self.readonly {
+            return Err(FsError::ReadOnly.into());
+        }
+        Ok(())
+    }
+
     /// Set the FUSE mountpoint inode to avoid deadlock when overlaying
     pub fn with_fuse_mountpoint(mut self, inode: u64) -> Self {
         self.fuse_mountpoint_inode = Some(inode);
@@ -274,6 +319,16 @@ impl HostFS {
         Ok(unsafe { OwnedFd::from_raw_fd(fd) })
     }
 
+    /// Map a failed xattr syscall to an error, translating ENOATTR.
+    fn xattr_error() -> Error {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ENOATTR) {
+            FsError::NoAttribute.into()
+        } else {
+            err.into()
+        }
+    }
+
     /// Create or reuse an inode for the given source identity
     fn get_or_create_inode(&self, path: PathBuf, stat: &libc::stat) -> (i64, bool) {
         let src_id = SrcId {
@@ -345,7 +400,7 @@ impl FileSystem for HostFS {
         }
 
         // Build child path
-        let child_path = parent_path.join(name);
+        let child_path = child_path(&parent_path, name)?;
 
         // Get stats using lstat (don't follow symlinks)
         let stat = match Self::lstat_path(&child_path) {
@@ -553,11 +608,20 @@ impl FileSystem for HostFS {
     }
 
     async fn chmod(&self, ino: i64, mode: u32) -> Result<()> {
+        self.check_writable()?;
         let path = self.get_inode_path(ino)?;
         let c_path = CString::new(path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
 
-        let result = unsafe { libc::chmod(c_path.as_ptr(), mode as libc::mode_t) };
+        // Don't follow symlinks: the target may lie outside the base directory
+        let result = unsafe {
+            libc::fchmodat(
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                mode as libc::mode_t,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
         if result < 0 {
             return Err(std::io::Error::last_os_error().into());
         }
@@ -565,6 +629,7 @@ impl FileSystem for HostFS {
     }
 
     async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.check_writable()?;
         let path = self.get_inode_path(ino)?;
 
         // Get current ownership if needed
@@ -584,6 +649,7 @@ impl FileSystem for HostFS {
     }
 
     async fn utimens(&self, ino: i64, atime: TimeChange, mtime: TimeChange) -> Result<()> {
+        self.check_writable()?;
         let path = self.get_inode_path(ino)?;
 
         let to_timespec = |tc: TimeChange| -> libc::timespec {
@@ -622,9 +688,159 @@ impl FileSystem for HostFS {
         Ok(())
     }
 
+    async fn access(&self, ino: i64, mask: i32, uid: u32, gid: u32) -> Result<bool> {
+        let stats = self.getattr(ino).await?.ok_or(FsError::NotFound)?;
+        // access(2) reports EROFS for write checks on a read-only filesystem
+        if mask & libc::W_OK != 0 {
+            self.check_writable()?;
+        }
+        Ok(check_access(&stats, mask, uid, gid))
+    }
+
+    async fn getxattr(&self, ino: i64, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.get_inode_path(ino)?;
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::Internal("invalid path".to_string()))?;
+        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+
+        loop {
+            let size = unsafe {
+                libc::getxattr(
+                    c_path.as_ptr(),
+                    c_name.as_ptr(),
+                    std::ptr::null_mut(),
+                    0,
+                    0,
+                    libc::XATTR_NOFOLLOW,
+                )
+            };
+            if size < 0 {
+                return match Self::xattr_error() {
+                    Error::Fs(FsError::NoAttribute) => Ok(None),
+                    e => Err(e),
+                };
+            }
+
+            let mut buf = vec![0u8; size as usize];
+            let read = unsafe {
+                libc::getxattr(
+                    c_path.as_ptr(),
+                    c_name.as_ptr(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                    libc::XATTR_NOFOLLOW,
+                )
+            };
+            if read < 0 {
+                // The value grew between the two calls; retry with the new size
+                if get_errno() == libc::ERANGE {
+                    continue;
+                }
+                return match Self::xattr_error() {
+                    Error::Fs(FsError::NoAttribute) => Ok(None),
+                    e => Err(e),
+                };
+            }
+            buf.truncate(read as usize);
+            return Ok(Some(buf));
+        }
+    }
+
+    async fn setxattr(&self, ino: i64, name: &str, value: &[u8], flags: i32) -> Result<()> {
+        self.check_writable()?;
+        let path = self.get_inode_path(ino)?;
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::Internal("invalid path".to_string()))?;
+        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+
+        let result = unsafe {
+            libc::setxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+                flags | libc::XATTR_NOFOLLOW,
+            )
+        };
+        if result < 0 {
+            return Err(Self::xattr_error());
+        }
+        Ok(())
+    }
+
+    async fn listxattr(&self, ino: i64) -> Result<Vec<String>> {
+        let path = self.get_inode_path(ino)?;
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::Internal("invalid path".to_string()))?;
+
+        let buf = loop {
+            let size = unsafe {
+                libc::listxattr(
+                    c_path.as_ptr(),
+                    std::ptr::null_mut(),
+                    0,
+                    libc::XATTR_NOFOLLOW,
+                )
+            };
+            if size < 0 {
+                return Err(Self::xattr_error());
+            }
+
+            let mut buf = vec![0u8; size as usize];
+            let read = unsafe {
+                libc::listxattr(
+                    c_path.as_ptr(),
+                    buf.as_mut_ptr() as *mut libc::c_char,
+                    buf.len(),
+                    libc::XATTR_NOFOLLOW,
+                )
+            };
+            if read < 0 {
+                if get_errno() == libc::ERANGE {
+                    continue;
+                }
+                return Err(Self::xattr_error());
+            }
+            buf.truncate(read as usize);
+            break buf;
+        };
+
+        Ok(buf
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+
+    async fn removexattr(&self, ino: i64, name: &str) -> Result<()> {
+        self.check_writable()?;
+        let path = self.get_inode_path(ino)?;
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::Internal("invalid path".to_string()))?;
+        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+
+        let result =
+            unsafe { libc::removexattr(c_path.as_ptr(), c_name.as_ptr(), libc::XATTR_NOFOLLOW) };
+        if result < 0 {
+            return Err(Self::xattr_error());
+        }
+        Ok(())
+    }
+
     async fn open(&self, ino: i64, flags: i32) -> Result<BoxedFile> {
+        if flags & WRITE_FLAGS != 0 {
+            self.check_writable()?;
+        }
         let path = self.get_inode_path(ino)?;
-        let real_fd = Self::open_path(&path, flags)?;
+
+        // Never open through a symlink: its target may lie outside the base directory
+        let stat = Self::lstat_path(&path)?;
+        if stat.st_mode & libc::S_IFMT == libc::S_IFLNK {
+            return Err(FsError::SymlinkLoop.into());
+        }
+        let real_fd = Self::open_path(&path, flags | libc::O_NOFOLLOW)?;
         Ok(Arc::new(HostFSFile { fd: real_fd }))
     }
 
@@ -636,8 +852,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<Stats> {
+        self.check_writable()?;
         let parent_path = self.get_inode_path(parent_ino)?;
-        let new_path = parent_path.join(name);
+        let new_path = child_path(&parent_path, name)?;
         let c_path = CString::new(new_path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
 
@@ -664,8 +881,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<(Stats, BoxedFile)> {
+        self.check_writable()?;
         let parent_path = self.get_inode_path(parent_ino)?;
-        let new_path = parent_path.join(name);
+        let new_path = child_path(&parent_path, name)?;
         let c_path = CString::new(new_path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
 
@@ -706,8 +924,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<Stats> {
+        self.check_writable()?;
         let parent_path = self.get_inode_path(parent_ino)?;
-        let new_path = parent_path.join(name);
+        let new_path = child_path(&parent_path, name)?;
         let c_path = CString::new(new_path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
 
@@ -734,8 +953,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<Stats> {
+        self.check_writable()?;
         let parent_path = self.get_inode_path(parent_ino)?;
-        let new_path = parent_path.join(name);
+        let new_path = child_path(&parent_path, name)?;
         let c_path = CString::new(new_path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
         let c_target = CString::new(target).map_err(|_| FsError::InvalidPath)?;
@@ -755,8 +975,9 @@ impl FileSystem for HostFS {
     }
 
     async fn unlink(&self, parent_ino: i64, name: &str) -> Result<()> {
+        self.check_writable()?;
         let parent_path = self.get_inode_path(parent_ino)?;
-        let path = parent_path.join(name);
+        let path = child_path(&parent_path, name)?;
         let c_path = CString::new(path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
 
@@ -766,6 +987,10 @@ impl FileSystem for HostFS {
             if err.kind() == std::io::ErrorKind::NotFound {
                 return Err(FsError::NotFound.into());
             }
+            // macOS reports EPERM rather than EISDIR for a directory
+            if std::fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir()) {
+                return Err(FsError::IsADirectory.into());
+            }
             return Err(err.into());
         }
 
@@ -773,8 +998,9 @@ impl FileSystem for HostFS {
     }
 
     async fn rmdir(&self, parent_ino: i64, name: &str) -> Result<()> {
+        self.check_writable()?;
         let parent_path = self.get_inode_path(parent_ino)?;
-        let path = parent_path.join(name);
+        let path = child_path(&parent_path, name)?;
         let c_path = CString::new(path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
 
@@ -797,9 +1023,10 @@ impl FileSystem for HostFS {
     }
 
     async fn link(&self, ino: i64, newparent_ino: i64, newname: &str) -> Result<Stats> {
+        self.check_writable()?;
         let path = self.get_inode_path(ino)?;
         let newparent_path = self.get_inode_path(newparent_ino)?;
-        let new_path = newparent_path.join(newname);
+        let new_path = child_path(&newparent_path, newname)?;
 
         let c_old = CString::new(path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
@@ -825,18 +1052,33 @@ impl FileSystem for HostFS {
         oldname: &str,
         newparent_ino: i64,
         newname: &str,
+        flags: u32,
     ) -> Result<()> {
+        self.check_writable()?;
         let oldparent_path = self.get_inode_path(oldparent_ino)?;
         let newparent_path = self.get_inode_path(newparent_ino)?;
-        let old_path = oldparent_path.join(oldname);
-        let new_path = newparent_path.join(newname);
+        let old_path = child_path(&oldparent_path, oldname)?;
+        let new_path = child_path(&newparent_path, newname)?;
 
         let c_old = CString::new(old_path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
         let c_new = CString::new(new_path.as_os_str().as_bytes())
             .map_err(|_| Error::Internal("invalid path".to_string()))?;
 
-        let result = unsafe { libc::rename(c_old.as_ptr(), c_new.as_ptr()) };
+        // Map renameat2(2) flags onto their renamex_np(2) equivalents
+        let mut darwin_flags = 0;
+        if flags & RENAME_NOREPLACE != 0 {
+            darwin_flags |= libc::RENAME_EXCL;
+        }
+        if flags & RENAME_EXCHANGE != 0 {
+            darwin_flags |= libc::RENAME_SWAP;
+        }
+
+        let result = if darwin_flags == 0 {
+            unsafe { libc::rename(c_old.as_ptr(), c_new.as_ptr()) }
+        } else {
+            unsafe { libc::renamex_np(c_old.as_ptr(), c_new.as_ptr(), darwin_flags) }
+        };
         if result < 0 {
             let err = std::io::Error::last_os_error();
             if err.kind() == std::io::ErrorKind::NotFound {
@@ -845,6 +1087,16 @@ impl FileSystem for HostFS {
             return Err(err.into());
         }
 
+        // An exchange also moves the former destination to the old path
+        if flags & RENAME_EXCHANGE != 0 {
+            if let Ok(Some(stats)) = self.lookup(oldparent_ino, oldname).await {
+                let mut inodes = self.inodes.write().unwrap();
+                if let Some(inode) = inodes.get_mut(&stats.ino) {
+                    inode.path = old_path.clone();
+                }
+            }
+        }
+
         // Update the cached path for the moved inode
         // First, find the inode for this entry
         if let Ok(Some(stats)) = self.lookup(newparent_ino, newname).await {
@@ -869,15 +1121,28 @@ impl FileSystem for HostFS {
                 return Err(std::io::Error::last_os_error().into());
             }
 
+            let block_size = statfs.f_bsize as u64;
             Ok(FilesystemStats {
-                inodes: statfs.f_files,
-                bytes_used: (statfs.f_blocks - statfs.f_bfree) * statfs.f_bsize as u64,
+                inodes: statfs.f_files.saturating_sub(statfs.f_ffree),
+                bytes_used: (statfs.f_blocks - statfs.f_bfree) * block_size,
+                total_bytes: statfs.f_blocks * block_size,
+                free_bytes: statfs.f_bavail * block_size,
+                total_inodes: statfs.f_files,
+                block_size: block_size as u32,
             })
         })
         .await
         .map_err(|e| Error::Internal(e.to_string()))?
     }
 
+    async fn check_op(&self, op: &Operation) -> Result<()> {
+        self.check_writable()?;
+        // The host kernel checks permissions against this process's
+        // credentials
+        let creds = unsafe { (libc::geteuid(), libc::getegid()) };
+        check_op_preconditions(self, op, Some(creds)).await
+    }
+
     async fn forget(&self, ino: i64, nlookup: u64) {
         // Never forget root inode
         if ino == ROOT_INO {
@@ -936,6 +1201,57 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_hostfs_readonly_rejects_writes() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("file.txt"), b"original")?;
+        std::fs::create_dir(dir.path().join("subdir"))?;
+        let fs = HostFS::new_readonly(dir.path())?;
+        assert!(fs.is_readonly());
+
+        let is_rofs = |result: Result<()>| matches!(result, Err(Error::Fs(FsError::ReadOnly)));
+        let file = fs.lookup(ROOT_INO, "file.txt").await?.unwrap();
+        assert!(is_rofs(fs.open(file.ino, libc::O_RDWR).await.map(|_| ())));
+        assert!(is_rofs(
+            fs.open(file.ino, libc::O_RDONLY | libc::O_TRUNC)
+                .await
+                .map(|_| ())
+        ));
+        assert!(is_rofs(fs.chmod(file.ino, 0o600).await));
+        assert!(is_rofs(fs.setxattr(file.ino, "user.test", b"v", 0).await));
+        assert!(is_rofs(
+            fs.create_file(ROOT_INO, "new.txt", DEFAULT_FILE_MODE, 0, 0)
+                .await
+                .map(|_| ())
+        ));
+        assert!(is_rofs(
+            fs.mkdir(ROOT_INO, "newdir", 0o755, 0, 0).await.map(|_| ())
+        ));
+        assert!(is_rofs(fs.unlink(ROOT_INO, "file.txt").await));
+        assert!(is_rofs(fs.rmdir(ROOT_INO, "subdir").await));
+        assert!(is_rofs(
+            fs.rename(ROOT_INO, "file.txt", ROOT_INO, "moved.txt", 0)
+                .await
+        ));
+        assert!(is_rofs(
+            fs.access(file.ino, libc::W_OK, 0, 0).await.map(|_| ())
+        ));
+
+        // Reads still work and nothing on the host changed
+        let data = fs
+            .open(file.ino, libc::O_RDONLY)
+            .await?
+            .pread(0, 100)
+            .await?;
+        assert_eq!(data, b"original");
+        assert!(fs.access(file.ino, libc::R_OK, 0, 0).await?);
+        assert_eq!(std::fs::read(dir.path().join("file.txt"))?, b"original");
+        assert!(!dir.path().join("new.txt").exists());
+        assert!(dir.path().join("subdir").is_dir());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_hostfs_mkdir_readdir() -> Result<()> {
         let dir = tempdir()?;
@@ -983,4 +1299,78 @@ mod tests {
 
         Ok(())
     }
+
+    fn is_denied<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Fs(FsError::PermissionDenied)))
+    }
+
+    #[tokio::test]
+    async fn test_hostfs_rejects_dotdot_names() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("base");
+        std::fs::create_dir_all(base.join("sub"))?;
+        std::fs::write(dir.path().join("secret.txt"), b"secret")?;
+        let fs = HostFS::new(&base)?;
+
+        assert!(is_denied(fs.lookup(ROOT_INO, "..").await));
+        assert!(is_denied(fs.lookup(ROOT_INO, "../secret.txt").await));
+        let sub = fs.lookup(ROOT_INO, "sub").await?.unwrap();
+        assert!(is_denied(fs.lookup(sub.ino, "../../secret.txt").await));
+
+        // Creating or moving entries outside the base is refused too
+        assert!(is_denied(
+            fs.create_file(ROOT_INO, "../escaped.txt", DEFAULT_FILE_MODE, 0, 0)
+                .await
+        ));
+        assert!(is_denied(
+            fs.mkdir(ROOT_INO, "../escaped", 0o755, 0, 0).await
+        ));
+        assert!(is_denied(
+            fs.rename(ROOT_INO, "sub", ROOT_INO, "../moved", 0).await
+        ));
+        assert!(is_denied(fs.unlink(sub.ino, "../../secret.txt").await));
+        assert!(!dir.path().join("escaped.txt").exists());
+        assert!(!dir.path().join("escaped").exists());
+        assert!(base.join("sub").is_dir());
+        assert!(dir.path().join("secret.txt").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hostfs_does_not_follow_escaping_symlinks() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("base");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(&base)?;
+        std::fs::create_dir_all(&outside)?;
+        std::fs::write(outside.join("secret.txt"), b"secret")?;
+        std::os::unix::fs::symlink(&outside, base.join("abs"))?;
+        std::os::unix::fs::symlink(outside.join("secret.txt"), base.join("abs_file"))?;
+        std::os::unix::fs::symlink("../outside/secret.txt", base.join("rel_file"))?;
+        // A chain that only leaves the base on its last hop
+        std::os::unix::fs::symlink("abs_file", base.join("hop2"))?;
+        std::os::unix::fs::symlink("hop2", base.join("hop1"))?;
+        let fs = HostFS::new(&base)?;
+
+        // Symlinks are returned as symlinks, never resolved
+        let abs = fs.lookup(ROOT_INO, "abs").await?.unwrap();
+        assert!(abs.is_symlink());
+        assert_eq!(
+            fs.readlink(abs.ino).await?.unwrap(),
+            outside.display().to_string()
+        );
+        assert!(is_denied(fs.lookup(ROOT_INO, "abs/secret.txt").await));
+
+        for name in ["abs_file", "rel_file", "hop1", "hop2"] {
+            let stats = fs.lookup(ROOT_INO, name).await?.unwrap();
+            assert!(stats.is_symlink(), "{name}");
+            assert!(matches!(
+                fs.open(stats.ino, libc::O_RDONLY).await,
+                Err(Error::Fs(FsError::SymlinkLoop))
+            ));
+        }
+
+        Ok(())
+    }
 }
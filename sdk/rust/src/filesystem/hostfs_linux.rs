@@ -1,4 +1,8 @@
-use super::{BoxedFile, DirEntry, File, FileSystem, FilesystemStats, FsError, Stats, TimeChange};
+use super::readonly::WRITE_FLAGS;
+use super::{
+    check_access, check_op_preconditions, host_off_t, BoxedFile, DirEntry, File, FileSystem,
+    FilesystemStats, FsError, Operation, Stats, TimeChange,
+};
 use crate::error::{Error, Result};
 use async_trait::async_trait;
 use std::collections::HashMap;
@@ -52,6 +56,8 @@ pub struct HostFS {
     /// FUSE mountpoint inode to avoid deadlock when overlaying
     #[cfg(target_family = "unix")]
     fuse_mountpoint_inode: Option<u64>,
+    /// Fail every mutating call with `EROFS`
+    readonly: bool,
 }
 
 /// An open file handle for HostFS (real fd for I/O)
@@ -64,6 +70,7 @@ pub struct HostFSFile {
 impl File for HostFSFile {
     async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
         let fd = self.fd.as_raw_fd();
+        let offset = host_off_t(offset)?;
         tokio::task::spawn_blocking(move || {
             let mut buf = vec![0u8; size as usize];
             let n = unsafe {
@@ -71,7 +78,7 @@ impl File for HostFSFile {
                     fd,
                     buf.as_mut_ptr() as *mut libc::c_void,
                     size as usize,
-                    offset as libc::off_t,
+                    offset,
                 )
             };
             if n < 0 {
@@ -86,15 +93,11 @@ impl File for HostFSFile {
 
     async fn pwrite(&self, offset: u64, data: &[u8]) -> Result<()> {
         let fd = self.fd.as_raw_fd();
+        let offset = host_off_t(offset)?;
         let data = data.to_vec();
         tokio::task::spawn_blocking(move || {
             let n = unsafe {
-                libc::pwrite(
-                    fd,
-                    data.as_ptr() as *const libc::c_void,
-                    data.len(),
-                    offset as libc::off_t,
-                )
+                libc::pwrite(fd, data.as_ptr() as *const libc::c_void, data.len(), offset)
             };
             if n < 0 {
                 return Err(std::io::Error::last_os_error().into());
@@ -107,8 +110,9 @@ impl File for HostFSFile {
 
     async fn truncate(&self, size: u64) -> Result<()> {
         let fd = self.fd.as_raw_fd();
+        let size = host_off_t(size)?;
         tokio::task::spawn_blocking(move || {
-            let result = unsafe { libc::ftruncate(fd, size as libc::off_t) };
+            let result = unsafe { libc::ftruncate(fd, size) };
             if result < 0 {
                 return Err(std::io::Error::last_os_error().into());
             }
@@ -146,6 +150,18 @@ impl File for HostFSFile {
     }
 }
 
+/// Convert a directory entry name to a C string, refusing names that leave the parent.
+///
+/// `openat` and friends resolve `..` and follow symlinks in every component
+/// but the last, so a name like `..` or `link/etc` would reach files outside
+/// the base directory. Such names are rejected with `PermissionDenied`.
+fn entry_name(name: &str) -> Result<CString> {
+    if name == ".." || name.contains('/') {
+        return Err(FsError::PermissionDenied.into());
+    }
+    CString::new(name).map_err(|_| FsError::InvalidPath.into())
+}
+
 /// Convert libc::stat to our Stats struct
 fn stat_to_stats(stat: &libc::stat) -> Stats {
     Stats {
@@ -162,6 +178,7 @@ fn stat_to_stats(stat: &libc::stat) -> Stats {
         mtime_nsec: stat.st_mtime_nsec as u32,
         ctime_nsec: stat.st_ctime_nsec as u32,
         rdev: stat.st_rdev,
+        blocks: stat.st_blocks as u64,
     }
 }
 
@@ -228,9 +245,36 @@ impl HostFS {
             src_to_ino: RwLock::new(src_to_ino),
             next_ino: AtomicU64::new(2), // 1 is root
             fuse_mountpoint_inode: None,
+            readonly: false,
+        })
+    }
+
+    /// Create a HostFS that refuses to modify the directory
+    ///
+    /// Every mutating call, including opening a file for writing, fails with
+    /// `FsError::ReadOnly` (`EROFS`) before touching the host. Overlays use
+    /// this for their base so that a copy-up bug can't write to the user's
+    /// real directory.
+    pub fn new_readonly(root: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            readonly: true,
+            ..Self::new(root)?
         })
     }
 
+    /// Whether this HostFS was created with [`HostFS::new_readonly`]
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Fail with `EROFS` if the directory must not be modified
+    fn check_writable(&self) -> Result<()> {
+        if self.readonly {
+            return Err(FsError::ReadOnly.into());
+        }
+        Ok(())
+    }
+
     /// Set the FUSE mountpoint inode to avoid deadlock when overlaying
     #[cfg(target_family = "unix")]
     pub fn with_fuse_mountpoint(mut self, inode: u64) -> Self {
@@ -272,6 +316,25 @@ impl HostFS {
         Ok(stat)
     }
 
+    /// Build the /proc/self/fd path for an O_PATH fd.
+    ///
+    /// Path-based syscalls (like the xattr family) don't accept O_PATH fds
+    /// directly, but do follow the /proc magic link to the underlying file.
+    fn proc_fd_path(fd: RawFd) -> Result<CString> {
+        CString::new(format!("/proc/self/fd/{}", fd))
+            .map_err(|_| Error::Internal("invalid path".to_string()))
+    }
+
+    /// Map a failed xattr syscall to an error, translating ENODATA.
+    fn xattr_error() -> Error {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ENODATA) {
+            FsError::NoAttribute.into()
+        } else {
+            err.into()
+        }
+    }
+
     /// Open a real fd from an O_PATH fd via /proc/self/fd/
     fn open_real_fd(o_path_fd: RawFd, flags: libc::c_int) -> Result<OwnedFd> {
         let proc_path = format!("/proc/self/fd/{}\0", o_path_fd);
@@ -354,7 +417,7 @@ impl FileSystem for HostFS {
             }
         }
 
-        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+        let c_name = entry_name(name)?;
 
         // Open child with O_PATH | O_NOFOLLOW
         let child_fd =
@@ -588,6 +651,7 @@ impl FileSystem for HostFS {
     }
 
     async fn chmod(&self, ino: i64, mode: u32) -> Result<()> {
+        self.check_writable()?;
         let fd = self.get_inode_fd(ino)?;
 
         // fchmod doesn't work on O_PATH fds, use fchmodat via /proc/self/fd
@@ -602,6 +666,7 @@ impl FileSystem for HostFS {
     }
 
     async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.check_writable()?;
         let fd = self.get_inode_fd(ino)?;
 
         // Get current ownership if needed
@@ -626,6 +691,7 @@ impl FileSystem for HostFS {
     }
 
     async fn utimens(&self, ino: i64, atime: TimeChange, mtime: TimeChange) -> Result<()> {
+        self.check_writable()?;
         let fd = self.get_inode_fd(ino)?;
 
         let to_timespec = |tc: TimeChange, current: libc::timespec| -> libc::timespec {
@@ -660,9 +726,136 @@ impl FileSystem for HostFS {
         Ok(())
     }
 
+    async fn access(&self, ino: i64, mask: i32, uid: u32, gid: u32) -> Result<bool> {
+        let stats = self.getattr(ino).await?.ok_or(FsError::NotFound)?;
+        // access(2) reports EROFS for write checks on a read-only filesystem
+        if mask & libc::W_OK != 0 {
+            self.check_writable()?;
+        }
+        Ok(check_access(&stats, mask, uid, gid))
+    }
+
+    async fn getxattr(&self, ino: i64, name: &str) -> Result<Option<Vec<u8>>> {
+        let fd = self.get_inode_fd(ino)?;
+        let proc_path = Self::proc_fd_path(fd)?;
+        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+
+        loop {
+            let size = unsafe {
+                libc::getxattr(proc_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0)
+            };
+            if size < 0 {
+                return match Self::xattr_error() {
+                    Error::Fs(FsError::NoAttribute) => Ok(None),
+                    e => Err(e),
+                };
+            }
+
+            let mut buf = vec![0u8; size as usize];
+            let read = unsafe {
+                libc::getxattr(
+                    proc_path.as_ptr(),
+                    c_name.as_ptr(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if read < 0 {
+                // The value grew between the two calls; retry with the new size
+                if std::io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) {
+                    continue;
+                }
+                return match Self::xattr_error() {
+                    Error::Fs(FsError::NoAttribute) => Ok(None),
+                    e => Err(e),
+                };
+            }
+            buf.truncate(read as usize);
+            return Ok(Some(buf));
+        }
+    }
+
+    async fn setxattr(&self, ino: i64, name: &str, value: &[u8], flags: i32) -> Result<()> {
+        self.check_writable()?;
+        let fd = self.get_inode_fd(ino)?;
+        let proc_path = Self::proc_fd_path(fd)?;
+        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+
+        let result = unsafe {
+            libc::setxattr(
+                proc_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                flags,
+            )
+        };
+        if result < 0 {
+            return Err(Self::xattr_error());
+        }
+        Ok(())
+    }
+
+    async fn listxattr(&self, ino: i64) -> Result<Vec<String>> {
+        let fd = self.get_inode_fd(ino)?;
+        let proc_path = Self::proc_fd_path(fd)?;
+
+        let buf = loop {
+            let size = unsafe { libc::listxattr(proc_path.as_ptr(), std::ptr::null_mut(), 0) };
+            if size < 0 {
+                return Err(Self::xattr_error());
+            }
+
+            let mut buf = vec![0u8; size as usize];
+            let read = unsafe {
+                libc::listxattr(
+                    proc_path.as_ptr(),
+                    buf.as_mut_ptr() as *mut libc::c_char,
+                    buf.len(),
+                )
+            };
+            if read < 0 {
+                if std::io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) {
+                    continue;
+                }
+                return Err(Self::xattr_error());
+            }
+            buf.truncate(read as usize);
+            break buf;
+        };
+
+        Ok(buf
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+
+    async fn removexattr(&self, ino: i64, name: &str) -> Result<()> {
+        self.check_writable()?;
+        let fd = self.get_inode_fd(ino)?;
+        let proc_path = Self::proc_fd_path(fd)?;
+        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+
+        let result = unsafe { libc::removexattr(proc_path.as_ptr(), c_name.as_ptr()) };
+        if result < 0 {
+            return Err(Self::xattr_error());
+        }
+        Ok(())
+    }
+
     async fn open(&self, ino: i64, flags: i32) -> Result<BoxedFile> {
+        if flags & WRITE_FLAGS != 0 {
+            self.check_writable()?;
+        }
         let fd = self.get_inode_fd(ino)?;
 
+        // Never open through a symlink: its target may lie outside the base directory
+        let stat = Self::fstatat_empty_path(fd)?;
+        if stat.st_mode & libc::S_IFMT == libc::S_IFLNK {
+            return Err(FsError::SymlinkLoop.into());
+        }
+
         // Open real fd via /proc/self/fd with the requested flags
         let real_fd = Self::open_real_fd(fd, flags)?;
 
@@ -677,8 +870,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<Stats> {
+        self.check_writable()?;
         let parent_fd = self.get_inode_fd(parent_ino)?;
-        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+        let c_name = entry_name(name)?;
 
         let result = unsafe { libc::mkdirat(parent_fd, c_name.as_ptr(), mode as libc::mode_t) };
         if result < 0 {
@@ -703,8 +897,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<(Stats, BoxedFile)> {
+        self.check_writable()?;
         let parent_fd = self.get_inode_fd(parent_ino)?;
-        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+        let c_name = entry_name(name)?;
 
         // Create and open the file
         let file_fd = unsafe {
@@ -752,8 +947,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<Stats> {
+        self.check_writable()?;
         let parent_fd = self.get_inode_fd(parent_ino)?;
-        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+        let c_name = entry_name(name)?;
 
         let result = unsafe {
             libc::mknodat(
@@ -784,8 +980,9 @@ impl FileSystem for HostFS {
         _uid: u32,
         _gid: u32,
     ) -> Result<Stats> {
+        self.check_writable()?;
         let parent_fd = self.get_inode_fd(parent_ino)?;
-        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+        let c_name = entry_name(name)?;
         let c_target = CString::new(target).map_err(|_| FsError::InvalidPath)?;
 
         let result = unsafe { libc::symlinkat(c_target.as_ptr(), parent_fd, c_name.as_ptr()) };
@@ -803,8 +1000,9 @@ impl FileSystem for HostFS {
     }
 
     async fn unlink(&self, parent_ino: i64, name: &str) -> Result<()> {
+        self.check_writable()?;
         let parent_fd = self.get_inode_fd(parent_ino)?;
-        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+        let c_name = entry_name(name)?;
 
         let result = unsafe { libc::unlinkat(parent_fd, c_name.as_ptr(), 0) };
         if result < 0 {
@@ -812,6 +1010,9 @@ impl FileSystem for HostFS {
             if err.kind() == std::io::ErrorKind::NotFound {
                 return Err(FsError::NotFound.into());
             }
+            if err.raw_os_error() == Some(libc::EISDIR) {
+                return Err(FsError::IsADirectory.into());
+            }
             return Err(err.into());
         }
 
@@ -823,8 +1024,9 @@ impl FileSystem for HostFS {
     }
 
     async fn rmdir(&self, parent_ino: i64, name: &str) -> Result<()> {
+        self.check_writable()?;
         let parent_fd = self.get_inode_fd(parent_ino)?;
-        let c_name = CString::new(name).map_err(|_| FsError::InvalidPath)?;
+        let c_name = entry_name(name)?;
 
         let result = unsafe { libc::unlinkat(parent_fd, c_name.as_ptr(), libc::AT_REMOVEDIR) };
         if result < 0 {
@@ -845,9 +1047,10 @@ impl FileSystem for HostFS {
     }
 
     async fn link(&self, ino: i64, newparent_ino: i64, newname: &str) -> Result<Stats> {
+        self.check_writable()?;
         let fd = self.get_inode_fd(ino)?;
         let newparent_fd = self.get_inode_fd(newparent_ino)?;
-        let c_newname = CString::new(newname).map_err(|_| FsError::InvalidPath)?;
+        let c_newname = entry_name(newname)?;
 
         // linkat with AT_EMPTY_PATH to link from an O_PATH fd
         let result = unsafe {
@@ -877,18 +1080,21 @@ impl FileSystem for HostFS {
         oldname: &str,
         newparent_ino: i64,
         newname: &str,
+        flags: u32,
     ) -> Result<()> {
+        self.check_writable()?;
         let oldparent_fd = self.get_inode_fd(oldparent_ino)?;
         let newparent_fd = self.get_inode_fd(newparent_ino)?;
-        let c_oldname = CString::new(oldname).map_err(|_| FsError::InvalidPath)?;
-        let c_newname = CString::new(newname).map_err(|_| FsError::InvalidPath)?;
+        let c_oldname = entry_name(oldname)?;
+        let c_newname = entry_name(newname)?;
 
         let result = unsafe {
-            libc::renameat(
+            libc::renameat2(
                 oldparent_fd,
                 c_oldname.as_ptr(),
                 newparent_fd,
                 c_newname.as_ptr(),
+                flags,
             )
         };
         if result < 0 {
@@ -902,6 +1108,19 @@ impl FileSystem for HostFS {
         Ok(())
     }
 
+    async fn fallocate(&self, ino: i64, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.check_writable()?;
+        let fd = self.get_inode_fd(ino)?;
+        let real_fd = Self::open_real_fd(fd, libc::O_WRONLY | libc::O_CLOEXEC)?;
+        let (offset, len) = (host_off_t(offset)?, host_off_t(len)?);
+
+        let result = unsafe { libc::fallocate(real_fd.as_raw_fd(), mode, offset, len) };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
     async fn statfs(&self) -> Result<FilesystemStats> {
         let fd = self.root_fd.as_raw_fd();
 
@@ -912,15 +1131,28 @@ impl FileSystem for HostFS {
                 return Err(std::io::Error::last_os_error().into());
             }
 
+            let block_size = statfs.f_bsize as u64;
             Ok(FilesystemStats {
-                inodes: statfs.f_files,
-                bytes_used: (statfs.f_blocks - statfs.f_bfree) * statfs.f_bsize as u64,
+                inodes: statfs.f_files.saturating_sub(statfs.f_ffree),
+                bytes_used: (statfs.f_blocks - statfs.f_bfree) * block_size,
+                total_bytes: statfs.f_blocks * block_size,
+                free_bytes: statfs.f_bavail * block_size,
+                total_inodes: statfs.f_files,
+                block_size: block_size as u32,
             })
         })
         .await
         .map_err(|e| Error::Internal(e.to_string()))?
     }
 
+    async fn check_op(&self, op: &Operation) -> Result<()> {
+        self.check_writable()?;
+        // The host kernel checks permissions against this process's
+        // credentials
+        let creds = unsafe { (libc::geteuid(), libc::getegid()) };
+        check_op_preconditions(self, op, Some(creds)).await
+    }
+
     async fn forget(&self, ino: i64, nlookup: u64) {
         // Never forget root inode
         if ino == ROOT_INO {
@@ -984,6 +1216,110 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_hostfs_readonly_rejects_writes() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("file.txt"), b"original")?;
+        std::fs::create_dir(dir.path().join("subdir"))?;
+        let fs = HostFS::new_readonly(dir.path())?;
+        assert!(fs.is_readonly());
+
+        let is_rofs = |result: Result<()>| matches!(result, Err(Error::Fs(FsError::ReadOnly)));
+        let file = fs.lookup(ROOT_INO, "file.txt").await?.unwrap();
+        assert!(is_rofs(fs.open(file.ino, libc::O_RDWR).await.map(|_| ())));
+        assert!(is_rofs(
+            fs.open(file.ino, libc::O_RDONLY | libc::O_TRUNC)
+                .await
+                .map(|_| ())
+        ));
+        assert!(is_rofs(fs.chmod(file.ino, 0o600).await));
+        assert!(is_rofs(fs.setxattr(file.ino, "user.test", b"v", 0).await));
+        assert!(is_rofs(
+            fs.create_file(ROOT_INO, "new.txt", DEFAULT_FILE_MODE, 0, 0)
+                .await
+                .map(|_| ())
+        ));
+        assert!(is_rofs(
+            fs.mkdir(ROOT_INO, "newdir", 0o755, 0, 0).await.map(|_| ())
+        ));
+        assert!(is_rofs(fs.unlink(ROOT_INO, "file.txt").await));
+        assert!(is_rofs(fs.rmdir(ROOT_INO, "subdir").await));
+        assert!(is_rofs(
+            fs.rename(ROOT_INO, "file.txt", ROOT_INO, "moved.txt", 0)
+                .await
+        ));
+        assert!(is_rofs(
+            fs.access(file.ino, libc::W_OK, 0, 0).await.map(|_| ())
+        ));
+
+        // Reads still work and nothing on the host changed
+        let data = fs
+            .open(file.ino, libc::O_RDONLY)
+            .await?
+            .pread(0, 100)
+            .await?;
+        assert_eq!(data, b"original");
+        assert!(fs.access(file.ino, libc::R_OK, 0, 0).await?);
+        assert_eq!(std::fs::read(dir.path().join("file.txt"))?, b"original");
+        assert!(!dir.path().join("new.txt").exists());
+        assert!(dir.path().join("subdir").is_dir());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hostfs_remove_file_and_remove_dir() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("file.txt"), b"data")?;
+        std::fs::create_dir_all(dir.path().join("full/inner"))?;
+        std::fs::create_dir(dir.path().join("empty"))?;
+        let fs = HostFS::new(dir.path())?;
+
+        assert!(matches!(
+            fs.remove_file("/empty").await,
+            Err(Error::Fs(FsError::IsADirectory))
+        ));
+        assert!(matches!(
+            fs.remove_dir("/file.txt").await,
+            Err(Error::Fs(FsError::NotADirectory))
+        ));
+        assert!(matches!(
+            fs.remove_dir("/full").await,
+            Err(Error::Fs(FsError::NotEmpty))
+        ));
+
+        fs.remove_file("/file.txt").await?;
+        fs.remove_dir("/empty").await?;
+        fs.remove("/full/inner").await?;
+        assert!(!dir.path().join("file.txt").exists());
+        assert!(!dir.path().join("empty").exists());
+        assert!(!dir.path().join("full/inner").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hostfs_atomic_write_keeps_mode() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir()?;
+        let path = dir.path().join("config");
+        std::fs::write(&path, b"old")?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        let fs = HostFS::new(dir.path())?;
+
+        fs.atomic_write("/config", b"new contents").await?;
+        assert_eq!(std::fs::read(&path)?, b"new contents");
+        assert_eq!(
+            std::fs::metadata(&path)?.permissions().mode() & 0o7777,
+            0o600
+        );
+        // The temporary file was renamed into place
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_hostfs_mkdir_readdir() -> Result<()> {
         let dir = tempdir()?;
@@ -1031,4 +1367,100 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_hostfs_readdir_unusual_names() -> Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("line\nbreak"), b"")?;
+        std::fs::write(dir.path().join("bell\u{7}"), b"")?;
+        std::fs::write(dir.path().join(OsStr::from_bytes(b"raw\xff")), b"")?;
+        let fs = HostFS::new(dir.path())?;
+
+        let mut entries = fs.readdir(ROOT_INO).await?.unwrap();
+        entries.sort();
+        assert_eq!(entries, vec!["bell\u{7}", "line\nbreak", "raw\u{fffd}"]);
+
+        // Control characters are valid UTF-8 and round-trip through lookup
+        assert!(fs.lookup(ROOT_INO, "line\nbreak").await?.is_some());
+        assert!(fs.lookup(ROOT_INO, "bell\u{7}").await?.is_some());
+
+        Ok(())
+    }
+
+    fn is_denied<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Fs(FsError::PermissionDenied)))
+    }
+
+    #[tokio::test]
+    async fn test_hostfs_rejects_dotdot_names() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("base");
+        std::fs::create_dir_all(base.join("sub"))?;
+        std::fs::write(dir.path().join("secret.txt"), b"secret")?;
+        let fs = HostFS::new(&base)?;
+
+        assert!(is_denied(fs.lookup(ROOT_INO, "..").await));
+        assert!(is_denied(fs.lookup(ROOT_INO, "../secret.txt").await));
+        let sub = fs.lookup(ROOT_INO, "sub").await?.unwrap();
+        assert!(is_denied(fs.lookup(sub.ino, "../../secret.txt").await));
+
+        // Creating or moving entries outside the base is refused too
+        assert!(is_denied(
+            fs.create_file(ROOT_INO, "../escaped.txt", DEFAULT_FILE_MODE, 0, 0)
+                .await
+        ));
+        assert!(is_denied(
+            fs.mkdir(ROOT_INO, "../escaped", 0o755, 0, 0).await
+        ));
+        assert!(is_denied(
+            fs.rename(ROOT_INO, "sub", ROOT_INO, "../moved", 0).await
+        ));
+        assert!(is_denied(fs.unlink(sub.ino, "../../secret.txt").await));
+        assert!(!dir.path().join("escaped.txt").exists());
+        assert!(!dir.path().join("escaped").exists());
+        assert!(base.join("sub").is_dir());
+        assert!(dir.path().join("secret.txt").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hostfs_does_not_follow_escaping_symlinks() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("base");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(&base)?;
+        std::fs::create_dir_all(&outside)?;
+        std::fs::write(outside.join("secret.txt"), b"secret")?;
+        std::os::unix::fs::symlink(&outside, base.join("abs"))?;
+        std::os::unix::fs::symlink(outside.join("secret.txt"), base.join("abs_file"))?;
+        std::os::unix::fs::symlink("../outside/secret.txt", base.join("rel_file"))?;
+        // A chain that only leaves the base on its last hop
+        std::os::unix::fs::symlink("abs_file", base.join("hop2"))?;
+        std::os::unix::fs::symlink("hop2", base.join("hop1"))?;
+        let fs = HostFS::new(&base)?;
+
+        // Symlinks are returned as symlinks, never resolved
+        let abs = fs.lookup(ROOT_INO, "abs").await?.unwrap();
+        assert!(abs.is_symlink());
+        assert_eq!(
+            fs.readlink(abs.ino).await?.unwrap(),
+            outside.display().to_string()
+        );
+        assert!(is_denied(fs.lookup(ROOT_INO, "abs/secret.txt").await));
+
+        for name in ["abs_file", "rel_file", "hop1", "hop2"] {
+            let stats = fs.lookup(ROOT_INO, name).await?.unwrap();
+            assert!(stats.is_symlink(), "{name}");
+            assert!(matches!(
+                fs.open(stats.ino, libc::O_RDONLY).await,
+                Err(Error::Fs(FsError::SymlinkLoop))
+            ));
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use super::FsError;
+use crate::error::Result;
+
+/// The kind of advisory lock requested by [`FileSystem::lock`](super::FileSystem::lock).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockType {
+    /// Any number of owners may hold a shared lock at once (`LOCK_SH`, `F_RDLCK`)
+    Shared,
+    /// Only one owner may hold an exclusive lock (`LOCK_EX`, `F_WRLCK`)
+    Exclusive,
+}
+
+/// Holders of the lock on one inode
+#[derive(Debug)]
+enum LockState {
+    Shared(HashSet<u64>),
+    Exclusive(u64),
+}
+
+/// In-memory table of whole-file advisory locks, keyed by inode.
+///
+/// Locks live only as long as the table: they are not stored in the
+/// database, so they are not seen by other processes that open the same
+/// filesystem and disappear when it is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct LockTable {
+    locks: Mutex<HashMap<i64, LockState>>,
+}
+
+impl LockTable {
+    /// Take or convert `owner`'s lock on `ino` without waiting.
+    ///
+    /// Fails with `FsError::WouldBlock` when another owner holds a
+    /// conflicting lock. Requesting a lock the owner already holds succeeds,
+    /// and an owner can upgrade a shared lock it holds alone or downgrade an
+    /// exclusive one.
+    pub(crate) fn lock(&self, ino: i64, lock_type: LockType, owner: u64) -> Result<()> {
+        let mut locks = self.locks.lock().unwrap();
+        match (locks.get_mut(&ino), lock_type) {
+            (None, LockType::Shared) => {
+                locks.insert(ino, LockState::Shared(HashSet::from([owner])));
+            }
+            (None, LockType::Exclusive) => {
+                locks.insert(ino, LockState::Exclusive(owner));
+            }
+            (Some(LockState::Shared(owners)), LockType::Shared) => {
+                owners.insert(owner);
+            }
+            (Some(LockState::Shared(owners)), LockType::Exclusive) => {
+                if owners.len() > 1 || !owners.contains(&owner) {
+                    return Err(FsError::WouldBlock.into());
+                }
+                locks.insert(ino, LockState::Exclusive(owner));
+            }
+            (Some(LockState::Exclusive(holder)), _) => {
+                if *holder != owner {
+                    return Err(FsError::WouldBlock.into());
+                }
+                if lock_type == LockType::Shared {
+                    locks.insert(ino, LockState::Shared(HashSet::from([owner])));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Release `owner`'s lock on `ino`, if it holds one.
+    pub(crate) fn unlock(&self, ino: i64, owner: u64) {
+        let mut locks = self.locks.lock().unwrap();
+        let empty = match locks.get_mut(&ino) {
+            Some(LockState::Shared(owners)) => {
+                owners.remove(&owner);
+                owners.is_empty()
+            }
+            Some(LockState::Exclusive(holder)) => *holder == owner,
+            None => false,
+        };
+        if empty {
+            locks.remove(&ino);
+        }
+    }
+
+    /// Drop every lock on `ino`, once the inode itself is gone.
+    pub(crate) fn clear(&self, ino: i64) {
+        self.locks.lock().unwrap().remove(&ino);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn would_block(result: Result<()>) -> bool {
+        matches!(result, Err(crate::error::Error::Fs(FsError::WouldBlock)))
+    }
+
+    #[test]
+    fn test_shared_and_exclusive_conflicts() {
+        let table = LockTable::default();
+        table.lock(1, LockType::Shared, 10).unwrap();
+        table.lock(1, LockType::Shared, 11).unwrap();
+        assert!(would_block(table.lock(1, LockType::Exclusive, 10)));
+
+        table.unlock(1, 11);
+        table.lock(1, LockType::Exclusive, 10).unwrap();
+        assert!(would_block(table.lock(1, LockType::Shared, 11)));
+        assert!(would_block(table.lock(1, LockType::Exclusive, 11)));
+
+        // Locks on other inodes are independent
+        table.lock(2, LockType::Exclusive, 11).unwrap();
+    }
+
+    #[test]
+    fn test_convert_and_unlock() {
+        let table = LockTable::default();
+        table.lock(1, LockType::Exclusive, 10).unwrap();
+        table.lock(1, LockType::Exclusive, 10).unwrap();
+        table.lock(1, LockType::Shared, 10).unwrap();
+        table.lock(1, LockType::Shared, 11).unwrap();
+
+        // Unlocking a lock that is not held is a no-op
+        table.unlock(1, 12);
+        table.unlock(1, 10);
+        table.unlock(1, 11);
+        table.lock(1, LockType::Exclusive, 12).unwrap();
+
+        table.clear(1);
+        table.lock(1, LockType::Exclusive, 13).unwrap();
+    }
+}
@@ -3,23 +3,36 @@ pub mod agentfs;
 pub mod hostfs_darwin;
 #[cfg(target_os = "linux")]
 pub mod hostfs_linux;
+pub mod lock;
 pub mod overlayfs;
+pub mod readonly;
+pub mod remote;
+pub mod writeback;
 
 use crate::error::Result;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use thiserror::Error;
 
 // Re-export implementations
-pub use agentfs::AgentFS;
+pub use agentfs::{
+    AgentFS, AtimePolicy, BlobKey, BusyRetry, ChangeEvent, ChangeEventKind, CheckpointPolicy,
+    CompactStats, CompressionKind, Inconsistency, SnapshotChanges, TrashEntry, TrashPolicy,
+    MAX_CHUNK_SIZE, MIN_CHUNK_SIZE,
+};
 #[cfg(target_os = "macos")]
 pub use hostfs_darwin::HostFS;
 #[cfg(target_os = "linux")]
 pub use hostfs_linux::HostFS;
-pub use overlayfs::OverlayFS;
+pub use lock::LockType;
+pub use overlayfs::{ChangeEntry, ChangeKind, OverlayFS};
+pub use readonly::ReadOnlyFS;
+pub use remote::RemoteFS;
+pub use writeback::{SpillPolicy, WriteBackPolicy};
 
 /// Filesystem-specific errors with errno semantics
-#[derive(Debug, Error)]
+#[derive(Debug, Error, Serialize, Deserialize)]
 pub enum FsError {
     #[error("Path does not exist")]
     NotFound,
@@ -53,6 +66,33 @@ pub enum FsError {
 
     #[error("Filename too long")]
     NameTooLong,
+
+    #[error("No such extended attribute")]
+    NoAttribute,
+
+    #[error("Operation not supported")]
+    NotSupported,
+
+    #[error("No space left on device")]
+    NoSpace,
+
+    #[error("Read-only file system")]
+    ReadOnly,
+
+    #[error("Resource temporarily unavailable")]
+    WouldBlock,
+
+    #[error("Permission denied")]
+    PermissionDenied,
+
+    #[error("Device or resource busy")]
+    Busy,
+
+    #[error("File too large")]
+    FileTooLarge,
+
+    #[error("Function not implemented")]
+    NotImplemented,
 }
 
 impl FsError {
@@ -66,10 +106,23 @@ impl FsError {
             FsError::IsADirectory => libc::EISDIR,
             FsError::NotASymlink => libc::EINVAL,
             FsError::InvalidPath => libc::EINVAL,
-            FsError::RootOperation => libc::EPERM,
+            // As removing or renaming `/` fails on Linux
+            FsError::RootOperation => libc::EBUSY,
             FsError::SymlinkLoop => libc::ELOOP,
             FsError::InvalidRename => libc::EINVAL,
             FsError::NameTooLong => libc::ENAMETOOLONG,
+            #[cfg(target_os = "macos")]
+            FsError::NoAttribute => libc::ENOATTR,
+            #[cfg(not(target_os = "macos"))]
+            FsError::NoAttribute => libc::ENODATA,
+            FsError::NotSupported => libc::EOPNOTSUPP,
+            FsError::NoSpace => libc::ENOSPC,
+            FsError::ReadOnly => libc::EROFS,
+            FsError::WouldBlock => libc::EWOULDBLOCK,
+            FsError::PermissionDenied => libc::EACCES,
+            FsError::Busy => libc::EBUSY,
+            FsError::FileTooLarge => libc::EFBIG,
+            FsError::NotImplemented => libc::ENOSYS,
         }
     }
 }
@@ -77,6 +130,10 @@ impl FsError {
 /// Maximum filename length in bytes.
 pub const MAX_NAME_LEN: usize = 255;
 
+/// Maximum file size in bytes, the largest size a signed 64-bit `Stats::size`
+/// can hold.
+pub const MAX_FILE_SIZE: u64 = i64::MAX as u64;
+
 // File types for mode field
 pub const S_IFMT: u32 = 0o170000; // File type mask
 pub const S_IFREG: u32 = 0o100000; // Regular file
@@ -91,8 +148,29 @@ pub const S_IFSOCK: u32 = 0o140000; // Socket
 pub const DEFAULT_FILE_MODE: u32 = S_IFREG | 0o644; // Regular file, rw-r--r--
 pub const DEFAULT_DIR_MODE: u32 = S_IFDIR | 0o755; // Directory, rwxr-xr-x
 
+// setxattr(2) flags
+pub const XATTR_CREATE: i32 = 0x1; // Fail if the attribute already exists
+pub const XATTR_REPLACE: i32 = 0x2; // Fail if the attribute does not exist
+
+// fallocate(2) modes
+pub const FALLOC_FL_KEEP_SIZE: i32 = 0x1; // Allocate without changing the file size
+
+// renameat2(2) flags
+pub const RENAME_NOREPLACE: u32 = 0x1; // Fail if the destination already exists
+pub const RENAME_EXCHANGE: u32 = 0x2; // Atomically swap source and destination
+
+/// Owner id that `chown(2)` interprets as "leave unchanged" (`(uid_t)-1`).
+pub const OWNER_UNCHANGED: u32 = u32::MAX;
+
+/// Bytes moved per read/write by the default `FileSystem::copy_range`
+const COPY_BUFFER_SIZE: u64 = 1 << 20;
+
+/// Symlinks followed by the default `FileSystem::create` before it fails
+/// with `FsError::SymlinkLoop` (Linux `MAXSYMLINKS`)
+const CREATE_MAX_SYMLINKS: usize = 40;
+
 /// Represents a timestamp change request for utimens.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum TimeChange {
     /// Do not change this timestamp.
     Omit,
@@ -103,7 +181,7 @@ pub enum TimeChange {
 }
 
 /// File statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stats {
     pub ino: i64,
     pub mode: u32,
@@ -117,20 +195,29 @@ pub struct Stats {
     pub atime_nsec: u32,
     pub mtime_nsec: u32,
     pub ctime_nsec: u32,
-    pub rdev: u64, // Device ID for special files (char/block devices)
+    pub rdev: u64,   // Device ID for special files (char/block devices)
+    pub blocks: u64, // 512-byte blocks actually stored, like st_blocks
 }
 
 /// Filesystem statistics for statfs
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FilesystemStats {
-    /// Total number of inodes (files, directories, symlinks)
+    /// Number of inodes in use (files, directories, symlinks)
     pub inodes: u64,
     /// Total bytes used by file contents
     pub bytes_used: u64,
+    /// Capacity of the filesystem in bytes
+    pub total_bytes: u64,
+    /// Bytes that can still be written
+    pub free_bytes: u64,
+    /// Maximum number of inodes
+    pub total_inodes: u64,
+    /// Preferred block size for I/O and space accounting
+    pub block_size: u32,
 }
 
 /// Directory entry with full statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirEntry {
     /// Entry name (without path)
     pub name: String,
@@ -138,7 +225,75 @@ pub struct DirEntry {
     pub stats: Stats,
 }
 
+/// Type of a file, as encoded in the `S_IFMT` bits of its mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    Fifo,
+    CharDevice,
+    BlockDevice,
+    Socket,
+    /// A mode without a known file type
+    Unknown,
+}
+
+impl FileType {
+    /// The type encoded in `mode`
+    pub fn from_mode(mode: u32) -> Self {
+        match mode & S_IFMT {
+            S_IFREG => FileType::Regular,
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFIFO => FileType::Fifo,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFSOCK => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// Short name, e.g. `"dir"` or `"file"`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileType::Regular => "file",
+            FileType::Directory => "dir",
+            FileType::Symlink => "symlink",
+            FileType::Fifo => "fifo",
+            FileType::CharDevice => "char",
+            FileType::BlockDevice => "block",
+            FileType::Socket => "socket",
+            FileType::Unknown => "unknown",
+        }
+    }
+
+    /// The `DT_*` value of `struct dirent::d_type` for this type
+    pub fn dirent_type(&self) -> u8 {
+        match self {
+            FileType::Unknown => 0,
+            FileType::Fifo => 1,
+            FileType::CharDevice => 2,
+            FileType::Directory => 4,
+            FileType::BlockDevice => 6,
+            FileType::Regular => 8,
+            FileType::Symlink => 10,
+            FileType::Socket => 12,
+        }
+    }
+}
+
+impl std::fmt::Display for FileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl Stats {
+    pub fn file_type(&self) -> FileType {
+        FileType::from_mode(self.mode)
+    }
+
     pub fn is_file(&self) -> bool {
         (self.mode & S_IFMT) == S_IFREG
     }
@@ -152,6 +307,384 @@ impl Stats {
     }
 }
 
+/// Encode extended attribute names as a NUL-separated buffer.
+///
+/// This matches the `listxattr(2)` convention where every name, including
+/// the last one, is terminated by a NUL byte.
+pub fn encode_xattr_names(names: &[String]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(names.iter().map(|n| n.len() + 1).sum());
+    for name in names {
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
+/// Evaluate an `access(2)` mask against an inode's owner and mode bits.
+///
+/// `mask` is `F_OK` or a combination of `R_OK`, `W_OK` and `X_OK`. Only the
+/// caller's primary `gid` is considered. Root is granted read and write
+/// unconditionally, and execute when any execute bit is set or the inode is
+/// a directory.
+pub fn check_access(stats: &Stats, mask: i32, uid: u32, gid: u32) -> bool {
+    let wanted = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u32;
+    if wanted == 0 {
+        return true;
+    }
+    if uid == 0 {
+        return wanted & libc::X_OK as u32 == 0 || stats.mode & 0o111 != 0 || stats.is_directory();
+    }
+    let granted = if uid == stats.uid {
+        (stats.mode >> 6) & 0o7
+    } else if gid == stats.gid {
+        (stats.mode >> 3) & 0o7
+    } else {
+        stats.mode & 0o7
+    };
+    granted & wanted == wanted
+}
+
+/// Split a path into the components it names, relative to the root.
+///
+/// Empty components from repeated or trailing slashes and `.` are dropped,
+/// and `..` removes the preceding component without going above the root.
+/// A path without a leading slash is taken relative to the root. The
+/// resolution is purely lexical: symlinks are not consulted.
+pub fn path_components(path: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    components
+}
+
+/// Normalize a path to its absolute form, as resolved by [`path_components`].
+///
+/// `/a/b/../c`, `//a///c/` and `a/./c` all become `/a/c`, and a path that
+/// names the root becomes `/`.
+pub fn normalize_path(path: &str) -> String {
+    format!("/{}", path_components(path).join("/"))
+}
+
+/// Resolve the parent directory of `path` and the name of its last component.
+///
+/// Removing or replacing `/` itself is meaningless, so a path that names the
+/// root fails with `FsError::RootOperation`. A missing or non-directory
+/// ancestor fails with `FsError::NotFound`.
+async fn lookup_parent<'a, F: FileSystem + ?Sized>(
+    fs: &F,
+    path: &'a str,
+) -> Result<(i64, &'a str)> {
+    let components = path_components(path);
+    let Some((name, ancestors)) = components.split_last() else {
+        return Err(FsError::RootOperation.into());
+    };
+    let mut parent_ino = 1;
+    for component in ancestors {
+        parent_ino = fs
+            .lookup(parent_ino, component)
+            .await?
+            .filter(|s| s.is_directory())
+            .ok_or(FsError::NotFound)?
+            .ino;
+    }
+    Ok((parent_ino, name))
+}
+
+/// Write `data` to a temporary file next to `path` and rename it into
+/// place, as the default [`FileSystem::atomic_write`] does.
+pub(crate) async fn atomic_write_via_rename<F: FileSystem + ?Sized>(
+    fs: &F,
+    path: &str,
+    data: &[u8],
+) -> Result<()> {
+    static NEXT_TEMP: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+    let (parent_ino, name) = match lookup_parent(fs, path).await {
+        Err(crate::error::Error::Fs(FsError::RootOperation)) => {
+            return Err(FsError::IsADirectory.into())
+        }
+        result => result?,
+    };
+    let existing = match fs.lookup(parent_ino, name).await? {
+        Some(stats) if stats.is_directory() => return Err(FsError::IsADirectory.into()),
+        stats => stats.filter(|s| s.is_file()),
+    };
+    let (mode, uid, gid) = match &existing {
+        Some(stats) => (stats.mode & 0o7777, stats.uid, stats.gid),
+        None => (DEFAULT_FILE_MODE & 0o7777, 0, 0),
+    };
+
+    let temp = format!(
+        ".agentfs-atomic-{}-{}",
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    );
+    let (stats, file) = fs.create_file(parent_ino, &temp, mode, uid, gid).await?;
+    let result = async {
+        file.pwrite(0, data).await?;
+        file.fsync().await?;
+        // A umask may have narrowed the mode the old file had
+        if existing.is_some() && stats.mode & 0o7777 != mode {
+            fs.chmod(stats.ino, mode).await?;
+        }
+        fs.rename(parent_ino, &temp, parent_ino, name, 0).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs.unlink(parent_ino, &temp).await;
+    }
+    result
+}
+
+/// A mutating operation, described for [`FileSystem::check_op`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Operation {
+    /// Create a directory, as [`FileSystem::mkdir`]
+    Mkdir { parent_ino: i64, name: String },
+    /// Create a regular file or special file, as
+    /// [`FileSystem::create_file`] or [`FileSystem::mknod`]
+    CreateFile { parent_ino: i64, name: String },
+    /// Create a symbolic link, as [`FileSystem::symlink`]
+    Symlink { parent_ino: i64, name: String },
+    /// Add a hard link to `ino`, as [`FileSystem::link`]
+    Link {
+        ino: i64,
+        newparent_ino: i64,
+        newname: String,
+    },
+    /// Remove a non-directory, as [`FileSystem::unlink`]
+    Unlink { parent_ino: i64, name: String },
+    /// Remove an empty directory, as [`FileSystem::rmdir`]
+    Rmdir { parent_ino: i64, name: String },
+    /// Move an entry, as [`FileSystem::rename`]
+    Rename {
+        oldparent_ino: i64,
+        oldname: String,
+        newparent_ino: i64,
+        newname: String,
+        flags: u32,
+    },
+    /// Write `len` bytes at `offset`, as [`File::pwrite`]
+    Write { ino: i64, offset: u64, len: u64 },
+    /// Set the size of a file, as [`File::truncate`]
+    Truncate { ino: i64, size: u64 },
+}
+
+/// Run the checks every backend makes before an operation, through the
+/// trait's own lookups.
+///
+/// The parent must be an existing directory, a new name must be valid and
+/// unused, a name to remove or move must exist with the right type, and a
+/// file to write must not be a directory or grow past [`MAX_FILE_SIZE`].
+/// With `creds`, the `(uid, gid)` of the caller, the directories changed
+/// and the files written must also grant them write access. Moving a
+/// directory into itself and quotas are left to the backend.
+pub(crate) async fn check_op_preconditions<F: FileSystem + ?Sized>(
+    fs: &F,
+    op: &Operation,
+    creds: Option<(u32, u32)>,
+) -> Result<()> {
+    match op {
+        Operation::Mkdir { parent_ino, name }
+        | Operation::CreateFile { parent_ino, name }
+        | Operation::Symlink { parent_ino, name } => {
+            check_new_entry(fs, *parent_ino, name, creds).await
+        }
+        Operation::Link {
+            ino,
+            newparent_ino,
+            newname,
+        } => {
+            let stats = fs.getattr(*ino).await?.ok_or(FsError::NotFound)?;
+            if stats.is_directory() {
+                return Err(FsError::IsADirectory.into());
+            }
+            check_new_entry(fs, *newparent_ino, newname, creds).await
+        }
+        Operation::Unlink { parent_ino, name } => {
+            let stats = existing_entry(fs, *parent_ino, name, creds).await?;
+            if stats.is_directory() {
+                return Err(FsError::IsADirectory.into());
+            }
+            Ok(())
+        }
+        Operation::Rmdir { parent_ino, name } => {
+            let stats = existing_entry(fs, *parent_ino, name, creds).await?;
+            if !stats.is_directory() {
+                return Err(FsError::NotADirectory.into());
+            }
+            check_empty_dir(fs, stats.ino).await
+        }
+        Operation::Rename {
+            oldparent_ino,
+            oldname,
+            newparent_ino,
+            newname,
+            flags,
+        } => {
+            let src = existing_entry(fs, *oldparent_ino, oldname, creds).await?;
+            check_parent_dir(fs, *newparent_ino, creds).await?;
+            check_name(newname)?;
+            let dst = fs.lookup(*newparent_ino, newname).await?;
+            if flags & RENAME_EXCHANGE != 0 {
+                return dst.map(|_| ()).ok_or_else(|| FsError::NotFound.into());
+            }
+            let Some(dst) = dst else {
+                return Ok(());
+            };
+            if flags & RENAME_NOREPLACE != 0 {
+                return Err(FsError::AlreadyExists.into());
+            }
+            if dst.ino == src.ino {
+                return Ok(());
+            }
+            match (src.is_directory(), dst.is_directory()) {
+                (true, false) => Err(FsError::NotADirectory.into()),
+                (false, true) => Err(FsError::IsADirectory.into()),
+                (true, true) => check_empty_dir(fs, dst.ino).await,
+                (false, false) => Ok(()),
+            }
+        }
+        Operation::Write { ino, offset, len } => {
+            checked_file_end(*offset, *len)?;
+            check_writable_file(fs, *ino, creds).await
+        }
+        Operation::Truncate { ino, size } => {
+            if *size > MAX_FILE_SIZE {
+                return Err(FsError::FileTooLarge.into());
+            }
+            check_writable_file(fs, *ino, creds).await
+        }
+    }
+}
+
+/// Fail unless `name` can be used for a directory entry.
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(FsError::InvalidPath.into());
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(FsError::NameTooLong.into());
+    }
+    Ok(())
+}
+
+/// Fail unless `ino` is a directory whose entries `creds` may change.
+async fn check_parent_dir<F: FileSystem + ?Sized>(
+    fs: &F,
+    ino: i64,
+    creds: Option<(u32, u32)>,
+) -> Result<()> {
+    let stats = fs.getattr(ino).await?.ok_or(FsError::NotFound)?;
+    if !stats.is_directory() {
+        return Err(FsError::NotADirectory.into());
+    }
+    if let Some((uid, gid)) = creds {
+        if !fs.access(ino, libc::W_OK | libc::X_OK, uid, gid).await? {
+            return Err(FsError::PermissionDenied.into());
+        }
+    }
+    Ok(())
+}
+
+/// Fail unless `name` can be created in directory `parent_ino`.
+async fn check_new_entry<F: FileSystem + ?Sized>(
+    fs: &F,
+    parent_ino: i64,
+    name: &str,
+    creds: Option<(u32, u32)>,
+) -> Result<()> {
+    check_parent_dir(fs, parent_ino, creds).await?;
+    check_name(name)?;
+    if fs.lookup(parent_ino, name).await?.is_some() {
+        return Err(FsError::AlreadyExists.into());
+    }
+    Ok(())
+}
+
+/// Stats of the entry `name` in directory `parent_ino`, which is about to be
+/// removed or moved.
+async fn existing_entry<F: FileSystem + ?Sized>(
+    fs: &F,
+    parent_ino: i64,
+    name: &str,
+    creds: Option<(u32, u32)>,
+) -> Result<Stats> {
+    check_parent_dir(fs, parent_ino, creds).await?;
+    check_name(name)?;
+    fs.lookup(parent_ino, name)
+        .await?
+        .ok_or_else(|| FsError::NotFound.into())
+}
+
+/// Fail with `FsError::NotEmpty` if directory `ino` has entries.
+async fn check_empty_dir<F: FileSystem + ?Sized>(fs: &F, ino: i64) -> Result<()> {
+    match fs.readdir(ino).await? {
+        Some(entries) if !entries.is_empty() => Err(FsError::NotEmpty.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Fail unless `ino` is an existing non-directory `creds` may write to.
+async fn check_writable_file<F: FileSystem + ?Sized>(
+    fs: &F,
+    ino: i64,
+    creds: Option<(u32, u32)>,
+) -> Result<()> {
+    let stats = fs.getattr(ino).await?.ok_or(FsError::NotFound)?;
+    if stats.is_directory() {
+        return Err(FsError::IsADirectory.into());
+    }
+    if let Some((uid, gid)) = creds {
+        if !fs.access(ino, libc::W_OK, uid, gid).await? {
+            return Err(FsError::PermissionDenied.into());
+        }
+    }
+    Ok(())
+}
+
+/// End of a range of `len` bytes at `offset`, failing with `FileTooLarge`
+/// rather than wrapping if it would lie past [`MAX_FILE_SIZE`].
+pub(crate) fn checked_file_end(offset: u64, len: u64) -> Result<u64> {
+    offset
+        .checked_add(len)
+        .filter(|&end| end <= MAX_FILE_SIZE)
+        .ok_or_else(|| FsError::FileTooLarge.into())
+}
+
+/// Convert a file offset or size for a host system call, failing with
+/// `FileTooLarge` rather than wrapping where `off_t` is too narrow for it.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn host_off_t(value: u64) -> Result<libc::off_t> {
+    libc::off_t::try_from(value).map_err(|_| FsError::FileTooLarge.into())
+}
+
+/// Validate the arguments of `FileSystem::copy_range`.
+///
+/// Fails with `FsError::InvalidPath` when a range overflows or when both
+/// ranges are in the same file and overlap.
+pub(crate) fn check_copy_range(
+    src_ino: i64,
+    src_offset: u64,
+    dst_ino: i64,
+    dst_offset: u64,
+    len: u64,
+) -> Result<()> {
+    let src_end = src_offset.checked_add(len).ok_or(FsError::InvalidPath)?;
+    let dst_end = dst_offset.checked_add(len).ok_or(FsError::InvalidPath)?;
+    if src_ino == dst_ino && src_offset < dst_end && dst_offset < src_end {
+        return Err(FsError::InvalidPath.into());
+    }
+    Ok(())
+}
+
 /// An open file handle for performing I/O operations.
 ///
 /// This trait represents an open file, similar to a file descriptor in POSIX.
@@ -160,6 +693,10 @@ impl Stats {
 #[async_trait]
 pub trait File: Send + Sync {
     /// Read from the file at the given offset (like POSIX pread).
+    ///
+    /// Reading stops at the end of the file: a read that straddles it
+    /// returns only the bytes before it, and one starting at or past it
+    /// returns an empty buffer, not an error.
     async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>>;
 
     /// Write to the file at the given offset (like POSIX pwrite).
@@ -171,6 +708,14 @@ pub trait File: Send + Sync {
     /// Synchronize file data to persistent storage.
     async fn fsync(&self) -> Result<()>;
 
+    /// Store any writes the handle buffered (like the flush on POSIX close).
+    ///
+    /// Unlike `fsync` this does not wait for storage to be durable. Handles
+    /// that do not buffer writes have nothing to do.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Get file statistics.
     async fn fstat(&self) -> Result<Stats>;
 }
@@ -178,6 +723,68 @@ pub trait File: Send + Sync {
 /// A boxed File trait object for dynamic dispatch.
 pub type BoxedFile = Arc<dyn File>;
 
+/// A cursor over the entries of a directory.
+///
+/// Returned by [`FileSystem::readdir_stream`] to walk large directories
+/// without materializing every entry at once.
+#[async_trait]
+pub trait DirStream: Send {
+    /// Return the next entry, or `Ok(None)` once the directory is exhausted.
+    async fn next_entry(&mut self) -> Result<Option<DirEntry>>;
+}
+
+/// A boxed DirStream trait object for dynamic dispatch.
+pub type BoxedDirStream = Box<dyn DirStream>;
+
+/// A DirStream over entries that were already collected.
+struct VecDirStream(std::vec::IntoIter<DirEntry>);
+
+#[async_trait]
+impl DirStream for VecDirStream {
+    async fn next_entry(&mut self) -> Result<Option<DirEntry>> {
+        Ok(self.0.next())
+    }
+}
+
+/// A cursor over the contents of a file.
+///
+/// Returned by [`FileSystem::read_stream`] to read a file front to back
+/// without the caller tracking offsets. Dropping the stream closes the file.
+#[async_trait]
+pub trait ReadStream: Send {
+    /// Return the next block, or `Ok(None)` once the end of file is reached.
+    ///
+    /// Every block but the last has the same length, chosen by the
+    /// backend: 64 KiB for the default [`FileSystem::read_stream`], and the
+    /// chunk size for AgentFS.
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>>;
+}
+
+/// A boxed ReadStream trait object for dynamic dispatch.
+pub type BoxedReadStream = Box<dyn ReadStream>;
+
+/// Size of the blocks the default `read_stream` reads with each `pread`.
+const READ_STREAM_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// A ReadStream over an open file, reading one block per `pread`.
+struct FileReadStream {
+    file: BoxedFile,
+    offset: u64,
+    block_size: u64,
+}
+
+#[async_trait]
+impl ReadStream for FileReadStream {
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let data = self.file.pread(self.offset, self.block_size).await?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+        self.offset += data.len() as u64;
+        Ok(Some(data))
+    }
+}
+
 /// A trait defining filesystem operations using inode semantics.
 ///
 /// This trait uses inode-based operations rather than path-based operations,
@@ -206,7 +813,8 @@ pub trait FileSystem: Send + Sync {
 
     /// List directory contents by inode.
     ///
-    /// Returns entry names (not full paths) for the directory.
+    /// Returns entry names (not full paths) for the directory. Names that are
+    /// not valid UTF-8 are decoded lossily, with U+FFFD replacing invalid bytes.
     /// Returns `Ok(None)` if the directory does not exist.
     async fn readdir(&self, ino: i64) -> Result<Option<Vec<String>>>;
 
@@ -218,15 +826,104 @@ pub trait FileSystem: Send + Sync {
     /// Returns `Ok(None)` if the directory does not exist.
     async fn readdir_plus(&self, ino: i64) -> Result<Option<Vec<DirEntry>>>;
 
+    /// List directory contents with the type of each entry.
+    ///
+    /// Enough to tell directories from files while walking a tree, without a
+    /// `getattr` per entry. The default implementation keeps the types of
+    /// `readdir_plus`; backends that can read types alone should override it.
+    ///
+    /// Returns `Ok(None)` if the directory does not exist.
+    async fn readdir_types(&self, ino: i64) -> Result<Option<Vec<(String, FileType)>>> {
+        Ok(self.readdir_plus(ino).await?.map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let file_type = entry.stats.file_type();
+                    (entry.name, file_type)
+                })
+                .collect()
+        }))
+    }
+
+    /// Open a cursor over directory entries with their statistics.
+    ///
+    /// Yields the same entries as `readdir_plus`, one at a time. The default
+    /// implementation buffers `readdir_plus`; backends that can page through
+    /// their storage should override it.
+    ///
+    /// Returns `Ok(None)` if the directory does not exist.
+    async fn readdir_stream(&self, ino: i64) -> Result<Option<BoxedDirStream>> {
+        Ok(self
+            .readdir_plus(ino)
+            .await?
+            .map(|entries| Box::new(VecDirStream(entries.into_iter())) as BoxedDirStream))
+    }
+
+    /// Open a cursor over the contents of a file.
+    ///
+    /// Yields the file in blocks from the start to the end of file. The
+    /// default implementation opens the inode and issues one `pread` per
+    /// block; backends that can fetch several blocks per request should
+    /// override it.
+    ///
+    /// Returns `Ok(None)` if the inode does not exist, and fails with
+    /// `FsError::IsADirectory` for a directory.
+    async fn read_stream(&self, ino: i64) -> Result<Option<BoxedReadStream>> {
+        let Some(stats) = self.getattr(ino).await? else {
+            return Ok(None);
+        };
+        if stats.is_directory() {
+            return Err(FsError::IsADirectory.into());
+        }
+        let file = self.open(ino, libc::O_RDONLY).await?;
+        Ok(Some(Box::new(FileReadStream {
+            file,
+            offset: 0,
+            block_size: READ_STREAM_BLOCK_SIZE,
+        }) as BoxedReadStream))
+    }
+
     /// Change file mode/permissions by inode.
     async fn chmod(&self, ino: i64, mode: u32) -> Result<()>;
 
     /// Change file ownership by inode.
+    ///
+    /// A `None` (or [`OWNER_UNCHANGED`]) uid or gid leaves that field untouched.
     async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()>;
 
     /// Set file access and modification times by inode (utimensat semantics).
     async fn utimens(&self, ino: i64, atime: TimeChange, mtime: TimeChange) -> Result<()>;
 
+    /// Check whether a caller may access an inode (`access(2)` semantics).
+    ///
+    /// Returns `Ok(false)` when the mode bits deny the requested `mask`, and
+    /// `FsError::NotFound` when the inode does not exist. The default
+    /// implementation applies [`check_access`] to [`FileSystem::getattr`].
+    async fn access(&self, ino: i64, mask: i32, uid: u32, gid: u32) -> Result<bool> {
+        let stats = self.getattr(ino).await?.ok_or(FsError::NotFound)?;
+        Ok(check_access(&stats, mask, uid, gid))
+    }
+
+    /// Get the value of an extended attribute.
+    ///
+    /// Returns `Ok(None)` if the attribute does not exist.
+    async fn getxattr(&self, ino: i64, name: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Set the value of an extended attribute.
+    ///
+    /// The `flags` parameter follows `setxattr(2)`: [`XATTR_CREATE`] fails with
+    /// `AlreadyExists` if the attribute exists, and [`XATTR_REPLACE`] fails with
+    /// `NoAttribute` if it does not.
+    async fn setxattr(&self, ino: i64, name: &str, value: &[u8], flags: i32) -> Result<()>;
+
+    /// List the names of all extended attributes of an inode.
+    async fn listxattr(&self, ino: i64) -> Result<Vec<String>>;
+
+    /// Remove an extended attribute.
+    ///
+    /// Returns `NoAttribute` if the attribute does not exist.
+    async fn removexattr(&self, ino: i64, name: &str) -> Result<()>;
+
     /// Open a file by inode and return a file handle for I/O operations.
     ///
     /// The `flags` parameter specifies the access mode (e.g., `libc::O_RDONLY`,
@@ -246,6 +943,28 @@ pub trait FileSystem: Send + Sync {
         gid: u32,
     ) -> Result<Stats>;
 
+    /// Create a directory and any missing ancestors (`mkdir -p`).
+    ///
+    /// `path` is absolute. Components that already exist as directories are
+    /// kept as they are; an existing non-directory component (including a
+    /// symlink) fails with `FsError::NotADirectory`. Returns the stats of the
+    /// final directory, whether it was created or already existed.
+    ///
+    /// The default implementation walks the path with [`FileSystem::lookup`]
+    /// and [`FileSystem::mkdir`], so a failure part-way leaves the ancestors
+    /// created so far in place.
+    async fn mkdir_all(&self, path: &str, mode: u32, uid: u32, gid: u32) -> Result<Stats> {
+        let mut stats = self.getattr(1).await?.ok_or(FsError::NotFound)?;
+        for name in path_components(path) {
+            stats = match self.lookup(stats.ino, name).await? {
+                Some(existing) if existing.is_directory() => existing,
+                Some(_) => return Err(FsError::NotADirectory.into()),
+                None => self.mkdir(stats.ino, name, mode, uid, gid).await?,
+            };
+        }
+        Ok(stats)
+    }
+
     /// Create a new empty file with the specified mode and ownership.
     ///
     /// Returns both the file stats and an open file handle in a single operation.
@@ -289,24 +1008,398 @@ pub trait FileSystem: Send + Sync {
     /// Remove an empty directory.
     async fn rmdir(&self, parent_ino: i64, name: &str) -> Result<()>;
 
+    /// Remove the non-directory entry at `path` (`unlink(2)`).
+    ///
+    /// `path` is absolute and its last component is not followed if it is a
+    /// symlink. A directory fails with `FsError::IsADirectory`, removing `/`
+    /// with `FsError::RootOperation` and a missing path with
+    /// `FsError::NotFound`.
+    ///
+    /// The default implementation resolves the parent with
+    /// [`FileSystem::lookup`] and calls [`FileSystem::unlink`].
+    async fn remove_file(&self, path: &str) -> Result<()> {
+        let (parent_ino, name) = lookup_parent(self, path).await?;
+        self.unlink(parent_ino, name).await
+    }
+
+    /// Remove the empty directory at `path` (`rmdir(2)`).
+    ///
+    /// `path` is absolute. A non-directory fails with
+    /// `FsError::NotADirectory`, a directory with entries with
+    /// `FsError::NotEmpty`, removing `/` with `FsError::RootOperation` and a
+    /// missing path with `FsError::NotFound`.
+    ///
+    /// The default implementation resolves the parent with
+    /// [`FileSystem::lookup`] and calls [`FileSystem::rmdir`].
+    async fn remove_dir(&self, path: &str) -> Result<()> {
+        let (parent_ino, name) = lookup_parent(self, path).await?;
+        self.rmdir(parent_ino, name).await
+    }
+
+    /// Remove the file or empty directory at `path`, as
+    /// [`FileSystem::remove_file`] or [`FileSystem::remove_dir`] would
+    /// depending on what it names.
+    async fn remove(&self, path: &str) -> Result<()> {
+        let (parent_ino, name) = lookup_parent(self, path).await?;
+        let stats = self
+            .lookup(parent_ino, name)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        if stats.is_directory() {
+            self.rmdir(parent_ino, name).await
+        } else {
+            self.unlink(parent_ino, name).await
+        }
+    }
+
+    /// Remove a file or a whole directory tree (`rm -rf`).
+    ///
+    /// `path` is absolute and its last component is not followed if it is a
+    /// symlink. Removing `/` fails with `FsError::RootOperation`, and a
+    /// missing path with `FsError::NotFound`.
+    ///
+    /// The default implementation removes entries one at a time with
+    /// [`FileSystem::unlink`] and [`FileSystem::rmdir`], children first, so
+    /// a failure part-way leaves the rest of the tree in place.
+    async fn remove_all(&self, path: &str) -> Result<()> {
+        let (parent_ino, name) = lookup_parent(self, path).await?;
+        let stats = self
+            .lookup(parent_ino, name)
+            .await?
+            .ok_or(FsError::NotFound)?;
+        if !stats.is_directory() {
+            return self.unlink(parent_ino, name).await;
+        }
+
+        // Depth-first, removing each directory once its children are gone
+        let mut stack = vec![(parent_ino, name.to_string(), stats.ino, false)];
+        while let Some((parent, name, ino, emptied)) = stack.pop() {
+            if emptied {
+                self.rmdir(parent, &name).await?;
+                continue;
+            }
+            stack.push((parent, name, ino, true));
+            for entry in self.readdir_plus(ino).await?.unwrap_or_default() {
+                if entry.stats.is_directory() {
+                    stack.push((ino, entry.name, entry.stats.ino, false));
+                } else {
+                    self.unlink(ino, &entry.name).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Create a hard link.
     ///
     /// Creates a new directory entry `newname` under `newparent_ino` that refers
     /// to the same inode as `ino`. Returns the stats of the linked inode.
     async fn link(&self, ino: i64, newparent_ino: i64, newname: &str) -> Result<Stats>;
 
-    /// Rename/move a file or directory.
+    /// Rename/move a file or directory (`renameat2(2)` semantics).
+    ///
+    /// An existing destination is replaced if it is of the same kind: a file
+    /// onto a directory fails with `FsError::IsADirectory`, a directory onto a
+    /// file with `FsError::NotADirectory`, and a directory onto a non-empty
+    /// directory with `FsError::NotEmpty`. Moving a directory into its own
+    /// subtree fails with `FsError::InvalidRename`.
+    ///
+    /// With [`RENAME_NOREPLACE`] an existing destination fails with
+    /// `FsError::AlreadyExists`; with [`RENAME_EXCHANGE`] both entries must
+    /// exist and are swapped. Pass `0` for plain `rename(2)` behavior.
     async fn rename(
         &self,
         oldparent_ino: i64,
         oldname: &str,
         newparent_ino: i64,
         newname: &str,
+        flags: u32,
     ) -> Result<()>;
 
+    /// Allocate storage for the byte range `[offset, offset + len)`.
+    ///
+    /// Newly allocated ranges read back as zeros and existing data is kept.
+    /// The file size grows to `offset + len` unless `mode` contains
+    /// [`FALLOC_FL_KEEP_SIZE`]. Other modes fail with `FsError::NotSupported`,
+    /// which is also the default for filesystems that cannot preallocate.
+    ///
+    /// A size quota only charges the growth of the file and reserves nothing,
+    /// so writing the allocated range past the file size later can still
+    /// fail with `FsError::NoSpace`.
+    async fn fallocate(&self, _ino: i64, _offset: u64, _len: u64, _mode: i32) -> Result<()> {
+        Err(FsError::NotSupported.into())
+    }
+
+    /// Copy `len` bytes from `src_ino` at `src_offset` to `dst_ino` at
+    /// `dst_offset` (`copy_file_range(2)` semantics).
+    ///
+    /// The copy stops at the end of the source file, and the destination
+    /// grows as needed. Returns the number of bytes copied. Directories fail
+    /// with `FsError::IsADirectory`, and overlapping ranges within the same
+    /// file with `FsError::InvalidPath`.
+    ///
+    /// The default implementation moves the bytes through [`File::pread`] and
+    /// [`File::pwrite`]; backends that can copy stored data directly should
+    /// override it.
+    async fn copy_range(
+        &self,
+        src_ino: i64,
+        src_offset: u64,
+        dst_ino: i64,
+        dst_offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        check_copy_range(src_ino, src_offset, dst_ino, dst_offset, len)?;
+        for ino in [src_ino, dst_ino] {
+            if self
+                .getattr(ino)
+                .await?
+                .ok_or(FsError::NotFound)?
+                .is_directory()
+            {
+                return Err(FsError::IsADirectory.into());
+            }
+        }
+        let src = self.open(src_ino, libc::O_RDONLY).await?;
+        let dst = self.open(dst_ino, libc::O_WRONLY).await?;
+
+        let mut copied = 0;
+        while copied < len {
+            let want = (len - copied).min(COPY_BUFFER_SIZE);
+            let data = src.pread(src_offset + copied, want).await?;
+            if data.is_empty() {
+                break;
+            }
+            dst.pwrite(dst_offset + copied, &data).await?;
+            copied += data.len() as u64;
+        }
+        Ok(copied)
+    }
+
+    /// Append `data` to the end of the file at `path` and return the new size.
+    ///
+    /// `path` is absolute. A missing file fails with `FsError::NotFound` and
+    /// a directory with `FsError::IsADirectory`.
+    ///
+    /// The default implementation resolves `path` with [`FileSystem::lookup`]
+    /// without following symlinks, opens the file with `O_APPEND` and writes
+    /// through [`File::pwrite`], so it is only atomic if the backend's
+    /// append-mode handles are. Backends should override it to find the end
+    /// of file and write in one step.
+    async fn append(&self, path: &str, data: &[u8]) -> Result<u64> {
+        let mut stats = self.getattr(1).await?.ok_or(FsError::NotFound)?;
+        for name in path_components(path) {
+            if !stats.is_directory() {
+                return Err(FsError::NotADirectory.into());
+            }
+            stats = self
+                .lookup(stats.ino, name)
+                .await?
+                .ok_or(FsError::NotFound)?;
+        }
+        if stats.is_directory() {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        let file = self
+            .open(stats.ino, libc::O_WRONLY | libc::O_APPEND)
+            .await?;
+        let offset = file.fstat().await?.size as u64;
+        file.pwrite(offset, data).await?;
+        Ok(file.fstat().await?.size as u64)
+    }
+
+    /// Create the regular file at `path`, or open an existing one, in the
+    /// manner of `open(2)` with `O_CREAT`.
+    ///
+    /// `path` is absolute and its parent directory must exist. A new file is
+    /// created with `mode` already applied and is owned by uid and gid 0.
+    /// With `libc::O_EXCL` in `flags`, an existing entry fails with
+    /// `FsError::AlreadyExists`; otherwise an existing regular file is kept,
+    /// and with `libc::O_TRUNC` its contents are discarded. Other flags are
+    /// ignored. An existing directory fails with `FsError::IsADirectory`.
+    /// A symlink in the last component is followed, creating its target if
+    /// missing, unless `libc::O_NOFOLLOW` is set, which fails with
+    /// `FsError::SymlinkLoop`.
+    ///
+    /// The default implementation resolves the parent with
+    /// [`FileSystem::lookup`] and creates the file with
+    /// [`FileSystem::create_file`], so `O_EXCL` holds for everything the
+    /// backend's lookup can see. A followed link target is resolved
+    /// lexically against the link's directory, like [`path_components`].
+    async fn create(&self, path: &str, mode: u32, flags: i32) -> Result<()> {
+        let mut path = path.to_string();
+        let mut followed = 0;
+        loop {
+            let components = path_components(&path);
+            let Some((name, ancestors)) = components.split_last() else {
+                return Err(FsError::IsADirectory.into());
+            };
+            let mut parent = self.getattr(1).await?.ok_or(FsError::NotFound)?;
+            for ancestor in ancestors {
+                if !parent.is_directory() {
+                    return Err(FsError::NotADirectory.into());
+                }
+                parent = self
+                    .lookup(parent.ino, ancestor)
+                    .await?
+                    .ok_or(FsError::NotFound)?;
+            }
+            if !parent.is_directory() {
+                return Err(FsError::NotADirectory.into());
+            }
+
+            let existing = match self.lookup(parent.ino, name).await? {
+                Some(stats) => stats,
+                None => match self.create_file(parent.ino, name, mode, 0, 0).await {
+                    Ok(_) => return Ok(()),
+                    // Lost a race with another creator; open theirs instead
+                    Err(crate::error::Error::Fs(FsError::AlreadyExists))
+                        if flags & libc::O_EXCL == 0 =>
+                    {
+                        self.lookup(parent.ino, name)
+                            .await?
+                            .ok_or(FsError::NotFound)?
+                    }
+                    Err(e) => return Err(e),
+                },
+            };
+            if flags & libc::O_EXCL != 0 {
+                return Err(FsError::AlreadyExists.into());
+            }
+            if existing.is_directory() {
+                return Err(FsError::IsADirectory.into());
+            }
+            if existing.is_symlink() {
+                if flags & libc::O_NOFOLLOW != 0 || followed == CREATE_MAX_SYMLINKS {
+                    return Err(FsError::SymlinkLoop.into());
+                }
+                followed += 1;
+                let target = self
+                    .readlink(existing.ino)
+                    .await?
+                    .ok_or(FsError::NotFound)?;
+                path = if target.starts_with('/') {
+                    target
+                } else {
+                    format!("/{}/{}", ancestors.join("/"), target)
+                };
+                continue;
+            }
+            if flags & libc::O_TRUNC != 0 && existing.is_file() {
+                let file = self.open(existing.ino, libc::O_WRONLY).await?;
+                file.truncate(0).await?;
+            }
+            return Ok(());
+        }
+    }
+
+    /// Replace the contents of the file at `path` with `data` atomically.
+    ///
+    /// `path` is absolute and its parent directory must exist. Readers see
+    /// either the previous contents or all of `data`, never a partial file.
+    /// An existing file keeps its mode and owner; a new one is created with
+    /// mode `0o644` and uid and gid 0. An existing directory fails with
+    /// `FsError::IsADirectory`, and an existing symlink is replaced rather
+    /// than followed.
+    ///
+    /// The default implementation writes `data` to a hidden temporary file
+    /// in the same directory and renames it over `path` with
+    /// [`FileSystem::rename`], removing it again if anything fails, so it is
+    /// as atomic as the backend's rename. Backends should override it to
+    /// store and link the new contents in one step.
+    async fn atomic_write(&self, path: &str, data: &[u8]) -> Result<()> {
+        atomic_write_via_rename(self, path, data).await
+    }
+
+    /// Create `dst` as a copy-on-write clone of the regular file `src`
+    /// (`cp --reflink`).
+    ///
+    /// Both paths are absolute; `dst` must not exist, and its parent
+    /// directory must. The clone gets the contents, mode and owner of
+    /// `src` and shares its stored data, so cloning costs next to no space
+    /// until either file is written; then only the blocks written diverge.
+    /// A directory source fails with `FsError::IsADirectory`, and any other
+    /// non-regular one with `FsError::InvalidPath`.
+    ///
+    /// The default implementation fails with `FsError::NotSupported`, as
+    /// `FICLONE` does on filesystems that cannot share data, so callers can
+    /// fall back to copying the bytes.
+    async fn reflink(&self, _src: &str, _dst: &str) -> Result<()> {
+        Err(FsError::NotSupported.into())
+    }
+
+    /// Take an advisory whole-file lock on an inode without waiting
+    /// (`flock(2)` with `LOCK_NB`).
+    ///
+    /// `owner` identifies the holder, such as an open file description or a
+    /// process; requesting a different type converts the owner's lock.
+    /// Fails with `FsError::WouldBlock` when another owner holds a
+    /// conflicting lock. Locks are kept in memory by the filesystem instance
+    /// and are not visible to other processes using the same database.
+    ///
+    /// The default implementation fails with `FsError::NotSupported`.
+    async fn lock(&self, _ino: i64, _lock_type: LockType, _owner: u64) -> Result<()> {
+        Err(FsError::NotSupported.into())
+    }
+
+    /// Release `owner`'s advisory lock on an inode.
+    ///
+    /// Releasing a lock that is not held succeeds. The default
+    /// implementation fails with `FsError::NotSupported`.
+    async fn unlock(&self, _ino: i64, _owner: u64) -> Result<()> {
+        Err(FsError::NotSupported.into())
+    }
+
+    /// Make every completed operation durable in the backing store.
+    ///
+    /// [`File::fsync`] only guarantees that one file's committed writes
+    /// survive a crash; they may still live in a write-ahead log next to the
+    /// main store. `sync_all` is a full checkpoint: once it returns, the
+    /// backing store on its own reflects every operation that completed
+    /// before the call, so it can be copied, snapshotted or closed safely.
+    /// Call it before unmounting or taking an external snapshot.
+    ///
+    /// The default implementation does nothing, for backends that write
+    /// straight through to their storage.
+    async fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Make the entries of directory `ino` durable, as `fsync(2)` on a
+    /// directory does.
+    ///
+    /// Once this returns, creates, removes and renames that completed in the
+    /// directory survive a crash. Unlike [`sync_all`](Self::sync_all) this
+    /// does not checkpoint: the changes may still live in a write-ahead log,
+    /// so the backing store is not yet safe to copy on its own.
+    ///
+    /// The default implementation does nothing, for backends that write
+    /// straight through to their storage.
+    async fn fsync_dir(&self, _ino: i64) -> Result<()> {
+        Ok(())
+    }
+
     /// Get filesystem statistics.
     async fn statfs(&self) -> Result<FilesystemStats>;
 
+    /// Check whether `op` would succeed, without performing it.
+    ///
+    /// Runs the precondition checks the operation itself would make, such
+    /// as whether the parent exists, whether a name is taken by an entry of
+    /// a conflicting type and whether a write fits in the quota, and fails
+    /// with the error the operation would fail with. Nothing is changed, so
+    /// a plan of several operations can be checked before any of it is
+    /// applied; each operation is checked against the current state, not
+    /// the state earlier operations in the plan would leave. A check that
+    /// passes doesn't guarantee the operation will, since the filesystem
+    /// may change in between or fail for other reasons, such as I/O errors.
+    ///
+    /// The default implementation fails with `FsError::NotImplemented`.
+    async fn check_op(&self, _op: &Operation) -> Result<()> {
+        Err(FsError::NotImplemented.into())
+    }
+
     /// Forget about an inode (called when kernel drops inode from cache).
     ///
     /// The `nlookup` parameter indicates how many lookups the kernel is forgetting.
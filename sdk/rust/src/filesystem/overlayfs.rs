@@ -4,7 +4,7 @@ use std::{
     collections::{HashMap, HashSet},
     sync::{
         atomic::{AtomicI64, Ordering},
-        Arc, RwLock,
+        Arc, Mutex, RwLock,
     },
     time::{SystemTime, UNIX_EPOCH},
 };
@@ -12,7 +12,11 @@ use tracing::trace;
 use turso::{Connection, Value};
 
 use super::{
-    agentfs::AgentFS, BoxedFile, DirEntry, FileSystem, FilesystemStats, FsError, Stats, TimeChange,
+    agentfs::{AgentFS, Inconsistency},
+    check_access, check_op_preconditions, checked_file_end,
+    lock::{LockTable, LockType},
+    BoxedFile, DirEntry, File, FileSystem, FilesystemStats, FsError, Operation, Stats, TimeChange,
+    RENAME_EXCHANGE, RENAME_NOREPLACE,
 };
 
 /// Root inode number (matches FUSE convention)
@@ -36,6 +40,43 @@ struct InodeInfo {
     path: String,
 }
 
+/// Residency state of a regular file copied up block by block.
+#[derive(Debug)]
+struct PartialCopyUp {
+    /// Path of the source file in the base layer
+    base_path: String,
+    /// Copy-up block size in bytes
+    block_size: u64,
+    /// Leading bytes that may still be served from the base file.
+    /// Shrinks on truncate so that re-extending yields zeros.
+    base_size: u64,
+    /// Indices of blocks already resident in the delta
+    resident: HashSet<u64>,
+}
+
+/// How a path in the overlay differs from the base layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Exists only in the delta layer
+    Added,
+    /// Exists in both layers; the delta copy shadows the base
+    Modified,
+    /// Exists in the base layer but is hidden by a whiteout
+    Deleted,
+}
+
+/// A single changed path, as reported by [`OverlayFS::changeset`].
+#[derive(Debug, Clone)]
+pub struct ChangeEntry {
+    /// Absolute path within the overlay
+    pub path: String,
+    pub kind: ChangeKind,
+    /// Stats in the base layer (`None` for added paths)
+    pub base: Option<Stats>,
+    /// Stats in the delta layer (`None` for deleted paths)
+    pub delta: Option<Stats>,
+}
+
 /// A copy-on-write overlay filesystem using inode-based operations.
 ///
 /// Combines a read-only base layer with a writable delta layer (AgentFS).
@@ -58,6 +99,18 @@ pub struct OverlayFS {
     whiteouts: RwLock<HashSet<String>>,
     /// Origin mapping: delta_ino -> base_ino (for copy-up consistency)
     origin_map: RwLock<HashMap<i64, i64>>,
+    /// Copy-up block size; `None` copies whole files
+    copyup_granularity: Option<u64>,
+    /// Partially copied-up files: delta_ino -> residency state
+    partial: RwLock<HashMap<i64, Arc<tokio::sync::Mutex<PartialCopyUp>>>>,
+    /// Opaque directories: base entries below these paths are hidden
+    opaque: RwLock<HashSet<String>>,
+    /// Advisory file locks, keyed by overlay inode
+    locks: LockTable,
+    /// Inodes and bytes the base adds to the merged view, walked by the
+    /// first statfs and adjusted as base entries are hidden; `None` until
+    /// then, or after a change it can't be adjusted for
+    base_usage: Mutex<Option<(u64, u64)>>,
 }
 
 impl OverlayFS {
@@ -88,9 +141,27 @@ impl OverlayFS {
             next_ino: AtomicI64::new(2),
             whiteouts: RwLock::new(HashSet::new()),
             origin_map: RwLock::new(HashMap::new()),
+            copyup_granularity: None,
+            partial: RwLock::new(HashMap::new()),
+            opaque: RwLock::new(HashSet::new()),
+            locks: LockTable::default(),
+            base_usage: Mutex::new(None),
         }
     }
 
+    /// Copy regular files up block by block instead of as a whole.
+    ///
+    /// Copy-up then creates a sparse delta file and only the blocks touched
+    /// by a write are copied from the base; reads of other blocks fall
+    /// through to the base file. The delta content of such a file is only
+    /// complete when read through the overlay.
+    ///
+    /// A block size of zero copies files up whole, as without this call.
+    pub fn with_copyup_granularity(mut self, block_size: u64) -> Self {
+        self.copyup_granularity = (block_size > 0).then_some(block_size);
+        self
+    }
+
     /// Initialize the overlay filesystem schema
     pub async fn init_schema(conn: &Connection, base_path: &str) -> Result<()> {
         conn.execute(
@@ -122,6 +193,32 @@ impl OverlayFS {
             (),
         )
         .await?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_copyup_partial (
+                delta_ino INTEGER PRIMARY KEY,
+                base_path TEXT NOT NULL,
+                block_size INTEGER NOT NULL,
+                base_size INTEGER NOT NULL
+            )",
+            (),
+        )
+        .await?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_copyup_block (
+                delta_ino INTEGER NOT NULL,
+                block_index INTEGER NOT NULL,
+                PRIMARY KEY (delta_ino, block_index)
+            )",
+            (),
+        )
+        .await?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fs_opaque_dir (
+                path TEXT PRIMARY KEY
+            )",
+            (),
+        )
+        .await?;
         Ok(())
     }
 
@@ -131,6 +228,8 @@ impl OverlayFS {
         Self::init_schema(&conn, base_path).await?;
         self.load_whiteouts(&conn).await?;
         self.load_origins(&conn).await?;
+        self.load_partial_copyups(&conn).await?;
+        self.load_opaque_dirs(&conn).await?;
         Ok(())
     }
 
@@ -159,15 +258,245 @@ impl OverlayFS {
         self.load_whiteouts(&conn).await
     }
 
-    /// Load persisted state (whiteouts and origin mappings) from database.
-    /// Call this after creating an OverlayFS for an existing database.
+    /// Load persisted state (whiteouts, origin mappings, partial copy-ups and
+    /// opaque directories) from database. Call this after creating an OverlayFS for an existing database.
     pub async fn load(&self) -> Result<()> {
         let conn = self.delta.get_connection().await?;
         self.load_whiteouts(&conn).await?;
         self.load_origins(&conn).await?;
+        self.load_partial_copyups(&conn).await?;
+        self.load_opaque_dirs(&conn).await?;
+        Ok(())
+    }
+
+    /// Discard the delta layer, including whiteouts and copy-up state.
+    ///
+    /// Afterwards the overlay shows the base layer unchanged. Callers are
+    /// expected to have persisted the delta (e.g. into the base directory)
+    /// first; overlay inodes handed out earlier are invalidated.
+    pub async fn clear_delta(&self) -> Result<()> {
+        self.delta.clear().await?;
+
+        let conn = self.delta.get_connection().await?;
+        for table in ["fs_whiteout", "fs_origin"] {
+            conn.execute(&format!("DELETE FROM {table}"), ()).await?;
+        }
+        // Databases created before block-granular copy-up or opaque
+        // directories lack these tables
+        for table in ["fs_copyup_partial", "fs_copyup_block", "fs_opaque_dir"] {
+            conn.execute(&format!("DELETE FROM {table}"), ()).await.ok();
+        }
+
+        self.whiteouts.write().unwrap().clear();
+        self.origin_map.write().unwrap().clear();
+        self.partial.write().unwrap().clear();
+        self.opaque.write().unwrap().clear();
+        self.inode_map
+            .write()
+            .unwrap()
+            .retain(|&ino, _| ino == ROOT_INO);
+        self.reverse_map
+            .write()
+            .unwrap()
+            .retain(|_, &mut ino| ino == ROOT_INO);
+        self.path_map.write().unwrap().retain(|path, _| path == "/");
         Ok(())
     }
 
+    /// Check the delta layer as [`AgentFS::check`] does, and report
+    /// whiteouts for paths that do not exist in the base.
+    pub async fn check(&self) -> Result<Vec<Inconsistency>> {
+        let mut found = self.delta.check().await?;
+        let mut whiteouts: Vec<String> = self.whiteouts.read().unwrap().iter().cloned().collect();
+        whiteouts.sort();
+        for path in whiteouts {
+            if self.lookup_base_path(&path).await?.is_none() {
+                found.push(Inconsistency::StaleWhiteout { path });
+            }
+        }
+        Ok(found)
+    }
+
+    /// Fix the repairable findings of [`OverlayFS::check`] and return how
+    /// many were fixed.
+    pub async fn repair(&self, found: &[Inconsistency]) -> Result<usize> {
+        let mut repaired = self.delta.repair(found).await?;
+        for finding in found {
+            if let Inconsistency::StaleWhiteout { path } = finding {
+                self.remove_whiteout(path).await?;
+                repaired += 1;
+            }
+        }
+        Ok(repaired)
+    }
+
+    /// Compare the delta layer against the base layer.
+    ///
+    /// Every path present in the delta is reported as added or modified,
+    /// depending on whether the base has it too, and every whiteout that
+    /// hides a base path is reported as deleted. Entries are sorted by path,
+    /// so directories come before their contents.
+    pub async fn changeset(&self) -> Result<Vec<ChangeEntry>> {
+        let mut changes = Vec::new();
+
+        let mut queue = vec![(ROOT_INO, String::new())];
+        while let Some((ino, prefix)) = queue.pop() {
+            let Some(entries) = FileSystem::readdir_plus(&self.delta, ino).await? else {
+                continue;
+            };
+            for entry in entries {
+                let path = format!("{}/{}", prefix, entry.name);
+                let base = self.base_stats(&path).await?;
+                if entry.stats.is_directory() {
+                    queue.push((entry.stats.ino, path.clone()));
+                }
+                changes.push(ChangeEntry {
+                    kind: if base.is_some() {
+                        ChangeKind::Modified
+                    } else {
+                        ChangeKind::Added
+                    },
+                    path,
+                    base,
+                    delta: Some(entry.stats),
+                });
+            }
+        }
+
+        let whiteouts: Vec<String> = self.whiteouts.read().unwrap().iter().cloned().collect();
+        for path in whiteouts {
+            changes.push(ChangeEntry {
+                base: self.base_stats(&path).await?,
+                kind: ChangeKind::Deleted,
+                path,
+                delta: None,
+            });
+        }
+
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(changes)
+    }
+
+    /// Inodes and bytes the base layer adds to the merged view.
+    ///
+    /// Walks the base tree alongside the delta, skipping whited-out paths,
+    /// the contents of opaque directories and entries the delta shadows, so
+    /// together with the delta's own usage every visible inode is counted
+    /// once. Directory sizes are not counted, as in the delta.
+    async fn base_usage(&self) -> Result<(u64, u64)> {
+        let mut inodes = 0u64;
+        let mut bytes = 0u64;
+        let mut seen = HashSet::new();
+
+        let mut queue = vec![(ROOT_INO, Some(ROOT_INO), String::new())];
+        while let Some((base_ino, delta_ino, prefix)) = queue.pop() {
+            let dir_path = if prefix.is_empty() { "/" } else { &prefix };
+            let entries = if self.is_opaque(dir_path) {
+                None
+            } else {
+                self.base.readdir_plus(base_ino).await?
+            };
+            // readdir_plus took a lookup on every entry, which a HostFS base
+            // holds an fd for; drop each once the walk is done with it
+            if base_ino != ROOT_INO {
+                self.base.forget(base_ino, 1).await;
+            }
+            let Some(entries) = entries else {
+                continue;
+            };
+            let delta_entries: HashMap<String, Stats> = match delta_ino {
+                Some(ino) => FileSystem::readdir_plus(&self.delta, ino)
+                    .await?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|entry| (entry.name, entry.stats))
+                    .collect(),
+                None => HashMap::new(),
+            };
+
+            for entry in entries {
+                let path = format!("{}/{}", prefix, entry.name);
+                // Whether to walk into the entry, and with which delta
+                // directory
+                let descend = if self.is_whiteout(&path) {
+                    None
+                } else {
+                    match delta_entries.get(&entry.name) {
+                        // The delta copy replaces the entry; a directory in
+                        // both layers still shows the base entries the delta
+                        // lacks
+                        Some(delta) => (entry.stats.is_directory() && delta.is_directory())
+                            .then_some(Some(delta.ino)),
+                        None => {
+                            if seen.insert(entry.stats.ino) {
+                                inodes += 1;
+                                if !entry.stats.is_directory() {
+                                    bytes += entry.stats.size.max(0) as u64;
+                                }
+                            }
+                            entry.stats.is_directory().then_some(None)
+                        }
+                    }
+                };
+                match descend {
+                    Some(delta_ino) => queue.push((entry.stats.ino, delta_ino, path)),
+                    None => self.base.forget(entry.stats.ino, 1).await,
+                }
+            }
+        }
+        Ok((inodes, bytes))
+    }
+
+    /// Inodes and bytes the base adds to the merged view, walking the base
+    /// only if no earlier walk is still current
+    async fn cached_base_usage(&self) -> Result<(u64, u64)> {
+        if let Some(usage) = *self.base_usage.lock().unwrap() {
+            return Ok(usage);
+        }
+        let usage = self.base_usage().await?;
+        *self.base_usage.lock().unwrap() = Some(usage);
+        Ok(usage)
+    }
+
+    /// Take a base entry that was visible out of the cached base usage, as
+    /// the delta now shadows it or a whiteout hides it
+    fn hide_base_usage(&self, stats: &Stats) {
+        let mut usage = self.base_usage.lock().unwrap();
+        let Some((inodes, bytes)) = *usage else {
+            return;
+        };
+        // A hard link may still show the inode under another name
+        if !stats.is_directory() && stats.nlink > 1 {
+            *usage = None;
+            return;
+        }
+        let size = if stats.is_directory() {
+            0
+        } else {
+            stats.size.max(0) as u64
+        };
+        *usage = Some((inodes.saturating_sub(1), bytes.saturating_sub(size)));
+    }
+
+    /// Whether an overlay inode still refers to its base entry
+    fn is_base_entry(&self, ino: i64) -> bool {
+        self.get_inode_info(ino)
+            .is_some_and(|info| info.layer == Layer::Base)
+    }
+
+    /// Drop the cached base usage, so the next statfs walks the base again
+    fn invalidate_base_usage(&self) {
+        *self.base_usage.lock().unwrap() = None;
+    }
+
+    /// Stats of a path in the base layer, if it exists there
+    async fn base_stats(&self, path: &str) -> Result<Option<Stats>> {
+        match self.lookup_base_path(path).await? {
+            Some(ino) => self.base.getattr(ino).await,
+            None => Ok(None),
+        }
+    }
+
     /// Load origin mappings from database
     async fn load_origins(&self, conn: &Connection) -> Result<()> {
         let result = conn
@@ -190,6 +519,71 @@ impl OverlayFS {
         Ok(())
     }
 
+    /// Load opaque directories from database
+    async fn load_opaque_dirs(&self, conn: &Connection) -> Result<()> {
+        let result = conn.query("SELECT path FROM fs_opaque_dir", ()).await;
+        let Ok(mut rows) = result else {
+            return Ok(());
+        };
+        let mut paths = Vec::new();
+        while let Some(row) = rows.next().await? {
+            if let Ok(Value::Text(path)) = row.get_value(0) {
+                paths.push(path.clone());
+            }
+        }
+        self.opaque.write().unwrap().extend(paths);
+        Ok(())
+    }
+
+    /// Load partial copy-up state from database
+    async fn load_partial_copyups(&self, conn: &Connection) -> Result<()> {
+        let result = conn
+            .query(
+                "SELECT delta_ino, base_path, block_size, base_size FROM fs_copyup_partial",
+                (),
+            )
+            .await;
+        let Ok(mut rows) = result else {
+            return Ok(());
+        };
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let int = |i: usize| row.get_value(i).ok().and_then(|v| v.as_integer().copied());
+            let base_path = row.get_value(1).ok().and_then(|v| match v {
+                Value::Text(s) => Some(s.clone()),
+                _ => None,
+            });
+            if let (Some(d), Some(p), Some(bs), Some(sz)) = (int(0), base_path, int(2), int(3)) {
+                entries.push((d, p, bs as u64, sz as u64));
+            }
+        }
+
+        for (delta_ino, base_path, block_size, base_size) in entries {
+            let mut rows = conn
+                .query(
+                    "SELECT block_index FROM fs_copyup_block WHERE delta_ino = ?",
+                    (delta_ino,),
+                )
+                .await?;
+            let mut resident = HashSet::new();
+            while let Some(row) = rows.next().await? {
+                if let Some(idx) = row.get_value(0).ok().and_then(|v| v.as_integer().copied()) {
+                    resident.insert(idx as u64);
+                }
+            }
+            self.partial.write().unwrap().insert(
+                delta_ino,
+                Arc::new(tokio::sync::Mutex::new(PartialCopyUp {
+                    base_path,
+                    block_size,
+                    base_size,
+                    resident,
+                })),
+            );
+        }
+        Ok(())
+    }
+
     /// Check if a path is whiteout (deleted from base)
     fn is_whiteout(&self, path: &str) -> bool {
         let whiteouts = self.whiteouts.read().unwrap();
@@ -229,6 +623,96 @@ impl OverlayFS {
         Ok(())
     }
 
+    /// Run `create`, which adds a delta entry at `path`, with any whiteout
+    /// for `path` removed. The whiteout is removed first and restored if
+    /// `create` fails, so a failure never leaves both the entry and its
+    /// whiteout behind.
+    async fn create_over_whiteout<T>(
+        &self,
+        path: &str,
+        create: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let whiteout = self.whiteouts.read().unwrap().contains(path);
+        if whiteout {
+            self.remove_whiteout(path).await?;
+        }
+        match create.await {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                if whiteout {
+                    self.create_whiteout(path).await?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Check if base entries below a directory are hidden, because the
+    /// directory or one of its ancestors is opaque
+    fn is_opaque(&self, dir_path: &str) -> bool {
+        let opaque = self.opaque.read().unwrap();
+        if opaque.is_empty() {
+            return false;
+        }
+        let mut current = String::new();
+        for component in dir_path.split('/').filter(|s| !s.is_empty()) {
+            current = format!("{}/{}", current, component);
+            if opaque.contains(&current) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Mark a directory opaque, so it no longer merges with the base
+    /// directory at the same path
+    async fn set_opaque(&self, path: &str) -> Result<()> {
+        let conn = self.delta.get_connection().await?;
+        conn.execute(
+            "INSERT OR REPLACE INTO fs_opaque_dir (path) VALUES (?)",
+            (path,),
+        )
+        .await?;
+        self.opaque.write().unwrap().insert(path.to_string());
+        Ok(())
+    }
+
+    /// Re-key the opaque marks at and below each source path onto its
+    /// target path, or drop them when the target is `None`. All moves are
+    /// resolved against the current marks first, so two paths can swap.
+    async fn remap_opaque(&self, moves: &[(&str, Option<&str>)]) -> Result<()> {
+        let mut remapped = Vec::new();
+        {
+            let opaque = self.opaque.read().unwrap();
+            for (from, to) in moves {
+                let prefix = format!("{}/", from);
+                for path in opaque.iter() {
+                    if path == *from || path.starts_with(&prefix) {
+                        let target = to.map(|to| format!("{}{}", to, &path[from.len()..]));
+                        remapped.push((path.clone(), target));
+                    }
+                }
+            }
+        }
+        if remapped.is_empty() {
+            return Ok(());
+        }
+        self.invalidate_base_usage();
+
+        let conn = self.delta.get_connection().await?;
+        for (path, _) in &remapped {
+            conn.execute("DELETE FROM fs_opaque_dir WHERE path = ?", (path.as_str(),))
+                .await?;
+            self.opaque.write().unwrap().remove(path);
+        }
+        for (_, target) in remapped {
+            if let Some(target) = target {
+                self.set_opaque(&target).await?;
+            }
+        }
+        Ok(())
+    }
+
     /// Get child whiteouts for a directory
     fn get_child_whiteouts(&self, dir_path: &str) -> HashSet<String> {
         let whiteouts = self.whiteouts.read().unwrap();
@@ -339,6 +823,70 @@ impl OverlayFS {
         Ok(())
     }
 
+    /// Record a file as partially copied up
+    async fn add_partial_copyup(
+        &self,
+        delta_ino: i64,
+        base_path: &str,
+        block_size: u64,
+        base_size: u64,
+    ) -> Result<()> {
+        let conn = self.delta.get_connection().await?;
+        conn.execute(
+            "INSERT OR REPLACE INTO fs_copyup_partial (delta_ino, base_path, block_size, base_size)
+            VALUES (?, ?, ?, ?)",
+            (delta_ino, base_path, block_size as i64, base_size as i64),
+        )
+        .await?;
+        self.partial.write().unwrap().insert(
+            delta_ino,
+            Arc::new(tokio::sync::Mutex::new(PartialCopyUp {
+                base_path: base_path.to_string(),
+                block_size,
+                base_size,
+                resident: HashSet::new(),
+            })),
+        );
+        Ok(())
+    }
+
+    /// Forget the partial copy-up of `delta_ino` once the delta inode is
+    /// gone, so its block map never outlives the file
+    async fn drop_partial_copyup(&self, delta_ino: i64) -> Result<()> {
+        if !self.partial.read().unwrap().contains_key(&delta_ino) {
+            return Ok(());
+        }
+        // Another link still reads through the base
+        if FileSystem::getattr(&self.delta, delta_ino).await?.is_some() {
+            return Ok(());
+        }
+        let conn = self.delta.get_connection().await?;
+        conn.execute(
+            "DELETE FROM fs_copyup_block WHERE delta_ino = ?",
+            (delta_ino,),
+        )
+        .await?;
+        conn.execute(
+            "DELETE FROM fs_copyup_partial WHERE delta_ino = ?",
+            (delta_ino,),
+        )
+        .await?;
+        self.partial.write().unwrap().remove(&delta_ino);
+        Ok(())
+    }
+
+    /// Resolve a base-layer path to its base inode
+    async fn lookup_base_path(&self, path: &str) -> Result<Option<i64>> {
+        let mut ino: i64 = ROOT_INO;
+        for comp in path.split('/').filter(|s| !s.is_empty()) {
+            match self.base.lookup(ino, comp).await? {
+                Some(stats) => ino = stats.ino,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(ino))
+    }
+
     /// Get origin inode for a delta inode
     fn get_origin_ino(&self, delta_ino: i64) -> Option<i64> {
         self.origin_map.read().unwrap().get(&delta_ino).copied()
@@ -431,6 +979,9 @@ impl OverlayFS {
             // lookups return consistent overlay inodes
             if let Some(base_ino) = origin_base_ino {
                 self.add_origin_mapping(new_stats.ino, base_ino).await?;
+                if let Some(s) = &base_stats {
+                    self.hide_base_usage(s);
+                }
                 // Promote the overlay inode to delta so readdir/unlink will check delta
                 self.promote_to_delta(&current_path, new_stats.ino);
             }
@@ -516,6 +1067,49 @@ impl OverlayFS {
             )
             .await?;
             stats.ino
+        } else if !base_stats.is_file() {
+            // FIFOs, sockets and device nodes have no content; recreate the
+            // node with the same type bits and device number
+            let stats = FileSystem::mknod(
+                &self.delta,
+                parent_ino,
+                name,
+                base_stats.mode,
+                base_stats.rdev,
+                base_stats.uid,
+                base_stats.gid,
+            )
+            .await?;
+            stats.ino
+        } else if let Some(block_size) = self.copyup_granularity {
+            // Regular file, block granularity - create a sparse placeholder and
+            // let OverlayFile copy blocks up as they are written
+            let (stats, delta_file) = FileSystem::create_file(
+                &self.delta,
+                parent_ino,
+                name,
+                base_stats.mode,
+                base_stats.uid,
+                base_stats.gid,
+            )
+            .await?;
+            // Record the copy-up before sizing the placeholder, and remove
+            // the placeholder if either fails, so a zero-filled delta file
+            // never hides the base content
+            let size = base_stats.size as u64;
+            let recorded = match self
+                .add_partial_copyup(stats.ino, path, block_size, size)
+                .await
+            {
+                Ok(()) => delta_file.truncate(size).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = recorded {
+                let _ = FileSystem::unlink(&self.delta, parent_ino, name).await;
+                let _ = self.drop_partial_copyup(stats.ino).await;
+                return Err(e);
+            }
+            stats.ino
         } else {
             // Regular file - read content and create
             let base_file = self.base.open(base_ino, libc::O_RDONLY).await?;
@@ -534,12 +1128,53 @@ impl OverlayFS {
             stats.ino
         };
 
+        // Carry extended attributes over, so later removals in the delta
+        // are not undone by the base copy
+        if let Ok(names) = self.base.listxattr(base_ino).await {
+            for name in names {
+                if let Some(value) = self.base.getxattr(base_ino, &name).await? {
+                    FileSystem::setxattr(&self.delta, delta_ino, &name, &value, 0).await?;
+                }
+            }
+        }
+
+        self.keep_base_metadata(delta_ino, &base_stats).await?;
+
         // Store origin mapping
         self.add_origin_mapping(delta_ino, base_ino).await?;
+        self.hide_base_usage(&base_stats);
 
         Ok(delta_ino)
     }
 
+    /// Give a copied-up inode the mode, ownership and timestamps of its base
+    /// file, which creating and filling it reset to the delta's umask and
+    /// the current time, so that copying up alone looks like no change.
+    async fn keep_base_metadata(&self, delta_ino: i64, base: &Stats) -> Result<()> {
+        // Buffered writes would set the mtime again when they are stored
+        self.delta.flush_writes().await?;
+        let conn = self.delta.get_connection().await?;
+        conn.execute(
+            "UPDATE fs_inode SET mode = ?, uid = ?, gid = ?, atime = ?, mtime = ?, ctime = ?,
+                 atime_nsec = ?, mtime_nsec = ?, ctime_nsec = ?
+             WHERE ino = ?",
+            (
+                base.mode as i64,
+                base.uid,
+                base.gid,
+                base.atime,
+                base.mtime,
+                base.ctime,
+                base.atime_nsec,
+                base.mtime_nsec,
+                base.ctime_nsec,
+                delta_ino,
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
     /// Copy-up a file and update the inode mapping so subsequent operations
     /// go to the delta layer. Returns the delta inode.
     async fn copy_up_and_update_mapping(&self, overlay_ino: i64, info: &InodeInfo) -> Result<i64> {
@@ -581,12 +1216,9 @@ impl FileSystem for OverlayFS {
         let parent_info = self.get_inode_info(parent_ino).ok_or(FsError::NotFound)?;
         let path = self.build_path(parent_ino, name)?;
 
-        // Check for whiteout
-        if self.is_whiteout(&path) {
-            return Ok(None);
-        }
-
-        // Try delta first - need to find the corresponding delta parent
+        // Try delta first, through the delta directory at the parent's path.
+        // A whiteout only hides the base entry, so an entry recreated in the
+        // delta is found even if its whiteout is still there.
         let delta_parent_ino = if parent_info.layer == Layer::Delta {
             Some(parent_info.underlying_ino)
         } else {
@@ -635,7 +1267,10 @@ impl FileSystem for OverlayFS {
             return Ok(Some(stats));
         }
 
-        // Try base
+        // Try base, unless a whiteout or an opaque directory hides it
+        if self.is_whiteout(&path) || self.is_opaque(&parent_info.path) {
+            return Ok(None);
+        }
         let base_parent_ino = if parent_info.layer == Layer::Base {
             parent_info.underlying_ino
         } else {
@@ -686,6 +1321,18 @@ impl FileSystem for OverlayFS {
         }))
     }
 
+    async fn access(&self, ino: i64, mask: i32, uid: u32, gid: u32) -> Result<bool> {
+        trace!("OverlayFS::access: ino={}, mask={}", ino, mask);
+
+        let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
+        if info.layer == Layer::Base && self.is_whiteout(&info.path) {
+            return Err(FsError::NotFound.into());
+        }
+
+        let stats = self.getattr(ino).await?.ok_or(FsError::NotFound)?;
+        Ok(check_access(&stats, mask, uid, gid))
+    }
+
     async fn readlink(&self, ino: i64) -> Result<Option<String>> {
         trace!("OverlayFS::readlink: ino={}", ino);
 
@@ -713,7 +1360,9 @@ impl FileSystem for OverlayFS {
         }
 
         // Get base entries (need to resolve base inode from path)
-        let base_ino = if info.layer == Layer::Base {
+        let base_ino = if self.is_opaque(&info.path) {
+            None
+        } else if info.layer == Layer::Base {
             Some(info.underlying_ino)
         } else {
             // Walk base to find corresponding directory
@@ -764,7 +1413,9 @@ impl FileSystem for OverlayFS {
         let mut entries_map: HashMap<String, DirEntry> = HashMap::new();
 
         // Get base entries first (so delta can override)
-        let base_ino = if info.layer == Layer::Base {
+        let base_ino = if self.is_opaque(&info.path) {
+            None
+        } else if info.layer == Layer::Base {
             Some(info.underlying_ino)
         } else {
             let components: Vec<&str> = info.path.split('/').filter(|s| !s.is_empty()).collect();
@@ -884,8 +1535,19 @@ impl FileSystem for OverlayFS {
         self.delta.utimens(delta_ino, atime, mtime).await
     }
 
-    async fn open(&self, ino: i64, flags: i32) -> Result<BoxedFile> {
-        trace!("OverlayFS::open: ino={}", ino);
+    async fn getxattr(&self, ino: i64, name: &str) -> Result<Option<Vec<u8>>> {
+        trace!("OverlayFS::getxattr: ino={}, name={}", ino, name);
+
+        let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
+
+        match info.layer {
+            Layer::Delta => FileSystem::getxattr(&self.delta, info.underlying_ino, name).await,
+            Layer::Base => self.base.getxattr(info.underlying_ino, name).await,
+        }
+    }
+
+    async fn setxattr(&self, ino: i64, name: &str, value: &[u8], flags: i32) -> Result<()> {
+        trace!("OverlayFS::setxattr: ino={}, name={}", ino, name);
 
         let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
 
@@ -894,32 +1556,90 @@ impl FileSystem for OverlayFS {
             Layer::Base => self.copy_up_and_update_mapping(ino, &info).await?,
         };
 
-        FileSystem::open(&self.delta, delta_ino, flags).await
+        FileSystem::setxattr(&self.delta, delta_ino, name, value, flags).await
     }
 
-    async fn mkdir(
-        &self,
-        parent_ino: i64,
-        name: &str,
-        mode: u32,
-        uid: u32,
-        gid: u32,
-    ) -> Result<Stats> {
-        trace!("OverlayFS::mkdir: parent_ino={}, name={}", parent_ino, name);
+    async fn listxattr(&self, ino: i64) -> Result<Vec<String>> {
+        trace!("OverlayFS::listxattr: ino={}", ino);
 
-        let parent_info = self.get_inode_info(parent_ino).ok_or(FsError::NotFound)?;
-        let path = self.build_path(parent_ino, name)?;
+        let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
 
-        // Check if already exists
-        if self.lookup(parent_ino, name).await?.is_some() {
-            return Err(FsError::AlreadyExists.into());
+        match info.layer {
+            Layer::Delta => FileSystem::listxattr(&self.delta, info.underlying_ino).await,
+            Layer::Base => self.base.listxattr(info.underlying_ino).await,
         }
+    }
 
-        // Remove whiteout if exists
-        self.remove_whiteout(&path).await?;
+    async fn removexattr(&self, ino: i64, name: &str) -> Result<()> {
+        trace!("OverlayFS::removexattr: ino={}, name={}", ino, name);
 
-        // Ensure parent dirs exist in delta
-        self.ensure_parent_dirs(&path, uid, gid).await?;
+        let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
+
+        // Copy-up brings the base attributes along, so removing from the
+        // delta hides the base value from then on
+        let delta_ino = match info.layer {
+            Layer::Delta => info.underlying_ino,
+            Layer::Base => self.copy_up_and_update_mapping(ino, &info).await?,
+        };
+
+        FileSystem::removexattr(&self.delta, delta_ino, name).await
+    }
+
+    async fn open(&self, ino: i64, flags: i32) -> Result<BoxedFile> {
+        trace!("OverlayFS::open: ino={}", ino);
+
+        let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
+
+        let delta_ino = match info.layer {
+            Layer::Delta => info.underlying_ino,
+            Layer::Base => self.copy_up_and_update_mapping(ino, &info).await?,
+        };
+
+        let delta_file = FileSystem::open(&self.delta, delta_ino, flags).await?;
+
+        // Partially copied-up files read unmodified blocks from the base
+        let partial = self.partial.read().unwrap().get(&delta_ino).cloned();
+        if let Some(state) = partial {
+            let base_path = state.lock().await.base_path.clone();
+            if let Some(base_ino) = self.lookup_base_path(&base_path).await? {
+                let base_file = self.base.open(base_ino, libc::O_RDONLY).await?;
+                return Ok(Arc::new(OverlayFile {
+                    delta: self.delta.clone(),
+                    delta_ino,
+                    delta_file,
+                    base_file,
+                    state,
+                }));
+            }
+        }
+
+        Ok(delta_file)
+    }
+
+    async fn mkdir(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        trace!("OverlayFS::mkdir: parent_ino={}, name={}", parent_ino, name);
+
+        let parent_info = self.get_inode_info(parent_ino).ok_or(FsError::NotFound)?;
+        let path = self.build_path(parent_ino, name)?;
+
+        // Check if already exists
+        if self.lookup(parent_ino, name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        // A directory recreated over a deleted base entry must not show that
+        // entry's old children
+        let replaces_base = self.whiteouts.read().unwrap().contains(&path);
+
+        // Ensure parent dirs exist in delta
+        self.ensure_parent_dirs(&path, uid, gid).await?;
 
         // Get delta parent inode
         let delta_parent_ino = if parent_info.layer == Layer::Delta {
@@ -935,8 +1655,16 @@ impl FileSystem for OverlayFS {
             ino
         };
 
-        let mut stats =
-            FileSystem::mkdir(&self.delta, delta_parent_ino, name, mode, uid, gid).await?;
+        let mut stats = self
+            .create_over_whiteout(&path, async {
+                let stats =
+                    FileSystem::mkdir(&self.delta, delta_parent_ino, name, mode, uid, gid).await?;
+                if replaces_base {
+                    self.set_opaque(&path).await?;
+                }
+                Ok(stats)
+            })
+            .await?;
         let overlay_ino = self.get_or_create_overlay_ino(Layer::Delta, stats.ino, &path);
         stats.ino = overlay_ino;
 
@@ -960,9 +1688,6 @@ impl FileSystem for OverlayFS {
         let parent_info = self.get_inode_info(parent_ino).ok_or(FsError::NotFound)?;
         let path = self.build_path(parent_ino, name)?;
 
-        // Remove whiteout if exists
-        self.remove_whiteout(&path).await?;
-
         // Ensure parent dirs exist in delta
         self.ensure_parent_dirs(&path, uid, gid).await?;
 
@@ -979,8 +1704,12 @@ impl FileSystem for OverlayFS {
             ino
         };
 
-        let (mut stats, file) =
-            FileSystem::create_file(&self.delta, delta_parent_ino, name, mode, uid, gid).await?;
+        let (mut stats, file) = self
+            .create_over_whiteout(
+                &path,
+                FileSystem::create_file(&self.delta, delta_parent_ino, name, mode, uid, gid),
+            )
+            .await?;
         let overlay_ino = self.get_or_create_overlay_ino(Layer::Delta, stats.ino, &path);
         stats.ino = overlay_ino;
 
@@ -1001,7 +1730,6 @@ impl FileSystem for OverlayFS {
         let parent_info = self.get_inode_info(parent_ino).ok_or(FsError::NotFound)?;
         let path = self.build_path(parent_ino, name)?;
 
-        self.remove_whiteout(&path).await?;
         self.ensure_parent_dirs(&path, uid, gid).await?;
 
         let delta_parent_ino = if parent_info.layer == Layer::Delta {
@@ -1016,8 +1744,12 @@ impl FileSystem for OverlayFS {
             ino
         };
 
-        let mut stats =
-            FileSystem::mknod(&self.delta, delta_parent_ino, name, mode, rdev, uid, gid).await?;
+        let mut stats = self
+            .create_over_whiteout(
+                &path,
+                FileSystem::mknod(&self.delta, delta_parent_ino, name, mode, rdev, uid, gid),
+            )
+            .await?;
         let overlay_ino = self.get_or_create_overlay_ino(Layer::Delta, stats.ino, &path);
         stats.ino = overlay_ino;
 
@@ -1042,7 +1774,6 @@ impl FileSystem for OverlayFS {
         let parent_info = self.get_inode_info(parent_ino).ok_or(FsError::NotFound)?;
         let path = self.build_path(parent_ino, name)?;
 
-        self.remove_whiteout(&path).await?;
         self.ensure_parent_dirs(&path, uid, gid).await?;
 
         let delta_parent_ino = if parent_info.layer == Layer::Delta {
@@ -1057,8 +1788,12 @@ impl FileSystem for OverlayFS {
             ino
         };
 
-        let mut stats =
-            FileSystem::symlink(&self.delta, delta_parent_ino, name, target, uid, gid).await?;
+        let mut stats = self
+            .create_over_whiteout(
+                &path,
+                FileSystem::symlink(&self.delta, delta_parent_ino, name, target, uid, gid),
+            )
+            .await?;
         let overlay_ino = self.get_or_create_overlay_ino(Layer::Delta, stats.ino, &path);
         stats.ino = overlay_ino;
 
@@ -1083,10 +1818,21 @@ impl FileSystem for OverlayFS {
         if stats.is_directory() {
             return Err(FsError::IsADirectory.into());
         }
+        let from_base = self.is_base_entry(stats.ino);
 
         // Try to remove from delta
         if parent_info.layer == Layer::Delta {
             let _ = FileSystem::unlink(&self.delta, parent_info.underlying_ino, name).await;
+            if let Some(info) = self.get_inode_info(stats.ino) {
+                if info.layer == Layer::Delta {
+                    self.drop_partial_copyup(info.underlying_ino).await?;
+                }
+            }
+        }
+
+        // Entries under an opaque directory never hide anything in the base
+        if self.is_opaque(&parent_info.path) {
+            return Ok(());
         }
 
         // Check if exists in base
@@ -1106,6 +1852,9 @@ impl FileSystem for OverlayFS {
 
         if self.base.lookup(base_parent_ino, name).await?.is_some() {
             self.create_whiteout(&path).await?;
+            if from_base {
+                self.hide_base_usage(&stats);
+            }
         }
 
         Ok(())
@@ -1131,12 +1880,18 @@ impl FileSystem for OverlayFS {
         if !dir_entries.is_empty() {
             return Err(FsError::NotEmpty.into());
         }
+        let from_base = self.is_base_entry(stats.ino);
 
         // Try to remove from delta
         if parent_info.layer == Layer::Delta {
             let _ = FileSystem::rmdir(&self.delta, parent_info.underlying_ino, name).await;
         }
 
+        self.remap_opaque(&[(path.as_str(), None)]).await?;
+        if self.is_opaque(&parent_info.path) {
+            return Ok(());
+        }
+
         // Check if exists in base
         let base_parent_ino = if parent_info.layer == Layer::Base {
             parent_info.underlying_ino
@@ -1154,6 +1909,9 @@ impl FileSystem for OverlayFS {
 
         if self.base.lookup(base_parent_ino, name).await?.is_some() {
             self.create_whiteout(&path).await?;
+            if from_base {
+                self.hide_base_usage(&stats);
+            }
         }
 
         Ok(())
@@ -1173,14 +1931,14 @@ impl FileSystem for OverlayFS {
             .ok_or(FsError::NotFound)?;
         let new_path = self.build_path(newparent_ino, newname)?;
 
-        // Ensure file is in delta (copy up if needed)
+        // Ensure file is in delta (copy up if needed), and remap the overlay
+        // inode so both names resolve to the same delta inode afterwards
         let delta_ino = if info.layer == Layer::Delta {
             info.underlying_ino
         } else {
-            self.copy_up(&info.path, info.underlying_ino).await?
+            self.copy_up_and_update_mapping(ino, &info).await?
         };
 
-        self.remove_whiteout(&new_path).await?;
         self.ensure_parent_dirs(&new_path, 0, 0).await?;
 
         // Get delta parent
@@ -1196,7 +1954,12 @@ impl FileSystem for OverlayFS {
             ino
         };
 
-        let mut stats = FileSystem::link(&self.delta, delta_ino, delta_parent_ino, newname).await?;
+        let mut stats = self
+            .create_over_whiteout(
+                &new_path,
+                FileSystem::link(&self.delta, delta_ino, delta_parent_ino, newname),
+            )
+            .await?;
         stats.ino = ino; // Keep original overlay inode
 
         Ok(stats)
@@ -1208,13 +1971,15 @@ impl FileSystem for OverlayFS {
         oldname: &str,
         newparent_ino: i64,
         newname: &str,
+        flags: u32,
     ) -> Result<()> {
         trace!(
-            "OverlayFS::rename: oldparent={}, oldname={}, newparent={}, newname={}",
+            "OverlayFS::rename: oldparent={}, oldname={}, newparent={}, newname={}, flags={:#x}",
             oldparent_ino,
             oldname,
             newparent_ino,
-            newname
+            newname,
+            flags
         );
 
         let old_parent_info = self
@@ -1235,7 +2000,70 @@ impl FileSystem for OverlayFS {
             .get_inode_info(src_stats.ino)
             .ok_or(FsError::NotFound)?;
 
-        // Ensure source is in delta
+        // Destination may live only in the base, so check it at the overlay level
+        let dst_stats = self.lookup(newparent_ino, newname).await?;
+        if flags & RENAME_NOREPLACE != 0 && dst_stats.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+        // A delta file replaced by the rename, whose copy-up state goes too
+        let replaced_delta_ino = dst_stats
+            .as_ref()
+            .filter(|dst| flags & RENAME_EXCHANGE == 0 && dst.ino != src_stats.ino)
+            .and_then(|dst| self.get_inode_info(dst.ino))
+            .filter(|info| info.layer == Layer::Delta)
+            .map(|info| info.underlying_ino);
+        if flags & RENAME_EXCHANGE == 0 {
+            if src_stats.is_directory() && new_path.starts_with(&format!("{}/", old_path)) {
+                return Err(FsError::InvalidRename.into());
+            }
+            // The delta can't see a destination that only lives in the base
+            match &dst_stats {
+                Some(dst) if dst.ino == src_stats.ino => {}
+                Some(dst) if dst.is_directory() && !src_stats.is_directory() => {
+                    return Err(FsError::IsADirectory.into());
+                }
+                Some(dst) if !dst.is_directory() && src_stats.is_directory() => {
+                    return Err(FsError::NotADirectory.into());
+                }
+                Some(dst) if dst.is_directory() => {
+                    let entries = self.readdir(dst.ino).await?.unwrap_or_default();
+                    if !entries.is_empty() {
+                        return Err(FsError::NotEmpty.into());
+                    }
+                }
+                _ => {}
+            }
+        }
+        if flags & RENAME_EXCHANGE != 0 {
+            let dst_stats = dst_stats.ok_or(FsError::NotFound)?;
+            let dst_info = self
+                .get_inode_info(dst_stats.ino)
+                .ok_or(FsError::NotFound)?;
+
+            // Base directories can't be swapped without dragging their base
+            // children along, same as Linux overlayfs without redirect_dir
+            let base_dir =
+                |info: &InodeInfo, stats: &Stats| info.layer == Layer::Base && stats.is_directory();
+            if base_dir(&src_info, &src_stats) || base_dir(&dst_info, &dst_stats) {
+                return Err(std::io::Error::from_raw_os_error(libc::EXDEV).into());
+            }
+
+            // Both entries must be in the delta before they can be swapped
+            if dst_info.layer == Layer::Base {
+                self.copy_up_and_update_mapping(dst_stats.ino, &dst_info)
+                    .await?;
+            }
+        }
+
+        // If source is in base, copy to delta first, which also creates
+        // its parent directories there. The source inode follows the copy,
+        // so later operations on it don't copy the old path up again.
+        if src_info.layer == Layer::Base {
+            self.copy_up_and_update_mapping(src_stats.ino, &src_info)
+                .await?;
+        }
+
+        // Get delta source parent
         let delta_src_parent_ino = if old_parent_info.layer == Layer::Delta {
             old_parent_info.underlying_ino
         } else {
@@ -1248,13 +2076,6 @@ impl FileSystem for OverlayFS {
             ino
         };
 
-        // If source is in base, copy to delta first
-        if src_info.layer == Layer::Base {
-            self.copy_up(&old_path, src_info.underlying_ino).await?;
-        }
-
-        // Remove whiteout at destination
-        self.remove_whiteout(&new_path).await?;
         self.ensure_parent_dirs(&new_path, 0, 0).await?;
 
         // Get delta destination parent
@@ -1271,16 +2092,49 @@ impl FileSystem for OverlayFS {
         };
 
         // Perform rename in delta
-        FileSystem::rename(
-            &self.delta,
-            delta_src_parent_ino,
-            oldname,
-            delta_dst_parent_ino,
-            newname,
+        self.create_over_whiteout(
+            &new_path,
+            FileSystem::rename(
+                &self.delta,
+                delta_src_parent_ino,
+                oldname,
+                delta_dst_parent_ino,
+                newname,
+                flags,
+            ),
         )
         .await?;
+        // Moving base entries, or replacing them, changes which base
+        // subtrees are visible; walk again rather than adjust
+        self.invalidate_base_usage();
+        if let Some(delta_ino) = replaced_delta_ino {
+            self.drop_partial_copyup(delta_ino).await?;
+        }
+
+        // Opaque directories keep hiding the base at their new location
+        if flags & RENAME_EXCHANGE != 0 {
+            self.remap_opaque(&[
+                (old_path.as_str(), Some(new_path.as_str())),
+                (new_path.as_str(), Some(old_path.as_str())),
+            ])
+            .await?;
+        } else {
+            self.remap_opaque(&[
+                (new_path.as_str(), None),
+                (old_path.as_str(), Some(new_path.as_str())),
+            ])
+            .await?;
+        }
+
+        // After an exchange both paths are still occupied, so no whiteout
+        if flags & RENAME_EXCHANGE != 0 {
+            return Ok(());
+        }
 
         // Create whiteout at source if it existed in base
+        if self.is_opaque(&old_parent_info.path) {
+            return Ok(());
+        }
         let base_src_parent_ino = if old_parent_info.layer == Layer::Base {
             old_parent_info.underlying_ino
         } else {
@@ -1302,13 +2156,115 @@ impl FileSystem for OverlayFS {
             .is_some()
         {
             self.create_whiteout(&old_path).await?;
+            self.invalidate_base_usage();
         }
 
         Ok(())
     }
 
+    async fn fallocate(&self, ino: i64, offset: u64, len: u64, mode: i32) -> Result<()> {
+        trace!(
+            "OverlayFS::fallocate: ino={}, offset={}, len={}, mode={:#x}",
+            ino,
+            offset,
+            len,
+            mode
+        );
+
+        let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
+
+        let delta_ino = match info.layer {
+            Layer::Delta => info.underlying_ino,
+            Layer::Base => self.copy_up_and_update_mapping(ino, &info).await?,
+        };
+
+        FileSystem::fallocate(&self.delta, delta_ino, offset, len, mode).await
+    }
+
+    async fn lock(&self, ino: i64, lock_type: LockType, owner: u64) -> Result<()> {
+        if self.get_inode_info(ino).is_none() {
+            return Err(FsError::NotFound.into());
+        }
+        self.locks.lock(ino, lock_type, owner)
+    }
+
+    async fn unlock(&self, ino: i64, owner: u64) -> Result<()> {
+        self.locks.unlock(ino, owner);
+        Ok(())
+    }
+
+    async fn sync_all(&self) -> Result<()> {
+        // All writes land in the delta; the base is never modified
+        FileSystem::sync_all(&self.delta).await
+    }
+
+    async fn fsync_dir(&self, ino: i64) -> Result<()> {
+        if self.getattr(ino).await?.is_none() {
+            return Err(FsError::NotFound.into());
+        }
+        // The delta is a single database, so syncing any of its directories
+        // makes every change to it durable
+        FileSystem::fsync_dir(&self.delta, ROOT_INO).await
+    }
+
     async fn statfs(&self) -> Result<FilesystemStats> {
-        FileSystem::statfs(&self.delta).await
+        // Usage is that of the merged tree. The base's own statfs can't be
+        // used for it: a HostFS base reports the whole host volume. Writes
+        // are bounded by the space left on that volume, though.
+        let delta = FileSystem::statfs(&self.delta).await?;
+        let base = self.base.statfs().await?;
+        let (base_inodes, base_bytes) = self.cached_base_usage().await?;
+        let inodes = delta.inodes + base_inodes;
+        Ok(FilesystemStats {
+            inodes,
+            bytes_used: delta.bytes_used + base_bytes,
+            total_bytes: delta.total_bytes.min(base.total_bytes),
+            free_bytes: delta.free_bytes.min(base.free_bytes),
+            total_inodes: base.total_inodes.max(inodes),
+            block_size: delta.block_size,
+        })
+    }
+
+    async fn check_op(&self, op: &Operation) -> Result<()> {
+        check_op_preconditions(self, op, None).await?;
+
+        let (ino, new_size) = match op {
+            Operation::Rename {
+                oldparent_ino,
+                oldname,
+                newparent_ino,
+                ..
+            } => {
+                let src = self.build_path(*oldparent_ino, oldname)?;
+                let dest = self
+                    .get_inode_info(*newparent_ino)
+                    .ok_or(FsError::NotFound)?
+                    .path;
+                if dest == src || dest.starts_with(&format!("{}/", src)) {
+                    return Err(FsError::InvalidRename.into());
+                }
+                return Ok(());
+            }
+            Operation::Write { ino, offset, len } => {
+                let stats = self.getattr(*ino).await?.ok_or(FsError::NotFound)?;
+                let end = checked_file_end(*offset, *len)?;
+                (*ino, end.max(stats.size as u64))
+            }
+            Operation::Truncate { ino, size } => (*ino, *size),
+            _ => return Ok(()),
+        };
+
+        // Writes land in the delta, so a base file is copied up whole first
+        let info = self.get_inode_info(ino).ok_or(FsError::NotFound)?;
+        let stored = match info.layer {
+            Layer::Delta => self.getattr(ino).await?.map_or(0, |s| s.size as u64),
+            Layer::Base => 0,
+        };
+        let free = FileSystem::statfs(&self.delta).await?.free_bytes;
+        if new_size.saturating_sub(stored) > free {
+            return Err(FsError::NoSpace.into());
+        }
+        Ok(())
     }
 
     async fn forget(&self, ino: i64, nlookup: u64) {
@@ -1336,11 +2292,126 @@ impl FileSystem for OverlayFS {
     }
 }
 
+/// A file handle for a file copied up block by block.
+///
+/// Blocks that are not yet resident in the delta are read from the base file.
+/// Writes first copy every touched block up, so partial-block writes merge
+/// with the base content.
+struct OverlayFile {
+    delta: AgentFS,
+    delta_ino: i64,
+    delta_file: BoxedFile,
+    base_file: BoxedFile,
+    state: Arc<tokio::sync::Mutex<PartialCopyUp>>,
+}
+
+impl OverlayFile {
+    /// Indices of the blocks overlapping `[offset, offset + len)`
+    fn block_range(block_size: u64, offset: u64, len: u64) -> std::ops::RangeInclusive<u64> {
+        let first = offset / block_size;
+        let last = (offset + len.max(1) - 1) / block_size;
+        first..=last
+    }
+}
+
+#[async_trait]
+impl File for OverlayFile {
+    async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let state = self.state.lock().await;
+        let mut data = self.delta_file.pread(offset, size).await?;
+        if data.is_empty() {
+            return Ok(data);
+        }
+
+        // Patch in base content for blocks that were never copied up
+        for block in Self::block_range(state.block_size, offset, data.len() as u64) {
+            if state.resident.contains(&block) {
+                continue;
+            }
+            let start = (block * state.block_size).max(offset);
+            let end = ((block + 1) * state.block_size)
+                .min(offset + data.len() as u64)
+                .min(state.base_size);
+            if start >= end {
+                continue;
+            }
+            let base = self.base_file.pread(start, end - start).await?;
+            let at = (start - offset) as usize;
+            data[at..at + base.len()].copy_from_slice(&base);
+        }
+        Ok(data)
+    }
+
+    async fn pwrite(&self, offset: u64, data: &[u8]) -> Result<()> {
+        checked_file_end(offset, data.len() as u64)?;
+        let mut state = self.state.lock().await;
+
+        // Copy up the touched blocks that still live in the base
+        let mut copied = Vec::new();
+        for block in Self::block_range(state.block_size, offset, data.len() as u64) {
+            if state.resident.contains(&block) {
+                continue;
+            }
+            let start = block * state.block_size;
+            if start < state.base_size {
+                let len = state.block_size.min(state.base_size - start);
+                let base = self.base_file.pread(start, len).await?;
+                self.delta_file.pwrite(start, &base).await?;
+            }
+            copied.push(block);
+        }
+
+        if !copied.is_empty() {
+            let conn = self.delta.get_connection().await?;
+            for block in &copied {
+                conn.execute(
+                    "INSERT OR IGNORE INTO fs_copyup_block (delta_ino, block_index) VALUES (?, ?)",
+                    (self.delta_ino, *block as i64),
+                )
+                .await?;
+            }
+            state.resident.extend(copied);
+        }
+
+        self.delta_file.pwrite(offset, data).await
+    }
+
+    async fn truncate(&self, size: u64) -> Result<()> {
+        let mut state = self.state.lock().await;
+        self.delta_file.truncate(size).await?;
+
+        // Bytes cut off must not come back from the base if the file regrows
+        if size < state.base_size {
+            let conn = self.delta.get_connection().await?;
+            conn.execute(
+                "UPDATE fs_copyup_partial SET base_size = ? WHERE delta_ino = ?",
+                (size as i64, self.delta_ino),
+            )
+            .await?;
+            state.base_size = size;
+        }
+        Ok(())
+    }
+
+    async fn fsync(&self) -> Result<()> {
+        self.delta_file.fsync().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.delta_file.flush().await
+    }
+
+    async fn fstat(&self) -> Result<Stats> {
+        self.delta_file.fstat().await
+    }
+}
+
 #[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
 mod tests {
     use super::*;
     use crate::filesystem::HostFS;
-    use crate::DEFAULT_FILE_MODE;
+    use crate::filesystem::{S_IFIFO, S_IFMT};
+    use crate::{DEFAULT_DIR_MODE, DEFAULT_FILE_MODE};
     use std::os::unix::fs::PermissionsExt;
     use tempfile::tempdir;
 
@@ -1350,7 +2421,8 @@ mod tests {
         std::fs::create_dir(base_dir.path().join("subdir"))?;
         std::fs::write(base_dir.path().join("subdir/nested.txt"), b"nested")?;
 
-        let base = Arc::new(HostFS::new(base_dir.path())?);
+        // Any attempt to write through to the base fails the test with EROFS
+        let base = Arc::new(HostFS::new_readonly(base_dir.path())?);
 
         let delta_dir = tempdir()?;
         let db_path = delta_dir.path().join("delta.db");
@@ -1373,6 +2445,46 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_overlay_writes_never_reach_readonly_base() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+        let base = base_dir.path();
+        let mode = std::fs::metadata(base.join("base.txt"))?
+            .permissions()
+            .mode();
+
+        let base_file = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let file = overlay.open(base_file.ino, libc::O_RDWR).await?;
+        file.pwrite(0, b"changed").await?;
+        file.truncate(4).await?;
+        overlay.chmod(base_file.ino, 0o700).await?;
+        overlay
+            .setxattr(base_file.ino, "user.test", b"value", 0)
+            .await?;
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        overlay
+            .rename(subdir.ino, "nested.txt", ROOT_INO, "moved.txt", 0)
+            .await?;
+        overlay.rmdir(ROOT_INO, "subdir").await?;
+
+        let file = overlay.open(base_file.ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 100).await?, b"chan");
+        assert!(overlay.lookup(ROOT_INO, "subdir").await?.is_none());
+
+        // The base directory is exactly as it was
+        assert_eq!(std::fs::read(base.join("base.txt"))?, b"base content");
+        assert_eq!(
+            std::fs::metadata(base.join("base.txt"))?
+                .permissions()
+                .mode(),
+            mode
+        );
+        assert_eq!(std::fs::read(base.join("subdir/nested.txt"))?, b"nested");
+        assert!(!base.join("moved.txt").exists());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_overlay_create_in_delta() -> Result<()> {
         let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
@@ -1406,6 +2518,28 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_overlay_access_whiteout_is_enoent() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        assert!(
+            overlay
+                .access(stats.ino, libc::R_OK, stats.uid, stats.gid)
+                .await?
+        );
+
+        // A stale inode for a whited-out path reports ENOENT
+        overlay.unlink(ROOT_INO, "base.txt").await?;
+        let err = overlay
+            .access(stats.ino, libc::F_OK, stats.uid, stats.gid)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::error::Error::Fs(FsError::NotFound)));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_overlay_copy_on_write() -> Result<()> {
         let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
@@ -1493,6 +2627,125 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_overlay_copy_on_write_setxattr() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+
+        // Lookup base file
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+
+        // setxattr should trigger copy-up
+        overlay
+            .setxattr(stats.ino, "user.origin", b"agent", 0)
+            .await?;
+
+        // Verify the attribute landed in the delta layer
+        let delta_stats = FileSystem::lookup(&overlay.delta, ROOT_INO, "base.txt")
+            .await?
+            .expect("file should be copied up to delta");
+        assert_eq!(
+            FileSystem::getxattr(&overlay.delta, delta_stats.ino, "user.origin").await?,
+            Some(b"agent".to_vec())
+        );
+
+        // Verify overlay returns the attribute through the same inode
+        assert_eq!(
+            overlay.getxattr(stats.ino, "user.origin").await?,
+            Some(b"agent".to_vec())
+        );
+        assert_eq!(overlay.listxattr(stats.ino).await?, vec!["user.origin"]);
+
+        // Removal stays in the delta
+        overlay.removexattr(stats.ino, "user.origin").await?;
+        assert!(overlay.getxattr(stats.ino, "user.origin").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_copy_on_write_utimens() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let base_mtime = std::fs::metadata(base_dir.path().join("base.txt"))?.modified()?;
+
+        // utimens should trigger copy-up
+        overlay
+            .utimens(
+                stats.ino,
+                TimeChange::Set(1_000_000_000, 500),
+                TimeChange::Set(1_000_000_001, 600),
+            )
+            .await?;
+
+        let stats_after = overlay.getattr(stats.ino).await?.unwrap();
+        assert_eq!(
+            (stats_after.atime, stats_after.atime_nsec),
+            (1_000_000_000, 500)
+        );
+        assert_eq!(
+            (stats_after.mtime, stats_after.mtime_nsec),
+            (1_000_000_001, 600)
+        );
+        assert_eq!(stats_after.ino, stats.ino);
+
+        // Base file timestamps are unchanged
+        let base_meta = std::fs::metadata(base_dir.path().join("base.txt"))?;
+        assert_eq!(base_meta.modified()?, base_mtime);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_copy_up_keeps_base_metadata() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+        let path = base_dir.path().join("base.txt");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))?;
+        let past = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_times(
+                std::fs::FileTimes::new()
+                    .set_accessed(past)
+                    .set_modified(past),
+            )?;
+        // A umask in the delta must not narrow the copied mode
+        overlay.delta.set_umask(Some(0o077)).await?;
+
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let before = overlay.getattr(stats.ino).await?.unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDWR).await?;
+        file.pwrite(0, b"B").await?;
+
+        let after = overlay.getattr(stats.ino).await?.unwrap();
+        assert_eq!(after.mode, before.mode);
+        assert_eq!((after.uid, after.gid), (before.uid, before.gid));
+        assert_eq!(
+            (after.atime, after.atime_nsec),
+            (before.atime, before.atime_nsec)
+        );
+        // Only the write itself moves the modification and change times
+        assert!(after.mtime > before.mtime);
+        assert!(after.ctime >= before.ctime);
+        assert_eq!(
+            overlay.delta.read_file("/base.txt").await?.unwrap(),
+            b"Base content"
+        );
+
+        // Copying up without changing the contents keeps the mtime too
+        let nested_dir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        let nested = overlay.lookup(nested_dir.ino, "nested.txt").await?.unwrap();
+        overlay.setxattr(nested.ino, "user.tag", b"x", 0).await?;
+        let copied = overlay.getattr(nested.ino).await?.unwrap();
+        assert_eq!(
+            (copied.mtime, copied.mtime_nsec),
+            (nested.mtime, nested.mtime_nsec)
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_overlay_copy_on_write_truncate() -> Result<()> {
         let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
@@ -1532,7 +2785,7 @@ mod tests {
 
         // Rename should trigger copy-up
         overlay
-            .rename(ROOT_INO, "base.txt", ROOT_INO, "renamed.txt")
+            .rename(ROOT_INO, "base.txt", ROOT_INO, "renamed.txt", 0)
             .await?;
 
         // Base file should still exist (we don't modify base)
@@ -1562,6 +2815,62 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_overlay_rename_onto_base_directory() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+
+        // The destination only exists in the base, but still isn't replaced
+        let result = overlay
+            .rename(ROOT_INO, "base.txt", ROOT_INO, "subdir", 0)
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::IsADirectory))
+        ));
+        overlay
+            .mkdir(ROOT_INO, "empty", DEFAULT_DIR_MODE, 0, 0)
+            .await?;
+        let result = overlay
+            .rename(ROOT_INO, "empty", ROOT_INO, "subdir", 0)
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::NotEmpty))
+        ));
+        let result = overlay
+            .rename(ROOT_INO, "subdir", subdir.ino, "inner", 0)
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::InvalidRename))
+        ));
+
+        assert!(overlay.lookup(ROOT_INO, "base.txt").await?.is_some());
+        assert!(overlay.lookup(subdir.ino, "nested.txt").await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_renamed_base_file_keeps_inode() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+        let base_file = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+
+        overlay
+            .rename(ROOT_INO, "base.txt", ROOT_INO, "moved.txt", 0)
+            .await?;
+
+        // The kernel keeps using the inode it looked up before the rename
+        overlay.chmod(base_file.ino, 0o600).await?;
+        assert!(overlay.lookup(ROOT_INO, "base.txt").await?.is_none());
+        let moved = overlay.lookup(ROOT_INO, "moved.txt").await?.unwrap();
+        assert_eq!(moved.ino, base_file.ino);
+        assert_eq!(moved.mode & 0o777, 0o600);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_overlay_copy_on_write_nested_file() -> Result<()> {
         let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
@@ -2338,6 +3647,29 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_overlay_link_base_file_shares_inode() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+
+        let src_stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let link_stats = overlay.link(src_stats.ino, ROOT_INO, "hard.txt").await?;
+        assert_eq!(link_stats.ino, src_stats.ino);
+        assert_eq!(link_stats.nlink, 2);
+
+        // Writes through the original name are visible through the new one
+        let file = overlay.open(src_stats.ino, libc::O_RDWR).await?;
+        file.pwrite(0, b"shared").await?;
+        let hard_stats = overlay.lookup(ROOT_INO, "hard.txt").await?.unwrap();
+        let file = overlay.open(hard_stats.ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 6).await?, b"shared");
+
+        // Base file is untouched
+        let base_content = std::fs::read(base_dir.path().join("base.txt"))?;
+        assert_eq!(base_content, b"base content");
+
+        Ok(())
+    }
+
     /// Test rmdir works for directories created in delta under base parent.
     #[tokio::test]
     async fn test_overlay_rmdir_delta_dir_in_base_parent() -> Result<()> {
@@ -2376,4 +3708,629 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_overlay_partial_copy_up_merges_blocks() -> Result<()> {
+        const SIZE: usize = 10 * 1024 * 1024;
+        const BLOCK: u64 = 64 * 1024;
+
+        let base_dir = tempdir()?;
+        let original: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+        std::fs::write(base_dir.path().join("large.bin"), &original)?;
+
+        let base = Arc::new(HostFS::new(base_dir.path())?);
+        let delta_dir = tempdir()?;
+        let db_path = delta_dir.path().join("delta.db");
+        let delta = AgentFS::new(db_path.to_str().unwrap()).await?;
+        let overlay = OverlayFS::new(base, delta).with_copyup_granularity(BLOCK);
+        overlay.init(base_dir.path().to_str().unwrap()).await?;
+
+        // Overwrite part of a block in the middle of the file
+        let stats = overlay.lookup(ROOT_INO, "large.bin").await?.unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDWR).await?;
+        let offset = (SIZE / 2) as u64 + 100;
+        let patch = vec![0xAA; 1000];
+        file.pwrite(offset, &patch).await?;
+
+        // A full read returns base content merged with the write
+        let mut expected = original.clone();
+        expected[offset as usize..offset as usize + patch.len()].copy_from_slice(&patch);
+        let content = file.pread(0, SIZE as u64).await?;
+        assert_eq!(content.len(), SIZE);
+        assert!(content == expected, "merged content mismatch");
+
+        // Same result through a fresh handle
+        let file = overlay.open(stats.ino, libc::O_RDONLY).await?;
+        let content = file.pread(0, SIZE as u64).await?;
+        assert!(content == expected, "merged content mismatch after reopen");
+
+        // Only the written block was copied up
+        let state = overlay
+            .partial
+            .read()
+            .unwrap()
+            .values()
+            .next()
+            .cloned()
+            .unwrap();
+        assert_eq!(state.lock().await.resident.len(), 1);
+
+        // Base file is unchanged
+        let base_content = std::fs::read(base_dir.path().join("large.bin"))?;
+        assert!(base_content == original, "base file should be unchanged");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_unlink_drops_partial_copy_up() -> Result<()> {
+        const BLOCK: u64 = 4096;
+
+        let base_dir = tempdir()?;
+        let original: Vec<u8> = (0..3 * BLOCK).map(|i| (i % 251) as u8).collect();
+        std::fs::write(base_dir.path().join("data.bin"), &original)?;
+
+        let base = Arc::new(HostFS::new(base_dir.path())?);
+        let delta_dir = tempdir()?;
+        let db_path = delta_dir.path().join("delta.db");
+        let delta = AgentFS::new(db_path.to_str().unwrap()).await?;
+        let overlay = OverlayFS::new(base, delta).with_copyup_granularity(BLOCK);
+        overlay.init(base_dir.path().to_str().unwrap()).await?;
+
+        let stats = overlay.lookup(ROOT_INO, "data.bin").await?.unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDWR).await?;
+        file.pwrite(BLOCK, b"patched").await?;
+        drop(file);
+        assert_eq!(overlay.partial.read().unwrap().len(), 1);
+
+        // Unlinking the file forgets its block map, in memory and stored
+        overlay.unlink(ROOT_INO, "data.bin").await?;
+        assert!(overlay.partial.read().unwrap().is_empty());
+        let conn = overlay.delta.get_connection().await?;
+        for table in ["fs_copyup_partial", "fs_copyup_block"] {
+            let mut rows = conn
+                .query(&format!("SELECT COUNT(*) FROM {table}"), ())
+                .await?;
+            let row = rows.next().await?.unwrap();
+            assert_eq!(row.get_value(0)?.as_integer().copied(), Some(0));
+        }
+        drop(conn);
+
+        // A file recreated at the same path reads only its own content
+        let (stats, file) = overlay
+            .create_file(ROOT_INO, "data.bin", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"fresh").await?;
+        let file = overlay.open(stats.ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 3 * BLOCK).await?, b"fresh");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_failed_partial_copy_up_keeps_base() -> Result<()> {
+        const BLOCK: u64 = 4096;
+
+        let base_dir = tempdir()?;
+        let original: Vec<u8> = (0..3 * BLOCK).map(|i| (i % 251) as u8).collect();
+        std::fs::write(base_dir.path().join("data.bin"), &original)?;
+
+        let base = Arc::new(HostFS::new(base_dir.path())?);
+        let delta_dir = tempdir()?;
+        let db_path = delta_dir.path().join("delta.db");
+        let delta = AgentFS::new(db_path.to_str().unwrap()).await?;
+        let overlay = OverlayFS::new(base, delta).with_copyup_granularity(BLOCK);
+        overlay.init(base_dir.path().to_str().unwrap()).await?;
+
+        // Make recording the partial copy-up fail
+        let conn = overlay.delta.get_connection().await?;
+        conn.execute("DROP TABLE fs_copyup_partial", ()).await?;
+        drop(conn);
+
+        let stats = overlay.lookup(ROOT_INO, "data.bin").await?.unwrap();
+        assert!(overlay.open(stats.ino, libc::O_RDWR).await.is_err());
+
+        // No delta file is left to hide the base content
+        assert!(FileSystem::lookup(&overlay.delta, ROOT_INO, "data.bin")
+            .await?
+            .is_none());
+        let conn = overlay.delta.get_connection().await?;
+        OverlayFS::init_schema(&conn, base_dir.path().to_str().unwrap()).await?;
+        drop(conn);
+        let stats = overlay.lookup(ROOT_INO, "data.bin").await?.unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 3 * BLOCK).await?, original);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_clear_delta_reveals_base() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+
+        let (_, file) = overlay
+            .create_file(ROOT_INO, "new.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"new content").await?;
+        overlay.unlink(ROOT_INO, "base.txt").await?;
+
+        overlay.clear_delta().await?;
+
+        assert!(overlay.lookup(ROOT_INO, "new.txt").await?.is_none());
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 64).await?, b"base content");
+
+        // The emptied delta can be written to again
+        overlay.mkdir(ROOT_INO, "fresh", 0o755, 0, 0).await?;
+        assert!(overlay.lookup(ROOT_INO, "fresh").await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_changeset() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+
+        // Modify a base file, add a new one and delete another
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDWR).await?;
+        file.pwrite(0, b"changed content!").await?;
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        let (_, file) = overlay
+            .create_file(subdir.ino, "added.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"added").await?;
+        overlay.unlink(subdir.ino, "nested.txt").await?;
+
+        let changes = overlay.changeset().await?;
+        let summary: Vec<(&str, ChangeKind)> =
+            changes.iter().map(|c| (c.path.as_str(), c.kind)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("/base.txt", ChangeKind::Modified),
+                ("/subdir", ChangeKind::Modified),
+                ("/subdir/added.txt", ChangeKind::Added),
+                ("/subdir/nested.txt", ChangeKind::Deleted),
+            ]
+        );
+
+        let modified = &changes[0];
+        assert_eq!(modified.base.as_ref().unwrap().size, 12);
+        assert_eq!(modified.delta.as_ref().unwrap().size, 16);
+        assert!(changes[2].base.is_none());
+        assert!(changes[3].delta.is_none());
+        assert_eq!(changes[3].base.as_ref().unwrap().size, 6);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_statfs_counts_merged_view() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+
+        // Root, base.txt, subdir and subdir/nested.txt
+        let stats = overlay.statfs().await?;
+        assert_eq!(stats.inodes, 4);
+        assert_eq!(stats.bytes_used, 12 + 6);
+
+        // Copying up base.txt replaces its base copy, added.txt and the
+        // copied-up subdir are new, and nested.txt is whited out
+        let base = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let file = overlay.open(base.ino, libc::O_RDWR).await?;
+        file.pwrite(0, b"changed content!").await?;
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        let (_, file) = overlay
+            .create_file(subdir.ino, "added.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"added").await?;
+        overlay.unlink(subdir.ino, "nested.txt").await?;
+
+        let stats = overlay.statfs().await?;
+        assert_eq!(stats.inodes, 4);
+        assert_eq!(stats.bytes_used, 16 + 5);
+        assert!(stats.total_inodes >= stats.inodes);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_statfs_keeps_base_usage_current() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+        std::fs::create_dir(base_dir.path().join("empty"))?;
+        std::fs::write(base_dir.path().join("subdir/other.txt"), b"other")?;
+
+        // The cached usage must match what a fresh walk of the base finds
+        async fn assert_current(overlay: &OverlayFS) -> Result<()> {
+            let cached = overlay.statfs().await?;
+            assert!(overlay.base_usage.lock().unwrap().is_some());
+            overlay.invalidate_base_usage();
+            let walked = overlay.statfs().await?;
+            assert_eq!(
+                (cached.inodes, cached.bytes_used),
+                (walked.inodes, walked.bytes_used)
+            );
+            Ok(())
+        }
+        assert_current(&overlay).await?;
+
+        let base = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let file = overlay.open(base.ino, libc::O_RDWR).await?;
+        file.pwrite(0, b"changed").await?;
+        assert_current(&overlay).await?;
+
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        overlay.unlink(subdir.ino, "nested.txt").await?;
+        assert_current(&overlay).await?;
+
+        overlay.rmdir(ROOT_INO, "empty").await?;
+        assert_current(&overlay).await?;
+
+        overlay
+            .rename(ROOT_INO, "subdir", ROOT_INO, "moved", 0)
+            .await?;
+        assert_current(&overlay).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_check_op_sees_merged_view() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        let base = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+
+        // Base entries conflict with new names and keep directories full
+        let mkdir = Operation::Mkdir {
+            parent_ino: ROOT_INO,
+            name: "base.txt".to_string(),
+        };
+        assert!(matches!(
+            overlay.check_op(&mkdir).await,
+            Err(crate::error::Error::Fs(FsError::AlreadyExists))
+        ));
+        let rmdir = Operation::Rmdir {
+            parent_ino: ROOT_INO,
+            name: "subdir".to_string(),
+        };
+        assert!(matches!(
+            overlay.check_op(&rmdir).await,
+            Err(crate::error::Error::Fs(FsError::NotEmpty))
+        ));
+        let rename = Operation::Rename {
+            oldparent_ino: ROOT_INO,
+            oldname: "subdir".to_string(),
+            newparent_ino: subdir.ino,
+            newname: "inner".to_string(),
+            flags: 0,
+        };
+        assert!(matches!(
+            overlay.check_op(&rename).await,
+            Err(crate::error::Error::Fs(FsError::InvalidRename))
+        ));
+
+        // A whited-out file no longer keeps its directory full
+        overlay.unlink(subdir.ino, "nested.txt").await?;
+        overlay.check_op(&rmdir).await?;
+
+        // Copying a base file up counts its whole size against the quota
+        overlay.delta().set_max_bytes(Some(10)).await?;
+        let write = Operation::Write {
+            ino: base.ino,
+            offset: 0,
+            len: 1,
+        };
+        assert!(matches!(
+            overlay.check_op(&write).await,
+            Err(crate::error::Error::Fs(FsError::NoSpace))
+        ));
+        assert!(FileSystem::lookup(overlay.delta(), ROOT_INO, "base.txt")
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_copy_up_preserves_fifo() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+        let fifo =
+            std::ffi::CString::new(base_dir.path().join("pipe").to_str().unwrap().as_bytes())
+                .unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        // chmod forces a copy-up, which must not try to read the FIFO
+        let stats = overlay.lookup(ROOT_INO, "pipe").await?.unwrap();
+        overlay.chmod(stats.ino, 0o600).await?;
+
+        let stats = overlay.getattr(stats.ino).await?.unwrap();
+        assert_eq!(stats.mode & S_IFMT, S_IFIFO);
+        assert_eq!(stats.mode & 0o7777, 0o600);
+
+        let delta = FileSystem::lookup(&overlay.delta, ROOT_INO, "pipe")
+            .await?
+            .unwrap();
+        assert_eq!(delta.mode & S_IFMT, S_IFIFO);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_recreated_dir_is_opaque() -> Result<()> {
+        let (overlay, base_dir, delta_dir) = create_test_overlay().await?;
+        std::fs::create_dir(base_dir.path().join("a"))?;
+        std::fs::write(base_dir.path().join("a/x"), b"x")?;
+        std::fs::write(base_dir.path().join("a/y"), b"y")?;
+
+        let a = overlay.lookup(ROOT_INO, "a").await?.unwrap();
+        overlay.unlink(a.ino, "x").await?;
+        overlay.unlink(a.ino, "y").await?;
+        overlay.rmdir(ROOT_INO, "a").await?;
+        let a = overlay.mkdir(ROOT_INO, "a", 0o755, 0, 0).await?;
+        overlay
+            .create_file(a.ino, "z", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+
+        // Entries that appear in the base directory later stay hidden too
+        std::fs::write(base_dir.path().join("a/w"), b"w")?;
+
+        assert_eq!(overlay.readdir(a.ino).await?.unwrap(), vec!["z"]);
+        let names: Vec<String> = overlay
+            .readdir_plus(a.ino)
+            .await?
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, vec!["z"]);
+        assert!(overlay.lookup(a.ino, "w").await?.is_none());
+
+        // The opaque mark is persisted
+        let db_path = delta_dir.path().join("delta.db");
+        let delta = AgentFS::new(db_path.to_str().unwrap()).await?;
+        let reopened = OverlayFS::new(Arc::new(HostFS::new(base_dir.path())?), delta);
+        reopened.load().await?;
+        let a = reopened.lookup(ROOT_INO, "a").await?.unwrap();
+        assert_eq!(reopened.readdir(a.ino).await?.unwrap(), vec!["z"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_mkdir_all_reuses_base_dirs() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+
+        let stats = overlay.mkdir_all("/subdir/x/y", 0o755, 0, 0).await?;
+        assert!(stats.is_directory());
+
+        // The base directory is kept, with its contents still visible
+        let subdir_after = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        assert_eq!(subdir_after.ino, subdir.ino);
+        let mut names = overlay.readdir(subdir.ino).await?.unwrap();
+        names.sort();
+        assert_eq!(names, vec!["nested.txt", "x"]);
+        let x = overlay.lookup(subdir.ino, "x").await?.unwrap();
+        assert_eq!(overlay.lookup(x.ino, "y").await?.unwrap().ino, stats.ino);
+
+        let err = overlay
+            .mkdir_all("/base.txt/z", 0o755, 0, 0)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Fs(FsError::NotADirectory)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_remove_all_whiteouts_base() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+        let subdir = overlay.lookup(ROOT_INO, "subdir").await?.unwrap();
+        overlay
+            .create_file(subdir.ino, "delta.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        overlay.mkdir_all("/subdir/a/b", 0o755, 0, 0).await?;
+
+        overlay.remove_all("/subdir").await?;
+
+        assert!(overlay.lookup(ROOT_INO, "subdir").await?.is_none());
+        assert!(overlay.lookup(ROOT_INO, "base.txt").await?.is_some());
+        // The base layer is untouched; the entries are hidden by whiteouts
+        assert!(base_dir.path().join("subdir/nested.txt").exists());
+        assert!(overlay.is_whiteout("/subdir"));
+
+        let err = overlay.remove_all("/").await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Fs(FsError::RootOperation)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_recreate_file_after_unlink() -> Result<()> {
+        let (overlay, base_dir, delta_dir) = create_test_overlay().await?;
+        overlay.unlink(ROOT_INO, "base.txt").await?;
+        assert!(overlay.lookup(ROOT_INO, "base.txt").await?.is_none());
+
+        let (_, file) = overlay
+            .create_file(ROOT_INO, "base.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"recreated").await?;
+        assert!(!overlay.is_whiteout("/base.txt"));
+
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        assert_eq!(stats.size, 9);
+        let file = overlay.open(stats.ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 100).await?, b"recreated");
+        let entries = overlay.readdir(ROOT_INO).await?.unwrap();
+        assert!(entries.contains(&"base.txt".to_string()));
+
+        // The whiteout row is gone from the database as well
+        let base = Arc::new(HostFS::new(base_dir.path())?);
+        let db_path = delta_dir.path().join("delta.db");
+        let delta = AgentFS::new(db_path.to_str().unwrap()).await?;
+        let overlay = OverlayFS::new(base, delta);
+        overlay.init(base_dir.path().to_str().unwrap()).await?;
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDONLY).await?;
+        assert_eq!(file.pread(0, 100).await?, b"recreated");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_recreate_dir_after_remove() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+        overlay.remove_all("/subdir").await?;
+
+        let stats = overlay.mkdir(ROOT_INO, "subdir", 0o755, 0, 0).await?;
+        assert!(!overlay.is_whiteout("/subdir"));
+        assert_eq!(
+            overlay.lookup(ROOT_INO, "subdir").await?.unwrap().ino,
+            stats.ino
+        );
+
+        // The old base children stay hidden, new ones are visible
+        assert!(overlay.readdir(stats.ino).await?.unwrap().is_empty());
+        assert!(overlay.lookup(stats.ino, "nested.txt").await?.is_none());
+        overlay
+            .create_file(stats.ino, "nested.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        let nested = overlay.lookup(stats.ino, "nested.txt").await?.unwrap();
+        assert_eq!(nested.size, 0);
+        assert_eq!(
+            overlay.readdir(stats.ino).await?.unwrap(),
+            vec!["nested.txt".to_string()]
+        );
+        assert!(overlay
+            .readdir(ROOT_INO)
+            .await?
+            .unwrap()
+            .contains(&"subdir".to_string()));
+
+        Ok(())
+    }
+
+    /// A whiteout left next to a recreated delta entry, as after a crash
+    /// between the create and the whiteout removal, only hides the base
+    #[tokio::test]
+    async fn test_overlay_stale_whiteout_does_not_hide_delta_entry() -> Result<()> {
+        let (overlay, _base_dir, _delta_dir) = create_test_overlay().await?;
+        overlay.unlink(ROOT_INO, "base.txt").await?;
+        let (_, file) = overlay
+            .create_file(ROOT_INO, "base.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await?;
+        file.pwrite(0, b"new").await?;
+        overlay.create_whiteout("/base.txt").await?;
+
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        assert_eq!(stats.size, 3);
+        assert!(overlay
+            .readdir(ROOT_INO)
+            .await?
+            .unwrap()
+            .contains(&"base.txt".to_string()));
+
+        // Unlinking it again still hides the base file
+        overlay.unlink(ROOT_INO, "base.txt").await?;
+        assert!(overlay.lookup(ROOT_INO, "base.txt").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_check_stale_whiteout() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+        overlay.unlink(ROOT_INO, "base.txt").await?;
+        assert_eq!(overlay.check().await?, vec![]);
+
+        // The whiteout outlives the base file it was hiding
+        std::fs::remove_file(base_dir.path().join("base.txt"))?;
+        let found = overlay.check().await?;
+        assert_eq!(
+            found,
+            vec![Inconsistency::StaleWhiteout {
+                path: "/base.txt".to_string()
+            }]
+        );
+
+        assert_eq!(overlay.repair(&found).await?, 1);
+        assert!(!overlay.is_whiteout("/base.txt"));
+        assert_eq!(overlay.check().await?, vec![]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_paths_normalize_like_base() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+        let base = HostFS::new_readonly(base_dir.path())?;
+        let layers: [&dyn FileSystem; 2] = [&overlay, &base];
+
+        // Both layers resolve every spelling to the same existing entry
+        for path in [
+            "/subdir/missing/../nested.txt",
+            "//subdir///nested.txt",
+            "/subdir/./nested.txt",
+        ] {
+            for fs in layers {
+                let result = fs.create(path, 0o600, libc::O_EXCL).await;
+                assert!(
+                    matches!(result, Err(crate::error::Error::Fs(FsError::AlreadyExists))),
+                    "{path}"
+                );
+            }
+        }
+        for path in ["/subdir/./", "//subdir//", "/missing/../subdir"] {
+            for fs in layers {
+                let stats = fs.mkdir_all(path, DEFAULT_DIR_MODE, 0, 0).await?;
+                assert!(stats.is_directory(), "{path}");
+                assert!(fs.lookup(ROOT_INO, "missing").await?.is_none(), "{path}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlay_create_sees_base() -> Result<()> {
+        let (overlay, base_dir, _delta_dir) = create_test_overlay().await?;
+
+        let result = overlay.create("/base.txt", 0o600, libc::O_EXCL).await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::AlreadyExists))
+        ));
+        let result = overlay
+            .create("/subdir/nested.txt", 0o600, libc::O_EXCL)
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::Fs(FsError::AlreadyExists))
+        ));
+
+        // Truncating a base file copies it up and leaves the base alone
+        overlay.create("/base.txt", 0o600, libc::O_TRUNC).await?;
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        assert_eq!(stats.size, 0);
+        assert_eq!(
+            std::fs::read(base_dir.path().join("base.txt"))?,
+            b"base content"
+        );
+
+        // A whited-out base file no longer counts as existing
+        overlay.unlink(ROOT_INO, "base.txt").await?;
+        overlay.create("/base.txt", 0o600, libc::O_EXCL).await?;
+        let stats = overlay.lookup(ROOT_INO, "base.txt").await?.unwrap();
+        assert_eq!(stats.mode & 0o7777, 0o600);
+        assert!(!overlay.is_whiteout("/base.txt"));
+
+        Ok(())
+    }
 }
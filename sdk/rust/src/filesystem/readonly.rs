@@ -0,0 +1,295 @@
+use crate::error::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use super::{
+    BoxedDirStream, BoxedFile, BoxedReadStream, DirEntry, File, FileSystem, FilesystemStats,
+    FsError, LockType, Operation, Stats, TimeChange,
+};
+
+/// Open flags that would let a handle modify the file.
+pub(super) const WRITE_FLAGS: i32 =
+    libc::O_WRONLY | libc::O_RDWR | libc::O_TRUNC | libc::O_APPEND | libc::O_CREAT;
+
+/// A read-only view of another filesystem.
+///
+/// Reads are forwarded to the wrapped filesystem unchanged. Every operation
+/// that would modify it fails with `FsError::ReadOnly` (`EROFS`) before
+/// reaching the inner filesystem, so the wrapper works the same over
+/// `AgentFS`, `OverlayFS` or `HostFS`.
+pub struct ReadOnlyFS {
+    inner: Arc<dyn FileSystem>,
+}
+
+impl ReadOnlyFS {
+    /// Wrap a filesystem so that it can only be read
+    pub fn new(inner: Arc<dyn FileSystem>) -> Self {
+        Self { inner }
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &Arc<dyn FileSystem> {
+        &self.inner
+    }
+}
+
+/// A file handle opened through a [`ReadOnlyFS`].
+struct ReadOnlyFile {
+    inner: BoxedFile,
+}
+
+#[async_trait]
+impl File for ReadOnlyFile {
+    async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        self.inner.pread(offset, size).await
+    }
+
+    async fn pwrite(&self, _offset: u64, _data: &[u8]) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn fsync(&self) -> Result<()> {
+        // Nothing can have been written through this handle
+        Ok(())
+    }
+
+    async fn fstat(&self) -> Result<Stats> {
+        self.inner.fstat().await
+    }
+}
+
+#[async_trait]
+impl FileSystem for ReadOnlyFS {
+    async fn lookup(&self, parent_ino: i64, name: &str) -> Result<Option<Stats>> {
+        self.inner.lookup(parent_ino, name).await
+    }
+
+    async fn getattr(&self, ino: i64) -> Result<Option<Stats>> {
+        self.inner.getattr(ino).await
+    }
+
+    async fn readlink(&self, ino: i64) -> Result<Option<String>> {
+        self.inner.readlink(ino).await
+    }
+
+    async fn readdir(&self, ino: i64) -> Result<Option<Vec<String>>> {
+        self.inner.readdir(ino).await
+    }
+
+    async fn readdir_plus(&self, ino: i64) -> Result<Option<Vec<DirEntry>>> {
+        self.inner.readdir_plus(ino).await
+    }
+
+    async fn readdir_stream(&self, ino: i64) -> Result<Option<BoxedDirStream>> {
+        self.inner.readdir_stream(ino).await
+    }
+
+    async fn read_stream(&self, ino: i64) -> Result<Option<BoxedReadStream>> {
+        self.inner.read_stream(ino).await
+    }
+
+    async fn chmod(&self, _ino: i64, _mode: u32) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn chown(&self, _ino: i64, _uid: Option<u32>, _gid: Option<u32>) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn utimens(&self, _ino: i64, _atime: TimeChange, _mtime: TimeChange) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn access(&self, ino: i64, mask: i32, uid: u32, gid: u32) -> Result<bool> {
+        // access(2) reports EROFS for write checks on a read-only filesystem
+        if mask & libc::W_OK != 0 {
+            self.inner.getattr(ino).await?.ok_or(FsError::NotFound)?;
+            return Err(FsError::ReadOnly.into());
+        }
+        self.inner.access(ino, mask, uid, gid).await
+    }
+
+    async fn getxattr(&self, ino: i64, name: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.getxattr(ino, name).await
+    }
+
+    async fn setxattr(&self, _ino: i64, _name: &str, _value: &[u8], _flags: i32) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn listxattr(&self, ino: i64) -> Result<Vec<String>> {
+        self.inner.listxattr(ino).await
+    }
+
+    async fn removexattr(&self, _ino: i64, _name: &str) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn open(&self, ino: i64, flags: i32) -> Result<BoxedFile> {
+        if flags & WRITE_FLAGS != 0 {
+            return Err(FsError::ReadOnly.into());
+        }
+        let inner = self.inner.open(ino, flags).await?;
+        Ok(Arc::new(ReadOnlyFile { inner }))
+    }
+
+    async fn mkdir(
+        &self,
+        _parent_ino: i64,
+        _name: &str,
+        _mode: u32,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<Stats> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn create_file(
+        &self,
+        _parent_ino: i64,
+        _name: &str,
+        _mode: u32,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<(Stats, BoxedFile)> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn mknod(
+        &self,
+        _parent_ino: i64,
+        _name: &str,
+        _mode: u32,
+        _rdev: u64,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<Stats> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn symlink(
+        &self,
+        _parent_ino: i64,
+        _name: &str,
+        _target: &str,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<Stats> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn unlink(&self, _parent_ino: i64, _name: &str) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn rmdir(&self, _parent_ino: i64, _name: &str) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn link(&self, _ino: i64, _newparent_ino: i64, _newname: &str) -> Result<Stats> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn rename(
+        &self,
+        _oldparent_ino: i64,
+        _oldname: &str,
+        _newparent_ino: i64,
+        _newname: &str,
+        _flags: u32,
+    ) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn fallocate(&self, _ino: i64, _offset: u64, _len: u64, _mode: i32) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn reflink(&self, _src: &str, _dst: &str) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    // Advisory locks do not modify the filesystem
+    async fn lock(&self, ino: i64, lock_type: LockType, owner: u64) -> Result<()> {
+        self.inner.lock(ino, lock_type, owner).await
+    }
+
+    async fn unlock(&self, ino: i64, owner: u64) -> Result<()> {
+        self.inner.unlock(ino, owner).await
+    }
+
+    async fn statfs(&self) -> Result<FilesystemStats> {
+        self.inner.statfs().await
+    }
+
+    async fn check_op(&self, _op: &Operation) -> Result<()> {
+        // Every operation check_op describes modifies the filesystem
+        Err(FsError::ReadOnly.into())
+    }
+
+    async fn forget(&self, ino: i64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::AgentFS;
+    use tempfile::tempdir;
+
+    fn is_read_only<T>(result: Result<T>) -> bool {
+        matches!(result, Err(crate::error::Error::Fs(FsError::ReadOnly)))
+    }
+
+    #[tokio::test]
+    async fn test_readonly_forwards_reads_and_rejects_writes() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let fs = AgentFS::new(db_path.to_str().unwrap()).await.unwrap();
+        fs.mkdir("/dir", 0, 0).await.unwrap();
+        fs.pwrite("/dir/file.txt", 0, b"hello").await.unwrap();
+
+        let ro = ReadOnlyFS::new(Arc::new(fs));
+        let dir_stats = ro.lookup(1, "dir").await.unwrap().unwrap();
+        let file_stats = ro.lookup(dir_stats.ino, "file.txt").await.unwrap().unwrap();
+
+        let file = ro.open(file_stats.ino, libc::O_RDONLY).await.unwrap();
+        assert_eq!(file.pread(0, 5).await.unwrap(), b"hello");
+        assert!(is_read_only(file.pwrite(0, b"x").await));
+        assert!(is_read_only(file.truncate(0).await));
+        file.fsync().await.unwrap();
+
+        assert!(is_read_only(ro.open(file_stats.ino, libc::O_RDWR).await));
+        assert!(is_read_only(ro.mkdir(1, "new", 0o755, 0, 0).await));
+        assert!(is_read_only(ro.unlink(dir_stats.ino, "file.txt").await));
+        assert!(is_read_only(
+            ro.rename(dir_stats.ino, "file.txt", 1, "moved", 0).await
+        ));
+        assert!(is_read_only(ro.symlink(1, "link", "/dir", 0, 0).await));
+        assert!(is_read_only(ro.link(file_stats.ino, 1, "hard").await));
+        assert!(is_read_only(ro.chmod(file_stats.ino, 0o600).await));
+        assert!(is_read_only(ro.atomic_write("/dir/file.txt", b"x").await));
+        assert!(is_read_only(ro.reflink("/dir/file.txt", "/clone").await));
+        assert!(is_read_only(
+            ro.access(file_stats.ino, libc::W_OK, 0, 0).await
+        ));
+        assert!(ro.access(file_stats.ino, libc::R_OK, 0, 0).await.unwrap());
+        assert!(is_read_only(
+            ro.check_op(&Operation::Mkdir {
+                parent_ino: 1,
+                name: "new".to_string(),
+            })
+            .await
+        ));
+
+        // Nothing reached the inner filesystem
+        let names = ro.readdir(dir_stats.ino).await.unwrap().unwrap();
+        assert_eq!(names, vec!["file.txt".to_string()]);
+        assert!(ro.lookup(1, "new").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,1343 @@
+//! A filesystem served over the network.
+//!
+//! [`serve`] exposes any [`FileSystem`] on a TCP listener, and [`RemoteFS`]
+//! implements [`FileSystem`] by forwarding each call to such a server.
+//!
+//! Every request and reply is one frame: the lengths of a JSON header and of
+//! a binary payload, each as a big-endian `u32`, followed by the header and
+//! the payload. File contents and extended attribute values travel in the
+//! payload so they are not inflated by JSON encoding.
+//!
+//! The protocol has no authentication or encryption; only serve it on
+//! loopback or on a network every peer is trusted on.
+
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+
+use super::{
+    atomic_write_via_rename, BoxedFile, DirEntry, File, FileSystem, FileType, FilesystemStats,
+    FsError, LockType, Operation, Stats, TimeChange,
+};
+
+/// Largest header or payload a peer may send, in bytes
+const MAX_FRAME_LEN: u32 = 64 << 20;
+
+/// Largest read or write sent in one request; bigger ones are split
+const MAX_IO_SIZE: u64 = 1 << 20;
+
+/// Open flags that must not be applied again when a file is reopened on a
+/// new connection
+const REOPEN_IGNORED_FLAGS: i32 = libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC;
+
+/// A call forwarded to the server.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Lookup {
+        parent_ino: i64,
+        name: String,
+    },
+    Getattr {
+        ino: i64,
+    },
+    Readlink {
+        ino: i64,
+    },
+    Readdir {
+        ino: i64,
+    },
+    ReaddirPlus {
+        ino: i64,
+    },
+    ReaddirTypes {
+        ino: i64,
+    },
+    Chmod {
+        ino: i64,
+        mode: u32,
+    },
+    Chown {
+        ino: i64,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    },
+    Utimens {
+        ino: i64,
+        atime: TimeChange,
+        mtime: TimeChange,
+    },
+    Access {
+        ino: i64,
+        mask: i32,
+        uid: u32,
+        gid: u32,
+    },
+    /// The value is returned in the payload
+    Getxattr {
+        ino: i64,
+        name: String,
+    },
+    /// The value is sent in the payload
+    Setxattr {
+        ino: i64,
+        name: String,
+        flags: i32,
+    },
+    Listxattr {
+        ino: i64,
+    },
+    Removexattr {
+        ino: i64,
+        name: String,
+    },
+    Open {
+        ino: i64,
+        flags: i32,
+    },
+    Mkdir {
+        parent_ino: i64,
+        name: String,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    },
+    MkdirAll {
+        path: String,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    },
+    CreateFile {
+        parent_ino: i64,
+        name: String,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    },
+    Mknod {
+        parent_ino: i64,
+        name: String,
+        mode: u32,
+        rdev: u64,
+        uid: u32,
+        gid: u32,
+    },
+    Symlink {
+        parent_ino: i64,
+        name: String,
+        target: String,
+        uid: u32,
+        gid: u32,
+    },
+    Unlink {
+        parent_ino: i64,
+        name: String,
+    },
+    Rmdir {
+        parent_ino: i64,
+        name: String,
+    },
+    RemoveAll {
+        path: String,
+    },
+    Link {
+        ino: i64,
+        newparent_ino: i64,
+        newname: String,
+    },
+    Rename {
+        oldparent_ino: i64,
+        oldname: String,
+        newparent_ino: i64,
+        newname: String,
+        flags: u32,
+    },
+    Fallocate {
+        ino: i64,
+        offset: u64,
+        len: u64,
+        mode: i32,
+    },
+    CopyRange {
+        src_ino: i64,
+        src_offset: u64,
+        dst_ino: i64,
+        dst_offset: u64,
+        len: u64,
+    },
+    /// The data is sent in the payload
+    Append {
+        path: String,
+    },
+    Create {
+        path: String,
+        mode: u32,
+        flags: i32,
+    },
+    /// The data is sent in the payload
+    AtomicWrite {
+        path: String,
+    },
+    Reflink {
+        src: String,
+        dst: String,
+    },
+    Lock {
+        ino: i64,
+        lock_type: LockType,
+        owner: u64,
+    },
+    Unlock {
+        ino: i64,
+        owner: u64,
+    },
+    SyncAll,
+    FsyncDir {
+        ino: i64,
+    },
+    Statfs,
+    CheckOp {
+        operation: Operation,
+    },
+    Forget {
+        ino: i64,
+        nlookup: u64,
+    },
+    /// The data is returned in the payload
+    Read {
+        handle: u64,
+        offset: u64,
+        size: u64,
+    },
+    /// The data is sent in the payload
+    Write {
+        handle: u64,
+        offset: u64,
+    },
+    Truncate {
+        handle: u64,
+        size: u64,
+    },
+    Fsync {
+        handle: u64,
+    },
+    Flush {
+        handle: u64,
+    },
+    Fstat {
+        handle: u64,
+    },
+    /// Close handles whose files were dropped by the client
+    Release {
+        handles: Vec<u64>,
+    },
+}
+
+/// An error returned by the server.
+#[derive(Debug, Serialize, Deserialize)]
+enum WireError {
+    /// A filesystem error, sent as itself so the client maps it to its own
+    /// platform's errno
+    Fs(FsError),
+    /// Any other error, as the errno the server gave it
+    Other { errno: i32, message: String },
+}
+
+impl From<Error> for WireError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Fs(e) => WireError::Fs(e),
+            e => WireError::Other {
+                errno: e.to_errno(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<WireError> for Error {
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::Fs(e) => Error::Fs(e),
+            WireError::Other { errno, message } => {
+                tracing::debug!("remote filesystem error: {}", message);
+                Error::Io(io::Error::from_raw_os_error(errno))
+            }
+        }
+    }
+}
+
+/// The header of a reply.
+type Reply = std::result::Result<serde_json::Value, WireError>;
+
+/// Read a frame, or `None` if the peer closed the connection between frames.
+async fn read_frame<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let header_len = match stream.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let payload_len = stream.read_u32().await?;
+    if header_len > MAX_FRAME_LEN || payload_len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame exceeds the maximum length",
+        ));
+    }
+    let mut header = vec![0; header_len as usize];
+    stream.read_exact(&mut header).await?;
+    let mut payload = vec![0; payload_len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok(Some((header, payload)))
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    header: &[u8],
+    payload: &[u8],
+) -> io::Result<()> {
+    stream.write_u32(header.len() as u32).await?;
+    stream.write_u32(payload.len() as u32).await?;
+    stream.write_all(header).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await
+}
+
+/// Serve `fs` to every client that connects to `listener`.
+///
+/// Each connection has its own table of open files, which are closed when
+/// the client disconnects. Runs until accepting a connection fails;
+/// dropping the returned future closes every connection.
+pub async fn serve(listener: TcpListener, fs: Arc<dyn FileSystem>) -> Result<()> {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                connections.spawn(serve_connection(stream, fs.clone()));
+            }
+            Some(finished) = connections.join_next() => {
+                if let Ok(Err(e)) = finished {
+                    tracing::debug!("remote filesystem connection failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn serve_connection(stream: TcpStream, fs: Arc<dyn FileSystem>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut stream = BufStream::new(stream);
+    let mut session = Session {
+        fs,
+        files: HashMap::new(),
+        next_handle: 1,
+    };
+    while let Some((header, payload)) = read_frame(&mut stream).await? {
+        let (reply, data): (Reply, Vec<u8>) = match serde_json::from_slice(&header) {
+            Ok(request) => match session.handle(request, payload).await {
+                Ok((value, data)) => (Ok(value), data),
+                Err(e) => (Err(e.into()), Vec::new()),
+            },
+            Err(e) => (
+                Err(WireError::Other {
+                    errno: libc::EINVAL,
+                    message: format!("malformed request: {}", e),
+                }),
+                Vec::new(),
+            ),
+        };
+        write_frame(&mut stream, &serde_json::to_vec(&reply)?, &data).await?;
+    }
+    Ok(())
+}
+
+/// The server side of one connection.
+struct Session {
+    fs: Arc<dyn FileSystem>,
+    /// Files opened by the client, by handle
+    files: HashMap<u64, BoxedFile>,
+    next_handle: u64,
+}
+
+/// A reply header without a payload
+fn reply<T: Serialize>(value: T) -> Result<(serde_json::Value, Vec<u8>)> {
+    Ok((serde_json::to_value(value)?, Vec::new()))
+}
+
+impl Session {
+    fn insert(&mut self, file: BoxedFile) -> u64 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.files.insert(handle, file);
+        handle
+    }
+
+    fn file(&self, handle: u64) -> Result<&BoxedFile> {
+        self.files
+            .get(&handle)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EBADF).into())
+    }
+
+    async fn handle(
+        &mut self,
+        request: Request,
+        payload: Vec<u8>,
+    ) -> Result<(serde_json::Value, Vec<u8>)> {
+        let fs = &*self.fs;
+        match request {
+            Request::Lookup { parent_ino, name } => reply(fs.lookup(parent_ino, &name).await?),
+            Request::Getattr { ino } => reply(fs.getattr(ino).await?),
+            Request::Readlink { ino } => reply(fs.readlink(ino).await?),
+            Request::Readdir { ino } => reply(fs.readdir(ino).await?),
+            Request::ReaddirPlus { ino } => reply(fs.readdir_plus(ino).await?),
+            Request::ReaddirTypes { ino } => reply(fs.readdir_types(ino).await?),
+            Request::Chmod { ino, mode } => reply(fs.chmod(ino, mode).await?),
+            Request::Chown { ino, uid, gid } => reply(fs.chown(ino, uid, gid).await?),
+            Request::Utimens { ino, atime, mtime } => reply(fs.utimens(ino, atime, mtime).await?),
+            Request::Access {
+                ino,
+                mask,
+                uid,
+                gid,
+            } => reply(fs.access(ino, mask, uid, gid).await?),
+            Request::Getxattr { ino, name } => match fs.getxattr(ino, &name).await? {
+                Some(value) => Ok((serde_json::Value::Bool(true), value)),
+                None => reply(false),
+            },
+            Request::Setxattr { ino, name, flags } => {
+                reply(fs.setxattr(ino, &name, &payload, flags).await?)
+            }
+            Request::Listxattr { ino } => reply(fs.listxattr(ino).await?),
+            Request::Removexattr { ino, name } => reply(fs.removexattr(ino, &name).await?),
+            Request::Open { ino, flags } => {
+                let file = fs.open(ino, flags).await?;
+                reply(self.insert(file))
+            }
+            Request::Mkdir {
+                parent_ino,
+                name,
+                mode,
+                uid,
+                gid,
+            } => reply(fs.mkdir(parent_ino, &name, mode, uid, gid).await?),
+            Request::MkdirAll {
+                path,
+                mode,
+                uid,
+                gid,
+            } => reply(fs.mkdir_all(&path, mode, uid, gid).await?),
+            Request::CreateFile {
+                parent_ino,
+                name,
+                mode,
+                uid,
+                gid,
+            } => {
+                let (stats, file) = fs.create_file(parent_ino, &name, mode, uid, gid).await?;
+                reply((stats, self.insert(file)))
+            }
+            Request::Mknod {
+                parent_ino,
+                name,
+                mode,
+                rdev,
+                uid,
+                gid,
+            } => reply(fs.mknod(parent_ino, &name, mode, rdev, uid, gid).await?),
+            Request::Symlink {
+                parent_ino,
+                name,
+                target,
+                uid,
+                gid,
+            } => reply(fs.symlink(parent_ino, &name, &target, uid, gid).await?),
+            Request::Unlink { parent_ino, name } => reply(fs.unlink(parent_ino, &name).await?),
+            Request::Rmdir { parent_ino, name } => reply(fs.rmdir(parent_ino, &name).await?),
+            Request::RemoveAll { path } => reply(fs.remove_all(&path).await?),
+            Request::Link {
+                ino,
+                newparent_ino,
+                newname,
+            } => reply(fs.link(ino, newparent_ino, &newname).await?),
+            Request::Rename {
+                oldparent_ino,
+                oldname,
+                newparent_ino,
+                newname,
+                flags,
+            } => reply(
+                fs.rename(oldparent_ino, &oldname, newparent_ino, &newname, flags)
+                    .await?,
+            ),
+            Request::Fallocate {
+                ino,
+                offset,
+                len,
+                mode,
+            } => reply(fs.fallocate(ino, offset, len, mode).await?),
+            Request::CopyRange {
+                src_ino,
+                src_offset,
+                dst_ino,
+                dst_offset,
+                len,
+            } => reply(
+                fs.copy_range(src_ino, src_offset, dst_ino, dst_offset, len)
+                    .await?,
+            ),
+            Request::Append { path } => reply(fs.append(&path, &payload).await?),
+            Request::Create { path, mode, flags } => reply(fs.create(&path, mode, flags).await?),
+            Request::AtomicWrite { path } => reply(fs.atomic_write(&path, &payload).await?),
+            Request::Reflink { src, dst } => reply(fs.reflink(&src, &dst).await?),
+            Request::Lock {
+                ino,
+                lock_type,
+                owner,
+            } => reply(fs.lock(ino, lock_type, owner).await?),
+            Request::Unlock { ino, owner } => reply(fs.unlock(ino, owner).await?),
+            Request::SyncAll => reply(fs.sync_all().await?),
+            Request::FsyncDir { ino } => reply(fs.fsync_dir(ino).await?),
+            Request::Statfs => reply(fs.statfs().await?),
+            Request::CheckOp { operation } => reply(fs.check_op(&operation).await?),
+            Request::Forget { ino, nlookup } => {
+                fs.forget(ino, nlookup).await;
+                reply(())
+            }
+            Request::Read {
+                handle,
+                offset,
+                size,
+            } => {
+                let data = self
+                    .file(handle)?
+                    .pread(offset, size.min(MAX_IO_SIZE))
+                    .await?;
+                Ok((serde_json::Value::Null, data))
+            }
+            Request::Write { handle, offset } => {
+                reply(self.file(handle)?.pwrite(offset, &payload).await?)
+            }
+            Request::Truncate { handle, size } => reply(self.file(handle)?.truncate(size).await?),
+            Request::Fsync { handle } => reply(self.file(handle)?.fsync().await?),
+            Request::Flush { handle } => reply(self.file(handle)?.flush().await?),
+            Request::Fstat { handle } => reply(self.file(handle)?.fstat().await?),
+            Request::Release { handles } => {
+                for handle in handles {
+                    self.files.remove(&handle);
+                }
+                reply(())
+            }
+        }
+    }
+}
+
+/// The connection shared by a [`RemoteFS`] and the files opened through it.
+struct Client {
+    addr: String,
+    state: tokio::sync::Mutex<ClientState>,
+    /// Handles of files dropped since the last request, with the generation
+    /// of the connection that opened them
+    released: std::sync::Mutex<Vec<(u64, u64)>>,
+}
+
+struct ClientState {
+    conn: Option<BufStream<TcpStream>>,
+    /// Number of connections made so far. Handles are only valid on the
+    /// connection that opened them.
+    generation: u64,
+}
+
+impl Client {
+    /// The error returned when the server cannot be reached, which callers
+    /// see as `EIO`
+    fn lost(&self, e: io::Error) -> Error {
+        Error::Io(io::Error::other(format!(
+            "connection to {} failed: {}",
+            self.addr, e
+        )))
+    }
+
+    async fn ensure_connected(&self, state: &mut ClientState) -> Result<()> {
+        if state.conn.is_none() {
+            let stream = TcpStream::connect(&self.addr)
+                .await
+                .map_err(|e| self.lost(e))?;
+            stream.set_nodelay(true).map_err(|e| self.lost(e))?;
+            state.conn = Some(BufStream::new(stream));
+            state.generation += 1;
+        }
+        Ok(())
+    }
+
+    /// Send `request` and return the reply's header and payload.
+    ///
+    /// Any I/O error drops the connection, so the next request reconnects.
+    /// The connection is only put back once the reply has been read, so a
+    /// call cancelled midway drops it too, rather than leaving its reply to
+    /// be read by the next request.
+    async fn exchange(
+        &self,
+        state: &mut ClientState,
+        request: &Request,
+        payload: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        if payload.len() > MAX_FRAME_LEN as usize {
+            return Err(FsError::FileTooLarge.into());
+        }
+        self.ensure_connected(state).await?;
+        let generation = state.generation;
+        let released: Vec<u64> = self
+            .released
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter(|&(g, _)| g == generation)
+            .map(|(_, handle)| handle)
+            .collect();
+        let mut conn = state.conn.take().unwrap();
+
+        let result = async {
+            if !released.is_empty() {
+                let release = Request::Release { handles: released };
+                roundtrip(&mut conn, &release, &[]).await?;
+            }
+            roundtrip(&mut conn, request, payload).await
+        }
+        .await;
+        match result {
+            Ok(reply) => {
+                state.conn = Some(conn);
+                Ok(reply)
+            }
+            Err(e) => Err(self.lost(e)),
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        request: Request,
+        payload: &[u8],
+    ) -> Result<(T, Vec<u8>)> {
+        let mut state = self.state.lock().await;
+        let (header, data) = self.exchange(&mut state, &request, payload).await?;
+        Ok((decode(&header)?, data))
+    }
+
+    /// Open `ino` and return the handle with the generation it is valid for
+    async fn open(&self, state: &mut ClientState, ino: i64, flags: i32) -> Result<(u64, u64)> {
+        let (header, _) = self
+            .exchange(state, &Request::Open { ino, flags }, &[])
+            .await?;
+        Ok((state.generation, decode(&header)?))
+    }
+
+    /// Send the request `op` builds from the handle of `file`, reopening the
+    /// file first if the connection that opened it was lost.
+    async fn call_file<T: DeserializeOwned>(
+        &self,
+        file: &RemoteFile,
+        op: impl FnOnce(u64) -> Request,
+        payload: &[u8],
+    ) -> Result<(T, Vec<u8>)> {
+        let mut state = self.state.lock().await;
+        self.ensure_connected(&mut state).await?;
+        let (generation, mut handle) = *file.handle.lock().unwrap();
+        if generation != state.generation {
+            let flags = file.flags & !REOPEN_IGNORED_FLAGS;
+            let reopened = self.open(&mut state, file.ino, flags).await?;
+            *file.handle.lock().unwrap() = reopened;
+            handle = reopened.1;
+        }
+        let (header, data) = self.exchange(&mut state, &op(handle), payload).await?;
+        Ok((decode(&header)?, data))
+    }
+}
+
+async fn roundtrip(
+    conn: &mut BufStream<TcpStream>,
+    request: &Request,
+    payload: &[u8],
+) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    write_frame(conn, &serde_json::to_vec(request)?, payload).await?;
+    read_frame(conn)
+        .await?
+        .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+fn decode<T: DeserializeOwned>(header: &[u8]) -> Result<T> {
+    match serde_json::from_slice::<Reply>(header)? {
+        Ok(value) => Ok(serde_json::from_value(value)?),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A filesystem on another host, reached through a server started with
+/// [`serve`].
+///
+/// Calls are sent one at a time over a single connection. If the connection
+/// drops, the call in progress fails with `EIO` and the next call
+/// reconnects; files opened before the drop are opened again on first use,
+/// without `O_CREAT`, `O_EXCL` or `O_TRUNC`.
+pub struct RemoteFS {
+    client: Arc<Client>,
+}
+
+impl RemoteFS {
+    /// Connect to the server listening on `addr`, such as `"10.0.0.2:7000"`
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let client = Client {
+            addr: addr.to_string(),
+            state: tokio::sync::Mutex::new(ClientState {
+                conn: None,
+                generation: 0,
+            }),
+            released: std::sync::Mutex::new(Vec::new()),
+        };
+        client
+            .ensure_connected(&mut *client.state.lock().await)
+            .await?;
+        Ok(Self {
+            client: Arc::new(client),
+        })
+    }
+
+    /// Get the address of the server
+    pub fn addr(&self) -> &str {
+        &self.client.addr
+    }
+
+    async fn call<T: DeserializeOwned>(&self, request: Request) -> Result<T> {
+        Ok(self.client.call(request, &[]).await?.0)
+    }
+}
+
+/// A file opened through a [`RemoteFS`].
+struct RemoteFile {
+    client: Arc<Client>,
+    ino: i64,
+    flags: i32,
+    /// Generation of the connection the handle is valid for, and the handle
+    handle: std::sync::Mutex<(u64, u64)>,
+}
+
+impl RemoteFile {
+    fn open(client: Arc<Client>, ino: i64, flags: i32, handle: (u64, u64)) -> BoxedFile {
+        Arc::new(Self {
+            client,
+            ino,
+            flags,
+            handle: std::sync::Mutex::new(handle),
+        })
+    }
+}
+
+impl Drop for RemoteFile {
+    fn drop(&mut self) {
+        let handle = *self.handle.lock().unwrap();
+        self.client.released.lock().unwrap().push(handle);
+    }
+}
+
+#[async_trait]
+impl File for RemoteFile {
+    async fn pread(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while (data.len() as u64) < size {
+            let want = (size - data.len() as u64).min(MAX_IO_SIZE);
+            let read_offset = offset + data.len() as u64;
+            let ((), chunk) = self
+                .client
+                .call_file::<()>(
+                    self,
+                    |handle| Request::Read {
+                        handle,
+                        offset: read_offset,
+                        size: want,
+                    },
+                    &[],
+                )
+                .await?;
+            let short = (chunk.len() as u64) < want;
+            data.extend_from_slice(&chunk);
+            if short {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    async fn pwrite(&self, offset: u64, data: &[u8]) -> Result<()> {
+        // An empty write changes nothing, so it sends no request
+        let mut written = 0;
+        for piece in data.chunks(MAX_IO_SIZE as usize) {
+            let write_offset = offset + written;
+            self.client
+                .call_file::<()>(
+                    self,
+                    |handle| Request::Write {
+                        handle,
+                        offset: write_offset,
+                    },
+                    piece,
+                )
+                .await?;
+            written += piece.len() as u64;
+        }
+        Ok(())
+    }
+
+    async fn truncate(&self, size: u64) -> Result<()> {
+        self.client
+            .call_file::<()>(self, |handle| Request::Truncate { handle, size }, &[])
+            .await
+            .map(|((), _)| ())
+    }
+
+    async fn fsync(&self) -> Result<()> {
+        self.client
+            .call_file::<()>(self, |handle| Request::Fsync { handle }, &[])
+            .await
+            .map(|((), _)| ())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.client
+            .call_file::<()>(self, |handle| Request::Flush { handle }, &[])
+            .await
+            .map(|((), _)| ())
+    }
+
+    async fn fstat(&self) -> Result<Stats> {
+        self.client
+            .call_file::<Stats>(self, |handle| Request::Fstat { handle }, &[])
+            .await
+            .map(|(stats, _)| stats)
+    }
+}
+
+#[async_trait]
+impl FileSystem for RemoteFS {
+    async fn lookup(&self, parent_ino: i64, name: &str) -> Result<Option<Stats>> {
+        self.call(Request::Lookup {
+            parent_ino,
+            name: name.to_string(),
+        })
+        .await
+    }
+
+    async fn getattr(&self, ino: i64) -> Result<Option<Stats>> {
+        self.call(Request::Getattr { ino }).await
+    }
+
+    async fn readlink(&self, ino: i64) -> Result<Option<String>> {
+        self.call(Request::Readlink { ino }).await
+    }
+
+    async fn readdir(&self, ino: i64) -> Result<Option<Vec<String>>> {
+        self.call(Request::Readdir { ino }).await
+    }
+
+    async fn readdir_plus(&self, ino: i64) -> Result<Option<Vec<DirEntry>>> {
+        self.call(Request::ReaddirPlus { ino }).await
+    }
+
+    async fn readdir_types(&self, ino: i64) -> Result<Option<Vec<(String, FileType)>>> {
+        self.call(Request::ReaddirTypes { ino }).await
+    }
+
+    async fn chmod(&self, ino: i64, mode: u32) -> Result<()> {
+        self.call(Request::Chmod { ino, mode }).await
+    }
+
+    async fn chown(&self, ino: i64, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.call(Request::Chown { ino, uid, gid }).await
+    }
+
+    async fn utimens(&self, ino: i64, atime: TimeChange, mtime: TimeChange) -> Result<()> {
+        self.call(Request::Utimens { ino, atime, mtime }).await
+    }
+
+    async fn access(&self, ino: i64, mask: i32, uid: u32, gid: u32) -> Result<bool> {
+        self.call(Request::Access {
+            ino,
+            mask,
+            uid,
+            gid,
+        })
+        .await
+    }
+
+    async fn getxattr(&self, ino: i64, name: &str) -> Result<Option<Vec<u8>>> {
+        let request = Request::Getxattr {
+            ino,
+            name: name.to_string(),
+        };
+        let (found, value) = self.client.call::<bool>(request, &[]).await?;
+        Ok(found.then_some(value))
+    }
+
+    async fn setxattr(&self, ino: i64, name: &str, value: &[u8], flags: i32) -> Result<()> {
+        let request = Request::Setxattr {
+            ino,
+            name: name.to_string(),
+            flags,
+        };
+        self.client.call::<()>(request, value).await?;
+        Ok(())
+    }
+
+    async fn listxattr(&self, ino: i64) -> Result<Vec<String>> {
+        self.call(Request::Listxattr { ino }).await
+    }
+
+    async fn removexattr(&self, ino: i64, name: &str) -> Result<()> {
+        self.call(Request::Removexattr {
+            ino,
+            name: name.to_string(),
+        })
+        .await
+    }
+
+    async fn open(&self, ino: i64, flags: i32) -> Result<BoxedFile> {
+        let mut state = self.client.state.lock().await;
+        let handle = self.client.open(&mut state, ino, flags).await?;
+        Ok(RemoteFile::open(self.client.clone(), ino, flags, handle))
+    }
+
+    async fn mkdir(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        self.call(Request::Mkdir {
+            parent_ino,
+            name: name.to_string(),
+            mode,
+            uid,
+            gid,
+        })
+        .await
+    }
+
+    async fn mkdir_all(&self, path: &str, mode: u32, uid: u32, gid: u32) -> Result<Stats> {
+        self.call(Request::MkdirAll {
+            path: path.to_string(),
+            mode,
+            uid,
+            gid,
+        })
+        .await
+    }
+
+    async fn create_file(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<(Stats, BoxedFile)> {
+        let request = Request::CreateFile {
+            parent_ino,
+            name: name.to_string(),
+            mode,
+            uid,
+            gid,
+        };
+        let mut state = self.client.state.lock().await;
+        let (header, _) = self.client.exchange(&mut state, &request, &[]).await?;
+        let (stats, handle): (Stats, u64) = decode(&header)?;
+        let file = RemoteFile::open(
+            self.client.clone(),
+            stats.ino,
+            libc::O_RDWR,
+            (state.generation, handle),
+        );
+        Ok((stats, file))
+    }
+
+    async fn mknod(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        mode: u32,
+        rdev: u64,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        self.call(Request::Mknod {
+            parent_ino,
+            name: name.to_string(),
+            mode,
+            rdev,
+            uid,
+            gid,
+        })
+        .await
+    }
+
+    async fn symlink(
+        &self,
+        parent_ino: i64,
+        name: &str,
+        target: &str,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Stats> {
+        self.call(Request::Symlink {
+            parent_ino,
+            name: name.to_string(),
+            target: target.to_string(),
+            uid,
+            gid,
+        })
+        .await
+    }
+
+    async fn unlink(&self, parent_ino: i64, name: &str) -> Result<()> {
+        self.call(Request::Unlink {
+            parent_ino,
+            name: name.to_string(),
+        })
+        .await
+    }
+
+    async fn rmdir(&self, parent_ino: i64, name: &str) -> Result<()> {
+        self.call(Request::Rmdir {
+            parent_ino,
+            name: name.to_string(),
+        })
+        .await
+    }
+
+    async fn remove_all(&self, path: &str) -> Result<()> {
+        self.call(Request::RemoveAll {
+            path: path.to_string(),
+        })
+        .await
+    }
+
+    async fn link(&self, ino: i64, newparent_ino: i64, newname: &str) -> Result<Stats> {
+        self.call(Request::Link {
+            ino,
+            newparent_ino,
+            newname: newname.to_string(),
+        })
+        .await
+    }
+
+    async fn rename(
+        &self,
+        oldparent_ino: i64,
+        oldname: &str,
+        newparent_ino: i64,
+        newname: &str,
+        flags: u32,
+    ) -> Result<()> {
+        self.call(Request::Rename {
+            oldparent_ino,
+            oldname: oldname.to_string(),
+            newparent_ino,
+            newname: newname.to_string(),
+            flags,
+        })
+        .await
+    }
+
+    async fn fallocate(&self, ino: i64, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.call(Request::Fallocate {
+            ino,
+            offset,
+            len,
+            mode,
+        })
+        .await
+    }
+
+    async fn copy_range(
+        &self,
+        src_ino: i64,
+        src_offset: u64,
+        dst_ino: i64,
+        dst_offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.call(Request::CopyRange {
+            src_ino,
+            src_offset,
+            dst_ino,
+            dst_offset,
+            len,
+        })
+        .await
+    }
+
+    /// Append `data` on the server in one step. Data larger than a single
+    /// request is sent in several appends, which other writers may
+    /// interleave with.
+    async fn append(&self, path: &str, data: &[u8]) -> Result<u64> {
+        let mut size = None;
+        for piece in data.chunks(MAX_IO_SIZE as usize) {
+            let request = Request::Append {
+                path: path.to_string(),
+            };
+            size = Some(self.client.call(request, piece).await?.0);
+        }
+        match size {
+            Some(size) => Ok(size),
+            None => {
+                let request = Request::Append {
+                    path: path.to_string(),
+                };
+                Ok(self.client.call(request, &[]).await?.0)
+            }
+        }
+    }
+
+    async fn create(&self, path: &str, mode: u32, flags: i32) -> Result<()> {
+        self.call(Request::Create {
+            path: path.to_string(),
+            mode,
+            flags,
+        })
+        .await
+    }
+
+    /// Replace the file on the server in one step. Data larger than a
+    /// single request is written to a temporary file in several requests
+    /// and renamed into place instead.
+    async fn atomic_write(&self, path: &str, data: &[u8]) -> Result<()> {
+        if data.len() as u64 > MAX_IO_SIZE {
+            return atomic_write_via_rename(self, path, data).await;
+        }
+        let request = Request::AtomicWrite {
+            path: path.to_string(),
+        };
+        self.client.call::<()>(request, data).await?;
+        Ok(())
+    }
+
+    async fn reflink(&self, src: &str, dst: &str) -> Result<()> {
+        self.call(Request::Reflink {
+            src: src.to_string(),
+            dst: dst.to_string(),
+        })
+        .await
+    }
+
+    async fn lock(&self, ino: i64, lock_type: LockType, owner: u64) -> Result<()> {
+        self.call(Request::Lock {
+            ino,
+            lock_type,
+            owner,
+        })
+        .await
+    }
+
+    async fn unlock(&self, ino: i64, owner: u64) -> Result<()> {
+        self.call(Request::Unlock { ino, owner }).await
+    }
+
+    async fn sync_all(&self) -> Result<()> {
+        self.call(Request::SyncAll).await
+    }
+
+    async fn fsync_dir(&self, ino: i64) -> Result<()> {
+        self.call(Request::FsyncDir { ino }).await
+    }
+
+    async fn statfs(&self) -> Result<FilesystemStats> {
+        self.call(Request::Statfs).await
+    }
+
+    async fn check_op(&self, op: &Operation) -> Result<()> {
+        self.call(Request::CheckOp {
+            operation: op.clone(),
+        })
+        .await
+    }
+
+    async fn forget(&self, ino: i64, nlookup: u64) {
+        if let Err(e) = self.call::<()>(Request::Forget { ino, nlookup }).await {
+            tracing::debug!("remote forget of inode {} failed: {}", ino, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::agentfs::AgentFS;
+    use crate::filesystem::{DEFAULT_FILE_MODE, S_IFDIR};
+    use tempfile::tempdir;
+
+    async fn start_server(addr: &str, fs: Arc<dyn FileSystem>) -> tokio::task::JoinHandle<()> {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let _ = serve(listener, fs).await;
+        })
+    }
+
+    #[tokio::test]
+    async fn test_remote_forwards_operations() {
+        let dir = tempdir().unwrap();
+        let fs = AgentFS::new(dir.path().join("test.db").to_str().unwrap())
+            .await
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, Arc::new(fs)));
+        let remote = RemoteFS::connect(&addr).await.unwrap();
+
+        let dir_stats = remote.mkdir(1, "dir", S_IFDIR | 0o755, 0, 0).await.unwrap();
+        let (stats, file) = remote
+            .create_file(dir_stats.ino, "file.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await
+            .unwrap();
+        file.pwrite(0, b"hello").await.unwrap();
+        assert_eq!(file.pread(0, 100).await.unwrap(), b"hello");
+        assert_eq!(file.fstat().await.unwrap().size, 5);
+        assert_eq!(
+            remote
+                .lookup(dir_stats.ino, "file.txt")
+                .await
+                .unwrap()
+                .unwrap()
+                .ino,
+            stats.ino
+        );
+        assert_eq!(
+            remote.readdir(dir_stats.ino).await.unwrap().unwrap(),
+            vec!["file.txt".to_string()]
+        );
+        assert_eq!(remote.append("/dir/file.txt", b"!").await.unwrap(), 6);
+        remote
+            .atomic_write("/dir/new.txt", b"replaced")
+            .await
+            .unwrap();
+        remote.reflink("/dir/new.txt", "/clone.txt").await.unwrap();
+        assert_eq!(
+            remote.lookup(1, "clone.txt").await.unwrap().unwrap().size,
+            8
+        );
+
+        remote
+            .setxattr(stats.ino, "user.tag", b"\0binary\xff", 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            remote
+                .getxattr(stats.ino, "user.tag")
+                .await
+                .unwrap()
+                .unwrap(),
+            b"\0binary\xff"
+        );
+        assert!(remote
+            .getxattr(stats.ino, "user.none")
+            .await
+            .unwrap()
+            .is_none());
+
+        // Errors keep their kind
+        assert!(matches!(
+            remote.mkdir(1, "dir", S_IFDIR | 0o755, 0, 0).await,
+            Err(Error::Fs(FsError::AlreadyExists))
+        ));
+        assert!(matches!(
+            remote.rmdir(1, "missing").await,
+            Err(Error::Fs(FsError::NotFound))
+        ));
+
+        // Reads and writes larger than a request are split
+        let data: Vec<u8> = (0..MAX_IO_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect();
+        file.pwrite(0, &data).await.unwrap();
+        assert_eq!(file.pread(0, u64::MAX).await.unwrap(), data);
+        remote.atomic_write("/dir/new.txt", &data).await.unwrap();
+        let new = remote
+            .lookup(dir_stats.ino, "new.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        let new_file = remote.open(new.ino, libc::O_RDONLY).await.unwrap();
+        assert_eq!(new_file.pread(0, u64::MAX).await.unwrap(), data);
+        assert_eq!(
+            remote.readdir(dir_stats.ino).await.unwrap().unwrap().len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_cancelled_call_does_not_leave_its_reply() {
+        let dir = tempdir().unwrap();
+        let fs = AgentFS::new(dir.path().join("test.db").to_str().unwrap())
+            .await
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, Arc::new(fs)));
+        let remote = RemoteFS::connect(&addr).await.unwrap();
+        let (stats, _) = remote
+            .create_file(1, "file.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await
+            .unwrap();
+
+        // Poll the call once: the request is sent, but its reply can't have
+        // arrived before it is dropped
+        tokio::select! {
+            biased;
+            _ = remote.readdir(1) => panic!("readdir finished without waiting for the server"),
+            _ = std::future::ready(()) => {}
+        }
+
+        let found = remote.lookup(1, "file.txt").await.unwrap().unwrap();
+        assert_eq!(found.ino, stats.ino);
+    }
+
+    #[tokio::test]
+    async fn test_remote_reconnects_after_connection_drop() {
+        let dir = tempdir().unwrap();
+        let fs: Arc<dyn FileSystem> = Arc::new(
+            AgentFS::new(dir.path().join("test.db").to_str().unwrap())
+                .await
+                .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let server = start_server(&addr, fs.clone()).await;
+
+        let remote = RemoteFS::connect(&addr).await.unwrap();
+        let (stats, file) = remote
+            .create_file(1, "file.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await
+            .unwrap();
+        file.pwrite(0, b"before").await.unwrap();
+
+        server.abort();
+        let _ = server.await;
+        let err = remote.getattr(stats.ino).await.unwrap_err();
+        assert_eq!(err.to_errno(), libc::EIO);
+
+        let _server = start_server(&addr, fs).await;
+        assert!(remote.getattr(stats.ino).await.unwrap().is_some());
+        // The file is reopened on the new connection, keeping its contents
+        assert_eq!(file.pread(0, 100).await.unwrap(), b"before");
+        file.pwrite(6, b" after").await.unwrap();
+        assert_eq!(file.pread(0, 100).await.unwrap(), b"before after");
+    }
+}
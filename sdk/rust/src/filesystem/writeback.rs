@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::Stats;
+
+/// How [`AgentFS`](super::AgentFS) buffers writes made through open files.
+///
+/// A write that continues or overwrites the bytes an inode already has
+/// buffered is kept in memory instead of being committed on its own, so a
+/// program writing a byte at a time costs one transaction per buffer rather
+/// than one per byte. Buffered bytes are stored once they reach `max_bytes`,
+/// within `flush_interval`, when the file is flushed or synced, and before
+/// any operation that would otherwise miss them. Reads and attributes
+/// through the filesystem see them all along.
+///
+/// Like the page cache, buffered bytes are lost if the process exits before
+/// they are stored, and an error storing them (such as an exceeded quota) is
+/// reported by whichever operation stored them, after which they are
+/// discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteBackPolicy {
+    /// Bytes an inode may buffer before they are stored; larger writes are
+    /// stored directly
+    pub max_bytes: usize,
+    /// Longest time buffered bytes wait before they are stored
+    pub flush_interval: Duration,
+}
+
+impl Default for WriteBackPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1024 * 1024,
+            flush_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// How [`AgentFS`](super::AgentFS) keeps large writes out of memory.
+///
+/// Once the bytes an inode has buffered under a [`WriteBackPolicy`] would
+/// pass `threshold`, they move to a temporary file in `dir` and later writes
+/// to them go there, so even a buffer of gigabytes holds at most `threshold`
+/// bytes in memory per inode. Spilled bytes, and writes through open files
+/// larger than `threshold`, are stored `threshold` bytes per transaction,
+/// which bounds what the database holds for one transaction too.
+///
+/// A write stored in several transactions is not atomic: if storing it
+/// fails partway, the bytes before the failure stay stored, as with a short
+/// write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpillPolicy {
+    /// Directory for the temporary files, or the system temporary directory
+    /// if `None`. Opening through [`AgentFSOptions`](crate::AgentFSOptions)
+    /// defaults it to the database's directory.
+    pub dir: Option<PathBuf>,
+    /// Bytes an inode may buffer in memory, and the most bytes stored in one
+    /// transaction
+    pub threshold: usize,
+}
+
+impl Default for SpillPolicy {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            threshold: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A temporary file holding an extent's bytes, removed once dropped
+#[derive(Debug)]
+struct SpillFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl SpillFile {
+    fn create(dir: &Path) -> io::Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        loop {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!(".agentfs-spill-{}-{}", std::process::id(), n));
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => {
+                    // Where an open file can be unlinked, do so right away so
+                    // that a crash leaves nothing behind
+                    #[cfg(unix)]
+                    let _ = std::fs::remove_file(&path);
+                    return Ok(Self { file, path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn write_at(&self, pos: u64, data: &[u8]) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(pos))?;
+        file.write_all(data)
+    }
+
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(buf)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Where an extent's bytes are kept
+#[derive(Debug)]
+enum Bytes {
+    Memory(Vec<u8>),
+    Spilled(SpillFile),
+}
+
+/// Contiguous bytes written to one inode and not yet stored
+#[derive(Debug)]
+struct Extent {
+    offset: u64,
+    len: u64,
+    bytes: Bytes,
+    /// Time of the latest write, seconds and nanoseconds
+    mtime: (i64, u32),
+}
+
+impl Extent {
+    fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+
+    /// Read `buf.len()` bytes starting `at` bytes into the extent
+    fn read_at(&self, at: u64, buf: &mut [u8]) -> io::Result<()> {
+        match &self.bytes {
+            Bytes::Memory(data) => {
+                let at = at as usize;
+                buf.copy_from_slice(&data[at..at + buf.len()]);
+                Ok(())
+            }
+            Bytes::Spilled(file) => file.read_at(at, buf),
+        }
+    }
+
+    /// Write `data` starting `at` bytes into the extent, which must be no
+    /// further than its end
+    fn write_at(&mut self, at: u64, data: &[u8]) -> io::Result<()> {
+        let end = at + data.len() as u64;
+        match &mut self.bytes {
+            Bytes::Memory(bytes) => {
+                let at = at as usize;
+                if bytes.len() < at + data.len() {
+                    bytes.resize(at + data.len(), 0);
+                }
+                bytes[at..at + data.len()].copy_from_slice(data);
+            }
+            Bytes::Spilled(file) => file.write_at(at, data)?,
+        }
+        self.len = self.len.max(end);
+        Ok(())
+    }
+
+    /// Move the bytes to a temporary file in `dir`, if they are in memory
+    fn spill(&mut self, dir: &Path) -> io::Result<()> {
+        if let Bytes::Memory(data) = &self.bytes {
+            let file = SpillFile::create(dir)?;
+            file.write_at(0, data)?;
+            self.bytes = Bytes::Spilled(file);
+        }
+        Ok(())
+    }
+}
+
+/// What [`WriteBuffer::push`] did with a write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Push {
+    /// The write is buffered
+    Buffered,
+    /// The write is buffered, and the inode's buffer is full and should be
+    /// stored
+    Full,
+    /// The write was not buffered and must be stored directly, after any
+    /// bytes the inode has buffered
+    Rejected,
+}
+
+/// Writes buffered per inode, shared by a filesystem, its clones and its
+/// open files.
+#[derive(Debug, Default)]
+pub(crate) struct WriteBuffer {
+    policy: Mutex<Option<WriteBackPolicy>>,
+    spill: Mutex<Option<SpillPolicy>>,
+    extents: Mutex<HashMap<i64, Extent>>,
+    /// Held while buffering a write, storing buffered bytes or reading a
+    /// file with buffered bytes, so that bytes never change while they are
+    /// being stored or read
+    pub(crate) gate: tokio::sync::Mutex<()>,
+}
+
+impl WriteBuffer {
+    /// Hold the gate while `ino` has buffered bytes, so that a read sees
+    /// either the stored bytes or the buffered ones, never neither
+    pub(crate) async fn read_gate(&self, ino: i64) -> Option<tokio::sync::MutexGuard<'_, ()>> {
+        if self.contains(ino) {
+            Some(self.gate.lock().await)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn policy(&self) -> Option<WriteBackPolicy> {
+        *self.policy.lock().unwrap()
+    }
+
+    pub(crate) fn set_policy(&self, policy: Option<WriteBackPolicy>) {
+        *self.policy.lock().unwrap() = policy;
+    }
+
+    pub(crate) fn spill(&self) -> Option<SpillPolicy> {
+        self.spill.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_spill(&self, policy: Option<SpillPolicy>) {
+        *self.spill.lock().unwrap() = policy;
+    }
+
+    /// The most bytes to store in one transaction, if writes are split
+    pub(crate) fn batch_bytes(&self) -> Option<usize> {
+        self.spill
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|spill| spill.threshold)
+    }
+
+    /// Buffer `data` written to `ino` at `offset` at time `mtime`.
+    ///
+    /// Only writes that start inside or right after the inode's buffered
+    /// bytes are buffered, so that each inode has one contiguous extent.
+    pub(crate) fn push(&self, ino: i64, offset: u64, data: &[u8], mtime: (i64, u32)) -> Push {
+        let Some(policy) = self.policy() else {
+            return Push::Rejected;
+        };
+        if data.len() >= policy.max_bytes {
+            return Push::Rejected;
+        }
+        let mut extents = self.extents.lock().unwrap();
+        if let Some(extent) = extents.get(&ino) {
+            if offset < extent.offset || offset > extent.end() {
+                return Push::Rejected;
+            }
+        }
+        let extent = extents.entry(ino).or_insert_with(|| Extent {
+            offset,
+            len: 0,
+            bytes: Bytes::Memory(Vec::new()),
+            mtime,
+        });
+        let start = offset - extent.offset;
+        let end = start + data.len() as u64;
+        let spilled = match &*self.spill.lock().unwrap() {
+            Some(spill) if end > spill.threshold as u64 => {
+                let dir = spill.dir.clone().unwrap_or_else(std::env::temp_dir);
+                extent.spill(&dir)
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = spilled.and_then(|()| extent.write_at(start, data)) {
+            // The write is stored directly instead, over whatever part of it
+            // made it into the buffer
+            tracing::warn!("failed to spill buffered writes: {}", e);
+            if extent.len == 0 {
+                extents.remove(&ino);
+            }
+            return Push::Rejected;
+        }
+        extent.mtime = mtime;
+        if extent.len >= policy.max_bytes as u64 {
+            Push::Full
+        } else {
+            Push::Buffered
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.extents.lock().unwrap().is_empty()
+    }
+
+    /// Whether `ino` has buffered bytes
+    pub(crate) fn contains(&self, ino: i64) -> bool {
+        self.extents.lock().unwrap().contains_key(&ino)
+    }
+
+    /// Inodes with buffered bytes
+    pub(crate) fn inodes(&self) -> Vec<i64> {
+        self.extents.lock().unwrap().keys().copied().collect()
+    }
+
+    /// The offset the bytes buffered for `ino` start at, and their length
+    pub(crate) fn pending(&self, ino: i64) -> Option<(u64, u64)> {
+        let extents = self.extents.lock().unwrap();
+        extents.get(&ino).map(|extent| (extent.offset, extent.len))
+    }
+
+    /// Up to `len` of the bytes buffered for `ino`, starting `at` bytes into
+    /// them
+    pub(crate) fn read(&self, ino: i64, at: u64, len: usize) -> io::Result<Vec<u8>> {
+        let extents = self.extents.lock().unwrap();
+        let Some(extent) = extents.get(&ino) else {
+            return Ok(Vec::new());
+        };
+        let mut data = vec![0; len.min(extent.len.saturating_sub(at) as usize)];
+        extent.read_at(at, &mut data)?;
+        Ok(data)
+    }
+
+    /// Forget the bytes buffered for `ino`, once they were stored
+    pub(crate) fn remove(&self, ino: i64) {
+        self.extents.lock().unwrap().remove(&ino);
+    }
+
+    /// Lay the bytes buffered for `ino` over `data`, which was read from
+    /// storage at `offset` for a read of up to `size` bytes.
+    ///
+    /// `data` grows if buffered bytes extend past what storage returned; a
+    /// gap before them is a hole and reads as zeros.
+    pub(crate) fn read_through(
+        &self,
+        ino: i64,
+        offset: u64,
+        size: u64,
+        data: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let extents = self.extents.lock().unwrap();
+        let Some(extent) = extents.get(&ino) else {
+            return Ok(());
+        };
+        let start = offset.max(extent.offset);
+        let end = offset.saturating_add(size).min(extent.end());
+        if start >= end {
+            return Ok(());
+        }
+        let len = (end - offset) as usize;
+        if data.len() < len {
+            data.resize(len, 0);
+        }
+        let to = (start - offset) as usize;
+        let count = (end - start) as usize;
+        extent.read_at(start - extent.offset, &mut data[to..to + count])
+    }
+
+    /// Show the size and mtime buffered writes give an inode in its stats
+    pub(crate) fn apply(&self, stats: &mut Stats) {
+        if let Some(extent) = self.extents.lock().unwrap().get(&stats.ino) {
+            stats.size = stats.size.max(extent.end() as i64);
+            (stats.mtime, stats.mtime_nsec) = extent.mtime;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(max_bytes: usize) -> WriteBuffer {
+        let buffer = WriteBuffer::default();
+        buffer.set_policy(Some(WriteBackPolicy {
+            max_bytes,
+            ..Default::default()
+        }));
+        buffer
+    }
+
+    #[test]
+    fn test_push_coalesces_contiguous_writes() {
+        let buffer = buffer(8);
+        assert_eq!(buffer.push(1, 10, b"ab", (0, 0)), Push::Buffered);
+        assert_eq!(buffer.push(1, 12, b"cd", (0, 0)), Push::Buffered);
+        // Overwriting buffered bytes stays in the same extent
+        assert_eq!(buffer.push(1, 11, b"X", (0, 0)), Push::Buffered);
+        assert_eq!(buffer.pending(1), Some((10, 4)));
+        assert_eq!(buffer.read(1, 0, 100).unwrap(), b"aXcd");
+
+        // Writes elsewhere, or too large to buffer, are stored directly
+        assert_eq!(buffer.push(1, 20, b"e", (0, 0)), Push::Rejected);
+        assert_eq!(buffer.push(1, 9, b"e", (0, 0)), Push::Rejected);
+        assert_eq!(buffer.push(2, 0, b"too large", (0, 0)), Push::Rejected);
+        assert!(!buffer.contains(2));
+
+        assert_eq!(buffer.push(1, 14, b"efgh", (0, 0)), Push::Full);
+        buffer.remove(1);
+        assert!(buffer.is_empty());
+
+        buffer.set_policy(None);
+        assert_eq!(buffer.push(1, 0, b"a", (0, 0)), Push::Rejected);
+    }
+
+    #[test]
+    fn test_read_through_overlays_buffered_bytes() {
+        let buffer = buffer(64);
+        buffer.push(1, 4, b"wxyz", (0, 0));
+
+        // Buffered bytes past the end of storage extend the read, with a hole
+        // before them
+        let mut data = b"ab".to_vec();
+        buffer.read_through(1, 0, 100, &mut data).unwrap();
+        assert_eq!(data, b"ab\0\0wxyz");
+
+        let mut data = b"012345".to_vec();
+        buffer.read_through(1, 0, 6, &mut data).unwrap();
+        assert_eq!(data, b"0123wx");
+
+        let mut data = Vec::new();
+        buffer.read_through(1, 8, 10, &mut data).unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_push_spills_past_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = buffer(64);
+        buffer.set_spill(Some(SpillPolicy {
+            dir: Some(dir.path().to_path_buf()),
+            threshold: 8,
+        }));
+        assert_eq!(buffer.push(1, 0, b"abcdef", (0, 0)), Push::Buffered);
+        let in_memory = |buffer: &WriteBuffer| {
+            matches!(buffer.extents.lock().unwrap()[&1].bytes, Bytes::Memory(_))
+        };
+        assert!(in_memory(&buffer));
+
+        // Growing past the threshold moves the bytes to a file, which later
+        // writes and reads go through
+        assert_eq!(buffer.push(1, 6, b"ghij", (0, 0)), Push::Buffered);
+        assert!(!in_memory(&buffer));
+        assert_eq!(buffer.push(1, 2, b"CD", (0, 0)), Push::Buffered);
+        assert_eq!(buffer.pending(1), Some((0, 10)));
+        assert_eq!(buffer.read(1, 0, 100).unwrap(), b"abCDefghij");
+        assert_eq!(buffer.read(1, 8, 100).unwrap(), b"ij");
+        let mut data = b"0123".to_vec();
+        buffer.read_through(1, 2, 4, &mut data).unwrap();
+        assert_eq!(data, b"CDef");
+        assert_eq!(buffer.batch_bytes(), Some(8));
+
+        buffer.remove(1);
+        #[cfg(unix)]
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -1,7 +1,9 @@
+pub mod client;
 pub mod connection_pool;
 pub mod error;
 pub mod filesystem;
 pub mod kvstore;
+pub mod manifest;
 pub mod schema;
 pub mod toolcalls;
 
@@ -16,14 +18,19 @@ use turso::{Builder, EncryptionOpts, Value};
 pub use turso::sync::{DatabaseSyncStats, PartialBootstrapStrategy, PartialSyncOpts};
 
 // Re-export filesystem types
+pub use client::AgentFSClient;
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 pub use filesystem::HostFS;
 pub use filesystem::{
-    BoxedFile, DirEntry, File, FileSystem, FilesystemStats, FsError, OverlayFS, Stats, TimeChange,
-    DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT,
-    S_IFREG, S_IFSOCK,
+    AtimePolicy, BlobKey, BoxedDirStream, BoxedFile, BoxedReadStream, BusyRetry, ChangeEntry,
+    ChangeEvent, ChangeEventKind, ChangeKind, CheckpointPolicy, CompactStats, CompressionKind,
+    DirEntry, DirStream, File, FileSystem, FileType, FilesystemStats, FsError, Inconsistency,
+    LockType, Operation, OverlayFS, ReadOnlyFS, ReadStream, RemoteFS, SnapshotChanges, SpillPolicy,
+    Stats, TimeChange, TrashEntry, TrashPolicy, WriteBackPolicy, DEFAULT_DIR_MODE,
+    DEFAULT_FILE_MODE, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK,
 };
 pub use kvstore::KvStore;
+pub use manifest::Manifest;
 pub use schema::{SchemaVersion, AGENTFS_SCHEMA_VERSION};
 pub use toolcalls::{ToolCall, ToolCallStats, ToolCallStatus, ToolCalls};
 
@@ -39,6 +46,8 @@ pub struct Mount {
     pub id: String,
     /// The mountpoint path
     pub mountpoint: PathBuf,
+    /// The filesystem type reported by the kernel (e.g. "fuse" or "nfs")
+    pub fstype: String,
 }
 
 /// Get all currently mounted agentfs filesystems by parsing /proc/mounts
@@ -54,7 +63,7 @@ pub fn get_mounts() -> Vec<Mount> {
         .lines()
         .filter_map(|line| {
             let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 && parts[0].starts_with("agentfs:") {
+            if parts.len() >= 3 && parts[0].starts_with("agentfs:") {
                 let agent_id = parts[0].strip_prefix("agentfs:")?.to_string();
                 // Skip the internal "fuse" mount used by the daemon
                 if agent_id == "fuse" {
@@ -63,6 +72,7 @@ pub fn get_mounts() -> Vec<Mount> {
                 Some(Mount {
                     id: agent_id,
                     mountpoint: PathBuf::from(parts[1]),
+                    fstype: parts[2].to_string(),
                 })
             } else {
                 None
@@ -116,6 +126,54 @@ pub struct AgentFSOptions {
     pub sync: SyncOptions,
     /// Encryption configuration for database at rest
     pub encryption: Option<EncryptionConfig>,
+    /// Optional filesystem size limit in bytes.
+    /// When set, it is persisted in `fs_config` and writes beyond it fail with ENOSPC.
+    pub max_bytes: Option<u64>,
+    /// Optional compression of file contents.
+    /// When set, it is persisted in `fs_config` and applies to data written afterwards.
+    pub compression: Option<CompressionKind>,
+    /// Store identical file contents only once.
+    /// When set, it is persisted in `fs_config` and applies to data written afterwards.
+    pub dedup: bool,
+    /// Optional owner of the root directory instead of the opening user.
+    /// When set, it is persisted in `fs_config` and applied on every open.
+    pub uid: Option<u32>,
+    /// Optional group of the root directory instead of the opening user's.
+    /// When set, it is persisted in `fs_config` and applied on every open.
+    pub gid: Option<u32>,
+    /// Optional umask for newly created files and directories.
+    /// When set, it is persisted in `fs_config` and applies to entries created afterwards.
+    pub umask: Option<u32>,
+    /// Optional size of the chunks file contents are stored in.
+    /// When set, it is persisted in `fs_config`; a filesystem that already
+    /// stores file data keeps its own chunk size.
+    pub chunk_size: Option<usize>,
+    /// Look up names ignoring case, as macOS volumes do by default.
+    /// When set, it is persisted in `fs_config` and cannot be turned off again.
+    pub case_insensitive: bool,
+    /// Optional policy for updating access times on reads.
+    /// When set, it is persisted in `fs_config` and applies to reads afterwards.
+    pub atime_policy: Option<AtimePolicy>,
+    /// Optional trash that removed entries go to instead of being deleted.
+    /// When set, it is persisted in `fs_config` and applies to removals afterwards.
+    pub trash: Option<TrashPolicy>,
+    /// How writes retry while another connection holds the database lock.
+    /// Not persisted; it applies while this instance is open.
+    pub busy_retry: BusyRetry,
+    /// When to checkpoint the write-ahead log in the background.
+    /// Not persisted; it applies while this instance is open.
+    pub checkpoint_policy: CheckpointPolicy,
+    /// Optional buffering of small writes made through open files.
+    /// Not persisted; it applies while this instance is open.
+    pub write_back: Option<WriteBackPolicy>,
+    /// Optional temporary files for large writes through open files, in the
+    /// database's directory unless the policy names one.
+    /// Not persisted; it applies while this instance is open.
+    pub spill: Option<SpillPolicy>,
+    /// Optional key encrypting file contents at rest.
+    /// Names, sizes and other metadata stay in plaintext. Once set, the same
+    /// key is required to open the filesystem again.
+    pub blob_key: Option<BlobKey>,
 }
 
 impl AgentFSOptions {
@@ -127,15 +185,30 @@ impl AgentFSOptions {
                 .chars()
                 .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
     }
+    /// Whether these options select an in-memory database that never touches disk.
+    ///
+    /// This is the case with neither `id` nor `path`, or with a `path` of
+    /// `:memory:` or the empty string.
+    pub fn is_ephemeral(&self) -> bool {
+        match &self.path {
+            Some(path) => path.is_empty() || path == ":memory:",
+            None => self.id.is_none(),
+        }
+    }
+
     pub fn db_path(&self) -> Result<String> {
         // Determine database path: path takes precedence over id
-        if let Some(path) = &self.path {
+        if self.is_ephemeral() {
+            // No id or path, or an explicit in-memory path
+            Ok(":memory:".to_string())
+        } else if let Some(path) = &self.path {
             // Custom path provided directly
             Ok(path.to_string())
-        } else if let Some(id) = &self.id {
+        } else {
+            let id = self.id.as_deref().unwrap_or_default();
             // Validate agent ID to prevent path traversal attacks
             if !Self::validate_agent_id(id) {
-                return Err(Error::InvalidAgentId(id.clone()));
+                return Err(Error::InvalidAgentId(id.to_string()));
             }
 
             // Ensure .agentfs directory exists
@@ -144,9 +217,6 @@ impl AgentFSOptions {
                 std::fs::create_dir_all(agentfs_dir)?;
             }
             Ok(format!("{}/{}.db", agentfs_dir.display(), id))
-        } else {
-            // No id or path = ephemeral in-memory database
-            Ok(":memory:".to_string())
         }
     }
     /// Create options for a persistent agent with the given ID
@@ -157,6 +227,21 @@ impl AgentFSOptions {
             base: None,
             sync: SyncOptions::default(),
             encryption: None,
+            max_bytes: None,
+            compression: None,
+            dedup: false,
+            uid: None,
+            gid: None,
+            umask: None,
+            chunk_size: None,
+            case_insensitive: false,
+            atime_policy: None,
+            trash: None,
+            busy_retry: BusyRetry::default(),
+            checkpoint_policy: CheckpointPolicy::Never,
+            write_back: None,
+            spill: None,
+            blob_key: None,
         }
     }
 
@@ -168,6 +253,21 @@ impl AgentFSOptions {
             base: None,
             sync: SyncOptions::default(),
             encryption: None,
+            max_bytes: None,
+            compression: None,
+            dedup: false,
+            uid: None,
+            gid: None,
+            umask: None,
+            chunk_size: None,
+            case_insensitive: false,
+            atime_policy: None,
+            trash: None,
+            busy_retry: BusyRetry::default(),
+            checkpoint_policy: CheckpointPolicy::Never,
+            write_back: None,
+            spill: None,
+            blob_key: None,
         }
     }
 
@@ -179,6 +279,21 @@ impl AgentFSOptions {
             base: None,
             sync: SyncOptions::default(),
             encryption: None,
+            max_bytes: None,
+            compression: None,
+            dedup: false,
+            uid: None,
+            gid: None,
+            umask: None,
+            chunk_size: None,
+            case_insensitive: false,
+            atime_policy: None,
+            trash: None,
+            busy_retry: BusyRetry::default(),
+            checkpoint_policy: CheckpointPolicy::Never,
+            write_back: None,
+            spill: None,
+            blob_key: None,
         }
     }
 
@@ -213,10 +328,100 @@ impl AgentFSOptions {
         self
     }
 
+    /// Limit the total size of files in the filesystem
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Compress file contents as they are written
+    pub fn with_compression(mut self, compression: CompressionKind) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Deduplicate file contents as they are written
+    pub fn with_dedup(mut self) -> Self {
+        self.dedup = true;
+        self
+    }
+
+    /// Give the root directory a fixed owner
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Give the root directory a fixed group
+    pub fn with_gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Mask permission bits of newly created files and directories
+    pub fn with_umask(mut self, umask: u32) -> Self {
+        self.umask = Some(umask);
+        self
+    }
+
+    /// Store file contents in chunks of the given size
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Look up names ignoring case
+    pub fn with_case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+
+    /// Choose when reads update access times
+    pub fn with_atime_policy(mut self, policy: AtimePolicy) -> Self {
+        self.atime_policy = Some(policy);
+        self
+    }
+
+    /// Move removed entries to the trash instead of deleting them
+    pub fn with_trash(mut self, policy: TrashPolicy) -> Self {
+        self.trash = Some(policy);
+        self
+    }
+
+    /// Retry writes with backoff while the database lock is busy
+    pub fn with_busy_retry(mut self, retry: BusyRetry) -> Self {
+        self.busy_retry = retry;
+        self
+    }
+
+    /// Checkpoint the write-ahead log in the background
+    pub fn with_checkpoint_policy(mut self, policy: CheckpointPolicy) -> Self {
+        self.checkpoint_policy = policy;
+        self
+    }
+
+    /// Buffer small writes made through open files
+    pub fn with_write_back(mut self, policy: WriteBackPolicy) -> Self {
+        self.write_back = Some(policy);
+        self
+    }
+
+    /// Keep large writes made through open files out of memory
+    pub fn with_spill(mut self, policy: SpillPolicy) -> Self {
+        self.spill = Some(policy);
+        self
+    }
+
+    /// Encrypt file contents with the given key
+    pub fn with_blob_key(mut self, key: BlobKey) -> Self {
+        self.blob_key = Some(key);
+        self
+    }
+
     /// Resolve an id-or-path string to AgentFSOptions
     ///
     /// Resolution order (first match wins):
-    /// 1. `:memory:` -> ephemeral in-memory database
+    /// 1. `:memory:` or empty string -> ephemeral in-memory database
     /// 2. Valid agent ID with existing `.agentfs/{id}.db` -> uses that agent
     /// 3. Existing file path -> uses that path directly
     ///
@@ -224,7 +429,7 @@ impl AgentFSOptions {
     pub fn resolve(id_or_path: impl Into<String>) -> Result<Self> {
         let id_or_path = id_or_path.into();
 
-        if id_or_path == ":memory:" {
+        if id_or_path.is_empty() || id_or_path == ":memory:" {
             return Ok(Self::ephemeral());
         }
 
@@ -267,6 +472,12 @@ pub struct AgentFS {
     pub kv: KvStore,
     pub fs: filesystem::AgentFS,
     pub tools: ToolCalls,
+    /// Database file, for a filesystem opened by ID or path
+    db_path: Option<String>,
+    /// Agent ID, for a filesystem opened by ID
+    id: Option<String>,
+    /// Whether the database was opened with an encryption key
+    encrypted: bool,
 }
 
 impl AgentFS {
@@ -308,6 +519,9 @@ impl AgentFS {
 
         let db_path = options.db_path()?;
         let meta_path = format!("{db_path}-info");
+        let ephemeral = options.is_ephemeral();
+        let id = options.id.clone().filter(|_| options.path.is_none());
+        let encrypted = options.encryption.is_some();
 
         // Determine if this is a synced database:
         // 1. If sync.remote_url is set, create a new synced database
@@ -364,7 +578,78 @@ impl AgentFS {
             OverlayFS::init_schema(&conn, &base_path_str).await?;
         }
 
-        Self::open_with_pool(pool, sync_db).await
+        // The chunk size is read when the filesystem is opened
+        if let Some(chunk_size) = options.chunk_size {
+            let conn = pool.get_connection().await?;
+            filesystem::AgentFS::init_chunk_size(&conn, chunk_size).await?;
+        }
+
+        // Like the chunk size, case folding is read when the filesystem is opened
+        if options.case_insensitive {
+            let conn = pool.get_connection().await?;
+            filesystem::AgentFS::init_case_insensitive(&conn).await?;
+        }
+
+        let mut agent = Self::open_with_pool(pool, sync_db).await?;
+        if !ephemeral {
+            agent.db_path = Some(db_path);
+            agent.id = id;
+        }
+        agent.encrypted = encrypted;
+
+        // Check the blob key before anything is read or persisted
+        match options.blob_key {
+            Some(key) => agent.fs.set_blob_key(key).await?,
+            None if agent.fs.blob_encrypted().await? => {
+                return Err(Error::InvalidEncryptionKey(
+                    "file contents are encrypted and no blob key was given".to_string(),
+                ));
+            }
+            None => {}
+        }
+
+        // Persist the size limit; without one, the stored limit (if any) applies
+        if let Some(max_bytes) = options.max_bytes {
+            agent.fs.set_max_bytes(Some(max_bytes)).await?;
+        }
+        if let Some(compression) = options.compression {
+            agent.fs.set_compression(Some(compression)).await?;
+        }
+        if options.dedup {
+            agent.fs.set_dedup(true).await?;
+        }
+        if options.uid.is_some() || options.gid.is_some() {
+            agent.fs.set_owner(options.uid, options.gid).await?;
+        }
+        if let Some(umask) = options.umask {
+            agent.fs.set_umask(Some(umask)).await?;
+        }
+        if let Some(policy) = options.atime_policy {
+            agent.fs.set_atime_policy(policy).await?;
+        }
+        if let Some(policy) = options.trash {
+            agent.fs.set_trash(Some(policy)).await?;
+        }
+        agent.fs.set_busy_retry(options.busy_retry);
+        agent.fs.set_checkpoint_policy(options.checkpoint_policy)?;
+        if let Some(policy) = options.write_back {
+            agent.fs.set_write_back(Some(policy)).await?;
+        }
+        if let Some(mut policy) = options.spill {
+            // Temporary files go next to the database, or to the system's
+            // temporary directory for an ephemeral one
+            if let Some(db_path) = agent.db_path.as_ref().filter(|_| policy.dir.is_none()) {
+                let parent = Path::new(db_path).parent().unwrap_or(Path::new(""));
+                policy.dir = Some(if parent.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    parent.to_path_buf()
+                });
+            }
+            agent.fs.set_spill(Some(policy))?;
+        }
+
+        Ok(agent)
     }
 
     /// Open an AgentFS instance from a connection pool
@@ -382,6 +667,9 @@ impl AgentFS {
             kv,
             fs,
             tools,
+            db_path: None,
+            id: None,
+            encrypted: false,
         })
     }
 
@@ -608,6 +896,51 @@ impl AgentFS {
         Ok(whiteouts)
     }
 
+    /// Write the manifest describing this filesystem next to its database
+    ///
+    /// The manifest records the current settings; call this again after
+    /// changing them to keep it accurate. Fails for in-memory filesystems
+    /// and for ones opened from a connection pool, which have no database
+    /// file to describe.
+    pub async fn write_manifest(&self) -> Result<Manifest> {
+        let Some(db_path) = &self.db_path else {
+            return Err(Error::Internal(
+                "filesystem has no database file to describe".to_string(),
+            ));
+        };
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs() as i64;
+        let manifest = Manifest {
+            version: manifest::MANIFEST_VERSION,
+            id: self.id.clone(),
+            database: db_path.clone(),
+            created_at,
+            schema_version: AGENTFS_SCHEMA_VERSION.to_string(),
+            base: self.is_overlay_enabled().await?,
+            chunk_size: self.fs.chunk_size(),
+            compression: self.fs.compression().map(|c| c.as_str().to_string()),
+            max_bytes: self.fs.max_bytes(),
+            dedup: self.fs.dedup(),
+            case_insensitive: self.fs.case_insensitive(),
+            atime: self.fs.atime_policy().as_str().to_string(),
+            encrypted: self.encrypted,
+        };
+        manifest.write()?;
+        Ok(manifest)
+    }
+
+    /// Read the manifest written next to this filesystem's database
+    ///
+    /// Returns `None` if no manifest was written, or if the filesystem has
+    /// no database file.
+    pub async fn manifest(&self) -> Result<Option<Manifest>> {
+        match &self.db_path {
+            Some(db_path) => Manifest::read(db_path),
+            None => Ok(None),
+        }
+    }
+
     /// Check if overlay is enabled for this filesystem
     ///
     /// Returns the base path if overlay is enabled, None otherwise.
@@ -643,6 +976,112 @@ impl AgentFS {
             Err(_) => Ok(None), // Table doesn't exist
         }
     }
+
+    /// Check whether the overlay may write to its base directory
+    ///
+    /// Overlay bases are opened with `HostFS::new_readonly` unless the
+    /// `base_writable` key in `fs_overlay_config` is set to `1` or `true`.
+    pub async fn is_base_writable(&self) -> Result<bool> {
+        let conn = self.pool.get_connection().await?;
+        let result = conn
+            .query(
+                "SELECT value FROM fs_overlay_config WHERE key = 'base_writable'",
+                (),
+            )
+            .await;
+
+        match result {
+            Ok(mut rows) => match rows.next().await? {
+                Some(row) => Ok(matches!(
+                    row.get_value(0),
+                    Ok(Value::Text(s)) if s == "1" || s.eq_ignore_ascii_case("true")
+                )),
+                None => Ok(false),
+            },
+            Err(_) => Ok(false), // Table doesn't exist
+        }
+    }
+
+    /// Open the base directory of an overlay as a `HostFS`
+    ///
+    /// The base is read-only unless `base_writable` is set, so a bug in the
+    /// overlay can never modify the user's real directory.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn open_overlay_base(&self, base_path: &str) -> Result<HostFS> {
+        if self.is_base_writable().await? {
+            HostFS::new(base_path)
+        } else {
+            HostFS::new_readonly(base_path)
+        }
+    }
+
+    /// Opt the overlay into (or out of) a writable base directory
+    ///
+    /// Fails if overlay is not enabled for this filesystem.
+    pub async fn set_base_writable(&self, writable: bool) -> Result<()> {
+        if self.is_overlay_enabled().await?.is_none() {
+            return Err(Error::Internal("overlay is not enabled".to_string()));
+        }
+        let conn = self.pool.get_connection().await?;
+        conn.execute(
+            "INSERT OR REPLACE INTO fs_overlay_config (key, value) VALUES ('base_writable', ?1)",
+            [Value::Text(if writable { "1" } else { "0" }.to_string())],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Point the overlay at another base directory, such as after the base
+    /// was moved
+    ///
+    /// The path is stored canonicalized, which is also what this returns,
+    /// and takes effect the next time the overlay is opened; the delta is
+    /// kept as it is, except that the base inode numbers recorded for
+    /// copied-up entries are dropped, as they belong to the old base. Fails
+    /// with an I/O error of kind `NotFound` if the path does not exist, with
+    /// `FsError::NotADirectory` if it is not a directory, and if overlay is
+    /// not enabled for this filesystem. It also fails while files are only
+    /// partially copied up, since their unmodified blocks are still read
+    /// from the base and would come from the new one.
+    pub async fn set_base_path(&self, base: impl AsRef<Path>) -> Result<String> {
+        if self.is_overlay_enabled().await?.is_none() {
+            return Err(Error::Internal("overlay is not enabled".to_string()));
+        }
+        let canonical = std::fs::canonicalize(base)?;
+        if !canonical.is_dir() {
+            return Err(FsError::NotADirectory.into());
+        }
+        let base_path = canonical.to_string_lossy().to_string();
+        let conn = self.pool.get_connection().await?;
+
+        let mut rows = conn
+            .query("SELECT COUNT(*) FROM fs_copyup_partial", ())
+            .await?;
+        let partial = match rows.next().await? {
+            Some(row) => row
+                .get_value(0)
+                .ok()
+                .and_then(|v| v.as_integer().copied())
+                .unwrap_or(0),
+            None => 0,
+        };
+        drop(rows);
+        if partial > 0 {
+            return Err(Error::Internal(format!(
+                "{} file(s) are partially copied up and still read from the current base",
+                partial
+            )));
+        }
+
+        // Dropped first: stale origins must not outlive a switched base
+        conn.execute("DELETE FROM fs_origin", ()).await?;
+        conn.execute(
+            "INSERT OR REPLACE INTO fs_overlay_config (key, value) VALUES ('base_path', ?1)",
+            [Value::Text(base_path.clone())],
+        )
+        .await?;
+        Ok(base_path)
+    }
 }
 
 #[cfg(test)]
@@ -756,11 +1195,212 @@ mod tests {
         assert_eq!(stats.successful, 1);
     }
 
+    #[tokio::test]
+    async fn test_base_writable_config() {
+        let agentfs = AgentFS::open(AgentFSOptions::ephemeral()).await.unwrap();
+        assert!(!agentfs.is_base_writable().await.unwrap());
+        assert!(agentfs.set_base_writable(true).await.is_err());
+
+        let conn = agentfs.get_connection().await.unwrap();
+        OverlayFS::init_schema(&conn, "/base").await.unwrap();
+        drop(conn);
+        assert!(!agentfs.is_base_writable().await.unwrap());
+
+        agentfs.set_base_writable(true).await.unwrap();
+        assert!(agentfs.is_base_writable().await.unwrap());
+        agentfs.set_base_writable(false).await.unwrap();
+        assert!(!agentfs.is_base_writable().await.unwrap());
+    }
+
     #[test]
     fn test_resolve_memory() {
         let opts = AgentFSOptions::resolve(":memory:").unwrap();
         assert!(opts.id.is_none());
         assert!(opts.path.is_none());
+
+        let opts = AgentFSOptions::resolve("").unwrap();
+        assert!(opts.is_ephemeral());
+    }
+
+    #[test]
+    fn test_ephemeral_paths() {
+        assert!(AgentFSOptions::ephemeral().is_ephemeral());
+        assert!(AgentFSOptions::with_path(":memory:").is_ephemeral());
+        assert!(AgentFSOptions::with_path("").is_ephemeral());
+        assert!(!AgentFSOptions::with_path("agent.db").is_ephemeral());
+        assert!(!AgentFSOptions::with_id("agent").is_ephemeral());
+        assert_eq!(AgentFSOptions::with_path("").db_path().unwrap(), ":memory:");
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_instances_are_isolated() {
+        let first = AgentFS::open(AgentFSOptions::ephemeral()).await.unwrap();
+        let second = AgentFS::open(AgentFSOptions::with_path(":memory:"))
+            .await
+            .unwrap();
+
+        first.kv.set("key", &"first").await.unwrap();
+        let (_, file) = first
+            .fs
+            .create_file("/only-in-first.txt", DEFAULT_FILE_MODE, 0, 0)
+            .await
+            .unwrap();
+        file.pwrite(0, b"first").await.unwrap();
+
+        assert!(second.kv.get::<String>("key").await.unwrap().is_none());
+        assert!(second
+            .fs
+            .lstat("/only-in-first.txt")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            first.fs.read_file("/only-in-first.txt").await.unwrap(),
+            Some(b"first".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_base_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = tempfile::tempdir().unwrap();
+        let moved = tempfile::tempdir().unwrap();
+        std::fs::write(moved.path().join("file.txt"), b"base").unwrap();
+        let db_path = dir.path().join("agent.db");
+        let agentfs = AgentFS::open(
+            AgentFSOptions::with_path(db_path.to_str().unwrap()).with_base(base.path()),
+        )
+        .await
+        .unwrap();
+
+        let canonical = moved.path().canonicalize().unwrap();
+        assert_eq!(
+            agentfs.set_base_path(moved.path()).await.unwrap(),
+            canonical.to_str().unwrap()
+        );
+        assert_eq!(
+            agentfs.is_overlay_enabled().await.unwrap().as_deref(),
+            canonical.to_str()
+        );
+
+        // A missing or non-directory base is refused, keeping the old one
+        match agentfs.set_base_path(dir.path().join("missing")).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        assert!(matches!(
+            agentfs.set_base_path(moved.path().join("file.txt")).await,
+            Err(Error::Fs(FsError::NotADirectory))
+        ));
+        assert_eq!(
+            agentfs.is_overlay_enabled().await.unwrap().as_deref(),
+            canonical.to_str()
+        );
+
+        let plain = AgentFS::open(AgentFSOptions::ephemeral()).await.unwrap();
+        assert!(plain.set_base_path(moved.path()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_base_path_with_copied_up_files() {
+        use crate::filesystem::{HostFS, OverlayFS};
+        use std::sync::Arc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let base = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        std::fs::write(base.path().join("small.txt"), b"small").unwrap();
+        std::fs::write(base.path().join("large.bin"), vec![1u8; 3 * 4096]).unwrap();
+        std::fs::write(other.path().join("large.bin"), vec![2u8; 3 * 4096]).unwrap();
+        let db_path = dir.path().join("agent.db");
+        let agentfs = AgentFS::open(
+            AgentFSOptions::with_path(db_path.to_str().unwrap()).with_base(base.path()),
+        )
+        .await
+        .unwrap();
+        async fn count(agentfs: &AgentFS, table: &str) -> i64 {
+            let conn = agentfs.get_connection().await.unwrap();
+            let mut rows = conn
+                .query(&format!("SELECT COUNT(*) FROM {table}"), ())
+                .await
+                .unwrap();
+            let row = rows.next().await.unwrap().unwrap();
+            row.get_value(0).unwrap().as_integer().copied().unwrap()
+        }
+
+        // A whole-file copy-up records the base inode it came from, which
+        // means nothing under another base
+        let overlay = OverlayFS::new(
+            Arc::new(HostFS::new(base.path()).unwrap()),
+            agentfs.fs.clone(),
+        );
+        overlay.load().await.unwrap();
+        let stats = overlay.lookup(1, "small.txt").await.unwrap().unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDWR).await.unwrap();
+        file.pwrite(0, b"S").await.unwrap();
+        assert_eq!(count(&agentfs, "fs_origin").await, 1);
+        let canonical = base.path().canonicalize().unwrap();
+        agentfs.set_base_path(base.path()).await.unwrap();
+        assert_eq!(count(&agentfs, "fs_origin").await, 0);
+
+        // A partially copied-up file would read its other blocks from the
+        // new base, so switching is refused
+        let overlay = OverlayFS::new(
+            Arc::new(HostFS::new(base.path()).unwrap()),
+            agentfs.fs.clone(),
+        )
+        .with_copyup_granularity(4096);
+        overlay.load().await.unwrap();
+        let stats = overlay.lookup(1, "large.bin").await.unwrap().unwrap();
+        let file = overlay.open(stats.ino, libc::O_RDWR).await.unwrap();
+        file.pwrite(4096, b"changed").await.unwrap();
+        assert_eq!(count(&agentfs, "fs_copyup_partial").await, 1);
+        assert!(matches!(
+            agentfs.set_base_path(other.path()).await,
+            Err(Error::Internal(_))
+        ));
+        assert_eq!(
+            agentfs.is_overlay_enabled().await.unwrap().as_deref(),
+            canonical.to_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("agent.db");
+        let agentfs = AgentFS::open(
+            AgentFSOptions::with_path(db_path.to_str().unwrap())
+                .with_base(base.path())
+                .with_chunk_size(16384)
+                .with_compression(CompressionKind::Zstd),
+        )
+        .await
+        .unwrap();
+        assert!(agentfs.manifest().await.unwrap().is_none());
+
+        let written = agentfs.write_manifest().await.unwrap();
+        assert_eq!(
+            Manifest::path_for(&db_path),
+            dir.path().join("agent.manifest.json")
+        );
+        assert_eq!(agentfs.manifest().await.unwrap(), Some(written.clone()));
+        assert_eq!(Manifest::read(&db_path).unwrap(), Some(written.clone()));
+        assert_eq!(written.id, None);
+        assert_eq!(written.database, db_path.to_str().unwrap());
+        assert_eq!(
+            written.base.as_deref(),
+            Some(base.path().canonicalize().unwrap().to_str().unwrap())
+        );
+        assert_eq!(written.chunk_size, 16384);
+        assert_eq!(written.compression.as_deref(), Some("zstd"));
+        assert!(!written.encrypted);
+
+        // In-memory filesystems have nowhere to keep a manifest
+        let ephemeral = AgentFS::open(AgentFSOptions::ephemeral()).await.unwrap();
+        assert!(ephemeral.write_manifest().await.is_err());
+        assert!(ephemeral.manifest().await.unwrap().is_none());
     }
 
     #[test]
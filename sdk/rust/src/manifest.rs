@@ -0,0 +1,85 @@
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Version of the manifest format written by this SDK
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// A JSON description of an agent filesystem, kept next to its database
+///
+/// The manifest lets tooling discover how a filesystem was configured
+/// without opening its database. The database remains the source of truth:
+/// the manifest is a cached view written when the filesystem is created,
+/// and may be stale if settings were changed since.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of the manifest format
+    pub version: u32,
+    /// Agent ID, if the filesystem was opened by ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Path of the database file
+    pub database: String,
+    /// When the manifest was written, in seconds since the Unix epoch
+    pub created_at: i64,
+    /// Version of the database schema
+    pub schema_version: String,
+    /// Base directory, for an overlay filesystem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+    /// Size of the chunks file contents are stored in, in bytes
+    pub chunk_size: usize,
+    /// Compression applied to new file contents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// Limit on the total size of file contents, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// Whether identical chunks are stored once
+    pub dedup: bool,
+    /// Whether names are looked up case-insensitively
+    pub case_insensitive: bool,
+    /// When reads update access times
+    pub atime: String,
+    /// Whether the database is encrypted
+    pub encrypted: bool,
+}
+
+impl Manifest {
+    /// Path of the manifest for the database at `db_path`
+    ///
+    /// `my-agent.db` has its manifest in `my-agent.manifest.json`.
+    pub fn path_for(db_path: impl AsRef<Path>) -> PathBuf {
+        let db_path = db_path.as_ref();
+        let stem = match db_path.extension() {
+            Some(ext) if ext == "db" => db_path.with_extension(""),
+            _ => db_path.to_path_buf(),
+        };
+        let mut name = stem.into_os_string();
+        name.push(".manifest.json");
+        PathBuf::from(name)
+    }
+
+    /// Read the manifest of the database at `db_path`
+    ///
+    /// Returns `None` if the database has no manifest.
+    pub fn read(db_path: impl AsRef<Path>) -> Result<Option<Self>> {
+        match std::fs::read_to_string(Self::path_for(db_path)) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the manifest next to the database it describes
+    ///
+    /// The file is replaced atomically, so readers never see a partial one.
+    pub fn write(&self) -> Result<()> {
+        let path = Self::path_for(&self.database);
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)? + "\n")?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
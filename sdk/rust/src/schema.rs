@@ -4,7 +4,7 @@ use crate::error::{Error, Result};
 use turso::Connection;
 
 /// Current schema version.
-pub const AGENTFS_SCHEMA_VERSION: &str = "0.4";
+pub const AGENTFS_SCHEMA_VERSION: &str = "0.5";
 
 /// Detected schema version based on column introspection.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -15,6 +15,8 @@ pub enum SchemaVersion {
     V0_2,
     /// Added atime_nsec, mtime_nsec, ctime_nsec, rdev columns to fs_inode
     V0_4,
+    /// Added allocated column to fs_inode
+    V0_5,
 }
 
 impl std::fmt::Display for SchemaVersion {
@@ -23,6 +25,7 @@ impl std::fmt::Display for SchemaVersion {
             SchemaVersion::V0_0 => write!(f, "0.0"),
             SchemaVersion::V0_2 => write!(f, "0.2"),
             SchemaVersion::V0_4 => write!(f, "0.4"),
+            SchemaVersion::V0_5 => write!(f, "0.5"),
         }
     }
 }
@@ -34,12 +37,13 @@ impl SchemaVersion {
             SchemaVersion::V0_0 => "0.0",
             SchemaVersion::V0_2 => "0.2",
             SchemaVersion::V0_4 => "0.4",
+            SchemaVersion::V0_5 => "0.5",
         }
     }
 
     /// Returns true if this version is the current version.
     pub fn is_current(&self) -> bool {
-        matches!(self, SchemaVersion::V0_4)
+        matches!(self, SchemaVersion::V0_5)
     }
 }
 
@@ -73,6 +77,12 @@ pub async fn detect_schema_version(conn: &Connection) -> Result<Option<SchemaVer
     let has_mtime_nsec = columns.iter().any(|c| c.name == "mtime_nsec");
     let has_ctime_nsec = columns.iter().any(|c| c.name == "ctime_nsec");
     let has_rdev = columns.iter().any(|c| c.name == "rdev");
+    let has_allocated = columns.iter().any(|c| c.name == "allocated");
+
+    // v0.5: has the allocated column
+    if has_allocated {
+        return Ok(Some(SchemaVersion::V0_5));
+    }
 
     // v0.4: has all nsec columns and rdev
     if has_atime_nsec && has_mtime_nsec && has_ctime_nsec && has_rdev {